@@ -46,6 +46,9 @@ except ImportError:
 # Version is exported from Rust core module
 from ._diskcache_rs import __version__
 
+# Configuration errors raised by the Rust core
+from ._diskcache_rs import CacheConfigError
+
 from .djangocache import DjangoCache
 
 __all__ = [
@@ -64,6 +67,7 @@ __all__ = [
     "JSONDisk",
     # Exceptions and warnings
     "Timeout",
+    "CacheConfigError",
     "EmptyDirWarning",
     "UnknownFileWarning",
     # Recipes: synchronization primitives
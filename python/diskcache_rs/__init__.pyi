@@ -9,7 +9,9 @@ import io
 from pathlib import Path
 from typing import (
     Any,
+    BinaryIO,
     Callable,
+    ContextManager,
     Dict,
     Iterator,
     List,
@@ -130,6 +132,45 @@ class Cache:
     def clear(self, retry: bool = False) -> int: ...
     def close(self) -> None: ...
     def exists(self, key: Any) -> bool: ...
+    def get_metadata(self, key: Any) -> Optional[Dict[str, Any]]: ...
+    def has_changed(self, key: Any, version: int) -> bool: ...
+    def get_with_version(
+        self, key: Any, default: Any = None
+    ) -> Tuple[Any, Optional[int]]: ...
+    def cas(
+        self,
+        key: Any,
+        version: Optional[int],
+        value: Any,
+        expire: Optional[float] = None,
+        tag: Optional[str] = None,
+    ) -> bool: ...
+    def lock_key(self, key: Any) -> ContextManager[Any]: ...
+    def subscribe(
+        self,
+        prefix: Optional[str] = None,
+        tag: Optional[str] = None,
+        subscription: Any = None,
+    ) -> Any: ...
+    def processes(self) -> List[Dict[str, Any]]: ...
+    def get_or_load(
+        self,
+        key: Any,
+        loader: Callable[[], Any],
+        expire: Optional[float] = None,
+        tag: Optional[str] = None,
+    ) -> Any: ...
+    def get_or_set(
+        self,
+        key: Any,
+        factory: Callable[[], Any],
+        expire: Optional[float] = None,
+        tag: Optional[str] = None,
+        stale: Optional[float] = None,
+    ) -> Any: ...
+    def alias(self, alias: str, key: str) -> None: ...
+    def unalias(self, alias: str) -> bool: ...
+    def aliases(self, key: str) -> List[str]: ...
     def keys(self) -> List[Any]: ...
     def values(self) -> List[Any]: ...
     def items(self) -> List[Tuple[Any, Any]]: ...
@@ -140,10 +181,21 @@ class Cache:
     def check(self, fix: bool = False, retry: bool = False) -> List[str]: ...
     def create_tag_index(self) -> None: ...
     def drop_tag_index(self) -> None: ...
-    def stats(self, enable: bool = True, reset: bool = False) -> Dict[str, Any]: ...
+    def stats(
+        self, enable: bool = True, reset: bool = False, aggregate: bool = False
+    ) -> Tuple[int, int]: ...
+    def top_keys(self, n: int = 10) -> List[Tuple[str, int]]: ...
+    def snapshot(self, dest_path: Union[str, Path]) -> Dict[str, int]: ...
+    def migrate_backend(self, target: str) -> int: ...
+    def export(self, path: Union[str, Path]) -> int: ...
+    @classmethod
+    def import_archive(
+        cls, path: Union[str, Path], dest_dir: Union[str, Path], **kwargs: Any
+    ) -> "Cache": ...
     def volume(self) -> int: ...
     def reset(self, key: str, value: Any = None, update: bool = True) -> Any: ...
-    def read(self, key: Any, retry: bool = False) -> io.BytesIO: ...
+    def read(self, key: Any, retry: bool = False) -> BinaryIO: ...
+    def open_write(self, key: str) -> BinaryIO: ...
     def touch(
         self, key: Any, expire: Optional[float] = None, retry: bool = False
     ) -> bool: ...
@@ -196,7 +248,7 @@ class Cache:
         tag: bool = False,
         retry: bool = False,
     ) -> Tuple: ...
-    def vacuum(self) -> None: ...
+    def vacuum(self) -> Dict[str, int]: ...
     def memoize(
         self,
         name: Optional[str] = None,
@@ -204,15 +256,21 @@ class Cache:
         expire: Optional[float] = None,
         tag: Optional[str] = None,
         ignore: Set[str] = ...,
+        lock: bool = False,
+        early_recompute: Optional[float] = None,
     ) -> Callable: ...
     def transact(self, retry: bool = False) -> Any: ...
 
     # Properties
     @property
-    def directory(self) -> Path: ...
+    def directory(self) -> Optional[Path]: ...
     @property
     def timeout(self) -> float: ...
     @property
+    def read_only(self) -> bool: ...
+    @property
+    def maintenance_leader(self) -> bool: ...
+    @property
     def disk(self) -> Any: ...
 
 class FanoutCache:
@@ -276,6 +334,42 @@ class FanoutCache:
     def clear(self, retry: bool = False) -> int: ...
     def close(self) -> None: ...
     def exists(self, key: Any) -> bool: ...
+    def get_metadata(self, key: Any) -> Optional[Dict[str, Any]]: ...
+    def has_changed(self, key: Any, version: int) -> bool: ...
+    def get_with_version(
+        self, key: Any, default: Any = None
+    ) -> Tuple[Any, Optional[int]]: ...
+    def cas(
+        self,
+        key: Any,
+        version: Optional[int],
+        value: Any,
+        expire: Optional[float] = None,
+        tag: Optional[str] = None,
+    ) -> bool: ...
+    def lock_key(self, key: Any) -> ContextManager[Any]: ...
+    def subscribe(
+        self,
+        prefix: Optional[str] = None,
+        tag: Optional[str] = None,
+        subscription: Any = None,
+    ) -> Any: ...
+    def processes(self) -> List[Dict[str, Any]]: ...
+    def get_or_load(
+        self,
+        key: Any,
+        loader: Callable[[], Any],
+        expire: Optional[float] = None,
+        tag: Optional[str] = None,
+    ) -> Any: ...
+    def get_or_set(
+        self,
+        key: Any,
+        factory: Callable[[], Any],
+        expire: Optional[float] = None,
+        tag: Optional[str] = None,
+        stale: Optional[float] = None,
+    ) -> Any: ...
     def keys(self) -> List[Any]: ...
     def values(self) -> List[Any]: ...
     def items(self) -> List[Tuple[Any, Any]]: ...
@@ -286,10 +380,12 @@ class FanoutCache:
     def check(self, fix: bool = False, retry: bool = False) -> List[str]: ...
     def create_tag_index(self) -> None: ...
     def drop_tag_index(self) -> None: ...
-    def stats(self, enable: bool = True, reset: bool = False) -> Dict[str, Any]: ...
+    def stats(
+        self, enable: bool = True, reset: bool = False, aggregate: bool = False
+    ) -> Tuple[int, int]: ...
     def volume(self) -> int: ...
     def reset(self, key: str, value: Any = None) -> Any: ...
-    def read(self, key: Any, retry: bool = False) -> io.BytesIO: ...
+    def read(self, key: Any, retry: bool = False) -> BinaryIO: ...
     def touch(
         self, key: Any, expire: Optional[float] = None, retry: bool = False
     ) -> bool: ...
@@ -342,7 +438,7 @@ class FanoutCache:
         tag: bool = False,
         retry: bool = False,
     ) -> Tuple: ...
-    def vacuum(self) -> None: ...
+    def vacuum(self) -> Dict[str, int]: ...
     def memoize(
         self,
         name: Optional[str] = None,
@@ -350,6 +446,8 @@ class FanoutCache:
         expire: Optional[float] = None,
         tag: Optional[str] = None,
         ignore: Set[str] = ...,
+        lock: bool = False,
+        early_recompute: Optional[float] = None,
     ) -> Callable: ...
     def transact(self, retry: bool = False) -> Any: ...
     def cache(
@@ -530,6 +628,11 @@ class PickleCache:
     def delete_object(self, key: str) -> bool: ...
     def list_cached_objects(self) -> List[str]: ...
     def get_cache_stats(self) -> Dict[str, Any]: ...
+    def register_upgrade(self, from_version: int, upgrade: Callable[[Any], Any]) -> None: ...
+    def upgrade(
+        self, from_version: int
+    ) -> Callable[[Callable[[Any], Any]], Callable[[Any], Any]]: ...
+    def schema_version_of(self, key: str) -> Optional[int]: ...
 
 # Utility Functions
 def cache_object(
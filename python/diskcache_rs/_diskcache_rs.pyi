@@ -4,25 +4,143 @@
 
 import builtins
 import typing
-from typing import Any, Dict, List, Optional
+from pathlib import Path
+from typing import Any, Callable, Dict, List, Optional, Union
+
+# Exceptions
+class CacheConfigError(ValueError):
+    """Invalid cache configuration"""
+
+    option: str
+    message: str
+    suggestion: str
 
 # Rust Cache Classes
 class PyCache:
     """Python wrapper for the Cache"""
     def __init__(
         self,
-        directory: str,
+        directory: Optional[str] = None,
         max_size: Optional[int] = None,
         max_entries: Optional[int] = None,
+        disk_write_threshold: Optional[int] = None,
+        use_file_locking: Optional[bool] = None,
+        disk: Optional[Any] = None,
+        auto_recover: Optional[bool] = None,
+        sync_writes: Optional[bool] = None,
+        batch_size: Optional[int] = None,
+        use_mmap: Optional[bool] = None,
+        wal: Optional[str] = None,
+        backend: Optional[str] = None,
+        pack_threshold: Optional[int] = None,
+        compaction_ratio: Optional[float] = None,
+        data_fanout: Optional[int] = None,
+        unlink_workers: Optional[int] = None,
+        unlink_rate: Optional[int] = None,
+        durability: Optional[str] = None,
+        group_commit: Optional[float] = None,
+        index_key: Optional[bytes] = None,
+        soft_delete: Optional[float] = None,
+        direct_io_threshold: Optional[int] = None,
+        designated_writer: Optional[bool] = None,
+        writer_lease: Optional[float] = None,
+        maintenance_lease: Optional[float] = None,
+        ring_capacity_bytes: Optional[int] = None,
+        remote_tier: Optional[str] = None,
+        file_naming: Optional[str] = None,
+        write_queue_capacity: Optional[int] = None,
+        queue_full: Optional[str] = None,
+        encryption_key: Optional[Union[bytes, Callable[[str], bytes]]] = None,
+        compression: Optional[str] = None,
+        compression_level: Optional[int] = None,
+        zstd_dictionary: Optional[bool] = None,
+        dictionary_threshold: Optional[int] = None,
+        seekable_compression: Optional[bool] = None,
+        watch_directory: Optional[bool] = None,
+        event_log: Optional[bool] = None,
+        lock_backend: Optional[str] = None,
+        lock_granularity: Optional[str] = None,
+        smb_mode: Optional[bool] = None,
+        sharing_retries: Optional[int] = None,
+        eviction_policy: Optional[str] = None,
+        hot_cache_policy: Optional[str] = None,
+        eviction_samples: Optional[int] = None,
+        max_idle: Optional[float] = None,
+        inline_evictions: Optional[int] = None,
+        warm_cache_size: Optional[int] = None,
+        warm_cache_bytes: Optional[int] = None,
+        statistics: Optional[bool] = None,
     ) -> None: ...
     def get(self, key: str) -> Optional[bytes]: ...
+    def get_many(
+        self, keys: List[str], prefetch_prefix: Optional[str] = None
+    ) -> List[Optional[bytes]]: ...
+    def peek(self, key: str) -> Optional[bytes]: ...
+    def data_file(self, key: str) -> Optional[tuple[Path, int]]: ...
+    def get_range(
+        self, key: str, start: int, end: Optional[int] = None
+    ) -> Optional[bytes]: ...
+    def open_frames(self, key: str) -> Optional[PyFrameReader]: ...
+    def peekitem(self, last: bool = True) -> Optional[tuple[str, bytes]]: ...
     def set(
         self,
         key: str,
         value: Any,
         expire_time: Optional[int] = None,
         tags: Optional[List[str]] = None,
+        weight: Optional[float] = None,
+        priority: Optional[str] = None,
+    ) -> Optional[int]: ...
+    def cas(
+        self,
+        key: str,
+        expected: Optional[int],
+        value: bytes,
+        expire_time: Optional[int] = None,
+        tags: Optional[List[str]] = None,
+    ) -> bool: ...
+    def get_with_version(self, key: str) -> Optional[tuple[bytes, int]]: ...
+    def alias(self, alias: str, key: str) -> None: ...
+    def unalias(self, alias: str) -> bool: ...
+    def aliases(self, key: str) -> List[str]: ...
+    def version(self, key: str) -> Optional[int]: ...
+    def has_changed(self, key: str, version: int) -> bool: ...
+    def pin(self, key: str) -> None: ...
+    def unpin(self, key: str) -> bool: ...
+    def set_tag_limit(
+        self,
+        tag: str,
+        max_bytes: Optional[int] = None,
+        max_entries: Optional[int] = None,
     ) -> None: ...
+    def remove_tag_limit(self, tag: str) -> bool: ...
+    def set_eviction_strategy(self, strategy: str) -> None: ...
+    def lock_key(self, key: str) -> PyKeyLock: ...
+    def subscribe(
+        self,
+        prefix: Optional[str] = None,
+        tag: Optional[str] = None,
+        subscription: Optional[PySubscription] = None,
+    ) -> PySubscription: ...
+    def serve(self, socket: Optional[Union[str, Path]] = None) -> PyCacheServer: ...
+    def is_read_only(self) -> bool: ...
+    def is_maintenance_leader(self) -> bool: ...
+    def processes(self) -> List[Dict[str, Any]]: ...
+    def get_or_load(
+        self,
+        key: str,
+        loader: Callable[[], bytes],
+        expire_time: Optional[int] = None,
+        tags: Optional[List[str]] = None,
+    ) -> bytes: ...
+    def get_or_set(
+        self,
+        key: str,
+        loader: Callable[[], bytes],
+        expire_time: Optional[int] = None,
+        tags: Optional[List[str]] = None,
+        stale: Optional[int] = None,
+    ) -> bytes: ...
     def set_many(
         self,
         items: List[tuple[str, bytes]],
@@ -30,16 +148,115 @@ class PyCache:
         tags: Optional[List[str]] = None,
     ) -> None: ...
     def delete(self, key: str) -> bool: ...
+    def undelete(self, key: str) -> bool: ...
+    def push(
+        self,
+        prefix: str,
+        value: bytes,
+        side: str = "back",
+        expire_time: Optional[int] = None,
+        tags: Optional[List[str]] = None,
+    ) -> tuple[int, str]: ...
+    def pull(self, prefix: str, side: str = "front") -> Optional[tuple[int, str, bytes]]: ...
+    def peek_queue(
+        self, prefix: str, side: str = "front"
+    ) -> Optional[tuple[int, str, bytes]]: ...
 
-    def clear(self) -> None: ...
+    def clear(self, progress: Optional[Callable[[int, int], None]] = None) -> None: ...
     def exists(self, key: str) -> bool: ...
     def keys(self) -> List[str]: ...
+    def iterkeys(self, reverse: bool = False) -> typing.Iterator[str]: ...
     def size(self) -> int: ...
+    def vacuum(self) -> Dict[str, int]: ...
+    def stats(self, aggregate: bool = False) -> Dict[str, int]: ...
+    def hit_stats(
+        self, enable: bool = True, reset: bool = False, aggregate: bool = False
+    ) -> tuple[int, int]: ...
+    def top_keys(self, n: int = 10) -> list[tuple[str, int]]: ...
     def hit_rate(self) -> float: ...
+    def write_amplification(self) -> float: ...
+    def info(self) -> Dict[str, Any]: ...
+    def verify_and_recover(self) -> Dict[str, Any]: ...
+    def snapshot(self, destination: str) -> Dict[str, int]: ...
+    def migrate_backend(self, target: str) -> int: ...
+    def export(
+        self,
+        path: str,
+        expire_times: Optional[Dict[str, int]] = None,
+        tags: Optional[Dict[str, List[str]]] = None,
+    ) -> int: ...
+    def import_archive(self, path: str) -> List[tuple[str, Optional[int], List[str]]]: ...
+    def estimate(
+        self, keys_per_day: int, avg_value_size: int, ttl: Optional[float] = None
+    ) -> Dict[str, Any]: ...
+    def relocate(self) -> int: ...
+    def link_file(self, key: str, path: str) -> None: ...
+    def open_write(self, key: str) -> PyValueWriter: ...
+    def load_manifest(self, path: str) -> int: ...
+
+class PyValueWriter:
+    """Writable file streaming one value into the cache; close() stores it"""
+    @property
+    def closed(self) -> bool: ...
+    def write(self, data: bytes) -> int: ...
+    def flush(self) -> None: ...
+    def close(self) -> None: ...
+    def discard(self) -> None: ...
+    def writable(self) -> bool: ...
+    def readable(self) -> bool: ...
+    def seekable(self) -> bool: ...
+    def __enter__(self) -> PyValueWriter: ...
+    def __exit__(self, exc_type: Any, exc_value: Any, traceback: Any) -> bool: ...
+
+class PyKeyLock:
+    """Advisory lock on one key; held until release() or the end of a with block"""
+    @property
+    def locked(self) -> bool: ...
+    def release(self) -> None: ...
+    def __enter__(self) -> PyKeyLock: ...
+    def __exit__(self, exc_type: Any, exc_value: Any, traceback: Any) -> bool: ...
+
+class PySubscription:
+    """(event, key) changes to subscribed topics; open until close() or the end of a with block"""
+    @property
+    def closed(self) -> bool: ...
+    def get(self, timeout: Optional[float] = None) -> Optional[tuple[str, str]]: ...
+    def close(self) -> None: ...
+    def __iter__(self) -> PySubscription: ...
+    def __next__(self) -> tuple[str, str]: ...
+    def __enter__(self) -> PySubscription: ...
+    def __exit__(self, exc_type: Any, exc_value: Any, traceback: Any) -> bool: ...
+
+class PyCacheServer:
+    """Serves a cache to other processes over a Unix socket until closed"""
+    @property
+    def socket(self) -> Path: ...
+    def serve_forever(self) -> None: ...
+    def close(self) -> None: ...
+    def __enter__(self) -> PyCacheServer: ...
+    def __exit__(self, exc_type: Any, exc_value: Any, traceback: Any) -> bool: ...
+
+class PyFrameReader:
+    """Seekable file over a value in compressed frames; reads decompress only what they reach"""
+    @property
+    def closed(self) -> bool: ...
+    @property
+    def size(self) -> int: ...
+    def read(self, size: int = -1) -> bytes: ...
+    def seek(self, offset: int, whence: int = 0) -> int: ...
+    def tell(self) -> int: ...
+    def close(self) -> None: ...
+    def readable(self) -> bool: ...
+    def writable(self) -> bool: ...
+    def seekable(self) -> bool: ...
+    def __enter__(self) -> PyFrameReader: ...
+    def __exit__(self, exc_type: Any, exc_value: Any, traceback: Any) -> bool: ...
 
 class Cache:
     """Drop-in replacement for diskcache.Cache"""
-    def __init__(self, directory: str, **kwargs: Any) -> None: ...
+    def __init__(
+        self, directory: Optional[str] = None, timeout: float = 60, **settings: Any
+    ) -> None: ...
     def __contains__(self, key: str) -> bool: ...
     def __getitem__(self, key: str) -> Any: ...
     def __setitem__(self, key: str, value: Any) -> None: ...
@@ -60,6 +277,7 @@ class Cache:
         self, key: str, value: Any, expire: Optional[int] = None, retry: bool = False
     ) -> bool: ...
     def delete(self, key: str, retry: bool = False) -> bool: ...
+    def undelete(self, key: str) -> bool: ...
     def pop(
         self,
         key: str,
@@ -67,18 +285,23 @@ class Cache:
         expire: Optional[int] = None,
         retry: bool = False,
     ) -> Any: ...
-    def clear(self, retry: bool = False) -> int: ...
+    def clear(
+        self, retry: bool = False, progress: Optional[Callable[[int, int], None]] = None
+    ) -> int: ...
     def close(self) -> None: ...
     def exists(self, key: str) -> bool: ...
     def keys(self) -> List[str]: ...
     def values(self) -> List[Any]: ...
     def items(self) -> List[tuple[str, Any]]: ...
-    def iterkeys(self) -> typing.Iterator[str]: ...
+    def iterkeys(self, reverse: bool = False) -> typing.Iterator[str]: ...
     def itervalues(self) -> typing.Iterator[Any]: ...
     def iteritems(self) -> typing.Iterator[tuple[str, Any]]: ...
     def expire(self, now: Optional[float] = None) -> int: ...
     def evict(self, tag: Optional[str] = None) -> int: ...
-    def stats(self, enable: bool = True, reset: bool = False) -> tuple[int, int]: ...
+    def stats(
+        self, enable: bool = True, reset: bool = False, aggregate: bool = False
+    ) -> tuple[int, int]: ...
+    def top_keys(self, n: int = 10) -> list[tuple[str, int]]: ...
     def volume(self) -> int: ...
     def reset(self, key: str, value: Any = None) -> None: ...
     def touch(
@@ -102,7 +325,11 @@ class Cache:
 class FanoutCache:
     """Drop-in replacement for diskcache.FanoutCache"""
     def __init__(
-        self, directory: str, shards: Optional[int] = None, **kwargs: Any
+        self,
+        directory: Optional[str] = None,
+        shards: Optional[int] = 8,
+        timeout: float = 0.010,
+        **settings: Any,
     ) -> None: ...
     def __contains__(self, key: str) -> bool: ...
     def __getitem__(self, key: str) -> Any: ...
@@ -121,7 +348,7 @@ class FanoutCache:
         retry: Optional[bool] = None,
     ) -> bool: ...
     def delete(self, key: str) -> bool: ...
-    def iterkeys(self) -> List[str]: ...
+    def iterkeys(self) -> typing.Iterator[str]: ...
 
 class PickleCache:
     """High-performance pickle cache with expiration support"""
@@ -132,9 +359,16 @@ class PickleCache:
         default_ttl_seconds: Optional[int] = None,
     ) -> None: ...
     def set_pickle(
-        self, key: str, pickled_data: Any, ttl_seconds: Optional[int] = None
+        self,
+        key: str,
+        pickled_data: Any,
+        ttl_seconds: Optional[int] = None,
+        schema_version: int = 0,
     ) -> None: ...
     def get_pickle(self, key: str) -> Optional[bytes]: ...
+    def get_pickle_versioned(self, key: str) -> Optional[tuple[bytes, int]]: ...
+    def upgrade_pickle(self, key: str, pickled_data: bytes, schema_version: int) -> bool: ...
+    def schema_version_pickle(self, key: str) -> Optional[int]: ...
     def delete(self, key: str) -> bool: ...
     def clear(self) -> None: ...
     def exists(self, key: str) -> bool: ...
@@ -6,12 +6,26 @@ import functools
 import hashlib
 import io
 import json
+import math
 import os
+import sys
+import tempfile
 import threading
 import time
 from contextlib import contextmanager
 from pathlib import Path
-from typing import Any, Callable, Dict, Iterator, List, Optional, Set, Tuple, Union
+from typing import (
+    Any,
+    BinaryIO,
+    Callable,
+    Dict,
+    Iterator,
+    List,
+    Optional,
+    Set,
+    Tuple,
+    Union,
+)
 
 # Use high-performance Rust pickle implementation when available
 try:
@@ -23,6 +37,43 @@ except ImportError:
 _RustCache = None
 _RAW_BYTES_PREFIX = b"\x00diskcache_rs:bytes\x00"
 _PICKLE_PREFIX = b"\x00diskcache_rs:pickle\x00"
+_QUEUE_PREFIX = "__queue__"
+_QUEUE_START = 500_000_000_000_000
+_SERVER_URL_PREFIX = "unix://"
+
+
+def _is_server_url(directory) -> bool:
+    """Whether *directory* names a cache server's socket rather than a directory"""
+    return isinstance(directory, str) and directory.startswith(_SERVER_URL_PREFIX)
+
+
+def _dispatch_removals(
+    removals: Any, callbacks: Dict[str, List[Callable[[str, str], Any]]]
+) -> None:
+    """Call the *callbacks* for each eviction and expiration until *removals* closes"""
+    for event, key in removals:
+        for callback in list(callbacks.get(event, ())):
+            try:
+                callback(key, event)
+            except Exception:
+                sys.excepthook(*sys.exc_info())
+
+
+def _check_early_recompute(
+    expire: Optional[float], early_recompute: Optional[float]
+) -> None:
+    """Reject memoize's *early_recompute* unless it is a fraction of *expire*"""
+    if early_recompute is None:
+        return
+    if expire is None:
+        raise ValueError("early_recompute needs expire")
+    if not 0 < early_recompute < 1:
+        raise ValueError("early_recompute must be between 0 and 1")
+
+
+def _queue_key(prefix: str, position: int) -> str:
+    """Storage key of the item at *position* in the queue named *prefix*"""
+    return f"{prefix}-{_QUEUE_START + position:015d}"
 
 
 def _get_rust_cache():
@@ -53,22 +104,218 @@ class Cache:
         Initialize cache
 
         Args:
-            directory: Cache directory path
+            directory: Cache directory path; optional with backend="memory".
+                A URL such as "unix:///path/to/server.sock" attaches to the
+                cache server listening on that socket instead (see
+                ``python -m diskcache_rs.server``), which then reads and
+                writes the entries on this cache's behalf
             timeout: Operation timeout (not used in Rust implementation)
             disk_min_file_size: Minimum file size for disk storage (deprecated, use disk_write_threshold)
             **kwargs: Additional arguments:
-                - max_size / size_limit: Maximum cache size in bytes (default: 1GB)
+                - max_size / size_limit: Maximum cache size in bytes (default: 1GB);
+                  each write evicts entries until the values stored, its own
+                  included, fit
                 - max_entries / count_limit: Maximum number of entries (default: 100,000)
+                - eviction_policy: Which entries go first once over a limit:
+                  "least-recently-stored", "least-recently-used",
+                  "least-frequently-used", "window-tinylfu", which keeps
+                  entries read often over new ones read once and suits
+                  read-heavy caches, "gdsf", which keeps entries read
+                  often, small and costly to recompute by the weight
+                  set() stored them with, or "none" (default:
+                  "least-recently-stored"). The order is saved to
+                  eviction.state on close and hourly, and picked up again
+                  on open
+                - statistics: Count hits and misses for stats() and
+                  top_keys() from the start; stats(enable=True) turns
+                  counting on later (default: False, as in diskcache)
+                - eviction_samples: Pick least-recently-used or
+                  least-frequently-used victims among this many randomly
+                  sampled keys each, like Redis, which saves memory on very
+                  many keys at the cost of an approximate order (default: 0,
+                  exact order)
+                - max_idle: Seconds an entry may go without being read or
+                  written before it expires, whatever its own expiry; reads
+                  in other processes do not count (default: None, never)
+                - inline_evictions: Most of its victims a write evicts
+                  before returning; a background thread evicts the rest, so
+                  writes over a limit do not stall deleting files. The
+                  backlog shows in stats() as eviction_backlog and
+                  eviction_backlog_bytes (default: None, writes evict all
+                  their victims)
                 - disk_write_threshold: Size threshold for writing to disk vs inline SQLite (default: 32768 bytes)
                   Items smaller than this threshold are stored inline and won't create data files.
                   Set to 0 to write all items to disk (useful for testing/debugging).
-                - use_file_locking: Enable file locking for NFS scenarios (default: False)
+                - pack_threshold: Items from disk_write_threshold up to this size are
+                  appended to shared segment files instead of one file each, saving
+                  inodes for millions of small values (default: 0, disabled)
+                - compaction_ratio: Share of segment bytes left dead by overwrites and
+                  deletes that starts a background compaction (default: 0.5)
+                - data_fanout: Levels of hash-prefix subdirectories data files are
+                  spread over, e.g. data/ab/cd/<hash>.dat for 2; 0 keeps data/ flat.
+                  Existing caches are moved to the new layout on open (default: 2)
+                - file_naming: How data files are named: "blake3" uses the key's
+                  full hash, "xxh3" a shorter one, and "slug" a readable form of
+                  the key followed by a hash, e.g. user_42.<hash>.dat, so files
+                  can be told apart by eye. Files already written keep their
+                  names (default: "blake3")
+                - compression: Codec values of 32KB or more are compressed with
+                  when that saves space: "lz4", "zstd" for smaller files on slow
+                  or shared disks, "snappy" for the least CPU, or "none". Values
+                  keep the codec they were written with, so it can change
+                  between opens (default: "lz4")
+                - compression_level: Zstd level, from 1 to 22 for smaller files
+                  at more CPU; other codecs have none (default: 3)
+                - zstd_dictionary: Compress values below dictionary_threshold with a
+                  zstd dictionary trained on the first 1000 of them, for many small,
+                  similar values (default: False)
+                - dictionary_threshold: Size in bytes below which values use the
+                  dictionary (default: 8KB)
+                - seekable_compression: Compress large values as 256KB frames of LZ4
+                  or zstd, so read() and seeking decompress only the frames they
+                  reach (default: False)
+                - unlink_workers: Threads removing data files on clear() (default: 8)
+                - unlink_rate: Most data files removed per second on clear(), to
+                  spare a shared file server; 0 is unlimited (default: 0)
+                - use_file_locking: Enable locking that holds up on NFS; file locks
+                  give way to lock files whose holder renews them, broken after
+                  30s without renewal, and readers hold their key's lock shared
+                  while reading its file (default: False)
                   Enable this when using cache on network filesystems to prevent corruption.
+                - auto_recover: Verify and repair the index on open when the previous
+                  owner did not close the cache cleanly (default: False)
+                - sync_writes, batch_size, use_mmap: Storage tuning options passed
+                  through to the Rust cache when given
+                - warm_cache_size, warm_cache_bytes: Most data files, and most
+                  bytes of them, kept memory-mapped between reads; the least
+                  recently read are unmapped first. Optimized backend only
+                  (default: 1000 files, 1GB)
+                - hot_cache_policy: Which values held in memory go once there
+                  are too many: "lru" drops the least recently used, "clock"
+                  those a sweep finds unused since its last pass, which makes
+                  hits cheaper under many threads. Optimized backend only
+                  (default: "lru")
+                - watch_directory: Watch the data directory and drop values held
+                  in memory as soon as another process rewrites or removes
+                  their files, for workers sharing a directory on a local
+                  disk. Optimized backend only (default: False)
+                - event_log: Record sets and deletes in events.log and read
+                  what other processes recorded before serving values held in
+                  memory, which also works on network filesystems. Optimized
+                  backend only (default: False)
+                - write_queue_capacity: Most data file writes waiting for the
+                  background writer, so bursts of large sets cannot grow memory
+                  without bound (default: 1024)
+                - queue_full: What set() does when that queue is full: "block"
+                  until there is room, "spill" the write onto the calling
+                  thread, or "error" out (default: "block")
+                - wal: Enable the write-ahead log with the given fsync policy,
+                  "always", "batch" or "never" (default: None, disabled)
+                - durability: How far writes get before they return: "relaxed"
+                  queues data files and may lose recent writes in a crash,
+                  "flush" hands every write to the OS, "fsync" syncs data files
+                  and the index to disk, and "fsync+dirsync" also syncs the
+                  directories new files land in (default: "flush")
+                - group_commit: Seconds a synced data file write waits for
+                  others to be synced in the same pass, e.g. 0.005; trades a
+                  little latency for much higher durable-write throughput on
+                  spinning disks and NFS. Needs durability "fsync" or above
+                  (default: None, every write syncs on its own)
+                - direct_io_threshold: Size in bytes from which data files
+                  are written with O_DIRECT on Linux, so large artifacts do
+                  not push co-located services out of the page cache
+                  (default: None, all writes are buffered)
+                - designated_writer: Let one of the processes sharing the
+                  directory apply everyone's writes, forwarded over a Unix
+                  socket, so batching and group commit span processes;
+                  another process takes over when the writer exits. Unix
+                  only (default: False)
+                - writer_lease: Seconds the writer lease runs between
+                  renewals, e.g. 30. Only the process holding the lease
+                  writes; the others open read-only and their writes raise
+                  PermissionError. A lease that is not renewed in time, because
+                  its holder exited or hung, goes to the next process that
+                  writes (default: None, every process writes)
+                - maintenance_lease: Seconds the maintenance lease runs
+                  between renewals, e.g. 7200. Only the process holding it
+                  runs the automatic vacuum, expiry sweep and segment
+                  compaction; it renews the lease whenever its maintenance
+                  comes due, every half period at most hourly, and the next
+                  process whose maintenance comes due takes over once it
+                  stops (default: None, every process maintains)
+                - lock_backend: What locks between the processes sharing the
+                  directory are made of: "file" locks files in it, and
+                  "named_mutex" uses Windows named mutexes, for SMB shares
+                  whose file locks are unreliable; those only exclude
+                  processes on the same machine, and other platforms use
+                  file locks regardless; "lock_file" creates files with a
+                  heartbeat inside, as use_file_locking does for NFS
+                  (default: "file"; "named_mutex" for
+                  FanoutCache on Windows)
+                - lock_granularity: How many keys share a lock: "entry" gives
+                  keys locks of their own as far as 65536 lock files go,
+                  "shard" hashes them onto 256 and "cache" uses one lock for
+                  the whole cache, which means the fewest lock files on NFS.
+                  Every process using the directory must agree on it
+                  (default: "shard")
+                - smb_mode: Share the directory over SMB without byte-range
+                  locks: keys are not locked, values are published by
+                  renaming a uniquely named temporary file into place, and no
+                  data file is kept open or mapped between reads. A file
+                  racing writers left not matching its row reads as a miss.
+                  Needs pack_threshold=0 (default: False)
+                - sharing_retries: How often a file operation failing with a
+                  Windows sharing violation is retried, after a jittered delay
+                  growing from 10ms to 1s (default: 4)
+                - index_key: Secret (bytes or str) every index row is signed
+                  with; a row that fails the check, such as one pointing a key
+                  at a file planted in a shared directory, is dropped instead
+                  of unpickled. Give every process the same secret
+                  (default: None, unsigned)
+                - encryption_key: Secret (bytes or str) values are encrypted
+                  with, using AES-256-GCM, before they reach the disk; or a
+                  callable given the directory that returns it, e.g. to read
+                  it from a keyring. Keys stay in the clear. A cache created
+                  with a key only opens with the same key
+                  (default: None, values are stored as given)
+                - soft_delete: Seconds deleted entries stay in a trash/
+                  directory, from which undelete() brings them back; guards
+                  values that are expensive to regenerate against buggy mass
+                  deletes. Evictions and queue pulls skip the trash
+                  (default: None, deletes are final)
+                - backend: "optimized" (default) or "sqlite" to read and write
+                  python-diskcache's own cache.db, so diskcache processes can
+                  share the directory while a fleet migrates
+                  "redb" keeps every entry in one embedded database file,
+                  for millions of tiny values; only one process at a time
+                  can open a redb cache
+                  "memory" keeps entries in process memory only, for tests
+                  and short-lived workers; the directory is never touched
+                  "ring" writes entries into one file allocated up front and
+                  overwrites the oldest once it is full, for telemetry and
+                  event buffers; only one process at a time can open it
+                - ring_capacity_bytes: Size of the ring backend's file, fixed
+                  when the ring is created and required by that backend
+                - remote_tier: Where evicted entries go instead of being
+                  dropped, and where reads that miss locally look next:
+                  "s3://bucket/prefix" (credentials from the AWS_* environment
+                  variables; needs a build with the "s3" feature) or a
+                  directory such as a mounted share. Optimized backend only
+                  (default: None)
+
+        Raises:
+            CacheConfigError: If options are invalid or conflict; the exception
+                carries ``option`` and ``suggestion`` attributes
         """
-        if directory is None:
+        in_memory = kwargs.get("backend") == "memory"
+        if directory is None and not in_memory:
             directory = os.path.join(os.getcwd(), "cache")
 
-        self._directory = Path(directory)
+        # Kept as given, as Path() would collapse the URL's slashes
+        self._server = directory if _is_server_url(directory) else None
+        self._directory = (
+            None if directory is None or self._server else Path(directory)
+        )
         self._timeout = timeout
         self._transaction_lock = (
             threading.RLock()
@@ -76,6 +323,11 @@ class Cache:
         self._transaction_depth = 0  # Track nested transaction depth
         self._expire_times: Dict[str, float] = {}  # Track expiration times for expire()
         self._tags: Dict[str, str] = {}  # Track tags for tag-based operations
+        # on_evict and on_expire callbacks by event, and the subscription
+        # their thread is fed by once there are any
+        self._removal_callbacks: Dict[str, List[Callable[[str, str], Any]]] = {}
+        self._removals: Any = None
+        self._removals_lock = threading.Lock()
 
         # Extract Rust cache parameters
         max_size = kwargs.get(
@@ -86,15 +338,82 @@ class Cache:
         # New configuration options for issue #17
         disk_write_threshold = kwargs.get("disk_write_threshold", disk_min_file_size)
         use_file_locking = kwargs.get("use_file_locking", False)
+        auto_recover = kwargs.get("auto_recover", False)
 
         # Create the underlying Rust cache
+        storage_options = {
+            name: kwargs[name]
+            for name in (
+                "sync_writes",
+                "batch_size",
+                "write_queue_capacity",
+                "queue_full",
+                "use_mmap",
+                "warm_cache_size",
+                "warm_cache_bytes",
+                "watch_directory",
+                "event_log",
+                "lock_backend",
+                "lock_granularity",
+                "smb_mode",
+                "sharing_retries",
+                "eviction_policy",
+                "statistics",
+                "hot_cache_policy",
+                "eviction_samples",
+                "max_idle",
+                "inline_evictions",
+                "wal",
+                "durability",
+                "group_commit",
+                "direct_io_threshold",
+                "designated_writer",
+                "writer_lease",
+                "maintenance_lease",
+                "index_key",
+                "encryption_key",
+                "soft_delete",
+                "backend",
+                "ring_capacity_bytes",
+                "remote_tier",
+                "pack_threshold",
+                "compaction_ratio",
+                "data_fanout",
+                "file_naming",
+                "compression",
+                "compression_level",
+                "zstd_dictionary",
+                "dictionary_threshold",
+                "seekable_compression",
+                "unlink_workers",
+                "unlink_rate",
+            )
+            if name in kwargs
+        }
+        # diskcache stores this setting as 0/1
+        if "statistics" in storage_options:
+            storage_options["statistics"] = bool(storage_options["statistics"])
+        if isinstance(storage_options.get("index_key"), str):
+            storage_options["index_key"] = storage_options["index_key"].encode()
+        if isinstance(storage_options.get("encryption_key"), str):
+            storage_options["encryption_key"] = storage_options[
+                "encryption_key"
+            ].encode()
+
+        # The sqlite backend strips our raw-bytes prefix so diskcache sees plain
+        # bytes; anything it returns without a prefix is therefore bytes too
+        self._unprefixed_is_bytes = kwargs.get("backend") == "sqlite"
+
         _RustCache = _get_rust_cache()
         self._cache = _RustCache(
-            str(self._directory),
+            self._server
+            or (None if self._directory is None else str(self._directory)),
             max_size=max_size,
             max_entries=max_entries,
             disk_write_threshold=disk_write_threshold,
             use_file_locking=use_file_locking,
+            auto_recover=auto_recover,
+            **storage_options,
         )
 
     def set(
@@ -105,6 +424,9 @@ class Cache:
         read: bool = False,
         tag: Optional[str] = None,
         retry: bool = False,
+        weight: Optional[float] = None,
+        pin: bool = False,
+        priority: str = "normal",
     ) -> bool:
         """
         Set key to value in cache
@@ -116,10 +438,28 @@ class Cache:
             read: Whether this is a read operation (ignored)
             tag: Tag for the entry
             retry: Whether to retry on failure (ignored)
+            weight: What the value costs to recompute, in any unit used for
+                all entries, such as seconds; the "gdsf" eviction policy
+                keeps entries with more weight per byte longer. Default: 1
+            pin: Never evict key, as :meth:`pin` does
+            priority: "low" entries are evicted before any other and "high"
+                ones only once nothing else is left, whatever the eviction
+                policy ranks them; within a priority the policy decides
 
         Returns:
             True if successful
+
+        Raises:
+            PermissionError: If another process holds the writer lease
+            ValueError: If weight is not a positive number, or priority is
+                not "low", "normal" or "high"
         """
+        if weight is not None and not (0 < weight < math.inf):
+            raise ValueError(f"weight must be a positive number, not {weight!r}")
+        if priority not in ("low", "normal", "high"):
+            raise ValueError(
+                f'priority must be "low", "normal" or "high", not {priority!r}'
+            )
         try:
             # Handle read=True: read value from file-like object
             if read and hasattr(value, "read"):
@@ -129,35 +469,31 @@ class Cache:
             serialized_value = self._serialize_value(value)
 
             # Calculate expiration time
-            expire_time = None
-            if expire is not None:
-                if expire > time.time():
-                    # Assume it's already a timestamp
-                    expire_time = int(expire)
-                else:
-                    # Assume it's seconds from now
-                    expire_time = int(time.time() + expire)
+            expire_time = self._expire_timestamp(expire)
 
             # Prepare tags
             tags = [tag] if tag else []
 
-            # Store in Rust cache
-            self._cache.set(key, serialized_value, expire_time=expire_time, tags=tags)
-
-            # Track expiration time for expire() method
-            if expire_time is not None:
-                self._expire_times[key] = float(expire_time)
-            else:
-                self._expire_times.pop(key, None)
+            # Pinned first, so making room for the value cannot evict it
+            if pin:
+                self._cache.pin(key)
 
-            # Track tag for tag-based operations
-            if tag is not None:
-                self._tags[key] = tag
-            else:
-                self._tags.pop(key, None)
+            # Store in Rust cache
+            self._cache.set(
+                key,
+                serialized_value,
+                expire_time=expire_time,
+                tags=tags,
+                weight=weight,
+                priority=priority,
+            )
+            self._track_metadata(key, expire_time, tag)
 
             return True
 
+        except PermissionError:
+            # Another process holds the writer lease
+            raise
         except Exception:
             return False
 
@@ -203,6 +539,31 @@ class Cache:
         except Exception:
             return 0
 
+    def _track_metadata(
+        self, key: str, expire_time: Optional[int], tag: Optional[str]
+    ) -> None:
+        """Record *key*'s expiration time and tag for expire() and evict()"""
+        if expire_time is not None:
+            self._expire_times[key] = float(expire_time)
+        else:
+            self._expire_times.pop(key, None)
+
+        if tag is not None:
+            self._tags[key] = tag
+        else:
+            self._tags.pop(key, None)
+
+    @staticmethod
+    def _expire_timestamp(expire: Optional[float]) -> Optional[int]:
+        """Convert *expire* (seconds from now, or a timestamp) to a timestamp"""
+        if expire is None:
+            return None
+        if expire > time.time():
+            # Assume it's already a timestamp
+            return int(expire)
+        # Assume it's seconds from now
+        return int(time.time() + expire)
+
     def _serialize_value(self, value: Any) -> bytes:
         if type(value) is bytes:
             return _RAW_BYTES_PREFIX + value
@@ -230,6 +591,9 @@ class Cache:
         if data.startswith(_PICKLE_PREFIX):
             return pickle.loads(data[len(_PICKLE_PREFIX) :])
 
+        if self._unprefixed_is_bytes:
+            return data
+
         # Try pickle first (legacy format)
         try:
             return pickle.loads(data)
@@ -319,6 +683,35 @@ class Cache:
                 return (default, None)
             return default
 
+    def get_many(
+        self, keys: List[str], prefetch_prefix: Optional[str] = None
+    ) -> Dict[str, Any]:
+        """
+        Get values for several keys in one call
+
+        Keys that are not already in memory are read from disk in parallel
+        rather than one after another.
+
+        Args:
+            keys: Cache keys to look up
+            prefetch_prefix: Also load entries whose keys start with this
+                prefix into memory, so later gets of sibling keys are fast
+
+        Returns:
+            Dict mapping each present, unexpired key to its value
+        """
+        keys = list(keys)
+        now = time.time()
+        result = {}
+        for key, data in zip(keys, self._cache.get_many(keys, prefetch_prefix)):
+            if data is None:
+                continue
+            et = self._expire_times.get(key)
+            if et is not None and et <= now:
+                continue
+            result[key] = self._auto_deserialize(data)
+        return result
+
     def delete(self, key: str) -> bool:
         """
         Delete key from cache
@@ -335,9 +728,24 @@ class Cache:
                 self._expire_times.pop(key, None)
                 self._tags.pop(key, None)
             return result
+        except PermissionError:
+            raise
         except Exception:
             return False
 
+    def undelete(self, key: str) -> bool:
+        """
+        Restore a deleted key from the trash kept with ``soft_delete``
+
+        Args:
+            key: Cache key to restore
+
+        Returns:
+            True if the key was in the trash and has been restored; a key set
+            again since it was deleted keeps its new value
+        """
+        return self._cache.undelete(key)
+
     def exists(self, key: str) -> bool:
         """Check if key exists in cache"""
         try:
@@ -352,6 +760,442 @@ class Cache:
         except Exception:
             return False
 
+    def get_metadata(self, key: str) -> Optional[Dict[str, Any]]:
+        """
+        Return metadata for key without reading its value
+
+        The ``version`` stamp is opaque and changes whenever the key is
+        written, so callers can remember it and later pass it to
+        :meth:`has_changed` for cheap change detection.
+
+        Args:
+            key: Cache key
+
+        Returns:
+            Dict with ``version``, ``expire_time`` and ``tag``, or None if the
+            key is not in the cache
+        """
+        version = self._cache.version(key)
+        if version is None:
+            return None
+        expire_time = self._expire_times.get(key)
+        if expire_time is not None and expire_time <= time.time():
+            return None
+        return {
+            "version": version,
+            "expire_time": expire_time,
+            "tag": self._tags.get(key),
+        }
+
+    def has_changed(self, key: str, version: int) -> bool:
+        """
+        Check whether key was rewritten or removed since it had *version*
+
+        Args:
+            key: Cache key
+            version: Version stamp from :meth:`get_metadata`
+
+        Returns:
+            True unless key is still stored with the same version
+        """
+        return self._cache.has_changed(key, version)
+
+    def get_with_version(
+        self, key: str, default: Any = None
+    ) -> Tuple[Any, Optional[int]]:
+        """
+        Return key's value together with the version stamp it was written with
+
+        Pass the version to :meth:`cas` to store a value computed from this
+        one. A key another process is rewriting can briefly read as missing;
+        the following :meth:`cas` then fails and the caller reads again.
+
+        Args:
+            key: Cache key
+            default: Value to return if key is not in the cache
+
+        Returns:
+            ``(value, version)``, or ``(default, None)`` if key is missing
+        """
+        found = self._cache.get_with_version(key)
+        if found is None:
+            return default, None
+        serialized_value, version = found
+        return self._auto_deserialize(serialized_value), version
+
+    def cas(
+        self,
+        key: str,
+        version: Optional[int],
+        value: Any,
+        expire: Optional[float] = None,
+        tag: Optional[str] = None,
+    ) -> bool:
+        """
+        Set key to value only if it still has *version*
+
+        Optimistic concurrency for writers in any process: read with
+        :meth:`get_with_version`, compute the next value and retry from the
+        read while this returns False. Only writers of the same key wait on
+        each other, and only for the comparison and the write. ``set`` does
+        not take part, so writes through it can land in between.
+
+        Args:
+            key: Cache key
+            version: Version from :meth:`get_with_version`, or None to only
+                set key if it is missing
+            value: Value to store
+            expire: Expiration time (seconds from now, or timestamp)
+            tag: Tag for the entry
+
+        Returns:
+            True if the value was stored, False if key had another version
+
+        Raises:
+            PermissionError: If another process holds the writer lease
+
+        Example:
+            >>> while True:
+            ...     count, version = cache.get_with_version('count', 0)
+            ...     if cache.cas('count', version, count + 1):
+            ...         break
+        """
+        expire_time = self._expire_timestamp(expire)
+        stored = self._cache.cas(
+            key,
+            version,
+            self._serialize_value(value),
+            expire_time=expire_time,
+            tags=[tag] if tag else [],
+        )
+        if stored:
+            self._track_metadata(key, expire_time, tag)
+        return stored
+
+    def pin(self, key: str) -> None:
+        """
+        Never evict key, nor drop it from memory to make room
+
+        Pins belong to this cache object: other processes sharing the
+        directory evict by their own pins. The key need not be stored yet
+        and stays pinned across deletes; expiration still removes it.
+
+        Args:
+            key: Cache key
+
+        Example:
+            >>> cache.pin('license')
+            >>> cache.set('config', snapshot, pin=True)
+        """
+        self._cache.pin(key)
+
+    def unpin(self, key: str) -> bool:
+        """
+        Let key be evicted again
+
+        Args:
+            key: Cache key
+
+        Returns:
+            True if key was pinned
+        """
+        return self._cache.unpin(key)
+
+    def set_tag_limit(
+        self,
+        tag: str,
+        max_bytes: Optional[int] = None,
+        max_entries: Optional[int] = None,
+    ) -> None:
+        """
+        Cap the bytes or entries stored under tag, so one tenant cannot
+        crowd out the others
+
+        Writes leaving the tag over its limit evict within it first, and
+        the tag is brought under a new limit at once. Setting a limit reads
+        every stored entry once to count the tag's. Limits belong to this
+        cache object and are not stored.
+
+        Args:
+            tag: Tag to limit
+            max_bytes: Most bytes stored under tag
+            max_entries: Most entries stored under tag
+
+        Raises:
+            ValueError: If neither max_bytes nor max_entries is given
+
+        Example:
+            >>> cache.set_tag_limit('tenant-a', max_bytes=100 * 2**20)
+        """
+        self._cache.set_tag_limit(tag, max_bytes=max_bytes, max_entries=max_entries)
+
+    def remove_tag_limit(self, tag: str) -> bool:
+        """
+        Stop limiting tag
+
+        Returns:
+            True if tag was limited
+        """
+        return self._cache.remove_tag_limit(tag)
+
+    def set_eviction_strategy(self, strategy: str) -> None:
+        """
+        Pick victims by another eviction policy from now on, without
+        reopening the cache
+
+        The new policy starts from the order the current one keeps. Other
+        calls wait until the switch is done. The switch belongs to this
+        cache object: reopening uses eviction_policy again.
+
+        Args:
+            strategy: One of the eviction_policy names, such as
+                "least-recently-used" or "least-frequently-used"
+
+        Raises:
+            CacheConfigError: If strategy is unknown or cannot be used with
+                this cache's other options
+
+        Example:
+            >>> cache.set_eviction_strategy('least-frequently-used')
+        """
+        self._cache.set_eviction_strategy(strategy)
+
+    def lock_key(self, key: str) -> Any:
+        """
+        Hold key against other holders of its lock, in any process
+
+        Reading a value and writing back one computed from it is atomic
+        under this lock, also on network filesystems. The lock is advisory:
+        writes that do not take it are not held back. :meth:`add`,
+        :meth:`incr` and :meth:`decr` take it.
+
+        Args:
+            key: Cache key
+
+        Returns:
+            Lock held until ``release()`` or the end of its ``with`` block
+
+        Example:
+            >>> with cache.lock_key('balance'):
+            ...     cache['balance'] = cache.get('balance', 0) - 10
+        """
+        return self._cache.lock_key(key)
+
+    def subscribe(
+        self,
+        prefix: Optional[str] = None,
+        tag: Optional[str] = None,
+        subscription: Any = None,
+    ) -> Any:
+        """
+        Hear about keys under *prefix*, or set with *tag*, changing
+
+        Iterating the subscription blocks for each next ``(event, key)``
+        pair, *event* being ``"set"``, ``"delete"``, ``"evict"``,
+        ``"expire"`` or ``"clear"``. Changes other processes make arrive too when the cache
+        has ``event_log=True`` or is attached to a cache server; a
+        ``"clear"`` with an empty key stands for changes that were missed.
+        A tag only hears about deletions and expirations of keys it saw
+        being set with the tag.
+
+        Args:
+            prefix: Key prefix to follow (default every key)
+            tag: Tag to follow instead of a prefix
+            subscription: Existing subscription to add the topic to
+
+        Returns:
+            Subscription open until ``close()`` or the end of its ``with``
+            block; ``get(timeout)`` waits for one change at most *timeout*
+            seconds
+
+        Example:
+            >>> with cache.subscribe(prefix='user:') as changes:
+            ...     for event, key in changes:
+            ...         derived.pop(key, None)
+        """
+        return self._cache.subscribe(
+            prefix=prefix, tag=tag, subscription=subscription
+        )
+
+    def on_evict(self, callback: Callable[[str, str], Any]) -> Callable[[str, str], Any]:
+        """
+        Call *callback* with ``(key, "evict")`` for every entry this cache
+        evicts to stay within its limits
+
+        Callbacks run one after another on a background thread, shortly
+        after the eviction, in the order they were added; an exception one
+        raises is reported and does not stop the others. Usable as a
+        decorator.
+
+        Args:
+            callback: Called with the evicted key and ``"evict"``
+
+        Returns:
+            *callback*
+
+        Example:
+            >>> @cache.on_evict
+            ... def invalidate(key, reason):
+            ...     derived.pop(key, None)
+        """
+        return self._on_removal("evict", callback)
+
+    def on_expire(self, callback: Callable[[str, str], Any]) -> Callable[[str, str], Any]:
+        """
+        Call *callback* with ``(key, "expire")`` for every entry dropped on
+        being found past its expiration time
+
+        Like :meth:`on_evict`, which the same callback can be added to as
+        well and tell the two apart by its second argument. Entries other
+        processes find expired count too when the cache has
+        ``event_log=True`` or is attached to a cache server.
+
+        Args:
+            callback: Called with the expired key and ``"expire"``
+
+        Returns:
+            *callback*
+        """
+        return self._on_removal("expire", callback)
+
+    def _on_removal(
+        self, event: str, callback: Callable[[str, str], Any]
+    ) -> Callable[[str, str], Any]:
+        """Add *callback* for *event*, starting the thread that calls them"""
+        with self._removals_lock:
+            self._removal_callbacks.setdefault(event, []).append(callback)
+            if self._removals is None:
+                self._removals = self._cache.subscribe()
+                # Not a method, so the thread does not keep the cache open
+                threading.Thread(
+                    target=_dispatch_removals,
+                    args=(self._removals, self._removal_callbacks),
+                    name="diskcache-removals",
+                    daemon=True,
+                ).start()
+        return callback
+
+    def get_or_load(
+        self,
+        key: str,
+        loader: Callable[[], Any],
+        expire: Optional[float] = None,
+        tag: Optional[str] = None,
+    ) -> Any:
+        """
+        Get key, computing and storing it with *loader* on a miss
+
+        Threads of this process that miss on the same key while *loader* runs
+        wait for its result instead of calling their own loader. If it raises,
+        the exception reaches only its caller and one of the waiters loads next.
+        :meth:`get_or_set` also waits for loads in other processes.
+
+        Args:
+            key: Cache key
+            loader: Called without arguments to compute the value
+            expire: Expiration time (seconds from now, or timestamp)
+            tag: Tag for a loaded entry
+
+        Returns:
+            Cached or freshly loaded value
+        """
+        expire_time = self._expire_timestamp(expire)
+        loaded = False
+
+        def load() -> bytes:
+            nonlocal loaded
+            serialized_value = self._serialize_value(loader())
+            loaded = True
+            return serialized_value
+
+        data = self._cache.get_or_load(
+            key, load, expire_time=expire_time, tags=[tag] if tag else []
+        )
+        if loaded:
+            self._track_metadata(key, expire_time, tag)
+        return self._auto_deserialize(data)
+
+    def get_or_set(
+        self,
+        key: str,
+        factory: Callable[[], Any],
+        expire: Optional[float] = None,
+        tag: Optional[str] = None,
+        stale: Optional[float] = None,
+    ) -> Any:
+        """
+        Get key, computing and storing it with *factory* on a miss, one
+        process at a time
+
+        Only one caller computes a missing key, whichever thread of whichever
+        process sharing the cache gets to it first; the others wait and
+        return its value. With *stale*, an expired value is kept that many
+        seconds longer: the first caller to find it expired computes the
+        next one while everyone else keeps getting the expired value, so
+        only misses ever wait. Plain reads see the expired value meanwhile
+        too. If *factory* raises, the exception reaches only its caller.
+
+        Args:
+            key: Cache key
+            factory: Called without arguments to compute the value
+            expire: Expiration time (seconds from now, or timestamp)
+            tag: Tag for a computed entry
+            stale: Seconds an expired value is still returned while its
+                successor is computed
+
+        Returns:
+            Cached or freshly computed value
+
+        Example:
+            >>> report = cache.get_or_set('report', build_report, expire=300, stale=60)
+        """
+        expire_time = self._expire_timestamp(expire)
+        if stale is not None and stale < 0:
+            raise ValueError("stale must not be negative")
+        loaded = False
+
+        def load() -> bytes:
+            nonlocal loaded
+            serialized_value = self._serialize_value(factory())
+            loaded = True
+            return serialized_value
+
+        data = self._cache.get_or_set(
+            key,
+            load,
+            expire_time=expire_time,
+            tags=[tag] if tag else [],
+            stale=None if stale is None else math.ceil(stale),
+        )
+        if loaded:
+            self._track_metadata(key, expire_time, tag)
+        return self._auto_deserialize(data)
+
+    def alias(self, alias: str, key: str) -> None:
+        """
+        Make *alias* a second name for *key* without storing the value twice
+
+        Reads through the alias see every later write to *key*, and deleting
+        *key* removes its aliases with it. ``del cache[alias]`` removes only
+        the alias.
+
+        Args:
+            alias: Additional lookup key
+            key: Stored key, or an existing alias of one
+
+        Raises:
+            KeyError: If *key* is not in the cache
+        """
+        self._cache.alias(alias, key)
+
+    def unalias(self, alias: str) -> bool:
+        """Remove *alias*, keeping the entry it points at; True if it existed"""
+        return self._cache.unalias(alias)
+
+    def aliases(self, key: str) -> List[str]:
+        """Return the aliases that point at *key*"""
+        return self._cache.aliases(key)
+
     def __getitem__(self, key: str) -> Any:
         """Get item using [] syntax"""
         result = self.get(key)
@@ -393,16 +1237,25 @@ class Cache:
         except Exception:
             return 0
 
-    def clear(self) -> int:
+    def clear(
+        self,
+        retry: bool = False,
+        progress: Optional[Callable[[int, int], None]] = None,
+    ) -> int:
         """
         Clear all items from cache
 
+        Args:
+            retry: Accepted for diskcache compatibility; clearing never times out
+            progress: Called as ``progress(done, total)`` from the removal
+                workers while data files are deleted, and once at the end
+
         Returns:
             Number of items removed
         """
         try:
             count = len(self)
-            self._cache.clear()
+            self._cache.clear(progress)
             self._expire_times.clear()
             self._tags.clear()
             return count
@@ -464,32 +1317,42 @@ class Cache:
                 return (default, None)
             return default
 
-    def stats(self, enable: bool = True, reset: bool = False) -> Dict[str, Any]:
+    def stats(
+        self, enable: bool = True, reset: bool = False, aggregate: bool = False
+    ) -> Tuple[int, int]:
         """
-        Get cache statistics
+        Return cache statistics hits and misses
 
         Args:
-            enable: Whether to enable stats (ignored)
-            reset: Whether to reset stats (not supported)
+            enable: Keep counting hits and misses after this call (default True)
+            reset: Reset hits and misses to 0 after reading them (default False)
+            aggregate: Count the hits and misses of every process that has
+                used the directory, including ones that have closed it, instead
+                of this process's only. Other processes' counts are written out
+                every few seconds, so they may lag slightly. A reset still only
+                zeroes this process's counts. (default False)
 
         Returns:
-            Dictionary of statistics
+            (hits, misses) as they were before any reset
         """
-        try:
-            rust_stats = self._cache.stats()
-
-            # Convert to python-diskcache compatible format
-            return {
-                "hits": rust_stats.get("hits", 0),
-                "misses": rust_stats.get("misses", 0),
-                "sets": rust_stats.get("sets", 0),
-                "deletes": rust_stats.get("deletes", 0),
-                "evictions": rust_stats.get("evictions", 0),
-                "size": rust_stats.get("total_size", 0),
-                "count": rust_stats.get("entry_count", 0),
-            }
-        except Exception:
-            return {}
+        return self._cache.hit_stats(enable, reset, aggregate)
+
+    def top_keys(self, n: int = 10) -> List[Tuple[str, int]]:
+        """
+        Return the keys with the most hits and their hit counts
+
+        Counts are approximate: they come from a fixed-size sketch, may run
+        slightly high but never low, and halve every few tens of thousands
+        of hits so they follow current traffic. Only the few hundred most
+        hit keys are tracked, and only while statistics are enabled.
+
+        Args:
+            n: How many keys to return (default 10)
+
+        Returns:
+            (key, hits) pairs, most hit first
+        """
+        return self._cache.top_keys(n)
 
     def volume(self) -> int:
         """Get cache size in bytes"""
@@ -521,9 +1384,10 @@ class Cache:
         Returns:
             True if key was added, False if key already exists
         """
-        if key in self:
-            return False
-        return self.set(key, value, expire, read, tag, retry)
+        with self.lock_key(key):
+            if key in self:
+                return False
+            return self.set(key, value, expire, read, tag, retry)
 
     def incr(
         self, key: str, delta: int = 1, default: int = 0, retry: bool = False
@@ -540,21 +1404,22 @@ class Cache:
         Returns:
             New value after increment
         """
-        try:
-            current = self.get(key)
-            if current is None:
+        with self.lock_key(key):
+            try:
+                current = self.get(key)
+                if current is None:
+                    new_value = default + delta
+                else:
+                    new_value = int(current) + delta
+                self.set(key, new_value)
+                return new_value
+            except Exception:
+                # If key doesn't exist and no default provided, raise KeyError
+                if default is None:
+                    raise KeyError(key)
                 new_value = default + delta
-            else:
-                new_value = int(current) + delta
-            self.set(key, new_value)
-            return new_value
-        except Exception:
-            # If key doesn't exist and no default provided, raise KeyError
-            if default is None:
-                raise KeyError(key)
-            new_value = default + delta
-            self.set(key, new_value)
-            return new_value
+                self.set(key, new_value)
+                return new_value
 
     def decr(
         self, key: str, delta: int = 1, default: int = 0, retry: bool = False
@@ -640,12 +1505,224 @@ class Cache:
 
         return count
 
-    def vacuum(self) -> None:
-        """Manually trigger vacuum operation to sync pending writes"""
-        self._cache.vacuum()
+    def vacuum(self) -> Dict[str, int]:
+        """
+        Manually trigger vacuum operation to sync pending writes
+
+        Also reclaims data files left behind by crashes or a lost index: ones
+        that can be indexed again under the key in their header are restored,
+        the rest are deleted. Data files longer than the value they hold are
+        truncated, and on Linux the regions of pack segments left by
+        overwritten or deleted values are punched out. Only files untouched
+        for an hour are considered.
+
+        Returns:
+            Dict with ``orphans_removed``, ``orphans_adopted``,
+            ``temp_files_removed`` and ``files_truncated`` counts, and the
+            ``bytes_reclaimed`` by truncating and hole punching
+        """
+        return self._cache.vacuum()
+
+    def info(self) -> Dict[str, Any]:
+        """
+        Describe the cache directory.
+
+        Returns:
+            Dictionary with ``directory``, ``was_unclean_shutdown`` (True when
+            the previous owner exited without closing the cache, None when
+            the filesystem has no file locks to tell by) and
+            ``last_recovery`` (result of the last verify_and_recover() pass)
+        """
+        return self._cache.info()
+
+    def verify_and_recover(self) -> Dict[str, Any]:
+        """
+        Check every indexed entry, drop the ones whose data is missing and
+        index data files the index lost track of.
+
+        Returns:
+            Dictionary with ``entries_checked``, ``entries_removed``,
+            ``entries_restored`` (entries indexed again from the key recorded
+            in their data file) and ``integrity_ok``
+        """
+        return self._cache.verify_and_recover()
+
+    def snapshot(self, dest_path: Union[str, os.PathLike]) -> Dict[str, int]:
+        """
+        Write a consistent point-in-time copy of the cache to ``dest_path``,
+        which must be empty or not exist yet.
+
+        Queued writes are flushed and the index copied in one step; data
+        files are then hard-linked (copied across filesystems) while reads
+        and writes carry on. The copy opens as a cache of its own, which
+        makes this suitable for nightly backups. Optimized backend only.
+
+        Returns:
+            Dictionary with ``entries`` in the snapshot, ``files`` placed in
+            it and ``bytes_copied`` for files that could not be linked
+        """
+        return self._cache.snapshot(os.fspath(dest_path))
+
+    def migrate_backend(self, target: str) -> int:
+        """
+        Move every entry into a new ``target`` backend ("optimized",
+        "sqlite", "redb" or "ring") in the same directory and switch to it,
+        without clearing the cache.
+
+        The new backend is built next to the current one, and other calls
+        wait until the switch is done; a switch cut short by a crash is
+        finished when the directory is next opened. Entries of the old
+        per-file format are moved too. No other process may have the
+        directory open, and it must be reopened with ``backend=target``.
+
+        Returns:
+            Number of entries moved
+        """
+        moved = self._cache.migrate_backend(target)
+        # Values the sqlite backend kept as plain bytes were copied as such
+        self._unprefixed_is_bytes = self._unprefixed_is_bytes or target == "sqlite"
+        return moved
+
+    def export(self, path: Union[str, os.PathLike]) -> int:
+        """
+        Write every live entry, with its tags and expiration time, to a single
+        archive file at ``path``.
+
+        The archive is portable: :meth:`import_archive` restores it into a
+        cache on another machine or with another backend, which makes it a
+        way to ship pre-warmed caches to CI runners. Expired entries are left
+        out, and ``path`` is only replaced once the archive is complete.
+
+        Returns:
+            Number of entries written
+        """
+        expire_times = {key: int(expire) for key, expire in self._expire_times.items()}
+        tags = {key: [tag] for key, tag in self._tags.items()}
+        return self._cache.export(os.fspath(path), expire_times, tags)
+
+    @classmethod
+    def import_archive(
+        cls,
+        path: Union[str, os.PathLike],
+        dest_dir: Union[str, os.PathLike],
+        **kwargs,
+    ) -> "Cache":
+        """
+        Open a cache in ``dest_dir`` and load the archive written by
+        :meth:`export` at ``path`` into it.
+
+        Entries replace any already under the same keys; those that expired
+        since the export are skipped. Other keyword arguments are passed to
+        the constructor.
+
+        Raises:
+            Exception: If the archive is damaged or cut short
+        """
+        cache = cls(dest_dir, **kwargs)
+        for key, expire_time, tags in cache._cache.import_archive(os.fspath(path)):
+            cache._track_metadata(key, expire_time, tags[0] if tags else None)
+        return cache
+
+    def estimate(
+        self,
+        keys_per_day: int,
+        avg_value_size: int,
+        ttl: Optional[float] = None,
+    ) -> Dict[str, Any]:
+        """
+        Project the footprint of a workload on this cache's configuration.
+
+        Args:
+            keys_per_day: New keys written per day
+            avg_value_size: Average serialized value size in bytes
+            ttl: Seconds each key lives; without it nothing expires and
+                30 days of writes are projected
+
+        Returns:
+            Dict with entries, disk_bytes, memory_bytes,
+            recommended_max_entries, recommended_max_size and
+            fits_current_limits
+        """
+        return self._cache.estimate(keys_per_day, avg_value_size, ttl)
+
+    def relocate(self) -> int:
+        """
+        Rewrite absolute data file paths written by older versions.
+
+        Indexes now store paths relative to the cache directory. Run this once
+        on a cache created by an older version before moving or copying it.
+
+        Returns:
+            Number of index entries rewritten
+        """
+        return self._cache.relocate()
+
+    def link_file(self, key: str, path: Union[str, os.PathLike]) -> None:
+        """
+        Store the file at *path* as the value of *key* without reading it.
+
+        The file is hard-linked into the cache directory, or copied when it
+        is on another filesystem, so adopting a multi-gigabyte file costs no
+        more than a rename. The value is the file's bytes: use ``read()`` to
+        stream it. A linked file shares its contents with the original, so do
+        not modify the original in place afterwards; deleting it or replacing
+        it by rename leaves the cache untouched.
+
+        Args:
+            key: Cache key
+            path: Path of the file to adopt
+        """
+        self._cache.link_file(key, os.fspath(path))
+        self._track_metadata(key, None, None)
+
+    def open_write(self, key: str) -> "_ValueWriter":
+        """
+        Open a writable binary file that stores its contents as *key*'s value.
+
+        The value is streamed to its data file as it is written, so values
+        larger than memory can be cached, for instance with
+        ``shutil.copyfileobj``. It is stored when the file is closed; until
+        then *key* keeps its previous value. Leaving a ``with`` block by an
+        exception, or calling ``discard()``, drops what was written. The value
+        reads back as bytes: use ``read()`` to stream it out again.
+
+        Args:
+            key: Cache key
+
+        Returns:
+            Writable binary file object
+
+        Example:
+            >>> with cache.open_write('video') as writer:
+            ...     shutil.copyfileobj(source, writer)
+        """
+        return _ValueWriter(self, key, self._cache.open_write(key))
+
+    def load_manifest(self, path: Union[str, os.PathLike]) -> int:
+        """
+        Serve prepackaged files through the cache without copying them.
+
+        The manifest is a JSON object mapping keys to file paths relative to
+        the manifest's directory, typically written next to an asset bundle at
+        build time. Each key reads back as its file's bytes: use ``read()`` or
+        ``get()`` on binary files. The files stay where they are and are never
+        modified or removed by the cache; deleting or clearing the keys only
+        forgets them.
+
+        Args:
+            path: Path of the manifest file
+
+        Returns:
+            Number of keys registered
+        """
+        return self._cache.load_manifest(os.fspath(path))
 
     def close(self) -> None:
         """Close cache and release resources (especially redb database lock)"""
+        removals = getattr(self, "_removals", None)
+        if removals is not None:
+            removals.close()
+            self._removals = None
         if hasattr(self, "_cache") and self._cache is not None:
             self._cache.close()
             del self._cache
@@ -665,7 +1742,10 @@ class Cache:
 
     def __getstate__(self):
         """Support pickling by returning directory and timeout."""
-        return (str(self._directory), self._timeout)
+        directory = self._server or (
+            None if self._directory is None else str(self._directory)
+        )
+        return (directory, self._timeout)
 
     def __setstate__(self, state):
         """Restore cache from pickled state."""
@@ -679,6 +1759,8 @@ class Cache:
         expire: Optional[float] = None,
         tag: Optional[str] = None,
         ignore: Set[str] = frozenset(),
+        lock: bool = False,
+        early_recompute: Optional[float] = None,
     ) -> Callable:
         """
         Memoizing cache decorator.
@@ -687,16 +1769,30 @@ class Cache:
         Repeated calls with the same arguments will lookup result in cache and
         avoid function evaluation.
 
+        Concurrent calls with the same arguments in this process share one
+        evaluation. With *lock*, calls in other processes sharing the cache
+        wait for it too, through :meth:`get_or_set`. With *early_recompute*,
+        the last fraction of *expire* is a refresh period: the first call to
+        land in it evaluates the function again while every other call, in
+        any process, keeps returning the previous result.
+
         Args:
             name: Name for callable (default None, uses function name)
             typed: Cache different types separately (default False)
             expire: Seconds until arguments expire (default None, no expiry)
             tag: Text to associate with arguments (default None)
             ignore: Positional or keyword args to ignore (default empty set)
+            lock: Evaluate once across processes (default False)
+            early_recompute: Fraction of *expire*, between 0 and 1, during
+                which one call refreshes the result (default None, implies
+                *lock*)
 
         Returns:
             Decorator function
 
+        Raises:
+            ValueError: If *early_recompute* is not a fraction or lacks *expire*
+
         Example:
             >>> cache = Cache()
             >>> @cache.memoize(expire=60)
@@ -704,7 +1800,11 @@ class Cache:
             ...     return x * x
             >>> expensive_function(5)
             25
+            >>> @cache.memoize(expire=3600, early_recompute=0.1)
+            ... def compile_shader(source):
+            ...     return source.upper()
         """
+        _check_early_recompute(expire, early_recompute)
 
         def decorator(func: Callable) -> Callable:
             # Determine the cache key prefix
@@ -717,17 +1817,14 @@ class Cache:
                     cache_key_prefix, args, kwargs, typed, ignore
                 )
 
-                # Try to get from cache - use __contains__ to check existence
-                if cache_key in self:
-                    return self.get(cache_key)
-
-                # Call the function
-                result = func(*args, **kwargs)
-
-                # Store in cache
-                self.set(cache_key, result, expire=expire)
-
-                return result
+                return self._load_memoized(
+                    cache_key,
+                    lambda: func(*args, **kwargs),
+                    expire,
+                    tag,
+                    lock,
+                    early_recompute,
+                )
 
             # Add __cache_key__ method to generate cache key
             def cache_key(*args, **kwargs):
@@ -740,6 +1837,30 @@ class Cache:
 
         return decorator
 
+    def _load_memoized(
+        self,
+        key: str,
+        evaluate: Callable[[], Any],
+        expire: Optional[float],
+        tag: Optional[str],
+        lock: bool,
+        early_recompute: Optional[float],
+    ) -> Any:
+        """Get a memoized result, evaluating it the way memoize's options ask"""
+        if early_recompute is not None:
+            # Fresh for the start of expire; one call refreshes it during the rest
+            return self.get_or_set(
+                key,
+                evaluate,
+                expire=expire * (1 - early_recompute),
+                tag=tag,
+                stale=expire * early_recompute,
+            )
+        if lock:
+            return self.get_or_set(key, evaluate, expire=expire, tag=tag)
+        # Concurrent calls with the same arguments share one evaluation
+        return self.get_or_load(key, evaluate, expire=expire, tag=tag)
+
     def _make_key(
         self,
         prefix: str,
@@ -793,10 +1914,12 @@ class Cache:
 
     def iterkeys(self, reverse: bool = False) -> Iterator[str]:
         """
-        Iterate cache keys in database sort order.
+        Iterate cache keys in store order, fetching them lazily.
+
+        Overwriting a key moves it to the end of store order.
 
         Args:
-            reverse: Reverse sort order (default False)
+            reverse: Newest keys first (default False)
 
         Returns:
             Iterator of cache keys
@@ -806,14 +1929,11 @@ class Cache:
             >>> for key in [4, 1, 3, 0, 2]:
             ...     cache[key] = key
             >>> list(cache.iterkeys())
-            [0, 1, 2, 3, 4]
+            ['4', '1', '3', '0', '2']
             >>> list(cache.iterkeys(reverse=True))
-            [4, 3, 2, 1, 0]
+            ['2', '0', '3', '1', '4']
         """
-        keys = sorted(self.keys())
-        if reverse:
-            keys = reversed(keys)
-        return iter(keys)
+        return self._cache.iterkeys(reverse)
 
     def __reversed__(self) -> Iterator[str]:
         """
@@ -832,10 +1952,13 @@ class Cache:
         retry: bool = False,
     ) -> tuple:
         """
-        Peek at key and value item pair in cache based on iteration order.
+        Peek at key and value item pair in cache based on store order.
+
+        Expired items are removed while searching. Reading an item does not
+        update its access statistics, so eviction order is left untouched.
 
         Args:
-            last: Last item in iteration order (default True)
+            last: Most recently stored item if True, oldest if False (default True)
             expire_time: If True, return expire_time in tuple (default False)
             tag: If True, return tag in tuple (default False)
             retry: Retry if database timeout occurs (default False)
@@ -855,14 +1978,19 @@ class Cache:
             >>> cache.peekitem(last=False)
             ('a', 0)
         """
-        keys = list(self.keys())
-        if not keys:
-            raise KeyError("cache is empty")
+        while True:
+            item = self._cache.peekitem(last)
+            if item is None:
+                raise KeyError("cache is empty")
 
-        # Sort keys to get consistent ordering
-        keys = sorted(keys)
-        key = keys[-1] if last else keys[0]
-        value = self.get(key)
+            key, data = item
+            et = self._expire_times.get(key)
+            if et is not None and et <= time.time():
+                self.delete(key)
+                continue
+            break
+
+        value = self._auto_deserialize(data)
 
         if expire_time or tag:
             result = [key, value]
@@ -874,8 +2002,9 @@ class Cache:
         return (key, value)
 
     @property
-    def directory(self) -> Path:
-        """Cache directory path"""
+    def directory(self) -> Optional[Path]:
+        """Cache directory path, or None for a memory cache created without one
+        and for a cache attached to a cache server"""
         return self._directory
 
     @property
@@ -883,6 +2012,29 @@ class Cache:
         """SQLite connection timeout value in seconds"""
         return self._timeout
 
+    @property
+    def read_only(self) -> bool:
+        """Whether writes fail because another process holds the writer lease"""
+        return self._cache.is_read_only()
+
+    @property
+    def maintenance_leader(self) -> bool:
+        """Whether this process runs the automatic vacuum, expiry sweep and compaction"""
+        return self._cache.is_maintenance_leader()
+
+    def processes(self) -> List[Dict[str, Any]]:
+        """
+        List the processes that have the cache directory open, oldest first
+
+        Processes that died without closing the cache are dropped from the
+        list, and the locks and writer lease they held are released.
+
+        Returns:
+            One dict per process, with pid, host, opened_at and heartbeat
+            (seconds since the epoch), and current (whether it is this one)
+        """
+        return self._cache.processes()
+
     @contextmanager
     def transact(self, retry: bool = False):
         """
@@ -942,7 +2094,7 @@ class Cache:
         warnings: List[str] = []
         try:
             # Check directory exists
-            if not self._directory.exists():
+            if self._directory is not None and not self._directory.exists():
                 warnings.append(f"Cache directory does not exist: {self._directory}")
                 if fix:
                     self._directory.mkdir(parents=True, exist_ok=True)
@@ -1123,46 +2275,25 @@ class Cache:
         if side not in ("back", "front"):
             raise ValueError(f"side must be 'back' or 'front', got {side!r}")
 
-        # Use a lock to ensure atomic queue operations
-        with self._transaction_lock:
-            queue_prefix = prefix if prefix is not None else "__queue__"
-            counter_key = f"__queue_counter_{queue_prefix}__"
-            front_key = f"__queue_front_{queue_prefix}__"
-
-            # Get current counter values
-            back_counter = self.get(counter_key)
-            if back_counter is None:
-                back_counter = 0
-            else:
-                back_counter = int(back_counter)
-
-            front_counter = self.get(front_key)
-            if front_counter is None:
-                front_counter = 0
-            else:
-                front_counter = int(front_counter)
-
-            if side == "back":
-                key_index = back_counter
-                back_counter += 1
-            else:  # front
-                front_counter -= 1
-                key_index = front_counter
-
-            # Build the actual cache key
-            if prefix is not None:
-                cache_key = f"{prefix}-{key_index}"
-            else:
-                cache_key = f"__queue__{key_index}"
-
-            # Store the value
-            self.set(cache_key, value, expire=expire, tag=tag)
+        if read and hasattr(value, "read"):
+            value = value.read()
 
-            # Update counters
-            self.set(counter_key, back_counter)
-            self.set(front_key, front_counter)
+        expire_at = self._expire_timestamp(expire)
+        queue_prefix = prefix if prefix is not None else _QUEUE_PREFIX
 
-            return key_index
+        with self._transaction_lock:
+            position, key = self._cache.push(
+                queue_prefix,
+                self._serialize_value(value),
+                side=side,
+                expire_time=expire_at,
+                tags=[tag] if tag else [],
+            )
+            if expire_at is not None:
+                self._expire_times[key] = float(expire_at)
+            if tag is not None:
+                self._tags[key] = tag
+        return position
 
     def pull(
         self,
@@ -1206,60 +2337,22 @@ class Cache:
         if side not in ("front", "back"):
             raise ValueError(f"side must be 'front' or 'back', got {side!r}")
 
-        with self._transaction_lock:
-            queue_prefix = prefix if prefix is not None else "__queue__"
-            counter_key = f"__queue_counter_{queue_prefix}__"
-            front_key = f"__queue_front_{queue_prefix}__"
-
-            back_counter = self.get(counter_key)
-            if back_counter is None:
-                back_counter = 0
-            else:
-                back_counter = int(back_counter)
-
-            front_counter = self.get(front_key)
-            if front_counter is None:
-                front_counter = 0
-            else:
-                front_counter = int(front_counter)
-
-            # Check if queue is empty
-            if front_counter >= back_counter:
-                if expire_time and tag:
-                    return default + (None, None)
-                elif expire_time or tag:
-                    return default + (None,)
-                return default
-
-            if side == "front":
-                key_index = front_counter
-                front_counter += 1
-            else:  # back
-                back_counter -= 1
-                key_index = back_counter
+        queue_prefix = prefix if prefix is not None else _QUEUE_PREFIX
 
-            # Build the actual cache key
-            if prefix is not None:
-                cache_key = f"{prefix}-{key_index}"
-            else:
-                cache_key = f"__queue__{key_index}"
-
-            # Get the value and remove it
-            value = self.get(cache_key)
-            et = self._expire_times.get(cache_key)
-            t = self._tags.get(cache_key)
-            self.delete(cache_key)
+        with self._transaction_lock:
+            while True:
+                item = self._cache.pull(queue_prefix, side=side)
+                if item is None:
+                    return self._queue_default(default, expire_time, tag)
 
-            # Update counters
-            self.set(counter_key, back_counter)
-            self.set(front_key, front_counter)
+                position, key, data = item
+                et = self._expire_times.pop(key, None)
+                t = self._tags.pop(key, None)
+                # Expired items are dropped, as python-diskcache does
+                if et is None or et > time.time():
+                    break
 
-            result = (key_index, value)
-            if expire_time:
-                result = result + (et,)
-            if tag:
-                result = result + (t,)
-            return result
+        return self._queue_result(position, data, et, t, expire_time, tag)
 
     def peek(
         self,
@@ -1299,66 +2392,66 @@ class Cache:
         if side not in ("front", "back"):
             raise ValueError(f"side must be 'front' or 'back', got {side!r}")
 
-        with self._transaction_lock:
-            queue_prefix = prefix if prefix is not None else "__queue__"
-            counter_key = f"__queue_counter_{queue_prefix}__"
-            front_key = f"__queue_front_{queue_prefix}__"
-
-            back_counter = self.get(counter_key)
-            if back_counter is None:
-                back_counter = 0
-            else:
-                back_counter = int(back_counter)
-
-            front_counter = self.get(front_key)
-            if front_counter is None:
-                front_counter = 0
-            else:
-                front_counter = int(front_counter)
-
-            # Check if queue is empty
-            if front_counter >= back_counter:
-                if expire_time and tag:
-                    return default + (None, None)
-                elif expire_time or tag:
-                    return default + (None,)
-                return default
+        queue_prefix = prefix if prefix is not None else _QUEUE_PREFIX
 
-            if side == "front":
-                key_index = front_counter
-            else:  # back
-                key_index = back_counter - 1
-
-            # Build the actual cache key
-            if prefix is not None:
-                cache_key = f"{prefix}-{key_index}"
-            else:
-                cache_key = f"__queue__{key_index}"
+        with self._transaction_lock:
+            while True:
+                item = self._cache.peek_queue(queue_prefix, side=side)
+                if item is None:
+                    return self._queue_default(default, expire_time, tag)
+
+                position, key, data = item
+                et = self._expire_times.get(key)
+                if et is None or et > time.time():
+                    break
+                self.delete(key)
+
+        return self._queue_result(
+            position, data, et, self._tags.get(key), expire_time, tag
+        )
 
-            # Get the value (don't remove)
-            value = self.get(cache_key)
+    @staticmethod
+    def _queue_default(default: Tuple, expire_time: bool, tag: bool) -> Tuple:
+        if expire_time and tag:
+            return default + (None, None)
+        elif expire_time or tag:
+            return default + (None,)
+        return default
 
-            result = (key_index, value)
-            if expire_time:
-                result = result + (self._expire_times.get(cache_key),)
-            if tag:
-                result = result + (self._tags.get(cache_key),)
-            return result
+    def _queue_result(
+        self,
+        position: int,
+        data: bytes,
+        et: Optional[float],
+        t: Optional[str],
+        expire_time: bool,
+        tag: bool,
+    ) -> Tuple:
+        result = (position, self._auto_deserialize(data))
+        if expire_time:
+            result = result + (et,)
+        if tag:
+            result = result + (t,)
+        return result
 
-    def read(self, key: str, retry: bool = False) -> io.BytesIO:
+    def read(self, key: str, retry: bool = False) -> BinaryIO:
         """
         Return file handle value corresponding to *key* from cache.
 
-        This method returns a :class:`io.BytesIO` object wrapping the
-        cached bytes value, emulating the file-handle behavior of
-        python-diskcache.
+        Large bytes values are served from their data file: the handle is
+        opened on that file and positioned at the start of the value. Those
+        compressed with ``seekable_compression`` are served through a
+        seekable handle that decompresses only the frames read. Values kept
+        inline in the index, otherwise compressed, or stored as pickles are
+        spilled to an anonymous temporary file instead, so callers always get
+        a real binary file. Use the handle as a context manager to close it.
 
         Args:
             key: Cache key
             retry: Retry if database timeout occurs (default False)
 
         Returns:
-            File-like object for reading
+            Binary file object for reading
 
         Raises:
             KeyError: If key is not found
@@ -1367,21 +2460,52 @@ class Cache:
             >>> cache = Cache()
             >>> cache.set('key', b'hello')
             True
-            >>> reader = cache.read('key')
-            >>> reader.read()
+            >>> with cache.read('key') as reader:
+            ...     reader.read()
             b'hello'
         """
-        try:
-            serialized_value = self._cache.get(key)
-            if serialized_value is None:
-                raise KeyError(key)
-
-            # Return as a BytesIO file handle
-            return io.BytesIO(serialized_value)
-        except KeyError:
-            raise
-        except Exception:
+        located = self._cache.data_file(key)
+        if located is not None:
+            path, offset = located
+            try:
+                handle = open(path, "rb")
+            except FileNotFoundError:
+                # Rewritten or deleted since the lookup; fall back to a plain read
+                handle = None
+            if handle is not None:
+                # The value follows the data file's header
+                handle.seek(offset)
+                prefix = handle.read(len(_RAW_BYTES_PREFIX))
+                if prefix == _RAW_BYTES_PREFIX:
+                    return handle
+                if not prefix.startswith(b"\x00"):
+                    # Unprefixed values, such as files adopted by link_file(),
+                    # would be spilled byte for byte; serve the file itself
+                    handle.seek(offset)
+                    return handle
+                handle.close()
+
+        frames = self._cache.open_frames(key)
+        if frames is not None:
+            prefix = frames.read(len(_RAW_BYTES_PREFIX))
+            if prefix == _RAW_BYTES_PREFIX:
+                return io.BufferedReader(_FrameFile(frames, len(prefix)))
+            if not prefix.startswith(b"\x00"):
+                return io.BufferedReader(_FrameFile(frames, 0))
+            frames.close()
+
+        serialized_value = self._cache.get(key)
+        if serialized_value is None:
             raise KeyError(key)
+        for prefix in (_RAW_BYTES_PREFIX, _PICKLE_PREFIX):
+            if serialized_value.startswith(prefix):
+                serialized_value = serialized_value[len(prefix) :]
+                break
+
+        spill = tempfile.TemporaryFile()
+        spill.write(serialized_value)
+        spill.seek(0)
+        return spill
 
     def reset(self, key: str, value: Any = None, update: bool = True) -> Any:
         """
@@ -1481,6 +2605,93 @@ class Cache:
         return result
 
 
+class _ValueWriter:
+    """Writable binary file returned by ``Cache.open_write()``"""
+
+    def __init__(self, cache: Cache, key: str, writer: Any):
+        self._cache = cache
+        self._key = key
+        self._writer = writer
+        # Streamed values read back as bytes, like bytes passed to set()
+        writer.write(_RAW_BYTES_PREFIX)
+
+    @property
+    def closed(self) -> bool:
+        return self._writer.closed
+
+    def writable(self) -> bool:
+        return True
+
+    def readable(self) -> bool:
+        return False
+
+    def seekable(self) -> bool:
+        return False
+
+    def write(self, data: Union[bytes, bytearray, memoryview]) -> int:
+        """Append *data* to the value, returning the number of bytes written"""
+        return self._writer.write(bytes(data))
+
+    def flush(self) -> None:
+        self._writer.flush()
+
+    def close(self) -> None:
+        """Store the value; closing again does nothing"""
+        if not self._writer.closed:
+            self._writer.close()
+            self._cache._track_metadata(self._key, None, None)
+
+    def discard(self) -> None:
+        """Drop what was written, keeping the key's previous value"""
+        self._writer.discard()
+
+    def __enter__(self) -> "_ValueWriter":
+        return self
+
+    def __exit__(self, exc_type, exc_val, exc_tb) -> None:
+        if exc_type is not None:
+            self.discard()
+        else:
+            self.close()
+
+
+class _FrameFile(io.RawIOBase):
+    """Raw file over a value in compressed frames, from *origin* on"""
+
+    def __init__(self, frames: Any, origin: int):
+        self._frames = frames
+        self._origin = origin
+        frames.seek(origin)
+
+    def readable(self) -> bool:
+        return True
+
+    def seekable(self) -> bool:
+        return True
+
+    def readinto(self, buffer: Any) -> int:
+        data = self._frames.read(len(buffer))
+        buffer[: len(data)] = data
+        return len(data)
+
+    def seek(self, offset: int, whence: int = io.SEEK_SET) -> int:
+        if whence == io.SEEK_SET:
+            if offset < 0:
+                raise ValueError(f"negative seek position {offset}")
+            offset += self._origin
+        position = self._frames.seek(offset, whence)
+        if position < self._origin:
+            position = self._frames.seek(self._origin)
+        return position - self._origin
+
+    def tell(self) -> int:
+        return self._frames.tell() - self._origin
+
+    def close(self) -> None:
+        self._frames.close()
+        super().close()
+
+
 class _DiskProxy:
     """
     Lightweight proxy providing disk-like interface for API compatibility.
@@ -1525,17 +2736,29 @@ class FanoutCache:
         Initialize fanout cache
 
         Args:
-            directory: Base cache directory
+            directory: Base cache directory, or a "unix://" URL of a cache
+                server, which is then the only shard
             shards: Number of cache shards
             timeout: Operation timeout
-            **kwargs: Additional arguments passed to Cache
+            **kwargs: Additional arguments passed to Cache; lock_backend
+                defaults to "named_mutex" on Windows
         """
         if directory is None:
             directory = os.path.join(os.getcwd(), "cache")
+        if sys.platform == "win32":
+            # File locks on SMB shares are unreliable
+            kwargs.setdefault("lock_backend", "named_mutex")
+
+        self.timeout = timeout
+        if _is_server_url(directory):
+            # Every shard would reach the same server, the only writer anyway
+            self.directory = directory
+            self.shards = 1
+            self._caches = [Cache(directory, timeout=timeout, **kwargs)]
+            return
 
         self.directory = Path(directory)
         self.shards = shards
-        self.timeout = timeout
 
         # Create shard caches
         self._caches = []
@@ -1580,6 +2803,122 @@ class FanoutCache:
         """Check if key exists in appropriate shard"""
         return key in self._get_shard(key)
 
+    def get_metadata(self, key: str) -> Optional[Dict[str, Any]]:
+        """Return metadata for key from appropriate shard"""
+        return self._get_shard(key).get_metadata(key)
+
+    def has_changed(self, key: str, version: int) -> bool:
+        """Check whether key changed since *version* in appropriate shard"""
+        return self._get_shard(key).has_changed(key, version)
+
+    def get_with_version(
+        self, key: str, default: Any = None
+    ) -> Tuple[Any, Optional[int]]:
+        """Return key's value and version from appropriate shard"""
+        return self._get_shard(key).get_with_version(key, default)
+
+    def cas(
+        self, key: str, version: Optional[int], value: Any, **kwargs
+    ) -> bool:
+        """Set key to value if it still has *version* in appropriate shard"""
+        return self._get_shard(key).cas(key, version, value, **kwargs)
+
+    def lock_key(self, key: str) -> Any:
+        """Hold key's lock in appropriate shard"""
+        return self._get_shard(key).lock_key(key)
+
+    def pin(self, key: str) -> None:
+        """Never evict key from appropriate shard"""
+        self._get_shard(key).pin(key)
+
+    def unpin(self, key: str) -> bool:
+        """Let key be evicted from appropriate shard again"""
+        return self._get_shard(key).unpin(key)
+
+    def set_tag_limit(
+        self,
+        tag: str,
+        max_bytes: Optional[int] = None,
+        max_entries: Optional[int] = None,
+    ) -> None:
+        """Cap tag in every shard, splitting the limit evenly among them"""
+
+        def share(limit: Optional[int]) -> Optional[int]:
+            return None if limit is None else -(-limit // self.shards)
+
+        for cache in self._caches:
+            cache.set_tag_limit(
+                tag, max_bytes=share(max_bytes), max_entries=share(max_entries)
+            )
+
+    def remove_tag_limit(self, tag: str) -> bool:
+        """Stop limiting tag in every shard"""
+        return any([cache.remove_tag_limit(tag) for cache in self._caches])
+
+    def set_eviction_strategy(self, strategy: str) -> None:
+        """Pick victims by another eviction policy in every shard"""
+        for cache in self._caches:
+            cache.set_eviction_strategy(strategy)
+
+    def subscribe(
+        self,
+        prefix: Optional[str] = None,
+        tag: Optional[str] = None,
+        subscription: Any = None,
+    ) -> Any:
+        """Hear about keys changing in every shard through one subscription"""
+        for cache in self._caches:
+            subscription = cache.subscribe(
+                prefix=prefix, tag=tag, subscription=subscription
+            )
+        return subscription
+
+    def on_evict(self, callback: Callable[[str, str], Any]) -> Callable[[str, str], Any]:
+        """Call *callback* for every entry any shard evicts"""
+        for cache in self._caches:
+            cache.on_evict(callback)
+        return callback
+
+    def on_expire(self, callback: Callable[[str, str], Any]) -> Callable[[str, str], Any]:
+        """Call *callback* for every entry any shard finds expired"""
+        for cache in self._caches:
+            cache.on_expire(callback)
+        return callback
+
+    def processes(self) -> List[Dict[str, Any]]:
+        """List the processes that have any shard open, once each"""
+        seen = {}
+        for cache in self._caches:
+            for process in cache.processes():
+                # Each shard registers its opener; keep the earliest
+                ident = (process["host"], process["pid"])
+                if ident not in seen or process["opened_at"] < seen[ident]["opened_at"]:
+                    seen[ident] = process
+        return sorted(seen.values(), key=lambda process: process["opened_at"])
+
+    def get_or_load(
+        self,
+        key: str,
+        loader: Callable[[], Any],
+        expire: Optional[float] = None,
+        tag: Optional[str] = None,
+    ) -> Any:
+        """Get key or load it once across concurrent misses, in appropriate shard"""
+        return self._get_shard(key).get_or_load(key, loader, expire=expire, tag=tag)
+
+    def get_or_set(
+        self,
+        key: str,
+        factory: Callable[[], Any],
+        expire: Optional[float] = None,
+        tag: Optional[str] = None,
+        stale: Optional[float] = None,
+    ) -> Any:
+        """Get key or compute it once across processes, in appropriate shard"""
+        return self._get_shard(key).get_or_set(
+            key, factory, expire=expire, tag=tag, stale=stale
+        )
+
     def __getitem__(self, key: str) -> Any:
         """Get item using [] syntax"""
         return self._get_shard(key)[key]
@@ -1605,24 +2944,18 @@ class FanoutCache:
         """Clear all items from all shards"""
         return sum(cache.clear() for cache in self._caches)
 
-    def stats(self, **kwargs) -> Dict[str, Any]:
-        """Get combined statistics from all shards"""
-        combined_stats = {
-            "hits": 0,
-            "misses": 0,
-            "sets": 0,
-            "deletes": 0,
-            "evictions": 0,
-            "size": 0,
-            "count": 0,
-        }
-
+    def stats(
+        self, enable: bool = True, reset: bool = False, aggregate: bool = False
+    ) -> Tuple[int, int]:
+        """Return (hits, misses) summed over all shards; see `Cache.stats`"""
+        hits = misses = 0
         for cache in self._caches:
-            shard_stats = cache.stats(**kwargs)
-            for key in combined_stats:
-                combined_stats[key] += shard_stats.get(key, 0)
-
-        return combined_stats
+            shard_hits, shard_misses = cache.stats(
+                enable=enable, reset=reset, aggregate=aggregate
+            )
+            hits += shard_hits
+            misses += shard_misses
+        return (hits, misses)
 
     def volume(self) -> int:
         """Get total cache size across all shards"""
@@ -1715,6 +3048,8 @@ class FanoutCache:
         expire: Optional[float] = None,
         tag: Optional[str] = None,
         ignore: Set[str] = frozenset(),
+        lock: bool = False,
+        early_recompute: Optional[float] = None,
     ) -> Callable:
         """
         Memoizing cache decorator.
@@ -1724,7 +3059,8 @@ class FanoutCache:
         avoid function evaluation.
 
         The cache key is determined by hashing the function arguments, and the
-        appropriate shard is selected based on this key.
+        appropriate shard is selected based on this key. *lock* and
+        *early_recompute* work as for :meth:`Cache.memoize`.
 
         Args:
             name: Name for callable (default None, uses function name)
@@ -1732,6 +3068,10 @@ class FanoutCache:
             expire: Seconds until arguments expire (default None, no expiry)
             tag: Text to associate with arguments (default None)
             ignore: Positional or keyword args to ignore (default empty set)
+            lock: Evaluate once across processes (default False)
+            early_recompute: Fraction of *expire*, between 0 and 1, during
+                which one call refreshes the result (default None, implies
+                *lock*)
 
         Returns:
             Decorator function
@@ -1744,6 +3084,7 @@ class FanoutCache:
             >>> expensive_function(5)
             25
         """
+        _check_early_recompute(expire, early_recompute)
 
         def decorator(func: Callable) -> Callable:
             # Determine the cache key prefix
@@ -1759,17 +3100,14 @@ class FanoutCache:
                 # Get the appropriate shard
                 shard = self._get_shard(cache_key)
 
-                # Try to get from cache - use __contains__ to check existence
-                if cache_key in shard:
-                    return shard.get(cache_key)
-
-                # Call the function
-                result = func(*args, **kwargs)
-
-                # Store in cache
-                shard.set(cache_key, result, expire=expire)
-
-                return result
+                return shard._load_memoized(
+                    cache_key,
+                    lambda: func(*args, **kwargs),
+                    expire,
+                    tag,
+                    lock,
+                    early_recompute,
+                )
 
             # Add __cache_key__ method to generate cache key
             def cache_key(*args, **kwargs):
@@ -2009,7 +3347,7 @@ class FanoutCache:
         """
         return sum(cache.evict(tag, retry=retry) for cache in self._caches)
 
-    def read(self, key: str, retry: bool = False) -> io.BytesIO:
+    def read(self, key: str, retry: bool = False) -> BinaryIO:
         """
         Return file handle value corresponding to *key* from cache.
 
@@ -2258,10 +3596,13 @@ class FanoutCache:
         cache_dir = self.directory / name
         return Index(directory=cache_dir)
 
-    def vacuum(self) -> None:
-        """Manually trigger vacuum operation on all shards."""
+    def vacuum(self) -> Dict[str, int]:
+        """Manually trigger vacuum operation on all shards, summing their reports."""
+        totals: Dict[str, int] = {}
         for cache in self._caches:
-            cache.vacuum()
+            for name, count in cache.vacuum().items():
+                totals[name] = totals.get(name, 0) + count
+        return totals
 
     def __del__(self):
         """Destructor to ensure resources are released."""
@@ -2380,7 +3721,7 @@ class Deque:
         """Iterate over deque items from front to back."""
         front, back = self._get_queue_range()
         for i in range(front, back):
-            cache_key = f"__queue__{i}"
+            cache_key = _queue_key(_QUEUE_PREFIX, i)
             value = self._cache.get(cache_key)
             if value is not None:
                 yield value
@@ -2389,7 +3730,7 @@ class Deque:
         """Iterate over deque items from back to front."""
         front, back = self._get_queue_range()
         for i in range(back - 1, front - 1, -1):
-            cache_key = f"__queue__{i}"
+            cache_key = _queue_key(_QUEUE_PREFIX, i)
             value = self._cache.get(cache_key)
             if value is not None:
                 yield value
@@ -2423,28 +3764,18 @@ class Deque:
 
     def _get_queue_range(self):
         """Get front and back indices of the queue."""
-        queue_prefix = "__queue__"
-        counter_key = f"__queue_counter_{queue_prefix}__"
-        front_key = f"__queue_front_{queue_prefix}__"
-
-        back = self._cache.get(counter_key)
-        front = self._cache.get(front_key)
-        if back is None:
-            back = 0
-        else:
-            back = int(back)
+        front, _ = self._cache.peek(side="front")
         if front is None:
-            front = 0
-        else:
-            front = int(front)
-        return front, back
+            return 0, 0
+        back, _ = self._cache.peek(side="back")
+        return front, back + 1
 
     def _get_all_items(self):
         """Get all items as a list."""
         front, back = self._get_queue_range()
         items = []
         for i in range(front, back):
-            cache_key = f"__queue__{i}"
+            cache_key = _queue_key(_QUEUE_PREFIX, i)
             value = self._cache.get(cache_key)
             if value is not None:
                 items.append(value)
@@ -2464,7 +3795,7 @@ class Deque:
                 index += length
             if index < 0 or index >= length:
                 raise IndexError("deque index out of range")
-            cache_key = f"__queue__{front + index}"
+            cache_key = _queue_key(_QUEUE_PREFIX, front + index)
             self._cache.set(cache_key, value)
         else:
             raise TypeError(f"deque indices must be integers, not {type(index).__name__}")
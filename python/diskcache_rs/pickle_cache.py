@@ -3,7 +3,7 @@ High-performance pickle cache with expiration support
 """
 
 import pickle
-from typing import Any, Dict, List, Optional
+from typing import Any, Callable, Dict, List, Optional
 
 from ._diskcache_rs import PickleCache as _PickleCache
 
@@ -17,12 +17,21 @@ class PickleCache:
     - LRU eviction when size limits are reached
     - Efficient pickle serialization/deserialization
     - Thread-safe operations
+    - Schema versions with upgrade callbacks, so objects cached by older
+      application versions are upgraded or dropped at read time
 
     Example:
         >>> cache = PickleCache("/tmp/pickle_cache", max_size=100*1024*1024)  # 100MB limit
         >>> cache.set("key", {"data": "value"}, ttl_seconds=3600)  # Expire in 1 hour
         >>> data = cache.get("key")
         >>> print(data)  # {"data": "value"}
+
+    Schema evolution:
+        >>> cache = PickleCache("/tmp/pickle_cache", schema_version=2)
+        >>> @cache.upgrade(1)
+        ... def add_email(user):
+        ...     return {**user, "email": None}
+        >>> cache.get("user")  # Stored under version 1, returned as version 2
     """
 
     def __init__(
@@ -30,6 +39,7 @@ class PickleCache:
         directory: str,
         max_size: Optional[int] = None,
         default_ttl_seconds: Optional[int] = None,
+        schema_version: Optional[int] = None,
     ):
         """
         Initialize the pickle cache.
@@ -38,8 +48,55 @@ class PickleCache:
             directory: Directory to store cache files
             max_size: Maximum cache size in bytes (None for unlimited)
             default_ttl_seconds: Default TTL for entries in seconds (None for no expiration)
+            schema_version: Version of the cached objects' schema. Values are
+                stored under it, and values stored under an older version are
+                passed through the registered upgrades on read, or dropped
+                when no chain of upgrades reaches this version. Entries from
+                before schema versions were used count as version 0. None
+                (the default) stores version 0 and reads every entry as is.
         """
         self._cache = _PickleCache(directory, max_size, default_ttl_seconds)
+        self._schema_version = schema_version
+        self._upgrades: Dict[int, Callable[[Any], Any]] = {}
+
+    def register_upgrade(self, from_version: int, upgrade: Callable[[Any], Any]) -> None:
+        """
+        Register how to turn an object of schema ``from_version`` into one of
+        ``from_version + 1``.
+
+        Upgrades are chained: an entry stored under version 1 read by a cache
+        at version 3 goes through the upgrades from 1 and from 2. The upgraded
+        object is written back, so each entry is upgraded once.
+
+        Args:
+            from_version: Schema version the callback accepts
+            upgrade: Callable taking the old object and returning the new one
+        """
+        if self._schema_version is None:
+            raise ValueError("Upgrades need a cache opened with a schema_version")
+        if not 0 <= from_version < self._schema_version:
+            raise ValueError(
+                f"from_version must be below the cache's schema version {self._schema_version}"
+            )
+        self._upgrades[from_version] = upgrade
+
+    def upgrade(self, from_version: int) -> Callable[[Callable[[Any], Any]], Callable[[Any], Any]]:
+        """
+        Decorator form of :meth:`register_upgrade`.
+        """
+
+        def decorator(func: Callable[[Any], Any]) -> Callable[[Any], Any]:
+            self.register_upgrade(from_version, func)
+            return func
+
+        return decorator
+
+    def schema_version_of(self, key: str) -> Optional[int]:
+        """
+        Schema version the value of ``key`` was stored under, or None if the
+        key doesn't exist or has expired.
+        """
+        return self._cache.schema_version_pickle(key)
 
     def set(self, key: str, value: Any, ttl_seconds: Optional[int] = None) -> None:
         """
@@ -56,7 +113,7 @@ class PickleCache:
         """
         try:
             pickled_data = pickle.dumps(value, protocol=pickle.HIGHEST_PROTOCOL)
-            self._cache.set_pickle(key, pickled_data, ttl_seconds)
+            self._cache.set_pickle(key, pickled_data, ttl_seconds, self._schema_version or 0)
         except Exception as e:
             raise RuntimeError(f"Failed to cache object: {e}") from e
 
@@ -71,14 +128,20 @@ class PickleCache:
         Returns:
             The cached object or default value
 
-        Raises:
-            PickleError: If the cached data cannot be unpickled
+        Values stored under an older schema version are upgraded, or
+        dropped and reported as missing when they cannot be.
         """
         try:
-            pickled_data = self._cache.get_pickle(key)
-            if pickled_data is not None:
+            found = self._cache.get_pickle_versioned(key)
+            if found is None:
+                return default
+            pickled_data, version = found
+            if self._schema_version is None or version == self._schema_version:
                 return pickle.loads(pickled_data)
-            return default
+            if version > self._schema_version:
+                # Written by a newer application; leave it to that one
+                return default
+            return self._upgrade(key, pickled_data, version, default)
         except Exception:
             # If unpickling fails, remove the corrupted entry
             try:
@@ -87,6 +150,20 @@ class PickleCache:
                 pass
             return default
 
+    def _upgrade(self, key: str, pickled_data: bytes, version: int, default: Any) -> Any:
+        """Run the upgrade chain from ``version``, dropping the entry if it is broken"""
+        if any(v not in self._upgrades for v in range(version, self._schema_version)):
+            self._cache.delete_pickle(key)
+            return default
+        # Raises for objects whose classes changed too much to unpickle; the
+        # caller drops those like any other unreadable entry
+        value = pickle.loads(pickled_data)
+        for v in range(version, self._schema_version):
+            value = self._upgrades[v](value)
+        upgraded = pickle.dumps(value, protocol=pickle.HIGHEST_PROTOCOL)
+        self._cache.upgrade_pickle(key, upgraded, self._schema_version)
+        return value
+
     def delete(self, key: str) -> bool:
         """
         Delete an entry from the cache.
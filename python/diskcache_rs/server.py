@@ -0,0 +1,60 @@
+"""
+Serve a cache directory to other processes over a Unix socket
+
+Run ``python -m diskcache_rs.server DIRECTORY`` to make this process the only
+one that opens DIRECTORY. Other processes get, set and delete through the
+socket, ``server.sock`` in DIRECTORY unless ``--socket`` says otherwise,
+instead of coordinating with each other through the filesystem; a Cache or
+FanoutCache opened with ``unix:///path/to/server.sock`` as its directory does
+so transparently.
+"""
+
+import argparse
+import signal
+import sys
+from typing import List, Optional
+
+from ._diskcache_rs import PyCache
+
+
+def main(argv: Optional[List[str]] = None) -> int:
+    parser = argparse.ArgumentParser(
+        prog="python -m diskcache_rs.server",
+        description="Serve a cache directory to other processes over a Unix socket",
+    )
+    parser.add_argument("directory", help="Cache directory to serve")
+    parser.add_argument(
+        "--socket",
+        help="Socket to listen on (default: server.sock in the cache directory)",
+    )
+    parser.add_argument(
+        "--max-size", type=int, help="Largest total size of stored values in bytes"
+    )
+    parser.add_argument("--max-entries", type=int, help="Most entries to keep")
+    args = parser.parse_args(argv)
+
+    if sys.platform == "win32":
+        parser.error("the cache server needs Unix sockets")
+
+    cache = PyCache(
+        args.directory, max_size=args.max_size, max_entries=args.max_entries
+    )
+
+    # Shut down cleanly under service managers, which stop with SIGTERM
+    def stop(signum, frame):
+        raise SystemExit(0)
+
+    signal.signal(signal.SIGTERM, stop)
+    try:
+        with cache.serve(args.socket) as server:
+            print(f"Serving {args.directory} on {server.socket}", flush=True)
+            server.serve_forever()
+    except KeyboardInterrupt:
+        pass
+    finally:
+        cache.close()
+    return 0
+
+
+if __name__ == "__main__":
+    sys.exit(main())
@@ -0,0 +1,234 @@
+//! Single-file archives of a cache's entries
+//!
+//! [`DiskCache::export`](crate::DiskCache::export) writes every live entry to
+//! one file and [`DiskCache::import_archive`](crate::DiskCache::import_archive)
+//! loads it into another cache, which may use a different backend or live on
+//! another machine. Keys and values are kept as the caller gave them, before
+//! any disk codec, so an archive does not depend on how its cache stored them.
+//!
+//! The file is a fixed header followed by one frame per entry and a closing
+//! frame holding the entry count. Each frame is its payload's length, the
+//! first four bytes of the payload's blake3 hash and the bincode payload, so
+//! a damaged or cut-off archive is refused rather than half imported.
+
+use crate::error::{CacheError, CacheResult};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const ARCHIVE_MAGIC: &[u8; 8] = b"DCRSARCV";
+const ARCHIVE_VERSION: u32 = 1;
+/// Payload length and the first four bytes of its blake3 hash
+const FRAME_HEADER_LEN: usize = 4 + 4;
+
+/// An entry as kept in an archive
+#[derive(Debug, Clone, PartialEq, bincode::Encode, bincode::Decode)]
+pub struct ArchiveEntry {
+    pub key: String,
+    pub value: Vec<u8>,
+    pub tags: Vec<String>,
+    pub expire_time: Option<u64>,
+}
+
+#[derive(bincode::Encode, bincode::Decode)]
+enum Frame {
+    Entry(ArchiveEntry),
+    End { entries: u64 },
+}
+
+/// Writes an archive under a temporary name, renamed into place by [`finish`](Self::finish)
+pub struct ArchiveWriter {
+    file: BufWriter<tempfile::NamedTempFile>,
+    entries: u64,
+}
+
+impl ArchiveWriter {
+    /// Start an archive that will replace `path` once finished
+    pub fn create(path: &Path) -> CacheResult<Self> {
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let mut file = BufWriter::new(tempfile::NamedTempFile::new_in(directory)?);
+        file.write_all(ARCHIVE_MAGIC)?;
+        file.write_all(&ARCHIVE_VERSION.to_le_bytes())?;
+        Ok(Self { file, entries: 0 })
+    }
+
+    pub fn append(&mut self, entry: ArchiveEntry) -> CacheResult<()> {
+        self.write_frame(&Frame::Entry(entry))?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Close the archive and move it to `path`, returning the entries written
+    pub fn finish(mut self, path: &Path) -> CacheResult<u64> {
+        self.write_frame(&Frame::End {
+            entries: self.entries,
+        })?;
+        let file = self
+            .file
+            .into_inner()
+            .map_err(|e| CacheError::Io(e.into_error()))?;
+        file.as_file().sync_all()?;
+        file.persist(path).map_err(|e| CacheError::Io(e.error))?;
+        Ok(self.entries)
+    }
+
+    fn write_frame(&mut self, frame: &Frame) -> CacheResult<()> {
+        let payload = bincode::encode_to_vec(frame, bincode::config::standard())
+            .map_err(|e| CacheError::Serialization(format!("archive entry: {}", e)))?;
+        let len = u32::try_from(payload.len()).map_err(|_| {
+            CacheError::Serialization(format!(
+                "archive entry of {} bytes is too large",
+                payload.len()
+            ))
+        })?;
+        self.file.write_all(&len.to_le_bytes())?;
+        self.file
+            .write_all(&blake3::hash(&payload).as_bytes()[..4])?;
+        self.file.write_all(&payload)?;
+        Ok(())
+    }
+}
+
+/// Reads the entries of an archive in the order they were written
+///
+/// Yields an error instead of stopping quietly when the archive is damaged
+/// or ends before its closing frame.
+pub struct ArchiveReader {
+    file: BufReader<File>,
+    entries: u64,
+    done: bool,
+}
+
+impl ArchiveReader {
+    pub fn open(path: &Path) -> CacheResult<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut header = [0; 12];
+        file.read_exact(&mut header)
+            .map_err(|_| not_an_archive(path))?;
+        if &header[..8] != ARCHIVE_MAGIC {
+            return Err(not_an_archive(path));
+        }
+        let version = u32::from_le_bytes(header[8..].try_into().unwrap());
+        if version != ARCHIVE_VERSION {
+            return Err(CacheError::Corruption(format!(
+                "Unsupported cache archive version {} in {:?}",
+                version, path
+            )));
+        }
+        Ok(Self {
+            file,
+            entries: 0,
+            done: false,
+        })
+    }
+
+    fn read_frame(&mut self) -> CacheResult<Frame> {
+        let mut header = [0; FRAME_HEADER_LEN];
+        self.file.read_exact(&mut header).map_err(truncated)?;
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let mut payload = vec![0; len];
+        self.file.read_exact(&mut payload).map_err(truncated)?;
+        if blake3::hash(&payload).as_bytes()[..4] != header[4..] {
+            return Err(CacheError::Corruption(format!(
+                "Cache archive entry {} fails its checksum",
+                self.entries
+            )));
+        }
+        bincode::decode_from_slice(&payload, bincode::config::standard())
+            .map(|(frame, _)| frame)
+            .map_err(|e| CacheError::Deserialization(format!("archive entry: {}", e)))
+    }
+}
+
+impl Iterator for ArchiveReader {
+    type Item = CacheResult<ArchiveEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let frame = self.read_frame();
+        match frame {
+            Ok(Frame::Entry(entry)) => {
+                self.entries += 1;
+                Some(Ok(entry))
+            }
+            Ok(Frame::End { entries }) => {
+                self.done = true;
+                (entries != self.entries).then(|| {
+                    Err(CacheError::Corruption(format!(
+                        "Cache archive lists {} entries but holds {}",
+                        entries, self.entries
+                    )))
+                })
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+fn not_an_archive(path: &Path) -> CacheError {
+    CacheError::Corruption(format!("{:?} is not a cache archive", path))
+}
+
+fn truncated(error: std::io::Error) -> CacheError {
+    if error.kind() == std::io::ErrorKind::UnexpectedEof {
+        CacheError::Corruption("Cache archive ends before its last entry".to_string())
+    } else {
+        CacheError::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: &str, expire_time: Option<u64>) -> ArchiveEntry {
+        ArchiveEntry {
+            key: key.to_string(),
+            value: key.repeat(100).into_bytes(),
+            tags: vec!["tag".to_string()],
+            expire_time,
+        }
+    }
+
+    #[test]
+    fn archives_round_trip_and_refuse_damage() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("cache.archive");
+        let entries = vec![entry("a", None), entry("b", Some(1_900_000_000))];
+
+        let mut writer = ArchiveWriter::create(&path).unwrap();
+        for entry in entries.clone() {
+            writer.append(entry).unwrap();
+        }
+        assert_eq!(writer.finish(&path).unwrap(), 2);
+
+        let read: Vec<_> = ArchiveReader::open(&path)
+            .unwrap()
+            .collect::<CacheResult<_>>()
+            .unwrap();
+        assert_eq!(read, entries);
+
+        // Cut off before the closing frame
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() - 3]).unwrap();
+        let read: CacheResult<Vec<_>> = ArchiveReader::open(&path).unwrap().collect();
+        assert!(matches!(read, Err(CacheError::Corruption(_))));
+
+        let mut flipped = bytes.clone();
+        flipped[20] ^= 1;
+        std::fs::write(&path, &flipped).unwrap();
+        let read: CacheResult<Vec<_>> = ArchiveReader::open(&path).unwrap().collect();
+        assert!(matches!(read, Err(CacheError::Corruption(_))));
+
+        std::fs::write(&path, b"not an archive").unwrap();
+        assert!(ArchiveReader::open(&path).is_err());
+    }
+}
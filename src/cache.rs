@@ -1,25 +1,183 @@
-use crate::error::CacheResult;
-use crate::eviction::{CombinedEviction, EvictionPolicy, EvictionStrategy};
+use crate::archive::{ArchiveEntry, ArchiveReader, ArchiveWriter};
+use crate::cache_meta;
+use crate::changes::{
+    ChangeFeed, ChangeKind, Notifier, Origin, Subscription, TagLookup, Topic, FEED_POLL, LOCAL,
+};
+use crate::entry_lock::{EntryLock, EntryLocks};
+use crate::error::{CacheError, CacheResult, ConfigIssue};
+use crate::eviction::{
+    load_checkpoint, save_checkpoint, CombinedEviction, EvictionPolicy, EvictionStrategy,
+    PriorityEviction, TrackedKey,
+};
+use crate::eviction_queue::{Evict, EvictionBacklog, EvictionQueue};
+use crate::fork::ForkCheck;
+use crate::lock_order::{LockLevel, OrderedMutex, OrderedRwLock};
 use crate::memory_cache::MemoryCache;
-use crate::migration::{detect_diskcache_format, DiskCacheMigrator};
-use crate::serialization::{CacheEntry, OptimizedSerializer};
-use crate::storage::{OptimizedStorage, StorageBackend};
-use crate::utils::{current_timestamp, validate_cache_config, validate_key, CacheStats};
-use parking_lot::RwLock;
+use crate::migration::{
+    detect_diskcache_format, finish_backend_switch, mark_backend_staged, DiskCacheMigrator,
+    LegacyMigration, BACKEND_STAGING_DIR,
+};
+use crate::popularity::KeyPopularity;
+use crate::process_lock::{LockBackend, LockGranularity};
+use crate::registry::{self, ProcessInfo, Registration};
+use crate::serialization::{
+    CacheEntry, Disk, EncryptedDisk, EncryptionKey, JsonDisk, OptimizedSerializer, Priority,
+    RawDisk,
+};
+#[cfg(unix)]
+use crate::server::{CacheServer, ServerStorage};
+use crate::shared_stats::{AggregateStats, StatCounts, StatsShard};
+use crate::single_flight::SingleFlight;
+use crate::storage::lease::{MaintenanceLease, WriterLease};
+use crate::storage::remote::{open_remote_tier, RemoteTier};
+#[cfg(unix)]
+use crate::storage::writer::DesignatedWriter;
+use crate::storage::{
+    Compression, DataFileWriter, Durability, FileNaming, FramedReader, HotCachePolicy, IndexKey,
+    IoStats, MemoryStorage, OptimizedStorage, QueueFullPolicy, RecoveryReport, RedbStorage,
+    RingStorage, SharingRetry, SnapshotReport, SqliteStorage, StorageBackend, StorageKind,
+    UnlinkPool, UnlinkProgress, VacuumReport, WalSyncPolicy,
+};
+use crate::tag_quota::{TagExcess, TagLimit, TagQuotas};
+use crate::trash::{Trash, TrashedEntry};
+use crate::utils::{current_timestamp, validate_cache_config, validate_key, CacheStats, SetupLock};
+use parking_lot::{MappedRwLockReadGuard, Mutex, RwLock, RwLockReadGuard};
 use pyo3::prelude::*;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
-// Simplified: Only one storage backend option
-// No need for enum - always use OptimizedStorage
+/// Key, expiration time and tags of an entry loaded from an archive
+type ImportedMetadata = (String, Option<u64>, Vec<String>);
 
 /// Simplified cache configuration
 ///
 /// # Fields
 /// * `disk_write_threshold` - Size threshold in bytes for writing to disk (vs inline SQLite). Default: 32KB
-/// * `use_file_locking` - Enable file locking for NFS scenarios. Default: false
+/// * `pack_threshold` - Values from `disk_write_threshold` up to this size share append-only
+///   segment files instead of getting a file each; 0 disables packing. Default: 0
+/// * `compaction_ratio` - Share of segment bytes left dead by overwrites and deletes at which
+///   a background compaction rewrites the live values. Default: 0.5
+/// * `data_fanout` - Directory levels data files are spread over under `data/`, each named
+///   after two hex digits of the key hash in the file name; 0 keeps one flat directory.
+///   Existing caches are moved to the configured layout on open. Default: 2
+/// * `file_naming` - How data files are named after their keys: the full BLAKE3 hash, an
+///   XXH3 hash, or a readable slug of the key followed by a hash. Files already written keep
+///   their names. Optimized backend only. Default: `FileNaming::Blake3`
+/// * `compression` - Codec values of at least 32KB are compressed with when that saves
+///   space; each value records its codec, so switching codecs keeps older values readable.
+///   Optimized backend only. Default: `Compression::Lz4`
+/// * `compression_level` - Zstd level, higher for smaller files at more CPU; other codecs
+///   have no levels. Default: None (zstd's default level, 3)
+/// * `zstd_dictionary` - Compress values below `dictionary_threshold` with a zstd dictionary
+///   trained on the first 1000 of them and kept under `dictionaries/`, for many small,
+///   similar values that barely compress alone. Optimized backend only. Default: false
+/// * `dictionary_threshold` - Size in bytes below which values use the dictionary. Default: 8KB
+/// * `seekable_compression` - Compress values in data files as 256KB frames of LZ4 or zstd,
+///   so ranges and streamed reads decompress only the frames they touch. Default: false
+/// * `unlink_workers` - Threads removing data files when clearing. Default: 8
+/// * `unlink_rate` - Most data files removed per second when clearing, to spare a shared
+///   file server; 0 is unlimited. Default: 0
+/// * `use_file_locking` - Enable locking that holds up on NFS: file locks
+///   give way to lock files with a heartbeat, broken once their holder stops
+///   renewing them, and readers hold their key's lock shared while reading its
+///   data file. Default: false
+/// * `auto_recover` - Run `verify_and_recover()` on open after an unclean shutdown. Default: false
+/// * `sync_writes` - Write data files synchronously instead of through the batcher. Default: false
+/// * `durability` - How far writes get before they return: buffered, flushed to the OS,
+///   fsynced, or fsynced along with their directory. Default: `Durability::Flush`
+/// * `group_commit` - How long fsynced data file writes wait for others to share one pass of
+///   syncs with; needs a durability of `Fsync` or above, or file locking. Optimized backend
+///   only. Default: None (each write syncs on its own)
+/// * `direct_io_threshold` - Data files at least this many bytes are written with O_DIRECT on
+///   Linux, so large values do not evict other processes' pages from the page cache; other
+///   platforms and filesystems without O_DIRECT write them normally. Optimized backend only.
+///   Default: None (every write goes through the page cache)
+/// * `batch_size` - Number of queued writes flushed together. Default: 100
+/// * `write_queue_capacity` - Most data file writes queued for the background writer; a
+///   burst of writes beyond it is held back by `queue_full`. Optimized backend only. Default: 1024
+/// * `queue_full` - What a write does when the queue is full: wait for room, write the file
+///   on the calling thread, or fail with [`CacheError::QueueFull`]. Default: `QueueFullPolicy::Block`
+/// * `use_mmap` - Allow memory-mapping large data files. Default: true
+/// * `warm_cache_size` - Most data files kept memory-mapped between reads; the least recently
+///   read are unmapped first. Optimized backend only. Default: 1,000
+/// * `warm_cache_bytes` - Most bytes of data files kept memory-mapped between reads.
+///   Optimized backend only. Default: 1GB
+/// * `hot_cache_policy` - Which values held in memory go once there are more than 10,000:
+///   the least recently used, or those a CLOCK sweep finds unused since its last pass,
+///   which makes hits cheaper under many threads. Optimized backend only.
+///   Default: `HotCachePolicy::Lru`
+/// * `watch_directory` - Watch `data/` for files other processes rewrite or remove and drop
+///   what the memory tiers hold for them right away, instead of on the next read of their
+///   keys. Optimized backend only. Default: false
+/// * `event_log` - Record every set, delete and clear in `events.log`, and read what other
+///   processes recorded before serving values held in memory, dropping those they changed.
+///   Unlike `watch_directory` it covers inline values and works on network filesystems, and
+///   it carries other processes' changes to [`DiskCache::subscribe`]. Optimized backend
+///   only. Default: false
+/// * `lock_backend` - What locks shared with the other processes using the directory are made
+///   of: lock files, or Windows named mutexes for SMB shares with unreliable file locks, which
+///   only exclude processes on the same machine, or files created exclusively with a heartbeat
+///   inside, for NFS. See [`LockBackend`]. Default: `LockBackend::File`
+/// * `lock_granularity` - How many keys share a key lock: rarely any, one of 256 locks, or
+///   the whole cache. Every process using the directory must agree on it. Optimized backend
+///   only. See [`LockGranularity`]. Default: `LockGranularity::Shard`
+/// * `smb_mode` - Share the directory over SMB without byte-range locks: the locks other
+///   processes share are lock files, keys are not locked at all, data files are published by
+///   renaming a uniquely named temporary file into place, and none is kept open or mapped
+///   between reads. A data file not matching its row, as racing writers can leave, reads as
+///   a miss. Optimized backend only, without packing. Default: false
+/// * `sharing_retry` - How often and how patiently file operations failing with a Windows
+///   sharing violation are retried. See [`SharingRetry`]. Default: 5 attempts, 10ms apart
+///   at first and doubling up to 1s
+/// * `eviction_samples` - Pick least-recently-used or least-frequently-used victims among
+///   this many randomly sampled keys each, as Redis does, keeping a timestamp and hit count
+///   per key instead of an exact order; approximate, but cheaper in memory for very many
+///   keys. Default: 0 (exact order)
+/// * `max_idle` - Expire entries not read or written for this long, even before their own
+///   expiry. Reads through this handle count, reads in other processes do not. Default: None
+/// * `cull_limit` - Most entries evicted per write when over `max_entries`; a write over `max_size` evicts as many as it takes. 0 disables automatic eviction. Default: 10
+/// * `inline_evictions` - Most of its victims a write evicts before returning; the rest are
+///   evicted by a background thread, so writes over a limit do not stall on deleting files.
+///   Default: None (writes evict all their victims themselves)
+/// * `statistics` - Count hits and misses from the start. Default: false
+/// * `wal` - Log writes ahead of applying them and replay the log on open. Default: None (disabled)
+/// * `soft_delete` - Keep deleted entries in a `trash/` directory this long, so `undelete()`
+///   can bring back entries removed by mistake; evictions and queue pulls skip the trash.
+///   Default: None (deletes are final)
+/// * `designated_writer` - Let one of the processes sharing the directory apply the writes of
+///   all of them, forwarded over a Unix socket, so batching and group commit span processes;
+///   another process takes over when the writer exits. Unix only. Default: false
+/// * `writer_lease` - Let only the process holding the directory's writer lease write, renewing
+///   it this often over; the others open read-only and their writes fail with
+///   [`CacheError::ReadOnly`] until the holder closes or stops renewing. Default: None (every
+///   process writes)
+/// * `maintenance_lease` - Let only the process holding the directory's maintenance lease run
+///   the automatic vacuum, expiry sweep and segment compaction, so processes sharing the
+///   directory do not all do them at once. The holder renews the lease each time its
+///   maintenance comes due, every half period at most hourly; once it stops, the next process
+///   whose maintenance comes due takes over. Default: None (every process maintains)
+/// * `index_key` - Secret the index rows are signed with, so a row pointing a key at bytes
+///   the cache did not write is refused instead of served. Optimized backend only. Default: None
+/// * `encryption_key` - Key values are encrypted with, using AES-256-GCM, before they reach
+///   the backend; keys stay readable. The cache records which key it was created with and
+///   refuses to open with another or none. Default: None (values are stored as given)
+/// * `backend` - Storage layout; `StorageKind::Sqlite` shares python-diskcache's `cache.db`,
+///   `StorageKind::Redb` keeps every entry in one embedded database file, `StorageKind::Memory`
+///   keeps entries in process memory and ignores `directory`, `StorageKind::Ring` writes every
+///   entry into one preallocated file and overwrites the oldest once it is full,
+///   `StorageKind::Server` goes through the [`CacheServer`](crate::server::CacheServer)
+///   listening on the socket `directory` names. Default: Optimized
+/// * `ring_capacity_bytes` - Size of the ring file's record area, fixed when the ring is
+///   created; required by the ring backend and rejected by the others. Default: None
+/// * `remote_tier` - Object store that evicted entries are pushed to and that reads missing
+///   locally fall through to; see [`crate::storage::remote`]. Optimized backend only.
+///   Default: None (evicted entries are dropped)
 #[derive(Debug, Clone)]
 pub struct CacheConfig {
     pub directory: PathBuf,
@@ -27,7 +185,51 @@ pub struct CacheConfig {
     pub max_entries: Option<u64>,
     pub eviction_strategy: EvictionStrategy,
     pub disk_write_threshold: usize, // Size threshold for writing to disk (vs inline SQLite)
+    pub pack_threshold: usize,       // Values below this are packed into segment files
+    pub compaction_ratio: f64,       // Dead share of segment bytes that starts compaction
+    pub data_fanout: usize,          // Hash-prefix directory levels under data/
+    pub file_naming: FileNaming,     // Hash or slug names for data files
+    pub compression: Compression,    // Codec large values are compressed with
+    pub compression_level: Option<i32>, // Zstd level; None uses its default
+    pub zstd_dictionary: bool,       // Compress small values with a trained dictionary
+    pub dictionary_threshold: usize, // Values below this use the dictionary
+    pub seekable_compression: bool,  // Compress data files in independently readable frames
+    pub unlink_workers: usize,       // Threads removing data files on clear
+    pub unlink_rate: u64,            // Data files removed per second on clear; 0 is unlimited
     pub use_file_locking: bool,      // Enable file locking for NFS scenarios
+    pub auto_recover: bool,          // Verify the index on open after an unclean shutdown
+    pub sync_writes: bool,           // Bypass the write batcher for data files
+    pub durability: Durability,      // Buffered, flushed or fsynced writes
+    pub group_commit: Option<Duration>, // Window fsynced writes are gathered over
+    pub direct_io_threshold: Option<usize>, // Data files this large skip the page cache
+    pub designated_writer: bool,     // Forward writes to the process holding writer.lock
+    pub writer_lease: Option<Duration>, // Lease period of the single writer; None lets all write
+    pub maintenance_lease: Option<Duration>, // Lease period of the maintenance leader
+    pub batch_size: usize,           // Writes flushed per batch
+    pub write_queue_capacity: usize, // Writes queued for the background writer at most
+    pub queue_full: QueueFullPolicy, // Block, spill or fail when the queue is full
+    pub use_mmap: bool,              // Memory-map large data files
+    pub warm_cache_size: usize,      // Data files kept mapped at most
+    pub warm_cache_bytes: u64,       // Bytes of data files kept mapped at most
+    pub hot_cache_policy: HotCachePolicy, // Which values held in memory go first
+    pub watch_directory: bool,       // Drop memory tiers as other processes change data files
+    pub event_log: bool,             // Share changes with other processes through events.log
+    pub lock_backend: LockBackend,   // Lock files or named mutexes between processes
+    pub lock_granularity: LockGranularity, // How many keys share a key lock
+    pub smb_mode: bool,              // Rename-only publication without byte-range or key locks
+    pub sharing_retry: SharingRetry, // Retries of file operations hitting sharing violations
+    pub cull_limit: usize,           // Entries evicted per write at most
+    pub inline_evictions: Option<usize>, // Victims evicted inside a write; None evicts all
+    pub eviction_samples: usize,     // Keys sampled per victim; 0 keeps the exact order
+    pub max_idle: Option<Duration>,  // Expire entries unused this long; None never does
+    pub statistics: bool,            // Count hits and misses
+    pub wal: Option<WalSyncPolicy>,  // Write-ahead log sync policy; None disables the log
+    pub backend: StorageKind,        // Which storage backend to open
+    pub index_key: Option<IndexKey>, // Sign index rows; None leaves them unsigned
+    pub encryption_key: Option<EncryptionKey>, // Encrypt values at rest; None stores them as given
+    pub soft_delete: Option<Duration>, // How long deleted entries stay in the trash
+    pub ring_capacity_bytes: Option<u64>, // Record area of the ring backend's file
+    pub remote_tier: Option<Arc<dyn RemoteTier>>, // Where evicted entries go instead of away
 }
 
 impl Default for CacheConfig {
@@ -38,57 +240,600 @@ impl Default for CacheConfig {
             max_entries: Some(100_000),
             eviction_strategy: EvictionStrategy::LeastRecentlyStored,
             disk_write_threshold: 32 * 1024, // 32KB - data smaller than this stays inline in SQLite
-            use_file_locking: false,         // Disabled by default for performance
+            pack_threshold: 0,               // Packing disabled
+            compaction_ratio: 0.5,
+            data_fanout: 2,
+            file_naming: FileNaming::Blake3,
+            compression: Compression::Lz4,
+            compression_level: None,
+            zstd_dictionary: false,
+            dictionary_threshold: 8 * 1024,
+            seekable_compression: false,
+            unlink_workers: 8,
+            unlink_rate: 0,
+            use_file_locking: false, // Disabled by default for performance
+            auto_recover: false,
+            sync_writes: false,
+            durability: Durability::Flush,
+            group_commit: None,
+            direct_io_threshold: None,
+            designated_writer: false,
+            writer_lease: None,
+            maintenance_lease: None,
+            batch_size: 100,
+            write_queue_capacity: 1024,
+            queue_full: QueueFullPolicy::Block,
+            use_mmap: true,
+            warm_cache_size: 1_000,
+            warm_cache_bytes: 1 << 30, // 1GB
+            hot_cache_policy: HotCachePolicy::Lru,
+            watch_directory: false,
+            event_log: false,
+            lock_backend: LockBackend::File,
+            lock_granularity: LockGranularity::Shard,
+            smb_mode: false,
+            sharing_retry: SharingRetry::default(),
+            cull_limit: 10,
+            inline_evictions: None,
+            eviction_samples: 0,
+            max_idle: None,
+            statistics: false,
+            wal: None,
+            backend: StorageKind::Optimized,
+            index_key: None,
+            encryption_key: None,
+            soft_delete: None,
+            ring_capacity_bytes: None,
+            remote_tier: None,
         }
     }
 }
 
+impl CacheConfig {
+    /// Codecs values written under this config may be stored with
+    fn codecs(&self) -> Vec<Compression> {
+        let mut codecs = vec![self.compression];
+        if self.zstd_dictionary {
+            codecs.push(Compression::ZstdDictionary);
+        }
+        if let Some(framed) = self
+            .compression
+            .framed()
+            .filter(|_| self.seekable_compression)
+        {
+            codecs.push(framed);
+        }
+        codecs
+    }
+}
+
 /// High-performance disk cache implementation
 pub struct DiskCache {
     config: CacheConfig,
-    storage: Box<dyn StorageBackend>,
-    eviction: Box<dyn EvictionPolicy>,
+    /// Replaced by [`DiskCache::migrate_backend`]; reach it through `storage()`
+    storage: Arc<RwLock<ActiveStorage>>,
+    /// Replaced by [`DiskCache::set_eviction_strategy`]; reach it through `eviction()`
+    eviction: Arc<RwLock<ActiveEviction>>,
     #[allow(dead_code)]
     serializer: OptimizedSerializer,
-    stats: Arc<RwLock<CacheStats>>,
-    last_vacuum: Arc<RwLock<u64>>,
+    stats: Arc<OrderedRwLock<CacheStats>>,
+    statistics: AtomicBool,
+    last_vacuum: Arc<OrderedRwLock<u64>>,
     memory_cache: Option<MemoryCache>,
+    disk: Arc<dyn Disk>,
+    last_recovery: OrderedRwLock<Option<RecoveryReport>>,
+    queue_lock: OrderedMutex<()>,
+    loads: SingleFlight<Vec<u8>>,
+    legacy: Option<Arc<LegacyMigration>>,
+    trash: Option<Trash>,
+    popularity: Mutex<KeyPopularity>,
+    entry_locks: Arc<EntryLocks>,
+    #[cfg(unix)]
+    writer: Option<Arc<DesignatedWriter>>,
+    lease: Option<Arc<WriterLease>>,
+    /// Held by the one process sharing the directory that runs maintenance
+    maintenance: Option<Arc<MaintenanceLease>>,
+    /// This process's entry in the directory's registry, dropped by `close()`
+    registration: Mutex<Option<Registration>>,
+    /// This process's statistics shard, folded into the directory's by `close()`
+    stats_shard: Mutex<Option<Arc<StatsShard>>>,
+    /// Notices being used in a forked child; see [`crate::fork`]
+    fork: ForkCheck,
+    /// Subscribers to changes of this cache's keys
+    notifier: Arc<Notifier>,
+    /// Keys this handle never evicts, as stored
+    pins: Mutex<HashSet<String>>,
+    /// Limits set on tags and what each limited tag holds
+    tag_quotas: Arc<TagQuotas>,
+    /// Victims of writes past `inline_evictions`, evicted in the background
+    eviction_queue: Arc<EvictionQueue>,
+}
+
+/// The policy a cache currently evicts by
+struct ActiveEviction {
+    strategy: EvictionStrategy,
+    policy: Box<dyn EvictionPolicy>,
+}
+
+/// The backend a cache currently stores its entries in
+struct ActiveStorage {
+    kind: StorageKind,
+    backend: Arc<dyn StorageBackend>,
+}
+
+/// Snapshot of cache state reported by [`DiskCache::info`]
+#[derive(Debug, Clone)]
+pub struct CacheInfo {
+    pub directory: PathBuf,
+    /// `None` when the directory cannot be locked to tell
+    pub was_unclean_shutdown: Option<bool>,
+    pub last_recovery: Option<RecoveryReport>,
+}
+
+/// Sizing projection returned by [`DiskCache::estimate`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapacityEstimate {
+    /// Live entries once writes and expirations balance out
+    pub entries: u64,
+    pub disk_bytes: u64,
+    pub memory_bytes: u64,
+    /// Suggested `max_entries`, with headroom for bursts
+    pub recommended_max_entries: u64,
+    /// Suggested `max_size`; this limit counts stored value bytes only
+    pub recommended_max_size: u64,
+    /// Whether the projection stays within the configured limits
+    pub fits_current_limits: bool,
+}
+
+/// End of a queue used by [`DiskCache::push`] and [`DiskCache::pull`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueSide {
+    Front,
+    Back,
+}
+
+impl std::str::FromStr for QueueSide {
+    type Err = CacheError;
+
+    fn from_str(side: &str) -> Result<Self, Self::Err> {
+        match side {
+            "front" => Ok(QueueSide::Front),
+            "back" => Ok(QueueSide::Back),
+            other => Err(CacheError::InvalidConfig(format!(
+                "side must be 'front' or 'back', got {:?}",
+                other
+            ))),
+        }
+    }
+}
+
+/// An item read from a queue
+#[derive(Debug, Clone)]
+pub struct QueueItem {
+    pub position: i64,
+    pub key: String,
+    pub value: Vec<u8>,
+}
+
+/// Value being streamed into the cache, returned by [`DiskCache::write_stream`]
+///
+/// The key keeps its previous value until [`ValueWriter::commit`]; dropping
+/// the writer instead discards everything written to it.
+pub struct ValueWriter<'a> {
+    cache: &'a DiskCache,
+    key: String,
+    file: DataFileWriter,
+}
+
+impl ValueWriter<'_> {
+    /// Store the value written so far under the key, returning its size in bytes
+    pub fn commit(self) -> CacheResult<u64> {
+        self.cache.commit_stream(&self.key, self.file)
+    }
+}
+
+impl Write for ValueWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Keys fetched per index query while iterating
+const KEY_PAGE_SIZE: usize = 256;
+
+/// Seconds between automatic vacuums and expiry sweeps
+const MAINTENANCE_INTERVAL: u64 = 3600;
+
+/// Idle entries expired per write at most; maintenance expires the rest
+const IDLE_SWEEP_BATCH: usize = 16;
+
+/// Seconds a stale value passes for fresh while one caller of
+/// [`DiskCache::get_or_set`] loads its successor
+const REFRESH_WINDOW: u64 = 30;
+
+/// Key length assumed by [`DiskCache::estimate`]
+const ESTIMATE_KEY_SIZE: usize = 32;
+/// Days of writes projected when entries never expire
+const ESTIMATE_HORIZON_DAYS: u64 = 30;
+/// Eviction bookkeeping per key besides two copies of the key itself
+const EVICTION_ENTRY_OVERHEAD: u64 = 96;
+
+/// Queue keys start mid-range so items can be pushed to either end
+const QUEUE_START: i64 = 500_000_000_000_000;
+const QUEUE_KEY_DIGITS: usize = 15;
+
+/// Stored keys a queue scan has yet to look at, start inclusive and end exclusive
+struct QueueRange {
+    start: String,
+    end: String,
+}
+
+fn queue_key(prefix: &str, position: i64) -> CacheResult<String> {
+    let offset = QUEUE_START + position;
+    if !(0..10_i64.pow(QUEUE_KEY_DIGITS as u32)).contains(&offset) {
+        return Err(CacheError::CacheFull);
+    }
+    Ok(format!(
+        "{}-{:0width$}",
+        prefix,
+        offset,
+        width = QUEUE_KEY_DIGITS
+    ))
+}
+
+/// Open the `kind` backend in `directory`, tuned by `config`
+fn open_storage(
+    config: &CacheConfig,
+    kind: StorageKind,
+    directory: &Path,
+) -> CacheResult<Arc<dyn StorageBackend>> {
+    let mut storage_config = crate::storage::optimized_backend::StorageConfig {
+        disk_write_threshold: config.disk_write_threshold,
+        pack_threshold: config.pack_threshold,
+        compaction_ratio: config.compaction_ratio,
+        data_fanout: config.data_fanout,
+        file_naming: config.file_naming,
+        compression: config.compression,
+        zstd_dictionary: config.zstd_dictionary,
+        dictionary_threshold: config.dictionary_threshold,
+        seekable_compression: config.seekable_compression,
+        unlink_workers: config.unlink_workers,
+        unlink_rate: config.unlink_rate,
+        use_file_locking: config.use_file_locking,
+        sync_writes: config.sync_writes,
+        durability: config.durability,
+        group_commit: config.group_commit,
+        direct_io_threshold: config.direct_io_threshold,
+        batch_size: config.batch_size,
+        write_queue_capacity: config.write_queue_capacity,
+        queue_full: config.queue_full,
+        hot_cache_policy: config.hot_cache_policy,
+        warm_cache_size: config.warm_cache_size,
+        warm_cache_bytes: config.warm_cache_bytes,
+        wal: config.wal,
+        index_key: config.index_key.clone(),
+        remote_tier: config.remote_tier.clone(),
+        watch_directory: config.watch_directory,
+        event_log: config.event_log,
+        lock_backend: config.lock_backend,
+        lock_granularity: config.lock_granularity,
+        smb_mode: config.smb_mode,
+        sharing_retry: config.sharing_retry,
+        ..Default::default()
+    };
+    if !config.use_mmap {
+        storage_config.mmap_threshold = 0;
+    }
+    if let Some(level) = config.compression_level {
+        storage_config.compression_level = level;
+    }
+
+    Ok(match kind {
+        StorageKind::Optimized => {
+            Arc::new(OptimizedStorage::with_config(directory, storage_config)?)
+        }
+        StorageKind::Sqlite => Arc::new(
+            SqliteStorage::with_min_file_size(directory, config.disk_write_threshold)?
+                .with_unlink_pool(UnlinkPool::new(config.unlink_workers, config.unlink_rate))
+                .with_durability(config.durability)?,
+        ),
+        StorageKind::Redb => Arc::new(RedbStorage::with_config(
+            directory,
+            config.sync_writes || config.durability >= Durability::Fsync,
+            config.batch_size,
+        )?),
+        StorageKind::Memory => Arc::new(MemoryStorage::new()),
+        StorageKind::Ring => Arc::new(RingStorage::with_config(
+            directory,
+            config.ring_capacity_bytes.unwrap_or_default(),
+            config.sync_writes || config.durability >= Durability::Fsync,
+        )?),
+        #[cfg(unix)]
+        StorageKind::Server => Arc::new(ServerStorage::connect(directory)?),
+        #[cfg(not(unix))]
+        StorageKind::Server => {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "backend",
+                "The cache server listens on a Unix socket",
+                "Open the cache directory directly on this platform",
+            )))
+        }
+    })
+}
+
+/// Release what `storage` holds open, such as redb's file lock
+fn close_storage(storage: &dyn StorageBackend) {
+    let storage = storage.as_any();
+    if let Some(optimized_storage) =
+        storage.downcast_ref::<crate::storage::optimized_backend::OptimizedStorage>()
+    {
+        optimized_storage.close_db();
+    } else if let Some(redb_storage) = storage.downcast_ref::<RedbStorage>() {
+        redb_storage.close_db();
+    } else if let Some(ring_storage) = storage.downcast_ref::<RingStorage>() {
+        ring_storage.close_db();
+    }
+}
+
+/// What other processes change in `storage`, for the backends that know
+fn change_feed(storage: &dyn StorageBackend) -> CacheResult<Option<Box<dyn ChangeFeed>>> {
+    let storage = storage.as_any();
+    if let Some(optimized_storage) =
+        storage.downcast_ref::<crate::storage::optimized_backend::OptimizedStorage>()
+    {
+        let log = optimized_storage.follow_events()?;
+        return Ok(log.map(|log| Box::new(log) as Box<dyn ChangeFeed>));
+    }
+    #[cfg(unix)]
+    if let Some(server_storage) = storage.downcast_ref::<ServerStorage>() {
+        return server_storage.change_feed().map(Some);
+    }
+    Ok(None)
+}
+
+/// Copy every live entry of `from` into `to` in store order, with its tags
+/// and expiration time, returning the number copied
+fn copy_entries(from: &dyn StorageBackend, to: &dyn StorageBackend) -> CacheResult<u64> {
+    let now = current_timestamp();
+    let mut copied = 0;
+    let mut cursor = None;
+    loop {
+        let page = from.key_page(cursor, false, KEY_PAGE_SIZE)?;
+        for (_, key) in &page {
+            let Some(entry) = from.get(key)? else {
+                continue;
+            };
+            if entry
+                .expire_time
+                .is_some_and(|expire_time| expire_time <= now)
+            {
+                continue;
+            }
+            let data = match &entry.storage {
+                crate::serialization::StorageMode::Inline(data) => data.clone(),
+                crate::serialization::StorageMode::File(filename) => {
+                    from.read_data_file(filename)?
+                }
+            };
+            let copy = CacheEntry::new_inline(key.clone(), data, entry.tags, entry.expire_time);
+            to.set(key, copy)?;
+            copied += 1;
+        }
+        match page.last() {
+            Some((seq, _)) if page.len() == KEY_PAGE_SIZE => cursor = Some(*seq),
+            _ => return Ok(copied),
+        }
+    }
 }
 
 impl DiskCache {
+    /// The backend entries are stored in
+    ///
+    /// The guard keeps [`DiskCache::migrate_backend`] from switching backends
+    /// under a call in progress, so it should not be held longer than one.
+    pub(crate) fn storage(&self) -> MappedRwLockReadGuard<'_, Arc<dyn StorageBackend>> {
+        let storage = RwLockReadGuard::map(self.storage.read_recursive(), |active| &active.backend);
+        if self.fork.forked() {
+            self.after_fork(storage.as_ref());
+        }
+        storage
+    }
+
+    /// The eviction policy currently in use
+    fn eviction(&self) -> MappedRwLockReadGuard<'_, dyn EvictionPolicy> {
+        RwLockReadGuard::map(self.eviction.read_recursive(), |active| {
+            active.policy.as_ref()
+        })
+    }
+
+    /// The strategy eviction currently picks victims by
+    pub fn eviction_strategy(&self) -> EvictionStrategy {
+        self.eviction.read_recursive().strategy
+    }
+
+    /// Take over in a forked child before it first uses the cache: restart
+    /// the storage's threads and register the child in the directory
+    fn after_fork(&self, storage: &dyn StorageBackend) {
+        storage.after_fork();
+        let mut registration = self.registration.lock();
+        // Leaves the parent's entry in place
+        if registration.take().is_some() {
+            match Registration::register(&self.config.directory) {
+                Ok(own) => *registration = Some(own),
+                Err(e) => tracing::warn!("Failed to register after fork: {}", e),
+            }
+        }
+        drop(registration);
+        // The parent's shard holds what it counted before the fork
+        let mut shard = self.stats_shard.lock();
+        if shard.take().is_some() {
+            let lock_backend = self
+                .config
+                .lock_backend
+                .nfs_safe(self.config.use_file_locking || self.config.smb_mode);
+            let base = StatCounts::new(&self.stats.read(), storage.io_stats());
+            match self.open_stats_shard(lock_backend, base) {
+                Ok(own) => *shard = Some(own),
+                Err(e) => tracing::warn!("Failed to open a statistics shard after fork: {}", e),
+            }
+        }
+    }
+
+    /// Start writing this cache's counters since `base` to a shard of the
+    /// directory's statistics; the shard stops once the cache is dropped
+    fn open_stats_shard(
+        &self,
+        lock_backend: LockBackend,
+        base: StatCounts,
+    ) -> CacheResult<Arc<StatsShard>> {
+        let stats = Arc::downgrade(&self.stats);
+        let storage = Arc::downgrade(&self.storage);
+        StatsShard::open(
+            &self.config.directory,
+            lock_backend,
+            Box::new(move || {
+                let io = storage.upgrade()?.read_recursive().backend.io_stats();
+                Some(StatCounts::new(&stats.upgrade()?.read(), io))
+            }),
+            base,
+        )
+    }
+
+    /// Have `storage` tell subscribers about the entries it finds expired,
+    /// keep the pinned ones in memory, and leave background maintenance to
+    /// the maintenance leader
+    fn hook_storage(&self, storage: &dyn StorageBackend) {
+        for key in self.pins.lock().iter() {
+            storage.set_pinned(key, true);
+        }
+        let notifier = Arc::downgrade(&self.notifier);
+        let tag_quotas = Arc::downgrade(&self.tag_quotas);
+        storage.watch_expirations(Arc::new(move |key| {
+            if let Some(tag_quotas) = tag_quotas.upgrade() {
+                tag_quotas.on_remove(key);
+            }
+            if let Some(notifier) = notifier.upgrade() {
+                notifier.publish(LOCAL, ChangeKind::Expire, key, &[]);
+            }
+        }));
+        if let Some(maintenance) = &self.maintenance {
+            let maintenance = Arc::downgrade(maintenance);
+            storage.gate_maintenance(Arc::new(move || {
+                maintenance
+                    .upgrade()
+                    .is_none_or(|maintenance| maintenance.is_held())
+            }));
+        }
+    }
+
+    /// Tell subscribers in this process that it changed the stored `key`
+    fn notify(&self, kind: ChangeKind, key: &str, tags: &[String]) {
+        self.publish(LOCAL, kind, key, tags);
+    }
+
+    /// Tell subscribers that `origin` changed the stored `key`
+    pub(crate) fn publish(&self, origin: Origin, kind: ChangeKind, key: &str, tags: &[String]) {
+        self.notifier.publish(origin, kind, key, tags);
+    }
+
     /// Check if we need to track access times for the current eviction strategy
     fn needs_access_time_tracking(&self) -> bool {
         matches!(
-            self.config.eviction_strategy,
+            self.eviction_strategy(),
             EvictionStrategy::Lru
                 | EvictionStrategy::LruTtl
                 | EvictionStrategy::Lfu
                 | EvictionStrategy::LfuTtl
-        )
+                | EvictionStrategy::TinyLfu
+                | EvictionStrategy::Gdsf
+        ) || self.config.max_idle.is_some()
     }
 
     /// Create a new high-performance cache instance
     pub fn new(config: CacheConfig) -> CacheResult<Self> {
-        // Validate configuration parameters
-        validate_cache_config(config.max_size, config.max_entries, &config.directory)?;
+        Self::with_disk(config, Arc::new(RawDisk))
+    }
 
-        // Create storage config from cache config
-        let storage_config = crate::storage::optimized_backend::StorageConfig {
-            disk_write_threshold: config.disk_write_threshold,
-            use_file_locking: config.use_file_locking,
-            ..Default::default()
+    /// Create a cache whose keys and values pass through a custom [`Disk`] codec
+    pub fn with_disk(config: CacheConfig, disk: Arc<dyn Disk>) -> CacheResult<Self> {
+        // Validate configuration parameters
+        validate_cache_config(&config)?;
+
+        // Other processes opening the directory at the same time wait until
+        // this one is done setting it up
+        let lock_backend = config
+            .lock_backend
+            .nfs_safe(config.use_file_locking || config.smb_mode);
+        let setup = config
+            .backend
+            .uses_directory()
+            .then(|| SetupLock::acquire(&config.directory, lock_backend))
+            .transpose()?;
+        if config.backend.uses_directory() {
+            // A switch cut short by a crash is finished before anything opens
+            if let Some(switched) = finish_backend_switch(&config.directory)? {
+                cache_meta::record_backend(
+                    &config.directory,
+                    switched,
+                    config.file_naming,
+                    &config.codecs(),
+                )?;
+            }
+            cache_meta::check_or_create(
+                &config.directory,
+                config.backend,
+                config.file_naming,
+                &config.codecs(),
+                config.encryption_key.as_ref(),
+            )?;
+        }
+        // Before the lease is opened, so one left by a crashed writer is free
+        let registration = config
+            .backend
+            .uses_directory()
+            .then(|| Registration::register(&config.directory))
+            .transpose()?;
+        let disk: Arc<dyn Disk> = match &config.encryption_key {
+            Some(key) => Arc::new(EncryptedDisk::new(disk, key)),
+            None => disk,
         };
 
-        // Initialize optimized storage backend with config
-        let storage: Box<dyn StorageBackend> = Box::new(
-            crate::storage::optimized_backend::OptimizedStorage::with_config(
-                &config.directory,
-                storage_config,
-            )?,
-        );
+        let kind = config.backend;
+        let storage = open_storage(&config, kind, &config.directory)?;
+        #[cfg(unix)]
+        let writer = config
+            .designated_writer
+            .then(|| DesignatedWriter::open(&config.directory, storage.clone()).map(Arc::new))
+            .transpose()?;
+        #[cfg(unix)]
+        let storage: Arc<dyn StorageBackend> = match &writer {
+            Some(writer) => writer.clone(),
+            None => storage,
+        };
+        let lease = config
+            .writer_lease
+            .map(|period| {
+                WriterLease::open(&config.directory, period, storage.clone()).map(Arc::new)
+            })
+            .transpose()?;
+        let storage: Arc<dyn StorageBackend> = match &lease {
+            Some(lease) => lease.clone(),
+            None => storage,
+        };
+        let maintenance = config
+            .maintenance_lease
+            .map(|period| MaintenanceLease::open(&config.directory, period).map(Arc::new))
+            .transpose()?;
 
         // Setup eviction policy
-        let eviction = Box::new(CombinedEviction::new(config.eviction_strategy));
+        let eviction = Arc::new(RwLock::new(ActiveEviction {
+            strategy: config.eviction_strategy,
+            policy: eviction_policy(&config),
+        }));
 
         // Initialize optimized serializer (MessagePack with LZ4)
         let serializer = OptimizedSerializer;
@@ -96,19 +841,79 @@ impl DiskCache {
         // Cross-process correctness depends on the storage backend checking the
         // persistent SQLite index as the source of truth.
         let memory_cache = None;
+        let statistics = AtomicBool::new(config.statistics);
+        let trash = config
+            .soft_delete
+            .map(|grace| Trash::open(&config.directory, grace))
+            .transpose()?;
+        let entry_locks = match config.backend {
+            StorageKind::Memory => EntryLocks::in_process(),
+            #[cfg(unix)]
+            StorageKind::Server => EntryLocks::on_server(&config.directory),
+            #[cfg(not(unix))]
+            StorageKind::Server => EntryLocks::in_process(),
+            _ => EntryLocks::in_directory(&config.directory, lock_backend),
+        }
+        .into();
+        let notifier = Arc::new(Notifier::new(disk.clone()));
 
         let mut cache = Self {
             config,
-            storage,
+            storage: Arc::new(RwLock::new(ActiveStorage {
+                kind,
+                backend: storage,
+            })),
             eviction,
             serializer,
-            stats: Arc::new(RwLock::new(CacheStats::new())),
-            last_vacuum: Arc::new(RwLock::new(current_timestamp())),
+            stats: Arc::new(OrderedRwLock::new(LockLevel::Stats, CacheStats::new())),
+            statistics,
+            last_vacuum: Arc::new(OrderedRwLock::new(LockLevel::Stats, current_timestamp())),
             memory_cache,
+            disk,
+            last_recovery: OrderedRwLock::new(LockLevel::Stats, None),
+            queue_lock: OrderedMutex::new(LockLevel::Queue, ()),
+            loads: SingleFlight::new(),
+            legacy: None,
+            trash,
+            popularity: Mutex::new(KeyPopularity::new()),
+            entry_locks,
+            #[cfg(unix)]
+            writer,
+            lease,
+            maintenance,
+            registration: Mutex::new(registration),
+            stats_shard: Mutex::new(None),
+            fork: ForkCheck::new(),
+            notifier,
+            pins: Mutex::new(HashSet::new()),
+            tag_quotas: Arc::new(TagQuotas::new()),
+            eviction_queue: Arc::new(EvictionQueue::new()),
         };
+        cache.hook_storage(cache.storage().as_ref());
+        if cache.config.backend.uses_directory() {
+            let shard = cache.open_stats_shard(lock_backend, StatCounts::default())?;
+            cache.stats_shard = Mutex::new(Some(shard));
+        }
+
+        if cache.config.auto_recover
+            && !cache.is_read_only()
+            && cache.storage().was_unclean_shutdown() == Some(true)
+        {
+            cache.verify_and_recover()?;
+        }
 
-        // Automatically migrate existing diskcache data for compatibility
-        cache.migrate_existing_data()?;
+        // Automatically migrate existing diskcache data for compatibility; the
+        // sqlite backend serves that data in place instead
+        if cache.config.backend == StorageKind::Optimized {
+            cache.migrate_existing_data()?;
+        }
+        cache.restore_eviction()?;
+        // Entry files of the old per-file backend move over in the background
+        if cache.config.backend.uses_directory() {
+            let storage = cache.storage().clone();
+            cache.legacy = LegacyMigration::start(&cache.config.directory, storage);
+        }
+        drop(setup);
 
         Ok(cache)
     }
@@ -125,6 +930,8 @@ impl DiskCache {
     /// Get a value from the cache
     pub fn get(&self, key: &str) -> CacheResult<Option<Vec<u8>>> {
         validate_key(key)?;
+        let requested = key;
+        let key = &self.disk.put(key)?;
 
         let should_track_access = self.needs_access_time_tracking();
 
@@ -133,743 +940,5304 @@ impl DiskCache {
             if let Some(entry) = memory_cache.get(key) {
                 // Fast path: only update eviction policy if needed
                 if should_track_access {
-                    self.eviction.on_access(key, &entry);
+                    self.eviction().on_access(key, &entry);
                 }
 
-                // Update stats
-                self.stats.write().hits += 1;
+                self.record_lookups(1, 0);
+                self.record_hits(&[requested]);
 
-                // Extract data based on storage mode
-                match &entry.storage {
-                    crate::serialization::StorageMode::Inline(data) => {
-                        return Ok(Some(data.clone()));
-                    }
-                    crate::serialization::StorageMode::File(filename) => {
-                        let data = self.storage.read_data_file(filename)?;
-                        return Ok(Some(data));
-                    }
-                }
+                return self.read_entry_data(&entry).map(Some);
             }
         }
 
         // Try disk storage
-        match self.storage.get(key)? {
-            Some(entry) => {
+        match self.lookup(key, true)? {
+            Some((key, entry)) => {
                 // Fast path: only update eviction policy if needed
                 if should_track_access {
-                    self.eviction.on_access(key, &entry);
+                    self.eviction().on_access(&key, &entry);
                 }
 
                 // Store in memory cache for future access (without modifying the entry)
                 if let Some(ref memory_cache) = self.memory_cache {
-                    memory_cache.put(key.to_string(), entry.clone());
+                    memory_cache.put(key, entry.clone());
                 }
 
-                // Update stats
-                self.stats.write().hits += 1;
+                self.record_lookups(1, 0);
+                self.record_hits(&[requested]);
 
-                // Extract data based on storage mode
-                match &entry.storage {
-                    crate::serialization::StorageMode::Inline(data) => Ok(Some(data.clone())),
-                    crate::serialization::StorageMode::File(filename) => {
-                        let data = self.storage.read_data_file(filename)?;
-                        Ok(Some(data))
-                    }
-                }
+                self.read_entry_data(&entry).map(Some)
             }
             None => {
-                self.stats.write().misses += 1;
+                self.record_lookups(0, 1);
                 Ok(None)
             }
         }
     }
 
-    /// Set a value in the cache
-    pub fn set(
-        &self,
-        key: &str,
-        value: &[u8],
-        expire_time: Option<u64>,
-        tags: Vec<String>,
-    ) -> CacheResult<()> {
-        validate_key(key)?;
-
-        // Enforce cache size and entry limits
-        self.enforce_cache_limits()?;
-
-        let existed = self.storage.exists(key)?;
-
-        // Always use inline storage for simplicity (OptimizedStorage handles the optimization)
-        let entry = CacheEntry::new_inline(key.to_string(), value.to_vec(), tags, expire_time);
+    /// Stored entry for an encoded key, following an alias if `key` is one
+    ///
+    /// Returns the key the entry is stored under alongside it. Without
+    /// `touch` the storage reads it without counting a use of it.
+    fn lookup(&self, key: &str, touch: bool) -> CacheResult<Option<(String, CacheEntry)>> {
+        let read = |key: &str| match touch {
+            true => self.storage().get(key),
+            false => self.storage().peek(key),
+        };
+        self.settle_legacy(key)?;
+        if self.expire_if_idle(key)? {
+            return Ok(None);
+        }
+        if let Some(entry) = read(key)? {
+            return Ok(Some((key.to_string(), entry)));
+        }
+        let Some(primary) = self.storage().resolve_alias(key)? else {
+            return Ok(None);
+        };
+        if self.expire_if_idle(&primary)? {
+            return Ok(None);
+        }
+        Ok(read(&primary)?.map(|entry| (primary, entry)))
+    }
 
-        // Store the entry metadata
-        self.storage.set(key, entry.clone())?;
-        self.eviction.on_insert(key, &entry);
+    /// Expire the entry under an encoded key if it went unused for
+    /// `max_idle`, returning whether it did
+    fn expire_if_idle(&self, key: &str) -> CacheResult<bool> {
+        if self.config.max_idle.is_none() || !self.eviction().is_idle(key) {
+            return Ok(false);
+        }
+        self.expire_idle(key)?;
+        Ok(true)
+    }
 
-        // Store in memory cache
+    /// Remove the entry under an encoded key for having gone unused
+    fn expire_idle(&self, key: &str) -> CacheResult<()> {
+        let existed = self.storage().delete(key)?;
+        self.eviction().on_remove(key);
+        self.tag_quotas.on_remove(key);
+        self.eviction_queue.cancel(key);
         if let Some(ref memory_cache) = self.memory_cache {
-            memory_cache.put(key.to_string(), entry.clone());
+            memory_cache.remove(key);
         }
-
-        // Update stats
-        let mut stats = self.stats.write();
-        stats.sets += 1;
-        stats.total_size += entry.size;
-        if !existed {
-            stats.entry_count += 1;
+        if existed {
+            self.notify(ChangeKind::Expire, key, &[]);
+            let mut stats = self.stats.write();
+            stats.entry_count = stats.entry_count.saturating_sub(1);
         }
-
         Ok(())
     }
 
-    /// Set multiple values in the cache using a batched storage path.
-    pub fn set_many(
-        &self,
-        items: Vec<(String, Vec<u8>)>,
-        expire_time: Option<u64>,
-        tags: Vec<String>,
-    ) -> CacheResult<()> {
-        if items.is_empty() {
-            return Ok(());
+    /// Expire up to `count` entries that went unused for `max_idle`, the
+    /// longest unused first
+    fn expire_idle_entries(&self, count: usize) -> CacheResult<usize> {
+        if self.config.max_idle.is_none() {
+            return Ok(0);
         }
+        let idle = self.eviction().idle_keys(count);
+        for key in &idle {
+            self.expire_idle(key)?;
+        }
+        Ok(idle.len())
+    }
 
-        self.enforce_cache_limits()?;
-
-        let mut storage_entries = Vec::with_capacity(items.len());
-        let mut cache_entries = Vec::with_capacity(items.len());
-        let mut total_size = 0_u64;
-        let mut new_entries = 0_u64;
-        let mut seen_keys = HashSet::with_capacity(items.len());
+    /// Get several values at once, in the order of `keys`
+    ///
+    /// Keys missing from the memory cache are fetched from storage in one batch so
+    /// their file reads overlap. When `prefetch_prefix` is given, entries under that
+    /// prefix are loaded into the storage hot tier alongside the batch, so follow-up
+    /// lookups of sibling keys are served from memory.
+    pub fn get_many(
+        &self,
+        keys: &[&str],
+        prefetch_prefix: Option<&str>,
+    ) -> CacheResult<Vec<Option<Vec<u8>>>> {
+        let requested = keys;
+        let keys = keys
+            .iter()
+            .map(|key| {
+                validate_key(key)?;
+                self.disk.put(key)
+            })
+            .collect::<CacheResult<Vec<String>>>()?;
+        let prefetch_prefix = prefetch_prefix
+            .map(|prefix| self.disk.put(prefix))
+            .transpose()?;
 
-        for (key, value) in items {
-            validate_key(&key)?;
+        let should_track_access = self.needs_access_time_tracking();
+        let mut entries = vec![None; keys.len()];
+        let mut cold_slots = Vec::new();
+        let mut cold_keys = Vec::new();
 
-            if seen_keys.insert(key.clone()) && !self.storage.exists(&key)? {
-                new_entries += 1;
+        for (slot, key) in keys.iter().enumerate() {
+            if self.expire_if_idle(key)? {
+                continue;
+            }
+            match self.memory_cache.as_ref().and_then(|cache| cache.get(key)) {
+                Some(entry) => entries[slot] = Some(entry),
+                None => {
+                    self.settle_legacy(key)?;
+                    cold_slots.push(slot);
+                    cold_keys.push(key.clone());
+                }
             }
-
-            total_size += value.len() as u64;
-            storage_entries.push((key.clone(), value.clone()));
-            cache_entries.push(CacheEntry::new_inline(
-                key,
-                value,
-                tags.clone(),
-                expire_time,
-            ));
         }
 
-        self.storage.set_batch(storage_entries)?;
+        let cold_entries = std::thread::scope(|scope| {
+            let prefetch = prefetch_prefix
+                .as_deref()
+                .map(|prefix| scope.spawn(move || self.storage().prefetch_prefix(prefix)));
+            let cold_entries = if cold_keys.is_empty() {
+                Ok(Vec::new())
+            } else {
+                self.storage().get_many(&cold_keys)
+            };
+            if let Some(prefetch) = prefetch {
+                // Prefetching is best effort; the requested keys are already answered
+                let _ = prefetch.join();
+            }
+            cold_entries
+        })?;
 
-        for entry in &cache_entries {
-            self.eviction.on_insert(&entry.key, entry);
-            if let Some(ref memory_cache) = self.memory_cache {
-                memory_cache.put(entry.key.clone(), entry.clone());
+        for (slot, entry) in cold_slots.into_iter().zip(cold_entries) {
+            if let (Some(entry), Some(memory_cache)) = (&entry, &self.memory_cache) {
+                memory_cache.put(keys[slot].clone(), entry.clone());
             }
+            entries[slot] = entry;
         }
 
-        let mut stats = self.stats.write();
-        stats.sets += cache_entries.len() as u64;
-        stats.total_size += total_size;
-        stats.entry_count += new_entries;
-        drop(stats);
-
-        self.enforce_cache_limits()?;
+        let mut values = Vec::with_capacity(keys.len());
+        let (mut hits, mut misses) = (0, 0);
+        let mut hit_keys = Vec::new();
+        for ((key, requested), entry) in keys.iter().zip(requested).zip(entries) {
+            match entry {
+                Some(entry) => {
+                    if should_track_access {
+                        self.eviction().on_access(key, &entry);
+                    }
+                    hits += 1;
+                    hit_keys.push(*requested);
+                    values.push(Some(self.read_entry_data(&entry)?));
+                }
+                None => {
+                    misses += 1;
+                    values.push(None);
+                }
+            }
+        }
 
-        Ok(())
+        self.record_lookups(hits, misses);
+        self.record_hits(&hit_keys);
+        Ok(values)
     }
 
-    /// Delete a value from the cache
-    pub fn delete(&self, key: &str) -> CacheResult<bool> {
+    /// Read a value without touching access statistics or eviction order
+    pub fn peek(&self, key: &str) -> CacheResult<Option<Vec<u8>>> {
         validate_key(key)?;
+        let key = self.disk.put(key)?;
 
-        let existed = self.storage.delete(key)?;
-        if existed {
-            self.eviction.on_remove(key);
-
-            // Remove from memory cache
-            if let Some(ref memory_cache) = self.memory_cache {
-                memory_cache.remove(key);
-            }
-
-            let mut stats = self.stats.write();
-            stats.deletes += 1;
-            stats.entry_count = stats.entry_count.saturating_sub(1);
+        match self.lookup(&key, false)? {
+            Some((_, entry)) => self.read_entry_data(&entry).map(Some),
+            None => Ok(None),
         }
+    }
 
-        Ok(existed)
+    /// Path of the data file holding `key`'s value byte for byte, if there is
+    /// one, and the offset the value starts at after the file's header
+    ///
+    /// Small values live inline in the index and large ones may be compressed or
+    /// re-encoded by the key codec; those return `None` and must be read with
+    /// [`DiskCache::get`]. The file belongs to the cache: open it read-only and
+    /// expect it to change or disappear once the key is rewritten or deleted.
+    pub fn data_file(&self, key: &str) -> CacheResult<Option<(PathBuf, u64)>> {
+        validate_key(key)?;
+        if !self.disk.stores_verbatim() {
+            return Ok(None);
+        }
+        let key = self.disk.put(key)?;
+        self.storage().data_file_path(&key)
     }
 
-    /// Check if a key exists in the cache
-    pub fn exists(&self, key: &str) -> CacheResult<bool> {
+    /// Seekable reader over `key`'s value if it is stored in compressed frames,
+    /// decompressing only the frames that reads reach
+    ///
+    /// Only values written with `seekable_compression` on are; others return
+    /// `None` and must be read with [`DiskCache::get`] or
+    /// [`DiskCache::read_stream`].
+    pub fn open_frames(&self, key: &str) -> CacheResult<Option<FramedReader<std::fs::File>>> {
         validate_key(key)?;
-        self.storage.exists(key)
+        if !self.disk.stores_verbatim() {
+            return Ok(None);
+        }
+        let key = self.disk.put(key)?;
+        self.storage().open_frames(&key)
     }
 
-    /// Get all keys in the cache
-    pub fn keys(&self) -> CacheResult<Vec<String>> {
-        self.storage.keys()
+    /// Bytes `range` of `key`'s value, or `None` if it is not stored
+    ///
+    /// The range is cut short at the end of the value. Values in a data file
+    /// of their own or in seekable frames are read from disk only as far as
+    /// the range reaches; others are read whole, as by [`DiskCache::get`].
+    pub fn get_range(&self, key: &str, range: Range<u64>) -> CacheResult<Option<Vec<u8>>> {
+        let len = range.end.saturating_sub(range.start);
+        let mut data = Vec::new();
+        if let Some((path, offset)) = self.data_file(key)? {
+            match std::fs::File::open(&path) {
+                Ok(mut file) => {
+                    file.seek(SeekFrom::Start(offset + range.start))?;
+                    file.take(len).read_to_end(&mut data)?;
+                    self.record_lookups(1, 0);
+                    self.record_hits(&[key]);
+                    return Ok(Some(data));
+                }
+                // Rewritten or deleted since the lookup; fall back to a plain read
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(CacheError::Io(e)),
+            }
+        }
+        if let Some(mut frames) = self.open_frames(key)? {
+            frames.seek(SeekFrom::Start(range.start))?;
+            frames.take(len).read_to_end(&mut data)?;
+            self.record_lookups(1, 0);
+            self.record_hits(&[key]);
+            return Ok(Some(data));
+        }
+        Ok(self.get(key)?.map(|value| {
+            let start = (range.start as usize).min(value.len());
+            let end = (range.end as usize).clamp(start, value.len());
+            value[start..end].to_vec()
+        }))
     }
 
-    /// Clear all entries from the cache
-    pub fn clear(&self) -> CacheResult<()> {
-        self.storage.clear()?;
-        self.eviction.clear();
+    /// Reader over `key`'s value, or `None` if it is not stored
+    ///
+    /// Values held in a data file of their own, such as streamed and linked
+    /// ones, are read from that file a chunk at a time, and values in seekable
+    /// frames a frame at a time; others are read into memory first, as by
+    /// [`DiskCache::get`].
+    pub fn read_stream(&self, key: &str) -> CacheResult<Option<Box<dyn Read + Send>>> {
+        if let Some((path, offset)) = self.data_file(key)? {
+            match std::fs::File::open(&path) {
+                Ok(mut file) => {
+                    file.seek(SeekFrom::Start(offset))?;
+                    self.record_lookups(1, 0);
+                    self.record_hits(&[key]);
+                    return Ok(Some(Box::new(std::io::BufReader::new(file))));
+                }
+                // Rewritten or deleted since the lookup; fall back to a plain read
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(CacheError::Io(e)),
+            }
+        }
+        if let Some(frames) = self.open_frames(key)? {
+            self.record_lookups(1, 0);
+            self.record_hits(&[key]);
+            return Ok(Some(Box::new(frames)));
+        }
+        Ok(self
+            .get(key)?
+            .map(|value| Box::new(std::io::Cursor::new(value)) as Box<dyn Read + Send>))
+    }
 
-        // Clear memory cache
-        if let Some(ref memory_cache) = self.memory_cache {
-            memory_cache.clear();
+    /// Version stamp of `key`, or `None` if it is not stored
+    ///
+    /// The stamp is opaque and changes whenever the key is written, so a caller
+    /// that remembers it can later tell whether anyone rewrote the entry.
+    pub fn version(&self, key: &str) -> CacheResult<Option<u64>> {
+        validate_key(key)?;
+        let key = self.disk.put(key)?;
+        match self.storage().version(&key)? {
+            Some(version) => Ok(Some(version)),
+            None => match self.storage().resolve_alias(&key)? {
+                Some(primary) => self.storage().version(&primary),
+                None => Ok(None),
+            },
         }
+    }
 
-        let mut stats = self.stats.write();
-        *stats = CacheStats::new();
+    /// Whether `key` was rewritten or removed since it had `version`
+    pub fn has_changed(&self, key: &str, version: u64) -> CacheResult<bool> {
+        Ok(self.version(key)? != Some(version))
+    }
 
+    /// Never evict `key`, nor drop its value from the storage's memory tiers
+    ///
+    /// Pins belong to this handle: they are not stored, and other processes
+    /// sharing the directory evict by their own. `key` need not be stored
+    /// yet, and stays pinned when deleted and set again. Expiration still
+    /// removes a pinned entry, and a cache whose entries are all pinned
+    /// stays over its limits.
+    pub fn pin(&self, key: &str) -> CacheResult<()> {
+        validate_key(key)?;
+        let key = self.disk.put(key)?;
+        self.eviction().set_pinned(&key, true);
+        self.storage().set_pinned(&key, true);
+        self.pins.lock().insert(key);
         Ok(())
     }
 
-    /// Get cache statistics
-    pub fn stats(&self) -> CacheStats {
-        self.stats.read().clone()
+    /// Let `key` be evicted again, returning whether it was pinned
+    pub fn unpin(&self, key: &str) -> CacheResult<bool> {
+        let key = self.disk.put(key)?;
+        self.eviction().set_pinned(&key, false);
+        self.storage().set_pinned(&key, false);
+        Ok(self.pins.lock().remove(&key))
     }
 
-    /// Get current cache size in bytes (estimated)
-    pub fn size(&self) -> CacheResult<u64> {
-        // Estimate size from stats
-        Ok(self.stats.read().total_size)
+    /// Cap the bytes or entries stored under `tag`, so it cannot crowd out
+    /// the rest of the cache
+    ///
+    /// Writes leaving the tag over its limit evict within it first, in the
+    /// order the eviction policy picks, up to `cull_limit` entries each; the
+    /// tag is brought under the new limit at once. The entries already under
+    /// the tag are counted by reading every stored entry once. Like pins,
+    /// limits belong to this handle and are not stored.
+    pub fn set_tag_limit(&self, tag: &str, limit: TagLimit) -> CacheResult<()> {
+        let mut stored = Vec::new();
+        for key in self.storage().keys()? {
+            if let Some(entry) = self.storage().get(&key)? {
+                if entry.tags.iter().any(|stored_tag| stored_tag == tag) {
+                    stored.push((key, entry.size));
+                }
+            }
+        }
+        self.tag_quotas.set_limit(tag, limit, stored);
+        self.enforce_tag_limits(&[tag.to_string()], usize::MAX)
     }
 
-    /// Manually trigger vacuum operation
-    pub fn vacuum(&self) -> CacheResult<()> {
-        self.storage.vacuum()?;
-        *self.last_vacuum.write() = current_timestamp();
-        Ok(())
+    /// Stop limiting `tag`, returning whether it was limited
+    pub fn remove_tag_limit(&self, tag: &str) -> bool {
+        self.tag_quotas.remove_limit(tag)
     }
 
-    /// Close the cache and release resources (especially redb database lock)
-    pub fn close(&self) {
-        // Close the redb database to release file lock
-        if let Some(optimized_storage) =
-            self.storage
-                .as_any()
-                .downcast_ref::<crate::storage::optimized_backend::OptimizedStorage>()
-        {
-            optimized_storage.close_db();
-        }
+    /// The limit set on `tag`, if any
+    pub fn tag_limit(&self, tag: &str) -> Option<TagLimit> {
+        self.tag_quotas.limit(tag)
     }
 
-    /// Check cache limits and evict entries if necessary
-    fn enforce_cache_limits(&self) -> CacheResult<()> {
-        let stats = self.stats.read();
-        let current_size = stats.total_size;
-        let current_entries = stats.entry_count;
-        drop(stats);
-
-        let mut evict_count = 0;
-
-        // Check size limit
-        if let Some(max_size) = self.config.max_size {
-            if current_size > max_size {
-                // Evict 10% of entries or enough to get under limit
-                evict_count = std::cmp::max(evict_count, (current_entries / 10).max(1));
-            }
-        }
-
-        // Check entry count limit
-        if let Some(max_entries) = self.config.max_entries {
-            if current_entries > max_entries {
-                evict_count = std::cmp::max(
-                    evict_count,
-                    current_entries - max_entries + (max_entries / 10),
-                );
-            }
-        }
-
-        // Perform eviction
-        if evict_count > 0 {
-            let victims = self.eviction.select_victims(evict_count as usize);
-            for key in victims {
-                self.storage.delete(&key)?;
-                self.eviction.on_remove(&key);
-                self.stats.write().evictions += 1;
-            }
-        }
-
-        // Auto vacuum every hour
-        let last_vacuum = *self.last_vacuum.read();
-        let now = current_timestamp();
-        if now - last_vacuum > 3600 {
-            // 1 hour
-            self.vacuum()?;
-        }
-
-        Ok(())
+    /// Hold `key` against other callers of `lock_key` until the guard drops
+    ///
+    /// Reading a value, computing its successor and writing it back is not
+    /// atomic on its own; doing so under this lock is, across threads and
+    /// processes sharing the directory, including over NFS. The lock is
+    /// advisory and only excludes other holders, not plain writes.
+    pub fn lock_key(&self, key: &str) -> CacheResult<EntryLock> {
+        validate_key(key)?;
+        self.entry_locks.lock(key)
     }
 
-    /// Automatically migrate existing diskcache data if detected
-    fn migrate_existing_data(&mut self) -> CacheResult<()> {
-        if detect_diskcache_format(&self.config.directory) {
-            tracing::info!("Detected python-diskcache data, starting auto-migration...");
-
-            // Create a backup first
-            let backup_dir = self.config.directory.join("diskcache_backup");
-            if !backup_dir.exists() {
-                let cache_db = self.config.directory.join("cache.db");
-                let backup_db = backup_dir.join("cache.db");
-                std::fs::create_dir_all(&backup_dir)?;
-                std::fs::copy(&cache_db, &backup_db)?;
-                tracing::info!("Created backup at: {:?}", backup_dir);
-            }
-
-            // Perform migration using OptimizedStorage
-            let mut migrator = DiskCacheMigrator::new(
-                self.config.directory.clone(),
-                Box::new(OptimizedStorage::new(&self.config.directory)?),
-            );
+    /// Whether writes fail because another process holds the writer lease
+    ///
+    /// Always false without `writer_lease`. A lease its holder stopped
+    /// renewing is only taken over by the next write, so this may report
+    /// true for a write that would succeed.
+    pub fn is_read_only(&self) -> bool {
+        self.lease.as_ref().is_some_and(|lease| !lease.is_writer())
+    }
 
-            match migrator.migrate() {
-                Ok(stats) => {
-                    tracing::info!("Migration completed: {:?}", stats);
-                    if stats.success {
-                        // Rename the original database to avoid future migrations
-                        let cache_db = self.config.directory.join("cache.db");
-                        let migrated_db = self.config.directory.join("cache.db.migrated");
-                        if cache_db.exists() && !migrated_db.exists() {
-                            std::fs::rename(&cache_db, &migrated_db)?;
-                        }
-                    }
-                }
-                Err(e) => {
-                    tracing::warn!("Migration failed: {}", e);
-                    // Continue without migration
-                }
-            }
+    /// The processes that have this cache's directory open, oldest first
+    ///
+    /// Processes that died without closing it are dropped from the registry
+    /// on the way, and the locks and writer lease they held are released.
+    /// Caches without a directory list no processes.
+    pub fn processes(&self) -> CacheResult<Vec<ProcessInfo>> {
+        if !self.config.backend.uses_directory() {
+            return Ok(Vec::new());
         }
-
-        Ok(())
+        registry::list(&self.config.directory)
     }
 
-    /// Get memory cache statistics
-    pub fn memory_stats(&self) -> Option<crate::memory_cache::MemoryCacheStats> {
-        self.memory_cache.as_ref().map(|mc| mc.stats())
+    /// Hear about keys of `topic` being set, deleted or found expired
+    ///
+    /// Changes made through this cache arrive right away. Changes other
+    /// processes make arrive too when the cache has an event log or is
+    /// attached to a cache server; if some were missed, a `Clear` stands in
+    /// for them. A tag topic only hears about deletions and expirations of
+    /// keys it saw being set with the tag.
+    pub fn subscribe(&self, topic: Topic) -> CacheResult<Subscription> {
+        let mut subscription = Subscription::new();
+        self.subscribe_into(topic, &mut subscription)?;
+        Ok(subscription)
     }
 
-    /// Manually migrate from python-diskcache
-    pub fn migrate_from_diskcache(&mut self) -> CacheResult<crate::migration::MigrationStats> {
-        let mut migrator = DiskCacheMigrator::new(
-            self.config.directory.clone(),
-            Box::new(OptimizedStorage::new(&self.config.directory)?),
-        );
-
-        migrator.migrate()
+    /// Like [`DiskCache::subscribe`], delivering to an existing subscription
+    /// so it can follow several caches or topics at once
+    pub fn subscribe_into(&self, topic: Topic, subscription: &mut Subscription) -> CacheResult<()> {
+        self.notifier.add(topic, None, subscription);
+        self.start_change_feed()
     }
-}
-
-/// Python wrapper for the Cache
-#[pyclass]
-pub struct PyCache {
-    cache: DiskCache,
-}
-
-#[pymethods]
-impl PyCache {
-    #[new]
-    #[pyo3(signature = (directory, max_size=None, max_entries=None, disk_write_threshold=None, use_file_locking=None))]
-    fn new(
-        directory: String,
-        max_size: Option<u64>,
-        max_entries: Option<u64>,
-        disk_write_threshold: Option<usize>,
-        use_file_locking: Option<bool>,
-    ) -> PyResult<Self> {
-        let mut config = CacheConfig {
-            directory: PathBuf::from(directory),
-            ..Default::default()
-        };
-        config.max_size = max_size;
-        config.max_entries = max_entries;
-
-        // Apply new configuration options if provided
-        if let Some(threshold) = disk_write_threshold {
-            config.disk_write_threshold = threshold;
-        }
-        if let Some(locking) = use_file_locking {
-            config.use_file_locking = locking;
-        }
 
-        let cache = DiskCache::new(config)?;
-        Ok(Self { cache })
+    /// Subscribe to every change except those `origin` reports making
+    pub(crate) fn subscribe_skipping(
+        &self,
+        origin: Origin,
+        subscription: &mut Subscription,
+    ) -> CacheResult<()> {
+        self.notifier
+            .add(Topic::Prefix(String::new()), Some(origin), subscription);
+        self.start_change_feed()
     }
 
-    fn get(&self, key: &str) -> PyResult<Option<Vec<u8>>> {
-        Ok(self.cache.get(key)?)
+    /// Pass changes other processes make on to the subscribers, if the
+    /// storage can tell about them
+    fn start_change_feed(&self) -> CacheResult<()> {
+        self.notifier.ensure_feed(|| {
+            let Some(feed) = change_feed(self.storage().as_ref())? else {
+                return Ok(None);
+            };
+            let storage = Arc::downgrade(&self.storage);
+            let tags: TagLookup = Box::new(move |key| {
+                storage
+                    .upgrade()
+                    .and_then(|storage| storage.read_recursive().backend.get(key).ok().flatten())
+                    .map(|entry| entry.tags)
+                    .unwrap_or_default()
+            });
+            Ok(Some((feed, tags)))
+        })
     }
 
-    #[pyo3(signature = (key, value, expire_time=None, tags=None))]
-    fn set(
+    /// Get `key`, or compute it with `loader` and store it on a miss
+    ///
+    /// Threads of this process that miss on the same key while a load is
+    /// running wait for it instead of calling their own loader. If the load
+    /// fails, one of the waiters tries its loader next.
+    pub fn get_or_load(
         &self,
         key: &str,
-        value: Py<PyAny>,
         expire_time: Option<u64>,
-        tags: Option<Vec<String>>,
-    ) -> PyResult<()> {
-        let tags = tags.unwrap_or_default();
-        // Convert PyObject to bytes for internal storage
-        let value_bytes = Python::attach(|py| {
-            let bytes = value.extract::<Vec<u8>>(py)?;
-            Ok::<Vec<u8>, PyErr>(bytes)
-        })?;
-        Ok(self.cache.set(key, &value_bytes, expire_time, tags)?)
+        tags: Vec<String>,
+        loader: impl FnOnce() -> CacheResult<Vec<u8>>,
+    ) -> CacheResult<Vec<u8>> {
+        validate_key(key)?;
+        self.loads.load(
+            key,
+            || self.get(key),
+            || {
+                let value = loader()?;
+                self.set(key, &value, expire_time, tags)?;
+                Ok(value)
+            },
+        )
     }
 
-    /// Set multiple values in the cache (batch operation for better performance)
-    #[pyo3(signature = (items, expire_time=None, tags=None))]
-    fn set_many(
+    /// Get `key`, or compute it with `loader` and store it, one load at a time
+    ///
+    /// Unlike [`get_or_load`](Self::get_or_load), misses also wait for loads
+    /// running in other processes sharing the cache: the loader runs under
+    /// the key's entry lock, and a caller that gets the lock after another's
+    /// load returns that value instead of loading again. With `stale`
+    /// seconds, a value is kept that long past `expire_time`, and returned
+    /// as it is while the first caller to find it expired, in whichever
+    /// process, loads its successor; only misses ever wait. Clients of a
+    /// cache server only wait for loads in their own process.
+    pub fn get_or_set(
         &self,
-        items: Vec<(String, Vec<u8>)>,
+        key: &str,
         expire_time: Option<u64>,
-        tags: Option<Vec<String>>,
-    ) -> PyResult<()> {
-        let tags = tags.unwrap_or_default();
-        self.cache.set_many(items, expire_time, tags)?;
-        Ok(())
-    }
+        stale: Option<u64>,
+        tags: Vec<String>,
+        loader: impl FnOnce() -> CacheResult<Vec<u8>>,
+    ) -> CacheResult<Vec<u8>> {
+        validate_key(key)?;
+        let (Some(expire_time), Some(stale)) = (expire_time, stale) else {
+            return self.load_locked(key, expire_time, tags, loader);
+        };
+        let kept_until = expire_time.saturating_add(stale);
+        let Some((value, fresh, version)) = self.read_stale(key, stale)? else {
+            return self.load_locked(key, Some(kept_until), tags, loader);
+        };
+        if fresh {
+            return Ok(value);
+        }
 
-    fn delete(&self, key: &str) -> PyResult<bool> {
-        Ok(self.cache.delete(key)?)
+        // Claim the refresh by making the value fresh a while longer; callers
+        // that lose the race return it as it is
+        let claimed_until = current_timestamp() + REFRESH_WINDOW + stale;
+        if !self.cas(
+            key,
+            Some(version),
+            &value,
+            Some(claimed_until),
+            tags.clone(),
+        )? {
+            return Ok(value);
+        }
+        let value = loader()?;
+        self.set(key, &value, Some(kept_until), tags)?;
+        Ok(value)
     }
 
-    fn exists(&self, key: &str) -> PyResult<bool> {
-        Ok(self.cache.exists(key)?)
+    /// Run `loader` for a missing `key` once across threads and processes
+    fn load_locked(
+        &self,
+        key: &str,
+        expire_time: Option<u64>,
+        tags: Vec<String>,
+        loader: impl FnOnce() -> CacheResult<Vec<u8>>,
+    ) -> CacheResult<Vec<u8>> {
+        self.loads.load(
+            key,
+            || self.get(key),
+            || {
+                let _lock = self.entry_locks.lock(key)?;
+                // Another process may have stored it while we waited on the lock
+                if let Some(value) = self.peek(key)? {
+                    return Ok(value);
+                }
+                let value = loader()?;
+                self.set(key, &value, expire_time, tags)?;
+                Ok(value)
+            },
+        )
     }
 
-    fn keys(&self) -> PyResult<Vec<String>> {
-        Ok(self.cache.keys()?)
-    }
+    /// Value and version of `key`, and whether it is fresh, when values are
+    /// kept `stale` seconds past their expiration time
+    fn read_stale(&self, key: &str, stale: u64) -> CacheResult<Option<(Vec<u8>, bool, u64)>> {
+        let Some(version) = self.version(key)? else {
+            self.record_lookups(0, 1);
+            return Ok(None);
+        };
+        let Some((_, entry)) = self.lookup(&self.disk.put(key)?, true)? else {
+            self.record_lookups(0, 1);
+            return Ok(None);
+        };
+        self.record_lookups(1, 0);
+        self.record_hits(&[key]);
 
-    fn clear(&self) -> PyResult<()> {
-        Ok(self.cache.clear()?)
+        let fresh = entry
+            .expire_time
+            .is_none_or(|expire_time| current_timestamp() < expire_time.saturating_sub(stale));
+        Ok(Some((self.read_entry_data(&entry)?, fresh, version)))
     }
 
-    fn size(&self) -> PyResult<u64> {
-        Ok(self.cache.size()?)
+    /// Read the oldest (`last == false`) or newest item in store order
+    ///
+    /// Like [`DiskCache::peek`], this leaves access statistics untouched.
+    pub fn peekitem(&self, last: bool) -> CacheResult<Option<(String, Vec<u8>)>> {
+        // Another handle may delete the key between the two lookups, or its
+        // value may be unreadable; move on to the next key in store order
+        let mut cursor = None;
+        while let Some((seq, key)) = self.storage().key_page(cursor, last, 1)?.pop() {
+            if let Some(entry) = self.storage().peek(&key)? {
+                let data = self.read_entry_data(&entry)?;
+                return Ok(Some((self.disk.get(&key)?, data)));
+            }
+            cursor = Some(seq);
+        }
+        Ok(None)
     }
 
-    fn vacuum(&self) -> PyResult<()> {
-        Ok(self.cache.vacuum()?)
+    /// Push a value onto one end of the queue named `prefix`
+    ///
+    /// Items are stored under `"{prefix}-{n:015}"` keys, so the storage layer's
+    /// ordered key scan yields them in queue order. Returns the item's position
+    /// relative to the first push (0, 1, ... at the back; -1, -2, ... at the front)
+    /// together with its key. Queues need a key codec that preserves ordering,
+    /// which the built-in codecs do.
+    pub fn push(
+        &self,
+        prefix: &str,
+        value: &[u8],
+        side: QueueSide,
+        expire_time: Option<u64>,
+        tags: Vec<String>,
+    ) -> CacheResult<(i64, String)> {
+        let _guard = self.queue_lock.lock();
+
+        // The queue lock is per process; another process pushing to the same
+        // end takes the position first, so move on past it
+        loop {
+            let mut range = self.queue_range(prefix)?;
+            let position = match self.queue_edge(prefix, side, &mut range)? {
+                Some((position, _)) => match side {
+                    QueueSide::Front => position - 1,
+                    QueueSide::Back => position + 1,
+                },
+                None => 0,
+            };
+            let key = queue_key(prefix, position)?;
+            let _lock = self.entry_locks.lock(&key)?;
+            if !self.storage().exists_local(&self.disk.put(&key)?)? {
+                self.set(&key, value, expire_time, tags)?;
+                return Ok((position, key));
+            }
+        }
     }
 
-    fn close(&self) -> PyResult<()> {
-        self.cache.close();
-        Ok(())
+    /// Remove and return the item at one end of the queue named `prefix`
+    pub fn pull(&self, prefix: &str, side: QueueSide) -> CacheResult<Option<QueueItem>> {
+        let _guard = self.queue_lock.lock();
+
+        // Another process may pull the same item; only the pull that removes it
+        // returns it, and the next pull attempt starts past it
+        let mut range = self.queue_range(prefix)?;
+        while let Some(item) = self.next_queue_item(prefix, side, &mut range)? {
+            let _lock = self.entry_locks.lock(&item.key)?;
+            // Pulled items are consumed, not deleted by mistake; they skip the trash
+            if self.remove(&self.disk.put(&item.key)?)? {
+                return Ok(Some(item));
+            }
+        }
+        Ok(None)
     }
 
-    fn stats(&self) -> PyResult<HashMap<String, u64>> {
-        let stats = self.cache.stats();
-        let mut result = HashMap::new();
-        result.insert("hits".to_string(), stats.hits);
-        result.insert("misses".to_string(), stats.misses);
-        result.insert("sets".to_string(), stats.sets);
-        result.insert("deletes".to_string(), stats.deletes);
-        result.insert("evictions".to_string(), stats.evictions);
-        result.insert("errors".to_string(), stats.errors);
-        result.insert("total_size".to_string(), stats.total_size);
-        result.insert("entry_count".to_string(), stats.entry_count);
-        Ok(result)
+    /// Return the item at one end of the queue named `prefix` without removing it
+    pub fn peek_queue(&self, prefix: &str, side: QueueSide) -> CacheResult<Option<QueueItem>> {
+        let _guard = self.queue_lock.lock();
+        let mut range = self.queue_range(prefix)?;
+        self.next_queue_item(prefix, side, &mut range)
     }
 
-    fn hit_rate(&self) -> PyResult<f64> {
-        Ok(self.cache.stats().hit_rate())
+    /// The readable item nearest one end of `range`, narrowing `range` past it
+    fn next_queue_item(
+        &self,
+        prefix: &str,
+        side: QueueSide,
+        range: &mut QueueRange,
+    ) -> CacheResult<Option<QueueItem>> {
+        // Another handle may remove an item between the scan and the read, or
+        // it may be unreadable; move on to the next one
+        while let Some((position, key)) = self.queue_edge(prefix, side, range)? {
+            if let Some(value) = self.peek(&key)? {
+                return Ok(Some(QueueItem {
+                    position,
+                    key,
+                    value,
+                }));
+            }
+        }
+        Ok(None)
     }
-}
-
-/// Drop-in replacement for diskcache.Cache
-#[pyclass(name = "Cache")]
-pub struct RustCache {
-    cache: DiskCache,
-}
 
-#[pymethods]
-impl RustCache {
-    #[new]
-    #[pyo3(signature = (directory, **kwargs))]
-    fn new(directory: String, kwargs: Option<&Bound<'_, pyo3::types::PyDict>>) -> PyResult<Self> {
-        let mut config = CacheConfig {
-            directory: PathBuf::from(directory),
-            ..Default::default()
-        };
+    /// Stored keys the items of the queue named `prefix` sort between
+    fn queue_range(&self, prefix: &str) -> CacheResult<QueueRange> {
+        Ok(QueueRange {
+            start: self
+                .disk
+                .put(&format!("{}-{}", prefix, "0".repeat(QUEUE_KEY_DIGITS)))?,
+            end: self.disk.put(&format!("{}-:", prefix))?,
+        })
+    }
 
-        // Parse kwargs for compatibility with diskcache.Cache
-        if let Some(kwargs) = kwargs {
-            // Support both size_limit (diskcache) and max_size (new API)
-            if let Ok(Some(size_limit)) = kwargs.get_item("size_limit") {
-                config.max_size = size_limit.extract::<Option<u64>>()?;
-            } else if let Ok(Some(max_size)) = kwargs.get_item("max_size") {
-                config.max_size = max_size.extract::<Option<u64>>()?;
+    /// Position and key of the item at one end of `range`, if it has any,
+    /// narrowing `range` past it
+    ///
+    /// Other keys sorting among the items, such as `"{prefix}-5x"`, are
+    /// skipped.
+    fn queue_edge(
+        &self,
+        prefix: &str,
+        side: QueueSide,
+        range: &mut QueueRange,
+    ) -> CacheResult<Option<(i64, String)>> {
+        loop {
+            let stored = self.storage().peek_key_in_range(
+                &range.start,
+                &range.end,
+                side == QueueSide::Back,
+            )?;
+            let Some(stored) = stored else {
+                return Ok(None);
+            };
+
+            let key = self.disk.get(&stored)?;
+            // The end is exclusive, and nothing sorts between a key and the
+            // key followed by a NUL
+            match side {
+                QueueSide::Back => range.end = stored,
+                QueueSide::Front => range.start = format!("{}\0", stored),
             }
-
-            // Support both count_limit (diskcache) and max_entries (new API)
-            if let Ok(Some(count_limit)) = kwargs.get_item("count_limit") {
-                config.max_entries = count_limit.extract::<Option<u64>>()?;
-            } else if let Ok(Some(max_entries)) = kwargs.get_item("max_entries") {
-                config.max_entries = max_entries.extract::<Option<u64>>()?;
+            let digits = key
+                .strip_prefix(prefix)
+                .and_then(|rest| rest.strip_prefix('-'))
+                .filter(|digits| {
+                    digits.len() == QUEUE_KEY_DIGITS && digits.bytes().all(|b| b.is_ascii_digit())
+                });
+            if let Some(position) = digits.and_then(|digits| digits.parse::<i64>().ok()) {
+                return Ok(Some((position - QUEUE_START, key)));
             }
+        }
+    }
 
-            // New configuration options for issue #17
-            if let Ok(Some(disk_write_threshold)) = kwargs.get_item("disk_write_threshold") {
-                config.disk_write_threshold = disk_write_threshold.extract::<usize>()?;
+    /// Extract an entry's bytes based on storage mode and decode them through the disk codec
+    fn read_entry_data(&self, entry: &CacheEntry) -> CacheResult<Vec<u8>> {
+        let data = match &entry.storage {
+            crate::serialization::StorageMode::Inline(data) => self.disk.fetch(data)?,
+            crate::serialization::StorageMode::File(filename) => {
+                let data = self.storage().read_data_file(filename)?;
+                self.disk.fetch(&data)?
             }
+        };
+        Ok(data)
+    }
 
-            if let Ok(Some(use_file_locking)) = kwargs.get_item("use_file_locking") {
-                config.use_file_locking = use_file_locking.extract::<bool>()?;
-            }
-        }
+    /// Set a value in the cache
+    pub fn set(
+        &self,
+        key: &str,
+        value: &[u8],
+        expire_time: Option<u64>,
+        tags: Vec<String>,
+    ) -> CacheResult<()> {
+        validate_key(key)?;
+        let key = &self.disk.put(key)?;
+        let value = self.disk.store(value)?;
 
-        let cache = DiskCache::new(config)?;
-        Ok(Self { cache })
+        // Always use inline storage for simplicity (OptimizedStorage handles the optimization)
+        self.store_entry(
+            key,
+            CacheEntry::new_inline(key.to_string(), value, tags, expire_time),
+        )
     }
 
-    fn get(
+    /// Set a value that costs `weight` to recompute
+    ///
+    /// The `gdsf` eviction policy keeps entries with a higher weight per
+    /// byte longer; `weight` is in whatever unit the caller weighs all its
+    /// entries in, such as seconds of compute, and entries set without one
+    /// weigh 1. Only this process's eviction sees it.
+    pub fn set_weighted(
         &self,
         key: &str,
-        default: Option<&Bound<'_, pyo3::PyAny>>,
-    ) -> PyResult<Option<Vec<u8>>> {
-        match self.cache.get(key)? {
-            Some(value) => Ok(Some(value)),
-            None => {
-                if let Some(default) = default {
-                    // Convert default to bytes if needed
-                    if let Ok(bytes) = default.extract::<Vec<u8>>() {
-                        Ok(Some(bytes))
-                    } else {
-                        Ok(None)
-                    }
-                } else {
-                    Ok(None)
-                }
-            }
-        }
+        value: &[u8],
+        expire_time: Option<u64>,
+        tags: Vec<String>,
+        weight: f64,
+    ) -> CacheResult<()> {
+        self.set_ranked(
+            key,
+            value,
+            expire_time,
+            tags,
+            Some(weight),
+            Priority::Normal,
+        )
     }
 
-    #[pyo3(signature = (key, value, expire=None, read=None, tag=None, retry=None))]
-    fn set(
+    /// Set a value evicted before or after the others, as `priority` says
+    ///
+    /// Within a priority the eviction policy decides. Only this process's
+    /// eviction sees it.
+    pub fn set_prioritized(
         &self,
         key: &str,
-        value: Py<PyAny>,
-        expire: Option<u64>,
-        read: Option<bool>,
-        tag: Option<String>,
-        retry: Option<bool>,
+        value: &[u8],
+        expire_time: Option<u64>,
+        tags: Vec<String>,
+        priority: Priority,
+    ) -> CacheResult<()> {
+        self.set_ranked(key, value, expire_time, tags, None, priority)
+    }
+
+    /// Set a value with what eviction ranks it by
+    fn set_ranked(
+        &self,
+        key: &str,
+        value: &[u8],
+        expire_time: Option<u64>,
+        tags: Vec<String>,
+        weight: Option<f64>,
+        priority: Priority,
+    ) -> CacheResult<()> {
+        validate_key(key)?;
+        let key = &self.disk.put(key)?;
+        let value = self.disk.store(value)?;
+
+        let mut entry = CacheEntry::new_inline(key.to_string(), value, tags, expire_time);
+        entry.weight = weight;
+        entry.priority = priority;
+        self.store_entry(key, entry)
+    }
+
+    /// Store an entry under an encoded key, keeping limits, eviction and stats up to date
+    fn store_entry(&self, key: &str, entry: CacheEntry) -> CacheResult<()> {
+        // Enforce cache size and entry limits
+        self.enforce_cache_limits(entry.size)?;
+
+        self.settle_legacy(key)?;
+        let existed = self.storage().exists_local(key)?;
+        self.eviction_queue.cancel(key);
+
+        // Store the entry metadata
+        self.storage().set(key, entry.clone())?;
+        self.record_store(key, &entry, existed);
+        self.enforce_tag_limits(&entry.tags, self.config.cull_limit)
+    }
+
+    /// Keep eviction, subscribers, the memory cache and stats up to date after storing `entry`
+    fn record_store(&self, key: &str, entry: &CacheEntry, existed: bool) {
+        self.eviction().on_insert(key, entry);
+        self.tag_quotas.on_store(key, &entry.tags, entry.size);
+        self.notify(ChangeKind::Set, key, &entry.tags);
+
+        // Store in memory cache
+        if let Some(ref memory_cache) = self.memory_cache {
+            memory_cache.put(key.to_string(), entry.clone());
+        }
+
+        // Update stats
+        let mut stats = self.stats.write();
+        stats.sets += 1;
+        stats.total_size += entry.size;
+        if !existed {
+            stats.entry_count += 1;
+        }
+    }
+
+    /// Set `key` to `value` only if its [`version`](Self::version) is still `expected`
+    ///
+    /// `None` expects the key to be absent, so the first writer of a key wins.
+    /// Returns whether the value was stored. Callers read a value and its
+    /// version with [`get_with_version`](Self::get_with_version), compute the
+    /// next value and retry from the read when this returns false; concurrent
+    /// writers of other keys are never held up. The comparison and the write
+    /// happen under the key's entry lock, so they are atomic against other
+    /// `cas` calls and [`lock_key`](Self::lock_key) holders in every process
+    /// sharing the cache, but not against plain `set`. Setting an alias swaps
+    /// its primary's entry.
+    pub fn cas(
+        &self,
+        key: &str,
+        expected: Option<u64>,
+        value: &[u8],
+        expire_time: Option<u64>,
+        tags: Vec<String>,
+    ) -> CacheResult<bool> {
+        validate_key(key)?;
+        let _lock = self.entry_locks.lock(key)?;
+        let mut stored = self.disk.put(key)?;
+        let value = self.disk.store(value)?;
+
+        self.enforce_cache_limits(value.len() as u64)?;
+        self.settle_legacy(&stored)?;
+        if !self.storage().exists_local(&stored)? {
+            if let Some(primary) = self.storage().resolve_alias(&stored)? {
+                stored = primary;
+            }
+        }
+
+        let entry = CacheEntry::new_inline(stored.clone(), value, tags, expire_time);
+        self.eviction_queue.cancel(&stored);
+        if !self
+            .storage()
+            .compare_and_set(&stored, expected, entry.clone())?
+        {
+            return Ok(false);
+        }
+        self.record_store(&stored, &entry, expected.is_some());
+        self.enforce_tag_limits(&entry.tags, self.config.cull_limit)?;
+        Ok(true)
+    }
+
+    /// Get the value of `key` together with its [`version`](Self::version)
+    ///
+    /// The two always belong to the same write, so the version can be passed
+    /// to [`cas`](Self::cas) to store a value computed from this one. A key
+    /// another process is rewriting can briefly read as absent; a `cas`
+    /// expecting it absent then fails, and the caller reads again.
+    pub fn get_with_version(&self, key: &str) -> CacheResult<Option<(Vec<u8>, u64)>> {
+        // Retry if the key is rewritten between reading the value and its version
+        loop {
+            let Some(version) = self.version(key)? else {
+                return Ok(None);
+            };
+            let Some(value) = self.get(key)? else {
+                // Expired, unless it was removed or rewritten in between
+                if self.version(key)? == Some(version) {
+                    return Ok(None);
+                }
+                continue;
+            };
+            if self.version(key)? == Some(version) {
+                return Ok(Some((value, version)));
+            }
+        }
+    }
+
+    /// Set multiple values in the cache using a batched storage path.
+    pub fn set_many(
+        &self,
+        items: Vec<(String, Vec<u8>)>,
+        expire_time: Option<u64>,
+        tags: Vec<String>,
+    ) -> CacheResult<()> {
+        if items.is_empty() {
+            return Ok(());
+        }
+
+        self.enforce_cache_limits(items.iter().map(|(_, value)| value.len() as u64).sum())?;
+
+        let mut storage_entries = Vec::with_capacity(items.len());
+        let mut cache_entries = Vec::with_capacity(items.len());
+        let mut total_size = 0_u64;
+        let mut new_entries = 0_u64;
+        let mut seen_keys = HashSet::with_capacity(items.len());
+
+        for (key, value) in items {
+            validate_key(&key)?;
+            let key = self.disk.put(&key)?;
+            let value = self.disk.store(&value)?;
+
+            self.settle_legacy(&key)?;
+            if seen_keys.insert(key.clone()) && !self.storage().exists_local(&key)? {
+                new_entries += 1;
+            }
+            self.eviction_queue.cancel(&key);
+
+            total_size += value.len() as u64;
+            storage_entries.push((key.clone(), value.clone()));
+            cache_entries.push(CacheEntry::new_inline(
+                key,
+                value,
+                tags.clone(),
+                expire_time,
+            ));
+        }
+
+        self.storage().set_batch(storage_entries)?;
+
+        for entry in &cache_entries {
+            self.eviction().on_insert(&entry.key, entry);
+            self.tag_quotas
+                .on_store(&entry.key, &entry.tags, entry.size);
+            if let Some(ref memory_cache) = self.memory_cache {
+                memory_cache.put(entry.key.clone(), entry.clone());
+            }
+            self.notify(ChangeKind::Set, &entry.key, &tags);
+        }
+
+        let mut stats = self.stats.write();
+        stats.sets += cache_entries.len() as u64;
+        stats.total_size += total_size;
+        stats.entry_count += new_entries;
+        drop(stats);
+
+        self.enforce_cache_limits(0)?;
+        self.enforce_tag_limits(&tags, self.config.cull_limit)?;
+
+        Ok(())
+    }
+
+    /// Delete a value from the cache
+    ///
+    /// With `soft_delete` on, the entry is kept in the trash for
+    /// [`DiskCache::undelete`] first.
+    pub fn delete(&self, key: &str) -> CacheResult<bool> {
+        validate_key(key)?;
+        let key = &self.disk.put(key)?;
+
+        self.settle_legacy(key)?;
+        if let Some(lease) = &self.lease {
+            // Before anything lands in the trash
+            lease.check_writable()?;
+        }
+        if let Some(trash) = &self.trash {
+            if let Some(entry) = self.storage().get(key)? {
+                let value = match &entry.storage {
+                    crate::serialization::StorageMode::Inline(data) => data.clone(),
+                    crate::serialization::StorageMode::File(filename) => {
+                        self.storage().read_data_file(filename)?
+                    }
+                };
+                trash.put(&TrashedEntry {
+                    key: key.clone(),
+                    value,
+                    tags: entry.tags,
+                    expire_time: entry.expire_time,
+                    deleted_at: current_timestamp(),
+                })?;
+            }
+        }
+        self.remove(key)
+    }
+
+    /// Remove the entry under an encoded key for good
+    fn remove(&self, key: &str) -> CacheResult<bool> {
+        let existed = self.storage().delete(key)?;
+        if existed {
+            self.eviction().on_remove(key);
+            self.tag_quotas.on_remove(key);
+            self.eviction_queue.cancel(key);
+            self.notify(ChangeKind::Delete, key, &[]);
+
+            // Remove from memory cache
+            if let Some(ref memory_cache) = self.memory_cache {
+                memory_cache.remove(key);
+            }
+
+            let mut stats = self.stats.write();
+            stats.deletes += 1;
+            stats.entry_count = stats.entry_count.saturating_sub(1);
+        } else {
+            // Deleting an alias removes only the alias, never the entry behind it
+            return self.storage().remove_alias(key);
+        }
+
+        Ok(existed)
+    }
+
+    /// Bring back an entry removed by [`DiskCache::delete`] within the
+    /// `soft_delete` grace period, returning whether it was restored
+    ///
+    /// A key written again since it was deleted keeps its new value, and the
+    /// copy in the trash is dropped.
+    pub fn undelete(&self, key: &str) -> CacheResult<bool> {
+        validate_key(key)?;
+        let Some(trash) = &self.trash else {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "soft_delete",
+                "Deleted entries are only kept with soft delete on",
+                "Set soft_delete to how long deleted entries should stay restorable",
+            )));
+        };
+        let key = &self.disk.put(key)?;
+
+        self.settle_legacy(key)?;
+        let Some(trashed) = trash.take(key)? else {
+            return Ok(false);
+        };
+        if self.storage().exists(key)? {
+            return Ok(false);
+        }
+        self.store_entry(
+            key,
+            CacheEntry::new_inline(
+                trashed.key,
+                trashed.value,
+                trashed.tags,
+                trashed.expire_time,
+            ),
+        )?;
+        Ok(true)
+    }
+
+    /// Check if a key, or an alias of a stored key, exists in the cache
+    pub fn exists(&self, key: &str) -> CacheResult<bool> {
+        validate_key(key)?;
+        let key = self.disk.put(key)?;
+        self.settle_legacy(&key)?;
+        if self.expire_if_idle(&key)? {
+            return Ok(false);
+        }
+        Ok(self.storage().exists(&key)? || self.storage().resolve_alias(&key)?.is_some())
+    }
+
+    /// Make `alias` a second name for `key`, without storing the value twice
+    ///
+    /// `key` may itself be an alias, in which case the new alias points at the
+    /// same entry. Lookups through the alias see every later rewrite of the
+    /// entry, and deleting the entry removes its aliases in the same
+    /// transaction. A stored key of the same name takes precedence over an alias.
+    pub fn alias(&self, alias: &str, key: &str) -> CacheResult<()> {
+        validate_key(alias)?;
+        validate_key(key)?;
+        let alias = self.disk.put(alias)?;
+        let mut key = self.disk.put(key)?;
+        self.settle_legacy(&key)?;
+        if !self.storage().exists_local(&key)? {
+            if let Some(primary) = self.storage().resolve_alias(&key)? {
+                key = primary;
+            }
+        }
+        self.storage().set_alias(&alias, &key)
+    }
+
+    /// Remove `alias`, returning whether it existed; the entry stays stored
+    pub fn unalias(&self, alias: &str) -> CacheResult<bool> {
+        validate_key(alias)?;
+        self.storage().remove_alias(&self.disk.put(alias)?)
+    }
+
+    /// Aliases that point at `key`
+    pub fn aliases(&self, key: &str) -> CacheResult<Vec<String>> {
+        validate_key(key)?;
+        self.storage()
+            .aliases(&self.disk.put(key)?)?
+            .iter()
+            .map(|alias| self.disk.get(alias))
+            .collect()
+    }
+
+    /// Get all keys in the cache
+    pub fn keys(&self) -> CacheResult<Vec<String>> {
+        self.storage()
+            .keys()?
+            .iter()
+            .map(|key| self.disk.get(key))
+            .collect()
+    }
+
+    /// One page of keys in store order and the cursor to fetch the next page with
+    ///
+    /// The cursor is `None` once iteration is complete.
+    pub fn key_page(
+        &self,
+        cursor: Option<i64>,
+        reverse: bool,
+        limit: usize,
+    ) -> CacheResult<(Vec<String>, Option<i64>)> {
+        let page = self.storage().key_page(cursor, reverse, limit)?;
+        let next = match page.last() {
+            Some((seq, _)) if page.len() == limit => Some(*seq),
+            _ => None,
+        };
+        let keys = page
+            .iter()
+            .map(|(_, key)| self.disk.get(key))
+            .collect::<CacheResult<_>>()?;
+        Ok((keys, next))
+    }
+
+    /// Lazily iterate keys in store order, oldest first unless `reverse`
+    pub fn iterkeys(&self, reverse: bool) -> impl Iterator<Item = CacheResult<String>> + '_ {
+        let mut cursor = None;
+        let mut page = std::collections::VecDeque::new();
+        let mut exhausted = false;
+        std::iter::from_fn(move || {
+            if page.is_empty() && !exhausted {
+                match self.key_page(cursor, reverse, KEY_PAGE_SIZE) {
+                    Ok((keys, next)) => {
+                        page.extend(keys);
+                        cursor = next;
+                        exhausted = next.is_none();
+                    }
+                    Err(e) => {
+                        exhausted = true;
+                        return Some(Err(e));
+                    }
+                }
+            }
+            page.pop_front().map(Ok)
+        })
+    }
+
+    /// Clear all entries from the cache
+    pub fn clear(&self) -> CacheResult<()> {
+        self.clear_with_progress(&|_| {})
+    }
+
+    /// Clear all entries, reporting progress while data files are removed
+    ///
+    /// `progress` is called from the removal workers, so it must not block for long.
+    pub fn clear_with_progress(
+        &self,
+        progress: &(dyn Fn(UnlinkProgress) + Sync),
+    ) -> CacheResult<()> {
+        self.storage().clear_with_progress(progress)?;
+        self.eviction().clear();
+        self.tag_quotas.clear();
+        self.eviction_queue.clear();
+        self.notify(ChangeKind::Clear, "", &[]);
+
+        // Clear memory cache
+        if let Some(ref memory_cache) = self.memory_cache {
+            memory_cache.clear();
+        }
+
+        let mut stats = self.stats.write();
+        *stats = CacheStats::new();
+
+        Ok(())
+    }
+
+    /// Get cache statistics
+    pub fn stats(&self) -> CacheStats {
+        let mut stats = self.stats.read().clone();
+        if let Some(sizes) = self.storage().size() {
+            stats.total_size = sizes.total();
+        }
+        stats.eviction_backlog = self.eviction_queue.backlog().entries;
+        stats
+    }
+
+    /// Victims queued for the background thread past `inline_evictions`,
+    /// not yet evicted
+    pub fn eviction_backlog(&self) -> EvictionBacklog {
+        self.eviction_queue.backlog()
+    }
+
+    /// Bytes, files and fsyncs the storage backend has written so far
+    pub fn io_stats(&self) -> IoStats {
+        self.storage().io_stats()
+    }
+
+    /// Counters added up over every process that has used this cache's
+    /// directory, this one included
+    ///
+    /// Each process writes its counters to the directory every few seconds
+    /// and folds them in for good when it closes the cache, so counts of
+    /// other live processes may lag a little, and those of a process that
+    /// died without closing are kept from its last write. Resetting counts
+    /// with [`DiskCache::hit_stats`] or [`DiskCache::clear`] resets this
+    /// process's share only. Caches without a directory count themselves.
+    pub fn aggregate_stats(&self) -> CacheResult<AggregateStats> {
+        let own = self.stat_counts();
+        match self.stats_shard.lock().as_ref() {
+            Some(shard) => shard.aggregate(own),
+            None => Ok(AggregateStats {
+                counts: own,
+                processes: 1,
+            }),
+        }
+    }
+
+    fn stat_counts(&self) -> StatCounts {
+        let io = self.io_stats();
+        StatCounts::new(&self.stats.read(), io)
+    }
+
+    /// Return `(hits, misses)`, then optionally zero them and switch counting on or off
+    ///
+    /// Mirrors python-diskcache's `Cache.stats(enable, reset)`: the returned counts
+    /// are the ones from before the reset, which also forgets the per-key counts
+    /// behind [`DiskCache::top_keys`]. Disabling only stops hit/miss and per-key
+    /// counting; size and entry accounting are always kept.
+    pub fn hit_stats(&self, enable: bool, reset: bool) -> (u64, u64) {
+        let mut stats = self.stats.write();
+        let counts = (stats.hits, stats.misses);
+        if reset {
+            stats.hits = 0;
+            stats.misses = 0;
+            self.popularity.lock().clear();
+        }
+        self.statistics.store(enable, Ordering::Relaxed);
+        counts
+    }
+
+    /// The `n` most hit keys with their approximate hit counts, most hit first
+    ///
+    /// Counts come from a fixed-size sketch, so they may run slightly high but
+    /// never low, and they decay by half every few tens of thousands of hits
+    /// to follow current traffic. Only the few hundred most hit keys are
+    /// tracked. Hits are counted while statistics are enabled.
+    pub fn top_keys(&self, n: usize) -> Vec<(String, u64)> {
+        self.popularity.lock().top(n)
+    }
+
+    fn record_hits(&self, keys: &[&str]) {
+        if !keys.is_empty() && self.statistics.load(Ordering::Relaxed) {
+            let mut popularity = self.popularity.lock();
+            for key in keys {
+                popularity.record(key);
+            }
+        }
+    }
+
+    fn record_lookups(&self, hits: u64, misses: u64) {
+        if self.statistics.load(Ordering::Relaxed) {
+            let mut stats = self.stats.write();
+            stats.hits += hits;
+            stats.misses += misses;
+        }
+    }
+
+    /// Project the footprint of a workload before deploying it
+    ///
+    /// `keys_per_day` new keys of `avg_value_size` bytes are assumed to live for
+    /// `ttl`; without a TTL nothing expires, so the projection covers
+    /// `ESTIMATE_HORIZON_DAYS` days of writes. Disk and memory figures come from
+    /// the storage backend's layout plus the eviction policy's per-key bookkeeping,
+    /// and recommended limits add 25% headroom.
+    pub fn estimate(
+        &self,
+        keys_per_day: u64,
+        avg_value_size: usize,
+        ttl: Option<std::time::Duration>,
+    ) -> CapacityEstimate {
+        const SECONDS_PER_DAY: u128 = 24 * 60 * 60;
+        let entries = match ttl {
+            Some(ttl) => {
+                let entries =
+                    (keys_per_day as u128 * ttl.as_secs() as u128).div_ceil(SECONDS_PER_DAY);
+                u64::try_from(entries).unwrap_or(u64::MAX)
+            }
+            None => keys_per_day.saturating_mul(ESTIMATE_HORIZON_DAYS),
+        };
+
+        let footprint =
+            self.storage()
+                .estimate_footprint(entries, ESTIMATE_KEY_SIZE, avg_value_size);
+        let eviction_bytes =
+            entries.saturating_mul(2 * ESTIMATE_KEY_SIZE as u64 + EVICTION_ENTRY_OVERHEAD);
+        let value_bytes = entries.saturating_mul(avg_value_size as u64);
+
+        let fits_current_limits = self.config.max_entries.is_none_or(|max| entries <= max)
+            && self.config.max_size.is_none_or(|max| value_bytes <= max);
+
+        CapacityEstimate {
+            entries,
+            disk_bytes: footprint.disk_bytes,
+            memory_bytes: footprint.memory_bytes.saturating_add(eviction_bytes),
+            recommended_max_entries: entries.saturating_add(entries.div_ceil(4)),
+            recommended_max_size: value_bytes.saturating_add(value_bytes.div_ceil(4)),
+            fits_current_limits,
+        }
+    }
+
+    /// Bytes of values stored in the cache
+    ///
+    /// Backends that count their live bytes report them exactly; for the
+    /// others this is an estimate from the bytes written so far.
+    pub fn size(&self) -> CacheResult<u64> {
+        match self.storage().size() {
+            Some(sizes) => Ok(sizes.total()),
+            None => Ok(self.stats.read().total_size),
+        }
+    }
+
+    /// Manually trigger vacuum operation
+    ///
+    /// Besides syncing writes and compacting, this reclaims data files the
+    /// index has lost track of and the space values no longer use at the end
+    /// of data files or in the middle of segment files; the report counts them.
+    pub fn vacuum(&self) -> CacheResult<VacuumReport> {
+        let report = self.storage().vacuum()?;
+        if let Some(trash) = &self.trash {
+            trash.purge()?;
+        }
+        *self.last_vacuum.write() = current_timestamp();
+        Ok(report)
+    }
+
+    /// Describe the cache directory and how it was last shut down
+    pub fn info(&self) -> CacheInfo {
+        CacheInfo {
+            directory: self.config.directory.clone(),
+            was_unclean_shutdown: self.storage().was_unclean_shutdown(),
+            last_recovery: self.last_recovery.read().clone(),
+        }
+    }
+
+    /// Check persisted entries and drop the ones that can no longer be served
+    pub fn verify_and_recover(&self) -> CacheResult<RecoveryReport> {
+        let report = self.storage().verify_and_recover()?;
+        *self.last_recovery.write() = Some(report.clone());
+        Ok(report)
+    }
+
+    /// Rewrite absolute data file paths left by older versions as paths relative
+    /// to the cache directory, returning how many index entries changed
+    ///
+    /// Needed only for caches written before the index stored relative paths;
+    /// once relocated the directory can be moved or copied freely.
+    pub fn relocate(&self) -> CacheResult<u64> {
+        match self
+            .storage()
+            .as_any()
+            .downcast_ref::<crate::storage::optimized_backend::OptimizedStorage>()
+        {
+            Some(optimized_storage) => optimized_storage.relocate(),
+            None => Ok(0),
+        }
+    }
+
+    /// Write a consistent point-in-time copy of the cache to `destination`
+    ///
+    /// The copy can be opened as a cache of its own. Data files are
+    /// hard-linked where possible, so a snapshot of a large cache on the same
+    /// filesystem takes little time or space; readers and writers are held up
+    /// only while the index is copied. Optimized backend only.
+    pub fn snapshot(&self, destination: &Path) -> CacheResult<SnapshotReport> {
+        match self
+            .storage()
+            .as_any()
+            .downcast_ref::<crate::storage::optimized_backend::OptimizedStorage>()
+        {
+            Some(optimized_storage) => optimized_storage.snapshot(destination),
+            None => Err(CacheError::Config(ConfigIssue::new(
+                "backend",
+                "Only the optimized backend can take snapshots",
+                "Copy the directory while no process has the cache open, or use the optimized backend",
+            ))),
+        }
+    }
+
+    /// Move every entry into a new `target` backend in the same directory and
+    /// switch the cache over to it, returning the number of entries moved
+    ///
+    /// The new backend is built under `.migrating/` and other calls on this
+    /// cache wait until the switch is done. Entries keep their tags,
+    /// expiration times and store order; aliases and version history are not
+    /// carried over. Once every entry is copied, a switch cut short by a crash
+    /// is finished the next time the directory is opened. No other process
+    /// may have the directory open, and it must be reopened with `target` as
+    /// its backend from then on. Entries of the old per-file `FileStorage`
+    /// format are moved into the current backend first.
+    pub fn migrate_backend(&self, target: StorageKind) -> CacheResult<u64> {
+        let mut target_config = self.config.clone();
+        target_config.backend = target;
+        validate_cache_config(&target_config)?;
+        if !target.uses_directory() || !self.config.backend.uses_directory() {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "backend",
+                "Memory caches and clients of a cache server have no directory to migrate to or from",
+                "Export the entries with export() and import them into the other cache instead",
+            )));
+        }
+        if self.config.designated_writer {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "designated_writer",
+                "Writes forwarded to a designated writer cannot follow a backend switch",
+                "Open the cache without designated_writer to migrate it",
+            )));
+        }
+        if self.config.writer_lease.is_some() {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "writer_lease",
+                "The writer lease guards the backend the cache was opened with",
+                "Open the cache without writer_lease to migrate it",
+            )));
+        }
+        if let Some(legacy) = &self.legacy {
+            legacy.wait();
+        }
+
+        let mut active = self.storage.write();
+        if active.kind == target {
+            return Ok(0);
+        }
+        let directory = &self.config.directory;
+        let staging = directory.join(BACKEND_STAGING_DIR);
+        match std::fs::remove_dir_all(&staging) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(CacheError::Io(e)),
+        }
+        std::fs::create_dir_all(&staging)?;
+
+        let staged = open_storage(&self.config, target, &staging)?;
+        let copied = copy_entries(active.backend.as_ref(), staged.as_ref());
+        close_storage(staged.as_ref());
+        drop(staged);
+        let moved = match copied {
+            Ok(moved) => moved,
+            Err(e) => {
+                let _ = std::fs::remove_dir_all(&staging);
+                return Err(e);
+            }
+        };
+
+        mark_backend_staged(directory, active.kind, target)?;
+        // Clearing removes the data files of layouts finish_backend_switch
+        // cannot name, such as the sqlite backend's value files
+        active.backend.clear()?;
+        close_storage(active.backend.as_ref());
+        finish_backend_switch(directory)?;
+        cache_meta::record_backend(
+            directory,
+            target,
+            self.config.file_naming,
+            &self.config.codecs(),
+        )?;
+        active.backend = open_storage(&self.config, target, directory)?;
+        active.kind = target;
+        self.hook_storage(active.backend.as_ref());
+        tracing::info!(
+            "Moved {} entries in {:?} to the {} backend",
+            moved,
+            directory,
+            target.name()
+        );
+        Ok(moved)
+    }
+
+    /// Write every live entry, with its tags and expiration time, to the
+    /// archive file at `path`, returning the number of entries written
+    ///
+    /// `metadata` may fill in tags and expiration times the backend does not
+    /// keep. Expired entries are left out. The archive replaces `path` only
+    /// once it is complete.
+    pub fn export(&self, path: &Path, metadata: &dyn Fn(&mut ArchiveEntry)) -> CacheResult<u64> {
+        let now = current_timestamp();
+        let mut archive = ArchiveWriter::create(path)?;
+        for stored_key in self.storage().keys()? {
+            // Deleted since the keys were listed
+            let Some((_, entry)) = self.lookup(&stored_key, false)? else {
+                continue;
+            };
+            let mut archived = ArchiveEntry {
+                key: self.disk.get(&stored_key)?,
+                value: self.read_entry_data(&entry)?,
+                tags: entry.tags,
+                expire_time: entry.expire_time,
+            };
+            metadata(&mut archived);
+            if archived
+                .expire_time
+                .is_some_and(|expire_time| expire_time <= now)
+            {
+                continue;
+            }
+            archive.append(archived)?;
+        }
+        archive.finish(path)
+    }
+
+    /// Load the entries of an archive written by [`DiskCache::export`],
+    /// returning the number imported
+    ///
+    /// Entries replace any under the same keys; those that have expired since
+    /// the export are skipped. `imported` sees each entry once it is stored.
+    /// A damaged archive is refused with [`CacheError::Corruption`] when it
+    /// is found, which may be after some entries were imported.
+    pub fn import_archive(
+        &self,
+        path: &Path,
+        imported: &mut dyn FnMut(&ArchiveEntry),
+    ) -> CacheResult<u64> {
+        /// Entries stored with one batched write
+        const IMPORT_BATCH: usize = 512;
+
+        let now = current_timestamp();
+        let mut count = 0;
+        let mut batch: Vec<ArchiveEntry> = Vec::with_capacity(IMPORT_BATCH);
+        let mut flush = |batch: &mut Vec<ArchiveEntry>| -> CacheResult<()> {
+            let Some(first) = batch.first() else {
+                return Ok(());
+            };
+            let (expire_time, tags) = (first.expire_time, first.tags.clone());
+            let items = batch
+                .iter()
+                .map(|entry| (entry.key.clone(), entry.value.clone()))
+                .collect();
+            self.set_many(items, expire_time, tags)?;
+            for entry in batch.drain(..) {
+                imported(&entry);
+                count += 1;
+            }
+            Ok(())
+        };
+
+        for entry in ArchiveReader::open(path)? {
+            let entry = entry?;
+            if entry
+                .expire_time
+                .is_some_and(|expire_time| expire_time <= now)
+            {
+                continue;
+            }
+            // A batch shares one expiration time and one set of tags
+            let fits = batch.first().is_none_or(|first| {
+                first.expire_time == entry.expire_time && first.tags == entry.tags
+            });
+            if !fits || batch.len() == IMPORT_BATCH {
+                flush(&mut batch)?;
+            }
+            batch.push(entry);
+        }
+        flush(&mut batch)?;
+        Ok(count)
+    }
+
+    /// Store the file at `path` as the value of `key` without reading it
+    ///
+    /// The file is hard-linked into the cache directory, or copied when it
+    /// lives on another filesystem. A linked file shares its contents with the
+    /// original, so the original must not be modified in place afterwards;
+    /// replacing it by rename or deleting it does not affect the cache.
+    pub fn link_file(&self, key: &str, path: &Path) -> CacheResult<()> {
+        validate_key(key)?;
+        if !self.disk.stores_verbatim() {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "disk",
+                "linked files are stored as-is, bypassing this disk codec",
+                "link files into a cache using the default disk",
+            )));
+        }
+        let key = &self.disk.put(key)?;
+
+        self.enforce_cache_limits(std::fs::metadata(path).map_or(0, |metadata| metadata.len()))?;
+
+        self.settle_legacy(key)?;
+        let existed = self.storage().exists_local(key)?;
+        self.eviction_queue.cancel(key);
+        let size = self.storage().link_file(key, path)?;
+
+        let entry = CacheEntry::new_file(
+            key.to_string(),
+            path.to_string_lossy().into_owned(),
+            size,
+            vec![],
+            None,
+        );
+        self.eviction().on_insert(key, &entry);
+        self.tag_quotas.on_store(key, &entry.tags, entry.size);
+        self.notify(ChangeKind::Set, key, &[]);
+        if let Some(ref memory_cache) = self.memory_cache {
+            memory_cache.remove(key);
+        }
+
+        let mut stats = self.stats.write();
+        stats.sets += 1;
+        stats.total_size += size;
+        if !existed {
+            stats.entry_count += 1;
+        }
+        Ok(())
+    }
+
+    /// Start writing `key`'s value a piece at a time, for values larger than memory
+    ///
+    /// Write the value to the returned writer, then [`ValueWriter::commit`] it.
+    /// The value is stored uncompressed in a data file of its own and can be
+    /// read back in chunks with [`DiskCache::read_stream`].
+    pub fn write_stream(&self, key: &str) -> CacheResult<ValueWriter<'_>> {
+        let (key, file) = self.begin_stream(key)?;
+        Ok(ValueWriter {
+            cache: self,
+            key,
+            file,
+        })
+    }
+
+    /// Encoded key and storage writer for streaming `key`'s value
+    fn begin_stream(&self, key: &str) -> CacheResult<(String, DataFileWriter)> {
+        validate_key(key)?;
+        if !self.disk.stores_verbatim() {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "disk",
+                "streamed values are stored as-is, bypassing this disk codec",
+                "stream values into a cache using the default disk",
+            )));
+        }
+        let key = self.disk.put(key)?;
+        let file = self.storage().write_stream(&key)?;
+        Ok((key, file))
+    }
+
+    /// Store a value streamed for the encoded `key`
+    fn commit_stream(&self, key: &str, file: DataFileWriter) -> CacheResult<u64> {
+        self.enforce_cache_limits(file.payload_len())?;
+
+        self.settle_legacy(key)?;
+        let existed = self.storage().exists_local(key)?;
+        self.eviction_queue.cancel(key);
+        let size = self.storage().commit_stream(file)?;
+
+        let entry = CacheEntry::new_file(
+            key.to_string(),
+            self.storage().generate_filename(key),
+            size,
+            vec![],
+            None,
+        );
+        self.eviction().on_insert(key, &entry);
+        self.tag_quotas.on_store(key, &entry.tags, entry.size);
+        self.notify(ChangeKind::Set, key, &[]);
+        if let Some(ref memory_cache) = self.memory_cache {
+            memory_cache.remove(key);
+        }
+
+        let mut stats = self.stats.write();
+        stats.sets += 1;
+        stats.total_size += size;
+        if !existed {
+            stats.entry_count += 1;
+        }
+        Ok(size)
+    }
+
+    /// Serve the files listed in a manifest through the cache, without copying them
+    ///
+    /// The manifest is a JSON object mapping keys to file paths, relative to
+    /// the manifest's own directory, as written next to an asset bundle at
+    /// build time. Each key reads back as its file's bytes. The files are never
+    /// modified or removed by the cache, so deleting a key only forgets it.
+    /// Returns the number of keys registered.
+    pub fn load_manifest(&self, manifest: &Path) -> CacheResult<u64> {
+        if !self.disk.stores_verbatim() {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "disk",
+                "manifest files are served as-is, which this disk codec cannot decode",
+                "load manifests into a cache using the default disk",
+            )));
+        }
+        let content = std::fs::read(manifest)?;
+        let listed: BTreeMap<String, PathBuf> = serde_json::from_slice(&content).map_err(|e| {
+            CacheError::Deserialization(format!("Invalid manifest {:?}: {}", manifest, e))
+        })?;
+        let base = manifest.parent().unwrap_or_else(|| Path::new(""));
+
+        let mut files = Vec::with_capacity(listed.len());
+        let mut new_entries = 0_u64;
+        for (key, path) in listed {
+            validate_key(&key)?;
+            let key = self.disk.put(&key)?;
+            self.settle_legacy(&key)?;
+            if !self.storage().exists_local(&key)? {
+                new_entries += 1;
+            }
+            self.eviction_queue.cancel(&key);
+            files.push((key, base.join(path)));
+        }
+        let registered = self.storage().register_files(&files)?;
+
+        let mut total_size = 0_u64;
+        for (key, path) in &files {
+            let size = std::fs::metadata(path).map_or(0, |metadata| metadata.len());
+            total_size += size;
+            let entry = CacheEntry::new_file(
+                key.clone(),
+                path.to_string_lossy().into_owned(),
+                size,
+                vec![],
+                None,
+            );
+            self.eviction().on_insert(key, &entry);
+            self.tag_quotas.on_store(key, &entry.tags, entry.size);
+            if let Some(ref memory_cache) = self.memory_cache {
+                memory_cache.remove(key);
+            }
+            self.notify(ChangeKind::Set, key, &[]);
+        }
+
+        let mut stats = self.stats.write();
+        stats.sets += registered;
+        stats.total_size += total_size;
+        stats.entry_count += new_entries;
+        Ok(registered)
+    }
+
+    /// Close the cache and release resources (especially redb database lock)
+    pub fn close(&self) {
+        if let Some(legacy) = &self.legacy {
+            legacy.stop();
+        }
+        #[cfg(unix)]
+        if let Some(writer) = &self.writer {
+            writer.close();
+        }
+        if let Some(lease) = &self.lease {
+            lease.close();
+        }
+        if let Some(maintenance) = &self.maintenance {
+            maintenance.close();
+        }
+        // Victims still queued are evicted before the storage closes under
+        // the background thread
+        for key in self.eviction_queue.drain() {
+            if let Err(e) = self.evict_key(&key) {
+                tracing::warn!("Failed to evict {} on close: {}", key, e);
+            }
+        }
+        self.checkpoint_eviction();
+        close_storage(self.storage().as_ref());
+        if let Some(shard) = self.stats_shard.lock().take() {
+            if let Err(e) = shard.retire(self.stat_counts()) {
+                tracing::warn!("Failed to save statistics: {}", e);
+            }
+        }
+        drop(self.registration.lock().take());
+    }
+
+    /// Check cache limits and evict entries if necessary, making room for
+    /// `incoming` bytes about to be stored
+    fn enforce_cache_limits(&self, incoming: u64) -> CacheResult<()> {
+        // Entries gone idle make room before anything is evicted
+        self.expire_idle_entries(IDLE_SWEEP_BATCH)?;
+
+        let current_size = self.size()?;
+        let current_entries = self.stats.read().entry_count;
+        // Room the background thread is already making
+        let backlog = self.eviction_queue.backlog();
+
+        let mut evict_count = 0;
+
+        // Check entry count limit
+        if let Some(max_entries) = self.config.max_entries {
+            if current_entries > max_entries {
+                evict_count = (current_entries - max_entries + (max_entries / 10))
+                    .saturating_sub(backlog.entries);
+            }
+        }
+
+        // Check size limit: enough victims to bring the bytes stored, the
+        // incoming ones included, back under it
+        let reclaim = self.config.max_size.map_or(0, |max_size| {
+            current_size
+                .saturating_add(incoming)
+                .saturating_sub(max_size)
+                .saturating_sub(backlog.bytes)
+        });
+
+        // Like python-diskcache, each write culls at most `cull_limit` entries
+        // for the entry count, but as many as the bytes need, so `max_size`
+        // holds even for a write larger than `cull_limit` small entries
+        let cull_limit = self.config.cull_limit;
+        if cull_limit > 0 && (evict_count > 0 || reclaim > 0) {
+            let count = evict_count.min(cull_limit as u64) as usize;
+            let mut victims = if backlog.entries == 0 {
+                self.eviction().select_victims(count)
+            } else {
+                self.eviction()
+                    .select_victims_among(count, &|key| !self.eviction_queue.contains(key))
+            };
+            if reclaim > 0 {
+                // Queued keys come first by size too, and are skipped
+                let mut by_size = self
+                    .eviction()
+                    .select_victims_by_size(reclaim.saturating_add(backlog.bytes));
+                if backlog.entries > 0 {
+                    by_size.retain(|key| !self.eviction_queue.contains(key));
+                }
+                if by_size.len() > victims.len() {
+                    victims = by_size;
+                }
+            }
+            let inline = self
+                .config
+                .inline_evictions
+                .map_or(victims.len(), |inline| inline.min(victims.len()));
+            let queued = victims.split_off(inline);
+            for key in victims {
+                self.evict_key(&key)?;
+            }
+            self.queue_evictions(queued)?;
+        }
+
+        let last_vacuum = *self.last_vacuum.read();
+        if current_timestamp() - last_vacuum > self.maintenance_interval() {
+            self.maintain()?;
+        }
+
+        Ok(())
+    }
+
+    /// Evict the entry under an encoded key
+    fn evict_key(&self, key: &str) -> CacheResult<()> {
+        evict_entry(
+            self.storage().as_ref(),
+            &*self.eviction(),
+            &self.tag_quotas,
+            &self.notifier,
+            &self.stats,
+            key,
+        )
+    }
+
+    /// Leave encoded `keys` to the background thread to evict
+    fn queue_evictions(&self, keys: Vec<String>) -> CacheResult<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let victims = keys
+            .into_iter()
+            .map(|key| {
+                let size = self.eviction().entry_size(&key).unwrap_or(0);
+                (key, size)
+            })
+            .collect();
+        self.eviction_queue
+            .push(victims, || self.background_evictor())
+    }
+
+    /// What the background thread evicts keys with; it holds on to nothing
+    /// the cache would otherwise drop
+    ///
+    /// Each key is claimed under its entry lock, so a key stored again since
+    /// it was queued is left alone, and `cas` or a
+    /// [`lock_key`](Self::lock_key) holder never sees it evicted midway.
+    fn background_evictor(&self) -> Evict {
+        let entry_locks = Arc::downgrade(&self.entry_locks);
+        let storage = Arc::downgrade(&self.storage);
+        let eviction = Arc::downgrade(&self.eviction);
+        let tag_quotas = Arc::downgrade(&self.tag_quotas);
+        let notifier = Arc::downgrade(&self.notifier);
+        let stats = Arc::downgrade(&self.stats);
+        Box::new(move |key, claim| {
+            let Some(entry_locks) = entry_locks.upgrade() else {
+                return Ok(());
+            };
+            let _lock = entry_locks.lock(key)?;
+            if !claim() {
+                return Ok(());
+            }
+            let (Some(storage), Some(eviction), Some(tag_quotas), Some(notifier), Some(stats)) = (
+                storage.upgrade(),
+                eviction.upgrade(),
+                tag_quotas.upgrade(),
+                notifier.upgrade(),
+                stats.upgrade(),
+            ) else {
+                return Ok(());
+            };
+            let backend = storage.read_recursive().backend.clone();
+            let eviction = eviction.read_recursive();
+            evict_entry(
+                backend.as_ref(),
+                eviction.policy.as_ref(),
+                &tag_quotas,
+                &notifier,
+                &stats,
+                key,
+            )
+        })
+    }
+
+    /// Evict at most `cull_limit` entries from each of `tags` over its limit
+    fn enforce_tag_limits(&self, tags: &[String], cull_limit: usize) -> CacheResult<()> {
+        if cull_limit == 0 {
+            return Ok(());
+        }
+        for tag in tags {
+            if let Some(excess) = self.tag_quotas.excess(tag) {
+                for key in self.tag_victims(tag, excess).into_iter().take(cull_limit) {
+                    self.evict_key(&key)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The fewest of `tag`'s keys whose eviction brings it back under its
+    /// limit, in the order the eviction policy picks them
+    ///
+    /// Keys the policy does not track, as with no eviction policy at all,
+    /// follow in the order they were stored; pinned keys are never picked.
+    fn tag_victims(&self, tag: &str, excess: TagExcess) -> Vec<String> {
+        let mut wanted = usize::try_from(excess.entries).unwrap_or(usize::MAX).max(1);
+        loop {
+            let mut victims = self
+                .eviction()
+                .select_victims_among(wanted, &|key| self.tag_quotas.size(tag, key).is_some());
+            let exhausted = victims.len() < wanted;
+            if exhausted {
+                let picked: HashSet<String> = victims.iter().cloned().collect();
+                let pins = self.pins.lock();
+                victims.extend(
+                    self.tag_quotas
+                        .keys(tag)
+                        .into_iter()
+                        .filter(|key| !picked.contains(key) && !pins.contains(key)),
+                );
+            }
+
+            let mut freed = 0u64;
+            let enough = victims.iter().enumerate().position(|(index, key)| {
+                freed = freed.saturating_add(self.tag_quotas.size(tag, key).unwrap_or(0));
+                index as u64 + 1 >= excess.entries && freed >= excess.bytes
+            });
+            if let Some(last) = enough {
+                victims.truncate(last + 1);
+                return victims;
+            }
+            if exhausted {
+                return victims;
+            }
+            wanted = wanted.saturating_mul(4);
+        }
+    }
+
+    /// Seconds between automatic maintenance runs: an hour, or half the
+    /// maintenance lease period if that is shorter, so its holder renews it
+    /// in time
+    fn maintenance_interval(&self) -> u64 {
+        match &self.maintenance {
+            Some(maintenance) => {
+                (maintenance.period().as_secs() / 2).clamp(1, MAINTENANCE_INTERVAL)
+            }
+            None => MAINTENANCE_INTERVAL,
+        }
+    }
+
+    /// Sweep out expired entries and vacuum, unless another process leads
+    /// maintenance
+    fn maintain(&self) -> CacheResult<()> {
+        *self.last_vacuum.write() = current_timestamp();
+        self.checkpoint_eviction();
+        let leads = match &self.maintenance {
+            Some(maintenance) => maintenance.claim()?,
+            None => true,
+        };
+        if !leads {
+            return Ok(());
+        }
+        let expired =
+            self.storage().remove_expired()? + self.expire_idle_entries(usize::MAX)? as u64;
+        if expired > 0 {
+            tracing::debug!("Swept out {} expired entries", expired);
+        }
+        self.vacuum()?;
+        Ok(())
+    }
+
+    /// Whether eviction state is kept in the directory across restarts
+    fn persists_eviction(&self) -> bool {
+        self.config.backend.uses_directory()
+            && !matches!(self.eviction_strategy(), EvictionStrategy::None)
+    }
+
+    /// Save the order eviction would pick keys in, so the next open starts
+    /// from it instead of from scratch
+    ///
+    /// Every process saves its own view, so the last to save wins.
+    fn checkpoint_eviction(&self) {
+        if !self.persists_eviction() || self.is_read_only() {
+            return;
+        }
+        if let Err(e) = save_checkpoint(&self.config.directory, &self.eviction().checkpoint()) {
+            tracing::warn!("Failed to save eviction state: {}", e);
+        }
+    }
+
+    /// Track the keys already stored, in the order last saved
+    ///
+    /// Keys stored since, by other processes or before the state was saved
+    /// at all, are the least known and go first.
+    fn restore_eviction(&self) -> CacheResult<()> {
+        if !self.persists_eviction() {
+            return Ok(());
+        }
+        let keys = self.tracked_keys(load_checkpoint(&self.config.directory))?;
+        self.eviction().restore(&keys);
+        Ok(())
+    }
+
+    /// The stored keys missing from `saved`, then those of `saved` still
+    /// stored, in the order they were saved
+    fn tracked_keys(&self, saved: Vec<TrackedKey>) -> CacheResult<Vec<TrackedKey>> {
+        let stored = self.storage().keys()?;
+        let known: HashSet<&str> = saved.iter().map(|tracked| tracked.key.as_str()).collect();
+        let mut keys: Vec<TrackedKey> = stored
+            .iter()
+            .filter(|key| !known.contains(key.as_str()))
+            .map(|key| TrackedKey::new(key.clone()))
+            .collect();
+        let stored: HashSet<String> = stored.into_iter().collect();
+        keys.extend(
+            saved
+                .into_iter()
+                .filter(|tracked| stored.contains(&tracked.key)),
+        );
+        Ok(keys)
+    }
+
+    /// Pick victims by `strategy` from now on, without reopening the cache
+    ///
+    /// The new policy starts from the keys the current one tracks, in its
+    /// order and with the hit counts it keeps, if any; stored keys it does
+    /// not track go first. Other calls on this cache wait until the switch is
+    /// done; keys not seen since the cache was opened lose their idle time.
+    /// Like pins, the switch belongs to this handle: other processes keep
+    /// their own strategy, and reopening uses the configured one.
+    pub fn set_eviction_strategy(&self, strategy: EvictionStrategy) -> CacheResult<()> {
+        let mut config = self.config.clone();
+        config.eviction_strategy = strategy;
+        validate_cache_config(&config)?;
+
+        let mut active = self.eviction.write();
+        if active.strategy == strategy {
+            return Ok(());
+        }
+        let policy = eviction_policy(&config);
+        policy.restore(&self.tracked_keys(active.policy.checkpoint())?);
+        for key in self.pins.lock().iter() {
+            policy.set_pinned(key, true);
+        }
+        *active = ActiveEviction { strategy, policy };
+        Ok(())
+    }
+
+    /// Whether this process runs the automatic vacuum, expiry sweep and
+    /// compaction: always without `maintenance_lease`, else while it holds
+    /// the lease
+    pub fn is_maintenance_leader(&self) -> bool {
+        self.maintenance
+            .as_ref()
+            .is_none_or(|maintenance| maintenance.is_held())
+    }
+
+    /// Move `key`'s entry over from a legacy entry file first, if the
+    /// background migration has not reached it yet
+    fn settle_legacy(&self, key: &str) -> CacheResult<()> {
+        match &self.legacy {
+            Some(legacy) => legacy.settle(key),
+            None => Ok(()),
+        }
+    }
+
+    /// Wait until the entry files of the old per-file backend found on open
+    /// have all been moved into storage
+    pub fn wait_for_legacy_migration(&self) {
+        if let Some(legacy) = &self.legacy {
+            legacy.wait();
+        }
+    }
+
+    /// Automatically migrate existing diskcache data if detected
+    fn migrate_existing_data(&mut self) -> CacheResult<()> {
+        if detect_diskcache_format(&self.config.directory) {
+            tracing::info!("Detected python-diskcache data, starting auto-migration...");
+
+            // Create a backup first
+            let backup_dir = self.config.directory.join("diskcache_backup");
+            if !backup_dir.exists() {
+                let cache_db = self.config.directory.join("cache.db");
+                let backup_db = backup_dir.join("cache.db");
+                std::fs::create_dir_all(&backup_dir)?;
+                std::fs::copy(&cache_db, &backup_db)?;
+                tracing::info!("Created backup at: {:?}", backup_dir);
+            }
+
+            // Perform migration using OptimizedStorage
+            let mut migrator = DiskCacheMigrator::new(
+                self.config.directory.clone(),
+                Box::new(OptimizedStorage::new(&self.config.directory)?),
+            );
+
+            match migrator.migrate() {
+                Ok(stats) => {
+                    tracing::info!("Migration completed: {:?}", stats);
+                    if stats.success {
+                        // Rename the original database to avoid future migrations
+                        let cache_db = self.config.directory.join("cache.db");
+                        let migrated_db = self.config.directory.join("cache.db.migrated");
+                        if cache_db.exists() && !migrated_db.exists() {
+                            std::fs::rename(&cache_db, &migrated_db)?;
+                        }
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("Migration failed: {}", e);
+                    // Continue without migration
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get memory cache statistics
+    pub fn memory_stats(&self) -> Option<crate::memory_cache::MemoryCacheStats> {
+        self.memory_cache.as_ref().map(|mc| mc.stats())
+    }
+
+    /// Manually migrate from python-diskcache
+    pub fn migrate_from_diskcache(&mut self) -> CacheResult<crate::migration::MigrationStats> {
+        let mut migrator = DiskCacheMigrator::new(
+            self.config.directory.clone(),
+            Box::new(OptimizedStorage::new(&self.config.directory)?),
+        );
+
+        migrator.migrate()
+    }
+}
+
+/// Adapter exposing a Python object with `put/get/store/fetch` methods as a [`Disk`]
+///
+/// `store` and `fetch` are required and work on bytes; `put` and `get` are
+/// optional key transforms that default to the identity.
+struct PyDisk {
+    codec: Py<PyAny>,
+}
+
+impl PyDisk {
+    fn new(codec: &Bound<'_, PyAny>) -> PyResult<Self> {
+        for method in ["store", "fetch"] {
+            if !codec.hasattr(method)? {
+                return Err(pyo3::exceptions::PyTypeError::new_err(format!(
+                    "disk codec must define a '{}' method",
+                    method
+                )));
+            }
+        }
+        Ok(Self {
+            codec: codec.clone().unbind(),
+        })
+    }
+
+    fn call<T>(&self, method: &str, arg: impl for<'py> IntoPyObject<'py>) -> CacheResult<Option<T>>
+    where
+        T: for<'a, 'py> FromPyObject<'a, 'py>,
+    {
+        Python::attach(|py| {
+            let codec = self.codec.bind(py);
+            if !codec.hasattr(method)? {
+                return Ok(None);
+            }
+            codec
+                .call_method1(method, (arg,))?
+                .extract::<T>()
+                .map(Some)
+                .map_err(Into::into)
+        })
+        .map_err(|e: PyErr| {
+            crate::error::CacheError::Serialization(format!("disk.{}() failed: {}", method, e))
+        })
+    }
+}
+
+impl Disk for PyDisk {
+    fn put(&self, key: &str) -> CacheResult<String> {
+        Ok(self.call("put", key)?.unwrap_or_else(|| key.to_string()))
+    }
+
+    fn get(&self, key: &str) -> CacheResult<String> {
+        Ok(self.call("get", key)?.unwrap_or_else(|| key.to_string()))
+    }
+
+    fn store(&self, value: &[u8]) -> CacheResult<Vec<u8>> {
+        Ok(self.call("store", value)?.unwrap_or_default())
+    }
+
+    fn fetch(&self, data: &[u8]) -> CacheResult<Vec<u8>> {
+        Ok(self.call("fetch", data)?.unwrap_or_default())
+    }
+}
+
+/// Resolve the `disk` constructor argument into a codec
+///
+/// Accepts `None`/`"raw"`, `"json"`, or any object implementing the
+/// `store`/`fetch` (and optionally `put`/`get`) protocol.
+fn resolve_disk(disk: Option<&Bound<'_, PyAny>>) -> PyResult<Arc<dyn Disk>> {
+    let Some(disk) = disk.filter(|d| !d.is_none()) else {
+        return Ok(Arc::new(RawDisk));
+    };
+
+    if let Ok(name) = disk.extract::<String>() {
+        return match name.to_ascii_lowercase().as_str() {
+            "raw" => Ok(Arc::new(RawDisk)),
+            "json" => Ok(Arc::new(JsonDisk::default())),
+            other => Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "Unknown disk codec '{}', expected 'raw', 'json' or a codec object",
+                other
+            ))),
+        };
+    }
+
+    Ok(Arc::new(PyDisk::new(disk)?))
+}
+
+/// Python wrapper for the Cache
+#[pyclass]
+pub struct PyCache {
+    cache: Arc<DiskCache>,
+}
+
+/// Writable binary file streaming one value into the cache, returned by
+/// `PyCache.open_write`
+///
+/// `close()` stores the value. Leaving a `with` block by an exception, or
+/// calling `discard()`, drops it and keeps the key's previous value.
+#[pyclass]
+pub struct PyValueWriter {
+    cache: Arc<DiskCache>,
+    key: String,
+    file: Mutex<Option<DataFileWriter>>,
+}
+
+#[pymethods]
+impl PyValueWriter {
+    /// Append `data` to the value, returning the number of bytes written
+    fn write(&self, py: Python<'_>, data: &[u8]) -> PyResult<usize> {
+        py.detach(|| match self.file.lock().as_mut() {
+            Some(file) => file.write_all(data).map_err(CacheError::Io),
+            None => Err(CacheError::Io(std::io::Error::other(
+                "write to a closed value writer",
+            ))),
+        })?;
+        Ok(data.len())
+    }
+
+    fn flush(&self, py: Python<'_>) -> PyResult<()> {
+        py.detach(|| match self.file.lock().as_mut() {
+            Some(file) => file.flush().map_err(CacheError::Io),
+            None => Ok(()),
+        })?;
+        Ok(())
+    }
+
+    /// Store the value under the key; closing again does nothing
+    fn close(&self, py: Python<'_>) -> PyResult<()> {
+        let Some(file) = self.file.lock().take() else {
+            return Ok(());
+        };
+        py.detach(|| self.cache.commit_stream(&self.key, file))?;
+        Ok(())
+    }
+
+    /// Drop what was written without storing it
+    fn discard(&self) {
+        self.file.lock().take();
+    }
+
+    #[getter]
+    fn closed(&self) -> bool {
+        self.file.lock().is_none()
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    fn readable(&self) -> bool {
+        false
+    }
+
+    fn seekable(&self) -> bool {
+        false
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (exc_type, _exc_value, _traceback))]
+    fn __exit__(
+        &self,
+        py: Python<'_>,
+        exc_type: Option<Bound<'_, PyAny>>,
+        _exc_value: Option<Bound<'_, PyAny>>,
+        _traceback: Option<Bound<'_, PyAny>>,
+    ) -> PyResult<bool> {
+        match exc_type {
+            Some(_) => self.discard(),
+            None => self.close(py)?,
+        }
+        Ok(false)
+    }
+}
+
+/// Server of a cache to other processes over a Unix socket, returned by
+/// `PyCache.serve`
+///
+/// Serves until `close()` or the end of its `with` block.
+#[cfg(unix)]
+#[pyclass]
+pub struct PyCacheServer {
+    server: CacheServer,
+}
+
+#[cfg(unix)]
+#[pymethods]
+impl PyCacheServer {
+    /// The socket clients connect to
+    #[getter]
+    fn socket(&self) -> PathBuf {
+        self.server.socket().to_path_buf()
+    }
+
+    /// Block until the server is closed; signals such as Ctrl-C interrupt
+    /// the wait with their exception
+    fn serve_forever(&self, py: Python<'_>) -> PyResult<()> {
+        while !py.detach(|| self.server.wait(Some(Duration::from_millis(100)))) {
+            py.check_signals()?;
+        }
+        Ok(())
+    }
+
+    /// Stop serving and remove the socket; closing again does nothing
+    fn close(&self, py: Python<'_>) {
+        py.detach(|| self.server.close());
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (_exc_type, _exc_value, _traceback))]
+    fn __exit__(
+        &self,
+        py: Python<'_>,
+        _exc_type: Option<Bound<'_, PyAny>>,
+        _exc_value: Option<Bound<'_, PyAny>>,
+        _traceback: Option<Bound<'_, PyAny>>,
+    ) -> bool {
+        self.close(py);
+        false
+    }
+}
+
+/// Advisory lock on one key, returned by `PyCache.lock_key`
+///
+/// Held until `release()` or the end of its `with` block.
+#[pyclass]
+pub struct PyKeyLock {
+    lock: Mutex<Option<EntryLock>>,
+}
+
+#[pymethods]
+impl PyKeyLock {
+    /// Let the next waiter take the key; releasing again does nothing
+    fn release(&self) {
+        self.lock.lock().take();
+    }
+
+    #[getter]
+    fn locked(&self) -> bool {
+        self.lock.lock().is_some()
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (_exc_type, _exc_value, _traceback))]
+    fn __exit__(
+        &self,
+        _exc_type: Option<Bound<'_, PyAny>>,
+        _exc_value: Option<Bound<'_, PyAny>>,
+        _traceback: Option<Bound<'_, PyAny>>,
+    ) -> bool {
+        self.release();
+        false
+    }
+}
+
+/// Changes to the keys of one or more topics, returned by `PyCache.subscribe`
+///
+/// Iterating blocks for each next `(event, key)` pair, with `event` one of
+/// `"set"`, `"delete"`, `"evict"`, `"expire"` and `"clear"`. Unsubscribed by `close()`
+/// or the end of its `with` block.
+#[pyclass(frozen)]
+pub struct PySubscription {
+    subscription: Mutex<Option<Subscription>>,
+}
+
+impl PySubscription {
+    /// Wait for the next change until `deadline`, or for good without one;
+    /// signals such as Ctrl-C interrupt the wait with their exception
+    fn next_change(
+        &self,
+        py: Python<'_>,
+        deadline: Option<std::time::Instant>,
+    ) -> PyResult<Option<(&'static str, String)>> {
+        loop {
+            let wait = match deadline {
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(std::time::Instant::now());
+                    left.min(FEED_POLL)
+                }
+                None => FEED_POLL,
+            };
+            let change = py.detach(|| {
+                self.subscription
+                    .lock()
+                    .as_ref()
+                    .map(|subscription| subscription.recv(Some(wait)))
+            });
+            match change {
+                None => return Ok(None),
+                Some(Some(change)) => return Ok(Some((change.kind.name(), change.key))),
+                Some(None) => {}
+            }
+            if deadline.is_some_and(|deadline| std::time::Instant::now() >= deadline) {
+                return Ok(None);
+            }
+            py.check_signals()?;
+        }
+    }
+}
+
+#[pymethods]
+impl PySubscription {
+    /// The next change, or None if none comes within `timeout` seconds or
+    /// the subscription is closed
+    #[pyo3(signature = (timeout=None))]
+    fn get(
+        &self,
+        py: Python<'_>,
+        timeout: Option<f64>,
+    ) -> PyResult<Option<(&'static str, String)>> {
+        let deadline = timeout
+            .map(|timeout| std::time::Instant::now() + Duration::from_secs_f64(timeout.max(0.0)));
+        self.next_change(py, deadline)
+    }
+
+    /// Stop receiving changes; closing again does nothing
+    fn close(&self) {
+        self.subscription.lock().take();
+    }
+
+    #[getter]
+    fn closed(&self) -> bool {
+        self.subscription.lock().is_none()
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// Block until the next change; iteration ends once the subscription
+    /// is closed
+    fn __next__(&self, py: Python<'_>) -> PyResult<Option<(&'static str, String)>> {
+        self.next_change(py, None)
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (_exc_type, _exc_value, _traceback))]
+    fn __exit__(
+        &self,
+        _exc_type: Option<Bound<'_, PyAny>>,
+        _exc_value: Option<Bound<'_, PyAny>>,
+        _traceback: Option<Bound<'_, PyAny>>,
+    ) -> bool {
+        self.close();
+        false
+    }
+}
+
+/// Seekable binary file over a value stored in compressed frames, returned
+/// by `PyCache.open_frames`
+///
+/// Reads decompress only the frames they reach.
+#[pyclass]
+pub struct PyFrameReader {
+    reader: Mutex<Option<FramedReader<std::fs::File>>>,
+}
+
+impl PyFrameReader {
+    fn with_reader<T>(
+        &self,
+        f: impl FnOnce(&mut FramedReader<std::fs::File>) -> std::io::Result<T>,
+    ) -> CacheResult<T> {
+        match self.reader.lock().as_mut() {
+            Some(reader) => f(reader).map_err(CacheError::Io),
+            None => Err(CacheError::Io(std::io::Error::other(
+                "read from a closed frame reader",
+            ))),
+        }
+    }
+}
+
+#[pymethods]
+impl PyFrameReader {
+    /// Read up to `size` bytes, or to the end of the value when `size` is
+    /// negative
+    #[pyo3(signature = (size=-1))]
+    fn read(&self, py: Python<'_>, size: i64) -> PyResult<Vec<u8>> {
+        Ok(py.detach(|| {
+            self.with_reader(|reader| {
+                let mut data = Vec::new();
+                match u64::try_from(size) {
+                    Ok(size) => reader.take(size).read_to_end(&mut data)?,
+                    Err(_) => reader.read_to_end(&mut data)?,
+                };
+                Ok(data)
+            })
+        })?)
+    }
+
+    /// Move to `offset` from the start, the current position or the end,
+    /// as `whence` is 0, 1 or 2, returning the new position
+    #[pyo3(signature = (offset, whence=0))]
+    fn seek(&self, offset: i64, whence: u8) -> PyResult<u64> {
+        let pos =
+            match whence {
+                0 => SeekFrom::Start(u64::try_from(offset).map_err(|_| {
+                    pyo3::exceptions::PyValueError::new_err("negative seek position")
+                })?),
+                1 => SeekFrom::Current(offset),
+                2 => SeekFrom::End(offset),
+                _ => {
+                    return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                        "invalid whence ({}, should be 0, 1 or 2)",
+                        whence
+                    )))
+                }
+            };
+        Ok(self.with_reader(|reader| reader.seek(pos))?)
+    }
+
+    fn tell(&self) -> PyResult<u64> {
+        Ok(self.with_reader(|reader| reader.stream_position())?)
+    }
+
+    /// Length of the whole value
+    #[getter]
+    fn size(&self) -> PyResult<u64> {
+        Ok(self.with_reader(|reader| Ok(reader.len()))?)
+    }
+
+    fn close(&self) {
+        self.reader.lock().take();
+    }
+
+    #[getter]
+    fn closed(&self) -> bool {
+        self.reader.lock().is_none()
+    }
+
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    fn seekable(&self) -> bool {
+        true
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (_exc_type, _exc_value, _traceback))]
+    fn __exit__(
+        &self,
+        _exc_type: Option<Bound<'_, PyAny>>,
+        _exc_value: Option<Bound<'_, PyAny>>,
+        _traceback: Option<Bound<'_, PyAny>>,
+    ) -> bool {
+        self.close();
+        false
+    }
+}
+
+/// Lazy iterator over keys in store order, returned by `iterkeys`
+///
+/// A fanout cache's shards are iterated one after another.
+#[pyclass]
+pub struct PyKeyIterator {
+    caches: std::collections::VecDeque<Arc<DiskCache>>,
+    cursor: Option<i64>,
+    reverse: bool,
+    page: std::collections::VecDeque<String>,
+    exhausted: bool,
+}
+
+impl PyKeyIterator {
+    fn new(caches: impl IntoIterator<Item = Arc<DiskCache>>, reverse: bool) -> Self {
+        Self {
+            caches: caches.into_iter().collect(),
+            cursor: None,
+            reverse,
+            page: std::collections::VecDeque::new(),
+            exhausted: false,
+        }
+    }
+}
+
+#[pymethods]
+impl PyKeyIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self) -> PyResult<Option<String>> {
+        while self.page.is_empty() {
+            let Some(cache) = self.caches.front() else {
+                return Ok(None);
+            };
+            if self.exhausted {
+                self.caches.pop_front();
+                self.cursor = None;
+                self.exhausted = false;
+                continue;
+            }
+            let (keys, next) = cache.key_page(self.cursor, self.reverse, KEY_PAGE_SIZE)?;
+            self.page.extend(keys);
+            self.cursor = next;
+            self.exhausted = next.is_none();
+        }
+        Ok(self.page.pop_front())
+    }
+}
+
+#[pymethods]
+impl PyCache {
+    #[new]
+    #[pyo3(signature = (directory=None, max_size=None, max_entries=None, disk_write_threshold=None, use_file_locking=None, disk=None, auto_recover=None, sync_writes=None, batch_size=None, use_mmap=None, wal=None, backend=None, pack_threshold=None, compaction_ratio=None, data_fanout=None, unlink_workers=None, unlink_rate=None, durability=None, group_commit=None, index_key=None, soft_delete=None, direct_io_threshold=None, designated_writer=None, writer_lease=None, maintenance_lease=None, ring_capacity_bytes=None, remote_tier=None, file_naming=None, write_queue_capacity=None, queue_full=None, encryption_key=None, compression=None, compression_level=None, zstd_dictionary=None, dictionary_threshold=None, seekable_compression=None, watch_directory=None, event_log=None, lock_backend=None, lock_granularity=None, smb_mode=None, sharing_retries=None, eviction_policy=None, hot_cache_policy=None, eviction_samples=None, max_idle=None, inline_evictions=None, warm_cache_size=None, warm_cache_bytes=None, statistics=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        directory: Option<String>,
+        max_size: Option<u64>,
+        max_entries: Option<u64>,
+        disk_write_threshold: Option<usize>,
+        use_file_locking: Option<bool>,
+        disk: Option<&Bound<'_, PyAny>>,
+        auto_recover: Option<bool>,
+        sync_writes: Option<bool>,
+        batch_size: Option<usize>,
+        use_mmap: Option<bool>,
+        wal: Option<&str>,
+        backend: Option<&str>,
+        pack_threshold: Option<usize>,
+        compaction_ratio: Option<f64>,
+        data_fanout: Option<usize>,
+        unlink_workers: Option<usize>,
+        unlink_rate: Option<u64>,
+        durability: Option<&str>,
+        group_commit: Option<f64>,
+        index_key: Option<Vec<u8>>,
+        soft_delete: Option<f64>,
+        direct_io_threshold: Option<usize>,
+        designated_writer: Option<bool>,
+        writer_lease: Option<f64>,
+        maintenance_lease: Option<f64>,
+        ring_capacity_bytes: Option<u64>,
+        remote_tier: Option<&str>,
+        file_naming: Option<&str>,
+        write_queue_capacity: Option<usize>,
+        queue_full: Option<&str>,
+        encryption_key: Option<&Bound<'_, PyAny>>,
+        compression: Option<&str>,
+        compression_level: Option<i32>,
+        zstd_dictionary: Option<bool>,
+        dictionary_threshold: Option<usize>,
+        seekable_compression: Option<bool>,
+        watch_directory: Option<bool>,
+        event_log: Option<bool>,
+        lock_backend: Option<String>,
+        lock_granularity: Option<String>,
+        smb_mode: Option<bool>,
+        sharing_retries: Option<u32>,
+        eviction_policy: Option<String>,
+        hot_cache_policy: Option<String>,
+        eviction_samples: Option<usize>,
+        max_idle: Option<f64>,
+        inline_evictions: Option<usize>,
+        warm_cache_size: Option<usize>,
+        warm_cache_bytes: Option<u64>,
+        statistics: Option<bool>,
+    ) -> PyResult<Self> {
+        let mut config = CacheConfig {
+            directory: directory.clone().map(PathBuf::from).unwrap_or_default(),
+            ..Default::default()
+        };
+        config.max_size = max_size;
+        config.max_entries = max_entries;
+
+        // Apply new configuration options if provided
+        if let Some(threshold) = disk_write_threshold {
+            config.disk_write_threshold = threshold;
+        }
+        if let Some(threshold) = pack_threshold {
+            config.pack_threshold = threshold;
+        }
+        if let Some(ratio) = compaction_ratio {
+            config.compaction_ratio = ratio;
+        }
+        if let Some(fanout) = data_fanout {
+            config.data_fanout = fanout;
+        }
+        if let Some(naming) = file_naming {
+            config.file_naming = naming.parse()?;
+        }
+        if let Some(codec) = compression {
+            config.compression = codec.parse()?;
+        }
+        config.compression_level = compression_level;
+        if let Some(enabled) = zstd_dictionary {
+            config.zstd_dictionary = enabled;
+        }
+        if let Some(threshold) = dictionary_threshold {
+            config.dictionary_threshold = threshold;
+        }
+        if let Some(seekable) = seekable_compression {
+            config.seekable_compression = seekable;
+        }
+        if let Some(workers) = unlink_workers {
+            config.unlink_workers = workers;
+        }
+        if let Some(rate) = unlink_rate {
+            config.unlink_rate = rate;
+        }
+        if let Some(locking) = use_file_locking {
+            config.use_file_locking = locking;
+        }
+        if let Some(recover) = auto_recover {
+            config.auto_recover = recover;
+        }
+        if let Some(sync_writes) = sync_writes {
+            config.sync_writes = sync_writes;
+        }
+        if let Some(batch_size) = batch_size {
+            config.batch_size = batch_size;
+        }
+        if let Some(capacity) = write_queue_capacity {
+            config.write_queue_capacity = capacity;
+        }
+        if let Some(policy) = queue_full {
+            config.queue_full = policy.parse()?;
+        }
+        if let Some(use_mmap) = use_mmap {
+            config.use_mmap = use_mmap;
+        }
+        if let Some(size) = warm_cache_size {
+            config.warm_cache_size = size;
+        }
+        if let Some(bytes) = warm_cache_bytes {
+            config.warm_cache_bytes = bytes;
+        }
+        if let Some(watch) = watch_directory {
+            config.watch_directory = watch;
+        }
+        if let Some(event_log) = event_log {
+            config.event_log = event_log;
+        }
+        if let Some(lock_backend) = lock_backend {
+            config.lock_backend = lock_backend.parse()?;
+        }
+        if let Some(granularity) = lock_granularity {
+            config.lock_granularity = granularity.parse()?;
+        }
+        if let Some(smb_mode) = smb_mode {
+            config.smb_mode = smb_mode;
+        }
+        if let Some(retries) = sharing_retries {
+            config.sharing_retry.attempts = retries.saturating_add(1);
+        }
+        if let Some(policy) = eviction_policy {
+            config.eviction_strategy = policy.parse()?;
+        }
+        if let Some(policy) = hot_cache_policy {
+            config.hot_cache_policy = policy.parse()?;
+        }
+        if let Some(samples) = eviction_samples {
+            config.eviction_samples = samples;
+        }
+        if let Some(max_idle) = max_idle {
+            config.max_idle = Some(idle_period(max_idle)?);
+        }
+        if let Some(inline) = inline_evictions {
+            config.inline_evictions = Some(inline);
+        }
+        if let Some(statistics) = statistics {
+            config.statistics = statistics;
+        }
+        if let Some(wal) = wal {
+            config.wal = Some(wal.parse()?);
+        }
+        if let Some(durability) = durability {
+            config.durability = durability.parse()?;
+        }
+        if let Some(window) = group_commit {
+            config.group_commit = Some(group_commit_window(window)?);
+        }
+        if let Some(secret) = index_key {
+            config.index_key = Some(IndexKey::new(&secret)?);
+        }
+        if let Some(key) = encryption_key.filter(|key| !key.is_none()) {
+            config.encryption_key = Some(resolve_encryption_key(key, &config.directory)?);
+        }
+        if let Some(threshold) = direct_io_threshold {
+            config.direct_io_threshold = Some(threshold);
+        }
+        if let Some(designated_writer) = designated_writer {
+            config.designated_writer = designated_writer;
+        }
+        if let Some(period) = writer_lease {
+            config.writer_lease = Some(writer_lease_period(period)?);
+        }
+        if let Some(period) = maintenance_lease {
+            config.maintenance_lease = Some(maintenance_lease_period(period)?);
+        }
+        if let Some(capacity) = ring_capacity_bytes {
+            config.ring_capacity_bytes = Some(capacity);
+        }
+        if let Some(location) = remote_tier {
+            config.remote_tier = Some(open_remote_tier(location)?);
+        }
+        if let Some(grace) = soft_delete {
+            config.soft_delete = Some(soft_delete_grace(grace)?);
+        }
+        if let Some(backend) = backend {
+            config.backend = backend.parse()?;
+        }
+        if let Some(directory) = &directory {
+            set_directory(&mut config, directory)?;
+        }
+        if directory.is_none() && config.backend != StorageKind::Memory {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "directory",
+                "A cache directory is required",
+                "Pass a directory, or backend=\"memory\" for a cache that keeps nothing on disk",
+            ))
+            .into());
+        }
+
+        let cache = DiskCache::with_disk(config, resolve_disk(disk)?)?;
+        Ok(Self {
+            cache: Arc::new(cache),
+        })
+    }
+
+    fn get(&self, key: &str) -> PyResult<Option<Vec<u8>>> {
+        Ok(self.cache.get(key)?)
+    }
+
+    /// Get several values at once, returning None for missing keys
+    #[pyo3(signature = (keys, prefetch_prefix=None))]
+    fn get_many(
+        &self,
+        keys: Vec<String>,
+        prefetch_prefix: Option<&str>,
+    ) -> PyResult<Vec<Option<Vec<u8>>>> {
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        Ok(self.cache.get_many(&keys, prefetch_prefix)?)
+    }
+
+    /// Read a value without updating access statistics
+    fn peek(&self, key: &str) -> PyResult<Option<Vec<u8>>> {
+        Ok(self.cache.peek(key)?)
+    }
+
+    /// (path, offset) of the file holding the stored bytes of `key`, or None if they live elsewhere
+    fn data_file(&self, key: &str) -> PyResult<Option<(PathBuf, u64)>> {
+        Ok(self.cache.data_file(key)?)
+    }
+
+    /// Bytes `start` to `end` of a value, or to its end without `end`,
+    /// reading only as much of a large value as the range needs
+    #[pyo3(signature = (key, start, end=None))]
+    fn get_range(
+        &self,
+        py: Python<'_>,
+        key: &str,
+        start: u64,
+        end: Option<u64>,
+    ) -> PyResult<Option<Vec<u8>>> {
+        let range = start..end.unwrap_or(u64::MAX);
+        Ok(py.detach(|| self.cache.get_range(key, range))?)
+    }
+
+    /// Seekable reader over a value stored in compressed frames, or None for
+    /// values stored otherwise
+    fn open_frames(&self, key: &str) -> PyResult<Option<PyFrameReader>> {
+        Ok(self.cache.open_frames(key)?.map(|reader| PyFrameReader {
+            reader: Mutex::new(Some(reader)),
+        }))
+    }
+
+    /// Return the first or last (key, value) pair in store order
+    #[pyo3(signature = (last=true))]
+    fn peekitem(&self, last: bool) -> PyResult<Option<(String, Vec<u8>)>> {
+        Ok(self.cache.peekitem(last)?)
+    }
+
+    /// Store a value, returning the version stamp it was written with
+    ///
+    /// `weight` is what the value costs to recompute, for the `gdsf`
+    /// eviction policy; `priority` is "low", "normal" or "high".
+    #[pyo3(signature = (key, value, expire_time=None, tags=None, weight=None, priority=None))]
+    fn set(
+        &self,
+        key: &str,
+        value: Py<PyAny>,
+        expire_time: Option<u64>,
+        tags: Option<Vec<String>>,
+        weight: Option<f64>,
+        priority: Option<&str>,
+    ) -> PyResult<Option<u64>> {
+        let tags = tags.unwrap_or_default();
+        // Convert PyObject to bytes for internal storage
+        let value_bytes = Python::attach(|py| {
+            let bytes = value.extract::<Vec<u8>>(py)?;
+            Ok::<Vec<u8>, PyErr>(bytes)
+        })?;
+        if let Some(weight) = weight.filter(|weight| !(weight.is_finite() && *weight > 0.0)) {
+            return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                "weight must be a positive number, not {}",
+                weight
+            )));
+        }
+        let priority = match priority {
+            None | Some("normal") => Priority::Normal,
+            Some("low") => Priority::Low,
+            Some("high") => Priority::High,
+            Some(other) => {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "priority must be \"low\", \"normal\" or \"high\", not {:?}",
+                    other
+                )));
+            }
+        };
+        self.cache
+            .set_ranked(key, &value_bytes, expire_time, tags, weight, priority)?;
+        Ok(self.cache.version(key)?)
+    }
+
+    /// Store a value only if `key` still has version `expected`, or is absent
+    /// when `expected` is None, returning whether it was stored
+    #[pyo3(signature = (key, expected, value, expire_time=None, tags=None))]
+    fn cas(
+        &self,
+        py: Python<'_>,
+        key: &str,
+        expected: Option<u64>,
+        value: Vec<u8>,
+        expire_time: Option<u64>,
+        tags: Option<Vec<String>>,
+    ) -> PyResult<bool> {
+        let tags = tags.unwrap_or_default();
+        // Waits on the key's entry lock, which another thread may hold
+        Ok(py.detach(|| self.cache.cas(key, expected, &value, expire_time, tags))?)
+    }
+
+    /// Get the value of `key` and the version stamp it was written with
+    fn get_with_version(&self, key: &str) -> PyResult<Option<(Vec<u8>, u64)>> {
+        Ok(self.cache.get_with_version(key)?)
+    }
+
+    /// Make `alias` a second name for the stored entry `key`
+    fn alias(&self, alias: &str, key: &str) -> PyResult<()> {
+        Ok(self.cache.alias(alias, key)?)
+    }
+
+    /// Remove `alias`, returning whether it existed
+    fn unalias(&self, alias: &str) -> PyResult<bool> {
+        Ok(self.cache.unalias(alias)?)
+    }
+
+    /// Aliases that point at `key`
+    fn aliases(&self, key: &str) -> PyResult<Vec<String>> {
+        Ok(self.cache.aliases(key)?)
+    }
+
+    /// Version stamp of `key`, or None if it is not stored
+    fn version(&self, key: &str) -> PyResult<Option<u64>> {
+        Ok(self.cache.version(key)?)
+    }
+
+    /// Whether `key` was rewritten or removed since `set` returned `version`
+    fn has_changed(&self, key: &str, version: u64) -> PyResult<bool> {
+        Ok(self.cache.has_changed(key, version)?)
+    }
+
+    /// Whether writes fail because another process holds the writer lease
+    fn is_read_only(&self) -> bool {
+        self.cache.is_read_only()
+    }
+
+    /// Whether this process runs the automatic vacuum, expiry sweep and
+    /// compaction, rather than another holding the maintenance lease
+    fn is_maintenance_leader(&self) -> bool {
+        self.cache.is_maintenance_leader()
+    }
+
+    /// The processes attached to the cache directory, as dicts with `pid`,
+    /// `host`, `opened_at`, `heartbeat` (seconds since the epoch) and `current`
+    fn processes<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, pyo3::types::PyList>> {
+        let processes = py.detach(|| self.cache.processes())?;
+        let result = pyo3::types::PyList::empty(py);
+        for process in processes {
+            let entry = pyo3::types::PyDict::new(py);
+            entry.set_item("pid", process.pid)?;
+            entry.set_item("host", process.host)?;
+            entry.set_item("opened_at", process.opened_at as f64 / 1000.0)?;
+            entry.set_item("heartbeat", process.heartbeat as f64 / 1000.0)?;
+            entry.set_item("current", process.current)?;
+            result.append(entry)?;
+        }
+        Ok(result)
+    }
+
+    /// Serve this cache's get, set and delete to other processes over a Unix
+    /// socket, by default `server.sock` in the cache directory
+    #[cfg(unix)]
+    #[pyo3(signature = (socket=None))]
+    fn serve(&self, socket: Option<PathBuf>) -> PyResult<PyCacheServer> {
+        let server = CacheServer::start(self.cache.clone(), socket.as_deref())?;
+        Ok(PyCacheServer { server })
+    }
+
+    /// Hear about keys under `prefix`, or set with `tag`, being set, deleted
+    /// or found expired
+    ///
+    /// Without either, every key is subscribed to. Passing an existing
+    /// `subscription` adds the topic to it and returns it, so one iterator
+    /// follows several topics or caches.
+    #[pyo3(signature = (prefix=None, tag=None, subscription=None))]
+    fn subscribe(
+        &self,
+        py: Python<'_>,
+        prefix: Option<String>,
+        tag: Option<String>,
+        subscription: Option<Py<PySubscription>>,
+    ) -> PyResult<Py<PySubscription>> {
+        let topic = match (prefix, tag) {
+            (Some(_), Some(_)) => {
+                return Err(pyo3::exceptions::PyValueError::new_err(
+                    "Subscribe to a prefix or a tag, not both",
+                ))
+            }
+            (None, Some(tag)) => Topic::Tag(tag),
+            (prefix, None) => Topic::Prefix(prefix.unwrap_or_default()),
+        };
+        let subscription = match subscription {
+            Some(subscription) => subscription,
+            None => Py::new(
+                py,
+                PySubscription {
+                    subscription: Mutex::new(Some(Subscription::new())),
+                },
+            )?,
+        };
+        py.detach(|| match subscription.get().subscription.lock().as_mut() {
+            Some(subscription) => self.cache.subscribe_into(topic, subscription),
+            None => Err(CacheError::Io(std::io::Error::other(
+                "subscribe with a closed subscription",
+            ))),
+        })?;
+        Ok(subscription)
+    }
+
+    /// Never evict `key`, nor drop it from memory to make room
+    fn pin(&self, key: &str) -> PyResult<()> {
+        Ok(self.cache.pin(key)?)
+    }
+
+    /// Let `key` be evicted again, returning whether it was pinned
+    fn unpin(&self, key: &str) -> PyResult<bool> {
+        Ok(self.cache.unpin(key)?)
+    }
+
+    /// Cap the bytes or entries stored under `tag`
+    #[pyo3(signature = (tag, max_bytes=None, max_entries=None))]
+    fn set_tag_limit(
+        &self,
+        py: Python<'_>,
+        tag: &str,
+        max_bytes: Option<u64>,
+        max_entries: Option<u64>,
+    ) -> PyResult<()> {
+        if max_bytes.is_none() && max_entries.is_none() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "set_tag_limit needs max_bytes or max_entries",
+            ));
+        }
+        let limit = TagLimit {
+            max_bytes,
+            max_entries,
+        };
+        Ok(py.detach(|| self.cache.set_tag_limit(tag, limit))?)
+    }
+
+    /// Stop limiting `tag`, returning whether it was limited
+    fn remove_tag_limit(&self, tag: &str) -> bool {
+        self.cache.remove_tag_limit(tag)
+    }
+
+    /// Pick victims by `strategy`, one of the `eviction_policy` names, from now on
+    fn set_eviction_strategy(&self, py: Python<'_>, strategy: &str) -> PyResult<()> {
+        let strategy = strategy.parse()?;
+        Ok(py.detach(|| self.cache.set_eviction_strategy(strategy))?)
+    }
+
+    /// Wait until no other process or thread holds `key`'s lock, then hold it
+    fn lock_key(&self, py: Python<'_>, key: &str) -> PyResult<PyKeyLock> {
+        let lock = py.detach(|| self.cache.lock_key(key))?;
+        Ok(PyKeyLock {
+            lock: Mutex::new(Some(lock)),
+        })
+    }
+
+    /// Get `key`, or store and return `loader()` on a miss
+    ///
+    /// Concurrent misses on the same key in this process call `loader` once.
+    #[pyo3(signature = (key, loader, expire_time=None, tags=None))]
+    fn get_or_load(
+        &self,
+        py: Python<'_>,
+        key: &str,
+        loader: Py<PyAny>,
+        expire_time: Option<u64>,
+        tags: Option<Vec<String>>,
+    ) -> PyResult<Vec<u8>> {
+        let mut raised = None;
+        // Waiting for another thread's load must not hold the GIL it needs
+        let loaded = py.detach(|| {
+            self.cache
+                .get_or_load(key, expire_time, tags.unwrap_or_default(), || {
+                    call_loader(&loader, &mut raised)
+                })
+        });
+        match raised {
+            Some(err) => Err(err),
+            None => Ok(loaded?),
+        }
+    }
+
+    /// Get `key`, or store and return `loader()` on a miss, with one load at
+    /// a time across processes; with `stale` seconds, an expired value is
+    /// returned that much longer while one caller loads its successor
+    #[pyo3(signature = (key, loader, expire_time=None, tags=None, stale=None))]
+    fn get_or_set(
+        &self,
+        py: Python<'_>,
+        key: &str,
+        loader: Py<PyAny>,
+        expire_time: Option<u64>,
+        tags: Option<Vec<String>>,
+        stale: Option<u64>,
+    ) -> PyResult<Vec<u8>> {
+        let mut raised = None;
+        // Waiting for another process's load must not hold the GIL
+        let loaded = py.detach(|| {
+            self.cache
+                .get_or_set(key, expire_time, stale, tags.unwrap_or_default(), || {
+                    call_loader(&loader, &mut raised)
+                })
+        });
+        match raised {
+            Some(err) => Err(err),
+            None => Ok(loaded?),
+        }
+    }
+
+    /// Push a value onto a queue, returning its position and key
+    #[pyo3(signature = (prefix, value, side="back", expire_time=None, tags=None))]
+    fn push(
+        &self,
+        prefix: &str,
+        value: Vec<u8>,
+        side: &str,
+        expire_time: Option<u64>,
+        tags: Option<Vec<String>>,
+    ) -> PyResult<(i64, String)> {
+        let side = parse_queue_side(side)?;
+        Ok(self
+            .cache
+            .push(prefix, &value, side, expire_time, tags.unwrap_or_default())?)
+    }
+
+    /// Remove and return (position, key, value) from one end of a queue
+    #[pyo3(signature = (prefix, side="front"))]
+    fn pull(&self, prefix: &str, side: &str) -> PyResult<Option<(i64, String, Vec<u8>)>> {
+        let item = self.cache.pull(prefix, parse_queue_side(side)?)?;
+        Ok(item.map(|item| (item.position, item.key, item.value)))
+    }
+
+    /// Return (position, key, value) from one end of a queue without removing it
+    #[pyo3(signature = (prefix, side="front"))]
+    fn peek_queue(&self, prefix: &str, side: &str) -> PyResult<Option<(i64, String, Vec<u8>)>> {
+        let item = self.cache.peek_queue(prefix, parse_queue_side(side)?)?;
+        Ok(item.map(|item| (item.position, item.key, item.value)))
+    }
+
+    /// Set multiple values in the cache (batch operation for better performance)
+    #[pyo3(signature = (items, expire_time=None, tags=None))]
+    fn set_many(
+        &self,
+        items: Vec<(String, Vec<u8>)>,
+        expire_time: Option<u64>,
+        tags: Option<Vec<String>>,
+    ) -> PyResult<()> {
+        let tags = tags.unwrap_or_default();
+        self.cache.set_many(items, expire_time, tags)?;
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> PyResult<bool> {
+        Ok(self.cache.delete(key)?)
+    }
+
+    fn undelete(&self, key: &str) -> PyResult<bool> {
+        Ok(self.cache.undelete(key)?)
+    }
+
+    fn exists(&self, key: &str) -> PyResult<bool> {
+        Ok(self.cache.exists(key)?)
+    }
+
+    fn keys(&self) -> PyResult<Vec<String>> {
+        Ok(self.cache.keys()?)
+    }
+
+    /// Lazily iterate keys in store order
+    #[pyo3(signature = (reverse=false))]
+    fn iterkeys(&self, reverse: bool) -> PyKeyIterator {
+        PyKeyIterator::new([Arc::clone(&self.cache)], reverse)
+    }
+
+    /// Clear all entries; `progress(removed, total)` is called as data files go
+    #[pyo3(signature = (progress=None))]
+    fn clear(&self, py: Python<'_>, progress: Option<Py<PyAny>>) -> PyResult<()> {
+        let Some(progress) = progress else {
+            return Ok(self.cache.clear()?);
+        };
+        // The removal workers take the GIL to report, so the caller must not hold it
+        py.detach(|| {
+            self.cache.clear_with_progress(&|done: UnlinkProgress| {
+                Python::attach(|py| {
+                    if let Err(err) = progress.call1(py, (done.done(), done.total)) {
+                        err.write_unraisable(py, Some(progress.bind(py)));
+                    }
+                })
+            })
+        })?;
+        Ok(())
+    }
+
+    fn size(&self) -> PyResult<u64> {
+        Ok(self.cache.size()?)
+    }
+
+    /// Sync, compact and reclaim orphaned files, returning what was cleaned up
+    fn vacuum<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, pyo3::types::PyDict>> {
+        let report = py.detach(|| self.cache.vacuum())?;
+        let result = pyo3::types::PyDict::new(py);
+        result.set_item("orphans_removed", report.orphans_removed)?;
+        result.set_item("orphans_adopted", report.orphans_adopted)?;
+        result.set_item("temp_files_removed", report.temp_files_removed)?;
+        result.set_item("files_truncated", report.files_truncated)?;
+        result.set_item("bytes_reclaimed", report.bytes_reclaimed)?;
+        Ok(result)
+    }
+
+    fn close(&self) -> PyResult<()> {
+        self.cache.close();
+        Ok(())
+    }
+
+    /// Counters of this process, or with `aggregate` of every process using
+    /// the directory; sizes always describe the directory
+    #[pyo3(signature = (aggregate=false))]
+    fn stats(&self, aggregate: bool) -> PyResult<HashMap<String, u64>> {
+        let mut stats = self.cache.stats();
+        let mut io = self.cache.io_stats();
+        let mut result = HashMap::new();
+        if aggregate {
+            let shared = self.cache.aggregate_stats()?;
+            let counts = shared.counts;
+            stats.hits = counts.hits;
+            stats.misses = counts.misses;
+            stats.sets = counts.sets;
+            stats.deletes = counts.deletes;
+            stats.evictions = counts.evictions;
+            stats.errors = counts.errors;
+            io = counts.io;
+            result.insert("processes".to_string(), shared.processes);
+        }
+        result.insert("hits".to_string(), stats.hits);
+        result.insert("misses".to_string(), stats.misses);
+        result.insert("sets".to_string(), stats.sets);
+        result.insert("deletes".to_string(), stats.deletes);
+        result.insert("evictions".to_string(), stats.evictions);
+        result.insert("errors".to_string(), stats.errors);
+        result.insert("total_size".to_string(), stats.total_size);
+        result.insert("entry_count".to_string(), stats.entry_count);
+        result.insert("eviction_backlog".to_string(), stats.eviction_backlog);
+        result.insert(
+            "eviction_backlog_bytes".to_string(),
+            self.cache.eviction_backlog().bytes,
+        );
+
+        result.insert(
+            "logical_bytes_written".to_string(),
+            io.logical_bytes_written,
+        );
+        result.insert("disk_bytes_written".to_string(), io.disk_bytes_written);
+        result.insert("files_created".to_string(), io.files_created);
+        result.insert("files_deleted".to_string(), io.files_deleted);
+        result.insert("fsyncs".to_string(), io.fsyncs);
+        Ok(result)
+    }
+
+    /// Return (hits, misses), optionally resetting them or toggling counting
+    ///
+    /// With `aggregate`, the counts are those of every process using the
+    /// directory; a reset still only zeroes this process's.
+    #[pyo3(signature = (enable=true, reset=false, aggregate=false))]
+    fn hit_stats(&self, enable: bool, reset: bool, aggregate: bool) -> PyResult<(u64, u64)> {
+        let shared = aggregate
+            .then(|| self.cache.aggregate_stats())
+            .transpose()?;
+        let own = self.cache.hit_stats(enable, reset);
+        Ok(shared.map_or(own, |shared| (shared.counts.hits, shared.counts.misses)))
+    }
+
+    /// The `n` most hit keys with their approximate hit counts, most hit first
+    #[pyo3(signature = (n=10))]
+    fn top_keys(&self, n: usize) -> Vec<(String, u64)> {
+        self.cache.top_keys(n)
+    }
+
+    fn hit_rate(&self) -> PyResult<f64> {
+        Ok(self.cache.stats().hit_rate())
+    }
+
+    /// Disk bytes written per value byte set, or 0.0 before the first write
+    fn write_amplification(&self) -> f64 {
+        self.cache.io_stats().write_amplification()
+    }
+
+    /// Describe the cache directory, including whether it was shut down uncleanly
+    fn info<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, pyo3::types::PyDict>> {
+        let info = self.cache.info();
+        let result = pyo3::types::PyDict::new(py);
+        result.set_item("directory", info.directory.to_string_lossy().to_string())?;
+        result.set_item("was_unclean_shutdown", info.was_unclean_shutdown)?;
+        match info.last_recovery {
+            Some(report) => {
+                result.set_item("last_recovery", recovery_report_to_dict(py, &report)?)?
+            }
+            None => result.set_item("last_recovery", py.None())?,
+        }
+        Ok(result)
+    }
+
+    /// Project disk and memory use for a workload; `ttl` is in seconds
+    #[pyo3(signature = (keys_per_day, avg_value_size, ttl=None))]
+    fn estimate<'py>(
+        &self,
+        py: Python<'py>,
+        keys_per_day: u64,
+        avg_value_size: usize,
+        ttl: Option<f64>,
+    ) -> PyResult<Bound<'py, pyo3::types::PyDict>> {
+        let ttl = ttl
+            .map(|ttl| {
+                std::time::Duration::try_from_secs_f64(ttl).map_err(|_| {
+                    pyo3::exceptions::PyValueError::new_err(
+                        "ttl must be a non-negative number of seconds",
+                    )
+                })
+            })
+            .transpose()?;
+        let estimate = self.cache.estimate(keys_per_day, avg_value_size, ttl);
+
+        let result = pyo3::types::PyDict::new(py);
+        result.set_item("entries", estimate.entries)?;
+        result.set_item("disk_bytes", estimate.disk_bytes)?;
+        result.set_item("memory_bytes", estimate.memory_bytes)?;
+        result.set_item("recommended_max_entries", estimate.recommended_max_entries)?;
+        result.set_item("recommended_max_size", estimate.recommended_max_size)?;
+        result.set_item("fits_current_limits", estimate.fits_current_limits)?;
+        Ok(result)
+    }
+
+    /// Rewrite legacy absolute data file paths relative to the cache directory
+    fn relocate(&self) -> PyResult<u64> {
+        Ok(self.cache.relocate()?)
+    }
+
+    /// Store the file at `path` as the value of `key` by hard-linking it into the cache
+    fn link_file(&self, key: &str, path: PathBuf) -> PyResult<()> {
+        Ok(self.cache.link_file(key, &path)?)
+    }
+
+    /// Writable file streaming the value of `key` into the cache; `close()` stores it
+    fn open_write(&self, key: &str) -> PyResult<PyValueWriter> {
+        let (key, file) = self.cache.begin_stream(key)?;
+        Ok(PyValueWriter {
+            cache: Arc::clone(&self.cache),
+            key,
+            file: Mutex::new(Some(file)),
+        })
+    }
+
+    /// Register the files listed in a JSON manifest as entries, without copying them
+    fn load_manifest(&self, path: PathBuf) -> PyResult<u64> {
+        Ok(self.cache.load_manifest(&path)?)
+    }
+
+    /// Drop index entries whose data can no longer be read
+    fn verify_and_recover<'py>(
+        &self,
+        py: Python<'py>,
+    ) -> PyResult<Bound<'py, pyo3::types::PyDict>> {
+        let report = self.cache.verify_and_recover()?;
+        recovery_report_to_dict(py, &report)
+    }
+
+    /// Write a point-in-time copy of the cache to `destination`
+    fn snapshot<'py>(
+        &self,
+        py: Python<'py>,
+        destination: PathBuf,
+    ) -> PyResult<Bound<'py, pyo3::types::PyDict>> {
+        let report = py.detach(|| self.cache.snapshot(&destination))?;
+        let result = pyo3::types::PyDict::new(py);
+        result.set_item("entries", report.entries)?;
+        result.set_item("files", report.files)?;
+        result.set_item("bytes_copied", report.bytes_copied)?;
+        Ok(result)
+    }
+
+    /// Move every entry into a new backend in the same directory and switch to it
+    fn migrate_backend(&self, py: Python, target: &str) -> PyResult<u64> {
+        let target = target.parse()?;
+        Ok(py.detach(|| self.cache.migrate_backend(target))?)
+    }
+
+    /// Write every live entry to an archive file, returning the number written
+    ///
+    /// `expire_times` and `tags` give metadata for keys whose backend does not keep it.
+    #[pyo3(signature = (path, expire_times=None, tags=None))]
+    fn export(
+        &self,
+        py: Python,
+        path: PathBuf,
+        expire_times: Option<HashMap<String, u64>>,
+        tags: Option<HashMap<String, Vec<String>>>,
+    ) -> PyResult<u64> {
+        let (expire_times, tags) = (expire_times.unwrap_or_default(), tags.unwrap_or_default());
+        let metadata = |entry: &mut ArchiveEntry| {
+            if let Some(expire_time) = expire_times.get(&entry.key) {
+                entry.expire_time = Some(*expire_time);
+            }
+            if let Some(tags) = tags.get(&entry.key) {
+                entry.tags = tags.clone();
+            }
+        };
+        Ok(py.detach(|| self.cache.export(&path, &metadata))?)
+    }
+
+    /// Load an archive written by `export`, returning `(key, expire_time, tags)`
+    /// for each imported entry that has an expiration time or tags
+    fn import_archive(&self, py: Python, path: PathBuf) -> PyResult<Vec<ImportedMetadata>> {
+        let mut with_metadata = Vec::new();
+        py.detach(|| {
+            self.cache.import_archive(&path, &mut |entry| {
+                if entry.expire_time.is_some() || !entry.tags.is_empty() {
+                    with_metadata.push((entry.key.clone(), entry.expire_time, entry.tags.clone()));
+                }
+            })
+        })?;
+        Ok(with_metadata)
+    }
+}
+
+/// Call a Python loader for its bytes, keeping an exception it raises in
+/// `raised` so it reaches the caller unchanged
+fn call_loader(loader: &Py<PyAny>, raised: &mut Option<PyErr>) -> CacheResult<Vec<u8>> {
+    Python::attach(|py| {
+        loader
+            .call0(py)
+            .and_then(|value| value.extract::<Vec<u8>>(py))
+    })
+    .map_err(|err| {
+        let message = err.to_string();
+        *raised = Some(err);
+        CacheError::Unknown(message)
+    })
+}
+
+fn parse_queue_side(side: &str) -> PyResult<QueueSide> {
+    side.parse()
+        .map_err(|e: CacheError| pyo3::exceptions::PyValueError::new_err(e.to_string()))
+}
+
+/// The group commit window for a Python `group_commit` given in seconds
+fn group_commit_window(seconds: f64) -> CacheResult<Duration> {
+    Duration::try_from_secs_f64(seconds).map_err(|_| {
+        CacheError::Config(ConfigIssue::new(
+            "group_commit",
+            format!(
+                "A group commit window of {} seconds is not a duration",
+                seconds
+            ),
+            "Use a small number of seconds, e.g. 0.005",
+        ))
+    })
+}
+
+/// The lease period for a Python `writer_lease` given in seconds
+fn writer_lease_period(seconds: f64) -> CacheResult<Duration> {
+    Duration::try_from_secs_f64(seconds).map_err(|_| {
+        CacheError::Config(ConfigIssue::new(
+            "writer_lease",
+            format!("A lease period of {} seconds is not a duration", seconds),
+            "Use how many seconds the writer may go without renewing its lease, e.g. 30",
+        ))
+    })
+}
+
+/// The lease period for a Python `maintenance_lease` given in seconds
+fn maintenance_lease_period(seconds: f64) -> CacheResult<Duration> {
+    Duration::try_from_secs_f64(seconds).map_err(|_| {
+        CacheError::Config(ConfigIssue::new(
+            "maintenance_lease",
+            format!("A lease period of {} seconds is not a duration", seconds),
+            "Use how many seconds the maintenance leader may go without renewing its lease, e.g. 7200",
+        ))
+    })
+}
+
+/// The eviction policy `config` asks for, tracking nothing yet
+fn eviction_policy(config: &CacheConfig) -> Box<dyn EvictionPolicy> {
+    Box::new(PriorityEviction::new(|| {
+        CombinedEviction::new(config.eviction_strategy, config.max_entries)
+            .sampled(config.eviction_samples)
+            .max_idle(config.max_idle)
+    }))
+}
+
+/// Evict the entry under an encoded key from `storage`, and forget it
+/// everywhere else
+fn evict_entry(
+    storage: &dyn StorageBackend,
+    eviction: &dyn EvictionPolicy,
+    tag_quotas: &TagQuotas,
+    notifier: &Notifier,
+    stats: &OrderedRwLock<CacheStats>,
+    key: &str,
+) -> CacheResult<()> {
+    let existed = storage.evict(key)?;
+    eviction.on_remove(key);
+    tag_quotas.on_remove(key);
+    if existed {
+        notifier.publish(LOCAL, ChangeKind::Evict, key, &[]);
+    }
+    let mut stats = stats.write();
+    stats.evictions += 1;
+    if existed {
+        stats.entry_count = stats.entry_count.saturating_sub(1);
+    }
+    Ok(())
+}
+
+/// How long entries may go unused for a Python `max_idle` given in seconds
+fn idle_period(seconds: f64) -> CacheResult<Duration> {
+    Duration::try_from_secs_f64(seconds).map_err(|_| {
+        CacheError::Config(ConfigIssue::new(
+            "max_idle",
+            format!("An idle period of {} seconds is not a duration", seconds),
+            "Use how many seconds entries may go unread before they expire, e.g. 3600",
+        ))
+    })
+}
+
+/// The soft delete grace period for a Python `soft_delete` given in seconds
+fn soft_delete_grace(seconds: f64) -> CacheResult<Duration> {
+    Duration::try_from_secs_f64(seconds).map_err(|_| {
+        CacheError::Config(ConfigIssue::new(
+            "soft_delete",
+            format!("A grace period of {} seconds is not a duration", seconds),
+            "Use how many seconds deleted entries stay restorable, e.g. 86400 for a day",
+        ))
+    })
+}
+
+/// The encryption key for a Python `encryption_key`
+///
+/// Takes the secret as bytes, or a callable given the cache directory that
+/// returns it, so the secret can come from a vault or keyring on open
+/// instead of living in the calling code.
+fn resolve_encryption_key(value: &Bound<'_, PyAny>, directory: &Path) -> PyResult<EncryptionKey> {
+    let secret = if value.is_callable() {
+        value.call1((directory.to_string_lossy().into_owned(),))?
+    } else {
+        value.clone()
+    };
+    let secret = secret.extract::<Vec<u8>>().map_err(|_| {
+        CacheError::Config(ConfigIssue::new(
+            "encryption_key",
+            "The encryption key is not bytes",
+            "Pass the secret as bytes, or a callable returning bytes",
+        ))
+    })?;
+    Ok(EncryptionKey::new(&secret)?)
+}
+
+fn recovery_report_to_dict<'py>(
+    py: Python<'py>,
+    report: &RecoveryReport,
+) -> PyResult<Bound<'py, pyo3::types::PyDict>> {
+    let result = pyo3::types::PyDict::new(py);
+    result.set_item("entries_checked", report.entries_checked)?;
+    result.set_item("entries_removed", report.entries_removed)?;
+    result.set_item("entries_restored", report.entries_restored)?;
+    result.set_item("integrity_ok", report.integrity_ok)?;
+    Ok(result)
+}
+
+/// Drop-in replacement for diskcache.Cache
+#[pyclass(name = "Cache")]
+pub struct RustCache {
+    cache: Arc<DiskCache>,
+}
+
+#[pymethods]
+impl RustCache {
+    #[new]
+    #[pyo3(signature = (directory=None, timeout=60.0, **kwargs))]
+    fn new(
+        directory: Option<String>,
+        timeout: f64,
+        kwargs: Option<&Bound<'_, pyo3::types::PyDict>>,
+    ) -> PyResult<Self> {
+        // SQLite busy handling is internal, so diskcache's timeout is accepted but unused
+        let _ = timeout;
+        let mut config = match kwargs {
+            Some(kwargs) => config_from_settings(PathBuf::new(), kwargs)?,
+            None => CacheConfig::default(),
+        };
+        match directory {
+            Some(directory) => set_directory(&mut config, &directory)?,
+            // Memory caches have no use for a directory
+            None if config.backend == StorageKind::Memory => {}
+            None => config.directory = temporary_cache_directory()?,
+        }
+        Ok(Self {
+            cache: Arc::new(DiskCache::new(config)?),
+        })
+    }
+
+    #[pyo3(signature = (key, default=None))]
+    fn get(
+        &self,
+        key: &str,
+        default: Option<&Bound<'_, pyo3::PyAny>>,
+    ) -> PyResult<Option<Vec<u8>>> {
+        match self.cache.get(key)? {
+            Some(value) => Ok(Some(value)),
+            None => {
+                if let Some(default) = default {
+                    // Convert default to bytes if needed
+                    if let Ok(bytes) = default.extract::<Vec<u8>>() {
+                        Ok(Some(bytes))
+                    } else {
+                        Ok(None)
+                    }
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    #[pyo3(signature = (key, value, expire=None, read=None, tag=None, retry=None))]
+    fn set(
+        &self,
+        key: &str,
+        value: Py<PyAny>,
+        expire: Option<u64>,
+        read: Option<bool>,
+        tag: Option<String>,
+        retry: Option<bool>,
+    ) -> PyResult<bool> {
+        let _ = read; // Unused parameter for compatibility
+        let _ = retry; // Unused parameter for compatibility
+
+        let tags = if let Some(tag) = tag {
+            vec![tag]
+        } else {
+            vec![]
+        };
+
+        // Convert PyObject to bytes for internal storage
+        let value_bytes = Python::attach(|py| {
+            let bytes = value.extract::<Vec<u8>>(py)?;
+            Ok::<Vec<u8>, PyErr>(bytes)
+        })?;
+        self.cache.set(key, &value_bytes, expire, tags)?;
+        Ok(true)
+    }
+
+    fn delete(&self, key: &str) -> PyResult<bool> {
+        Ok(self.cache.delete(key)?)
+    }
+
+    // Implement __contains__ for 'key in cache' syntax
+    fn __contains__(&self, key: &str) -> PyResult<bool> {
+        Ok(self.cache.exists(key)?)
+    }
+
+    // Implement iterkeys() for compatibility; keys come back in store order
+    #[pyo3(signature = (reverse=false))]
+    fn iterkeys(&self, reverse: bool) -> PyKeyIterator {
+        PyKeyIterator::new([Arc::clone(&self.cache)], reverse)
+    }
+
+    fn clear(&self) -> PyResult<()> {
+        Ok(self.cache.clear()?)
+    }
+
+    // Return (hits, misses) tuple like diskcache; `aggregate` counts every
+    // process using the directory
+    #[pyo3(signature = (enable=true, reset=false, aggregate=false))]
+    fn stats(&self, enable: bool, reset: bool, aggregate: bool) -> PyResult<(u64, u64)> {
+        let shared = aggregate
+            .then(|| self.cache.aggregate_stats())
+            .transpose()?;
+        let own = self.cache.hit_stats(enable, reset);
+        Ok(shared.map_or(own, |shared| (shared.counts.hits, shared.counts.misses)))
+    }
+
+    #[pyo3(signature = (n=10))]
+    fn top_keys(&self, n: usize) -> Vec<(String, u64)> {
+        self.cache.top_keys(n)
+    }
+
+    fn volume(&self) -> PyResult<u64> {
+        Ok(self.cache.size()?)
+    }
+
+    fn close(&self) -> PyResult<()> {
+        self.cache.close();
+        Ok(())
+    }
+
+    fn __len__(&self) -> PyResult<usize> {
+        let stats = self.cache.stats();
+        Ok(stats.entry_count as usize)
+    }
+}
+
+impl RustCache {
+    /// Open one shard of a `FanoutCache`, whose locks are
+    /// [`LockBackend::fanout_default`] unless `lock_backend` says otherwise
+    fn open_shard(
+        directory: PathBuf,
+        kwargs: Option<&Bound<'_, pyo3::types::PyDict>>,
+    ) -> PyResult<Self> {
+        let config = match kwargs {
+            Some(kwargs) if kwargs.contains("lock_backend")? => {
+                config_from_settings(directory, kwargs)?
+            }
+            Some(kwargs) => CacheConfig {
+                lock_backend: LockBackend::fanout_default(),
+                ..config_from_settings(directory, kwargs)?
+            },
+            None => CacheConfig {
+                directory,
+                lock_backend: LockBackend::fanout_default(),
+                ..Default::default()
+            },
+        };
+        let cache = Arc::new(DiskCache::new(config)?);
+        Ok(Self { cache })
+    }
+}
+
+/// Build a cache config from python-diskcache style settings
+///
+/// Accepts diskcache's names (`size_limit`, `count_limit`, `cull_limit`,
+/// `eviction_policy`, `statistics`, `disk_min_file_size`) alongside this
+/// crate's own options. Settings that only matter to diskcache's SQLite
+/// layout, such as `sqlite_*` pragmas or `tag_index`, are accepted and ignored.
+fn config_from_settings(
+    directory: PathBuf,
+    kwargs: &Bound<'_, pyo3::types::PyDict>,
+) -> PyResult<CacheConfig> {
+    let mut config = CacheConfig {
+        directory,
+        ..Default::default()
+    };
+
+    // Support both size_limit (diskcache) and max_size (new API)
+    if let Ok(Some(size_limit)) = kwargs.get_item("size_limit") {
+        config.max_size = size_limit.extract::<Option<u64>>()?;
+    } else if let Ok(Some(max_size)) = kwargs.get_item("max_size") {
+        config.max_size = max_size.extract::<Option<u64>>()?;
+    }
+
+    // Support both count_limit (diskcache) and max_entries (new API)
+    if let Ok(Some(count_limit)) = kwargs.get_item("count_limit") {
+        config.max_entries = count_limit.extract::<Option<u64>>()?;
+    } else if let Ok(Some(max_entries)) = kwargs.get_item("max_entries") {
+        config.max_entries = max_entries.extract::<Option<u64>>()?;
+    }
+
+    // Support both disk_min_file_size (diskcache) and disk_write_threshold (new API)
+    if let Ok(Some(min_file_size)) = kwargs.get_item("disk_min_file_size") {
+        config.disk_write_threshold = min_file_size.extract::<usize>()?;
+    } else if let Ok(Some(disk_write_threshold)) = kwargs.get_item("disk_write_threshold") {
+        config.disk_write_threshold = disk_write_threshold.extract::<usize>()?;
+    }
+
+    if let Ok(Some(pack_threshold)) = kwargs.get_item("pack_threshold") {
+        config.pack_threshold = pack_threshold.extract::<usize>()?;
+    }
+
+    if let Ok(Some(compaction_ratio)) = kwargs.get_item("compaction_ratio") {
+        config.compaction_ratio = compaction_ratio.extract::<f64>()?;
+    }
+
+    if let Ok(Some(data_fanout)) = kwargs.get_item("data_fanout") {
+        config.data_fanout = data_fanout.extract::<usize>()?;
+    }
+
+    if let Ok(Some(file_naming)) = kwargs.get_item("file_naming") {
+        config.file_naming = file_naming.extract::<String>()?.parse()?;
+    }
+
+    if let Ok(Some(compression)) = kwargs.get_item("compression") {
+        config.compression = compression.extract::<String>()?.parse()?;
+    }
+
+    if let Ok(Some(level)) = kwargs.get_item("compression_level") {
+        config.compression_level = level.extract::<Option<i32>>()?;
+    }
+
+    if let Ok(Some(zstd_dictionary)) = kwargs.get_item("zstd_dictionary") {
+        config.zstd_dictionary = zstd_dictionary.extract::<bool>()?;
+    }
+
+    if let Ok(Some(threshold)) = kwargs.get_item("dictionary_threshold") {
+        config.dictionary_threshold = threshold.extract::<usize>()?;
+    }
+
+    if let Ok(Some(seekable)) = kwargs.get_item("seekable_compression") {
+        config.seekable_compression = seekable.extract::<bool>()?;
+    }
+
+    if let Ok(Some(unlink_workers)) = kwargs.get_item("unlink_workers") {
+        config.unlink_workers = unlink_workers.extract::<usize>()?;
+    }
+
+    if let Ok(Some(unlink_rate)) = kwargs.get_item("unlink_rate") {
+        config.unlink_rate = unlink_rate.extract::<u64>()?;
+    }
+
+    if let Ok(Some(use_file_locking)) = kwargs.get_item("use_file_locking") {
+        config.use_file_locking = use_file_locking.extract::<bool>()?;
+    }
+
+    if let Ok(Some(auto_recover)) = kwargs.get_item("auto_recover") {
+        config.auto_recover = auto_recover.extract::<bool>()?;
+    }
+
+    if let Ok(Some(sync_writes)) = kwargs.get_item("sync_writes") {
+        config.sync_writes = sync_writes.extract::<bool>()?;
+    }
+
+    if let Ok(Some(batch_size)) = kwargs.get_item("batch_size") {
+        config.batch_size = batch_size.extract::<usize>()?;
+    }
+
+    if let Ok(Some(capacity)) = kwargs.get_item("write_queue_capacity") {
+        config.write_queue_capacity = capacity.extract::<usize>()?;
+    }
+
+    if let Ok(Some(queue_full)) = kwargs.get_item("queue_full") {
+        config.queue_full = queue_full.extract::<String>()?.parse()?;
+    }
+
+    if let Ok(Some(policy)) = kwargs.get_item("hot_cache_policy") {
+        config.hot_cache_policy = policy.extract::<String>()?.parse()?;
+    }
+
+    if let Ok(Some(use_mmap)) = kwargs.get_item("use_mmap") {
+        config.use_mmap = use_mmap.extract::<bool>()?;
+    }
+
+    if let Ok(Some(size)) = kwargs.get_item("warm_cache_size") {
+        config.warm_cache_size = size.extract::<usize>()?;
+    }
+
+    if let Ok(Some(bytes)) = kwargs.get_item("warm_cache_bytes") {
+        config.warm_cache_bytes = bytes.extract::<u64>()?;
+    }
+
+    if let Ok(Some(watch)) = kwargs.get_item("watch_directory") {
+        config.watch_directory = watch.extract::<bool>()?;
+    }
+
+    if let Ok(Some(event_log)) = kwargs.get_item("event_log") {
+        config.event_log = event_log.extract::<bool>()?;
+    }
+
+    if let Ok(Some(lock_backend)) = kwargs.get_item("lock_backend") {
+        config.lock_backend = lock_backend.extract::<String>()?.parse()?;
+    }
+
+    if let Ok(Some(granularity)) = kwargs.get_item("lock_granularity") {
+        config.lock_granularity = granularity.extract::<String>()?.parse()?;
+    }
+
+    if let Ok(Some(smb_mode)) = kwargs.get_item("smb_mode") {
+        config.smb_mode = smb_mode.extract::<bool>()?;
+    }
+
+    if let Ok(Some(retries)) = kwargs.get_item("sharing_retries") {
+        config.sharing_retry.attempts = retries.extract::<u32>()?.saturating_add(1);
+    }
+
+    if let Ok(Some(wal)) = kwargs.get_item("wal") {
+        if let Some(wal) = wal.extract::<Option<String>>()? {
+            config.wal = Some(wal.parse()?);
+        }
+    }
+
+    if let Ok(Some(durability)) = kwargs.get_item("durability") {
+        config.durability = durability.extract::<String>()?.parse()?;
+    }
+
+    if let Ok(Some(group_commit)) = kwargs.get_item("group_commit") {
+        if let Some(window) = group_commit.extract::<Option<f64>>()? {
+            config.group_commit = Some(group_commit_window(window)?);
+        }
+    }
+
+    if let Ok(Some(threshold)) = kwargs.get_item("direct_io_threshold") {
+        config.direct_io_threshold = threshold.extract::<Option<usize>>()?;
+    }
+
+    if let Ok(Some(designated_writer)) = kwargs.get_item("designated_writer") {
+        config.designated_writer = designated_writer.extract::<bool>()?;
+    }
+
+    if let Ok(Some(writer_lease)) = kwargs.get_item("writer_lease") {
+        if let Some(period) = writer_lease.extract::<Option<f64>>()? {
+            config.writer_lease = Some(writer_lease_period(period)?);
+        }
+    }
+
+    if let Ok(Some(maintenance_lease)) = kwargs.get_item("maintenance_lease") {
+        if let Some(period) = maintenance_lease.extract::<Option<f64>>()? {
+            config.maintenance_lease = Some(maintenance_lease_period(period)?);
+        }
+    }
+
+    if let Ok(Some(index_key)) = kwargs.get_item("index_key") {
+        if let Some(secret) = index_key.extract::<Option<Vec<u8>>>()? {
+            config.index_key = Some(IndexKey::new(&secret)?);
+        }
+    }
+
+    if let Ok(Some(key)) = kwargs.get_item("encryption_key") {
+        if !key.is_none() {
+            config.encryption_key = Some(resolve_encryption_key(&key, &config.directory)?);
+        }
+    }
+
+    if let Ok(Some(soft_delete)) = kwargs.get_item("soft_delete") {
+        if let Some(grace) = soft_delete.extract::<Option<f64>>()? {
+            config.soft_delete = Some(soft_delete_grace(grace)?);
+        }
+    }
+
+    if let Ok(Some(backend)) = kwargs.get_item("backend") {
+        config.backend = backend.extract::<String>()?.parse()?;
+    }
+
+    if let Ok(Some(capacity)) = kwargs.get_item("ring_capacity_bytes") {
+        config.ring_capacity_bytes = capacity.extract::<Option<u64>>()?;
+    }
+
+    if let Ok(Some(remote_tier)) = kwargs.get_item("remote_tier") {
+        if let Some(location) = remote_tier.extract::<Option<String>>()? {
+            config.remote_tier = Some(open_remote_tier(&location)?);
+        }
+    }
+
+    if let Ok(Some(cull_limit)) = kwargs.get_item("cull_limit") {
+        config.cull_limit = cull_limit.extract::<usize>()?;
+    }
+
+    if let Ok(Some(policy)) = kwargs.get_item("eviction_policy") {
+        config.eviction_strategy = policy.extract::<String>()?.parse()?;
+    }
+
+    if let Ok(Some(samples)) = kwargs.get_item("eviction_samples") {
+        config.eviction_samples = samples.extract::<usize>()?;
+    }
+
+    if let Ok(Some(inline)) = kwargs.get_item("inline_evictions") {
+        config.inline_evictions = inline.extract::<Option<usize>>()?;
+    }
+
+    if let Ok(Some(max_idle)) = kwargs.get_item("max_idle") {
+        if let Some(seconds) = max_idle.extract::<Option<f64>>()? {
+            config.max_idle = Some(idle_period(seconds)?);
+        }
+    }
+
+    if let Ok(Some(statistics)) = kwargs.get_item("statistics") {
+        // diskcache stores this setting as 0/1
+        config.statistics = statistics.is_truthy()?;
+    }
+
+    Ok(config)
+}
+
+/// Prefix of a directory naming the socket of a cache server to attach to
+const SERVER_URL_PREFIX: &str = "unix://";
+
+/// Open `config` in `directory`, or attach it to the cache server listening
+/// on the socket a `unix:///path/to/server.sock` directory names
+fn set_directory(config: &mut CacheConfig, directory: &str) -> CacheResult<()> {
+    let Some(socket) = directory.strip_prefix(SERVER_URL_PREFIX) else {
+        config.directory = PathBuf::from(directory);
+        return Ok(());
+    };
+    if !matches!(config.backend, StorageKind::Optimized | StorageKind::Server) {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "backend",
+            format!(
+                "{} is a cache server's socket, which keeps entries in its own backend",
+                directory
+            ),
+            "Drop the backend option, or choose it when starting the server",
+        )));
+    }
+    config.backend = StorageKind::Server;
+    config.directory = PathBuf::from(socket);
+    Ok(())
+}
+
+/// Fresh directory for caches created without one, as python-diskcache does
+fn temporary_cache_directory() -> PyResult<PathBuf> {
+    let directory = std::env::temp_dir().join(format!("diskcache-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&directory).map_err(CacheError::Io)?;
+    Ok(directory)
+}
+
+/// Drop-in replacement for diskcache.FanoutCache
+#[pyclass(name = "FanoutCache")]
+pub struct RustFanoutCache {
+    caches: Vec<RustCache>,
+    shards: usize,
+}
+
+#[pymethods]
+impl RustFanoutCache {
+    #[new]
+    #[pyo3(signature = (directory=None, shards=Some(8), timeout=0.010, **kwargs))]
+    fn new(
+        directory: Option<String>,
+        shards: Option<usize>,
+        timeout: f64,
+        kwargs: Option<&Bound<'_, pyo3::types::PyDict>>,
+    ) -> PyResult<Self> {
+        let _ = timeout;
+        if let Some(url) = directory
+            .as_ref()
+            .filter(|directory| directory.starts_with(SERVER_URL_PREFIX))
+        {
+            // Shards would all reach the same server, the only writer anyway
+            let cache = RustCache::new(Some(url.clone()), timeout, kwargs)?;
+            return Ok(Self {
+                caches: vec![cache],
+                shards: 1,
+            });
+        }
+        let directory = match directory {
+            Some(directory) => PathBuf::from(directory),
+            None => temporary_cache_directory()?,
+        };
+        let shards = shards.unwrap_or(8);
+        let mut caches = Vec::with_capacity(shards);
+
+        for i in 0..shards {
+            let shard_dir = directory.join(format!("shard_{:03}", i));
+            let cache = RustCache::open_shard(shard_dir, kwargs)?;
+            caches.push(cache);
+        }
+
+        Ok(Self { caches, shards })
+    }
+
+    #[pyo3(signature = (key, default=None))]
+    fn get(
+        &self,
+        key: &str,
+        default: Option<&Bound<'_, pyo3::PyAny>>,
+    ) -> PyResult<Option<Vec<u8>>> {
+        let shard = self.get_shard(key);
+        self.caches[shard].get(key, default)
+    }
+
+    #[pyo3(signature = (key, value, expire=None, read=None, tag=None, retry=None))]
+    fn set(
+        &self,
+        key: &str,
+        value: Py<PyAny>,
+        expire: Option<u64>,
+        read: Option<bool>,
+        tag: Option<String>,
+        retry: Option<bool>,
     ) -> PyResult<bool> {
-        let _ = read; // Unused parameter for compatibility
-        let _ = retry; // Unused parameter for compatibility
+        let shard = self.get_shard(key);
+        self.caches[shard].set(key, value, expire, read, tag, retry)
+    }
+
+    fn delete(&self, key: &str) -> PyResult<bool> {
+        let shard = self.get_shard(key);
+        self.caches[shard].delete(key)
+    }
+
+    fn __contains__(&self, key: &str) -> PyResult<bool> {
+        let shard = self.get_shard(key);
+        self.caches[shard].__contains__(key)
+    }
+
+    fn iterkeys(&self) -> PyKeyIterator {
+        let caches = self.caches.iter().map(|cache| Arc::clone(&cache.cache));
+        PyKeyIterator::new(caches, false)
+    }
+
+    fn clear(&self) -> PyResult<()> {
+        for cache in &self.caches {
+            cache.clear()?;
+        }
+        Ok(())
+    }
+
+    #[pyo3(signature = (enable=true, reset=false, aggregate=false))]
+    fn stats(&self, enable: bool, reset: bool, aggregate: bool) -> PyResult<(u64, u64)> {
+        let mut total_hits = 0;
+        let mut total_misses = 0;
+
+        for cache in &self.caches {
+            let (hits, misses) = cache.stats(enable, reset, aggregate)?;
+            total_hits += hits;
+            total_misses += misses;
+        }
+
+        Ok((total_hits, total_misses))
+    }
+
+    fn volume(&self) -> PyResult<u64> {
+        let mut total_volume = 0;
+        for cache in &self.caches {
+            total_volume += cache.volume()?;
+        }
+        Ok(total_volume)
+    }
+
+    fn __len__(&self) -> PyResult<usize> {
+        let mut total_len = 0;
+        for cache in &self.caches {
+            total_len += cache.__len__()?;
+        }
+        Ok(total_len)
+    }
+}
+
+impl RustFanoutCache {
+    fn get_shard(&self, key: &str) -> usize {
+        // Use BLAKE3 for deterministic hashing across process restarts.
+        // Rust's DefaultHasher (SipHash) uses a per-process random seed,
+        // which causes cache misses when keys are looked up in a different
+        // process than the one that stored them (issue #73).
+        let hash = blake3::hash(key.as_bytes());
+        let hash_bytes = hash.as_bytes();
+        // Use first 8 bytes of the BLAKE3 hash as a u64
+        let hash_value = u64::from_le_bytes([
+            hash_bytes[0],
+            hash_bytes[1],
+            hash_bytes[2],
+            hash_bytes[3],
+            hash_bytes[4],
+            hash_bytes[5],
+            hash_bytes[6],
+            hash_bytes[7],
+        ]);
+        (hash_value as usize) % self.shards
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheConfig, DiskCache, QueueSide, BACKEND_STAGING_DIR, ESTIMATE_HORIZON_DAYS};
+    use crate::changes::{Change, ChangeKind, Topic};
+    use crate::error::CacheError;
+    use crate::storage::StorageKind;
+    use crate::tag_quota::TagLimit;
+    use crate::utils::current_timestamp;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[test]
+    fn disk_cache_set_many_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = DiskCache::with_directory(temp_dir.path()).unwrap();
+
+        cache
+            .set_many(
+                vec![
+                    ("alpha".to_string(), b"one".to_vec()),
+                    ("beta".to_string(), b"two".to_vec()),
+                ],
+                None,
+                vec![],
+            )
+            .unwrap();
+
+        assert_eq!(cache.get("alpha").unwrap(), Some(b"one".to_vec()));
+        assert_eq!(cache.get("beta").unwrap(), Some(b"two".to_vec()));
+
+        cache.close();
+    }
+
+    #[test]
+    fn legacy_entry_files_are_moved_into_storage() {
+        let temp_dir = TempDir::new().unwrap();
+        let write_legacy = |key: &str, data: &[u8], expire_time: Option<u64>| {
+            let entry = crate::serialization::CacheEntry::new(
+                key.into(),
+                data.to_vec(),
+                vec![],
+                expire_time,
+            );
+            let bytes = bincode::serde::encode_to_vec(&entry, bincode::config::legacy()).unwrap();
+            let name = format!("{}.cache", blake3::hash(key.as_bytes()).to_hex());
+            std::fs::write(temp_dir.path().join(name), bytes).unwrap();
+        };
+        for i in 0..50u8 {
+            write_legacy(&format!("old{}", i), &[i; 100], None);
+        }
+        write_legacy("expired", b"gone", Some(1));
+
+        let cache = DiskCache::with_directory(temp_dir.path()).unwrap();
+        // Keys touched before the background pass reaches them are moved first
+        assert_eq!(cache.get("old49").unwrap(), Some(vec![49; 100]));
+        cache.set("old48", b"newer", None, vec![]).unwrap();
+        cache.wait_for_legacy_migration();
+
+        assert_eq!(cache.get("old48").unwrap(), Some(b"newer".to_vec()));
+        for i in 0..48u8 {
+            assert_eq!(cache.get(&format!("old{}", i)).unwrap(), Some(vec![i; 100]));
+        }
+        assert_eq!(cache.get("expired").unwrap(), None);
+        assert!(crate::migration::find_legacy_entry_files(temp_dir.path()).is_empty());
+        cache.close();
+    }
+
+    #[test]
+    fn only_the_writer_lease_holder_writes() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = || CacheConfig {
+            directory: temp_dir.path().to_path_buf(),
+            writer_lease: Some(Duration::from_secs(30)),
+            soft_delete: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        let writer = DiskCache::new(config()).unwrap();
+        let reader = DiskCache::new(config()).unwrap();
+        assert!(!writer.is_read_only());
+        assert!(reader.is_read_only());
+
+        writer.set("key", b"value", None, vec![]).unwrap();
+        assert!(matches!(
+            reader.set("other", b"value", None, vec![]),
+            Err(CacheError::ReadOnly(_))
+        ));
+        assert!(matches!(reader.delete("key"), Err(CacheError::ReadOnly(_))));
+        assert_eq!(reader.get("key").unwrap(), Some(b"value".to_vec()));
+        // The refused delete left nothing to undelete
+        writer.set("key", b"rewritten", None, vec![]).unwrap();
+        assert!(!writer.undelete("key").unwrap());
+
+        writer.close();
+        reader.set("other", b"value", None, vec![]).unwrap();
+        assert!(!reader.is_read_only());
+        assert!(writer.is_read_only());
+    }
+
+    #[test]
+    fn maintenance_is_left_to_the_lease_holder() {
+        let temp_dir = TempDir::new().unwrap();
+        let open = || {
+            DiskCache::new(CacheConfig {
+                directory: temp_dir.path().to_path_buf(),
+                maintenance_lease: Some(Duration::from_secs(4)),
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let (leader, other) = (open(), open());
+        assert!(leader.is_maintenance_leader());
+        assert!(!other.is_maintenance_leader());
+
+        let expired = current_timestamp() - 1;
+        leader.set("old", b"value", Some(expired), vec![]).unwrap();
+        // Past the two-second maintenance interval, counted in whole seconds
+        std::thread::sleep(Duration::from_millis(3100));
+        // Due for maintenance, but another process leads it
+        other.set("new", b"value", None, vec![]).unwrap();
+        assert!(other.storage().keys().unwrap().contains(&"old".to_string()));
+        leader.set("newer", b"value", None, vec![]).unwrap();
+        assert!(!other.storage().keys().unwrap().contains(&"old".to_string()));
+        assert!(leader.is_maintenance_leader());
+    }
+
+    #[test]
+    fn soft_deleted_entries_can_be_undeleted() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = |grace| CacheConfig {
+            directory: temp_dir.path().to_path_buf(),
+            soft_delete: Some(grace),
+            ..Default::default()
+        };
+        let cache = DiskCache::new(config(Duration::from_secs(3600))).unwrap();
+        cache.set("key", b"value", None, vec![]).unwrap();
+        assert!(cache.delete("key").unwrap());
+        assert_eq!(cache.get("key").unwrap(), None);
+
+        assert!(cache.undelete("key").unwrap());
+        assert_eq!(cache.get("key").unwrap(), Some(b"value".to_vec()));
+        assert!(!cache.undelete("key").unwrap());
+
+        // Pulled queue items are gone for good
+        cache
+            .push("queue", b"item", QueueSide::Back, None, vec![])
+            .unwrap();
+        let item = cache.pull("queue", QueueSide::Front).unwrap().unwrap();
+        assert!(!cache.undelete(&item.key).unwrap());
+
+        cache.delete("key").unwrap();
+        cache.close();
+        drop(cache);
+        let cache = DiskCache::new(config(Duration::ZERO)).unwrap();
+        assert!(!cache.undelete("key").unwrap());
+        assert_eq!(
+            std::fs::read_dir(temp_dir.path().join("trash"))
+                .unwrap()
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn disk_cache_concurrent_mixed_operations() {
+        // Debug builds check the lock hierarchy on every acquisition here
+        let temp_dir = TempDir::new().unwrap();
+        let config = CacheConfig {
+            directory: temp_dir.path().to_path_buf(),
+            max_entries: Some(50),
+            eviction_strategy: crate::eviction::EvictionStrategy::LruTtl,
+            wal: Some(crate::storage::WalSyncPolicy::Never),
+            ..Default::default()
+        };
+        let cache = std::sync::Arc::new(DiskCache::new(config).unwrap());
+
+        let workers: Vec<_> = (0..4)
+            .map(|worker| {
+                let cache = cache.clone();
+                std::thread::spawn(move || {
+                    for i in 0..100 {
+                        let key = format!("key:{}", (worker * 7 + i) % 60);
+                        cache
+                            .set(&key, &[i as u8; 64], Some(u64::MAX), vec![])
+                            .unwrap();
+                        cache.get(&key).unwrap();
+                        if i % 10 == 0 {
+                            cache.delete(&key).unwrap();
+                            cache
+                                .push("jobs", b"job", QueueSide::Back, None, vec![])
+                                .unwrap();
+                            cache.pull("jobs", QueueSide::Front).unwrap();
+                        }
+                        if i % 50 == 0 {
+                            cache.stats();
+                            cache.vacuum().unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        cache.clear().unwrap();
+        assert!(cache.keys().unwrap().is_empty());
+        cache.close();
+    }
+
+    #[test]
+    fn cas_increments_from_several_handles_lose_no_updates() {
+        let temp_dir = TempDir::new().unwrap();
+        let first = DiskCache::with_directory(temp_dir.path()).unwrap();
+        assert!(first.cas("counter", None, b"0", None, vec![]).unwrap());
+        assert!(!first.cas("counter", None, b"0", None, vec![]).unwrap());
+
+        // Each handle stands in for another process sharing the directory
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = DiskCache::with_directory(temp_dir.path()).unwrap();
+                std::thread::spawn(move || {
+                    for _ in 0..25 {
+                        loop {
+                            // A reader can miss the key while another handle rewrites it
+                            let (count, version) = match cache.get_with_version("counter").unwrap()
+                            {
+                                Some((value, version)) => (
+                                    std::str::from_utf8(&value).unwrap().parse::<u64>().unwrap(),
+                                    Some(version),
+                                ),
+                                None => (0, None),
+                            };
+                            let next = (count + 1).to_string();
+                            if cache
+                                .cas("counter", version, next.as_bytes(), None, vec![])
+                                .unwrap()
+                            {
+                                break;
+                            }
+                        }
+                    }
+                    cache.close();
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        assert_eq!(first.get("counter").unwrap(), Some(b"100".to_vec()));
+        let (_, version) = first.get_with_version("counter").unwrap().unwrap();
+        first.set("counter", b"reset", None, vec![]).unwrap();
+        assert!(!first
+            .cas("counter", Some(version), b"101", None, vec![])
+            .unwrap());
+        first.close();
+    }
+
+    #[test]
+    fn get_or_set_loads_once_across_handles() {
+        let temp_dir = TempDir::new().unwrap();
+        let loads = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+        // Each handle stands in for another process sharing the directory
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = DiskCache::with_directory(temp_dir.path()).unwrap();
+                let loads = loads.clone();
+                std::thread::spawn(move || {
+                    let value = cache
+                        .get_or_set("key", None, None, vec![], || {
+                            loads.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                            std::thread::sleep(Duration::from_millis(200));
+                            Ok(b"loaded".to_vec())
+                        })
+                        .unwrap();
+                    cache.close();
+                    value
+                })
+            })
+            .collect();
+        for worker in workers {
+            assert_eq!(worker.join().unwrap(), b"loaded");
+        }
+        assert_eq!(loads.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn get_or_set_serves_stale_values_while_one_caller_reloads() {
+        let temp_dir = TempDir::new().unwrap();
+        let first = DiskCache::with_directory(temp_dir.path()).unwrap();
+        let second = DiskCache::with_directory(temp_dir.path()).unwrap();
+        let expired = Some(current_timestamp() - 1);
+        let value = first
+            .get_or_set("key", expired, Some(60), vec![], || Ok(b"old".to_vec()))
+            .unwrap();
+        assert_eq!(value, b"old");
+
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let reloading = std::thread::spawn(move || {
+            first
+                .get_or_set("key", Some(u64::MAX - 60), Some(60), vec![], || {
+                    started_tx.send(()).unwrap();
+                    std::thread::sleep(Duration::from_millis(200));
+                    Ok(b"new".to_vec())
+                })
+                .unwrap()
+        });
+        started_rx.recv().unwrap();
+        let value = second
+            .get_or_set("key", expired, Some(60), vec![], || {
+                panic!("the stale value is being reloaded")
+            })
+            .unwrap();
+        assert_eq!(value, b"old");
+
+        assert_eq!(reloading.join().unwrap(), b"new");
+        assert_eq!(second.get("key").unwrap(), Some(b"new".to_vec()));
+    }
+
+    #[test]
+    fn disk_cache_queue_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = DiskCache::with_directory(temp_dir.path()).unwrap();
+
+        let push = |value: &[u8], side| cache.push("jobs", value, side, None, vec![]).unwrap();
+        assert_eq!(push(b"b", QueueSide::Back).0, 0);
+        assert_eq!(push(b"c", QueueSide::Back).0, 1);
+        let (position, key) = push(b"a", QueueSide::Front);
+        assert_eq!(position, -1);
+        assert_eq!(key, "jobs-499999999999999");
+
+        let front = cache.pull("jobs", QueueSide::Front).unwrap().unwrap();
+        assert_eq!((front.position, front.value), (-1, b"a".to_vec()));
+        let back = cache.pull("jobs", QueueSide::Back).unwrap().unwrap();
+        assert_eq!((back.position, back.value), (1, b"c".to_vec()));
+        let peeked = cache.peek_queue("jobs", QueueSide::Front).unwrap().unwrap();
+        assert_eq!(peeked.value, b"b".to_vec());
+
+        // Numbering continues from the remaining item
+        assert_eq!(push(b"d", QueueSide::Back).0, 1);
+        assert!(cache.pull("other", QueueSide::Front).unwrap().is_none());
+
+        cache.close();
+    }
+
+    #[test]
+    fn disk_cache_peekitem_moves_past_entries_whose_file_is_gone() {
+        let temp_dir = TempDir::new().unwrap();
+        // Incompressible, so it is kept verbatim in a data file
+        let mut large = vec![0u8; 256 * 1024];
+        blake3::Hasher::new().finalize_xof().fill(&mut large);
+        {
+            let cache = DiskCache::with_directory(temp_dir.path()).unwrap();
+            cache.set("oldest", &large, None, vec![]).unwrap();
+            cache.set("newest", b"value", None, vec![]).unwrap();
+            let (path, _) = cache.data_file("oldest").unwrap().unwrap();
+            std::fs::remove_file(path).unwrap();
+            cache.close();
+        }
+
+        let cache = DiskCache::with_directory(temp_dir.path()).unwrap();
+        let (key, value) = cache.peekitem(false).unwrap().unwrap();
+        assert_eq!((key.as_str(), value.as_slice()), ("newest", &b"value"[..]));
+        // The entry is dropped rather than found again
+        assert_eq!(cache.keys().unwrap(), ["newest"]);
+        cache.close();
+    }
+
+    #[test]
+    fn disk_cache_queue_moves_past_unreadable_items() {
+        let temp_dir = TempDir::new().unwrap();
+        // Without key locks, a row whose file is gone is kept for its writer
+        let cache = DiskCache::new(CacheConfig {
+            directory: temp_dir.path().to_path_buf(),
+            smb_mode: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let mut large = vec![0u8; 256 * 1024];
+        blake3::Hasher::new().finalize_xof().fill(&mut large);
+        let (_, unreadable) = cache
+            .push("jobs", &large, QueueSide::Back, None, vec![])
+            .unwrap();
+        cache
+            .push("jobs", b"next", QueueSide::Back, None, vec![])
+            .unwrap();
+        let (path, _) = cache.data_file(&unreadable).unwrap().unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let peeked = cache.peek_queue("jobs", QueueSide::Front).unwrap().unwrap();
+        assert_eq!(peeked.value, b"next".to_vec());
+        let pulled = cache.pull("jobs", QueueSide::Front).unwrap().unwrap();
+        assert_eq!((pulled.position, pulled.value), (1, b"next".to_vec()));
+        assert!(cache.pull("jobs", QueueSide::Front).unwrap().is_none());
+        cache.close();
+    }
+
+    #[test]
+    fn disk_cache_queue_skips_other_keys_with_its_prefix() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = DiskCache::with_directory(temp_dir.path()).unwrap();
+
+        let strays = ["jobs-1", "jobs-5x", "jobs-999999999999999-extra"];
+        for key in strays {
+            cache.set(key, b"stray", None, vec![]).unwrap();
+        }
+        assert!(cache
+            .peek_queue("jobs", QueueSide::Front)
+            .unwrap()
+            .is_none());
+        assert!(cache.peek_queue("jobs", QueueSide::Back).unwrap().is_none());
+
+        let push = |value: &[u8], side| cache.push("jobs", value, side, None, vec![]).unwrap();
+        assert_eq!(push(b"b", QueueSide::Back).0, 0);
+        assert_eq!(push(b"a", QueueSide::Front).0, -1);
+        assert_eq!(push(b"c", QueueSide::Back).0, 1);
+        let front = cache.pull("jobs", QueueSide::Front).unwrap().unwrap();
+        assert_eq!((front.position, front.value), (-1, b"a".to_vec()));
+        let back = cache.pull("jobs", QueueSide::Back).unwrap().unwrap();
+        assert_eq!((back.position, back.value), (1, b"c".to_vec()));
+
+        for key in strays {
+            assert_eq!(cache.get(key).unwrap(), Some(b"stray".to_vec()));
+        }
+        cache.close();
+
+        // Codecs may decode a stray key to anything at all
+        struct Renaming;
+        impl crate::serialization::Disk for Renaming {
+            fn get(&self, key: &str) -> crate::error::CacheResult<String> {
+                let numbered = key.ends_with(|c: char| c.is_ascii_digit());
+                Ok(if numbered {
+                    key.to_string()
+                } else {
+                    "é".to_string()
+                })
+            }
+            fn store(&self, value: &[u8]) -> crate::error::CacheResult<Vec<u8>> {
+                Ok(value.to_vec())
+            }
+            fn fetch(&self, data: &[u8]) -> crate::error::CacheResult<Vec<u8>> {
+                Ok(data.to_vec())
+            }
+        }
+        let temp_dir = TempDir::new().unwrap();
+        let config = CacheConfig {
+            directory: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let cache = DiskCache::with_disk(config, std::sync::Arc::new(Renaming)).unwrap();
+        cache.set("jobs-5x", b"stray", None, vec![]).unwrap();
+        assert!(cache.peek_queue("jobs", QueueSide::Back).unwrap().is_none());
+        cache
+            .push("jobs", b"job", QueueSide::Back, None, vec![])
+            .unwrap();
+        let pulled = cache.pull("jobs", QueueSide::Back).unwrap().unwrap();
+        assert_eq!(pulled.value, b"job".to_vec());
+        cache.close();
+    }
+
+    #[test]
+    fn disk_cache_get_many_keeps_request_order() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = DiskCache::with_directory(temp_dir.path()).unwrap();
 
-        let tags = if let Some(tag) = tag {
-            vec![tag]
-        } else {
-            vec![]
-        };
+        let large = vec![5u8; 256 * 1024];
+        for i in 0..12 {
+            let value = if i % 3 == 0 {
+                large.clone()
+            } else {
+                vec![i as u8]
+            };
+            cache
+                .set(&format!("user:{}", i), &value, None, vec![])
+                .unwrap();
+        }
 
-        // Convert PyObject to bytes for internal storage
-        let value_bytes = Python::attach(|py| {
-            let bytes = value.extract::<Vec<u8>>(py)?;
-            Ok::<Vec<u8>, PyErr>(bytes)
-        })?;
-        self.cache.set(key, &value_bytes, expire, tags)?;
-        Ok(true)
-    }
+        let keys: Vec<String> = (0..12).rev().map(|i| format!("user:{}", i)).collect();
+        let mut requested: Vec<&str> = keys.iter().map(String::as_str).collect();
+        requested.insert(3, "user:missing");
+        let values = cache.get_many(&requested, Some("user:")).unwrap();
 
-    fn delete(&self, key: &str) -> PyResult<bool> {
-        Ok(self.cache.delete(key)?)
-    }
+        assert_eq!(values.len(), requested.len());
+        assert!(values[3].is_none());
+        for (key, value) in requested.iter().zip(&values) {
+            assert_eq!(value, &cache.get(key).unwrap(), "{}", key);
+        }
+        assert_eq!(values[2].as_deref(), Some(&large[..]));
 
-    // Implement __contains__ for 'key in cache' syntax
-    fn __contains__(&self, key: &str) -> PyResult<bool> {
-        Ok(self.cache.exists(key)?)
+        cache.close();
     }
 
-    // Implement iterkeys() for compatibility
-    fn iterkeys(&self) -> PyResult<Vec<String>> {
-        Ok(self.cache.keys()?)
-    }
+    #[test]
+    fn disk_cache_hit_stats_reset_and_disable() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = DiskCache::with_directory(temp_dir.path()).unwrap();
+        cache.set("key", b"value", None, vec![]).unwrap();
 
-    fn clear(&self) -> PyResult<()> {
-        Ok(self.cache.clear()?)
-    }
+        // Off until enabled, as in diskcache
+        cache.get("key").unwrap();
+        cache.get("missing").unwrap();
+        assert_eq!(cache.hit_stats(true, false), (0, 0));
 
-    fn stats(&self) -> PyResult<(u64, u64)> {
-        let stats = self.cache.stats();
-        // Return (hits, misses) tuple like diskcache
-        Ok((stats.hits, stats.misses))
-    }
+        cache.get("key").unwrap();
+        cache.get("missing").unwrap();
+        assert_eq!(cache.hit_stats(true, true), (1, 1));
+        assert_eq!(cache.hit_stats(false, false), (0, 0));
 
-    fn volume(&self) -> PyResult<u64> {
-        Ok(self.cache.size()?)
-    }
+        cache.get("key").unwrap();
+        cache.get("missing").unwrap();
+        assert_eq!(cache.hit_stats(true, false), (0, 0));
 
-    fn close(&self) -> PyResult<()> {
-        self.cache.close();
-        Ok(())
-    }
+        cache.get("key").unwrap();
+        assert_eq!(cache.hit_stats(true, false), (1, 0));
 
-    fn __len__(&self) -> PyResult<usize> {
-        let stats = self.cache.stats();
-        Ok(stats.entry_count as usize)
+        cache.close();
     }
-}
-
-/// Drop-in replacement for diskcache.FanoutCache
-#[pyclass(name = "FanoutCache")]
-pub struct RustFanoutCache {
-    caches: Vec<RustCache>,
-    shards: usize,
-}
 
-#[pymethods]
-impl RustFanoutCache {
-    #[new]
-    #[pyo3(signature = (directory, shards=Some(8), **kwargs))]
-    fn new(
-        directory: String,
-        shards: Option<usize>,
-        kwargs: Option<&Bound<'_, pyo3::types::PyDict>>,
-    ) -> PyResult<Self> {
-        let shards = shards.unwrap_or(8);
-        let mut caches = Vec::with_capacity(shards);
+    #[test]
+    fn disk_cache_estimate_scales_with_ttl() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = DiskCache::with_directory(temp_dir.path()).unwrap();
 
-        for i in 0..shards {
-            let shard_dir = format!("{}/shard_{:03}", directory, i);
-            let cache = RustCache::new(shard_dir, kwargs)?;
-            caches.push(cache);
+        let day = Duration::from_secs(24 * 60 * 60);
+        let small = cache.estimate(10_000, 1024, Some(day));
+        assert_eq!(small.entries, 10_000);
+        assert!(small.disk_bytes > 10_000 * 1024);
+        assert!(small.fits_current_limits);
+        assert_eq!(small.recommended_max_entries, 12_500);
+
+        let half_day = cache.estimate(10_000, 1024, Some(day / 2));
+        assert_eq!(half_day.entries, 5_000);
+
+        // File-backed values round up to whole blocks on disk
+        let large = cache.estimate(1_000, 100 * 1024, Some(day));
+        assert!(large.disk_bytes >= 1_000 * 100 * 1024);
+        assert!(large.memory_bytes < large.disk_bytes);
+
+        let unbounded = cache.estimate(10_000, 1024, None);
+        assert_eq!(unbounded.entries, 10_000 * ESTIMATE_HORIZON_DAYS);
+        assert!(!unbounded.fits_current_limits);
+
+        // Workloads too large to count in bytes report the largest count
+        for value_size in [100, 4 * 1024, 100 * 1024, usize::MAX] {
+            let huge = cache.estimate(u64::MAX, value_size, Some(Duration::MAX));
+            assert_eq!(huge.entries, u64::MAX);
+            assert_eq!(huge.disk_bytes, u64::MAX);
+            assert_eq!(huge.recommended_max_size, u64::MAX);
         }
 
-        Ok(Self { caches, shards })
+        cache.close();
     }
 
-    fn get(
-        &self,
-        key: &str,
-        default: Option<&Bound<'_, pyo3::PyAny>>,
-    ) -> PyResult<Option<Vec<u8>>> {
-        let shard = self.get_shard(key);
-        self.caches[shard].get(key, default)
-    }
+    #[test]
+    fn eviction_strategy_switches_at_runtime() {
+        use crate::eviction::EvictionStrategy;
 
-    #[pyo3(signature = (key, value, expire=None, read=None, tag=None, retry=None))]
-    fn set(
-        &self,
-        key: &str,
-        value: Py<PyAny>,
-        expire: Option<u64>,
-        read: Option<bool>,
-        tag: Option<String>,
-        retry: Option<bool>,
-    ) -> PyResult<bool> {
-        let shard = self.get_shard(key);
-        self.caches[shard].set(key, value, expire, read, tag, retry)
-    }
+        let temp_dir = TempDir::new().unwrap();
+        let cache = DiskCache::new(CacheConfig {
+            directory: temp_dir.path().to_path_buf(),
+            eviction_strategy: EvictionStrategy::Lru,
+            ..Default::default()
+        })
+        .unwrap();
+        for i in 0..4 {
+            cache
+                .set(&format!("k{}", i), b"value", None, vec![])
+                .unwrap();
+        }
+        cache.get("k0").unwrap();
+        assert_eq!(
+            cache.eviction().select_victims(usize::MAX),
+            ["k1", "k2", "k3", "k0"]
+        );
 
-    fn delete(&self, key: &str) -> PyResult<bool> {
-        let shard = self.get_shard(key);
-        self.caches[shard].delete(key)
-    }
+        // The order carries over, and counts from then on
+        cache.set_eviction_strategy(EvictionStrategy::Lfu).unwrap();
+        assert_eq!(cache.eviction_strategy(), EvictionStrategy::Lfu);
+        cache.get("k1").unwrap();
+        cache.get("k1").unwrap();
+        assert_eq!(
+            cache.eviction().select_victims(usize::MAX),
+            ["k2", "k3", "k0", "k1"]
+        );
 
-    fn __contains__(&self, key: &str) -> PyResult<bool> {
-        let shard = self.get_shard(key);
-        self.caches[shard].__contains__(key)
+        // Keys stored under either strategy are still evicted
+        cache.set("k4", b"value", None, vec![]).unwrap();
+        cache.set_eviction_strategy(EvictionStrategy::Lru).unwrap();
+        assert_eq!(cache.eviction().select_victims(usize::MAX).len(), 5);
+        cache.close();
     }
 
-    fn iterkeys(&self) -> PyResult<Vec<String>> {
-        let mut all_keys = Vec::new();
-        for cache in &self.caches {
-            let keys = cache.iterkeys()?;
-            all_keys.extend(keys);
+    #[test]
+    fn victims_past_inline_evictions_are_evicted_in_the_background() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = DiskCache::new(CacheConfig {
+            directory: temp_dir.path().to_path_buf(),
+            max_size: Some(10_000),
+            cull_limit: 1_000,
+            inline_evictions: Some(2),
+            eviction_strategy: crate::eviction::EvictionStrategy::Lru,
+            ..Default::default()
+        })
+        .unwrap();
+        for i in 0..100 {
+            cache
+                .set(&format!("key{}", i), &[0; 100], None, vec![])
+                .unwrap();
         }
-        Ok(all_keys)
+        assert_eq!(cache.stats().evictions, 0);
+
+        // Room for this takes some fifty evictions, two of them inline
+        cache.set("large", &[0; 5_000], None, vec![]).unwrap();
+        for _ in 0..500 {
+            if cache.eviction_backlog().entries == 0 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(cache.eviction_backlog(), Default::default());
+        assert!(cache.stats().evictions >= 50);
+        assert!(cache.size().unwrap() <= 10_000);
+        assert!(cache.exists("large").unwrap());
+        assert!(!cache.exists("key0").unwrap());
+        assert!(cache.exists("key99").unwrap());
+        cache.close();
     }
 
-    fn clear(&self) -> PyResult<()> {
-        for cache in &self.caches {
-            cache.clear()?;
+    #[test]
+    fn keys_stored_again_while_queued_survive_background_eviction() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = DiskCache::new(CacheConfig {
+            directory: temp_dir.path().to_path_buf(),
+            max_size: Some(10_000),
+            cull_limit: 1_000,
+            inline_evictions: Some(2),
+            eviction_strategy: crate::eviction::EvictionStrategy::Lru,
+            ..Default::default()
+        })
+        .unwrap();
+        for i in 0..100 {
+            cache
+                .set(&format!("key{}", i), &[0; 100], None, vec![])
+                .unwrap();
         }
-        Ok(())
+
+        // The background thread stops at key2, first in the queue, until the
+        // lock is released, by which time key2 holds a new value
+        let lock = cache.lock_key("key2").unwrap();
+        cache.set("large", &[0; 5_000], None, vec![]).unwrap();
+        assert!(cache.eviction_backlog().entries > 0);
+        std::thread::sleep(Duration::from_millis(50));
+        cache.set("key2", b"stored again", None, vec![]).unwrap();
+        drop(lock);
+
+        for _ in 0..500 {
+            if cache.eviction_backlog().entries == 0 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(cache.eviction_backlog(), Default::default());
+        assert!(!cache.exists("key3").unwrap());
+        assert_eq!(cache.get("key2").unwrap(), Some(b"stored again".to_vec()));
+        cache.close();
     }
 
-    fn stats(&self) -> PyResult<(u64, u64)> {
-        let mut total_hits = 0;
-        let mut total_misses = 0;
+    #[test]
+    fn disk_cache_cull_limit_bounds_evictions_per_write() {
+        let open = |dir: &TempDir, cull_limit| {
+            DiskCache::new(CacheConfig {
+                directory: dir.path().to_path_buf(),
+                max_entries: Some(20),
+                cull_limit,
+                ..Default::default()
+            })
+            .unwrap()
+        };
 
-        for cache in &self.caches {
-            let (hits, misses) = cache.stats()?;
-            total_hits += hits;
-            total_misses += misses;
+        let temp_dir = TempDir::new().unwrap();
+        let cache = open(&temp_dir, 1);
+        for i in 0..30 {
+            cache
+                .set(&format!("key{}", i), b"value", None, vec![])
+                .unwrap();
         }
+        // Without the cap the first write over the limit would drop three entries
+        assert_eq!(cache.stats().evictions, 9);
+        cache.close();
 
-        Ok((total_hits, total_misses))
+        let temp_dir = TempDir::new().unwrap();
+        let cache = open(&temp_dir, 0);
+        for i in 0..30 {
+            cache
+                .set(&format!("key{}", i), b"value", None, vec![])
+                .unwrap();
+        }
+        assert_eq!(cache.stats().evictions, 0);
+        assert_eq!(cache.keys().unwrap().len(), 30);
+        cache.close();
     }
 
-    fn volume(&self) -> PyResult<u64> {
-        let mut total_volume = 0;
-        for cache in &self.caches {
-            total_volume += cache.volume()?;
+    #[test]
+    fn cull_limit_does_not_cap_evictions_for_max_size() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = DiskCache::new(CacheConfig {
+            directory: temp_dir.path().to_path_buf(),
+            max_size: Some(1_000),
+            cull_limit: 3,
+            eviction_strategy: crate::eviction::EvictionStrategy::Lru,
+            ..Default::default()
+        })
+        .unwrap();
+        for i in 0..10 {
+            cache
+                .set(&format!("key{}", i), &[0; 100], None, vec![])
+                .unwrap();
         }
-        Ok(total_volume)
+
+        // Room for this takes six of the small entries
+        cache.set("large", &[0; 600], None, vec![]).unwrap();
+        assert_eq!(cache.stats().evictions, 6);
+        assert!(cache.size().unwrap() <= 1_000);
+        assert!(cache.exists("large").unwrap());
+        cache.close();
     }
 
-    fn __len__(&self) -> PyResult<usize> {
-        let mut total_len = 0;
-        for cache in &self.caches {
-            total_len += cache.__len__()?;
+    #[test]
+    fn disk_cache_max_size_evicts_just_enough_bytes() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = DiskCache::new(CacheConfig {
+            directory: temp_dir.path().to_path_buf(),
+            max_size: Some(10_000),
+            ..Default::default()
+        })
+        .unwrap();
+
+        for i in 0..100 {
+            let value = vec![i as u8; if i % 10 == 0 { 3_000 } else { 400 }];
+            cache
+                .set(&format!("key{}", i), &value, None, vec![])
+                .unwrap();
+            let size = cache.size().unwrap();
+            assert!(size <= 10_000, "{} bytes after key{}", size, i);
+            // Once full, never more evicted than the largest value needs
+            if i >= 20 {
+                assert!(size > 10_000 - 3_000 - 400, "{} bytes after key{}", size, i);
+            }
         }
-        Ok(total_len)
+        cache.close();
     }
-}
 
-impl RustFanoutCache {
-    fn get_shard(&self, key: &str) -> usize {
-        // Use BLAKE3 for deterministic hashing across process restarts.
-        // Rust's DefaultHasher (SipHash) uses a per-process random seed,
-        // which causes cache misses when keys are looked up in a different
-        // process than the one that stored them (issue #73).
-        let hash = blake3::hash(key.as_bytes());
-        let hash_bytes = hash.as_bytes();
-        // Use first 8 bytes of the BLAKE3 hash as a u64
-        let hash_value = u64::from_le_bytes([
-            hash_bytes[0],
-            hash_bytes[1],
-            hash_bytes[2],
-            hash_bytes[3],
-            hash_bytes[4],
-            hash_bytes[5],
-            hash_bytes[6],
-            hash_bytes[7],
-        ]);
-        (hash_value as usize) % self.shards
+    #[test]
+    fn entries_left_unread_for_max_idle_expire() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = DiskCache::new(CacheConfig {
+            directory: temp_dir.path().to_path_buf(),
+            max_idle: Some(Duration::from_millis(300)),
+            ..Default::default()
+        })
+        .unwrap();
+        cache.set("read", b"1", None, vec![]).unwrap();
+        cache.set("unread", b"2", None, vec![]).unwrap();
+
+        std::thread::sleep(Duration::from_millis(200));
+        assert_eq!(cache.get("read").unwrap(), Some(b"1".to_vec()));
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(!cache.exists("unread").unwrap());
+        assert_eq!(cache.get("read").unwrap(), Some(b"1".to_vec()));
+
+        // Writes sweep out what went idle without being asked for
+        std::thread::sleep(Duration::from_millis(400));
+        cache.set("fresh", b"3", None, vec![]).unwrap();
+        assert_eq!(cache.stats().entry_count, 1);
+        assert!(cache.exists("fresh").unwrap());
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::DiskCache;
-    use tempfile::TempDir;
 
     #[test]
-    fn disk_cache_set_many_round_trip() {
+    fn tag_limits_evict_within_the_tag() {
         let temp_dir = TempDir::new().unwrap();
-        let cache = DiskCache::with_directory(temp_dir.path()).unwrap();
+        let cache = DiskCache::new(CacheConfig {
+            directory: temp_dir.path().to_path_buf(),
+            eviction_strategy: crate::eviction::EvictionStrategy::Lru,
+            ..Default::default()
+        })
+        .unwrap();
+        let tagged = |tag: &str| vec![tag.to_string()];
+        for i in 0..10 {
+            cache
+                .set(&format!("b{}", i), &[0; 100], None, tagged("tenant-b"))
+                .unwrap();
+        }
+        cache
+            .set_tag_limit(
+                "tenant-a",
+                TagLimit {
+                    max_bytes: Some(1_000),
+                    max_entries: Some(5),
+                },
+            )
+            .unwrap();
 
+        for i in 0..20 {
+            cache
+                .set(&format!("a{}", i), &[0; 100], None, tagged("tenant-a"))
+                .unwrap();
+        }
+        // The newest five of the limited tag, and all of the other
+        assert!((15..20).all(|i| cache.exists(&format!("a{}", i)).unwrap()));
+        assert!(!(0..15).any(|i| cache.exists(&format!("a{}", i)).unwrap()));
+        assert!((0..10).all(|i| cache.exists(&format!("b{}", i)).unwrap()));
+
+        // Entries already stored count towards a limit set later
         cache
-            .set_many(
-                vec![
-                    ("alpha".to_string(), b"one".to_vec()),
-                    ("beta".to_string(), b"two".to_vec()),
-                ],
-                None,
-                vec![],
+            .set_tag_limit(
+                "tenant-b",
+                TagLimit {
+                    max_bytes: Some(450),
+                    max_entries: None,
+                },
             )
             .unwrap();
+        assert!(!(0..6).any(|i| cache.exists(&format!("b{}", i)).unwrap()));
+        assert!((6..10).all(|i| cache.exists(&format!("b{}", i)).unwrap()));
+
+        assert!(cache.remove_tag_limit("tenant-a"));
+        for i in 20..30 {
+            cache
+                .set(&format!("a{}", i), &[0; 100], None, tagged("tenant-a"))
+                .unwrap();
+        }
+        assert!((15..30).all(|i| cache.exists(&format!("a{}", i)).unwrap()));
+        cache.close();
+    }
 
-        assert_eq!(cache.get("alpha").unwrap(), Some(b"one".to_vec()));
-        assert_eq!(cache.get("beta").unwrap(), Some(b"two".to_vec()));
+    #[test]
+    fn eviction_order_survives_a_restart() {
+        let temp_dir = TempDir::new().unwrap();
+        let open = || {
+            DiskCache::new(CacheConfig {
+                directory: temp_dir.path().to_path_buf(),
+                max_size: Some(20 * 100),
+                eviction_strategy: crate::eviction::EvictionStrategy::Lru,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let cache = open();
+        for i in 0..20 {
+            cache
+                .set(&format!("key{}", i), &[0; 100], None, vec![])
+                .unwrap();
+        }
+        for i in 0..5 {
+            cache.get(&format!("key{}", i)).unwrap().unwrap();
+        }
+        cache.close();
+
+        // The least recently used before the restart go first after it
+        let cache = open();
+        cache.set("new", &[0; 100], None, vec![]).unwrap();
+        assert!((0..5).all(|i| cache.exists(&format!("key{}", i)).unwrap()));
+        assert!(!cache.exists("key5").unwrap());
+        assert!((6..20).all(|i| cache.exists(&format!("key{}", i)).unwrap()));
+        assert!(cache.exists("new").unwrap());
+        cache.close();
+    }
+
+    #[test]
+    fn entries_follow_the_cache_to_another_backend() {
+        let temp_dir = TempDir::new().unwrap();
+        let open = |backend| {
+            DiskCache::new(CacheConfig {
+                directory: temp_dir.path().to_path_buf(),
+                backend,
+                ..Default::default()
+            })
+        };
+        let cache = open(StorageKind::Optimized).unwrap();
+        for i in 0..20u8 {
+            let value = vec![i; if i % 2 == 0 { 100 } else { 64 * 1024 }];
+            cache
+                .set(&format!("key{}", i), &value, None, vec![])
+                .unwrap();
+        }
+        cache
+            .set("tagged", b"value", Some(u64::MAX), vec!["tag".into()])
+            .unwrap();
+
+        for backend in [
+            StorageKind::Sqlite,
+            StorageKind::Redb,
+            StorageKind::Optimized,
+        ] {
+            assert_eq!(cache.migrate_backend(backend).unwrap(), 21);
+            assert_eq!(cache.get("key0").unwrap(), Some(vec![0; 100]));
+            assert_eq!(cache.get("key19").unwrap(), Some(vec![19; 64 * 1024]));
+            assert!(!temp_dir.path().join(BACKEND_STAGING_DIR).exists());
+        }
+        assert_eq!(cache.migrate_backend(StorageKind::Optimized).unwrap(), 0);
+        assert_eq!(cache.keys().unwrap().len(), 21);
+        assert_eq!(cache.keys().unwrap()[0], "key0");
+        cache.migrate_backend(StorageKind::Redb).unwrap();
+        cache.set("after", b"switch", None, vec![]).unwrap();
+        cache.close();
+        drop(cache);
+
+        assert!(open(StorageKind::Optimized).is_err());
+        let cache = open(StorageKind::Redb).unwrap();
+        assert_eq!(cache.get("after").unwrap(), Some(b"switch".to_vec()));
+        assert_eq!(cache.get("key1").unwrap(), Some(vec![1; 64 * 1024]));
+        cache.close();
+    }
+
+    #[test]
+    fn disk_cache_memory_backend_leaves_directory_alone() {
+        let temp_dir = TempDir::new().unwrap();
+        let directory = temp_dir.path().join("never-created");
+        let cache = DiskCache::new(CacheConfig {
+            directory: directory.clone(),
+            backend: StorageKind::Memory,
+            ..Default::default()
+        })
+        .unwrap();
+
+        for i in 0..5 {
+            cache
+                .set(&format!("key{}", i), &[i as u8; 64 * 1024], None, vec![])
+                .unwrap();
+        }
+        assert_eq!(cache.get("key4").unwrap(), Some(vec![4; 64 * 1024]));
+        assert_eq!(cache.keys().unwrap().len(), 5);
+        assert!(cache.delete("key4").unwrap());
+        cache.close();
+
+        assert!(!directory.exists());
+    }
+
+    #[test]
+    fn disk_cache_workers_share_one_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let workers: Vec<_> = (0..6u32)
+            .map(|worker| {
+                let directory = temp_dir.path().to_path_buf();
+                std::thread::spawn(move || {
+                    // Opened together, as a pre-forking server starts its workers
+                    let cache = DiskCache::new(CacheConfig {
+                        directory,
+                        disk_write_threshold: if worker % 2 == 0 { 0 } else { 32 * 1024 },
+                        ..Default::default()
+                    })
+                    .unwrap();
+                    for n in 0..60u32 {
+                        let key = format!("shared{}", n % 5);
+                        let value = vec![(worker * 60 + n) as u8; 1000 + (n as usize % 4) * 20_000];
+                        cache.set(&key, &value, None, vec![]).unwrap();
+                        // Whoever wrote it last, a value is never torn or refused
+                        let read = format!("shared{}", (n + 2) % 5);
+                        if let Some(value) = cache.get(&read).unwrap() {
+                            assert!(value.iter().all(|byte| *byte == value[0]));
+                        }
+                        if n % 13 == 0 {
+                            cache.delete(&key).unwrap();
+                        }
+                    }
+                    cache
+                        .set(&format!("own{}", worker), b"done", None, vec![])
+                        .unwrap();
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
 
+        let cache = DiskCache::with_directory(temp_dir.path()).unwrap();
+        for worker in 0..6 {
+            assert_eq!(
+                cache.get(&format!("own{}", worker)).unwrap(),
+                Some(b"done".to_vec())
+            );
+        }
         cache.close();
     }
+    #[test]
+    fn subscribers_hear_about_changes_from_other_handles() {
+        let temp_dir = TempDir::new().unwrap();
+        let open = || {
+            DiskCache::new(CacheConfig {
+                directory: temp_dir.path().to_path_buf(),
+                event_log: true,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let (reader, writer) = (open(), open());
+        let mut subscription = reader.subscribe(Topic::Prefix("user:".into())).unwrap();
+        reader
+            .subscribe_into(Topic::Tag("red".into()), &mut subscription)
+            .unwrap();
+        let next = || subscription.recv(Some(Duration::from_secs(10))).unwrap();
+
+        writer.set("order:1", b"value", None, vec![]).unwrap();
+        writer.set("user:1", b"value", None, vec![]).unwrap();
+        assert_eq!(next(), Change::new(ChangeKind::Set, "user:1"));
+        writer
+            .set("order:2", b"value", None, vec!["red".into()])
+            .unwrap();
+        assert_eq!(next(), Change::new(ChangeKind::Set, "order:2"));
+        writer.delete("order:2").unwrap();
+        assert_eq!(next(), Change::new(ChangeKind::Delete, "order:2"));
+
+        let expired = current_timestamp() - 1;
+        reader
+            .set("user:2", b"value", Some(expired), vec![])
+            .unwrap();
+        assert_eq!(next(), Change::new(ChangeKind::Set, "user:2"));
+        assert_eq!(reader.get("user:2").unwrap(), None);
+        assert_eq!(next(), Change::new(ChangeKind::Expire, "user:2"));
+        assert_eq!(subscription.try_recv(), None);
+    }
 }
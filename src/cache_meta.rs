@@ -0,0 +1,437 @@
+//! Format metadata kept in each cache directory
+//!
+//! `cache_meta.json` records the on-disk format version, the backend that
+//! owns the directory, how it names and compresses what it stores and which
+//! key, if any, its values are encrypted with. It is written when a cache is
+//! first opened in a directory and checked on every open after, so a
+//! directory written by another backend, by a newer release or under another
+//! encryption key is refused instead of misread.
+//!
+//! Directories created before the file existed are recognised by the files
+//! each backend keeps, and get the file written for them on open.
+
+use crate::error::{CacheError, CacheResult, ConfigIssue};
+use crate::serialization::EncryptionKey;
+use crate::storage::optimized_backend::INDEX_FILE;
+use crate::storage::redb_backend::REDB_FILE_NAME;
+use crate::storage::ring_backend::RING_FILE_NAME;
+use crate::storage::sqlite_backend::DISKCACHE_DB_NAME;
+use crate::storage::{Compression, FileNaming, StorageKind};
+use crate::utils::current_timestamp;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+
+/// Name of the metadata file inside the cache directory
+pub const CACHE_META_FILE: &str = "cache_meta.json";
+
+/// Version of the directory format this build writes
+///
+/// Raised whenever a change makes directories unreadable to older builds;
+/// a directory with a higher version is refused.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Compression codecs this build can read
+const KNOWN_COMPRESSION: [&str; 7] = [
+    "none",
+    "lz4",
+    "zstd",
+    "snappy",
+    "zstd-dictionary",
+    "lz4-frames",
+    "zstd-frames",
+];
+
+/// Contents of `cache_meta.json`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheMeta {
+    pub format_version: u32,
+    pub backend: String,
+    /// Data file naming scheme; only the optimized backend names files by key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_naming: Option<String>,
+    /// Codecs large values have been compressed with, comma separated
+    pub compression: String,
+    /// [`EncryptionKey::fingerprint`] of the key values are encrypted with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<String>,
+    pub created_at: u64,
+    /// Release of diskcache_rs that created the directory
+    pub created_by: String,
+}
+
+impl CacheMeta {
+    fn new(
+        backend: StorageKind,
+        file_naming: FileNaming,
+        codecs: &[Compression],
+        encryption: Option<&EncryptionKey>,
+    ) -> Self {
+        let optimized = backend == StorageKind::Optimized;
+        let codecs: Vec<&str> = codecs
+            .iter()
+            .filter(|codec| optimized && codec.is_compressed())
+            .map(|codec| codec.name())
+            .collect();
+        Self {
+            format_version: FORMAT_VERSION,
+            backend: backend.name().to_string(),
+            file_naming: optimized.then(|| file_naming.name().to_string()),
+            compression: if codecs.is_empty() {
+                Compression::None.name().to_string()
+            } else {
+                codecs.join(",")
+            },
+            encryption: encryption.map(EncryptionKey::fingerprint),
+            created_at: current_timestamp(),
+            created_by: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
+
+/// Check the metadata of the cache in `directory` against the backend it is
+/// being opened with, writing it first if the directory has none
+///
+/// `codecs` are those the cache will compress new values with.
+pub fn check_or_create(
+    directory: &Path,
+    backend: StorageKind,
+    file_naming: FileNaming,
+    codecs: &[Compression],
+    encryption: Option<&EncryptionKey>,
+) -> CacheResult<CacheMeta> {
+    let path = directory.join(CACHE_META_FILE);
+    let expected = CacheMeta::new(backend, file_naming, codecs, encryption);
+    let content = match std::fs::read(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            if let Some(found) = detect_backend(directory, backend) {
+                return Err(backend_mismatch(found.name(), backend));
+            }
+            write(directory, &expected)?;
+            return Ok(expected);
+        }
+        Err(e) => return Err(CacheError::Io(e)),
+    };
+
+    let mut meta: CacheMeta = serde_json::from_slice(&content).map_err(|e| {
+        CacheError::Config(ConfigIssue::new(
+            "directory",
+            format!("{} is not valid: {}", CACHE_META_FILE, e),
+            format!(
+                "Restore {} or remove it to have it written again",
+                CACHE_META_FILE
+            ),
+        ))
+    })?;
+    if meta.format_version > FORMAT_VERSION {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "directory",
+            format!(
+                "The cache was written in format version {} by diskcache_rs {}; this build reads up to version {}",
+                meta.format_version, meta.created_by, FORMAT_VERSION
+            ),
+            "Upgrade diskcache_rs or choose another directory",
+        )));
+    }
+    if meta.backend != expected.backend {
+        return Err(backend_mismatch(&meta.backend, backend));
+    }
+    if let Some(unknown) = meta
+        .compression
+        .split(',')
+        .find(|codec| !KNOWN_COMPRESSION.contains(codec))
+    {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "directory",
+            format!(
+                "The cache compresses values with {:?}, which this build cannot read",
+                unknown
+            ),
+            "Upgrade diskcache_rs or choose another directory",
+        )));
+    }
+    if meta.encryption != expected.encryption {
+        return Err(encryption_mismatch(&meta, &expected));
+    }
+    // Files keep the name they were written under, so a new scheme only
+    // applies to new files and is recorded rather than refused
+    let mut changed = meta.file_naming != expected.file_naming;
+    meta.file_naming = expected.file_naming;
+    // Values keep the codec they were written with, so builds reading the
+    // directory must know every codec it has used
+    for codec in expected.compression.split(',') {
+        if codec != Compression::None.name() && !meta.compression.split(',').any(|c| c == codec) {
+            meta.compression = format!("{},{}", meta.compression, codec);
+            changed = true;
+        }
+    }
+    if changed {
+        write(directory, &meta)?;
+    }
+    Ok(meta)
+}
+
+/// Record that the cache in `directory` now belongs to `backend`, after a
+/// backend migration
+///
+/// Values move over as stored, so they keep their encryption, and are
+/// compressed afresh with `codecs` by the backend they move to.
+pub fn record_backend(
+    directory: &Path,
+    backend: StorageKind,
+    file_naming: FileNaming,
+    codecs: &[Compression],
+) -> CacheResult<()> {
+    let mut meta = CacheMeta::new(backend, file_naming, codecs, None);
+    if let Some(previous) = std::fs::read(directory.join(CACHE_META_FILE))
+        .ok()
+        .and_then(|content| serde_json::from_slice::<CacheMeta>(&content).ok())
+    {
+        meta.created_at = previous.created_at;
+        meta.created_by = previous.created_by;
+        meta.encryption = previous.encryption;
+    }
+    write(directory, &meta)
+}
+
+/// Backend other than `backend` whose files a directory without metadata holds
+///
+/// python-diskcache's `cache.db` is left out for the optimized backend,
+/// which migrates it on open.
+fn detect_backend(directory: &Path, backend: StorageKind) -> Option<StorageKind> {
+    let signatures = [
+        (StorageKind::Optimized, INDEX_FILE),
+        (StorageKind::Redb, REDB_FILE_NAME),
+        (StorageKind::Ring, RING_FILE_NAME),
+        (StorageKind::Sqlite, DISKCACHE_DB_NAME),
+    ];
+    let holds = |name: &str| directory.join(name).is_file();
+    if signatures
+        .iter()
+        .any(|(kind, name)| *kind == backend && holds(name))
+    {
+        return None;
+    }
+    signatures
+        .iter()
+        .filter(|(kind, _)| {
+            *kind != backend && !(backend == StorageKind::Optimized && *kind == StorageKind::Sqlite)
+        })
+        .find(|(_, name)| holds(name))
+        .map(|(kind, _)| *kind)
+}
+
+fn backend_mismatch(found: &str, backend: StorageKind) -> CacheError {
+    CacheError::Config(ConfigIssue::new(
+        "backend",
+        format!(
+            "The directory holds a {} cache, not a {} one",
+            found,
+            backend.name()
+        ),
+        format!(
+            "Open it with backend=\"{}\" or choose another directory",
+            found
+        ),
+    ))
+}
+
+fn encryption_mismatch(meta: &CacheMeta, expected: &CacheMeta) -> CacheError {
+    let (message, suggestion) = match (&meta.encryption, &expected.encryption) {
+        (Some(_), Some(_)) => (
+            "The cache's values are encrypted with another key",
+            "Pass the encryption_key the cache was created with",
+        ),
+        (Some(_), None) => (
+            "The cache's values are encrypted",
+            "Pass the encryption_key the cache was created with",
+        ),
+        _ => (
+            "The cache's values are not encrypted, and could not be read with a key",
+            "Open it without encryption_key, or choose another directory",
+        ),
+    };
+    CacheError::Config(ConfigIssue::new("encryption_key", message, suggestion))
+}
+
+/// Replace the metadata file in one rename, so readers never see half of it
+fn write(directory: &Path, meta: &CacheMeta) -> CacheResult<()> {
+    let content =
+        serde_json::to_vec_pretty(meta).map_err(|e| CacheError::Serialization(e.to_string()))?;
+    let mut temp = tempfile::NamedTempFile::new_in(directory)?;
+    temp.write_all(&content)?;
+    temp.persist(directory.join(CACHE_META_FILE))
+        .map_err(|e| CacheError::Io(e.error))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn metadata_is_written_once_and_checked_after() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let created = check_or_create(
+            dir,
+            StorageKind::Optimized,
+            FileNaming::Blake3,
+            &[Compression::Lz4],
+            None,
+        )
+        .unwrap();
+        assert_eq!(created.format_version, FORMAT_VERSION);
+        assert_eq!(created.file_naming.as_deref(), Some("blake3"));
+
+        // A new naming scheme is recorded; another backend is refused
+        let renamed = check_or_create(
+            dir,
+            StorageKind::Optimized,
+            FileNaming::Slug,
+            &[Compression::Lz4],
+            None,
+        )
+        .unwrap();
+        assert_eq!(renamed.file_naming.as_deref(), Some("slug"));
+        assert_eq!(renamed.created_at, created.created_at);
+        assert!(matches!(
+            check_or_create(dir, StorageKind::Redb, FileNaming::Blake3, &[Compression::Lz4], None),
+            Err(CacheError::Config(issue)) if issue.option == "backend"
+        ));
+
+        let newer = CacheMeta {
+            format_version: FORMAT_VERSION + 1,
+            ..created
+        };
+        write(dir, &newer).unwrap();
+        assert!(matches!(
+            check_or_create(dir, StorageKind::Optimized, FileNaming::Blake3, &[Compression::Lz4], None),
+            Err(CacheError::Config(issue)) if issue.option == "directory"
+        ));
+    }
+
+    #[test]
+    fn every_codec_a_cache_used_is_recorded() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let open = |codecs: &[Compression]| {
+            check_or_create(
+                dir,
+                StorageKind::Optimized,
+                FileNaming::Blake3,
+                codecs,
+                None,
+            )
+            .unwrap()
+            .compression
+        };
+        assert_eq!(open(&[Compression::Lz4]), "lz4");
+        assert_eq!(open(&[Compression::Zstd]), "lz4,zstd");
+        assert_eq!(open(&[Compression::None]), "lz4,zstd");
+        assert_eq!(
+            open(&[Compression::Lz4, Compression::ZstdDictionary]),
+            "lz4,zstd,zstd-dictionary"
+        );
+
+        let meta = CacheMeta {
+            compression: "lz4,brotli".to_string(),
+            ..check_or_create(
+                dir,
+                StorageKind::Optimized,
+                FileNaming::Blake3,
+                &[Compression::Lz4],
+                None,
+            )
+            .unwrap()
+        };
+        write(dir, &meta).unwrap();
+        assert!(matches!(
+            check_or_create(dir, StorageKind::Optimized, FileNaming::Blake3, &[Compression::Lz4], None),
+            Err(CacheError::Config(issue)) if issue.option == "directory"
+        ));
+    }
+
+    #[test]
+    fn encrypted_caches_only_open_with_their_key() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        let key = EncryptionKey::new(b"first secret").unwrap();
+        let created = check_or_create(
+            dir,
+            StorageKind::Optimized,
+            FileNaming::Blake3,
+            &[Compression::Lz4],
+            Some(&key),
+        )
+        .unwrap();
+        assert_eq!(created.encryption, Some(key.fingerprint()));
+        check_or_create(
+            dir,
+            StorageKind::Optimized,
+            FileNaming::Blake3,
+            &[Compression::Lz4],
+            Some(&key),
+        )
+        .unwrap();
+
+        let other = EncryptionKey::new(b"second secret").unwrap();
+        for encryption in [Some(&other), None] {
+            assert!(matches!(
+                check_or_create(dir, StorageKind::Optimized, FileNaming::Blake3, &[Compression::Lz4], encryption),
+                Err(CacheError::Config(issue)) if issue.option == "encryption_key"
+            ));
+        }
+
+        // Migrating to another backend keeps the record of the key
+        record_backend(
+            dir,
+            StorageKind::Redb,
+            FileNaming::Blake3,
+            &[Compression::Lz4],
+        )
+        .unwrap();
+        check_or_create(
+            dir,
+            StorageKind::Redb,
+            FileNaming::Blake3,
+            &[Compression::Lz4],
+            Some(&key),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn directories_without_metadata_are_recognised_by_their_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        std::fs::write(dir.join(REDB_FILE_NAME), b"").unwrap();
+        assert!(matches!(
+            check_or_create(dir, StorageKind::Ring, FileNaming::Blake3, &[Compression::Lz4], None),
+            Err(CacheError::Config(issue)) if issue.option == "backend"
+        ));
+        assert!(!dir.join(CACHE_META_FILE).exists());
+        check_or_create(
+            dir,
+            StorageKind::Redb,
+            FileNaming::Blake3,
+            &[Compression::Lz4],
+            None,
+        )
+        .unwrap();
+
+        // python-diskcache directories are migrated by the optimized backend
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join(DISKCACHE_DB_NAME), b"").unwrap();
+        check_or_create(
+            temp_dir.path(),
+            StorageKind::Optimized,
+            FileNaming::Blake3,
+            &[Compression::Lz4],
+            None,
+        )
+        .unwrap();
+    }
+}
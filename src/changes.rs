@@ -0,0 +1,488 @@
+//! Change notifications for subscribers to a cache
+//!
+//! [`DiskCache::subscribe`](crate::DiskCache::subscribe) hands out a
+//! [`Subscription`] that receives a [`Change`] whenever a key under a prefix,
+//! or one carrying a tag, is set, deleted, evicted or found expired. Changes made
+//! through the cache reach its subscribers over a channel as they happen; a
+//! subscription that leaves [`SUBSCRIPTION_CAPACITY`] changes unread misses
+//! the ones after, and hears of them as one [`ChangeKind::Clear`].
+//! Changes made by other processes arrive through a feed, on a thread that
+//! runs while anyone is subscribed: the optimized backend's event log when
+//! `event_log` is on, or the change stream of the cache server a cache is
+//! attached to. A feed that falls behind reports [`ChangeKind::Clear`], as
+//! anything may have changed in the meantime.
+//!
+//! Tag subscriptions match sets by the tags written with them, and deletes,
+//! evictions and expirations of the keys they have seen set with the tag since they
+//! subscribed. Past [`MAX_TAG_MEMBERS`] such keys they stop keeping track, and
+//! hear of every delete, eviction and expiration until the next clear.
+
+use crate::error::CacheResult;
+use crate::fork;
+use crate::serialization::Disk;
+use parking_lot::Mutex;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+/// How long a feed waits for changes before checking it still has subscribers
+pub(crate) const FEED_POLL: Duration = Duration::from_millis(100);
+
+/// Changes a subscription holds unread before it misses the ones after
+pub const SUBSCRIPTION_CAPACITY: usize = 4096;
+
+/// Keys a tag subscription tracks before it reports every removal
+pub const MAX_TAG_MEMBERS: usize = 65_536;
+
+/// What happened to a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Set,
+    Delete,
+    /// The entry was dropped on being found past its expiration time
+    Expire,
+    /// The entry was removed to keep the cache within its limits; other
+    /// processes' evictions arrive through their feed as deletes
+    Evict,
+    /// Every entry may have changed: the cache was cleared, or changes were
+    /// missed; the key is empty
+    Clear,
+}
+
+impl ChangeKind {
+    /// Name the kind is reported by in Python
+    pub fn name(self) -> &'static str {
+        match self {
+            ChangeKind::Set => "set",
+            ChangeKind::Delete => "delete",
+            ChangeKind::Expire => "expire",
+            ChangeKind::Evict => "evict",
+            ChangeKind::Clear => "clear",
+        }
+    }
+
+    /// Byte the kind is sent as between processes
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            ChangeKind::Set => 1,
+            ChangeKind::Delete => 2,
+            ChangeKind::Expire => 3,
+            ChangeKind::Clear => 4,
+            ChangeKind::Evict => 5,
+        }
+    }
+
+    /// Kind sent as `id`; kinds added later count as sets, which is what
+    /// tells subscribers the least and still makes them look
+    pub(crate) fn from_id(id: u8) -> Self {
+        match id {
+            2 => ChangeKind::Delete,
+            3 => ChangeKind::Expire,
+            4 => ChangeKind::Clear,
+            5 => ChangeKind::Evict,
+            _ => ChangeKind::Set,
+        }
+    }
+}
+
+/// One change delivered to a [`Subscription`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub key: String,
+}
+
+impl Change {
+    pub fn new(kind: ChangeKind, key: impl Into<String>) -> Self {
+        Self {
+            kind,
+            key: key.into(),
+        }
+    }
+}
+
+/// Which keys a subscription hears about
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Topic {
+    /// Keys starting with the prefix; an empty prefix matches every key
+    Prefix(String),
+    /// Keys set with the tag
+    Tag(String),
+}
+
+/// Where a change came from, so a process fed by a cache server is not
+/// sent back its own writes
+pub(crate) type Origin = u64;
+
+/// Origin of changes made through the cache itself or reported by its feed
+pub(crate) const LOCAL: Origin = 0;
+
+/// Changes other processes make to a cache's storage
+pub(crate) trait ChangeFeed: Send {
+    /// Changes since the last call, waiting up to `timeout` when there are none
+    fn next(&mut self, timeout: Duration) -> CacheResult<Vec<Change>>;
+}
+
+/// Tags stored with a key, looked up for sets a feed reports
+pub(crate) type TagLookup = Box<dyn Fn(&str) -> Vec<String> + Send>;
+
+struct Subscriber {
+    id: u64,
+    topic: Topic,
+    /// Origin whose changes this subscriber already knows about
+    skip: Option<Origin>,
+    /// Keys seen set with the subscribed tag, for tag topics; `None` once
+    /// there were too many to track
+    members: Option<HashSet<String>>,
+    sender: SyncSender<Change>,
+    /// Set when a change did not fit the channel
+    missed: Arc<AtomicBool>,
+}
+
+impl Subscriber {
+    fn matches(&mut self, kind: ChangeKind, key: &str, tags: &[String]) -> bool {
+        match (&self.topic, kind) {
+            (_, ChangeKind::Clear) => {
+                self.members = Some(HashSet::new());
+                true
+            }
+            (Topic::Prefix(prefix), _) => key.starts_with(prefix.as_str()),
+            (Topic::Tag(tag), ChangeKind::Set) if tags.contains(tag) => {
+                if let Some(members) = &mut self.members {
+                    members.insert(key.to_string());
+                    if members.len() > MAX_TAG_MEMBERS {
+                        self.members = None;
+                    }
+                }
+                true
+            }
+            // Rewritten without the tag: what was derived from it is stale
+            (Topic::Tag(_), _) => self
+                .members
+                .as_mut()
+                .is_none_or(|members| members.remove(key)),
+        }
+    }
+
+    fn send(&self, change: Change) {
+        match self.sender.try_send(change) {
+            Err(TrySendError::Full(_)) => self.missed.store(true, Ordering::Release),
+            // A subscription being dropped unregisters itself right after
+            Ok(()) | Err(TrySendError::Disconnected(_)) => {}
+        }
+    }
+}
+
+/// The subscribers of one cache
+pub(crate) struct Notifier {
+    disk: Arc<dyn Disk>,
+    subscribers: Mutex<Vec<Subscriber>>,
+    /// Subscriber count, so publishing to nobody costs no lock
+    count: AtomicUsize,
+    next_id: AtomicU64,
+    /// Fork generation the feed thread runs in, if it runs
+    feeding: Mutex<Option<u64>>,
+}
+
+impl Notifier {
+    /// Subscribers are told keys as `disk` decodes them
+    pub(crate) fn new(disk: Arc<dyn Disk>) -> Self {
+        Self {
+            disk,
+            subscribers: Mutex::new(Vec::new()),
+            count: AtomicUsize::new(0),
+            next_id: AtomicU64::new(1),
+            feeding: Mutex::new(None),
+        }
+    }
+
+    /// Add `subscription` as a subscriber to `topic`, skipping changes from `skip`
+    pub(crate) fn add(
+        self: &Arc<Self>,
+        topic: Topic,
+        skip: Option<Origin>,
+        subscription: &mut Subscription,
+    ) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut subscribers = self.subscribers.lock();
+        subscribers.push(Subscriber {
+            id,
+            topic,
+            skip,
+            members: Some(HashSet::new()),
+            sender: subscription.sender.clone(),
+            missed: Arc::clone(&subscription.missed),
+        });
+        self.count.store(subscribers.len(), Ordering::Release);
+        subscription.registrations.push((Arc::downgrade(self), id));
+    }
+
+    fn remove(&self, id: u64) {
+        let mut subscribers = self.subscribers.lock();
+        subscribers.retain(|subscriber| subscriber.id != id);
+        self.count.store(subscribers.len(), Ordering::Release);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.count.load(Ordering::Acquire) == 0
+    }
+
+    /// Whether any subscriber goes by tag, so sets need their tags
+    pub(crate) fn wants_tags(&self) -> bool {
+        !self.is_empty()
+            && self
+                .subscribers
+                .lock()
+                .iter()
+                .any(|subscriber| matches!(subscriber.topic, Topic::Tag(_)))
+    }
+
+    /// Tell the subscribers `origin` changed the stored `key`
+    pub(crate) fn publish(&self, origin: Origin, kind: ChangeKind, key: &str, tags: &[String]) {
+        if self.is_empty() {
+            return;
+        }
+        let key = match kind {
+            ChangeKind::Clear => String::new(),
+            _ => match self.disk.get(key) {
+                Ok(key) => key,
+                Err(e) => {
+                    tracing::debug!("Not notifying about undecodable key {:?}: {}", key, e);
+                    return;
+                }
+            },
+        };
+        for subscriber in self.subscribers.lock().iter_mut() {
+            if subscriber.skip != Some(origin) && subscriber.matches(kind, &key, tags) {
+                subscriber.send(Change::new(kind, key.clone()));
+            }
+        }
+    }
+
+    /// Pass on what the feed `open` returns on a thread of its own, unless
+    /// one runs already; it stops once the last subscriber leaves
+    pub(crate) fn ensure_feed(
+        self: &Arc<Self>,
+        open: impl FnOnce() -> CacheResult<Option<(Box<dyn ChangeFeed>, TagLookup)>>,
+    ) -> CacheResult<()> {
+        let mut feeding = self.feeding.lock();
+        let generation = fork::generation();
+        if *feeding == Some(generation) {
+            return Ok(());
+        }
+        let Some((feed, tags)) = open()? else {
+            return Ok(());
+        };
+        let notifier = Arc::downgrade(self);
+        std::thread::Builder::new()
+            .name("cache-notify".into())
+            .spawn(move || Self::run_feed(notifier, feed, tags))?;
+        *feeding = Some(generation);
+        Ok(())
+    }
+
+    fn run_feed(notifier: Weak<Self>, mut feed: Box<dyn ChangeFeed>, tags: TagLookup) {
+        loop {
+            {
+                let Some(notifier) = notifier.upgrade() else {
+                    return;
+                };
+                // Checked under the lock a new subscriber starts feeds under,
+                // so none is left without one
+                let mut feeding = notifier.feeding.lock();
+                if notifier.is_empty() {
+                    *feeding = None;
+                    return;
+                }
+            }
+            let changes = match feed.next(FEED_POLL) {
+                Ok(changes) => changes,
+                Err(e) => {
+                    tracing::warn!("Failed to read changes from other processes: {}", e);
+                    std::thread::sleep(FEED_POLL);
+                    continue;
+                }
+            };
+            let Some(notifier) = notifier.upgrade() else {
+                return;
+            };
+            let wants_tags = notifier.wants_tags();
+            for change in changes {
+                let tags = match change.kind {
+                    ChangeKind::Set if wants_tags => tags(&change.key),
+                    _ => Vec::new(),
+                };
+                notifier.publish(LOCAL, change.kind, &change.key, &tags);
+            }
+        }
+    }
+}
+
+/// Changes to the keys of one or more topics, as they happen
+///
+/// Dropping the subscription unsubscribes it.
+pub struct Subscription {
+    sender: SyncSender<Change>,
+    receiver: Receiver<Change>,
+    missed: Arc<AtomicBool>,
+    registrations: Vec<(Weak<Notifier>, u64)>,
+}
+
+impl Subscription {
+    /// A subscription to nothing yet, for
+    /// [`DiskCache::subscribe_into`](crate::DiskCache::subscribe_into)
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::sync_channel(SUBSCRIPTION_CAPACITY);
+        Self {
+            sender,
+            receiver,
+            missed: Arc::new(AtomicBool::new(false)),
+            registrations: Vec::new(),
+        }
+    }
+
+    /// A clear standing for the changes that did not fit the channel, and
+    /// those still in it
+    fn missed(&self) -> Option<Change> {
+        if !self.missed.swap(false, Ordering::Acquire) {
+            return None;
+        }
+        while self.receiver.try_recv().is_ok() {}
+        Some(Change::new(ChangeKind::Clear, ""))
+    }
+
+    /// Next change, waiting for one up to `timeout` or for good with `None`
+    pub fn recv(&self, timeout: Option<Duration>) -> Option<Change> {
+        if let Some(change) = self.missed() {
+            return Some(change);
+        }
+        match timeout {
+            Some(timeout) => self.receiver.recv_timeout(timeout).ok(),
+            // The subscription holds a sender itself, so this never disconnects
+            None => self.receiver.recv().ok(),
+        }
+    }
+
+    /// Next change if there is one already
+    pub fn try_recv(&self) -> Option<Change> {
+        self.missed().or_else(|| self.receiver.try_recv().ok())
+    }
+}
+
+impl Default for Subscription {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        for (notifier, id) in self.registrations.drain(..) {
+            if let Some(notifier) = notifier.upgrade() {
+                notifier.remove(id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialization::RawDisk;
+
+    #[test]
+    fn subscribers_hear_about_their_topics() {
+        let notifier = Arc::new(Notifier::new(Arc::new(RawDisk)));
+        let (mut users, mut tagged) = (Subscription::new(), Subscription::new());
+        notifier.add(Topic::Prefix("user:".into()), None, &mut users);
+        notifier.add(Topic::Tag("red".into()), Some(7), &mut tagged);
+
+        let red = vec!["red".to_string()];
+        notifier.publish(LOCAL, ChangeKind::Set, "user:1", &red);
+        notifier.publish(LOCAL, ChangeKind::Set, "order:1", &[]);
+        notifier.publish(7, ChangeKind::Set, "user:2", &red);
+        notifier.publish(LOCAL, ChangeKind::Expire, "user:1", &[]);
+        notifier.publish(LOCAL, ChangeKind::Delete, "user:2", &[]);
+        notifier.publish(LOCAL, ChangeKind::Set, "user:3", &red);
+        notifier.publish(LOCAL, ChangeKind::Evict, "user:3", &[]);
+
+        let changes: Vec<_> = std::iter::from_fn(|| users.try_recv()).collect();
+        assert_eq!(
+            changes,
+            vec![
+                Change::new(ChangeKind::Set, "user:1"),
+                Change::new(ChangeKind::Set, "user:2"),
+                Change::new(ChangeKind::Expire, "user:1"),
+                Change::new(ChangeKind::Delete, "user:2"),
+                Change::new(ChangeKind::Set, "user:3"),
+                Change::new(ChangeKind::Evict, "user:3"),
+            ]
+        );
+        // Origin 7's set was skipped, so its delete is of no tagged key
+        let changes: Vec<_> = std::iter::from_fn(|| tagged.try_recv()).collect();
+        assert_eq!(
+            changes,
+            vec![
+                Change::new(ChangeKind::Set, "user:1"),
+                Change::new(ChangeKind::Expire, "user:1"),
+                Change::new(ChangeKind::Set, "user:3"),
+                Change::new(ChangeKind::Evict, "user:3"),
+            ]
+        );
+
+        drop(users);
+        notifier.publish(LOCAL, ChangeKind::Clear, "", &[]);
+        assert_eq!(tagged.try_recv(), Some(Change::new(ChangeKind::Clear, "")));
+        assert_eq!(notifier.subscribers.lock().len(), 1);
+    }
+
+    #[test]
+    fn subscribers_that_fall_behind_hear_of_a_clear() {
+        let notifier = Arc::new(Notifier::new(Arc::new(RawDisk)));
+        let mut subscription = Subscription::new();
+        notifier.add(Topic::Prefix(String::new()), None, &mut subscription);
+
+        for i in 0..SUBSCRIPTION_CAPACITY + 1 {
+            notifier.publish(LOCAL, ChangeKind::Set, &format!("key:{}", i), &[]);
+        }
+        assert_eq!(
+            subscription.try_recv(),
+            Some(Change::new(ChangeKind::Clear, ""))
+        );
+        assert_eq!(subscription.try_recv(), None);
+
+        notifier.publish(LOCAL, ChangeKind::Delete, "key:0", &[]);
+        assert_eq!(
+            subscription.recv(Some(Duration::ZERO)),
+            Some(Change::new(ChangeKind::Delete, "key:0"))
+        );
+    }
+
+    #[test]
+    fn tag_subscribers_hear_of_every_removal_past_the_member_cap() {
+        let notifier = Arc::new(Notifier::new(Arc::new(RawDisk)));
+        let mut tagged = Subscription::new();
+        notifier.add(Topic::Tag("red".into()), None, &mut tagged);
+
+        let red = vec!["red".to_string()];
+        for i in 0..=MAX_TAG_MEMBERS {
+            notifier.publish(LOCAL, ChangeKind::Set, &format!("key:{}", i), &red);
+        }
+        assert!(notifier.subscribers.lock()[0].members.is_none());
+        while tagged.try_recv().is_some() {}
+
+        notifier.publish(LOCAL, ChangeKind::Delete, "untagged", &[]);
+        assert_eq!(
+            tagged.try_recv(),
+            Some(Change::new(ChangeKind::Delete, "untagged"))
+        );
+
+        // A clear starts tracking over
+        notifier.publish(LOCAL, ChangeKind::Clear, "", &[]);
+        assert_eq!(tagged.try_recv(), Some(Change::new(ChangeKind::Clear, "")));
+        notifier.publish(LOCAL, ChangeKind::Delete, "untagged", &[]);
+        assert_eq!(tagged.try_recv(), None);
+    }
+}
@@ -0,0 +1,206 @@
+//! Advisory locks on single entries for read-modify-write operations
+//!
+//! [`DiskCache::lock_key`](crate::DiskCache::lock_key) holds one while a
+//! caller reads a value, computes the next one and writes it back, so
+//! `incr`, `add` and the like stay atomic between processes. Each locked key
+//! has a [`ProcessLock`] named by a file under `locks/entries/` after the
+//! hash of the key. With file locks, the holder removes that file on release
+//! on Unix, so files do not pile up for keys locked once. The lock is
+//! advisory: writes that do not take it are not held back.
+//!
+//! Clients of a [`CacheServer`](crate::CacheServer) hold entry locks through
+//! the server, on its cache's entry locks; caches without a directory lock
+//! entries within the process only.
+
+use crate::error::CacheResult;
+use crate::process_lock::{LockBackend, ProcessLock};
+use crate::storage::key_locks::LOCK_DIR;
+use parking_lot::{Condvar, Mutex};
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Directory under [`LOCK_DIR`] holding the entry lock files
+const ENTRY_LOCK_DIR: &str = "entries";
+
+/// Where a cache's entry locks live
+pub enum EntryLocks {
+    Directory(PathBuf, LockBackend),
+    Process(Arc<ProcessLocks>),
+    /// Held by the cache server listening on the socket
+    #[cfg(unix)]
+    Server(PathBuf),
+}
+
+/// Keys locked by this process, for caches without a directory
+#[derive(Default)]
+pub struct ProcessLocks {
+    held: Mutex<HashSet<String>>,
+    released: Condvar,
+}
+
+/// A held entry lock, released when dropped
+pub struct EntryLock {
+    held: Held,
+}
+
+enum Held {
+    File {
+        lock: ProcessLock,
+        path: PathBuf,
+    },
+    Process {
+        locks: Arc<ProcessLocks>,
+        key: String,
+    },
+    /// Connection on which the server holds the lock
+    #[cfg(unix)]
+    Server(std::os::unix::net::UnixStream),
+}
+
+impl EntryLocks {
+    /// Entry locks named by files under `cache_directory`
+    pub fn in_directory(cache_directory: &Path, backend: LockBackend) -> Self {
+        Self::Directory(cache_directory.join(LOCK_DIR).join(ENTRY_LOCK_DIR), backend)
+    }
+
+    /// Entry locks that only exclude threads of this process
+    pub fn in_process() -> Self {
+        Self::Process(Arc::default())
+    }
+
+    /// Entry locks held by the cache server listening on `socket`
+    #[cfg(unix)]
+    pub fn on_server(socket: &Path) -> Self {
+        Self::Server(socket.to_path_buf())
+    }
+
+    /// Wait until no one else holds `key`'s lock, then hold it
+    pub fn lock(&self, key: &str) -> CacheResult<EntryLock> {
+        match self {
+            Self::Directory(directory, backend) => Self::lock_file(directory, *backend, key),
+            #[cfg(unix)]
+            Self::Server(socket) => Ok(EntryLock {
+                held: Held::Server(crate::server::lock_entry(socket, key)?),
+            }),
+            Self::Process(locks) => {
+                let mut held = locks.held.lock();
+                while held.contains(key) {
+                    locks.released.wait(&mut held);
+                }
+                held.insert(key.to_string());
+                Ok(EntryLock {
+                    held: Held::Process {
+                        locks: locks.clone(),
+                        key: key.to_string(),
+                    },
+                })
+            }
+        }
+    }
+
+    fn lock_file(directory: &Path, backend: LockBackend, key: &str) -> CacheResult<EntryLock> {
+        std::fs::create_dir_all(directory)?;
+        let path = directory.join(format!("{}.lock", blake3::hash(key.as_bytes()).to_hex()));
+        loop {
+            let lock = ProcessLock::acquire(backend, &path)?;
+            // The previous holder may have removed the file while we waited
+            // on it, and someone else locked a new one under the same name
+            let linked = match lock.file() {
+                Some(file) => Self::still_linked(file, &path)?,
+                None => true,
+            };
+            if linked {
+                return Ok(EntryLock {
+                    held: Held::File { lock, path },
+                });
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    fn still_linked(file: &File, path: &Path) -> CacheResult<bool> {
+        use std::os::unix::fs::MetadataExt;
+
+        let opened = file.metadata()?;
+        match std::fs::metadata(path) {
+            Ok(linked) => Ok(linked.dev() == opened.dev() && linked.ino() == opened.ino()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Lock files are never removed here, so the one opened is the one named
+    #[cfg(not(unix))]
+    fn still_linked(_file: &File, _path: &Path) -> CacheResult<bool> {
+        Ok(true)
+    }
+}
+
+impl Drop for EntryLock {
+    fn drop(&mut self) {
+        match &self.held {
+            // Removed while still locked; the lock goes with the descriptor
+            #[cfg(unix)]
+            Held::File { lock, path } => {
+                if lock.file().is_some() {
+                    let _ = std::fs::remove_file(path);
+                }
+            }
+            #[cfg(not(unix))]
+            Held::File { .. } => {}
+            Held::Process { locks, key } => {
+                locks.held.lock().remove(key);
+                locks.released.notify_all();
+            }
+            // Hanging up releases it
+            #[cfg(unix)]
+            Held::Server(stream) => {
+                let _ = stream.shutdown(std::net::Shutdown::Both);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    fn assert_excludes(locks: Arc<EntryLocks>) {
+        let lock = locks.lock("key").unwrap();
+        // Other keys are free
+        drop(locks.lock("other").unwrap());
+
+        let (locked_tx, locked_rx) = mpsc::channel();
+        let waiter = {
+            let locks = locks.clone();
+            std::thread::spawn(move || {
+                let _lock = locks.lock("key").unwrap();
+                locked_tx.send(()).unwrap();
+            })
+        };
+        assert!(locked_rx.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(lock);
+        locked_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        waiter.join().unwrap();
+    }
+
+    #[test]
+    fn entry_locks_exclude_other_holders_of_the_key() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        assert_excludes(Arc::new(EntryLocks::in_directory(
+            temp_dir.path(),
+            LockBackend::File,
+        )));
+        assert_excludes(Arc::new(EntryLocks::in_process()));
+
+        #[cfg(unix)]
+        {
+            let entries = temp_dir.path().join(LOCK_DIR).join(ENTRY_LOCK_DIR);
+            assert_eq!(std::fs::read_dir(entries).unwrap().count(), 0);
+        }
+    }
+}
@@ -1,7 +1,44 @@
-use pyo3::exceptions::PyException;
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyKeyError, PyPermissionError, PyValueError};
 use pyo3::prelude::*;
+use std::fmt;
 use thiserror::Error;
 
+create_exception!(
+    _diskcache_rs,
+    CacheConfigError,
+    PyValueError,
+    "Invalid cache configuration; see the `option` and `suggestion` attributes."
+);
+
+/// A rejected configuration option together with how to fix it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    pub option: &'static str,
+    pub message: String,
+    pub suggestion: String,
+}
+
+impl ConfigIssue {
+    pub fn new(
+        option: &'static str,
+        message: impl Into<String>,
+        suggestion: impl Into<String>,
+    ) -> Self {
+        Self {
+            option,
+            message: message.into(),
+            suggestion: suggestion.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} ({})", self.option, self.message, self.suggestion)
+    }
+}
+
 /// Custom error types for the cache
 #[derive(Error, Debug)]
 pub enum CacheError {
@@ -23,6 +60,9 @@ pub enum CacheError {
     #[error("Invalid configuration: {0}")]
     InvalidConfig(String),
 
+    #[error("Invalid configuration: {0}")]
+    Config(ConfigIssue),
+
     #[error("Network file system error: {0}")]
     NetworkFileSystem(String),
 
@@ -32,6 +72,28 @@ pub enum CacheError {
     #[error("Corruption detected: {0}")]
     Corruption(String),
 
+    /// A stored value failed its checksum; the entry has been dropped
+    #[error("Stored value for key {0:?} failed its checksum")]
+    Corrupted(String),
+
+    /// An index row failed its signature check; the row has been dropped
+    #[error("Index entry for key {0:?} failed its signature check")]
+    Tampered(String),
+
+    /// A key's data file holds another key's value, written over it under a
+    /// colliding file name; the entry has been dropped
+    #[error("Data file for key {0:?} holds the value of another key")]
+    Collided(String),
+
+    /// This process may not write to the cache, e.g. because another one
+    /// holds its writer lease
+    #[error("Cache is read-only: {0}")]
+    ReadOnly(String),
+
+    /// The write queue is full and the configured policy refuses to wait
+    #[error("Write queue is full")]
+    QueueFull,
+
     #[error("Operation timeout")]
     Timeout,
 
@@ -41,7 +103,23 @@ pub enum CacheError {
 
 impl From<CacheError> for PyErr {
     fn from(err: CacheError) -> PyErr {
-        PyException::new_err(err.to_string())
+        match err {
+            CacheError::Config(issue) => Python::attach(|py| {
+                let py_err = CacheConfigError::new_err(issue.to_string());
+                let value = py_err.value(py);
+                let attached = value
+                    .setattr("option", issue.option)
+                    .and_then(|_| value.setattr("message", &issue.message))
+                    .and_then(|_| value.setattr("suggestion", &issue.suggestion));
+                match attached {
+                    Ok(()) => py_err,
+                    Err(e) => e,
+                }
+            }),
+            CacheError::KeyNotFound(key) => PyKeyError::new_err(key),
+            err @ CacheError::ReadOnly(_) => PyPermissionError::new_err(err.to_string()),
+            err => PyException::new_err(err.to_string()),
+        }
     }
 }
 
@@ -1,7 +1,18 @@
-use crate::serialization::CacheEntry;
-use parking_lot::RwLock;
-use std::collections::{BTreeMap, HashMap};
+use crate::error::{CacheError, CacheResult, ConfigIssue};
+use crate::lock_order::{LockLevel, OrderedRwLock};
+use crate::popularity::FrequencySketch;
+use crate::serialization::{CacheEntry, Priority};
+use crate::utils::current_timestamp_millis;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+
+/// Name of the file under the cache directory eviction state is saved to
+pub const EVICTION_STATE_FILE: &str = "eviction.state";
 
 /// Eviction policy trait
 pub trait EvictionPolicy: Send + Sync {
@@ -10,22 +21,192 @@ pub trait EvictionPolicy: Send + Sync {
     fn on_remove(&self, key: &str);
     fn select_victims(&self, count: usize) -> Vec<String>;
     fn clear(&self);
+
+    /// Never pick `key` as a victim while `pinned`; policies that cannot
+    /// exempt keys ignore it
+    fn set_pinned(&self, _key: &str, _pinned: bool) {}
+
+    /// Whether `key` went unused for longer than the policy's idle limit
+    fn is_idle(&self, _key: &str) -> bool {
+        false
+    }
+
+    /// Up to `count` keys unused for longer than the policy's idle limit,
+    /// the longest unused first
+    fn idle_keys(&self, _count: usize) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Every tracked key, least valuable first, for [`restore`](Self::restore)
+    /// to take back after a restart
+    fn checkpoint(&self) -> Vec<TrackedKey> {
+        self.select_victims(usize::MAX)
+            .into_iter()
+            .map(|key| TrackedKey {
+                size: self.entry_size(&key).unwrap_or(0),
+                weight: self.entry_weight(&key),
+                key,
+                hits: 0,
+                expire_time: None,
+                priority: Priority::Normal,
+            })
+            .collect()
+    }
+
+    /// Track `keys` as if they were stored in turn, with what was saved of them
+    fn restore(&self, keys: &[TrackedKey]) {
+        for tracked in keys {
+            self.on_insert(&tracked.key, &tracked.entry());
+        }
+    }
+
+    /// Bytes `key` held when it was last stored, if the policy tracks sizes
+    fn entry_size(&self, _key: &str) -> Option<u64> {
+        None
+    }
+
+    /// Weight `key` was last stored with, if it had one and the policy
+    /// tracks weights
+    fn entry_weight(&self, _key: &str) -> Option<f64> {
+        None
+    }
+
+    /// Victims in the order [`select_victims`](Self::select_victims) picks
+    /// them, until their sizes add up to `bytes`
+    ///
+    /// Keys of unknown size free nothing, so a policy that does not track
+    /// sizes gives every victim it has.
+    fn select_victims_by_size(&self, bytes: u64) -> Vec<String> {
+        let mut count = SIZED_VICTIM_BATCH;
+        loop {
+            let mut victims = self.select_victims(count);
+            let exhausted = victims.len() < count;
+            let mut freed = 0u64;
+            let enough = victims.iter().position(|key| {
+                freed = freed.saturating_add(self.entry_size(key).unwrap_or(0));
+                freed >= bytes
+            });
+            if let Some(last) = enough {
+                victims.truncate(last + 1);
+                return victims;
+            }
+            if exhausted {
+                return victims;
+            }
+            count = count.saturating_mul(4);
+        }
+    }
+
+    /// Up to `count` victims in the order [`select_victims`](Self::select_victims)
+    /// picks them, among the keys `among` accepts
+    fn select_victims_among(&self, count: usize, among: &dyn Fn(&str) -> bool) -> Vec<String> {
+        let mut batch = SIZED_VICTIM_BATCH.max(count);
+        loop {
+            let victims = self.select_victims(batch);
+            let exhausted = victims.len() < batch;
+            let mut accepted: Vec<String> = victims.into_iter().filter(|key| among(key)).collect();
+            if accepted.len() >= count || exhausted {
+                accepted.truncate(count);
+                return accepted;
+            }
+            batch = batch.saturating_mul(4);
+        }
+    }
+}
+
+/// What a [`checkpoint`](EvictionPolicy::checkpoint) keeps of a key
+#[derive(Debug, Clone, PartialEq, bincode::Encode, bincode::Decode)]
+pub struct TrackedKey {
+    pub key: String,
+    pub size: u64,
+    /// Accesses counted, by the policies that count them
+    pub hits: u64,
+    pub expire_time: Option<u64>,
+    pub priority: Priority,
+    /// Weight the key was stored with, for GDSF
+    pub weight: Option<f64>,
 }
 
+impl TrackedKey {
+    /// A key nothing is known of yet
+    pub fn new(key: String) -> Self {
+        Self {
+            key,
+            size: 0,
+            hits: 0,
+            expire_time: None,
+            priority: Priority::Normal,
+            weight: None,
+        }
+    }
+
+    /// An entry standing in for the stored one; policies only look at its
+    /// size, access count, expiry, priority and weight
+    fn entry(&self) -> CacheEntry {
+        let mut entry = CacheEntry::new_file(
+            self.key.clone(),
+            String::new(),
+            self.size,
+            vec![],
+            self.expire_time,
+        );
+        entry.access_count = self.hits;
+        entry.priority = self.priority;
+        entry.weight = self.weight;
+        entry
+    }
+}
+
+/// Save `keys` to the cache in `directory`, replacing what was saved before
+/// in one rename
+pub fn save_checkpoint(directory: &Path, keys: &[TrackedKey]) -> CacheResult<()> {
+    let content = bincode::encode_to_vec(keys, bincode::config::standard())
+        .map_err(|e| CacheError::Serialization(format!("eviction state: {}", e)))?;
+    let mut temp = tempfile::NamedTempFile::new_in(directory)?;
+    temp.write_all(&content)?;
+    temp.persist(directory.join(EVICTION_STATE_FILE))
+        .map_err(|e| CacheError::Io(e.error))?;
+    Ok(())
+}
+
+/// What [`save_checkpoint`] saved for the cache in `directory`
+///
+/// A missing or unreadable file yields nothing, as if no key had been
+/// tracked.
+pub fn load_checkpoint(directory: &Path) -> Vec<TrackedKey> {
+    let Ok(content) = std::fs::read(directory.join(EVICTION_STATE_FILE)) else {
+        return Vec::new();
+    };
+    match bincode::decode_from_slice(&content, bincode::config::standard()) {
+        Ok((keys, _)) => keys,
+        Err(e) => {
+            tracing::warn!("Ignoring unreadable eviction state: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Victims a size-aware selection asks a policy for at first, and four
+/// times as many each time they free too few bytes
+const SIZED_VICTIM_BATCH: usize = 16;
+
 /// Least Recently Used (LRU) eviction policy
 pub struct LruEviction {
-    access_order: Arc<RwLock<BTreeMap<u64, String>>>,
-    key_to_time: Arc<RwLock<HashMap<String, u64>>>,
-    counter: Arc<RwLock<u64>>,
+    access_order: Arc<OrderedRwLock<BTreeMap<u64, String>>>,
+    key_to_time: Arc<OrderedRwLock<HashMap<String, u64>>>,
+    counter: Arc<OrderedRwLock<u64>>,
 }
 
 impl LruEviction {
     #[allow(dead_code)]
     pub fn new() -> Self {
         Self {
-            access_order: Arc::new(RwLock::new(BTreeMap::new())),
-            key_to_time: Arc::new(RwLock::new(HashMap::new())),
-            counter: Arc::new(RwLock::new(0)),
+            access_order: Arc::new(OrderedRwLock::new(
+                LockLevel::EvictionOrder,
+                BTreeMap::new(),
+            )),
+            key_to_time: Arc::new(OrderedRwLock::new(LockLevel::EvictionKeys, HashMap::new())),
+            counter: Arc::new(OrderedRwLock::new(LockLevel::EvictionCounter, 0)),
         }
     }
 
@@ -78,31 +259,37 @@ impl EvictionPolicy for LruEviction {
 
 /// Least Frequently Used (LFU) eviction policy
 pub struct LfuEviction {
-    frequency_order: Arc<RwLock<BTreeMap<u64, Vec<String>>>>,
-    key_to_frequency: Arc<RwLock<HashMap<String, u64>>>,
+    frequency_order: Arc<OrderedRwLock<BTreeMap<u64, Vec<String>>>>,
+    key_to_frequency: Arc<OrderedRwLock<HashMap<String, u64>>>,
 }
 
 impl LfuEviction {
     #[allow(dead_code)]
     pub fn new() -> Self {
         Self {
-            frequency_order: Arc::new(RwLock::new(BTreeMap::new())),
-            key_to_frequency: Arc::new(RwLock::new(HashMap::new())),
+            frequency_order: Arc::new(OrderedRwLock::new(
+                LockLevel::EvictionOrder,
+                BTreeMap::new(),
+            )),
+            key_to_frequency: Arc::new(OrderedRwLock::new(LockLevel::EvictionKeys, HashMap::new())),
         }
     }
 }
 
-impl EvictionPolicy for LfuEviction {
-    fn on_access(&self, key: &str, entry: &CacheEntry) {
-        let mut frequency_order = self.frequency_order.write();
-        let mut key_to_frequency = self.key_to_frequency.write();
+impl LfuEviction {
+    /// Accesses counted for `key`
+    pub fn frequency(&self, key: &str) -> Option<u64> {
+        self.key_to_frequency.read().get(key).copied()
+    }
 
-        // Get current frequency
-        let old_freq = key_to_frequency.get(key).copied().unwrap_or(0);
-        let new_freq = entry.access_count;
+    /// Count `hits` more accesses of `key`, tracking it if it is new
+    fn count(&self, key: &str, hits: u64) {
+        let mut key_to_frequency = self.key_to_frequency.write();
+        let mut frequency_order = self.frequency_order.write();
 
         // Remove from old frequency bucket
-        if old_freq > 0 {
+        let old_freq = key_to_frequency.get(key).copied();
+        if let Some(old_freq) = old_freq {
             if let Some(bucket) = frequency_order.get_mut(&old_freq) {
                 bucket.retain(|k| k != key);
                 if bucket.is_empty() {
@@ -112,6 +299,7 @@ impl EvictionPolicy for LfuEviction {
         }
 
         // Add to new frequency bucket
+        let new_freq = old_freq.unwrap_or(0).saturating_add(hits);
         frequency_order
             .entry(new_freq)
             .or_default()
@@ -119,14 +307,21 @@ impl EvictionPolicy for LfuEviction {
 
         key_to_frequency.insert(key.to_string(), new_freq);
     }
+}
+
+impl EvictionPolicy for LfuEviction {
+    fn on_access(&self, key: &str, _entry: &CacheEntry) {
+        self.count(key, 1);
+    }
 
     fn on_insert(&self, key: &str, entry: &CacheEntry) {
-        self.on_access(key, entry);
+        // Only restored entries come with accesses counted already
+        self.count(key, entry.access_count);
     }
 
     fn on_remove(&self, key: &str) {
-        let mut frequency_order = self.frequency_order.write();
         let mut key_to_frequency = self.key_to_frequency.write();
+        let mut frequency_order = self.frequency_order.write();
 
         if let Some(freq) = key_to_frequency.remove(key) {
             if let Some(bucket) = frequency_order.get_mut(&freq) {
@@ -165,17 +360,85 @@ impl EvictionPolicy for LfuEviction {
 
 /// Time-based eviction policy (TTL)
 pub struct TtlEviction {
-    expiry_times: Arc<RwLock<BTreeMap<u64, Vec<String>>>>,
-    key_to_expiry: Arc<RwLock<HashMap<String, u64>>>,
+    expiry_times: Arc<OrderedRwLock<BTreeMap<u64, Vec<String>>>>,
+    key_to_expiry: Arc<OrderedRwLock<HashMap<String, u64>>>,
+    /// Keys by the millisecond they were last used, for idle expiration;
+    /// only fed through [`TtlEviction::touch`]
+    access_times: OrderedRwLock<BTreeMap<(u64, u64), String>>,
+    key_to_access: OrderedRwLock<HashMap<String, (u64, u64)>>,
+    /// Tells apart keys used within the same millisecond
+    access_sequence: AtomicU64,
 }
 
 impl TtlEviction {
     #[allow(dead_code)]
     pub fn new() -> Self {
         Self {
-            expiry_times: Arc::new(RwLock::new(BTreeMap::new())),
-            key_to_expiry: Arc::new(RwLock::new(HashMap::new())),
+            expiry_times: Arc::new(OrderedRwLock::new(
+                LockLevel::EvictionOrder,
+                BTreeMap::new(),
+            )),
+            key_to_expiry: Arc::new(OrderedRwLock::new(LockLevel::EvictionKeys, HashMap::new())),
+            access_times: OrderedRwLock::new(LockLevel::EvictionOrder, BTreeMap::new()),
+            key_to_access: OrderedRwLock::new(LockLevel::EvictionKeys, HashMap::new()),
+            access_sequence: AtomicU64::new(0),
+        }
+    }
+
+    /// Note that `key` was used just now
+    pub fn touch(&self, key: &str) {
+        let stamp = (
+            current_timestamp_millis(),
+            self.access_sequence.fetch_add(1, Ordering::Relaxed),
+        );
+        let mut key_to_access = self.key_to_access.write();
+        let mut access_times = self.access_times.write();
+        if let Some(previous) = key_to_access.insert(key.to_string(), stamp) {
+            access_times.remove(&previous);
         }
+        access_times.insert(stamp, key.to_string());
+    }
+
+    /// Whether `key` was last [touched](Self::touch) `max_idle` or longer ago
+    pub fn is_idle(&self, key: &str, max_idle: Duration) -> bool {
+        let cutoff = Self::idle_cutoff(max_idle);
+        self.key_to_access
+            .read()
+            .get(key)
+            .is_some_and(|&(used, _)| used <= cutoff)
+    }
+
+    /// Up to `count` keys last touched `max_idle` or longer ago, the longest
+    /// unused first
+    pub fn get_idle_keys(&self, max_idle: Duration, count: usize) -> Vec<String> {
+        let cutoff = Self::idle_cutoff(max_idle);
+        self.access_times
+            .read()
+            .iter()
+            .take_while(|((used, _), _)| *used <= cutoff)
+            .take(count)
+            .map(|(_, key)| key.clone())
+            .collect()
+    }
+
+    fn idle_cutoff(max_idle: Duration) -> u64 {
+        let max_idle = u64::try_from(max_idle.as_millis()).unwrap_or(u64::MAX);
+        current_timestamp_millis().saturating_sub(max_idle)
+    }
+
+    /// When `key` expires, if it does
+    pub fn expiry(&self, key: &str) -> Option<u64> {
+        self.key_to_expiry.read().get(key).copied()
+    }
+
+    /// Every key that expires, soonest first
+    pub fn keys(&self) -> Vec<String> {
+        self.expiry_times
+            .read()
+            .values()
+            .flatten()
+            .cloned()
+            .collect()
     }
 
     pub fn get_expired_keys(&self) -> Vec<String> {
@@ -206,8 +469,8 @@ impl EvictionPolicy for TtlEviction {
 
     fn on_insert(&self, key: &str, entry: &CacheEntry) {
         if let Some(expire_time) = entry.expire_time {
-            let mut expiry_times = self.expiry_times.write();
             let mut key_to_expiry = self.key_to_expiry.write();
+            let mut expiry_times = self.expiry_times.write();
 
             // Remove old expiry if exists
             if let Some(old_expiry) = key_to_expiry.get(key) {
@@ -230,8 +493,16 @@ impl EvictionPolicy for TtlEviction {
     }
 
     fn on_remove(&self, key: &str) {
-        let mut expiry_times = self.expiry_times.write();
+        {
+            let mut key_to_access = self.key_to_access.write();
+            let mut access_times = self.access_times.write();
+            if let Some(used) = key_to_access.remove(key) {
+                access_times.remove(&used);
+            }
+        }
+
         let mut key_to_expiry = self.key_to_expiry.write();
+        let mut expiry_times = self.expiry_times.write();
 
         if let Some(expiry) = key_to_expiry.remove(key) {
             if let Some(bucket) = expiry_times.get_mut(&expiry) {
@@ -250,24 +521,29 @@ impl EvictionPolicy for TtlEviction {
     fn clear(&self) {
         self.expiry_times.write().clear();
         self.key_to_expiry.write().clear();
+        self.access_times.write().clear();
+        self.key_to_access.write().clear();
     }
 }
 
 /// Least Recently Stored eviction policy - removes oldest stored items
 /// This policy does NOT track access times, only store times (like diskcache default)
 pub struct LeastRecentlyStoredEviction {
-    store_order: Arc<RwLock<BTreeMap<u64, String>>>,
-    key_to_time: Arc<RwLock<HashMap<String, u64>>>,
-    counter: Arc<RwLock<u64>>,
+    store_order: Arc<OrderedRwLock<BTreeMap<u64, String>>>,
+    key_to_time: Arc<OrderedRwLock<HashMap<String, u64>>>,
+    counter: Arc<OrderedRwLock<u64>>,
 }
 
 impl LeastRecentlyStoredEviction {
     #[allow(dead_code)]
     pub fn new() -> Self {
         Self {
-            store_order: Arc::new(RwLock::new(BTreeMap::new())),
-            key_to_time: Arc::new(RwLock::new(HashMap::new())),
-            counter: Arc::new(RwLock::new(0)),
+            store_order: Arc::new(OrderedRwLock::new(
+                LockLevel::EvictionOrder,
+                BTreeMap::new(),
+            )),
+            key_to_time: Arc::new(OrderedRwLock::new(LockLevel::EvictionKeys, HashMap::new())),
+            counter: Arc::new(OrderedRwLock::new(LockLevel::EvictionCounter, 0)),
         }
     }
 
@@ -313,16 +589,524 @@ impl EvictionPolicy for LeastRecentlyStoredEviction {
     }
 }
 
+/// What a [`SampledEviction`] ranks sampled keys by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleRank {
+    /// Least recently used first
+    Recency,
+    /// Least often used first, least recently used among equals
+    Frequency,
+}
+
+/// Approximate LRU or LFU eviction by sampling, as Redis does
+///
+/// Each key costs one slot holding the tick it was last used at and its
+/// hit count, instead of the ordered maps [`LruEviction`] and
+/// [`LfuEviction`] keep besides. Picking a victim looks at `samples`
+/// randomly chosen keys and takes the lowest ranked; more samples come
+/// closer to the exact order at the cost of slower eviction.
+pub struct SampledEviction {
+    state: OrderedRwLock<SampledState>,
+    samples: usize,
+    rank: SampleRank,
+}
+
+struct SampledState {
+    /// Dense, so a random index is a random key
+    slots: Vec<SampledSlot>,
+    index: HashMap<Arc<str>, usize>,
+    tick: u64,
+    rng: u64,
+}
+
+struct SampledSlot {
+    key: Arc<str>,
+    last_used: u64,
+    hits: u32,
+}
+
+impl SampledEviction {
+    pub fn new(samples: usize, rank: SampleRank) -> Self {
+        Self {
+            state: OrderedRwLock::new(
+                LockLevel::EvictionOrder,
+                SampledState {
+                    slots: Vec::new(),
+                    index: HashMap::new(),
+                    tick: 0,
+                    // xorshift never leaves zero
+                    rng: uuid::Uuid::new_v4().as_u64_pair().0 | 1,
+                },
+            ),
+            samples: samples.max(1),
+            rank,
+        }
+    }
+
+    /// Hits counted for `key`
+    pub fn hits(&self, key: &str) -> Option<u64> {
+        let state = self.state.read();
+        let slot = *state.index.get(key)?;
+        Some(u64::from(state.slots[slot].hits))
+    }
+
+    fn score(&self, slot: &SampledSlot) -> (u64, u64) {
+        match self.rank {
+            SampleRank::Recency => (slot.last_used, 0),
+            SampleRank::Frequency => (u64::from(slot.hits), slot.last_used),
+        }
+    }
+}
+
+impl SampledState {
+    /// Note a use of `key` adding `hits`, adding the key if it is new
+    fn touch(&mut self, key: &str, hits: u32) {
+        self.tick += 1;
+        match self.index.get(key) {
+            Some(&slot) => {
+                let slot = &mut self.slots[slot];
+                slot.last_used = self.tick;
+                slot.hits = slot.hits.saturating_add(hits);
+            }
+            None => {
+                let key: Arc<str> = Arc::from(key);
+                self.index.insert(key.clone(), self.slots.len());
+                self.slots.push(SampledSlot {
+                    key,
+                    last_used: self.tick,
+                    hits,
+                });
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        let Some(slot) = self.index.remove(key) else {
+            return;
+        };
+        self.slots.swap_remove(slot);
+        if let Some(moved) = self.slots.get(slot) {
+            self.index.insert(moved.key.clone(), slot);
+        }
+    }
+
+    /// Random index below `len`, by xorshift64*
+    fn random(&mut self, len: usize) -> usize {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        (self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d) % len as u64) as usize
+    }
+}
+
+impl EvictionPolicy for SampledEviction {
+    fn on_access(&self, key: &str, _entry: &CacheEntry) {
+        self.state.write().touch(key, 1);
+    }
+
+    fn on_insert(&self, key: &str, entry: &CacheEntry) {
+        // Only restored entries come with accesses counted already
+        let hits = u32::try_from(entry.access_count).unwrap_or(u32::MAX);
+        self.state.write().touch(key, hits);
+    }
+
+    fn on_remove(&self, key: &str) {
+        self.state.write().remove(key);
+    }
+
+    fn select_victims(&self, count: usize) -> Vec<String> {
+        // Drawing samples advances the generator, so even picking writes
+        let mut state = self.state.write();
+        let len = state.slots.len();
+        let mut picked: Vec<usize> = if count.saturating_mul(self.samples) >= len {
+            (0..len).collect()
+        } else {
+            let mut picked: Vec<usize> = (0..count * self.samples)
+                .map(|_| state.random(len))
+                .collect();
+            picked.sort_unstable();
+            picked.dedup();
+            picked
+        };
+        picked.sort_by_key(|&slot| self.score(&state.slots[slot]));
+        picked
+            .into_iter()
+            .take(count)
+            .map(|slot| state.slots[slot].key.to_string())
+            .collect()
+    }
+
+    fn clear(&self) {
+        let mut state = self.state.write();
+        state.slots.clear();
+        state.index.clear();
+        state.tick = 0;
+    }
+}
+
+/// Entries a W-TinyLFU policy sizes itself for when the cache has no entry limit
+const DEFAULT_TINY_LFU_CAPACITY: u64 = 100_000;
+/// Keys a W-TinyLFU policy weighs for each victim it picks past the rejected ones
+const VICTIM_SAMPLE: usize = 4;
+
+/// Where a key sits in a [`TinyLfuEviction`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Region {
+    Window,
+    Probation,
+    Protected,
+    /// Lost admission to the main region; evicted first
+    Rejected,
+}
+
+/// Window TinyLFU eviction policy
+///
+/// New keys enter a small LRU window of 1% of `capacity`. A key pushed out of
+/// the window joins the main region's probation segment while there is room;
+/// once the main region is full it has to beat the segment's least recently
+/// used key, by a count-min sketch of recent accesses, and whichever of the
+/// two was accessed less often is rejected. Rejected keys are the first
+/// victims, then the least accessed of the oldest keys on probation and in
+/// the window. A key hit again while on probation moves to the protected
+/// segment, 80% of the main region, whose least recently used keys fall back
+/// to probation. Keys stored once and never read again so cannot push out
+/// keys that are read over and over, while the window still gives new keys
+/// the time to gather hits.
+pub struct TinyLfuEviction {
+    state: OrderedRwLock<TinyLfuState>,
+}
+
+struct TinyLfuState {
+    sketch: FrequencySketch,
+    /// Each region's keys by the tick they were last used at
+    order: HashMap<Region, BTreeMap<u64, String>>,
+    keys: HashMap<String, (Region, u64)>,
+    tick: u64,
+    window_capacity: usize,
+    main_capacity: usize,
+    protected_capacity: usize,
+}
+
+impl TinyLfuEviction {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(2);
+        let window_capacity = (capacity / 100).max(1);
+        let main_capacity = capacity - window_capacity;
+        Self {
+            state: OrderedRwLock::new(
+                LockLevel::EvictionOrder,
+                TinyLfuState {
+                    // Wide enough that keys seen once rarely share every
+                    // counter with keys seen often
+                    sketch: FrequencySketch::new(
+                        capacity.saturating_mul(4).next_power_of_two(),
+                        capacity as u64 * 10,
+                    ),
+                    order: HashMap::new(),
+                    keys: HashMap::new(),
+                    tick: 0,
+                    window_capacity,
+                    main_capacity,
+                    protected_capacity: main_capacity * 4 / 5,
+                },
+            ),
+        }
+    }
+}
+
+impl TinyLfuState {
+    fn len(&self, region: Region) -> usize {
+        self.order.get(&region).map_or(0, BTreeMap::len)
+    }
+
+    /// Make `key` the most recently used key of `region`
+    fn place(&mut self, key: &str, region: Region) {
+        self.remove(key);
+        self.tick += 1;
+        self.order
+            .entry(region)
+            .or_default()
+            .insert(self.tick, key.to_string());
+        self.keys.insert(key.to_string(), (region, self.tick));
+    }
+
+    fn remove(&mut self, key: &str) -> Option<Region> {
+        let (region, tick) = self.keys.remove(key)?;
+        if let Some(order) = self.order.get_mut(&region) {
+            order.remove(&tick);
+        }
+        Some(region)
+    }
+
+    /// Least recently used key of `region`
+    fn oldest(&self, region: Region) -> Option<String> {
+        self.order
+            .get(&region)
+            .and_then(|order| order.values().next().cloned())
+    }
+
+    fn count(&mut self, key: &str) {
+        self.sketch.increment(key);
+        self.sketch.halve_if_due();
+    }
+
+    /// Move keys the window overflows with into the main region, or reject them
+    fn admit(&mut self) {
+        while self.len(Region::Window) > self.window_capacity {
+            let Some(candidate) = self.oldest(Region::Window) else {
+                break;
+            };
+            let main = self.len(Region::Probation) + self.len(Region::Protected);
+            let victim = self
+                .oldest(Region::Probation)
+                .or_else(|| self.oldest(Region::Protected));
+            match victim {
+                Some(victim) if main >= self.main_capacity => {
+                    if self.sketch.estimate(&candidate) > self.sketch.estimate(&victim) {
+                        self.place(&victim, Region::Rejected);
+                        self.place(&candidate, Region::Probation);
+                    } else {
+                        self.place(&candidate, Region::Rejected);
+                    }
+                }
+                _ => self.place(&candidate, Region::Probation),
+            }
+        }
+    }
+
+    /// Note a hit on `key`, which was counted already
+    fn hit(&mut self, key: &str) {
+        match self.keys.get(key).map(|(region, _)| *region) {
+            Some(Region::Probation | Region::Protected) => {
+                self.place(key, Region::Protected);
+                while self.len(Region::Protected) > self.protected_capacity {
+                    let Some(demoted) = self.oldest(Region::Protected) else {
+                        break;
+                    };
+                    self.place(&demoted, Region::Probation);
+                }
+            }
+            Some(Region::Window) => self.place(key, Region::Window),
+            // Rejected keys get another go, as do keys stored before the
+            // policy saw them
+            Some(Region::Rejected) | None => {
+                self.place(key, Region::Window);
+                self.admit();
+            }
+        }
+    }
+}
+
+impl EvictionPolicy for TinyLfuEviction {
+    fn on_access(&self, key: &str, _entry: &CacheEntry) {
+        let mut state = self.state.write();
+        state.count(key);
+        state.hit(key);
+    }
+
+    fn on_insert(&self, key: &str, _entry: &CacheEntry) {
+        let mut state = self.state.write();
+        state.count(key);
+        if state.keys.contains_key(key) {
+            state.hit(key);
+        } else {
+            state.place(key, Region::Window);
+            state.admit();
+        }
+    }
+
+    fn on_remove(&self, key: &str) {
+        self.state.write().remove(key);
+    }
+
+    fn select_victims(&self, count: usize) -> Vec<String> {
+        let state = self.state.read();
+        let keys = |region| {
+            state
+                .order
+                .get(&region)
+                .into_iter()
+                .flat_map(|order| order.values())
+        };
+        let mut victims: Vec<String> = keys(Region::Rejected).take(count).cloned().collect();
+
+        // Past the rejected keys, the least accessed of the oldest on
+        // probation and in the window, so a hot key that just aged onto
+        // probation is not culled with the cold ones around it
+        let wanted = count - victims.len();
+        let mut sampled: Vec<(u64, &String)> = keys(Region::Probation)
+            .chain(keys(Region::Window))
+            .take(wanted.saturating_mul(VICTIM_SAMPLE))
+            .map(|key| (state.sketch.estimate(key), key))
+            .collect();
+        // Stable, so keys accessed as often go oldest first
+        sampled.sort_by_key(|(estimate, _)| *estimate);
+        victims.extend(sampled.into_iter().take(wanted).map(|(_, key)| key.clone()));
+
+        let wanted = count - victims.len();
+        victims.extend(keys(Region::Protected).take(wanted).cloned());
+        victims
+    }
+
+    fn clear(&self) {
+        let mut state = self.state.write();
+        state.sketch.clear();
+        state.order.clear();
+        state.keys.clear();
+        state.tick = 0;
+    }
+}
+
+/// Weight of entries stored without one
+const DEFAULT_WEIGHT: f64 = 1.0;
+
+/// Greedy Dual-Size Frequency eviction policy
+///
+/// Each key gets the priority `clock + hits * weight / size`: entries read
+/// often, costly to recompute and small are kept longest, and the cheapest
+/// per byte to lose go first. `weight` is whatever the caller stored the
+/// entry with, for example seconds of compute, and [`DEFAULT_WEIGHT`]
+/// otherwise. Evicting a key moves the clock up to its priority, so keys
+/// that were popular long ago age out instead of outliving every newcomer.
+pub struct GdsfEviction {
+    state: OrderedRwLock<GdsfState>,
+}
+
+struct GdsfState {
+    /// Keys by the bits of their priority, non-negative so they sort as
+    /// floats do, then by the tick that placed them
+    order: BTreeMap<(u64, u64), String>,
+    keys: HashMap<String, GdsfKey>,
+    clock: f64,
+    tick: u64,
+}
+
+struct GdsfKey {
+    hits: u64,
+    weight: f64,
+    size: u64,
+    /// Where the key sits in `order`
+    slot: (u64, u64),
+}
+
+impl GdsfEviction {
+    pub fn new() -> Self {
+        Self {
+            state: OrderedRwLock::new(
+                LockLevel::EvictionOrder,
+                GdsfState {
+                    order: BTreeMap::new(),
+                    keys: HashMap::new(),
+                    clock: 0.0,
+                    tick: 0,
+                },
+            ),
+        }
+    }
+}
+
+impl GdsfState {
+    /// Give `key` a priority from its hits, weight and size at the current clock
+    fn place(&mut self, key: &str, hits: u64, weight: f64, size: u64) {
+        self.remove(key);
+        let priority = self.clock + hits as f64 * weight / size.max(1) as f64;
+        self.tick += 1;
+        let slot = (priority.to_bits(), self.tick);
+        self.order.insert(slot, key.to_string());
+        self.keys.insert(
+            key.to_string(),
+            GdsfKey {
+                hits,
+                weight,
+                size,
+                slot,
+            },
+        );
+    }
+
+    fn remove(&mut self, key: &str) -> Option<GdsfKey> {
+        let removed = self.keys.remove(key)?;
+        self.order.remove(&removed.slot);
+        Some(removed)
+    }
+}
+
+impl EvictionPolicy for GdsfEviction {
+    fn on_access(&self, key: &str, entry: &CacheEntry) {
+        let mut state = self.state.write();
+        let (hits, weight, size) = match state.keys.get(key) {
+            Some(known) => (known.hits + 1, known.weight, known.size),
+            // Stored before the policy saw it
+            None => (1, entry.weight.unwrap_or(DEFAULT_WEIGHT), entry.size),
+        };
+        state.place(key, hits, weight, size);
+    }
+
+    fn on_insert(&self, key: &str, entry: &CacheEntry) {
+        let weight = entry.weight.unwrap_or(DEFAULT_WEIGHT);
+        self.state.write().place(key, 1, weight, entry.size);
+    }
+
+    fn on_remove(&self, key: &str) {
+        let mut state = self.state.write();
+        let Some(removed) = state.remove(key) else {
+            return;
+        };
+        // Removing the lowest priority key, as evicting does, moves the
+        // clock up to it
+        let lowest = state
+            .order
+            .keys()
+            .next()
+            .is_none_or(|first| removed.slot < *first);
+        if lowest {
+            state.clock = state.clock.max(f64::from_bits(removed.slot.0));
+        }
+    }
+
+    fn select_victims(&self, count: usize) -> Vec<String> {
+        self.state
+            .read()
+            .order
+            .values()
+            .take(count)
+            .cloned()
+            .collect()
+    }
+
+    fn clear(&self) {
+        let mut state = self.state.write();
+        state.order.clear();
+        state.keys.clear();
+        state.clock = 0.0;
+        state.tick = 0;
+    }
+}
+
 /// Combined eviction policy that uses multiple strategies
 pub struct CombinedEviction {
     lru: LruEviction,
     lfu: LfuEviction,
     ttl: TtlEviction,
     least_recently_stored: LeastRecentlyStoredEviction,
+    gdsf: GdsfEviction,
+    /// Only built for [`EvictionStrategy::TinyLfu`], as its sketch is sized up front
+    tiny_lfu: Option<TinyLfuEviction>,
+    /// Stands in for `lru` or `lfu` once [`CombinedEviction::sampled`] asks for it
+    sampled: Option<SampledEviction>,
     primary_strategy: EvictionStrategy,
+    /// Bytes each tracked key held when it was last stored
+    sizes: OrderedRwLock<HashMap<String, u64>>,
+    /// Weights of the tracked keys stored with one
+    weights: OrderedRwLock<HashMap<String, f64>>,
+    /// Keys never picked as victims, tracked or not
+    pinned: OrderedRwLock<HashSet<String>>,
+    /// How long keys may go unused before they expire; `ttl` tracks when
+    /// each was last used while set
+    max_idle: Option<Duration>,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[allow(dead_code)]
 pub enum EvictionStrategy {
     /// No eviction policy - items are never automatically removed
@@ -339,30 +1123,155 @@ pub enum EvictionStrategy {
     LruTtl,
     /// Combined LFU + TTL
     LfuTtl,
+    /// Window TinyLFU - admits keys to the main region by how often they were accessed
+    TinyLfu,
+    /// Greedy Dual-Size Frequency - evicts what costs least to recompute per byte and hit
+    Gdsf,
+}
+
+/// Parses python-diskcache `eviction_policy` names
+impl FromStr for EvictionStrategy {
+    type Err = CacheError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "least-recently-stored" => Ok(Self::LeastRecentlyStored),
+            "least-recently-used" => Ok(Self::Lru),
+            "least-frequently-used" => Ok(Self::Lfu),
+            "window-tinylfu" => Ok(Self::TinyLfu),
+            "gdsf" => Ok(Self::Gdsf),
+            other => Err(CacheError::Config(ConfigIssue::new(
+                "eviction_policy",
+                format!("unknown eviction policy {:?}", other),
+                "use \"least-recently-stored\", \"least-recently-used\", \"least-frequently-used\", \"window-tinylfu\", \"gdsf\" or \"none\"",
+            ))),
+        }
+    }
 }
 
 impl CombinedEviction {
-    #[allow(dead_code)]
-    pub fn new(strategy: EvictionStrategy) -> Self {
+    /// A policy for `strategy` in a cache holding at most `max_entries`
+    pub fn new(strategy: EvictionStrategy, max_entries: Option<u64>) -> Self {
+        let tiny_lfu = matches!(strategy, EvictionStrategy::TinyLfu).then(|| {
+            let capacity = max_entries.unwrap_or(DEFAULT_TINY_LFU_CAPACITY);
+            TinyLfuEviction::new(usize::try_from(capacity).unwrap_or(usize::MAX))
+        });
         Self {
             lru: LruEviction::new(),
             lfu: LfuEviction::new(),
             ttl: TtlEviction::new(),
             least_recently_stored: LeastRecentlyStoredEviction::new(),
+            gdsf: GdsfEviction::new(),
+            tiny_lfu,
+            sampled: None,
             primary_strategy: strategy,
+            sizes: OrderedRwLock::new(LockLevel::EvictionKeys, HashMap::new()),
+            weights: OrderedRwLock::new(LockLevel::EvictionKeys, HashMap::new()),
+            pinned: OrderedRwLock::new(LockLevel::EvictionKeys, HashSet::new()),
+            max_idle: None,
+        }
+    }
+
+    /// Report keys unused for `max_idle` as idle, whatever the strategy
+    pub fn max_idle(mut self, max_idle: Option<Duration>) -> Self {
+        self.max_idle = max_idle;
+        self
+    }
+
+    /// Approximate LRU or LFU by looking at `samples` random keys per victim
+    ///
+    /// Only affects the least-recently-used and least-frequently-used
+    /// strategies; zero keeps their exact order.
+    pub fn sampled(mut self, samples: usize) -> Self {
+        let rank = match self.primary_strategy {
+            EvictionStrategy::Lru | EvictionStrategy::LruTtl => SampleRank::Recency,
+            EvictionStrategy::Lfu | EvictionStrategy::LfuTtl => SampleRank::Frequency,
+            _ => return self,
+        };
+        self.sampled = (samples > 0).then(|| SampledEviction::new(samples, rank));
+        self
+    }
+
+    /// Up to `count` victims, pinned keys included
+    fn candidates(&self, count: usize) -> Vec<String> {
+        // First, get expired keys
+        let mut victims = self.ttl.get_expired_keys();
+
+        if victims.len() >= count {
+            return victims.into_iter().take(count).collect();
+        }
+
+        // If we need more victims, use the primary strategy
+        victims.extend(self.ordered(count - victims.len()));
+        victims.into_iter().take(count).collect()
+    }
+
+    /// Up to `count` keys the primary strategy would evict first
+    fn ordered(&self, remaining: usize) -> Vec<String> {
+        match self.primary_strategy {
+            EvictionStrategy::Lru
+            | EvictionStrategy::LruTtl
+            | EvictionStrategy::Lfu
+            | EvictionStrategy::LfuTtl
+                if self.sampled.is_some() =>
+            {
+                self.sampled
+                    .as_ref()
+                    .map(|sampled| sampled.select_victims(remaining))
+                    .unwrap_or_default()
+            }
+            EvictionStrategy::Lru | EvictionStrategy::LruTtl => self.lru.select_victims(remaining),
+            EvictionStrategy::Lfu | EvictionStrategy::LfuTtl => self.lfu.select_victims(remaining),
+            EvictionStrategy::LeastRecentlyStored => {
+                self.least_recently_stored.select_victims(remaining)
+            }
+            EvictionStrategy::TinyLfu => self
+                .tiny_lfu
+                .as_ref()
+                .map(|tiny_lfu| tiny_lfu.select_victims(remaining))
+                .unwrap_or_default(),
+            EvictionStrategy::Gdsf => self.gdsf.select_victims(remaining),
+            EvictionStrategy::Ttl | EvictionStrategy::None => Vec::new(),
+        }
+    }
+
+    /// Accesses counted for `key`, by the strategies that count them
+    fn hits(&self, key: &str) -> u64 {
+        match (&self.sampled, self.primary_strategy) {
+            (Some(sampled), _) => sampled.hits(key),
+            (None, EvictionStrategy::Lfu | EvictionStrategy::LfuTtl) => self.lfu.frequency(key),
+            _ => None,
         }
+        .unwrap_or(0)
     }
 }
 
 impl EvictionPolicy for CombinedEviction {
     fn on_access(&self, key: &str, entry: &CacheEntry) {
         match self.primary_strategy {
+            EvictionStrategy::Lru
+            | EvictionStrategy::LruTtl
+            | EvictionStrategy::Lfu
+            | EvictionStrategy::LfuTtl
+                if self.sampled.is_some() =>
+            {
+                if let Some(sampled) = &self.sampled {
+                    sampled.on_access(key, entry);
+                }
+            }
             EvictionStrategy::Lru | EvictionStrategy::LruTtl => {
                 self.lru.on_access(key, entry);
             }
             EvictionStrategy::Lfu | EvictionStrategy::LfuTtl => {
                 self.lfu.on_access(key, entry);
             }
+            EvictionStrategy::TinyLfu => {
+                if let Some(tiny_lfu) = &self.tiny_lfu {
+                    tiny_lfu.on_access(key, entry);
+                }
+            }
+            EvictionStrategy::Gdsf => self.gdsf.on_access(key, entry),
             EvictionStrategy::Ttl
             | EvictionStrategy::LeastRecentlyStored
             | EvictionStrategy::None => {
@@ -376,11 +1285,30 @@ impl EvictionPolicy for CombinedEviction {
         ) {
             self.ttl.on_access(key, entry);
         }
+        if self.max_idle.is_some() {
+            self.ttl.touch(key);
+        }
     }
 
     fn on_insert(&self, key: &str, entry: &CacheEntry) {
+        self.sizes.write().insert(key.to_string(), entry.size);
+        match entry.weight {
+            Some(weight) => self.weights.write().insert(key.to_string(), weight),
+            None => self.weights.write().remove(key),
+        };
+
         // For insert, we need to track in the appropriate strategy
         match self.primary_strategy {
+            EvictionStrategy::Lru
+            | EvictionStrategy::LruTtl
+            | EvictionStrategy::Lfu
+            | EvictionStrategy::LfuTtl
+                if self.sampled.is_some() =>
+            {
+                if let Some(sampled) = &self.sampled {
+                    sampled.on_insert(key, entry);
+                }
+            }
             EvictionStrategy::Lru | EvictionStrategy::LruTtl => {
                 self.lru.on_insert(key, entry);
             }
@@ -390,6 +1318,12 @@ impl EvictionPolicy for CombinedEviction {
             EvictionStrategy::LeastRecentlyStored => {
                 self.least_recently_stored.on_insert(key, entry);
             }
+            EvictionStrategy::TinyLfu => {
+                if let Some(tiny_lfu) = &self.tiny_lfu {
+                    tiny_lfu.on_insert(key, entry);
+                }
+            }
+            EvictionStrategy::Gdsf => self.gdsf.on_insert(key, entry),
             EvictionStrategy::Ttl | EvictionStrategy::None => {}
         }
 
@@ -399,42 +1333,441 @@ impl EvictionPolicy for CombinedEviction {
         ) {
             self.ttl.on_insert(key, entry);
         }
+        if self.max_idle.is_some() {
+            self.ttl.touch(key);
+        }
     }
 
     fn on_remove(&self, key: &str) {
+        self.sizes.write().remove(key);
+        self.weights.write().remove(key);
         self.lru.on_remove(key);
         self.lfu.on_remove(key);
         self.ttl.on_remove(key);
         self.least_recently_stored.on_remove(key);
+        self.gdsf.on_remove(key);
+        if let Some(tiny_lfu) = &self.tiny_lfu {
+            tiny_lfu.on_remove(key);
+        }
+        if let Some(sampled) = &self.sampled {
+            sampled.on_remove(key);
+        }
     }
 
     fn select_victims(&self, count: usize) -> Vec<String> {
-        // First, get expired keys
-        let mut victims = self.ttl.get_expired_keys();
+        // Enough candidates that skipping every pinned key still leaves `count`
+        let pinned_count = self.pinned.read().len();
+        let candidates = self.candidates(count.saturating_add(pinned_count));
+        let pinned = self.pinned.read();
+        candidates
+            .into_iter()
+            .filter(|key| !pinned.contains(key))
+            .take(count)
+            .collect()
+    }
 
-        if victims.len() >= count {
-            return victims.into_iter().take(count).collect();
+    fn clear(&self) {
+        self.sizes.write().clear();
+        self.weights.write().clear();
+        self.lru.clear();
+        self.lfu.clear();
+        self.ttl.clear();
+        self.least_recently_stored.clear();
+        self.gdsf.clear();
+        if let Some(tiny_lfu) = &self.tiny_lfu {
+            tiny_lfu.clear();
         }
+        if let Some(sampled) = &self.sampled {
+            sampled.clear();
+        }
+    }
 
-        // If we need more victims, use the primary strategy
-        let remaining = count - victims.len();
-        let additional_victims = match self.primary_strategy {
-            EvictionStrategy::Lru | EvictionStrategy::LruTtl => self.lru.select_victims(remaining),
-            EvictionStrategy::Lfu | EvictionStrategy::LfuTtl => self.lfu.select_victims(remaining),
-            EvictionStrategy::LeastRecentlyStored => {
-                self.least_recently_stored.select_victims(remaining)
+    fn entry_size(&self, key: &str) -> Option<u64> {
+        self.sizes.read().get(key).copied()
+    }
+
+    fn entry_weight(&self, key: &str) -> Option<f64> {
+        self.weights.read().get(key).copied()
+    }
+
+    fn is_idle(&self, key: &str) -> bool {
+        self.max_idle
+            .is_some_and(|max_idle| self.ttl.is_idle(key, max_idle))
+    }
+
+    fn idle_keys(&self, count: usize) -> Vec<String> {
+        self.max_idle
+            .map(|max_idle| self.ttl.get_idle_keys(max_idle, count))
+            .unwrap_or_default()
+    }
+
+    fn set_pinned(&self, key: &str, pinned: bool) {
+        if pinned {
+            self.pinned.write().insert(key.to_string());
+        } else {
+            self.pinned.write().remove(key);
+        }
+    }
+
+    /// Keys only the TTL strategy tracks go first, as their order means
+    /// nothing to the others
+    fn checkpoint(&self) -> Vec<TrackedKey> {
+        let ordered = self.ordered(usize::MAX);
+        let seen: HashSet<&str> = ordered.iter().map(String::as_str).collect();
+        let expiring: Vec<String> = self
+            .ttl
+            .keys()
+            .into_iter()
+            .filter(|key| !seen.contains(key.as_str()))
+            .collect();
+        expiring
+            .into_iter()
+            .chain(ordered)
+            .map(|key| TrackedKey {
+                size: self.entry_size(&key).unwrap_or(0),
+                weight: self.entry_weight(&key),
+                hits: self.hits(&key),
+                expire_time: self.ttl.expiry(&key),
+                priority: Priority::Normal,
+                key,
+            })
+            .collect()
+    }
+}
+
+/// Evicts low [`Priority`] entries before any other, and high priority ones
+/// last
+///
+/// Each level has a policy of its own, so within a level entries go in the
+/// order the cache's eviction policy picks them.
+pub struct PriorityEviction {
+    /// Lowest priority first
+    levels: [CombinedEviction; 3],
+    /// The level of every tracked key not at [`Priority::Normal`]
+    priorities: OrderedRwLock<HashMap<String, Priority>>,
+}
+
+impl PriorityEviction {
+    /// Levels each evicting by a policy from `build`
+    pub fn new(build: impl Fn() -> CombinedEviction) -> Self {
+        Self {
+            levels: [build(), build(), build()],
+            priorities: OrderedRwLock::new(LockLevel::EvictionKeys, HashMap::new()),
+        }
+    }
+
+    fn level(&self, priority: Priority) -> &CombinedEviction {
+        &self.levels[priority as usize]
+    }
+
+    fn priority(&self, key: &str) -> Priority {
+        self.priorities.read().get(key).copied().unwrap_or_default()
+    }
+}
+
+impl EvictionPolicy for PriorityEviction {
+    fn on_access(&self, key: &str, entry: &CacheEntry) {
+        self.level(self.priority(key)).on_access(key, entry);
+    }
+
+    fn on_insert(&self, key: &str, entry: &CacheEntry) {
+        let previous = {
+            let mut priorities = self.priorities.write();
+            match entry.priority {
+                Priority::Normal => priorities.remove(key),
+                priority => priorities.insert(key.to_string(), priority),
             }
-            EvictionStrategy::Ttl | EvictionStrategy::None => Vec::new(),
-        };
+        }
+        .unwrap_or_default();
+        if previous != entry.priority {
+            self.level(previous).on_remove(key);
+        }
+        self.level(entry.priority).on_insert(key, entry);
+    }
 
-        victims.extend(additional_victims);
-        victims.into_iter().take(count).collect()
+    fn on_remove(&self, key: &str) {
+        let priority = self.priorities.write().remove(key).unwrap_or_default();
+        self.level(priority).on_remove(key);
+    }
+
+    fn select_victims(&self, count: usize) -> Vec<String> {
+        let mut victims = Vec::new();
+        for level in &self.levels {
+            if victims.len() >= count {
+                break;
+            }
+            victims.extend(level.select_victims(count - victims.len()));
+        }
+        victims
     }
 
     fn clear(&self) {
-        self.lru.clear();
-        self.lfu.clear();
-        self.ttl.clear();
-        self.least_recently_stored.clear();
+        self.priorities.write().clear();
+        for level in &self.levels {
+            level.clear();
+        }
+    }
+
+    fn entry_size(&self, key: &str) -> Option<u64> {
+        self.level(self.priority(key)).entry_size(key)
+    }
+
+    fn entry_weight(&self, key: &str) -> Option<f64> {
+        self.level(self.priority(key)).entry_weight(key)
+    }
+
+    fn is_idle(&self, key: &str) -> bool {
+        self.level(self.priority(key)).is_idle(key)
+    }
+
+    fn idle_keys(&self, count: usize) -> Vec<String> {
+        let mut idle = Vec::new();
+        for level in &self.levels {
+            if idle.len() >= count {
+                break;
+            }
+            idle.extend(level.idle_keys(count - idle.len()));
+        }
+        idle
+    }
+
+    fn set_pinned(&self, key: &str, pinned: bool) {
+        for level in &self.levels {
+            level.set_pinned(key, pinned);
+        }
+    }
+
+    fn checkpoint(&self) -> Vec<TrackedKey> {
+        let priorities = [Priority::Low, Priority::Normal, Priority::High];
+        self.levels
+            .iter()
+            .zip(priorities)
+            .flat_map(|(level, priority)| {
+                level
+                    .checkpoint()
+                    .into_iter()
+                    .map(move |tracked| TrackedKey {
+                        priority,
+                        ..tracked
+                    })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Store `key` under `policy`, evicting down to `capacity` keys like a cache would
+    fn store(policy: &dyn EvictionPolicy, key: &str, stored: &mut Vec<String>, capacity: usize) {
+        let entry = CacheEntry::new_inline(key.to_string(), vec![0], vec![], None);
+        policy.on_insert(key, &entry);
+        stored.push(key.to_string());
+        if stored.len() > capacity {
+            for victim in policy.select_victims(stored.len() - capacity) {
+                policy.on_remove(&victim);
+                stored.retain(|key| *key != victim);
+            }
+        }
+    }
+
+    #[test]
+    fn tiny_lfu_keeps_hot_keys_through_a_scan_of_one_hit_wonders() {
+        let capacity = 100;
+        for (strategy, hot_kept) in [
+            (EvictionStrategy::TinyLfu, true),
+            (EvictionStrategy::Lru, false),
+        ] {
+            let policy = CombinedEviction::new(strategy, Some(capacity as u64));
+            let mut stored = Vec::new();
+            let hot: Vec<String> = (0..50).map(|i| format!("hot-{}", i)).collect();
+            for key in &hot {
+                store(&policy, key, &mut stored, capacity);
+            }
+            for _ in 0..5 {
+                for key in &hot {
+                    let entry = CacheEntry::new_inline(key.clone(), vec![0], vec![], None);
+                    policy.on_access(key, &entry);
+                }
+            }
+            for i in 0..1_000 {
+                store(&policy, &format!("once-{}", i), &mut stored, capacity);
+            }
+
+            assert_eq!(stored.len(), capacity);
+            let kept = hot.iter().filter(|key| stored.contains(key)).count();
+            assert_eq!(kept == hot.len(), hot_kept, "{:?} kept {}", strategy, kept);
+        }
+    }
+
+    #[test]
+    fn victims_by_size_free_just_enough_bytes() {
+        let policy = CombinedEviction::new(EvictionStrategy::LeastRecentlyStored, None);
+        for (key, size) in [("small", 10), ("large", 1_000), ("medium", 100)] {
+            let entry = CacheEntry::new_inline(key.to_string(), vec![0; size], vec![], None);
+            policy.on_insert(key, &entry);
+        }
+        assert_eq!(policy.select_victims_by_size(5), ["small"]);
+        assert_eq!(policy.select_victims_by_size(11), ["small", "large"]);
+        assert_eq!(policy.select_victims_by_size(1_010), ["small", "large"]);
+        assert_eq!(policy.select_victims_by_size(u64::MAX).len(), 3);
+
+        // A rewrite counts the new size
+        let entry = CacheEntry::new_inline("small".into(), vec![0; 2_000], vec![], None);
+        policy.on_insert("small", &entry);
+        assert_eq!(
+            policy.select_victims_by_size(1_500),
+            ["large", "medium", "small"]
+        );
+        policy.on_remove("large");
+        assert_eq!(policy.select_victims_by_size(50), ["medium"]);
+    }
+
+    #[test]
+    fn gdsf_keeps_entries_that_cost_more_to_recompute_per_byte() {
+        let policy = CombinedEviction::new(EvictionStrategy::Gdsf, None);
+        let store = |key: &str, size: usize, weight: Option<f64>| {
+            let mut entry = CacheEntry::new_inline(key.to_string(), vec![0; size], vec![], None);
+            entry.weight = weight;
+            policy.on_insert(key, &entry);
+            entry
+        };
+        store("render", 1_000, Some(3_600.0));
+        store("thumbnail", 1_000, Some(0.01));
+        store("unweighed", 1_000, None);
+        let lookup = store("lookup", 10, Some(0.5));
+        assert_eq!(
+            policy.select_victims(4),
+            ["thumbnail", "unweighed", "lookup", "render"]
+        );
+
+        // Hits multiply an entry's worth
+        for _ in 0..150 {
+            policy.on_access("thumbnail", &lookup);
+        }
+        assert_eq!(policy.select_victims(1), ["unweighed"]);
+
+        // Evicting moves the clock up, so a newcomer outranks what is left
+        // of old hits once they are worth less than the evicted entry
+        policy.on_remove("unweighed");
+        store("fresh", 1_000, None);
+        assert_eq!(policy.select_victims(1), ["thumbnail"]);
+    }
+
+    #[test]
+    fn weights_survive_a_checkpoint_under_another_strategy() {
+        let lru = PriorityEviction::new(|| CombinedEviction::new(EvictionStrategy::Lru, None));
+        for (key, weight) in [
+            ("render", Some(3_600.0)),
+            ("thumbnail", Some(0.01)),
+            ("plain", None),
+        ] {
+            let mut entry = CacheEntry::new_inline(key.to_string(), vec![0; 1_000], vec![], None);
+            entry.weight = weight;
+            lru.on_insert(key, &entry);
+        }
+        let checkpoint = lru.checkpoint();
+        assert!(checkpoint
+            .iter()
+            .any(|tracked| tracked.key == "render" && tracked.weight == Some(3_600.0)));
+
+        let gdsf = PriorityEviction::new(|| CombinedEviction::new(EvictionStrategy::Gdsf, None));
+        gdsf.restore(&checkpoint);
+        assert_eq!(gdsf.select_victims(3), ["thumbnail", "plain", "render"]);
+    }
+
+    #[test]
+    fn pinned_keys_are_never_victims() {
+        let policy = CombinedEviction::new(EvictionStrategy::Lru, None);
+        let mut stored = Vec::new();
+        policy.set_pinned("license", true);
+        for key in ["license", "a", "b", "c"] {
+            store(&policy, key, &mut stored, 2);
+        }
+        assert!(stored.contains(&"license".to_string()));
+        assert_eq!(policy.select_victims(usize::MAX), ["c"]);
+
+        policy.set_pinned("license", false);
+        assert_eq!(policy.select_victims(1), ["license"]);
+    }
+
+    #[test]
+    fn sampled_lru_evicts_keys_among_the_least_recently_used() {
+        let policy = CombinedEviction::new(EvictionStrategy::Lru, None).sampled(5);
+        let entry = CacheEntry::new_inline("key".into(), vec![0], vec![], None);
+        for i in 0..1000 {
+            policy.on_insert(&format!("key-{:04}", i), &entry);
+        }
+        // Reading the oldest keys makes them the most recently used
+        for i in 0..100 {
+            policy.on_access(&format!("key-{:04}", i), &entry);
+        }
+        let victims = policy.select_victims(100);
+        assert_eq!(victims.len(), 100);
+        assert!(victims
+            .iter()
+            .all(|key| (100..600).contains(&key[4..].parse::<u32>().unwrap())));
+
+        // Removals keep the sampled slots in step with what is stored
+        for i in 0..990 {
+            policy.on_remove(&format!("key-{:04}", i));
+        }
+        let mut remaining = policy.select_victims(usize::MAX);
+        remaining.sort();
+        let expected: Vec<String> = (990..1000).map(|i| format!("key-{:04}", i)).collect();
+        assert_eq!(remaining, expected);
+
+        policy.clear();
+        assert!(policy.select_victims(usize::MAX).is_empty());
+    }
+
+    #[test]
+    fn priorities_outrank_the_policy() {
+        let policy = PriorityEviction::new(|| CombinedEviction::new(EvictionStrategy::Lru, None));
+        let stored = |key: &str, priority| {
+            let mut entry = CacheEntry::new_inline(key.into(), vec![0], vec![], None);
+            entry.priority = priority;
+            policy.on_insert(key, &entry);
+        };
+        stored("simulation", Priority::High);
+        stored("config", Priority::Normal);
+        stored("thumbnail", Priority::Low);
+        assert_eq!(
+            policy.select_victims(usize::MAX),
+            ["thumbnail", "config", "simulation"]
+        );
+
+        // Storing again at another priority moves the key
+        stored("simulation", Priority::Low);
+        assert_eq!(policy.select_victims(2), ["thumbnail", "simulation"]);
+        policy.on_remove("simulation");
+        assert_eq!(policy.select_victims(usize::MAX), ["thumbnail", "config"]);
+
+        let checkpoint = policy.checkpoint();
+        assert_eq!(checkpoint[0].priority, Priority::Low);
+        policy.clear();
+        policy.restore(&checkpoint);
+        assert_eq!(policy.select_victims(usize::MAX), ["thumbnail", "config"]);
+    }
+
+    #[test]
+    fn tiny_lfu_admits_newcomers_that_are_accessed_more_often() {
+        let policy = TinyLfuEviction::new(10);
+        let mut stored = Vec::new();
+        for i in 0..10 {
+            store(&policy, &format!("old-{}", i), &mut stored, 10);
+        }
+        // Seen before, so it wins admission over the least used old key
+        let entry = CacheEntry::new_inline("new".into(), vec![0], vec![], None);
+        policy.on_access("new", &entry);
+        policy.on_remove("new");
+        store(&policy, "new", &mut stored, 10);
+        store(&policy, "newer", &mut stored, 10);
+        assert!(stored.contains(&"new".to_string()));
+        assert!(!stored.contains(&"old-0".to_string()));
+
+        policy.clear();
+        assert!(policy.select_victims(usize::MAX).is_empty());
     }
 }
@@ -0,0 +1,180 @@
+//! Background eviction
+//!
+//! With [`CacheConfig::inline_evictions`](crate::CacheConfig::inline_evictions)
+//! set, a write evicts at most that many of its victims itself and queues the
+//! rest here, so a write over a limit never deletes thousands of files before
+//! returning. A thread started on demand evicts the queued keys in order and
+//! stops once the queue is empty; a forked child starts its own.
+//!
+//! Queued keys stay tracked by the eviction policy until they are evicted, so
+//! writes meanwhile leave them out when picking victims and count them as
+//! room already being made. Storing a queued key again takes it off the queue
+//! before the new value is written, waiting out its eviction if that already
+//! started, so the new value is never the one evicted.
+
+use crate::error::CacheResult;
+use crate::fork;
+use parking_lot::{Condvar, Mutex};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Weak};
+
+/// Evicts one encoded key on the background thread, once the second
+/// argument claims it; a key no longer queued by then is left alone
+pub type Evict = Box<dyn Fn(&str, &dyn Fn() -> bool) -> CacheResult<()> + Send>;
+
+/// Keys waiting to be evicted, and their sizes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EvictionBacklog {
+    pub entries: u64,
+    pub bytes: u64,
+}
+
+#[derive(Default)]
+struct Queued {
+    order: VecDeque<String>,
+    /// Size of each key queued or being evicted
+    sizes: HashMap<String, u64>,
+    bytes: u64,
+    /// Fork generation of the running thread, if one runs
+    running: Option<u64>,
+    /// Key claimed for eviction and being evicted
+    evicting: Option<String>,
+}
+
+impl Queued {
+    fn forget(&mut self, key: &str) -> bool {
+        match self.sizes.remove(key) {
+            Some(size) => {
+                self.bytes -= size;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Victims a cache evicts in the background
+#[derive(Default)]
+pub struct EvictionQueue {
+    queued: Mutex<Queued>,
+    /// Signalled when an eviction in flight is done
+    evicted: Condvar,
+}
+
+impl EvictionQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue `victims` with their sizes, starting a thread to evict them
+    /// with what `evict` returns unless one runs already
+    pub fn push(
+        self: &Arc<Self>,
+        victims: Vec<(String, u64)>,
+        evict: impl FnOnce() -> Evict,
+    ) -> CacheResult<()> {
+        let mut queued = self.queued.lock();
+        for (key, size) in victims {
+            if !queued.sizes.contains_key(&key) {
+                queued.sizes.insert(key.clone(), size);
+                queued.bytes += size;
+                queued.order.push_back(key);
+            }
+        }
+        let generation = fork::generation();
+        if queued.order.is_empty() || queued.running == Some(generation) {
+            return Ok(());
+        }
+        let queue = Arc::downgrade(self);
+        let evict = evict();
+        std::thread::Builder::new()
+            .name("cache-evict".into())
+            .spawn(move || Self::run(queue, evict))?;
+        queued.running = Some(generation);
+        Ok(())
+    }
+
+    fn run(queue: Weak<Self>, evict: Evict) {
+        loop {
+            let Some(queue) = queue.upgrade() else {
+                return;
+            };
+            let key = {
+                let mut queued = queue.queued.lock();
+                let Some(key) = queued.order.front() else {
+                    queued.running = None;
+                    return;
+                };
+                key.clone()
+            };
+            if let Err(e) = evict(&key, &|| queue.claim(&key)) {
+                tracing::warn!("Failed to evict {} in the background: {}", key, e);
+            }
+            queue.finish(&key);
+        }
+    }
+
+    /// Mark `key` as being evicted, if it is still queued
+    fn claim(&self, key: &str) -> bool {
+        let mut queued = self.queued.lock();
+        if !queued.sizes.contains_key(key) {
+            return false;
+        }
+        queued.order.retain(|queued| queued != key);
+        queued.evicting = Some(key.to_string());
+        true
+    }
+
+    /// Take `key` off the queue once evicted, or when it was never claimed
+    fn finish(&self, key: &str) {
+        let mut queued = self.queued.lock();
+        if queued.evicting.as_deref() == Some(key) {
+            queued.forget(key);
+            queued.evicting = None;
+            self.evicted.notify_all();
+        } else if queued.order.front().map(String::as_str) == Some(key) {
+            queued.order.pop_front();
+            queued.forget(key);
+        }
+    }
+
+    /// Whether `key` is waiting to be evicted or being evicted
+    pub fn contains(&self, key: &str) -> bool {
+        self.queued.lock().sizes.contains_key(key)
+    }
+
+    /// Keep `key`, about to be stored again, from being evicted for having
+    /// been queued, waiting until its eviction is done if it started
+    pub fn cancel(&self, key: &str) {
+        let mut queued = self.queued.lock();
+        while queued.evicting.as_deref() == Some(key) {
+            self.evicted.wait(&mut queued);
+        }
+        if queued.forget(key) {
+            queued.order.retain(|queued| queued != key);
+        }
+    }
+
+    /// Take every key off the queue, for the caller to evict
+    pub fn drain(&self) -> Vec<String> {
+        let mut queued = self.queued.lock();
+        let keys: Vec<String> = queued.order.drain(..).collect();
+        for key in &keys {
+            queued.forget(key);
+        }
+        keys
+    }
+
+    /// Forget every queued key
+    pub fn clear(&self) {
+        self.drain();
+    }
+
+    pub fn backlog(&self) -> EvictionBacklog {
+        let queued = self.queued.lock();
+        EvictionBacklog {
+            entries: queued.sizes.len() as u64,
+            bytes: queued.bytes,
+        }
+    }
+}
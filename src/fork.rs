@@ -0,0 +1,145 @@
+//! Detection of forked children
+//!
+//! A process forked with a cache open, as gunicorn and celery prefork
+//! workers are, gets a copy of the cache's memory but none of its threads:
+//! the write batcher's worker and the heartbeats of locks, leases and
+//! registrations are gone in the child, which also shares its parent's SQLite
+//! connection and locks. `pthread_atfork` bumps a generation counter in every
+//! child, and [`ForkCheck`] notices the change the next time the cache is
+//! used there, so the cache starts its own threads, reopens its connection
+//! and leaves what belongs to the parent alone.
+//!
+//! Locks other threads of the parent held at the moment of the fork stay
+//! held in the child for good, as with any fork of a threaded process, so
+//! fork while no other thread is using the cache.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(unix)]
+use std::sync::Once;
+
+/// Forks this process is removed from its original ancestor
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Generation a [`BackgroundThread`] that never ran is taken to be from
+const NEVER: u64 = u64::MAX;
+
+#[cfg(unix)]
+extern "C" fn forked_child() {
+    GENERATION.fetch_add(1, Ordering::AcqRel);
+}
+
+/// How many forks this process is removed from the one that first asked
+pub fn generation() -> u64 {
+    #[cfg(unix)]
+    {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            // SAFETY: the handler only touches an atomic, which is
+            // async-signal-safe as a forked child requires
+            let status = unsafe { libc::pthread_atfork(None, None, Some(forked_child)) };
+            if status != 0 {
+                tracing::warn!("Failed to watch for forks: error {}", status);
+            }
+        });
+    }
+    GENERATION.load(Ordering::Acquire)
+}
+
+/// Tells an object whether the process forked since it last asked
+pub struct ForkCheck {
+    generation: AtomicU64,
+}
+
+impl ForkCheck {
+    pub fn new() -> Self {
+        Self {
+            generation: AtomicU64::new(generation()),
+        }
+    }
+
+    /// Whether this is a forked child that has not been told so yet; true
+    /// for exactly one caller after each fork
+    pub fn forked(&self) -> bool {
+        let current = generation();
+        let seen = self.generation.load(Ordering::Acquire);
+        seen != current
+            && self
+                .generation
+                .compare_exchange(seen, current, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+    }
+}
+
+impl Default for ForkCheck {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A process-wide background thread, started again in forked children
+pub struct BackgroundThread {
+    /// Generation of the process the thread was started in
+    started: AtomicU64,
+}
+
+impl BackgroundThread {
+    pub const fn new() -> Self {
+        Self {
+            started: AtomicU64::new(NEVER),
+        }
+    }
+
+    /// Start `body` on a thread named `name` unless it runs in this process
+    /// already; in a forked child, `on_fork` first drops what the thread
+    /// looked after for the parent
+    pub fn ensure(&self, name: &str, on_fork: impl FnOnce(), body: fn()) {
+        let current = generation();
+        let started = self.started.load(Ordering::Acquire);
+        if started == current
+            || self
+                .started
+                .compare_exchange(started, current, Ordering::AcqRel, Ordering::Acquire)
+                .is_err()
+        {
+            return;
+        }
+        if started != NEVER {
+            on_fork();
+        }
+        std::thread::Builder::new()
+            .name(name.into())
+            .spawn(body)
+            .expect("failed to start a background thread");
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fork_checks_fire_once_in_the_child() {
+        let check = ForkCheck::new();
+        assert!(!check.forked());
+
+        // SAFETY: the child only touches atomics and exits without unwinding
+        match unsafe { libc::fork() } {
+            0 => {
+                let status = if check.forked() && !check.forked() {
+                    0
+                } else {
+                    1
+                };
+                unsafe { libc::_exit(status) };
+            }
+            -1 => panic!("fork failed"),
+            child => {
+                let mut status = 0;
+                unsafe { libc::waitpid(child, &mut status, 0) };
+                assert!(libc::WIFEXITED(status));
+                assert_eq!(libc::WEXITSTATUS(status), 0);
+                assert!(!check.forked());
+            }
+        }
+    }
+}
@@ -0,0 +1,398 @@
+//! Response caching middleware for tower and axum services
+//!
+//! [`CacheLayer`] wraps an HTTP service and answers repeated requests from a
+//! [`DiskCache`], which gives Rust services what the Python side gets from
+//! caching an HTTP client. A [`KeyExtractor`] maps each request to the key its
+//! response is stored under. Requests it gives no key pass straight through.
+//! Only `200 OK` responses that are neither private nor setting cookies are
+//! stored. Lookups and stores are the cache's ordinary blocking calls, made
+//! inline in the service's future.
+
+use crate::cache::DiskCache;
+use crate::error::{CacheError, CacheResult};
+use crate::utils::current_timestamp;
+use bytes::Bytes;
+use http::header::{CACHE_CONTROL, SET_COOKIE};
+use http::response::Parts;
+use http::{HeaderName, HeaderValue, Method, Request, Response, StatusCode};
+use http_body::Body;
+use http_body_util::{BodyExt, Full};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// Error of a [`CacheService`], converted from the inner service's or its body's
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Maps a request to the cache key of its response; `None` bypasses the cache
+pub trait KeyExtractor<B>: Clone {
+    fn extract(&self, request: &Request<B>) -> Option<String>;
+}
+
+impl<B, F> KeyExtractor<B> for F
+where
+    F: Fn(&Request<B>) -> Option<String> + Clone,
+{
+    fn extract(&self, request: &Request<B>) -> Option<String> {
+        self(request)
+    }
+}
+
+/// Keys `GET` and `HEAD` requests by method and full URI; nothing else is cached
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MethodAndUri;
+
+impl<B> KeyExtractor<B> for MethodAndUri {
+    fn extract(&self, request: &Request<B>) -> Option<String> {
+        matches!(*request.method(), Method::GET | Method::HEAD)
+            .then(|| format!("http:{} {}", request.method(), request.uri()))
+    }
+}
+
+/// A response as stored in the cache
+#[derive(bincode::Encode, bincode::Decode)]
+struct StoredResponse {
+    status: u16,
+    headers: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+    /// Unix time after which the response is stale
+    expires_at: Option<u64>,
+}
+
+impl StoredResponse {
+    fn decode(bytes: &[u8]) -> CacheResult<Self> {
+        bincode::decode_from_slice(bytes, bincode::config::standard())
+            .map(|(stored, _)| stored)
+            .map_err(|e| CacheError::Deserialization(format!("cached response: {}", e)))
+    }
+
+    fn encode(&self) -> CacheResult<Vec<u8>> {
+        bincode::encode_to_vec(self, bincode::config::standard())
+            .map_err(|e| CacheError::Serialization(format!("cached response: {}", e)))
+    }
+
+    fn into_response(self) -> CacheResult<Response<Full<Bytes>>> {
+        let mut response = Response::new(Full::new(Bytes::from(self.body)));
+        *response.status_mut() = StatusCode::from_u16(self.status)
+            .map_err(|e| CacheError::Deserialization(format!("cached response: {}", e)))?;
+        let headers = response.headers_mut();
+        for (name, value) in self.headers {
+            let name = HeaderName::try_from(name);
+            let value = HeaderValue::try_from(value);
+            if let (Ok(name), Ok(value)) = (name, value) {
+                headers.append(name, value);
+            }
+        }
+        Ok(response)
+    }
+}
+
+/// [`Layer`] that caches the responses of the services it wraps
+#[derive(Clone)]
+pub struct CacheLayer<K = MethodAndUri> {
+    cache: Arc<DiskCache>,
+    key: K,
+    ttl: Option<Duration>,
+}
+
+impl CacheLayer {
+    /// Cache `GET` and `HEAD` responses by URI, until evicted
+    pub fn new(cache: Arc<DiskCache>) -> Self {
+        Self {
+            cache,
+            key: MethodAndUri,
+            ttl: None,
+        }
+    }
+}
+
+impl<K> CacheLayer<K> {
+    /// Choose which requests are cached and under which key
+    pub fn with_key_extractor<K2>(self, key: K2) -> CacheLayer<K2> {
+        CacheLayer {
+            cache: self.cache,
+            key,
+            ttl: self.ttl,
+        }
+    }
+
+    /// Serve stored responses for at most `ttl`, then ask the service again
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+}
+
+impl<S, K: Clone> Layer<S> for CacheLayer<K> {
+    type Service = CacheService<S, K>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CacheService {
+            inner,
+            cache: self.cache.clone(),
+            key: self.key.clone(),
+            ttl: self.ttl,
+        }
+    }
+}
+
+/// Service answering from the cache, made by [`CacheLayer`]
+#[derive(Clone)]
+pub struct CacheService<S, K = MethodAndUri> {
+    inner: S,
+    cache: Arc<DiskCache>,
+    key: K,
+    ttl: Option<Duration>,
+}
+
+impl<S, K> CacheService<S, K> {
+    /// The stored response for `key`, if there is a fresh one
+    ///
+    /// A cache that cannot be read is treated as a miss rather than failing
+    /// the request.
+    fn lookup(&self, key: &str) -> Option<Response<Full<Bytes>>> {
+        let stored = self.cache.get(key).and_then(|bytes| {
+            bytes
+                .map(|bytes| StoredResponse::decode(&bytes))
+                .transpose()
+        });
+        match stored {
+            Ok(Some(stored))
+                if stored
+                    .expires_at
+                    .is_some_and(|at| at <= current_timestamp()) =>
+            {
+                let _ = self.cache.delete(key);
+                None
+            }
+            Ok(Some(stored)) => stored
+                .into_response()
+                .map_err(|e| tracing::warn!("Ignoring cached response {:?}: {}", key, e))
+                .ok(),
+            Ok(None) => None,
+            Err(e) => {
+                tracing::warn!("Ignoring cached response {:?}: {}", key, e);
+                None
+            }
+        }
+    }
+}
+
+/// Whether a response may be stored and served to later requests
+fn cacheable(parts: &Parts) -> bool {
+    let private = parts
+        .headers
+        .get_all(CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|directive| {
+            let directive = directive.trim();
+            directive.eq_ignore_ascii_case("no-store") || directive.eq_ignore_ascii_case("private")
+        });
+    parts.status == StatusCode::OK && !private && !parts.headers.contains_key(SET_COOKIE)
+}
+
+fn store(
+    cache: &DiskCache,
+    key: &str,
+    parts: &Parts,
+    body: &Bytes,
+    ttl: Option<Duration>,
+) -> CacheResult<()> {
+    let stored = StoredResponse {
+        status: parts.status.as_u16(),
+        headers: parts
+            .headers
+            .iter()
+            .map(|(name, value)| (name.as_str().to_string(), value.as_bytes().to_vec()))
+            .collect(),
+        body: body.to_vec(),
+        expires_at: ttl.map(|ttl| current_timestamp() + ttl.as_secs()),
+    };
+    cache.set(key, &stored.encode()?, None, vec![])
+}
+
+impl<S, K, ReqBody, ResBody> Service<Request<ReqBody>> for CacheService<S, K>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<BoxError>,
+    K: KeyExtractor<ReqBody>,
+    ResBody: Body + Send + 'static,
+    ResBody::Data: Send,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response<Full<Bytes>>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let key = self.key.extract(&request);
+        if let Some(response) = key.as_deref().and_then(|key| self.lookup(key)) {
+            return Box::pin(std::future::ready(Ok(response)));
+        }
+
+        // Call the instance that was polled ready and keep a fresh clone
+        let clone = self.inner.clone();
+        let response = std::mem::replace(&mut self.inner, clone).call(request);
+        let cache = self.cache.clone();
+        let ttl = self.ttl;
+        Box::pin(async move {
+            let (parts, body) = response.await.map_err(Into::into)?.into_parts();
+            let body = body.collect().await.map_err(Into::into)?.to_bytes();
+            if let Some(key) = key.filter(|_| cacheable(&parts)) {
+                if let Err(e) = store(&cache, &key, &parts, &body, ttl) {
+                    tracing::warn!("Failed to cache response {:?}: {}", key, e);
+                }
+            }
+            Ok(Response::from_parts(parts, Full::new(body)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::CacheConfig;
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Wake, Waker};
+    use tempfile::TempDir;
+
+    /// Drive a future that never waits on anything external
+    fn block_on<F: Future>(future: F) -> F::Output {
+        struct NoopWaker;
+        impl Wake for NoopWaker {
+            fn wake(self: Arc<Self>) {}
+        }
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    /// Answers with the number of requests it has served; `/private` is marked
+    /// private and `/missing` is a 404
+    #[derive(Clone, Default)]
+    struct Counter {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Service<Request<()>> for Counter {
+        type Response = Response<Full<Bytes>>;
+        type Error = Infallible;
+        type Future = std::future::Ready<Result<Self::Response, Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<()>) -> Self::Future {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            let mut response = Response::new(Full::new(Bytes::from(format!("call {}", call))));
+            response
+                .headers_mut()
+                .insert("x-served-by", HeaderValue::from_static("counter"));
+            match request.uri().path() {
+                "/private" => {
+                    response.headers_mut().insert(
+                        CACHE_CONTROL,
+                        HeaderValue::from_static("max-age=60, private"),
+                    );
+                }
+                "/missing" => *response.status_mut() = StatusCode::NOT_FOUND,
+                _ => {}
+            }
+            std::future::ready(Ok(response))
+        }
+    }
+
+    fn request(method: Method, uri: &str) -> Request<()> {
+        Request::builder().method(method).uri(uri).body(()).unwrap()
+    }
+
+    fn body_of(
+        service: &mut impl Service<Request<()>, Response = Response<Full<Bytes>>, Error = BoxError>,
+        request: Request<()>,
+    ) -> (Response<Full<Bytes>>, Bytes) {
+        let response = block_on(service.call(request)).unwrap();
+        let (parts, body) = response.into_parts();
+        let body = block_on(body.collect()).unwrap().to_bytes();
+        (Response::from_parts(parts, Full::new(body.clone())), body)
+    }
+
+    fn cache(dir: &TempDir) -> Arc<DiskCache> {
+        Arc::new(
+            DiskCache::new(CacheConfig {
+                directory: dir.path().to_path_buf(),
+                ..Default::default()
+            })
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn repeated_gets_are_served_from_the_cache() {
+        let dir = TempDir::new().unwrap();
+        let counter = Counter::default();
+        let mut service = CacheLayer::new(cache(&dir)).layer(counter.clone());
+
+        let (first, body) = body_of(&mut service, request(Method::GET, "/items?page=1"));
+        assert_eq!(body, "call 0");
+        let (second, body) = body_of(&mut service, request(Method::GET, "/items?page=1"));
+        assert_eq!(body, "call 0");
+        assert_eq!(
+            second.headers()["x-served-by"],
+            first.headers()["x-served-by"]
+        );
+
+        // A different query, a POST, a 404 and a private response all reach the service
+        for (method, uri) in [
+            (Method::GET, "/items?page=2"),
+            (Method::POST, "/items?page=1"),
+            (Method::POST, "/items?page=1"),
+            (Method::GET, "/missing"),
+            (Method::GET, "/missing"),
+            (Method::GET, "/private"),
+            (Method::GET, "/private"),
+        ] {
+            body_of(&mut service, request(method, uri));
+        }
+        assert_eq!(counter.calls.load(Ordering::SeqCst), 8);
+    }
+
+    #[test]
+    fn key_extractor_and_ttl_are_configurable() {
+        let dir = TempDir::new().unwrap();
+        let counter = Counter::default();
+        // Every method shares one entry per path
+        let by_path = |request: &Request<()>| Some(request.uri().path().to_string());
+        let mut service = CacheLayer::new(cache(&dir))
+            .with_key_extractor(by_path)
+            .layer(counter.clone());
+        body_of(&mut service, request(Method::GET, "/a?x=1"));
+        let (_, body) = body_of(&mut service, request(Method::POST, "/a?x=2"));
+        assert_eq!(body, "call 0");
+
+        // Stored responses expire with the TTL
+        let mut service = CacheLayer::new(cache(&dir))
+            .with_ttl(Duration::ZERO)
+            .layer(counter.clone());
+        body_of(&mut service, request(Method::GET, "/b"));
+        let (_, body) = body_of(&mut service, request(Method::GET, "/b"));
+        assert_eq!(body, "call 2");
+    }
+}
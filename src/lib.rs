@@ -1,19 +1,57 @@
 use pyo3::prelude::*;
 use pyo3::wrap_pyfunction;
 
+mod archive;
 mod cache;
+mod cache_meta;
+mod changes;
+mod entry_lock;
 mod error;
 mod eviction;
+mod eviction_queue;
+mod fork;
+#[cfg(feature = "tower")]
+pub mod http_cache;
+mod lock_order;
 mod memory_cache;
 mod migration;
 mod pickle_cache;
+mod popularity;
+mod process_lock;
+mod registry;
 mod serialization;
+#[cfg(unix)]
+mod server;
+mod shared_stats;
+mod single_flight;
 mod storage;
+mod tag_quota;
+mod trash;
 mod utils;
 
-pub use cache::DiskCache;
-pub use error::{CacheError, CacheResult};
+pub use archive::ArchiveEntry;
+pub use cache::{CapacityEstimate, DiskCache, ValueWriter};
+pub use changes::{Change, ChangeKind, Subscription, Topic};
+pub use entry_lock::EntryLock;
+pub use error::{CacheError, CacheResult, ConfigIssue};
+pub use eviction_queue::EvictionBacklog;
 pub use migration::{detect_diskcache_format, DiskCacheMigrator, MigrationStats};
+pub use process_lock::{LockBackend, LockGranularity};
+pub use registry::ProcessInfo;
+pub use serialization::{CacheEntry, EncryptionKey, Priority, StorageMode};
+#[cfg(unix)]
+pub use server::{CacheClient, CacheServer, ServerStorage};
+pub use shared_stats::{AggregateStats, StatCounts};
+#[cfg(feature = "conformance")]
+pub use storage::conformance;
+#[cfg(feature = "s3")]
+pub use storage::S3Tier;
+pub use storage::{
+    Compression, DataFileWriter, DirectoryTier, Durability, FileNaming, Footprint, FramedReader,
+    HotCachePolicy, IndexKey, IoStats, QueueFullPolicy, RecoveryReport, RedbStorage, RemoteTier,
+    RingStorage, SqliteStorage, StorageBackend, StorageKind, TierSizes, VacuumReport,
+};
+pub use tag_quota::TagLimit;
 
 /// A Python module implemented in Rust.
 #[pymodule]
@@ -21,6 +59,11 @@ fn _diskcache_rs(m: &Bound<'_, PyModule>) -> PyResult<()> {
     // Add version from Cargo.toml
     m.add("__version__", env!("CARGO_PKG_VERSION"))?;
 
+    m.add(
+        "CacheConfigError",
+        m.py().get_type::<error::CacheConfigError>(),
+    )?;
+
     // Add the main cache class
     m.add_class::<cache::PyCache>()?;
 
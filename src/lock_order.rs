@@ -0,0 +1,215 @@
+//! Lock hierarchy for locks that can be held together
+//!
+//! Every lock that is ever held while taking another one has a [`LockLevel`],
+//! and a thread may only take locks in strictly increasing level order. Debug
+//! builds record the levels each thread holds and panic on an out-of-order
+//! acquisition before blocking on it, so a path that could deadlock fails the
+//! first test that runs it instead of hanging once in a while under load.
+//! Release builds compile the bookkeeping away.
+//!
+//! Locks left out of the hierarchy (buffer pools, the write batcher's channel,
+//! in-flight loads, per-key hit counts, the connection to the designated
+//! writer, the shard locks of the storage hot caches) are never held while
+//! taking another lock. For the hot caches that means copying an entry out of
+//! its map before consulting the index.
+//!
+//! The key locks of `OptimizedStorage` and the setup lock of a cache
+//! directory are file locks shared with other processes, so they cannot be
+//! checked here. Both are only ever taken before any lock in the hierarchy.
+
+use parking_lot::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::ops::{Deref, DerefMut};
+
+/// Position of a lock in the hierarchy, outermost first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockLevel {
+    /// `DiskCache` queue operations, held across a whole push or pull
+    Queue,
+    /// A legacy entry file being moved into the storage backend
+    LegacyMigration,
+    /// The write-ahead log, held from logging a write until it is applied
+    WriteAheadLog,
+    /// The cross-process event log, held while appending to or reading it
+    EventLog,
+    /// A segment compaction run
+    Compaction,
+    /// A storage backend's index connection or database
+    Index,
+    /// `OptimizedStorage`'s in-memory file metadata
+    ColdIndex,
+    /// Eviction policy maps from key to position
+    EvictionKeys,
+    /// Eviction policy orderings
+    EvictionOrder,
+    /// Eviction policy counters
+    EvictionCounter,
+    /// Memory cache entries
+    MemoryEntries,
+    /// Memory cache byte count
+    MemorySize,
+    /// Cache statistics and bookkeeping timestamps
+    Stats,
+}
+
+#[cfg(debug_assertions)]
+mod held {
+    use super::LockLevel;
+    use std::cell::RefCell;
+
+    thread_local! {
+        static HELD: RefCell<Vec<LockLevel>> = const { RefCell::new(Vec::new()) };
+    }
+
+    pub fn acquire(level: LockLevel) {
+        // Thread-local storage is gone during thread teardown; nothing to check then
+        let _ = HELD.try_with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(&innermost) = held.iter().max() {
+                assert!(
+                    level > innermost,
+                    "lock order violation: taking {:?} while holding {:?}",
+                    level,
+                    innermost
+                );
+            }
+            held.push(level);
+        });
+    }
+
+    pub fn release(level: LockLevel) {
+        let _ = HELD.try_with(|held| {
+            let mut held = held.borrow_mut();
+            // Guards may be dropped in any order
+            if let Some(position) = held.iter().rposition(|&held| held == level) {
+                held.remove(position);
+            }
+        });
+    }
+}
+
+#[cfg(not(debug_assertions))]
+mod held {
+    use super::LockLevel;
+
+    #[inline(always)]
+    pub fn acquire(_level: LockLevel) {}
+
+    #[inline(always)]
+    pub fn release(_level: LockLevel) {}
+}
+
+/// A guard of a lock in the hierarchy; releases its level when dropped
+pub struct Ordered<G> {
+    guard: G,
+    level: LockLevel,
+}
+
+impl<G: Deref> Deref for Ordered<G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<G: DerefMut> DerefMut for Ordered<G> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl<G> Drop for Ordered<G> {
+    fn drop(&mut self) {
+        held::release(self.level);
+    }
+}
+
+pub type OrderedMutexGuard<'a, T> = Ordered<MutexGuard<'a, T>>;
+
+/// A [`Mutex`] at a fixed level of the hierarchy
+pub struct OrderedMutex<T> {
+    level: LockLevel,
+    inner: Mutex<T>,
+}
+
+impl<T> OrderedMutex<T> {
+    pub const fn new(level: LockLevel, value: T) -> Self {
+        Self {
+            level,
+            inner: Mutex::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> OrderedMutexGuard<'_, T> {
+        held::acquire(self.level);
+        Ordered {
+            guard: self.inner.lock(),
+            level: self.level,
+        }
+    }
+}
+
+/// A [`RwLock`] at a fixed level of the hierarchy
+///
+/// Readers are checked like writers: a second read of the same lock on one
+/// thread can deadlock behind a queued writer.
+pub struct OrderedRwLock<T> {
+    level: LockLevel,
+    inner: RwLock<T>,
+}
+
+impl<T> OrderedRwLock<T> {
+    pub const fn new(level: LockLevel, value: T) -> Self {
+        Self {
+            level,
+            inner: RwLock::new(value),
+        }
+    }
+
+    pub fn read(&self) -> Ordered<RwLockReadGuard<'_, T>> {
+        held::acquire(self.level);
+        Ordered {
+            guard: self.inner.read(),
+            level: self.level,
+        }
+    }
+
+    pub fn write(&self) -> Ordered<RwLockWriteGuard<'_, T>> {
+        held::acquire(self.level);
+        Ordered {
+            guard: self.inner.write(),
+            level: self.level,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locks_taken_in_order_and_released_in_any_order() {
+        let outer = OrderedMutex::new(LockLevel::Index, 1);
+        let inner = OrderedRwLock::new(LockLevel::Stats, 2);
+
+        let outer_guard = outer.lock();
+        let inner_guard = inner.write();
+        drop(outer_guard);
+        drop(inner_guard);
+
+        // Both levels were released, so the outer lock can be taken again first
+        let _outer = outer.lock();
+        assert_eq!(*inner.read(), 2);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "lock order violation: taking Index while holding Stats")]
+    fn out_of_order_acquisition_panics() {
+        let outer = OrderedMutex::new(LockLevel::Index, ());
+        let inner = OrderedRwLock::new(LockLevel::Stats, ());
+
+        let _inner = inner.read();
+        let _outer = outer.lock();
+    }
+}
@@ -1,14 +1,14 @@
+use crate::lock_order::{LockLevel, OrderedRwLock};
 use crate::serialization::CacheEntry;
 use lru::LruCache;
-use parking_lot::RwLock;
 use std::num::NonZeroUsize;
 use std::sync::Arc;
 
 /// In-memory cache layer for frequently accessed items
 pub struct MemoryCache {
-    cache: Arc<RwLock<LruCache<String, CacheEntry>>>,
+    cache: Arc<OrderedRwLock<LruCache<String, CacheEntry>>>,
     max_memory_size: u64,
-    current_memory_size: Arc<RwLock<u64>>,
+    current_memory_size: Arc<OrderedRwLock<u64>>,
 }
 
 impl MemoryCache {
@@ -18,9 +18,12 @@ impl MemoryCache {
         let capacity = NonZeroUsize::new(max_entries).unwrap_or(NonZeroUsize::new(1000).unwrap());
 
         Self {
-            cache: Arc::new(RwLock::new(LruCache::new(capacity))),
+            cache: Arc::new(OrderedRwLock::new(
+                LockLevel::MemoryEntries,
+                LruCache::new(capacity),
+            )),
             max_memory_size,
-            current_memory_size: Arc::new(RwLock::new(0)),
+            current_memory_size: Arc::new(OrderedRwLock::new(LockLevel::MemorySize, 0)),
         }
     }
 
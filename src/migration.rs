@@ -1,10 +1,28 @@
 use crate::error::{CacheError, CacheResult};
-use crate::serialization::CacheEntry;
-use crate::storage::StorageBackend;
+use crate::fork::ForkCheck;
+use crate::lock_order::{LockLevel, OrderedMutex};
+use crate::serialization::{CacheEntry, StorageMode};
+use crate::storage::events::EVENT_LOG_FILE;
+use crate::storage::key_locks::LOCK_DIR;
+use crate::storage::optimized_backend::INDEX_FILE;
+use crate::storage::redb_backend::REDB_FILE_NAME;
+use crate::storage::ring_backend::RING_FILE_NAME;
+use crate::storage::segment::SEGMENT_DIR;
+use crate::storage::sqlite_backend::DISKCACHE_DB_NAME;
+use crate::storage::wal::WAL_FILE_NAME;
+use crate::storage::{StorageBackend, StorageKind};
+use crate::utils::current_timestamp;
+use parking_lot::Mutex;
 use rusqlite::{Connection, Row};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Extension of the files the old `FileStorage` backend kept one entry in,
+/// each named after the BLAKE3 hash of its key
+const LEGACY_ENTRY_EXTENSION: &str = "cache";
+
 /// Migrates data from python-diskcache format to diskcache_rs format
 pub struct DiskCacheMigrator {
     source_dir: PathBuf,
@@ -271,3 +289,315 @@ pub fn auto_migrate_if_needed(
         Ok(None)
     }
 }
+
+/// Directories the old `FileStorage` backend put its entry files in
+fn legacy_directories(cache_dir: &Path) -> [PathBuf; 2] {
+    [cache_dir.to_path_buf(), cache_dir.join("data")]
+}
+
+/// Name of the legacy entry file for `key`
+fn legacy_file_name(key: &str) -> String {
+    format!(
+        "{}.{}",
+        blake3::hash(key.as_bytes()).to_hex(),
+        LEGACY_ENTRY_EXTENSION
+    )
+}
+
+/// Legacy entry files in a cache directory: `<blake3>.cache` files in the
+/// directory itself or directly under `data/`
+pub fn find_legacy_entry_files(cache_dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for directory in legacy_directories(cache_dir) {
+        let Ok(entries) = std::fs::read_dir(&directory) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_legacy = path
+                .extension()
+                .is_some_and(|ext| ext == LEGACY_ENTRY_EXTENSION)
+                && path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .is_some_and(|stem| {
+                        stem.len() == 64 && stem.bytes().all(|b| b.is_ascii_hexdigit())
+                    });
+            if is_legacy && path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    files
+}
+
+/// Decode a legacy entry file, a bincode-encoded [`CacheEntry`], along with
+/// the separate file its value lived in, if any
+///
+/// That value is read back inline. Returns `None` when the file does not
+/// belong to the key it holds, as a file that merely looks like a legacy
+/// entry would not.
+pub fn read_legacy_entry(
+    path: &Path,
+    bytes: &[u8],
+) -> CacheResult<Option<(CacheEntry, Option<PathBuf>)>> {
+    let (mut entry, _): (CacheEntry, _) =
+        bincode::serde::decode_from_slice(bytes, bincode::config::legacy())
+            .map_err(|e| CacheError::Deserialization(format!("{:?}: {}", path, e)))?;
+    if path.file_name() != Some(legacy_file_name(&entry.key).as_ref()) {
+        return Ok(None);
+    }
+    let data_path = entry
+        .get_filename()
+        .map(|filename| path.with_file_name(filename));
+    if let Some(data_path) = &data_path {
+        entry.storage = StorageMode::Inline(std::fs::read(data_path)?);
+    }
+    Ok(Some((entry, data_path)))
+}
+
+/// Moves the entries left by the old per-file `FileStorage` backend into the
+/// configured storage backend, in a background thread
+///
+/// Each entry keeps its key, tags and expiry; expired ones are dropped. Until
+/// the pass finishes, [`LegacyMigration::settle`] moves a single key ahead of
+/// it, so callers can settle a key before touching it and never see it
+/// missing, nor have a newer write overwritten by the older legacy value.
+pub struct LegacyMigration {
+    directory: PathBuf,
+    storage: Arc<dyn StorageBackend>,
+    /// Held while an entry moves, so each legacy file is moved at most once
+    moving: OrderedMutex<()>,
+    finished: AtomicBool,
+    stop: AtomicBool,
+    worker: Mutex<Option<std::thread::JoinHandle<()>>>,
+    fork: ForkCheck,
+    migrated: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl LegacyMigration {
+    /// Start moving the legacy entry files in `directory`, or return `None`
+    /// when there are none
+    pub fn start(directory: &Path, storage: Arc<dyn StorageBackend>) -> Option<Arc<Self>> {
+        let files = find_legacy_entry_files(directory);
+        if files.is_empty() {
+            return None;
+        }
+        tracing::info!(
+            "Migrating {} legacy entry files in {:?}",
+            files.len(),
+            directory
+        );
+
+        let migration = Arc::new(Self {
+            directory: directory.to_path_buf(),
+            storage,
+            moving: OrderedMutex::new(LockLevel::LegacyMigration, ()),
+            finished: AtomicBool::new(false),
+            stop: AtomicBool::new(false),
+            worker: Mutex::new(None),
+            fork: ForkCheck::new(),
+            migrated: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        });
+        let worker = migration.clone();
+        let handle = std::thread::spawn(move || worker.run(files));
+        *migration.worker.lock() = Some(handle);
+        Some(migration)
+    }
+
+    fn run(&self, files: Vec<PathBuf>) {
+        for path in files {
+            if self.stop.load(Ordering::Acquire) {
+                return;
+            }
+            if let Err(e) = self.move_file(&path) {
+                tracing::warn!("Failed to migrate legacy entry {:?}: {}", path, e);
+                self.failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.finished.store(true, Ordering::Release);
+        tracing::info!(
+            "Legacy migration finished: {} entries moved, {} left in place after errors",
+            self.migrated(),
+            self.failed.load(Ordering::Relaxed)
+        );
+    }
+
+    /// Move one legacy file into storage and remove it
+    ///
+    /// A key the storage already holds was written after the legacy entry,
+    /// so the legacy value is dropped rather than moved over it.
+    fn move_file(&self, path: &Path) -> CacheResult<()> {
+        let _moving = self.moving.lock();
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            // Settled already
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(CacheError::Io(e)),
+        };
+        let Some((entry, data_path)) = read_legacy_entry(path, &bytes)? else {
+            return Ok(());
+        };
+
+        let expired = entry
+            .expire_time
+            .is_some_and(|expire| expire <= current_timestamp());
+        if !expired && !self.storage.exists(&entry.key)? {
+            self.storage.set(&entry.key.clone(), entry)?;
+            self.migrated.fetch_add(1, Ordering::Relaxed);
+        }
+
+        std::fs::remove_file(path)?;
+        if let Some(data_path) = data_path {
+            let _ = std::fs::remove_file(data_path);
+        }
+        Ok(())
+    }
+
+    /// Move `key`'s legacy entry now if it has not been moved yet
+    pub fn settle(&self, key: &str) -> CacheResult<()> {
+        if self.finished.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        let name = legacy_file_name(key);
+        for directory in legacy_directories(&self.directory) {
+            self.move_file(&directory.join(&name))?;
+        }
+        Ok(())
+    }
+
+    /// Entries moved into storage so far
+    pub fn migrated(&self) -> u64 {
+        self.migrated.load(Ordering::Relaxed)
+    }
+
+    /// Wait for the background pass to finish
+    ///
+    /// In a forked child the pass is left to the parent, whose thread it
+    /// runs on; the child still settles the keys it reads.
+    pub fn wait(&self) {
+        let worker = self.worker.lock().take();
+        if self.fork.forked() {
+            std::mem::forget(worker);
+            return;
+        }
+        if let Some(worker) = worker {
+            let _ = worker.join();
+        }
+    }
+
+    /// Stop the background pass and wait for it; files it did not reach are
+    /// moved the next time the cache is opened
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Release);
+        self.wait();
+    }
+}
+
+/// Directory under the cache directory a backend migration builds the new
+/// backend in
+pub const BACKEND_STAGING_DIR: &str = ".migrating";
+/// File in [`BACKEND_STAGING_DIR`] written once every entry is copied; it
+/// names the backends switched from and to
+const STAGED_MARKER: &str = ".complete";
+
+/// Names under the cache directory that belong to a backend
+fn backend_files(kind: StorageKind) -> &'static [&'static str] {
+    match kind {
+        StorageKind::Optimized => &[
+            INDEX_FILE,
+            "index.sqlite3-wal",
+            "index.sqlite3-shm",
+            "data",
+            SEGMENT_DIR,
+            WAL_FILE_NAME,
+            EVENT_LOG_FILE,
+        ],
+        StorageKind::Sqlite => &[DISKCACHE_DB_NAME, "cache.db-wal", "cache.db-shm"],
+        StorageKind::Redb => &[REDB_FILE_NAME, "data"],
+        StorageKind::Ring => &[RING_FILE_NAME, "data"],
+        StorageKind::Memory | StorageKind::Server => &[],
+    }
+}
+
+/// Mark the backend staged in [`BACKEND_STAGING_DIR`] as complete, so the
+/// switch to it is finished even if this process dies part way
+pub fn mark_backend_staged(
+    directory: &Path,
+    from: StorageKind,
+    to: StorageKind,
+) -> CacheResult<()> {
+    let staging = directory.join(BACKEND_STAGING_DIR);
+    let marker = staging.join(STAGED_MARKER);
+    std::fs::write(&marker, format!("{} {}", from.name(), to.name()))?;
+    std::fs::File::open(&marker)?.sync_all()?;
+    Ok(())
+}
+
+/// Replace the backend in `directory` with the one staged by a migration
+///
+/// A staging directory without its completion marker is left from a copy
+/// that never finished, and is dropped; the old backend is still whole. With
+/// the marker, the old backend's files are removed and the staged ones moved
+/// into place. Names both backends use that are no longer staged were moved
+/// already, so running this again after an interruption finishes the switch.
+/// Returns the backend switched to, if any.
+pub fn finish_backend_switch(directory: &Path) -> CacheResult<Option<StorageKind>> {
+    let staging = directory.join(BACKEND_STAGING_DIR);
+    if !staging.is_dir() {
+        return Ok(None);
+    }
+    let marker = match std::fs::read_to_string(staging.join(STAGED_MARKER)) {
+        Ok(marker) => marker,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            tracing::warn!("Dropping unfinished backend migration in {:?}", staging);
+            std::fs::remove_dir_all(&staging)?;
+            return Ok(None);
+        }
+        Err(e) => return Err(CacheError::Io(e)),
+    };
+    let (from, to) = match marker.split_once(' ') {
+        Some((from, to)) => (from.parse::<StorageKind>()?, to.parse::<StorageKind>()?),
+        None => {
+            return Err(CacheError::Corruption(format!(
+                "Backend migration marker in {:?} names no backends",
+                staging
+            )))
+        }
+    };
+
+    let staged = backend_files(to);
+    for name in backend_files(from) {
+        if staged.contains(name) && !staging.join(name).exists() {
+            continue;
+        }
+        let path = directory.join(name);
+        let removed = if path.is_dir() {
+            std::fs::remove_dir_all(&path)
+        } else {
+            std::fs::remove_file(&path)
+        };
+        match removed {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(CacheError::Io(e)),
+        }
+    }
+    for entry in std::fs::read_dir(&staging)? {
+        let entry = entry?;
+        // Lock files stay with the directory they lock, which has its own
+        if entry.file_name() != STAGED_MARKER && entry.file_name() != LOCK_DIR {
+            std::fs::rename(entry.path(), directory.join(entry.file_name()))?;
+        }
+    }
+    std::fs::remove_dir_all(&staging)?;
+    tracing::info!(
+        "Switched the cache in {:?} from the {} to the {} backend",
+        directory,
+        from.name(),
+        to.name()
+    );
+    Ok(Some(to))
+}
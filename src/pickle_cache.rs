@@ -1,10 +1,142 @@
+use crate::error::{CacheError, CacheResult};
 use chrono::{DateTime, Duration, Utc};
 use pyo3::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::fs;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
 use std::path::PathBuf;
 
+/// Index snapshot, rewritten whole at each checkpoint
+const INDEX_FILE: &str = "index.bin";
+/// Index changes since the last checkpoint, appended as they happen
+const JOURNAL_FILE: &str = "index.journal";
+/// Journal records written before the snapshot is brought up to date
+const CHECKPOINT_RECORDS: usize = 1024;
+/// Payload length and the first four bytes of its blake3 hash
+const JOURNAL_FRAME_HEADER_LEN: usize = 4 + 4;
+/// JSON index written by earlier versions; read once and replaced
+const LEGACY_INDEX_FILE: &str = "index.json";
+const INDEX_MAGIC: &[u8; 8] = b"DCRSPKIX";
+const INDEX_VERSION: u32 = 1;
+/// Magic, format version, payload length and the payload's blake3 hash
+const INDEX_HEADER_LEN: usize = 8 + 4 + 8 + 32;
+
+type PickleIndex = HashMap<String, PickleCacheEntry>;
+
+/// Encode the index as a checksummed MessagePack payload behind a fixed header
+fn encode_index(index: &PickleIndex) -> CacheResult<Vec<u8>> {
+    let payload =
+        rmp_serde::to_vec_named(index).map_err(|e| CacheError::Serialization(e.to_string()))?;
+    let mut encoded = Vec::with_capacity(INDEX_HEADER_LEN + payload.len());
+    encoded.extend_from_slice(INDEX_MAGIC);
+    encoded.extend_from_slice(&INDEX_VERSION.to_le_bytes());
+    encoded.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    encoded.extend_from_slice(blake3::hash(&payload).as_bytes());
+    encoded.extend_from_slice(&payload);
+    Ok(encoded)
+}
+
+fn decode_index(encoded: &[u8]) -> CacheResult<PickleIndex> {
+    if encoded.len() < INDEX_HEADER_LEN || &encoded[..8] != INDEX_MAGIC {
+        return Err(CacheError::Corruption(
+            "Pickle cache index has no valid header".to_string(),
+        ));
+    }
+    let version = u32::from_le_bytes(encoded[8..12].try_into().unwrap());
+    if version != INDEX_VERSION {
+        return Err(CacheError::Corruption(format!(
+            "Unsupported pickle cache index version {}",
+            version
+        )));
+    }
+    let len = u64::from_le_bytes(encoded[12..20].try_into().unwrap());
+    let payload = &encoded[INDEX_HEADER_LEN..];
+    if payload.len() as u64 != len {
+        return Err(CacheError::Corruption(format!(
+            "Pickle cache index is truncated: expected {} payload bytes, found {}",
+            len,
+            payload.len()
+        )));
+    }
+    if blake3::hash(payload).as_bytes() != &encoded[20..INDEX_HEADER_LEN] {
+        return Err(CacheError::Corruption(
+            "Pickle cache index checksum mismatch".to_string(),
+        ));
+    }
+    rmp_serde::from_slice(payload).map_err(|e| CacheError::Deserialization(e.to_string()))
+}
+
+/// One change to the index, as logged in the journal
+#[derive(Debug, Serialize, Deserialize)]
+enum JournalRecord {
+    Put {
+        key: String,
+        entry: PickleCacheEntry,
+    },
+    Touch {
+        key: String,
+        accessed_at: DateTime<Utc>,
+    },
+    Delete {
+        key: String,
+    },
+    Clear,
+}
+
+impl JournalRecord {
+    /// Replaying the journal over a snapshot that already has some of its
+    /// records leaves the same index, so a crash between writing the snapshot
+    /// and emptying the journal loses nothing
+    fn apply(self, index: &mut PickleIndex) {
+        match self {
+            JournalRecord::Put { key, entry } => {
+                index.insert(key, entry);
+            }
+            JournalRecord::Touch { key, accessed_at } => {
+                if let Some(entry) = index.get_mut(&key) {
+                    entry.accessed_at = accessed_at;
+                }
+            }
+            JournalRecord::Delete { key } => {
+                index.remove(&key);
+            }
+            JournalRecord::Clear => index.clear(),
+        }
+    }
+
+    fn encode_frame(&self) -> CacheResult<Vec<u8>> {
+        let payload =
+            rmp_serde::to_vec_named(self).map_err(|e| CacheError::Serialization(e.to_string()))?;
+        let mut frame = Vec::with_capacity(JOURNAL_FRAME_HEADER_LEN + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&blake3::hash(&payload).as_bytes()[..4]);
+        frame.extend_from_slice(&payload);
+        Ok(frame)
+    }
+}
+
+/// Records in a journal, up to the first torn or damaged frame
+fn decode_journal(mut journal: &[u8]) -> Vec<JournalRecord> {
+    let mut records = Vec::new();
+    while journal.len() >= JOURNAL_FRAME_HEADER_LEN {
+        let len = u32::from_le_bytes(journal[..4].try_into().unwrap()) as usize;
+        let Some(payload) = journal.get(JOURNAL_FRAME_HEADER_LEN..JOURNAL_FRAME_HEADER_LEN + len)
+        else {
+            break;
+        };
+        if blake3::hash(payload).as_bytes()[..4] != journal[4..JOURNAL_FRAME_HEADER_LEN] {
+            break;
+        }
+        match rmp_serde::from_slice(payload) {
+            Ok(record) => records.push(record),
+            Err(_) => break,
+        }
+        journal = &journal[JOURNAL_FRAME_HEADER_LEN + len..];
+    }
+    records
+}
+
 /// Entry in the pickle cache with expiration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PickleCacheEntry {
@@ -18,6 +150,10 @@ pub struct PickleCacheEntry {
     pub accessed_at: DateTime<Utc>,
     /// Size in bytes
     pub size: usize,
+    /// Application schema version the value was pickled under; 0 for
+    /// entries written without one
+    #[serde(default)]
+    pub schema_version: u32,
 }
 
 impl PickleCacheEntry {
@@ -32,6 +168,7 @@ impl PickleCacheEntry {
             created_at: now,
             accessed_at: now,
             size,
+            schema_version: 0,
         }
     }
 
@@ -61,6 +198,10 @@ pub struct PickleCache {
     current_size: usize,
     /// Default TTL for entries
     default_ttl: Option<Duration>,
+    /// Append handle on the journal
+    journal: Option<File>,
+    /// Records in the journal since the last checkpoint
+    journal_records: usize,
 }
 
 #[pymethods]
@@ -92,6 +233,8 @@ impl PickleCache {
             max_size,
             current_size: 0,
             default_ttl,
+            journal: None,
+            journal_records: 0,
         };
 
         // Load existing cache index
@@ -101,12 +244,13 @@ impl PickleCache {
     }
 
     /// Set a pickled object in the cache
-    #[pyo3(signature = (key, pickled_data, ttl_seconds = None))]
+    #[pyo3(signature = (key, pickled_data, ttl_seconds = None, schema_version = 0))]
     pub fn set_pickle(
         &mut self,
         key: &str,
         pickled_data: Py<PyAny>,
         ttl_seconds: Option<i64>,
+        schema_version: u32,
     ) -> PyResult<()> {
         let ttl = ttl_seconds.map(Duration::seconds).or(self.default_ttl);
 
@@ -116,7 +260,8 @@ impl PickleCache {
             Ok::<Vec<u8>, PyErr>(bytes)
         })?;
 
-        let entry = PickleCacheEntry::new(data_bytes, ttl);
+        let mut entry = PickleCacheEntry::new(data_bytes, ttl);
+        entry.schema_version = schema_version;
 
         // Write to disk
         let file_path = self.get_file_path(key);
@@ -132,18 +277,24 @@ impl PickleCache {
             self.current_size = self.current_size.saturating_sub(old_entry.size);
         }
         self.current_size += entry.size;
+        self.log(JournalRecord::Put {
+            key: key.to_string(),
+            entry,
+        })?;
 
         // Check size limits and evict if necessary
         self.evict_if_needed()?;
 
-        // Save index
-        self.save_index()?;
-
         Ok(())
     }
 
     /// Get a pickled object from the cache
     pub fn get_pickle(&mut self, key: &str) -> PyResult<Option<Vec<u8>>> {
+        Ok(self.get_pickle_versioned(key)?.map(|(data, _)| data))
+    }
+
+    /// Get a pickled object from the cache with the schema version it was stored under
+    pub fn get_pickle_versioned(&mut self, key: &str) -> PyResult<Option<(Vec<u8>, u32)>> {
         if let Some(entry) = self.index.get_mut(key) {
             // Check if expired
             if entry.is_expired() {
@@ -153,18 +304,27 @@ impl PickleCache {
 
             // Update access time
             entry.touch();
+            let accessed_at = entry.accessed_at;
+            let schema_version = entry.schema_version;
 
             // Read from disk
             let file_path = self.get_file_path(key);
             match fs::read(&file_path) {
                 Ok(data) => {
-                    self.save_index()?; // Save updated access time
-                    Ok(Some(data))
+                    self.log(JournalRecord::Touch {
+                        key: key.to_string(),
+                        accessed_at,
+                    })?;
+                    Ok(Some((data, schema_version)))
                 }
                 Err(_) => {
                     // File doesn't exist, remove from index
-                    self.index.remove(key);
-                    self.save_index()?;
+                    if let Some(entry) = self.index.remove(key) {
+                        self.current_size = self.current_size.saturating_sub(entry.size);
+                    }
+                    self.log(JournalRecord::Delete {
+                        key: key.to_string(),
+                    })?;
                     Ok(None)
                 }
             }
@@ -173,6 +333,51 @@ impl PickleCache {
         }
     }
 
+    /// Replace the value of an existing key after a schema upgrade, keeping
+    /// its expiration
+    pub fn upgrade_pickle(
+        &mut self,
+        key: &str,
+        pickled_data: Vec<u8>,
+        schema_version: u32,
+    ) -> PyResult<bool> {
+        let Some(entry) = self.index.get(key) else {
+            return Ok(false);
+        };
+        let mut entry = PickleCacheEntry {
+            size: pickled_data.len(),
+            data: pickled_data,
+            schema_version,
+            ..entry.clone()
+        };
+        entry.touch();
+
+        fs::write(self.get_file_path(key), &entry.data).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
+                "Failed to write cache file: {}",
+                e
+            ))
+        })?;
+        if let Some(old_entry) = self.index.insert(key.to_string(), entry.clone()) {
+            self.current_size = self.current_size.saturating_sub(old_entry.size);
+        }
+        self.current_size += entry.size;
+        self.log(JournalRecord::Put {
+            key: key.to_string(),
+            entry,
+        })?;
+        self.evict_if_needed()?;
+        Ok(true)
+    }
+
+    /// Schema version a key's value was stored under
+    pub fn schema_version_pickle(&self, key: &str) -> Option<u32> {
+        self.index
+            .get(key)
+            .filter(|entry| !entry.is_expired())
+            .map(|entry| entry.schema_version)
+    }
+
     /// Delete a pickled object from the cache
     pub fn delete_pickle(&mut self, key: &str) -> PyResult<bool> {
         if let Some(entry) = self.index.remove(key) {
@@ -182,7 +387,9 @@ impl PickleCache {
             let file_path = self.get_file_path(key);
             let _ = fs::remove_file(&file_path); // Ignore errors if file doesn't exist
 
-            self.save_index()?;
+            self.log(JournalRecord::Delete {
+                key: key.to_string(),
+            })?;
             Ok(true)
         } else {
             Ok(false)
@@ -234,7 +441,7 @@ impl PickleCache {
 
         self.index.clear();
         self.current_size = 0;
-        self.save_index()?;
+        self.log(JournalRecord::Clear)?;
 
         Ok(())
     }
@@ -263,7 +470,11 @@ impl PickleCache {
     pub fn expire_pickle(&mut self, key: &str, ttl_seconds: i64) -> PyResult<bool> {
         if let Some(entry) = self.index.get_mut(key) {
             entry.expires_at = Some(Utc::now() + Duration::seconds(ttl_seconds));
-            self.save_index()?;
+            let entry = entry.clone();
+            self.log(JournalRecord::Put {
+                key: key.to_string(),
+                entry,
+            })?;
             Ok(true)
         } else {
             Ok(false)
@@ -300,39 +511,118 @@ impl PickleCache {
     }
 
     fn load_index(&mut self) -> PyResult<()> {
-        let index_path = self.directory.join("index.json");
+        let index_path = self.directory.join(INDEX_FILE);
+        let legacy_path = self.directory.join(LEGACY_INDEX_FILE);
+
         if index_path.exists() {
-            match fs::read_to_string(&index_path) {
-                Ok(content) => {
-                    if let Ok(index) =
-                        serde_json::from_str::<HashMap<String, PickleCacheEntry>>(&content)
-                    {
-                        self.current_size = index.values().map(|e| e.size).sum();
-                        self.index = index;
-                    }
-                }
-                Err(_) => {
+            match fs::read(&index_path)
+                .map_err(CacheError::Io)
+                .and_then(|encoded| decode_index(&encoded))
+            {
+                Ok(index) => self.index = index,
+                Err(e) => {
                     // If index is corrupted, start fresh
+                    tracing::warn!("Discarding pickle cache index {:?}: {}", index_path, e);
                     self.index.clear();
-                    self.current_size = 0;
                 }
             }
+        } else if legacy_path.exists() {
+            let legacy = fs::read_to_string(&legacy_path)
+                .ok()
+                .and_then(|content| serde_json::from_str::<PickleIndex>(&content).ok());
+            if let Some(index) = legacy {
+                self.index = index;
+                self.save_index()?;
+            }
+            // Only the binary index is kept up to date from here on
+            let _ = fs::remove_file(&legacy_path);
+        }
+
+        // Changes made since the last checkpoint
+        let journal_path = self.directory.join(JOURNAL_FILE);
+        let replayed = match fs::read(&journal_path) {
+            Ok(journal) => {
+                let records = decode_journal(&journal);
+                let replayed = !journal.is_empty();
+                for record in records {
+                    record.apply(&mut self.index);
+                }
+                replayed
+            }
+            Err(_) => false,
+        };
+        self.current_size = self.index.values().map(|e| e.size).sum();
+
+        let journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&journal_path)
+            .map_err(|e| {
+                PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
+                    "Failed to open index journal: {}",
+                    e
+                ))
+            })?;
+        self.journal = Some(journal);
+        if replayed {
+            // Also drops a torn record left at the end by a crash
+            self.checkpoint()?;
         }
         Ok(())
     }
 
-    fn save_index(&self) -> PyResult<()> {
-        let index_path = self.directory.join("index.json");
-        let content = serde_json::to_string(&self.index).map_err(|e| {
-            PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!(
-                "Failed to serialize index: {}",
+    /// Append a change to the journal, checkpointing once enough have piled up
+    fn log(&mut self, record: JournalRecord) -> PyResult<()> {
+        let frame = record.encode_frame()?;
+        let journal = self.journal.as_mut().ok_or_else(|| {
+            PyErr::new::<pyo3::exceptions::PyIOError, _>("Index journal is not open")
+        })?;
+        // One write per record, so a crash tears at most the last one
+        journal.write_all(&frame).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
+                "Failed to append to index journal: {}",
                 e
             ))
         })?;
+        self.journal_records += 1;
+        if self.journal_records >= CHECKPOINT_RECORDS {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
 
-        fs::write(&index_path, content).map_err(|e| {
+    /// Write the whole index as a new snapshot, then empty the journal
+    fn checkpoint(&mut self) -> PyResult<()> {
+        self.save_index()?;
+        if let Some(journal) = &self.journal {
+            journal.set_len(0).map_err(|e| {
+                PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
+                    "Failed to reset index journal: {}",
+                    e
+                ))
+            })?;
+        }
+        self.journal_records = 0;
+        Ok(())
+    }
+
+    /// Write the index to a temporary file and rename it over the old one,
+    /// so readers and crashes only ever see a complete index
+    fn save_index(&self) -> PyResult<()> {
+        let encoded = encode_index(&self.index)?;
+
+        let mut file = tempfile::NamedTempFile::new_in(&self.directory).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyIOError, _>(format!("Failed to save index: {}", e))
+        })?;
+        file.write_all(&encoded).map_err(|e| {
             PyErr::new::<pyo3::exceptions::PyIOError, _>(format!("Failed to save index: {}", e))
         })?;
+        file.persist(self.directory.join(INDEX_FILE)).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
+                "Failed to save index: {}",
+                e.error
+            ))
+        })?;
 
         Ok(())
     }
@@ -379,3 +669,74 @@ pub fn rust_pickle_loads(py: Python, data: Py<PyAny>) -> PyResult<Py<PyAny>> {
     let result = loads_func.call1((data,))?;
     Ok(result.into())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_round_trips_and_rejects_damage() {
+        let mut index = PickleIndex::new();
+        let mut entry = PickleCacheEntry::new(vec![1, 2, 3], Some(Duration::seconds(60)));
+        entry.schema_version = 3;
+        index.insert("key".to_string(), entry);
+        let encoded = encode_index(&index).unwrap();
+
+        let decoded = decode_index(&encoded).unwrap();
+        assert_eq!(decoded["key"].data, vec![1, 2, 3]);
+        assert_eq!(decoded["key"].expires_at, index["key"].expires_at);
+        assert_eq!(decoded["key"].schema_version, 3);
+
+        let mut flipped = encoded.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            decode_index(&flipped),
+            Err(CacheError::Corruption(_))
+        ));
+        assert!(matches!(
+            decode_index(&encoded[..encoded.len() - 1]),
+            Err(CacheError::Corruption(_))
+        ));
+    }
+
+    #[test]
+    fn journal_replay_stops_at_a_torn_record() {
+        let entry = PickleCacheEntry::new(vec![7], None);
+        let accessed_at = Utc::now() + Duration::seconds(5);
+        let mut journal = Vec::new();
+        for record in [
+            JournalRecord::Put {
+                key: "a".to_string(),
+                entry: entry.clone(),
+            },
+            JournalRecord::Put {
+                key: "b".to_string(),
+                entry,
+            },
+            JournalRecord::Touch {
+                key: "a".to_string(),
+                accessed_at,
+            },
+            JournalRecord::Delete {
+                key: "b".to_string(),
+            },
+        ] {
+            journal.extend(record.encode_frame().unwrap());
+        }
+        let complete = journal.len();
+        journal.extend(JournalRecord::Clear.encode_frame().unwrap());
+        journal.truncate(complete + 3);
+
+        let mut index = PickleIndex::new();
+        for record in decode_journal(&journal) {
+            record.apply(&mut index);
+        }
+        assert_eq!(index.len(), 1);
+        assert_eq!(index["a"].accessed_at, accessed_at);
+
+        // A damaged frame ends replay even with intact records after it
+        journal.truncate(complete);
+        journal[JOURNAL_FRAME_HEADER_LEN] ^= 1;
+        assert!(decode_journal(&journal).is_empty());
+    }
+}
@@ -0,0 +1,208 @@
+//! Approximate per-key hit counts
+//!
+//! Every hit bumps the key in a count-min sketch: a few rows of counters, each
+//! indexed by a different hash of the key, whose smallest counter for a key
+//! never undercounts it and overcounts it only by collisions. The sketch takes
+//! a fixed amount of memory however many keys pass through it, but it cannot
+//! list keys, so the keys with the highest estimates are kept beside it in a
+//! small table. Once the sketch has counted `SAMPLE_FACTOR` hits per counter
+//! all counts are halved, so popularity reflects recent traffic rather than
+//! everything since the cache opened.
+//!
+//! The sketch on its own, [`FrequencySketch`], also estimates how often keys
+//! were accessed for W-TinyLFU eviction.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+/// Rows of counters, each indexed by its own hash of the key
+const DEPTH: usize = 4;
+/// Counters per row of [`KeyPopularity`]'s sketch
+const WIDTH: usize = 4096;
+/// Keys whose counts are kept for [`KeyPopularity::top`]
+const TRACKED_KEYS: usize = 256;
+/// Occurrences per counter after which every count is halved
+const SAMPLE_FACTOR: u64 = 10;
+
+/// Count-min sketch of how often keys occurred recently
+///
+/// Counts are halved every `sample_size` occurrences, which keeps them far
+/// from the counters' limit.
+pub struct FrequencySketch {
+    counters: Vec<u16>,
+    /// Counters per row
+    width: usize,
+    /// Occurrences counted between halvings
+    sample_size: u64,
+    /// Occurrences counted since the last halving
+    sampled: u64,
+}
+
+impl FrequencySketch {
+    /// A sketch with `width` counters in each of its rows, halving its
+    /// counts every `sample_size` occurrences
+    pub fn new(width: usize, sample_size: u64) -> Self {
+        let width = width.max(1);
+        Self {
+            counters: vec![0; DEPTH * width],
+            width,
+            sample_size,
+            sampled: 0,
+        }
+    }
+
+    /// Count an occurrence of `key` and return its new estimate
+    pub fn increment(&mut self, key: &str) -> u64 {
+        let mut estimate = u16::MAX;
+        for index in self.indexes(key) {
+            let counter = &mut self.counters[index];
+            *counter = counter.saturating_add(1);
+            estimate = estimate.min(*counter);
+        }
+        self.sampled += 1;
+        estimate as u64
+    }
+
+    /// How often `key` occurred, never less than it did
+    pub fn estimate(&self, key: &str) -> u64 {
+        self.indexes(key)
+            .map(|index| self.counters[index])
+            .min()
+            .unwrap_or(0) as u64
+    }
+
+    /// Halve every count once `sample_size` occurrences were counted since
+    /// the last time, returning whether it did
+    pub fn halve_if_due(&mut self) -> bool {
+        if self.sampled < self.sample_size {
+            return false;
+        }
+        for counter in &mut self.counters {
+            *counter /= 2;
+        }
+        self.sampled = 0;
+        true
+    }
+
+    /// Forget every count
+    pub fn clear(&mut self) {
+        self.counters.fill(0);
+        self.sampled = 0;
+    }
+
+    /// Positions of `key`'s counter in each row
+    fn indexes(&self, key: &str) -> impl Iterator<Item = usize> {
+        let (h1, h2) = hashes(key);
+        let width = self.width;
+        (0..DEPTH).map(move |row| {
+            row * width + h1.wrapping_add((row as u64).wrapping_mul(h2)) as usize % width
+        })
+    }
+}
+
+pub struct KeyPopularity {
+    sketch: FrequencySketch,
+    /// Keys with the highest estimates and those estimates
+    tracked: HashMap<String, u64>,
+    /// Lowest estimate in `tracked` once it is full; a key has to beat it to get in
+    floor: u64,
+}
+
+impl KeyPopularity {
+    pub fn new() -> Self {
+        Self {
+            sketch: FrequencySketch::new(WIDTH, SAMPLE_FACTOR * WIDTH as u64),
+            tracked: HashMap::new(),
+            floor: 0,
+        }
+    }
+
+    /// Count a hit on `key`
+    pub fn record(&mut self, key: &str) {
+        let estimate = self.sketch.increment(key);
+        if let Some(count) = self.tracked.get_mut(key) {
+            *count = estimate;
+        } else if self.tracked.len() < TRACKED_KEYS {
+            self.tracked.insert(key.to_string(), estimate);
+        } else if estimate > self.floor {
+            if let Some(coldest) = self.coldest() {
+                self.tracked.remove(&coldest);
+            }
+            self.tracked.insert(key.to_string(), estimate);
+            self.floor = self.tracked.values().copied().min().unwrap_or(0);
+        }
+
+        if self.sketch.halve_if_due() {
+            self.tracked.retain(|_, count| {
+                *count /= 2;
+                *count > 0
+            });
+            self.floor /= 2;
+        }
+    }
+
+    /// Up to `n` keys with the most hits, most hit first, with their estimated counts
+    pub fn top(&self, n: usize) -> Vec<(String, u64)> {
+        let mut top: Vec<_> = self
+            .tracked
+            .iter()
+            .map(|(key, count)| (key.clone(), *count))
+            .collect();
+        top.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top.truncate(n);
+        top
+    }
+
+    /// Forget every count
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    fn coldest(&self) -> Option<String> {
+        self.tracked
+            .iter()
+            .min_by_key(|(_, count)| **count)
+            .map(|(key, _)| key.clone())
+    }
+}
+
+/// Two independent hashes of `key`; row `i` indexes with `h1 + i * h2`
+fn hashes(key: &str) -> (u64, u64) {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    let h1 = hasher.finish();
+    0xa076_1d64_78bd_642fu64.hash(&mut hasher);
+    // Odd, so successive rows never land on the same column
+    (h1, hasher.finish() | 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heavy_hitters_rise_to_the_top() {
+        let mut popularity = KeyPopularity::new();
+        for i in 0..5_000 {
+            popularity.record(&format!("once-{}", i));
+            if i % 10 == 0 {
+                popularity.record("hot");
+            }
+            if i % 50 == 0 {
+                popularity.record("warm");
+            }
+        }
+
+        let top = popularity.top(2);
+        assert_eq!(top[0].0, "hot");
+        assert_eq!(top[1].0, "warm");
+        // Never an undercount
+        assert!(top[0].1 >= 500);
+        assert!(top[1].1 >= 100);
+        assert_eq!(popularity.top(1000).len(), TRACKED_KEYS);
+
+        popularity.clear();
+        assert!(popularity.top(10).is_empty());
+    }
+}
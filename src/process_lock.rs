@@ -0,0 +1,759 @@
+//! Locks shared between the processes using a cache directory
+//!
+//! The setup lock, the key locks of the optimized backend and the entry
+//! locks behind `lock_key` are each named by a path in the cache directory.
+//! [`LockBackend::File`] locks that file, which works across machines
+//! sharing the directory as far as the filesystem's locks do. Some Windows
+//! SMB mounts honour file locks inconsistently, so
+//! [`LockBackend::NamedMutex`] locks a Windows named mutex named after the
+//! path instead. Named mutexes only exclude processes on the same machine,
+//! and are released by the thread that took them; other platforms fall back
+//! to file locks.
+//!
+//! NFS clients do not always honour file locks either. With
+//! `use_file_locking`, file locks give way to [`LockBackend::LockFile`],
+//! which relies only on exclusive creates: the holder creates `<path>.held`
+//! with its pid, host name and a heartbeat time inside, rewrites the
+//! heartbeat while it holds the lock, and removes the file on release. A
+//! lock whose heartbeat is 30 seconds old, or whose holder on this host
+//! has exited, is broken by the next process waiting for it.
+//!
+//! Locks are exclusive, or shared with other readers when taken with
+//! [`ProcessLock::acquire_shared`]. File locks have a shared mode of their
+//! own. A reader of a lock file creates `<path>.<token>.reader` once no
+//! `<path>.held` exists, and backs off again should one have appeared
+//! meanwhile; a writer creates `<path>.held` and then waits for the reader
+//! files to go. Named mutexes have no shared mode and are always exclusive.
+
+use crate::error::{CacheError, CacheResult, ConfigIssue};
+use crate::storage::key_locks::LOCK_DIR;
+use fs4::fs_std::FileExt;
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// What cross-process locks are made of
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LockBackend {
+    /// Advisory locks on lock files in the cache directory
+    #[default]
+    File,
+    /// Windows named mutexes, for shares whose file locks are unreliable
+    NamedMutex,
+    /// Lock files created exclusively and kept alive by a heartbeat, for
+    /// network filesystems whose file locks are unreliable
+    LockFile,
+}
+
+impl LockBackend {
+    /// The backend `FanoutCache` uses when none is given: named mutexes on
+    /// Windows, file locks elsewhere
+    pub fn fanout_default() -> Self {
+        if cfg!(windows) {
+            Self::NamedMutex
+        } else {
+            Self::File
+        }
+    }
+
+    /// This backend, with file locks traded for lock files when
+    /// `use_file_locking` asks for locks that hold up on NFS
+    pub fn nfs_safe(self, use_file_locking: bool) -> Self {
+        match self {
+            Self::File if use_file_locking => Self::LockFile,
+            backend => backend,
+        }
+    }
+}
+
+/// How many keys share a key lock of the optimized backend
+///
+/// Finer locks let writers of different keys and readers run side by side;
+/// coarser ones mean fewer lock files, each of which costs round trips on a
+/// network filesystem. Every process using a directory must agree on it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LockGranularity {
+    /// Keys hash onto 65536 locks, so they rarely share one
+    Entry,
+    /// Keys hash onto 256 locks
+    #[default]
+    Shard,
+    /// One lock for the whole cache
+    Cache,
+}
+
+impl FromStr for LockGranularity {
+    type Err = CacheError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "entry" => Ok(Self::Entry),
+            "shard" => Ok(Self::Shard),
+            "cache" => Ok(Self::Cache),
+            other => Err(CacheError::Config(ConfigIssue::new(
+                "lock_granularity",
+                format!("unknown lock granularity {:?}", other),
+                "use \"entry\", \"shard\" or \"cache\"",
+            ))),
+        }
+    }
+}
+
+impl FromStr for LockBackend {
+    type Err = CacheError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "file" => Ok(Self::File),
+            "named_mutex" => Ok(Self::NamedMutex),
+            "lock_file" => Ok(Self::LockFile),
+            other => Err(CacheError::Config(ConfigIssue::new(
+                "lock_backend",
+                format!("unknown lock backend {:?}", other),
+                "use \"file\", \"named_mutex\" or \"lock_file\"",
+            ))),
+        }
+    }
+}
+
+/// A held cross-process lock, released when dropped
+pub struct ProcessLock {
+    held: Held,
+}
+
+enum Held {
+    File(File),
+    LockFile(#[allow(dead_code)] lock_file::HeldFile),
+    #[cfg(windows)]
+    Mutex(named_mutex::NamedMutex),
+}
+
+impl ProcessLock {
+    /// Wait until no other process holds the lock named by `path`, then hold it
+    ///
+    /// The directory `path` is in must exist.
+    pub fn acquire(backend: LockBackend, path: &Path) -> CacheResult<Self> {
+        Self::take(backend, path, false)
+    }
+
+    /// Wait until no other process holds the lock named by `path`
+    /// exclusively, then hold it along with any other readers
+    pub fn acquire_shared(backend: LockBackend, path: &Path) -> CacheResult<Self> {
+        Self::take(backend, path, true)
+    }
+
+    fn take(backend: LockBackend, path: &Path, shared: bool) -> CacheResult<Self> {
+        match backend {
+            LockBackend::LockFile => Ok(Self {
+                held: Held::LockFile(if shared {
+                    lock_file::HeldFile::acquire_shared(path)?
+                } else {
+                    lock_file::HeldFile::acquire(path)?
+                }),
+            }),
+            #[cfg(windows)]
+            LockBackend::NamedMutex => Ok(Self {
+                held: Held::Mutex(named_mutex::NamedMutex::acquire(&Self::mutex_name(path)?)?),
+            }),
+            _ => {
+                let file = OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(path)?;
+                let locked = if shared {
+                    FileExt::lock_shared(&file)
+                } else {
+                    FileExt::lock_exclusive(&file)
+                };
+                locked.map_err(|e| {
+                    CacheError::Io(std::io::Error::other(format!(
+                        "Failed to lock {:?}: {}",
+                        path, e
+                    )))
+                })?;
+                Ok(Self {
+                    held: Held::File(file),
+                })
+            }
+        }
+    }
+
+    /// The locked file, for locks that are one
+    pub fn file(&self) -> Option<&File> {
+        match &self.held {
+            Held::File(file) => Some(file),
+            Held::LockFile(_) => None,
+            #[cfg(windows)]
+            Held::Mutex(_) => None,
+        }
+    }
+
+    /// Mutex name every process derives alike from `path`
+    #[cfg(windows)]
+    fn mutex_name(path: &Path) -> CacheResult<String> {
+        let directory = path.parent().unwrap_or(path).canonicalize()?;
+        let path = directory.join(path.file_name().unwrap_or_default());
+        // Windows paths compare without regard to case
+        let path = path.to_string_lossy().to_lowercase();
+        Ok(format!(
+            "Global\\diskcache_rs-{}",
+            blake3::hash(path.as_bytes()).to_hex()
+        ))
+    }
+}
+
+mod lock_file {
+    use parking_lot::Mutex;
+    use std::collections::HashMap;
+    use std::fs::OpenOptions;
+    use std::io::{self, Read, Seek, Write};
+    use std::path::{Path, PathBuf};
+    use std::sync::OnceLock;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::{host_name, process_alive};
+    use crate::fork::BackgroundThread;
+
+    /// Age of a heartbeat after which its lock is taken to be abandoned
+    const STALE_AFTER: Duration = Duration::from_secs(30);
+    /// How often held lock files have their heartbeat rewritten
+    const HEARTBEAT: Duration = Duration::from_secs(5);
+    /// Longest pause between attempts to take a held lock
+    const MAX_POLL: Duration = Duration::from_millis(100);
+
+    /// A lock file this process created and keeps alive
+    pub struct HeldFile {
+        path: PathBuf,
+        token: String,
+        /// Process that created it; a forked child does not release it
+        pid: u32,
+    }
+
+    /// Who holds a lock file, as recorded inside it
+    struct Owner {
+        pid: u32,
+        host: String,
+        heartbeat: u64,
+        token: String,
+    }
+
+    impl Owner {
+        fn is_stale(&self) -> bool {
+            if now_millis().saturating_sub(self.heartbeat) > STALE_AFTER.as_millis() as u64 {
+                return true;
+            }
+            self.host == host_name() && !process_alive(self.pid)
+        }
+    }
+
+    impl HeldFile {
+        /// Wait until `path`'s lock file can be created, breaking it if its
+        /// holder is gone, and until its readers are done, then keep it
+        /// alive until dropped
+        pub fn acquire(path: &Path) -> io::Result<Self> {
+            let held = held_path(path);
+            let token = uuid::Uuid::new_v4().simple().to_string();
+            let mut poll = Duration::from_millis(1);
+            let lock = loop {
+                if let Some(lock) = Self::create(&held, &token)? {
+                    break lock;
+                }
+                if writer_gone(&held, &token)? {
+                    continue;
+                }
+                std::thread::sleep(poll);
+                poll = (poll * 2).min(MAX_POLL);
+            };
+            // Readers that came after see our file and back off
+            let mut poll = Duration::from_millis(1);
+            while readers_remain(path, &token)? {
+                std::thread::sleep(poll);
+                poll = (poll * 2).min(MAX_POLL);
+            }
+            Ok(lock)
+        }
+
+        /// Wait until no one holds `path`'s lock file, then keep a reader
+        /// file of our own alive until dropped
+        pub fn acquire_shared(path: &Path) -> io::Result<Self> {
+            let held = held_path(path);
+            let token = uuid::Uuid::new_v4().simple().to_string();
+            let mut name = path.file_name().unwrap_or_default().to_os_string();
+            name.push(format!(".{}.reader", token));
+            let reader = path.with_file_name(name);
+            let mut poll = Duration::from_millis(1);
+            loop {
+                if writer_gone(&held, &token)? {
+                    let lock = Self::create(&reader, &token)?
+                        .ok_or_else(|| io::Error::other("reader file already exists"))?;
+                    // A writer that came meanwhile goes first
+                    if !held.exists() {
+                        return Ok(lock);
+                    }
+                    drop(lock);
+                }
+                std::thread::sleep(poll);
+                poll = (poll * 2).min(MAX_POLL);
+            }
+        }
+
+        /// Create the lock file `path` for this process unless it exists
+        fn create(path: &Path, token: &str) -> io::Result<Option<Self>> {
+            let mut file = match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            file.write_all(record(token).as_bytes())?;
+            file.sync_all()?;
+            holders()
+                .lock()
+                .insert(path.to_path_buf(), token.to_string());
+            Ok(Some(Self {
+                path: path.to_path_buf(),
+                token: token.to_string(),
+                pid: std::process::id(),
+            }))
+        }
+    }
+
+    fn held_path(path: &Path) -> PathBuf {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(".held");
+        path.with_file_name(name)
+    }
+
+    /// Whether no one holds the lock file `held`, after breaking it if its
+    /// holder is gone
+    fn writer_gone(held: &Path, token: &str) -> io::Result<bool> {
+        match read_owner(held)? {
+            None => Ok(true),
+            Some(owner) if owner.is_stale() => {
+                break_stale(held, &owner, token)?;
+                Ok(true)
+            }
+            Some(_) => Ok(false),
+        }
+    }
+
+    /// Whether live readers of `path`'s lock remain, after breaking the
+    /// reader files of readers that are gone
+    fn readers_remain(path: &Path, token: &str) -> io::Result<bool> {
+        let directory = path.parent().unwrap_or(Path::new("."));
+        let mut prefix = path.file_name().unwrap_or_default().to_os_string();
+        prefix.push(".");
+        let prefix = prefix.to_string_lossy().into_owned();
+        let mut remain = false;
+        for entry in std::fs::read_dir(directory)? {
+            let reader = entry?.path();
+            let is_reader = reader.extension() == Some("reader".as_ref())
+                && reader
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with(&prefix));
+            if !is_reader {
+                continue;
+            }
+            match read_owner(&reader)? {
+                None => {}
+                Some(owner) if owner.is_stale() => break_stale(&reader, &owner, token)?,
+                Some(_) => remain = true,
+            }
+        }
+        Ok(remain)
+    }
+
+    fn break_stale(path: &Path, owner: &Owner, token: &str) -> io::Result<()> {
+        tracing::warn!(
+            "Breaking lock {:?} of process {} on {}, which stopped renewing it",
+            path,
+            owner.pid,
+            owner.host
+        );
+        break_lock(path, owner, token)
+    }
+
+    impl Drop for HeldFile {
+        fn drop(&mut self) {
+            if self.pid != std::process::id() {
+                return;
+            }
+            holders().lock().remove(&self.path);
+            // A lock broken while we stalled belongs to someone else now
+            if let Ok(Some(owner)) = read_owner(&self.path) {
+                if owner.token == self.token {
+                    let _ = std::fs::remove_file(&self.path);
+                }
+            }
+        }
+    }
+
+    /// Move a stale lock file aside, unless another waiter did first
+    ///
+    /// Moving it is atomic, so of several waiters judging the same lock
+    /// stale one breaks it. Should the file moved aside turn out to be a
+    /// fresh lock created in the meantime, it is linked back.
+    fn break_lock(path: &Path, stale: &Owner, token: &str) -> io::Result<()> {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".{}.broken", token));
+        let broken = path.with_file_name(name);
+        match std::fs::rename(path, &broken) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        if read_owner(&broken)?.is_some_and(|owner| owner.token != stale.token) {
+            let _ = std::fs::hard_link(&broken, path);
+        }
+        std::fs::remove_file(&broken)
+    }
+
+    /// The holder recorded in a lock file, or `None` if there is none
+    fn read_owner(path: &Path) -> io::Result<Option<Owner>> {
+        let mut file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        let mut fields = contents.split_whitespace();
+        let owner = (|| {
+            Some(Owner {
+                pid: fields.next()?.parse().ok()?,
+                heartbeat: fields.next()?.parse().ok()?,
+                token: fields.next()?.to_string(),
+                host: fields.next().unwrap_or_default().to_string(),
+            })
+        })();
+        // A file still being written counts as fresh until it is old
+        let owner = owner.unwrap_or_else(|| Owner {
+            pid: 0,
+            host: String::new(),
+            heartbeat: file
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |modified| modified.as_millis() as u64),
+            token: String::new(),
+        });
+        Ok(Some(owner))
+    }
+
+    /// The contents of a lock file, always the same length so a heartbeat
+    /// can rewrite it in place
+    fn record(token: &str) -> String {
+        let mut host = host_name();
+        host.truncate(64);
+        format!(
+            "{:>10} {:>20} {} {:<64}\n",
+            std::process::id(),
+            now_millis(),
+            token,
+            host
+        )
+    }
+
+    /// Lock files held by this process, renewed by the heartbeat thread
+    fn holders() -> &'static Mutex<HashMap<PathBuf, String>> {
+        static HOLDERS: OnceLock<Mutex<HashMap<PathBuf, String>>> = OnceLock::new();
+        static HEARTBEAT: BackgroundThread = BackgroundThread::new();
+        let holders = HOLDERS.get_or_init(Default::default);
+        // A forked child leaves its parent's locks to the parent
+        HEARTBEAT.ensure("lock-heartbeat", || holders.lock().clear(), renew_held);
+        holders
+    }
+
+    fn renew_held() {
+        loop {
+            std::thread::sleep(HEARTBEAT);
+            let held: Vec<_> = holders()
+                .lock()
+                .iter()
+                .map(|(path, token)| (path.clone(), token.clone()))
+                .collect();
+            for (path, token) in held {
+                if let Err(e) = renew(&path, &token) {
+                    tracing::warn!("Failed to renew lock {:?}: {}", path, e);
+                }
+            }
+        }
+    }
+
+    /// Rewrite the heartbeat of a lock file that is still ours
+    fn renew(path: &Path, token: &str) -> io::Result<()> {
+        match read_owner(path)? {
+            Some(owner) if owner.token == token => {}
+            _ => return Ok(()),
+        }
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.rewind()?;
+        file.write_all(record(token).as_bytes())?;
+        file.sync_data()
+    }
+
+    fn now_millis() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64)
+    }
+
+    /// Break the lock files in `directory`, and in the directories under it
+    /// if `recursive`, that process `pid` on `host` holds
+    pub fn break_owned_by(
+        directory: &Path,
+        recursive: bool,
+        pid: u32,
+        host: &str,
+    ) -> io::Result<usize> {
+        let mut broken = 0;
+        let mut pending = vec![directory.to_path_buf()];
+        while let Some(directory) = pending.pop() {
+            let entries = match std::fs::read_dir(&directory) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for entry in entries {
+                let entry = entry?;
+                let path = entry.path();
+                if entry.file_type()?.is_dir() {
+                    if recursive {
+                        pending.push(path);
+                    }
+                    continue;
+                }
+                let extension = path.extension();
+                if extension != Some("held".as_ref()) && extension != Some("reader".as_ref()) {
+                    continue;
+                }
+                if let Some(owner) = read_owner(&path)? {
+                    if owner.pid == pid && owner.host == host {
+                        let token = uuid::Uuid::new_v4().simple().to_string();
+                        break_lock(&path, &owner, &token)?;
+                        broken += 1;
+                    }
+                }
+            }
+        }
+        Ok(broken)
+    }
+}
+
+/// Break the lock files of a cache directory held by process `pid` on
+/// `host`, which the process registry found gone; returns how many
+pub(crate) fn break_lock_files_of(directory: &Path, pid: u32, host: &str) -> CacheResult<usize> {
+    Ok(lock_file::break_owned_by(directory, false, pid, host)?
+        + lock_file::break_owned_by(&directory.join(LOCK_DIR), true, pid, host)?)
+}
+
+/// Name of this host, as recorded by the holders of locks and registrations
+pub(crate) fn host_name() -> String {
+    static HOST: OnceLock<String> = OnceLock::new();
+    HOST.get_or_init(|| {
+        #[cfg(unix)]
+        {
+            let mut name = [0u8; 256];
+            // SAFETY: the buffer is valid for its whole length
+            if unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) } == 0 {
+                let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+                return String::from_utf8_lossy(&name[..end]).replace(' ', "_");
+            }
+            String::new()
+        }
+        #[cfg(not(unix))]
+        {
+            std::env::var("COMPUTERNAME")
+                .unwrap_or_default()
+                .replace(' ', "_")
+        }
+    })
+    .clone()
+}
+
+/// Whether process `pid` on this host is still running
+pub(crate) fn process_alive(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        // SAFETY: signal 0 only checks that the process exists
+        let alive = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+        alive || std::io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = pid;
+        true
+    }
+}
+
+#[cfg(windows)]
+mod named_mutex {
+    use std::ffi::OsStr;
+    use std::io;
+    use std::os::windows::ffi::OsStrExt;
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::synchapi::{CreateMutexW, ReleaseMutex, WaitForSingleObject};
+    use winapi::um::winbase::{INFINITE, WAIT_ABANDONED, WAIT_OBJECT_0};
+    use winapi::um::winnt::HANDLE;
+
+    /// An owned Windows named mutex
+    pub struct NamedMutex(HANDLE);
+
+    // The handle may move between threads; only releasing it is tied to the
+    // thread that acquired it
+    unsafe impl Send for NamedMutex {}
+    unsafe impl Sync for NamedMutex {}
+
+    impl NamedMutex {
+        pub fn acquire(name: &str) -> io::Result<Self> {
+            let name: Vec<u16> = OsStr::new(name)
+                .encode_wide()
+                .chain(std::iter::once(0))
+                .collect();
+            // SAFETY: `name` is a NUL-terminated wide string that outlives the call
+            let handle = unsafe { CreateMutexW(std::ptr::null_mut(), 0, name.as_ptr()) };
+            if handle.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: `handle` is a mutex handle we own
+            match unsafe { WaitForSingleObject(handle, INFINITE) } {
+                // An abandoned mutex was held by a process that died; its
+                // owner's work is as unfinished as after a crash with file locks
+                WAIT_OBJECT_0 | WAIT_ABANDONED => Ok(Self(handle)),
+                _ => {
+                    let error = io::Error::last_os_error();
+                    // SAFETY: as above; not used afterwards
+                    unsafe { CloseHandle(handle) };
+                    Err(error)
+                }
+            }
+        }
+    }
+
+    impl Drop for NamedMutex {
+        fn drop(&mut self) {
+            // SAFETY: we own the handle and hold the mutex
+            unsafe {
+                ReleaseMutex(self.0);
+                CloseHandle(self.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn process_locks_exclude_other_holders() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("test.lock");
+        for backend in [
+            LockBackend::File,
+            LockBackend::NamedMutex,
+            LockBackend::LockFile,
+        ] {
+            let lock = ProcessLock::acquire(backend, &path).unwrap();
+            let (locked_tx, locked_rx) = mpsc::channel();
+            let waiter = {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let _lock = ProcessLock::acquire(backend, &path).unwrap();
+                    locked_tx.send(()).unwrap();
+                })
+            };
+            assert!(locked_rx.recv_timeout(Duration::from_millis(100)).is_err());
+            drop(lock);
+            locked_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+            waiter.join().unwrap();
+        }
+        assert_eq!(
+            "named_mutex".parse::<LockBackend>().unwrap(),
+            LockBackend::NamedMutex
+        );
+        assert!("flock".parse::<LockBackend>().is_err());
+    }
+
+    #[test]
+    fn shared_locks_admit_readers_and_exclude_writers() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("test.lock");
+        for backend in [LockBackend::File, LockBackend::LockFile] {
+            let first = ProcessLock::acquire_shared(backend, &path).unwrap();
+            let second = ProcessLock::acquire_shared(backend, &path).unwrap();
+
+            let (locked_tx, locked_rx) = mpsc::channel();
+            let writer = {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let lock = ProcessLock::acquire(backend, &path).unwrap();
+                    locked_tx.send(()).unwrap();
+                    std::thread::sleep(Duration::from_millis(200));
+                    drop(lock);
+                })
+            };
+            assert!(locked_rx.recv_timeout(Duration::from_millis(100)).is_err());
+            drop(first);
+            assert!(locked_rx.recv_timeout(Duration::from_millis(100)).is_err());
+            drop(second);
+            locked_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+            // Readers wait for the writer in turn
+            drop(ProcessLock::acquire_shared(backend, &path).unwrap());
+            writer.join().unwrap();
+        }
+        let files: Vec<_> = std::fs::read_dir(temp_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, ["test.lock"]);
+        assert_eq!(
+            "cache".parse::<LockGranularity>().unwrap(),
+            LockGranularity::Cache
+        );
+        assert!("row".parse::<LockGranularity>().is_err());
+    }
+
+    #[test]
+    fn lock_files_of_exited_holders_are_broken() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("test.lock");
+        let held = temp_dir.path().join("test.lock.held");
+
+        // Left behind by a process on this host that has since exited
+        #[cfg(unix)]
+        {
+            let mut child = std::process::Command::new(std::env::current_exe().unwrap())
+                .arg("--list")
+                .stdout(std::process::Stdio::null())
+                .spawn()
+                .unwrap();
+            let pid = child.id();
+            child.wait().unwrap();
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis();
+            let host = host_name();
+            std::fs::write(&held, format!("{} {} stale {}", pid, now, host)).unwrap();
+            drop(ProcessLock::acquire(LockBackend::LockFile, &path).unwrap());
+            assert!(!held.exists());
+        }
+
+        // Left behind by a holder elsewhere that stopped renewing it
+        std::fs::write(&held, "1 0 stale elsewhere").unwrap();
+        let lock = ProcessLock::acquire(LockBackend::LockFile, &path).unwrap();
+        let contents = std::fs::read_to_string(&held).unwrap();
+        assert!(contents.contains(&std::process::id().to_string()));
+        assert!(!contents.contains("stale"));
+
+        drop(lock);
+        assert!(!held.exists());
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+    }
+}
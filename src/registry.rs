@@ -0,0 +1,256 @@
+//! Registry of the processes that have a cache directory open
+//!
+//! Every process opening a cache directory records itself in a file under
+//! `processes/`: its pid, host name, when it opened the cache and a heartbeat
+//! time that a background thread rewrites every few seconds. Closing the
+//! cache removes the file again. [`list`] reads the registry back, so one
+//! can see who else is attached to a directory.
+//!
+//! A registration whose heartbeat is [`STALE_AFTER`] old, or whose process on
+//! this host has exited, belongs to a process that died without closing the
+//! cache. Opening the cache or listing the registry reaps it: the lock files
+//! that process held are broken and its writer lease released, so a crashed
+//! worker does not hold everyone else up until those time out on their own.
+//! Heartbeats are wall-clock times, so hosts sharing a directory need clocks
+//! that agree to well within [`STALE_AFTER`].
+
+use crate::error::CacheResult;
+use crate::fork::BackgroundThread;
+use crate::process_lock::{break_lock_files_of, host_name, process_alive};
+use crate::storage::lease;
+use crate::utils::current_timestamp_millis;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Directory under the cache directory holding one file per attached process
+pub const PROCESS_DIR: &str = "processes";
+const REGISTRATION_EXTENSION: &str = "proc";
+/// How often registrations have their heartbeat rewritten
+const HEARTBEAT: Duration = Duration::from_secs(5);
+/// Age of a heartbeat after which its process is taken to be gone
+pub const STALE_AFTER: Duration = Duration::from_secs(30);
+
+/// A process attached to a cache directory, as listed by [`list`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub host: String,
+    /// Milliseconds since the epoch the process opened the cache
+    pub opened_at: u64,
+    /// Milliseconds since the epoch of its last heartbeat
+    pub heartbeat: u64,
+    /// Whether the registration belongs to the calling process
+    pub current: bool,
+}
+
+impl ProcessInfo {
+    fn is_stale(&self, now: u64) -> bool {
+        if now.saturating_sub(self.heartbeat) > STALE_AFTER.as_millis() as u64 {
+            return true;
+        }
+        self.host == host_name() && !process_alive(self.pid)
+    }
+}
+
+/// This process's entry in a directory's registry, removed when dropped
+pub struct Registration {
+    path: PathBuf,
+    /// Process that registered; a forked child leaves the entry alone
+    pid: u32,
+}
+
+impl Registration {
+    /// Record this process as attached to `directory`, after reaping the
+    /// registrations of processes that died without closing it
+    pub fn register(directory: &Path) -> CacheResult<Self> {
+        reap(directory)?;
+        let registry = directory.join(PROCESS_DIR);
+        std::fs::create_dir_all(&registry)?;
+        let path = registry.join(format!(
+            "{}.{}",
+            uuid::Uuid::new_v4().simple(),
+            REGISTRATION_EXTENSION
+        ));
+        let opened_at = current_timestamp_millis();
+        write_record(&path, opened_at)?;
+        registered().lock().insert(path.clone(), opened_at);
+        Ok(Self {
+            path,
+            pid: std::process::id(),
+        })
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if self.pid != std::process::id() {
+            return;
+        }
+        registered().lock().remove(&self.path);
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!("Failed to unregister {:?}: {}", self.path, e);
+            }
+        }
+    }
+}
+
+/// The live processes attached to `directory`, oldest first, after reaping
+/// the registrations of the dead ones
+pub fn list(directory: &Path) -> CacheResult<Vec<ProcessInfo>> {
+    reap(directory)
+}
+
+/// Drop the registrations of processes that are gone, with their lock files
+/// and writer lease, and return the rest
+fn reap(directory: &Path) -> CacheResult<Vec<ProcessInfo>> {
+    let registry = directory.join(PROCESS_DIR);
+    let entries = match std::fs::read_dir(&registry) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let ours = registered().lock().clone();
+    let now = current_timestamp_millis();
+    let mut live = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension() != Some(REGISTRATION_EXTENSION.as_ref()) {
+            continue;
+        }
+        let Some(mut process) = read_record(&path)? else {
+            continue;
+        };
+        process.current = ours.contains_key(&path);
+        if process.current || !process.is_stale(now) {
+            live.push(process);
+            continue;
+        }
+        tracing::warn!(
+            "Process {} on {} left {:?} without closing it; releasing its locks",
+            process.pid,
+            process.host,
+            directory
+        );
+        break_lock_files_of(directory, process.pid, &process.host)?;
+        lease::release_abandoned(directory, process.pid, &process.host)?;
+        match std::fs::remove_file(&path) {
+            Ok(()) => {}
+            // Reaped by another process at the same time
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    live.sort_by_key(|process| process.opened_at);
+    Ok(live)
+}
+
+/// The process recorded in a registration, or `None` if it is gone or
+/// still being written
+fn read_record(path: &Path) -> CacheResult<Option<ProcessInfo>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut fields = contents.split_whitespace();
+    Ok((|| {
+        Some(ProcessInfo {
+            pid: fields.next()?.parse().ok()?,
+            opened_at: fields.next()?.parse().ok()?,
+            heartbeat: fields.next()?.parse().ok()?,
+            host: fields.next().unwrap_or_default().to_string(),
+            current: false,
+        })
+    })())
+}
+
+/// Write this process's registration with a fresh heartbeat, replacing it by
+/// rename so readers never see half of one
+fn write_record(path: &Path, opened_at: u64) -> CacheResult<()> {
+    let staged = path.with_extension("tmp");
+    std::fs::write(
+        &staged,
+        format!(
+            "{} {} {} {}\n",
+            std::process::id(),
+            opened_at,
+            current_timestamp_millis(),
+            host_name()
+        ),
+    )?;
+    std::fs::rename(&staged, path)?;
+    Ok(())
+}
+
+/// Registrations of this process and when they were made, renewed by the
+/// heartbeat thread
+fn registered() -> &'static Mutex<HashMap<PathBuf, u64>> {
+    static REGISTERED: OnceLock<Mutex<HashMap<PathBuf, u64>>> = OnceLock::new();
+    static HEARTBEAT_THREAD: BackgroundThread = BackgroundThread::new();
+    let registered = REGISTERED.get_or_init(Default::default);
+    // A forked child registers on its own; its parent's entries stay the parent's
+    HEARTBEAT_THREAD.ensure(
+        "registry-heartbeat",
+        || registered.lock().clear(),
+        renew_registered,
+    );
+    registered
+}
+
+fn renew_registered() {
+    loop {
+        std::thread::sleep(HEARTBEAT);
+        let held = registered().lock().clone();
+        for (path, opened_at) in held {
+            // Unregistered since the copy was taken
+            if !registered().lock().contains_key(&path) {
+                continue;
+            }
+            if let Err(e) = write_record(&path, opened_at) {
+                tracing::warn!("Failed to renew registration {:?}: {}", path, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dead_processes_are_reaped_with_their_locks_and_lease() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let directory = temp_dir.path();
+
+        let registration = Registration::register(directory).unwrap();
+        let listed = list(directory).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].pid, std::process::id());
+        assert_eq!(listed[0].host, host_name());
+        assert!(listed[0].current);
+
+        // A process elsewhere that stopped heartbeating, holding a lock file
+        // and the writer lease
+        let registry = directory.join(PROCESS_DIR);
+        std::fs::write(registry.join("dead.proc"), "4242 1 1 elsewhere\n").unwrap();
+        let locks = directory.join(crate::storage::key_locks::LOCK_DIR);
+        std::fs::create_dir_all(&locks).unwrap();
+        let held = locks.join("00.lock.held");
+        let now = current_timestamp_millis();
+        std::fs::write(&held, format!("4242 {} token elsewhere", now)).unwrap();
+        let lease_file = directory.join("writer.lease");
+        std::fs::write(&lease_file, format!("4242@elsewhere-nonce {}", u64::MAX)).unwrap();
+
+        assert_eq!(list(directory).unwrap().len(), 1);
+        assert!(!registry.join("dead.proc").exists());
+        assert!(!held.exists());
+        assert!(!lease_file.exists());
+
+        drop(registration);
+        assert!(list(directory).unwrap().is_empty());
+    }
+}
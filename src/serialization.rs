@@ -1,5 +1,17 @@
-use crate::error::{CacheError, CacheResult};
+use crate::error::{CacheError, CacheResult, ConfigIssue};
+use aes_gcm::aead::{AeadCore, AeadInPlace, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce, Tag};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Leading byte of a value encrypted by [`EncryptedDisk`]
+const ENCRYPTED_FORMAT_V1: u8 = 1;
+/// Length of an AES-GCM nonce
+const NONCE_LEN: usize = 12;
+/// Length of an AES-GCM authentication tag
+const TAG_LEN: usize = 16;
+/// Header in front of an encrypted value: format byte, nonce and tag
+const ENCRYPTED_HEADER_LEN: usize = 1 + NONCE_LEN + TAG_LEN;
 
 /// Optimized serialization using MessagePack with LZ4 compression
 pub struct OptimizedSerializer;
@@ -52,6 +64,30 @@ pub enum StorageMode {
     File(String), // filename
 }
 
+/// How readily eviction gives up an entry, whatever its policy ranks it
+///
+/// Low priority entries are evicted before any other, and high priority
+/// ones only once nothing else is left; within a level the policy decides.
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    bincode::Encode,
+    bincode::Decode,
+)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
 /// Metadata for cached entries
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CacheEntry {
@@ -63,6 +99,14 @@ pub struct CacheEntry {
     pub size: u64,
     pub tags: Vec<String>,
     pub expire_time: Option<u64>,
+    /// What the value costs to recompute, as weighed by the caller; only
+    /// eviction uses it, and it is not stored
+    #[serde(default)]
+    pub weight: Option<f64>,
+    /// Which eviction level the entry belongs to; like `weight`, only
+    /// eviction uses it, and it is not stored
+    #[serde(default)]
+    pub priority: Priority,
 }
 
 impl CacheEntry {
@@ -89,6 +133,8 @@ impl CacheEntry {
             size,
             tags,
             expire_time,
+            weight: None,
+            priority: Priority::Normal,
         }
     }
 
@@ -114,6 +160,8 @@ impl CacheEntry {
             size,
             tags,
             expire_time,
+            weight: None,
+            priority: Priority::Normal,
         }
     }
 
@@ -163,3 +211,241 @@ impl CacheEntry {
         }
     }
 }
+
+/// Key/value codec applied between the public cache API and the storage
+/// backend, mirroring python-diskcache's `Disk` extension point.
+pub trait Disk: Send + Sync {
+    /// Encode a user key into the key stored by the backend
+    fn put(&self, key: &str) -> CacheResult<String> {
+        Ok(key.to_string())
+    }
+
+    /// Decode a stored key back into the user key
+    fn get(&self, key: &str) -> CacheResult<String> {
+        Ok(key.to_string())
+    }
+
+    /// Encode value bytes before they are handed to the backend
+    fn store(&self, value: &[u8]) -> CacheResult<Vec<u8>>;
+
+    /// Decode bytes read from the backend back into value bytes
+    fn fetch(&self, data: &[u8]) -> CacheResult<Vec<u8>>;
+
+    /// Whether `store` leaves value bytes unchanged, so stored data can be read
+    /// directly from the backend's files
+    fn stores_verbatim(&self) -> bool {
+        false
+    }
+}
+
+/// Default codec: keys and values are stored unchanged
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RawDisk;
+
+impl Disk for RawDisk {
+    fn store(&self, value: &[u8]) -> CacheResult<Vec<u8>> {
+        Ok(value.to_vec())
+    }
+
+    fn fetch(&self, data: &[u8]) -> CacheResult<Vec<u8>> {
+        Ok(data.to_vec())
+    }
+
+    fn stores_verbatim(&self) -> bool {
+        true
+    }
+}
+
+/// JSON codec: values must be JSON documents, which are normalized to their
+/// compact form and optionally LZ4-compressed before storage
+#[derive(Debug, Clone, Copy)]
+pub struct JsonDisk {
+    compress: bool,
+}
+
+impl JsonDisk {
+    pub fn new(compress: bool) -> Self {
+        Self { compress }
+    }
+}
+
+impl Default for JsonDisk {
+    fn default() -> Self {
+        Self::new(true)
+    }
+}
+
+impl Disk for JsonDisk {
+    fn store(&self, value: &[u8]) -> CacheResult<Vec<u8>> {
+        let document: serde_json::Value = serde_json::from_slice(value)
+            .map_err(|e| CacheError::Serialization(format!("Value is not valid JSON: {}", e)))?;
+        let compact =
+            serde_json::to_vec(&document).map_err(|e| CacheError::Serialization(e.to_string()))?;
+
+        if self.compress {
+            Ok(lz4_flex::compress_prepend_size(&compact))
+        } else {
+            Ok(compact)
+        }
+    }
+
+    fn fetch(&self, data: &[u8]) -> CacheResult<Vec<u8>> {
+        if self.compress {
+            lz4_flex::decompress_size_prepended(data)
+                .map_err(|e| CacheError::Deserialization(format!("Decompression failed: {}", e)))
+        } else {
+            Ok(data.to_vec())
+        }
+    }
+}
+
+/// Key values are encrypted with at rest
+///
+/// Derived from a secret of any non-zero length, so a passphrase serves as
+/// well as random bytes. Only the derived key is kept.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    /// Derive the AES-256 key from a secret of any non-zero length
+    pub fn new(secret: &[u8]) -> CacheResult<Self> {
+        if secret.is_empty() {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "encryption_key",
+                "an empty secret encrypts nothing",
+                "pass the secret the cache's values are encrypted with",
+            )));
+        }
+        Ok(Self(blake3::derive_key(
+            "diskcache_rs value encryption v1",
+            secret,
+        )))
+    }
+
+    /// Name of the key that gives nothing about it away, recorded with the
+    /// cache so opening it with another key fails before any value is read
+    pub fn fingerprint(&self) -> String {
+        let check = blake3::keyed_hash(&self.0, b"diskcache_rs encryption key check");
+        format!("aes-256-gcm:{}", &check.to_hex()[..16])
+    }
+}
+
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// Codec encrypting the values of another codec with AES-256-GCM
+///
+/// Each value gets a fresh random nonce. The header in front of the
+/// ciphertext holds the nonce and the authentication tag, so a value altered
+/// on disk, or read with another key, fails to decrypt rather than coming
+/// back garbled. Keys are left as the inner codec encodes them.
+pub struct EncryptedDisk {
+    inner: Arc<dyn Disk>,
+    cipher: Aes256Gcm,
+}
+
+impl EncryptedDisk {
+    pub fn new(inner: Arc<dyn Disk>, key: &EncryptionKey) -> Self {
+        Self {
+            inner,
+            cipher: Aes256Gcm::new(&key.0.into()),
+        }
+    }
+}
+
+impl Disk for EncryptedDisk {
+    fn put(&self, key: &str) -> CacheResult<String> {
+        self.inner.put(key)
+    }
+
+    fn get(&self, key: &str) -> CacheResult<String> {
+        self.inner.get(key)
+    }
+
+    fn store(&self, value: &[u8]) -> CacheResult<Vec<u8>> {
+        let mut plaintext = self.inner.store(value)?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(&nonce, &[], &mut plaintext)
+            .map_err(|_| CacheError::Serialization("Failed to encrypt value".to_string()))?;
+
+        let mut stored = Vec::with_capacity(ENCRYPTED_HEADER_LEN + plaintext.len());
+        stored.push(ENCRYPTED_FORMAT_V1);
+        stored.extend_from_slice(&nonce);
+        stored.extend_from_slice(&tag);
+        stored.extend_from_slice(&plaintext);
+        Ok(stored)
+    }
+
+    fn fetch(&self, data: &[u8]) -> CacheResult<Vec<u8>> {
+        if data.len() < ENCRYPTED_HEADER_LEN || data[0] != ENCRYPTED_FORMAT_V1 {
+            return Err(CacheError::Deserialization(
+                "Value was not encrypted by this cache".to_string(),
+            ));
+        }
+        let nonce = Nonce::from_slice(&data[1..1 + NONCE_LEN]);
+        let tag = Tag::from_slice(&data[1 + NONCE_LEN..ENCRYPTED_HEADER_LEN]);
+        let mut plaintext = data[ENCRYPTED_HEADER_LEN..].to_vec();
+        self.cipher
+            .decrypt_in_place_detached(nonce, &[], &mut plaintext, tag)
+            .map_err(|_| {
+                CacheError::Deserialization(
+                    "Value failed authentication: wrong encryption key or altered data".to_string(),
+                )
+            })?;
+        self.inner.fetch(&plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_disk_round_trip() {
+        let disk = JsonDisk::default();
+        let stored = disk
+            .store(br#"{ "name": "shader", "passes": [1, 2, 3] }"#)
+            .unwrap();
+        let fetched = disk.fetch(&stored).unwrap();
+        assert_eq!(fetched, br#"{"name":"shader","passes":[1,2,3]}"#.to_vec());
+    }
+
+    #[test]
+    fn test_encrypted_disk_round_trip() {
+        let key = EncryptionKey::new(b"studio secret").unwrap();
+        let disk = EncryptedDisk::new(Arc::new(RawDisk), &key);
+        let stored = disk.store(b"frame 1001").unwrap();
+        assert_eq!(stored.len(), ENCRYPTED_HEADER_LEN + 10);
+        assert!(!stored.windows(10).any(|window| window == b"frame 1001"));
+        assert_eq!(disk.fetch(&stored).unwrap(), b"frame 1001");
+        // Nonces are fresh per value
+        assert_ne!(disk.store(b"frame 1001").unwrap(), stored);
+
+        let other = EncryptedDisk::new(
+            Arc::new(RawDisk),
+            &EncryptionKey::new(b"other secret").unwrap(),
+        );
+        assert!(matches!(
+            other.fetch(&stored),
+            Err(CacheError::Deserialization(_))
+        ));
+        let mut altered = stored.clone();
+        *altered.last_mut().unwrap() ^= 1;
+        assert!(disk.fetch(&altered).is_err());
+        assert!(disk.fetch(b"frame 1001").is_err());
+    }
+
+    #[test]
+    fn test_json_disk_rejects_invalid_json() {
+        let disk = JsonDisk::new(false);
+        assert!(matches!(
+            disk.store(b"not json"),
+            Err(CacheError::Serialization(_))
+        ));
+    }
+}
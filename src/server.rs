@@ -0,0 +1,1102 @@
+//! Cache server owning a directory on behalf of other processes
+//!
+//! Processes sharing a cache directory normally coordinate through it:
+//! locks, leases and a SQLite index every one of them opens. A
+//! [`CacheServer`] instead makes one process the only one to open the
+//! directory and serves `get`, `set` and `delete` to the others over a Unix
+//! socket, so none of that coordination is needed. A lock file in the
+//! directory keeps a second server from starting on it.
+//!
+//! Requests and replies are frames of a little-endian `u64` length followed
+//! by that many bytes, as between a designated writer and its followers. A
+//! request is an operation byte followed by length-prefixed fields:
+//!
+//! * `GET key` replies with `0` for a miss or `1` and the value
+//! * `SET key value expire_time tag...` stores the value; `expire_time` is
+//!   eight little-endian bytes of seconds since the epoch, 0 for none
+//! * `DELETE key` replies with `1` if the key existed, else `0`
+//! * `STORAGE origin call` makes a [`StorageBackend`] call on the served
+//!   cache's storage, for [`ServerStorage`]; the call and its result are
+//!   encoded with bincode, so both ends must run the same version of this
+//!   crate. `origin` is eight little-endian bytes naming the client
+//! * `SUBSCRIBE origin` turns the connection into a stream of the served
+//!   cache's changes, other than those of the client named `origin`: after
+//!   the reply, the server sends a frame of the [`ChangeKind`] id followed
+//!   by the key for each
+//! * `LOCK key` waits for the served cache's entry lock on the key and
+//!   replies once it holds it; the connection then holds the lock for the
+//!   client until the client hangs up, so clients' `lock_key`, `cas` and the
+//!   like exclude each other and the server's own process
+//!
+//! Replies start with a status byte, `0` for success, `1` for an error
+//! followed by its message or `2` for a missing key followed by the key.
+//! Values are the bytes [`DiskCache::get`] returns, so clients share the
+//! encoding of the process serving them.
+//!
+//! [`ServerStorage`] is the storage of a cache attached to a server, opened
+//! with the `server` backend and the socket as its directory, which Python
+//! spells `unix:///path/to/server.sock`. Such a cache runs its own eviction,
+//! expiration and codecs on top, as over any other backend, so its values are
+//! encoded as the client stores them rather than as the server would.
+
+use crate::cache::DiskCache;
+use crate::changes::{Change, ChangeFeed, ChangeKind, Origin, Subscription, FEED_POLL};
+use crate::error::{CacheError, CacheResult};
+use crate::fork::ForkCheck;
+use crate::serialization::{CacheEntry, StorageMode};
+use crate::storage::writer::{put_bytes, read_frame, take_bytes, take_string, write_frame};
+use crate::storage::{FileNaming, StorageBackend, TierSizes, VacuumReport};
+use parking_lot::{Condvar, Mutex};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Socket a server listens on unless told otherwise, in the cache directory
+pub const SOCKET_FILE: &str = "server.sock";
+const LOCK_FILE: &str = "server.lock";
+
+const OP_GET: u8 = 1;
+const OP_SET: u8 = 2;
+const OP_DELETE: u8 = 3;
+const OP_STORAGE: u8 = 4;
+const OP_SUBSCRIBE: u8 = 5;
+const OP_LOCK: u8 = 6;
+const STATUS_OK: u8 = 0;
+const STATUS_ERROR: u8 = 1;
+const STATUS_KEY_NOT_FOUND: u8 = 2;
+
+/// Client connections and the threads serving them
+type Connections = Arc<Mutex<Vec<(UnixStream, JoinHandle<()>)>>>;
+
+/// Serves a cache to other processes until closed or dropped
+pub struct CacheServer {
+    /// Held while serving, so no other server starts on the directory
+    lock: Mutex<Option<File>>,
+    socket: PathBuf,
+    stop: Arc<AtomicBool>,
+    connections: Connections,
+    acceptor: Mutex<Option<JoinHandle<()>>>,
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl CacheServer {
+    /// Serve `cache` on `socket`, or on `server.sock` in its directory
+    ///
+    /// Fails if another server has the directory. A socket left behind by a
+    /// server that died is replaced.
+    pub fn start(cache: Arc<DiskCache>, socket: Option<&Path>) -> CacheResult<Self> {
+        use fs4::fs_std::FileExt;
+
+        let directory = cache.info().directory;
+        std::fs::create_dir_all(&directory)?;
+        let lock = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(directory.join(LOCK_FILE))?;
+        if !FileExt::try_lock_exclusive(&lock).unwrap_or(false) {
+            return Err(CacheError::Lock(format!(
+                "another server is serving {:?}",
+                directory
+            )));
+        }
+        let socket = socket.map_or_else(|| directory.join(SOCKET_FILE), Path::to_path_buf);
+        if UnixStream::connect(&socket).is_ok() {
+            return Err(CacheError::Lock(format!(
+                "another server is listening on {:?}",
+                socket
+            )));
+        }
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let connections: Connections = Arc::default();
+        let acceptor = {
+            let stop = stop.clone();
+            let connections = connections.clone();
+            std::thread::Builder::new()
+                .name("cache-server".into())
+                .spawn(move || Self::accept(listener, cache, stop, connections))?
+        };
+        Ok(Self {
+            lock: Mutex::new(Some(lock)),
+            socket,
+            stop,
+            connections,
+            acceptor: Mutex::new(Some(acceptor)),
+            stopped: Arc::default(),
+        })
+    }
+
+    /// The socket clients connect to
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    /// Block until the server is closed, or at most `timeout`; returns
+    /// whether it is closed
+    pub fn wait(&self, timeout: Option<Duration>) -> bool {
+        let (stopped, changed) = &*self.stopped;
+        let mut stopped = stopped.lock();
+        match timeout {
+            Some(timeout) => {
+                if !*stopped {
+                    changed.wait_for(&mut stopped, timeout);
+                }
+            }
+            None => {
+                while !*stopped {
+                    changed.wait(&mut stopped);
+                }
+            }
+        }
+        *stopped
+    }
+
+    /// Stop accepting clients, hang up on the connected ones and remove the
+    /// socket
+    pub fn close(&self) {
+        let Some(acceptor) = self.acceptor.lock().take() else {
+            return;
+        };
+        self.stop.store(true, Ordering::Release);
+        // Wake the acceptor so it sees the stop flag
+        let _ = UnixStream::connect(&self.socket);
+        let _ = acceptor.join();
+        let _ = std::fs::remove_file(&self.socket);
+        for (stream, worker) in self.connections.lock().drain(..) {
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+            let _ = worker.join();
+        }
+        drop(self.lock.lock().take());
+        let (stopped, changed) = &*self.stopped;
+        *stopped.lock() = true;
+        changed.notify_all();
+    }
+
+    fn accept(
+        listener: UnixListener,
+        cache: Arc<DiskCache>,
+        stop: Arc<AtomicBool>,
+        connections: Connections,
+    ) {
+        for stream in listener.incoming() {
+            if stop.load(Ordering::Acquire) {
+                break;
+            }
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let Ok(handle) = stream.try_clone() else {
+                continue;
+            };
+            let cache = cache.clone();
+            let stop = stop.clone();
+            let worker = std::thread::spawn(move || Self::serve(stream, &cache, &stop));
+            let mut connections = connections.lock();
+            connections.retain(|(_, worker)| !worker.is_finished());
+            connections.push((handle, worker));
+        }
+    }
+
+    /// Answer one client's requests until it hangs up
+    fn serve(mut stream: UnixStream, cache: &DiskCache, stop: &AtomicBool) {
+        while let Ok(Some(request)) = read_frame(&mut stream) {
+            match request.split_first() {
+                Some((&OP_SUBSCRIBE, mut body)) => {
+                    let _ = Self::stream_changes(stream, cache, take_origin(&mut body), stop);
+                    return;
+                }
+                Some((&OP_LOCK, mut body)) => {
+                    let _ = Self::hold_lock(stream, cache, take_string(&mut body));
+                    return;
+                }
+                _ => {}
+            }
+            let reply = match apply(cache, &request) {
+                Ok(mut payload) => {
+                    payload.insert(0, STATUS_OK);
+                    payload
+                }
+                Err(CacheError::KeyNotFound(key)) => {
+                    let mut reply = vec![STATUS_KEY_NOT_FOUND];
+                    reply.extend_from_slice(key.as_bytes());
+                    reply
+                }
+                Err(e) => {
+                    let mut reply = vec![STATUS_ERROR];
+                    reply.extend_from_slice(e.to_string().as_bytes());
+                    reply
+                }
+            };
+            if write_frame(&mut stream, &reply).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Send a subscribed client the changes not made by `origin` until it
+    /// hangs up or the server closes
+    fn stream_changes(
+        mut stream: UnixStream,
+        cache: &DiskCache,
+        origin: CacheResult<Origin>,
+        stop: &AtomicBool,
+    ) -> io::Result<()> {
+        let mut subscription = Subscription::new();
+        let subscribed =
+            origin.and_then(|origin| cache.subscribe_skipping(origin, &mut subscription));
+        if let Err(e) = subscribed {
+            let mut reply = vec![STATUS_ERROR];
+            reply.extend_from_slice(e.to_string().as_bytes());
+            return write_frame(&mut stream, &reply);
+        }
+        write_frame(&mut stream, &[STATUS_OK])?;
+        while !stop.load(Ordering::Acquire) {
+            match subscription.recv(Some(FEED_POLL)) {
+                Some(change) => {
+                    let mut frame = vec![change.kind.id()];
+                    frame.extend_from_slice(change.key.as_bytes());
+                    write_frame(&mut stream, &frame)?;
+                }
+                None if hung_up(&stream)? => break,
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Take the entry lock on `key` for a client and hold it until the
+    /// client hangs up or the server closes
+    fn hold_lock(
+        mut stream: UnixStream,
+        cache: &DiskCache,
+        key: CacheResult<String>,
+    ) -> io::Result<()> {
+        let _lock = match key.and_then(|key| cache.lock_key(&key)) {
+            Ok(lock) => lock,
+            Err(e) => {
+                let mut reply = vec![STATUS_ERROR];
+                reply.extend_from_slice(e.to_string().as_bytes());
+                return write_frame(&mut stream, &reply);
+            }
+        };
+        write_frame(&mut stream, &[STATUS_OK])?;
+        // Nothing more is sent on the connection but its end
+        while read_frame(&mut stream)?.is_some() {}
+        Ok(())
+    }
+}
+
+/// Whether the other end of `stream` closed it, without blocking
+fn hung_up(stream: &UnixStream) -> io::Result<bool> {
+    stream.set_nonblocking(true)?;
+    let closed = match peek(stream) {
+        Ok(read) => read == 0,
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+            ) =>
+        {
+            false
+        }
+        Err(e) => return Err(e),
+    };
+    stream.set_nonblocking(false)?;
+    Ok(closed)
+}
+
+/// Wait for a byte to read on `stream` as a read would, without reading
+/// it; returns 0 at the end of the stream
+fn peek(stream: &UnixStream) -> io::Result<usize> {
+    use std::os::fd::AsRawFd;
+
+    let mut byte = 0u8;
+    let read = unsafe {
+        libc::recv(
+            stream.as_raw_fd(),
+            (&mut byte as *mut u8).cast(),
+            1,
+            libc::MSG_PEEK,
+        )
+    };
+    if read < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(read as usize)
+}
+
+fn take_origin(body: &mut &[u8]) -> CacheResult<Origin> {
+    let Some((origin, rest)) = body.split_first_chunk::<8>() else {
+        return Err(CacheError::Deserialization("missing origin".into()));
+    };
+    *body = rest;
+    Ok(Origin::from_le_bytes(*origin))
+}
+
+impl Drop for CacheServer {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Answer one request against `cache`, returning the reply payload
+fn apply(cache: &DiskCache, request: &[u8]) -> CacheResult<Vec<u8>> {
+    let Some((&op, mut body)) = request.split_first() else {
+        return Err(CacheError::Deserialization("empty request".into()));
+    };
+    match op {
+        OP_GET => match cache.get(&take_string(&mut body)?)? {
+            Some(value) => {
+                let mut reply = vec![1];
+                reply.extend_from_slice(&value);
+                Ok(reply)
+            }
+            None => Ok(vec![0]),
+        },
+        OP_SET => {
+            let key = take_string(&mut body)?;
+            let value = take_bytes(&mut body)?;
+            let expire_time = take_bytes(&mut body)?
+                .try_into()
+                .map(u64::from_le_bytes)
+                .map_err(|_| CacheError::Deserialization("malformed expire time".into()))?;
+            let mut tags = Vec::new();
+            while !body.is_empty() {
+                tags.push(take_string(&mut body)?);
+            }
+            cache.set(&key, value, (expire_time != 0).then_some(expire_time), tags)?;
+            Ok(Vec::new())
+        }
+        OP_DELETE => {
+            let existed = cache.delete(&take_string(&mut body)?)?;
+            Ok(vec![existed as u8])
+        }
+        OP_STORAGE => {
+            let origin = take_origin(&mut body)?;
+            let (call, _) = bincode::serde::decode_from_slice::<StorageCall, _>(
+                body,
+                bincode::config::standard(),
+            )
+            .map_err(|e| CacheError::Deserialization(format!("storage call: {}", e)))?;
+            call_storage(cache, origin, call)
+        }
+        op => Err(CacheError::Deserialization(format!(
+            "unknown request {}",
+            op
+        ))),
+    }
+}
+
+/// A [`StorageBackend`] call made through a `STORAGE` request
+#[derive(Serialize, Deserialize)]
+enum StorageCall {
+    Get(String),
+    GetMany(Vec<String>),
+    Set(String, CacheEntry),
+    SetBatch(Vec<(String, Vec<u8>)>),
+    Delete(String),
+    Evict(String),
+    Exists(String),
+    Version(String),
+    CompareAndSet(String, Option<u64>, CacheEntry),
+    SetAlias(String, String),
+    ResolveAlias(String),
+    RemoveAlias(String),
+    Aliases(String),
+    Keys,
+    PeekKey(bool),
+    KeyPage(Option<i64>, bool, u64),
+    PeekKeyInRange(String, String, bool),
+    Clear,
+    Vacuum,
+    WriteDataFile(String, Vec<u8>),
+    ReadDataFile(String),
+    Size,
+    ExistsLocal(String),
+}
+
+/// [`VacuumReport`] fields in declaration order
+type VacuumCounts = (u64, u64, u64, u64, u64);
+
+fn encode<T: Serialize>(result: &T) -> CacheResult<Vec<u8>> {
+    bincode::serde::encode_to_vec(result, bincode::config::standard())
+        .map_err(|e| CacheError::Serialization(format!("storage result: {}", e)))
+}
+
+/// Make `call` on the storage of `cache`, returning its encoded result
+///
+/// Changes are reported to the cache's subscribers as made by `origin`.
+fn call_storage(cache: &DiskCache, origin: Origin, call: StorageCall) -> CacheResult<Vec<u8>> {
+    let storage = cache.storage();
+    let storage = storage.as_ref();
+    let notify = |kind, key: &str, tags: &[String]| cache.publish(origin, kind, key, tags);
+    match call {
+        StorageCall::Get(key) => encode(&inline(storage, storage.get(&key)?)?),
+        StorageCall::GetMany(keys) => {
+            let entries = storage
+                .get_many(&keys)?
+                .into_iter()
+                .map(|entry| inline(storage, entry))
+                .collect::<CacheResult<Vec<_>>>()?;
+            encode(&entries)
+        }
+        StorageCall::Set(key, entry) => {
+            let tags = entry.tags.clone();
+            storage.set(&key, entry)?;
+            notify(ChangeKind::Set, &key, &tags);
+            encode(&())
+        }
+        StorageCall::SetBatch(entries) => {
+            let keys: Vec<String> = entries.iter().map(|(key, _)| key.clone()).collect();
+            storage.set_batch(entries)?;
+            for key in &keys {
+                notify(ChangeKind::Set, key, &[]);
+            }
+            encode(&())
+        }
+        StorageCall::Delete(key) => {
+            let existed = storage.delete(&key)?;
+            if existed {
+                notify(ChangeKind::Delete, &key, &[]);
+            }
+            encode(&existed)
+        }
+        StorageCall::Evict(key) => {
+            let existed = storage.evict(&key)?;
+            if existed {
+                notify(ChangeKind::Evict, &key, &[]);
+            }
+            encode(&existed)
+        }
+        StorageCall::Exists(key) => encode(&storage.exists(&key)?),
+        StorageCall::ExistsLocal(key) => encode(&storage.exists_local(&key)?),
+        StorageCall::Version(key) => encode(&storage.version(&key)?),
+        StorageCall::CompareAndSet(key, expected, entry) => {
+            // The client holds the key's entry lock through the server
+            let tags = entry.tags.clone();
+            let stored = storage.compare_and_set(&key, expected, entry)?;
+            if stored {
+                notify(ChangeKind::Set, &key, &tags);
+            }
+            encode(&stored)
+        }
+        StorageCall::SetAlias(alias, key) => encode(&storage.set_alias(&alias, &key)?),
+        StorageCall::ResolveAlias(alias) => encode(&storage.resolve_alias(&alias)?),
+        StorageCall::RemoveAlias(alias) => encode(&storage.remove_alias(&alias)?),
+        StorageCall::Aliases(key) => encode(&storage.aliases(&key)?),
+        StorageCall::Keys => encode(&storage.keys()?),
+        StorageCall::PeekKey(last) => encode(&storage.peek_key(last)?),
+        StorageCall::KeyPage(cursor, reverse, limit) => {
+            encode(&storage.key_page(cursor, reverse, limit as usize)?)
+        }
+        StorageCall::PeekKeyInRange(start, end, last) => {
+            encode(&storage.peek_key_in_range(&start, &end, last)?)
+        }
+        StorageCall::Clear => {
+            storage.clear()?;
+            notify(ChangeKind::Clear, "", &[]);
+            encode(&())
+        }
+        StorageCall::Vacuum => {
+            let report = storage.vacuum()?;
+            let counts: VacuumCounts = (
+                report.orphans_removed,
+                report.orphans_adopted,
+                report.temp_files_removed,
+                report.files_truncated,
+                report.bytes_reclaimed,
+            );
+            encode(&counts)
+        }
+        StorageCall::WriteDataFile(filename, data) => {
+            encode(&storage.write_data_file(&filename, &data)?)
+        }
+        StorageCall::ReadDataFile(filename) => encode(&storage.read_data_file(&filename)?),
+        StorageCall::Size => encode(
+            &storage
+                .size()
+                .map(|sizes| (sizes.inline, sizes.packed, sizes.files)),
+        ),
+    }
+}
+
+/// `entry` with a value kept in a data file read into it, as clients have
+/// no access to the server's files
+fn inline(
+    storage: &dyn StorageBackend,
+    entry: Option<CacheEntry>,
+) -> CacheResult<Option<CacheEntry>> {
+    let Some(mut entry) = entry else {
+        return Ok(None);
+    };
+    if let StorageMode::File(filename) = &entry.storage {
+        entry.storage = StorageMode::Inline(storage.read_data_file(filename)?);
+    }
+    Ok(Some(entry))
+}
+
+/// Payload of a reply from a [`CacheServer`], or the error it reports
+fn decode_reply(reply: Vec<u8>) -> CacheResult<Vec<u8>> {
+    match reply.split_first() {
+        Some((&STATUS_OK, payload)) => Ok(payload.to_vec()),
+        Some((&STATUS_ERROR, message)) => Err(CacheError::Io(io::Error::other(format!(
+            "cache server: {}",
+            String::from_utf8_lossy(message)
+        )))),
+        Some((&STATUS_KEY_NOT_FOUND, key)) => Err(CacheError::KeyNotFound(
+            String::from_utf8_lossy(key).into_owned(),
+        )),
+        _ => Err(CacheError::Corruption(
+            "malformed reply from the cache server".into(),
+        )),
+    }
+}
+
+fn exchange(stream: &mut UnixStream, request: &[u8]) -> io::Result<Vec<u8>> {
+    write_frame(stream, request)?;
+    read_frame(stream)?.ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+}
+
+/// Connection to a [`CacheServer`]
+pub struct CacheClient {
+    stream: Mutex<UnixStream>,
+}
+
+impl CacheClient {
+    pub fn connect(socket: &Path) -> CacheResult<Self> {
+        Ok(Self {
+            stream: Mutex::new(UnixStream::connect(socket)?),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> CacheResult<Option<Vec<u8>>> {
+        let mut request = vec![OP_GET];
+        put_bytes(&mut request, key.as_bytes());
+        let reply = self.exchange(&request)?;
+        match reply.split_first() {
+            Some((&1, value)) => Ok(Some(value.to_vec())),
+            _ => Ok(None),
+        }
+    }
+
+    /// Store `value` under `key`; `expire_time` is in seconds since the epoch
+    pub fn set(
+        &self,
+        key: &str,
+        value: &[u8],
+        expire_time: Option<u64>,
+        tags: &[String],
+    ) -> CacheResult<()> {
+        let mut request = vec![OP_SET];
+        put_bytes(&mut request, key.as_bytes());
+        put_bytes(&mut request, value);
+        put_bytes(&mut request, &expire_time.unwrap_or(0).to_le_bytes());
+        for tag in tags {
+            put_bytes(&mut request, tag.as_bytes());
+        }
+        self.exchange(&request)?;
+        Ok(())
+    }
+
+    pub fn delete(&self, key: &str) -> CacheResult<bool> {
+        let mut request = vec![OP_DELETE];
+        put_bytes(&mut request, key.as_bytes());
+        Ok(self.exchange(&request)?.first() == Some(&1))
+    }
+
+    fn exchange(&self, request: &[u8]) -> CacheResult<Vec<u8>> {
+        decode_reply(exchange(&mut self.stream.lock(), request)?)
+    }
+}
+
+/// Take the entry lock on `key` from the server listening on `socket`,
+/// returning the connection holding it; dropping it releases the lock
+pub(crate) fn lock_entry(socket: &Path, key: &str) -> CacheResult<UnixStream> {
+    let mut stream = UnixStream::connect(socket)?;
+    let mut request = vec![OP_LOCK];
+    put_bytes(&mut request, key.as_bytes());
+    decode_reply(exchange(&mut stream, &request)?)?;
+    Ok(stream)
+}
+
+/// Storage backend kept by a [`CacheServer`], reached over its socket
+///
+/// Threads each take a connection of their own from a pool, so concurrent
+/// calls do not queue behind each other.
+pub struct ServerStorage {
+    socket: PathBuf,
+    /// Connections not in use by any thread
+    idle: Mutex<Vec<UnixStream>>,
+    /// Names this client to the server, so its change stream leaves out
+    /// what the client changed itself
+    origin: AtomicU64,
+    fork: ForkCheck,
+}
+
+impl ServerStorage {
+    /// Attach to the server listening on `socket`
+    pub fn connect(socket: &Path) -> CacheResult<Self> {
+        let stream = UnixStream::connect(socket).map_err(|e| {
+            CacheError::Io(io::Error::new(
+                e.kind(),
+                format!("no cache server listening on {:?}: {}", socket, e),
+            ))
+        })?;
+        Ok(Self {
+            socket: socket.to_path_buf(),
+            idle: Mutex::new(vec![stream]),
+            origin: AtomicU64::new(new_origin()),
+            fork: ForkCheck::new(),
+        })
+    }
+
+    /// The changes other clients and the server's own process make
+    pub(crate) fn change_feed(&self) -> CacheResult<Box<dyn ChangeFeed>> {
+        let mut feed = ServerFeed {
+            socket: self.socket.clone(),
+            origin: self.origin.load(Ordering::Relaxed),
+            stream: None,
+            dropped: false,
+        };
+        feed.subscribe()?;
+        Ok(Box::new(feed))
+    }
+
+    fn call<T: DeserializeOwned>(&self, call: &StorageCall) -> CacheResult<T> {
+        let mut request = vec![OP_STORAGE];
+        request.extend_from_slice(&self.origin.load(Ordering::Relaxed).to_le_bytes());
+        request.extend(
+            bincode::serde::encode_to_vec(call, bincode::config::standard())
+                .map_err(|e| CacheError::Serialization(format!("storage call: {}", e)))?,
+        );
+        let reply = self.send(&request)?;
+        let (result, _) = bincode::serde::decode_from_slice(&reply, bincode::config::standard())
+            .map_err(|e| CacheError::Deserialization(format!("storage result: {}", e)))?;
+        Ok(result)
+    }
+
+    /// Send `request` on an idle connection, or a new one if there is none
+    /// or the server hung up on it, and return the reply payload
+    fn send(&self, request: &[u8]) -> CacheResult<Vec<u8>> {
+        let idle = self.idle.lock().pop();
+        let reply = match idle {
+            Some(mut stream) => match exchange(&mut stream, request) {
+                Ok(reply) => Some((stream, reply)),
+                Err(e) => {
+                    // Such as a connection left from before the server restarted
+                    tracing::debug!("Lost a cache server connection: {}", e);
+                    None
+                }
+            },
+            None => None,
+        };
+        let (stream, reply) = match reply {
+            Some(reply) => reply,
+            None => {
+                let mut stream = UnixStream::connect(&self.socket)?;
+                let reply = exchange(&mut stream, request)?;
+                (stream, reply)
+            }
+        };
+        self.idle.lock().push(stream);
+        decode_reply(reply)
+    }
+}
+
+impl StorageBackend for ServerStorage {
+    fn get(&self, key: &str) -> CacheResult<Option<CacheEntry>> {
+        self.call(&StorageCall::Get(key.to_string()))
+    }
+
+    fn get_many(&self, keys: &[String]) -> CacheResult<Vec<Option<CacheEntry>>> {
+        self.call(&StorageCall::GetMany(keys.to_vec()))
+    }
+
+    fn set(&self, key: &str, entry: CacheEntry) -> CacheResult<()> {
+        self.call(&StorageCall::Set(key.to_string(), entry))
+    }
+
+    fn set_batch(&self, entries: Vec<(String, Vec<u8>)>) -> CacheResult<()> {
+        self.call(&StorageCall::SetBatch(entries))
+    }
+
+    fn delete(&self, key: &str) -> CacheResult<bool> {
+        self.call(&StorageCall::Delete(key.to_string()))
+    }
+
+    fn evict(&self, key: &str) -> CacheResult<bool> {
+        self.call(&StorageCall::Evict(key.to_string()))
+    }
+
+    fn exists(&self, key: &str) -> CacheResult<bool> {
+        self.call(&StorageCall::Exists(key.to_string()))
+    }
+
+    fn exists_local(&self, key: &str) -> CacheResult<bool> {
+        self.call(&StorageCall::ExistsLocal(key.to_string()))
+    }
+
+    fn version(&self, key: &str) -> CacheResult<Option<u64>> {
+        self.call(&StorageCall::Version(key.to_string()))
+    }
+
+    fn compare_and_set(
+        &self,
+        key: &str,
+        expected: Option<u64>,
+        entry: CacheEntry,
+    ) -> CacheResult<bool> {
+        self.call(&StorageCall::CompareAndSet(
+            key.to_string(),
+            expected,
+            entry,
+        ))
+    }
+
+    fn set_alias(&self, alias: &str, key: &str) -> CacheResult<()> {
+        self.call(&StorageCall::SetAlias(alias.to_string(), key.to_string()))
+    }
+
+    fn resolve_alias(&self, alias: &str) -> CacheResult<Option<String>> {
+        self.call(&StorageCall::ResolveAlias(alias.to_string()))
+    }
+
+    fn remove_alias(&self, alias: &str) -> CacheResult<bool> {
+        self.call(&StorageCall::RemoveAlias(alias.to_string()))
+    }
+
+    fn aliases(&self, key: &str) -> CacheResult<Vec<String>> {
+        self.call(&StorageCall::Aliases(key.to_string()))
+    }
+
+    fn keys(&self) -> CacheResult<Vec<String>> {
+        self.call(&StorageCall::Keys)
+    }
+
+    fn peek_key(&self, last: bool) -> CacheResult<Option<String>> {
+        self.call(&StorageCall::PeekKey(last))
+    }
+
+    fn key_page(
+        &self,
+        cursor: Option<i64>,
+        reverse: bool,
+        limit: usize,
+    ) -> CacheResult<Vec<(i64, String)>> {
+        self.call(&StorageCall::KeyPage(cursor, reverse, limit as u64))
+    }
+
+    fn peek_key_in_range(&self, start: &str, end: &str, last: bool) -> CacheResult<Option<String>> {
+        self.call(&StorageCall::PeekKeyInRange(
+            start.to_string(),
+            end.to_string(),
+            last,
+        ))
+    }
+
+    fn clear(&self) -> CacheResult<()> {
+        self.call(&StorageCall::Clear)
+    }
+
+    fn vacuum(&self) -> CacheResult<VacuumReport> {
+        let (
+            orphans_removed,
+            orphans_adopted,
+            temp_files_removed,
+            files_truncated,
+            bytes_reclaimed,
+        ) = self.call::<VacuumCounts>(&StorageCall::Vacuum)?;
+        Ok(VacuumReport {
+            orphans_removed,
+            orphans_adopted,
+            temp_files_removed,
+            files_truncated,
+            bytes_reclaimed,
+        })
+    }
+
+    fn generate_filename(&self, key: &str) -> String {
+        FileNaming::Blake3.file_name(key)
+    }
+
+    fn write_data_file(&self, filename: &str, data: &[u8]) -> CacheResult<()> {
+        self.call(&StorageCall::WriteDataFile(
+            filename.to_string(),
+            data.to_vec(),
+        ))
+    }
+
+    fn read_data_file(&self, filename: &str) -> CacheResult<Vec<u8>> {
+        self.call(&StorageCall::ReadDataFile(filename.to_string()))
+    }
+
+    /// Connections inherited from the parent stay the parent's, and the
+    /// child names itself anew so it hears about the parent's changes
+    fn after_fork(&self) {
+        if self.fork.forked() {
+            // Closing them here leaves the parent's ends open
+            self.idle.lock().clear();
+            self.origin.store(new_origin(), Ordering::Relaxed);
+        }
+    }
+
+    /// The server's sizes, or none if it cannot be reached
+    fn size(&self) -> Option<TierSizes> {
+        let sizes: Option<(u64, u64, u64)> = self.call(&StorageCall::Size).ok()?;
+        sizes.map(|(inline, packed, files)| TierSizes {
+            inline,
+            packed,
+            files,
+        })
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+fn new_origin() -> Origin {
+    uuid::Uuid::new_v4().as_u64_pair().0
+}
+
+/// The change stream of a [`CacheServer`], reconnected to if it drops
+struct ServerFeed {
+    socket: PathBuf,
+    origin: Origin,
+    stream: Option<UnixStream>,
+    /// Whether a stream dropped, so changes may have been missed
+    dropped: bool,
+}
+
+impl ServerFeed {
+    fn subscribe(&mut self) -> CacheResult<()> {
+        let mut stream = UnixStream::connect(&self.socket)?;
+        let mut request = vec![OP_SUBSCRIBE];
+        request.extend_from_slice(&self.origin.to_le_bytes());
+        decode_reply(exchange(&mut stream, &request)?)?;
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    /// Give up on the stream after `error`; the next call reconnects
+    fn drop_stream(&mut self, error: CacheError) -> CacheError {
+        self.stream = None;
+        self.dropped = true;
+        error
+    }
+}
+
+impl ChangeFeed for ServerFeed {
+    fn next(&mut self, timeout: Duration) -> CacheResult<Vec<Change>> {
+        if self.stream.is_none() {
+            self.subscribe()?;
+        }
+        let mut changes = Vec::new();
+        if std::mem::take(&mut self.dropped) {
+            changes.push(Change::new(ChangeKind::Clear, ""));
+        }
+        let stream = self.stream.as_mut().expect("subscribed above");
+        // Wait for the next frame without reading into it, so a timeout
+        // leaves the stream at a frame boundary
+        stream.set_read_timeout(Some(timeout))?;
+        let ready = match peek(stream) {
+            Ok(_) => true,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) =>
+            {
+                false
+            }
+            Err(e) => return Err(self.drop_stream(e.into())),
+        };
+        stream.set_read_timeout(None)?;
+        if !ready {
+            return Ok(changes);
+        }
+        match read_frame(stream) {
+            Ok(Some(frame)) => {
+                if let Some((&kind, key)) = frame.split_first() {
+                    let key = String::from_utf8_lossy(key);
+                    changes.push(Change::new(ChangeKind::from_id(kind), key));
+                }
+                Ok(changes)
+            }
+            Ok(None) => {
+                Err(self.drop_stream(CacheError::Io(io::Error::other("the cache server hung up"))))
+            }
+            Err(e) => Err(self.drop_stream(e.into())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::CacheConfig;
+    use crate::changes::Topic;
+    use crate::storage::StorageKind;
+    use tempfile::TempDir;
+
+    #[test]
+    fn clients_get_set_and_delete_through_the_server() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = Arc::new(
+            DiskCache::new(CacheConfig {
+                directory: temp_dir.path().to_path_buf(),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let server = CacheServer::start(cache.clone(), None).unwrap();
+        assert!(CacheServer::start(cache.clone(), None).is_err());
+
+        let client = CacheClient::connect(server.socket()).unwrap();
+        assert_eq!(client.get("key").unwrap(), None);
+        client
+            .set("key", b"value", None, &["tag".to_string()])
+            .unwrap();
+        assert_eq!(client.get("key").unwrap().as_deref(), Some(&b"value"[..]));
+        assert_eq!(cache.get("key").unwrap().as_deref(), Some(&b"value"[..]));
+        assert!(client.delete("key").unwrap());
+        assert!(!client.delete("key").unwrap());
+        assert!(client.set("", b"value", None, &[]).is_err());
+
+        assert!(!server.wait(Some(Duration::from_millis(10))));
+        server.close();
+        assert!(server.wait(None));
+        assert!(!server.socket().exists());
+        assert!(client.get("key").is_err());
+        drop(CacheServer::start(cache, None).unwrap());
+    }
+
+    #[test]
+    fn caches_attach_to_the_server_as_their_storage() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = Arc::new(
+            DiskCache::new(CacheConfig {
+                directory: temp_dir.path().to_path_buf(),
+                disk_write_threshold: 16,
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let server = CacheServer::start(cache.clone(), None).unwrap();
+        let client = DiskCache::new(CacheConfig {
+            directory: server.socket().to_path_buf(),
+            backend: StorageKind::Server,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let large = vec![7u8; 4096];
+        client.set("small", b"value", None, vec![]).unwrap();
+        client.set("large", &large, None, vec![]).unwrap();
+        assert_eq!(cache.get("large").unwrap(), Some(large.clone()));
+        assert_eq!(client.get("large").unwrap(), Some(large));
+        cache
+            .set("other", b"set by the server", None, vec![])
+            .unwrap();
+        assert_eq!(
+            client.get("other").unwrap().as_deref(),
+            Some(&b"set by the server"[..])
+        );
+
+        let storage = client.storage().clone();
+        assert_eq!(storage.keys().unwrap().len(), 3);
+        assert_eq!(storage.peek_key(false).unwrap().as_deref(), Some("small"));
+        assert!(matches!(
+            storage.set_alias("alias", "missing"),
+            Err(CacheError::KeyNotFound(key)) if key == "missing"
+        ));
+        assert!(client.delete("small").unwrap());
+        assert!(!cache.exists("small").unwrap());
+
+        // Connections broken by a restart are replaced
+        server.close();
+        assert!(client.get("other").is_err());
+        let _server = CacheServer::start(cache, None).unwrap();
+        assert!(client.exists("other").unwrap());
+    }
+
+    #[test]
+    fn clients_hear_about_changes_through_the_server() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = Arc::new(DiskCache::with_directory(temp_dir.path()).unwrap());
+        let server = CacheServer::start(cache.clone(), None).unwrap();
+        let attach = || {
+            DiskCache::new(CacheConfig {
+                directory: server.socket().to_path_buf(),
+                backend: StorageKind::Server,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let (writer, reader) = (attach(), attach());
+        let subscription = reader.subscribe(Topic::Prefix(String::new())).unwrap();
+        let next = || subscription.recv(Some(Duration::from_secs(10))).unwrap();
+
+        writer.set("key", b"value", None, vec![]).unwrap();
+        assert_eq!(next(), Change::new(ChangeKind::Set, "key"));
+        cache.delete("key").unwrap();
+        assert_eq!(next(), Change::new(ChangeKind::Delete, "key"));
+        // A client hears about its own changes once
+        reader.set("own", b"value", None, vec![]).unwrap();
+        assert_eq!(next(), Change::new(ChangeKind::Set, "own"));
+        writer.clear().unwrap();
+        assert_eq!(next(), Change::new(ChangeKind::Clear, ""));
+        assert_eq!(subscription.recv(Some(Duration::from_millis(300))), None);
+    }
+
+    #[test]
+    fn clients_lock_entries_through_the_server() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = Arc::new(DiskCache::with_directory(temp_dir.path()).unwrap());
+        let server = CacheServer::start(cache.clone(), None).unwrap();
+        let attach = || {
+            DiskCache::new(CacheConfig {
+                directory: server.socket().to_path_buf(),
+                backend: StorageKind::Server,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let (first, second) = (attach(), Arc::new(attach()));
+
+        let lock = first.lock_key("key").unwrap();
+        assert!(cache.lock_key("other").is_ok());
+        let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+        let waiter = {
+            let second = second.clone();
+            std::thread::spawn(move || {
+                let _lock = second.lock_key("key").unwrap();
+                locked_tx.send(()).unwrap();
+            })
+        };
+        assert!(locked_rx.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(lock);
+        locked_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        waiter.join().unwrap();
+
+        // Compare-and-set holds the lock through the server too
+        assert!(first.cas("key", None, b"value", None, vec![]).unwrap());
+        let version = second.version("key").unwrap();
+        assert!(version.is_some());
+        assert!(!second.cas("key", None, b"other", None, vec![]).unwrap());
+        assert!(second.cas("key", version, b"other", None, vec![]).unwrap());
+        assert_eq!(cache.get("key").unwrap().as_deref(), Some(&b"other"[..]));
+    }
+}
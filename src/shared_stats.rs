@@ -0,0 +1,394 @@
+//! Statistics shared by the processes using a cache directory
+//!
+//! Hit, miss and write counters live in each process and start over with
+//! it. To report on a whole deployment, every process opening a directory
+//! writes its counters to a shard under `stats/` every few seconds, and
+//! [`StatsShard::aggregate`] adds up the shards of all of them. A process
+//! closing the cache folds its shard into `stats/retired`, so what it counted
+//! outlives it; so are the shards of processes on this host that exited
+//! without closing. Shards are only read and folded under `stats/.lock`, so a
+//! shard is never counted both on its own and in `retired`.
+
+use crate::error::CacheResult;
+use crate::fork::BackgroundThread;
+use crate::process_lock::{host_name, process_alive, LockBackend, ProcessLock};
+use crate::storage::IoStats;
+use crate::utils::CacheStats;
+use parking_lot::Mutex;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock, Weak};
+use std::time::Duration;
+
+/// Directory under the cache directory holding the shards
+pub const STATS_DIR: &str = "stats";
+const SHARD_EXTENSION: &str = "stats";
+const RETIRED_FILE: &str = "retired";
+const LOCK_FILE: &str = ".lock";
+/// How often open shards are written out
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Counters kept per process and added up across processes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatCounts {
+    pub hits: u64,
+    pub misses: u64,
+    pub sets: u64,
+    pub deletes: u64,
+    pub evictions: u64,
+    pub errors: u64,
+    pub io: IoStats,
+}
+
+impl StatCounts {
+    pub fn new(stats: &CacheStats, io: IoStats) -> Self {
+        Self {
+            hits: stats.hits,
+            misses: stats.misses,
+            sets: stats.sets,
+            deletes: stats.deletes,
+            evictions: stats.evictions,
+            errors: stats.errors,
+            io,
+        }
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.sets += other.sets;
+        self.deletes += other.deletes;
+        self.evictions += other.evictions;
+        self.errors += other.errors;
+        self.io.logical_bytes_written += other.io.logical_bytes_written;
+        self.io.disk_bytes_written += other.io.disk_bytes_written;
+        self.io.files_created += other.io.files_created;
+        self.io.files_deleted += other.io.files_deleted;
+        self.io.fsyncs += other.io.fsyncs;
+    }
+
+    /// Counts made since `base` was taken
+    fn since(&self, base: &Self) -> Self {
+        let mut counts = *self;
+        counts.hits = counts.hits.saturating_sub(base.hits);
+        counts.misses = counts.misses.saturating_sub(base.misses);
+        counts.sets = counts.sets.saturating_sub(base.sets);
+        counts.deletes = counts.deletes.saturating_sub(base.deletes);
+        counts.evictions = counts.evictions.saturating_sub(base.evictions);
+        counts.errors = counts.errors.saturating_sub(base.errors);
+        let io = &mut counts.io;
+        io.logical_bytes_written = io
+            .logical_bytes_written
+            .saturating_sub(base.io.logical_bytes_written);
+        io.disk_bytes_written = io
+            .disk_bytes_written
+            .saturating_sub(base.io.disk_bytes_written);
+        io.files_created = io.files_created.saturating_sub(base.io.files_created);
+        io.files_deleted = io.files_deleted.saturating_sub(base.io.files_deleted);
+        io.fsyncs = io.fsyncs.saturating_sub(base.io.fsyncs);
+        counts
+    }
+
+    fn fields(&self) -> [(&'static str, u64); 11] {
+        [
+            ("hits", self.hits),
+            ("misses", self.misses),
+            ("sets", self.sets),
+            ("deletes", self.deletes),
+            ("evictions", self.evictions),
+            ("errors", self.errors),
+            ("logical_bytes_written", self.io.logical_bytes_written),
+            ("disk_bytes_written", self.io.disk_bytes_written),
+            ("files_created", self.io.files_created),
+            ("files_deleted", self.io.files_deleted),
+            ("fsyncs", self.io.fsyncs),
+        ]
+    }
+
+    fn set(&mut self, name: &str, value: u64) {
+        match name {
+            "hits" => self.hits = value,
+            "misses" => self.misses = value,
+            "sets" => self.sets = value,
+            "deletes" => self.deletes = value,
+            "evictions" => self.evictions = value,
+            "errors" => self.errors = value,
+            "logical_bytes_written" => self.io.logical_bytes_written = value,
+            "disk_bytes_written" => self.io.disk_bytes_written = value,
+            "files_created" => self.io.files_created = value,
+            "files_deleted" => self.io.files_deleted = value,
+            "fsyncs" => self.io.fsyncs = value,
+            // Written by a newer version
+            _ => {}
+        }
+    }
+}
+
+/// Counters added up over the processes using a directory, as returned by
+/// [`DiskCache::aggregate_stats`](crate::DiskCache::aggregate_stats)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AggregateStats {
+    pub counts: StatCounts,
+    /// Processes with a shard still open, this one included
+    pub processes: u64,
+}
+
+/// Pid and host of the process a shard belongs to
+type Owner = (u32, String);
+
+/// Reads the current counters of an open cache, or `None` once it is gone
+pub type CountSource = Box<dyn Fn() -> Option<StatCounts> + Send + Sync>;
+
+/// This process's shard of a directory's statistics
+pub struct StatsShard {
+    directory: PathBuf,
+    path: PathBuf,
+    lock_backend: LockBackend,
+    source: CountSource,
+    /// Counts the source started from, made by the parent of a forked child
+    base: StatCounts,
+    /// Process that opened it; a forked child opens its own
+    pid: u32,
+}
+
+impl StatsShard {
+    /// Start writing the counters `source` reads, less `base`, to a new
+    /// shard in `cache_directory`
+    pub fn open(
+        cache_directory: &Path,
+        lock_backend: LockBackend,
+        source: CountSource,
+        base: StatCounts,
+    ) -> CacheResult<Arc<Self>> {
+        let directory = cache_directory.join(STATS_DIR);
+        std::fs::create_dir_all(&directory)?;
+        let shard = Arc::new(Self {
+            path: directory.join(format!(
+                "{}.{}",
+                uuid::Uuid::new_v4().simple(),
+                SHARD_EXTENSION
+            )),
+            directory,
+            lock_backend,
+            source,
+            base,
+            pid: std::process::id(),
+        });
+        open_shards().lock().push(Arc::downgrade(&shard));
+        Ok(shard)
+    }
+
+    /// Counters of every process using the directory, with `own` standing
+    /// in for this shard
+    pub fn aggregate(&self, own: StatCounts) -> CacheResult<AggregateStats> {
+        let _lock = self.lock()?;
+        self.fold_exited()?;
+        let mut total = read_counts(&self.directory.join(RETIRED_FILE))?
+            .map(|(counts, _)| counts)
+            .unwrap_or_default();
+        total.add(&own.since(&self.base));
+        let mut processes = 1;
+        for (path, counts, _) in self.shards()? {
+            if path != self.path {
+                total.add(&counts);
+                processes += 1;
+            }
+        }
+        Ok(AggregateStats {
+            counts: total,
+            processes,
+        })
+    }
+
+    /// Fold the final counters into `retired` and remove the shard
+    pub fn retire(&self, last: StatCounts) -> CacheResult<()> {
+        if self.pid != std::process::id() {
+            return Ok(());
+        }
+        open_shards()
+            .lock()
+            .retain(|shard| shard.upgrade().is_some_and(|shard| shard.path != self.path));
+        let _lock = self.lock()?;
+        self.fold(&self.path, &last.since(&self.base))
+    }
+
+    /// Write the current counters out, unless the cache is gone
+    fn flush(&self) -> CacheResult<()> {
+        if let Some(counts) = (self.source)() {
+            write_counts(&self.path, &counts.since(&self.base))?;
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> CacheResult<ProcessLock> {
+        ProcessLock::acquire(self.lock_backend, &self.directory.join(LOCK_FILE))
+    }
+
+    /// Add `counts` to `retired` and remove the shard at `path`; called
+    /// under the lock
+    fn fold(&self, path: &Path, counts: &StatCounts) -> CacheResult<()> {
+        let retired = self.directory.join(RETIRED_FILE);
+        let mut total = read_counts(&retired)?
+            .map(|(counts, _)| counts)
+            .unwrap_or_default();
+        total.add(counts);
+        write_counts(&retired, &total)?;
+        match std::fs::remove_file(path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Fold the shards of processes on this host that exited without
+    /// closing the cache; called under the lock
+    fn fold_exited(&self) -> CacheResult<()> {
+        let host = host_name();
+        for (path, counts, owner) in self.shards()? {
+            if owner.is_some_and(|(pid, owner_host)| owner_host == host && !process_alive(pid)) {
+                self.fold(&path, &counts)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Every shard in the directory, with its counters and owner
+    fn shards(&self) -> CacheResult<Vec<(PathBuf, StatCounts, Option<Owner>)>> {
+        let mut shards = Vec::new();
+        for entry in std::fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if path.extension() != Some(SHARD_EXTENSION.as_ref()) {
+                continue;
+            }
+            if let Some((counts, owner)) = read_counts(&path)? {
+                shards.push((path, counts, owner));
+            }
+        }
+        Ok(shards)
+    }
+}
+
+/// Counters in a shard or `retired`, with the shard's owning pid and host
+fn read_counts(path: &Path) -> CacheResult<Option<(StatCounts, Option<Owner>)>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut counts = StatCounts::default();
+    let (mut pid, mut host) = (None, None);
+    for line in contents.lines() {
+        let Some((name, value)) = line.split_once(' ') else {
+            continue;
+        };
+        match name {
+            "pid" => pid = value.parse().ok(),
+            "host" => host = Some(value.to_string()),
+            _ => counts.set(name, value.parse().unwrap_or(0)),
+        }
+    }
+    Ok(Some((counts, pid.zip(host))))
+}
+
+/// Write counters with this process as their owner, replacing the file by
+/// rename so readers never see half of it
+fn write_counts(path: &Path, counts: &StatCounts) -> CacheResult<()> {
+    let mut contents = format!("pid {}\nhost {}\n", std::process::id(), host_name());
+    for (name, value) in counts.fields() {
+        contents.push_str(&format!("{} {}\n", name, value));
+    }
+    let staged = path.with_extension("tmp");
+    std::fs::write(&staged, contents)?;
+    std::fs::rename(&staged, path)?;
+    Ok(())
+}
+
+/// Shards open in this process, written out by the flush thread
+fn open_shards() -> &'static Mutex<Vec<Weak<StatsShard>>> {
+    static OPEN: OnceLock<Mutex<Vec<Weak<StatsShard>>>> = OnceLock::new();
+    static FLUSH_THREAD: BackgroundThread = BackgroundThread::new();
+    let open = OPEN.get_or_init(Default::default);
+    // A forked child's caches open shards of their own
+    FLUSH_THREAD.ensure("stats-flush", || open.lock().clear(), flush_open);
+    open
+}
+
+fn flush_open() {
+    loop {
+        std::thread::sleep(FLUSH_INTERVAL);
+        let open: Vec<_> = {
+            let mut open = open_shards().lock();
+            open.retain(|shard| shard.strong_count() > 0);
+            open.iter().filter_map(Weak::upgrade).collect()
+        };
+        for shard in open {
+            if let Err(e) = shard.flush() {
+                tracing::warn!("Failed to write statistics to {:?}: {}", shard.path, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    fn shard(directory: &Path, hits: u64) -> (Arc<StatsShard>, Arc<AtomicU64>) {
+        let count = Arc::new(AtomicU64::new(hits));
+        let source = {
+            let count = count.clone();
+            Box::new(move || {
+                Some(StatCounts {
+                    hits: count.load(Ordering::Relaxed),
+                    ..Default::default()
+                })
+            })
+        };
+        let shard =
+            StatsShard::open(directory, LockBackend::File, source, StatCounts::default()).unwrap();
+        (shard, count)
+    }
+
+    #[test]
+    fn shards_add_up_and_outlive_their_process() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let (first, _) = shard(temp_dir.path(), 3);
+        let (second, _) = shard(temp_dir.path(), 4);
+        second.flush().unwrap();
+
+        let own = StatCounts {
+            hits: 3,
+            ..Default::default()
+        };
+        let aggregate = first.aggregate(own).unwrap();
+        assert_eq!(aggregate.counts.hits, 7);
+        assert_eq!(aggregate.processes, 2);
+
+        second
+            .retire(StatCounts {
+                hits: 5,
+                ..Default::default()
+            })
+            .unwrap();
+        let aggregate = first.aggregate(own).unwrap();
+        assert_eq!(aggregate.counts.hits, 8);
+        assert_eq!(aggregate.processes, 1);
+
+        // Left behind by a process on this host that has since exited
+        let mut child = std::process::Command::new(std::env::current_exe().unwrap())
+            .arg("--list")
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        std::fs::write(
+            temp_dir.path().join(STATS_DIR).join("dead.stats"),
+            format!("pid {}\nhost {}\nhits 2\n", pid, host_name()),
+        )
+        .unwrap();
+        let aggregate = first.aggregate(own).unwrap();
+        assert_eq!(aggregate.counts.hits, 10);
+        assert_eq!(aggregate.processes, 1);
+    }
+}
@@ -0,0 +1,181 @@
+use parking_lot::{Condvar, Mutex};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Coalesces concurrent loads of the same key within one process
+///
+/// The first caller to miss on a key runs its loader; callers that miss while
+/// that load is in flight wait for it and share the value. A failed load is
+/// not shared: its waiters start over, and one of them runs its own loader.
+pub struct SingleFlight<T> {
+    flights: Mutex<HashMap<String, Arc<Flight<T>>>>,
+}
+
+struct Flight<T> {
+    landing: Mutex<Landing<T>>,
+    landed: Condvar,
+}
+
+enum Landing<T> {
+    InFlight,
+    Loaded(T),
+    Failed,
+}
+
+impl<T: Clone> SingleFlight<T> {
+    pub fn new() -> Self {
+        Self {
+            flights: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Return `lookup()`'s value, or run `load` once across concurrent misses on `key`
+    pub fn load<E>(
+        &self,
+        key: &str,
+        mut lookup: impl FnMut() -> Result<Option<T>, E>,
+        load: impl FnOnce() -> Result<T, E>,
+    ) -> Result<T, E> {
+        loop {
+            if let Some(value) = lookup()? {
+                return Ok(value);
+            }
+
+            let joined = {
+                let mut flights = self.flights.lock();
+                match flights.get(key) {
+                    Some(flight) => Some(flight.clone()),
+                    None => {
+                        flights.insert(
+                            key.to_string(),
+                            Arc::new(Flight {
+                                landing: Mutex::new(Landing::InFlight),
+                                landed: Condvar::new(),
+                            }),
+                        );
+                        None
+                    }
+                }
+            };
+
+            if let Some(flight) = joined {
+                if let Some(value) = flight.wait() {
+                    return Ok(value);
+                }
+                continue;
+            }
+
+            let mut lead = Lead {
+                flights: self,
+                key,
+                value: None,
+            };
+            // The previous flight may have landed between our lookup and takeoff
+            if let Some(value) = lookup()? {
+                lead.value = Some(value.clone());
+                return Ok(value);
+            }
+            let value = load()?;
+            lead.value = Some(value.clone());
+            return Ok(value);
+        }
+    }
+}
+
+impl<T: Clone> Flight<T> {
+    fn wait(&self) -> Option<T> {
+        let mut landing = self.landing.lock();
+        loop {
+            match &*landing {
+                Landing::InFlight => self.landed.wait(&mut landing),
+                Landing::Loaded(value) => return Some(value.clone()),
+                Landing::Failed => return None,
+            }
+        }
+    }
+}
+
+/// The running load of one key; lands its flight when dropped, even on error or panic
+struct Lead<'a, T> {
+    flights: &'a SingleFlight<T>,
+    key: &'a str,
+    value: Option<T>,
+}
+
+impl<T> Drop for Lead<'_, T> {
+    fn drop(&mut self) {
+        let Some(flight) = self.flights.flights.lock().remove(self.key) else {
+            return;
+        };
+        *flight.landing.lock() = match self.value.take() {
+            Some(value) => Landing::Loaded(value),
+            None => Landing::Failed,
+        };
+        flight.landed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+    use std::time::Duration;
+
+    #[test]
+    fn concurrent_misses_share_one_load() {
+        let flights = Arc::new(SingleFlight::<u32>::new());
+        let loads = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(8));
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let (flights, loads, barrier) = (flights.clone(), loads.clone(), barrier.clone());
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    flights.load(
+                        "key",
+                        || Ok::<_, ()>(None),
+                        || {
+                            loads.fetch_add(1, Ordering::SeqCst);
+                            std::thread::sleep(Duration::from_millis(100));
+                            Ok(42)
+                        },
+                    )
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), Ok(42));
+        }
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        assert!(flights.flights.lock().is_empty());
+    }
+
+    #[test]
+    fn failed_load_lets_a_waiter_retry() {
+        let flights = Arc::new(SingleFlight::<u32>::new());
+        let started = Arc::new(Barrier::new(2));
+
+        let leader = {
+            let (flights, started) = (flights.clone(), started.clone());
+            std::thread::spawn(move || {
+                flights.load(
+                    "key",
+                    || Ok(None),
+                    || {
+                        started.wait();
+                        std::thread::sleep(Duration::from_millis(50));
+                        Err("unavailable")
+                    },
+                )
+            })
+        };
+        started.wait();
+        let retried = flights.load("key", || Ok(None), || Ok::<_, &str>(7));
+
+        assert_eq!(leader.join().unwrap(), Err("unavailable"));
+        assert_eq!(retried, Ok(7));
+    }
+}
@@ -1,26 +1,715 @@
-use crate::error::CacheResult;
+use crate::error::{CacheError, CacheResult, ConfigIssue};
 use crate::serialization::CacheEntry;
+use std::str::FromStr;
 
-// Only the optimized storage backend
+pub mod compression;
+pub mod data_file;
+pub(crate) mod events;
+mod fd_cache;
+pub mod frames;
+pub(crate) mod key_locks;
+pub mod lease;
+pub mod memory_backend;
 pub mod optimized_backend;
+pub mod redb_backend;
+pub mod remote;
+pub mod ring_backend;
+pub mod segment;
+pub mod sharing;
+pub mod sqlite_backend;
+pub mod unlink;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+pub mod wal;
+mod watcher;
+#[cfg(unix)]
+pub mod writer;
 
+#[cfg(any(test, feature = "conformance"))]
+#[cfg_attr(not(feature = "conformance"), allow(dead_code))]
+pub mod conformance;
+#[cfg(test)]
+mod tests;
+#[cfg(test)]
+mod tier_invariants;
+
+pub use compression::Compression;
+pub use data_file::DataFileWriter;
+pub use frames::FramedReader;
+pub use memory_backend::MemoryStorage;
 pub use optimized_backend::OptimizedStorage;
+pub use redb_backend::RedbStorage;
+#[cfg(feature = "s3")]
+pub use remote::S3Tier;
+pub use remote::{DirectoryTier, RemoteTier};
+pub use ring_backend::RingStorage;
+pub use sharing::SharingRetry;
+pub use sqlite_backend::SqliteStorage;
+pub use unlink::{UnlinkPool, UnlinkProgress};
+pub use wal::WalSyncPolicy;
+
+/// Which [`StorageBackend`] a cache opens in its directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StorageKind {
+    /// [`OptimizedStorage`], this crate's own index and data file layout
+    #[default]
+    Optimized,
+    /// [`SqliteStorage`], python-diskcache's `cache.db`, shared live with diskcache processes
+    Sqlite,
+    /// [`RedbStorage`], a single embedded database file suited to many tiny entries
+    Redb,
+    /// [`MemoryStorage`], entries kept in process memory; the directory is never touched
+    Memory,
+    /// [`RingStorage`], one preallocated file whose oldest entries are overwritten once full
+    Ring,
+    /// [`ServerStorage`](crate::server::ServerStorage), entries kept by a cache server; the
+    /// directory is the server's socket
+    Server,
+}
+
+impl StorageKind {
+    /// Name the backend is given by, as parsed by [`FromStr`]
+    pub fn name(self) -> &'static str {
+        match self {
+            StorageKind::Optimized => "optimized",
+            StorageKind::Sqlite => "sqlite",
+            StorageKind::Redb => "redb",
+            StorageKind::Memory => "memory",
+            StorageKind::Ring => "ring",
+            StorageKind::Server => "server",
+        }
+    }
+
+    /// Whether the cache keeps files in its directory, which memory caches
+    /// and clients of a cache server do not
+    pub fn uses_directory(self) -> bool {
+        !matches!(self, StorageKind::Memory | StorageKind::Server)
+    }
+}
+
+impl FromStr for StorageKind {
+    type Err = CacheError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "optimized" => Ok(Self::Optimized),
+            "sqlite" => Ok(Self::Sqlite),
+            "redb" => Ok(Self::Redb),
+            "memory" => Ok(Self::Memory),
+            "ring" => Ok(Self::Ring),
+            "server" => Ok(Self::Server),
+            other => Err(CacheError::Config(ConfigIssue::new(
+                "backend",
+                format!("unknown storage backend {:?}", other),
+                "use \"optimized\", \"sqlite\", \"redb\", \"memory\", \"ring\" or \"server\"",
+            ))),
+        }
+    }
+}
+
+/// How far a write must get before it is acknowledged
+///
+/// Levels are ordered from fastest to safest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Durability {
+    /// Data files are queued to a background writer and the index is not
+    /// synced, so a crash can lose recent writes; for scratch caches
+    Relaxed,
+    /// Every write reaches the OS before it returns, so it survives the
+    /// process crashing but not the machine losing power
+    #[default]
+    Flush,
+    /// Data files are fsynced before the index points at them and the index
+    /// commits with SQLite's `synchronous=FULL`
+    Fsync,
+    /// As [`Durability::Fsync`], and the directory holding each new file is
+    /// fsynced too, so the file's name survives power loss as well as its bytes
+    FsyncDir,
+}
+
+impl Durability {
+    /// SQLite `synchronous` setting that matches this level
+    pub fn sqlite_synchronous(self) -> &'static str {
+        match self {
+            Durability::Relaxed => "OFF",
+            Durability::Flush => "NORMAL",
+            Durability::Fsync | Durability::FsyncDir => "FULL",
+        }
+    }
+}
+
+impl FromStr for Durability {
+    type Err = CacheError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "relaxed" => Ok(Self::Relaxed),
+            "flush" => Ok(Self::Flush),
+            "fsync" => Ok(Self::Fsync),
+            "fsync+dirsync" => Ok(Self::FsyncDir),
+            other => Err(CacheError::Config(ConfigIssue::new(
+                "durability",
+                format!("unknown durability level {:?}", other),
+                "use \"relaxed\", \"flush\", \"fsync\" or \"fsync+dirsync\"",
+            ))),
+        }
+    }
+}
+
+/// What a write does when the optimized backend's write queue is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QueueFullPolicy {
+    /// Wait for the background writer to make room
+    #[default]
+    Block,
+    /// Write the data file on the calling thread instead, unless an earlier
+    /// write to the same file is still queued, in which case wait
+    Spill,
+    /// Fail with [`CacheError::QueueFull`]
+    Error,
+}
+
+impl FromStr for QueueFullPolicy {
+    type Err = CacheError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(Self::Block),
+            "spill" => Ok(Self::Spill),
+            "error" => Ok(Self::Error),
+            other => Err(CacheError::Config(ConfigIssue::new(
+                "queue_full",
+                format!("unknown queue full policy {:?}", other),
+                "use \"block\", \"spill\" or \"error\"",
+            ))),
+        }
+    }
+}
+
+/// Which entries the optimized backend's hot tier drops once it holds more
+/// than `hot_cache_size`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HotCachePolicy {
+    /// The least recently used; every hit takes a tick of a shared counter
+    #[default]
+    Lru,
+    /// CLOCK: a sweep drops entries not used since it last passed them, an
+    /// approximation of LRU whose hits only set a flag, once per sweep
+    Clock,
+}
+
+impl FromStr for HotCachePolicy {
+    type Err = CacheError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lru" => Ok(Self::Lru),
+            "clock" => Ok(Self::Clock),
+            other => Err(CacheError::Config(ConfigIssue::new(
+                "hot_cache_policy",
+                format!("unknown hot cache policy {:?}", other),
+                "use \"lru\" or \"clock\"",
+            ))),
+        }
+    }
+}
+
+/// How the optimized backend names a key's data file
+///
+/// Every scheme ends in a hash of the key, which the fan-out directories are
+/// taken from; files keep the name they were written under when the scheme
+/// changes, so a cache can switch schemes between opens.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FileNaming {
+    /// The full 64-digit BLAKE3 hash of the key
+    #[default]
+    Blake3,
+    /// The 32-digit XXH3-128 hash of the key, cheaper to compute and shorter
+    Xxh3,
+    /// A readable slug of the key followed by 32 digits of its BLAKE3 hash,
+    /// so files can be matched to keys by eye
+    Slug,
+}
+
+/// Most characters of the key kept in a [`FileNaming::Slug`] name
+const SLUG_LEN: usize = 64;
+
+impl FileNaming {
+    /// Every scheme, for recognising files written under another one
+    pub const ALL: [FileNaming; 3] = [FileNaming::Blake3, FileNaming::Xxh3, FileNaming::Slug];
+
+    /// Name the scheme is given by, as parsed by [`FromStr`]
+    pub fn name(self) -> &'static str {
+        match self {
+            FileNaming::Blake3 => "blake3",
+            FileNaming::Xxh3 => "xxh3",
+            FileNaming::Slug => "slug",
+        }
+    }
+
+    /// Data file name for `key` under this scheme
+    pub fn file_name(self, key: &str) -> String {
+        match self {
+            FileNaming::Blake3 => format!("{}.dat", blake3::hash(key.as_bytes()).to_hex()),
+            FileNaming::Xxh3 => format!("{:032x}.dat", xxhash_rust::xxh3::xxh3_128(key.as_bytes())),
+            FileNaming::Slug => {
+                // Dots are replaced too: the hash is what follows the last one
+                let slug: String = key
+                    .chars()
+                    .take(SLUG_LEN)
+                    .map(|c| match c {
+                        'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+                        _ => '_',
+                    })
+                    .collect();
+                format!(
+                    "{}.{}.dat",
+                    if slug.is_empty() { "_" } else { &slug },
+                    &blake3::hash(key.as_bytes()).to_hex()[..32]
+                )
+            }
+        }
+    }
+
+    /// The hash digits of a data file name written under any scheme
+    pub fn hash_digits(file_name: &str) -> &str {
+        let stem = file_name.strip_suffix(".dat").unwrap_or(file_name);
+        stem.rsplit('.').next().unwrap_or(stem)
+    }
+}
+
+impl FromStr for FileNaming {
+    type Err = CacheError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blake3" => Ok(Self::Blake3),
+            "xxh3" => Ok(Self::Xxh3),
+            "slug" => Ok(Self::Slug),
+            other => Err(CacheError::Config(ConfigIssue::new(
+                "file_naming",
+                format!("unknown file naming scheme {:?}", other),
+                "use \"blake3\", \"xxh3\" or \"slug\"",
+            ))),
+        }
+    }
+}
+
+/// Secret the optimized backend signs its index rows with
+///
+/// Every row carries a keyed BLAKE3 MAC over its key and stored bytes, so a
+/// process that shares the directory without the secret cannot make a key
+/// resolve to bytes the cache did not write. Only the derived key is kept.
+#[derive(Clone, PartialEq, Eq)]
+pub struct IndexKey([u8; 32]);
+
+impl IndexKey {
+    /// Derive the signing key from a secret of any non-zero length
+    pub fn new(secret: &[u8]) -> CacheResult<Self> {
+        if secret.is_empty() {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "index_key",
+                "an empty secret signs nothing",
+                "pass the secret shared by every process using the directory",
+            )));
+        }
+        Ok(Self(blake3::derive_key(
+            "diskcache_rs index row signature v1",
+            secret,
+        )))
+    }
+
+    /// Hasher for the MAC of `key`'s stored bytes, which the caller feeds in
+    pub fn hasher(&self, key: &str, compression: Compression) -> blake3::Hasher {
+        let mut hasher = blake3::Hasher::new_keyed(&self.0);
+        hasher.update(&(key.len() as u64).to_le_bytes());
+        hasher.update(key.as_bytes());
+        hasher.update(&[compression.id()]);
+        hasher
+    }
+
+    /// MAC of `key`'s stored bytes
+    pub fn sign(&self, key: &str, compression: Compression, stored: &[u8]) -> [u8; 32] {
+        *self
+            .hasher(key, compression)
+            .update(stored)
+            .finalize()
+            .as_bytes()
+    }
+
+    /// Whether `mac` is the MAC of `key`'s stored bytes, compared in constant time
+    pub fn verify(
+        &self,
+        key: &str,
+        compression: Compression,
+        stored: &[u8],
+        mac: Option<&[u8]>,
+    ) -> bool {
+        mac.is_some_and(|mac| self.hasher(key, compression).update(stored).finalize() == *mac)
+    }
+}
+
+impl std::fmt::Debug for IndexKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("IndexKey(..)")
+    }
+}
+
+fn streaming_unsupported() -> CacheError {
+    CacheError::Config(ConfigIssue::new(
+        "backend",
+        "this storage backend cannot stream values",
+        "use the optimized backend",
+    ))
+}
+
+/// Called with the key of each entry a backend drops on finding it expired
+pub type ExpiryListener = std::sync::Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Asked before a backend starts background maintenance; false skips it
+pub type MaintenanceGate = std::sync::Arc<dyn Fn() -> bool + Send + Sync>;
+
+/// Asked before a backend drops an entry a read found expired or unreadable;
+/// false leaves the entry for the process that may write to drop
+pub type CleanupGate = std::sync::Arc<dyn Fn() -> bool + Send + Sync>;
 
 /// Storage backend trait
 pub trait StorageBackend: Send + Sync {
     fn get(&self, key: &str) -> CacheResult<Option<CacheEntry>>;
+    /// Read `key` like [`get`](Self::get) without it counting as a use, so
+    /// tiers kept in recency order stay as they were
+    fn peek(&self, key: &str) -> CacheResult<Option<CacheEntry>> {
+        self.get(key)
+    }
+    /// Fetch several entries at once; backends may overlap the underlying reads
+    fn get_many(&self, keys: &[String]) -> CacheResult<Vec<Option<CacheEntry>>> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
+    /// Load entries whose keys start with `prefix` into memory ahead of use,
+    /// returning how many were loaded
+    fn prefetch_prefix(&self, _prefix: &str) -> CacheResult<usize> {
+        Ok(0)
+    }
     fn set(&self, key: &str, entry: CacheEntry) -> CacheResult<()>;
     fn set_batch(&self, entries: Vec<(String, Vec<u8>)>) -> CacheResult<()>;
     fn delete(&self, key: &str) -> CacheResult<bool>;
+    /// Remove `key` to make room, returning whether it existed
+    ///
+    /// Backends with a remote tier move the entry there instead of dropping
+    /// it; the default deletes it.
+    fn evict(&self, key: &str) -> CacheResult<bool> {
+        self.delete(key)
+    }
 
     fn exists(&self, key: &str) -> CacheResult<bool>;
+    /// Whether `key` is in the local tiers, leaving out any remote tier the
+    /// backend reads through to; writes count entries by it
+    fn exists_local(&self, key: &str) -> CacheResult<bool> {
+        self.exists(key)
+    }
+
+    /// Opaque stamp that changes whenever `key` is written, or None if it is absent
+    ///
+    /// Equal stamps mean the entry was not rewritten in between. The default
+    /// hashes the stored bytes, so rewriting an identical value keeps the stamp;
+    /// backends that record a per-write stamp override it.
+    fn version(&self, key: &str) -> CacheResult<Option<u64>> {
+        let Some(entry) = self.get(key)? else {
+            return Ok(None);
+        };
+        let hash = match &entry.storage {
+            crate::serialization::StorageMode::Inline(data) => blake3::hash(data),
+            crate::serialization::StorageMode::File(filename) => {
+                blake3::hash(&self.read_data_file(filename)?)
+            }
+        };
+        let mut stamp = [0u8; 8];
+        stamp.copy_from_slice(&hash.as_bytes()[..8]);
+        Ok(Some(u64::from_le_bytes(stamp)))
+    }
+
+    /// Store `entry` under `key` only if its [`version`](Self::version) is still `expected`
+    ///
+    /// `None` expects the key to be absent. Returns whether the entry was
+    /// stored. The comparison and the write are only atomic while the caller
+    /// holds the key's entry lock; backends that forward calls to another
+    /// process override this to do both there.
+    fn compare_and_set(
+        &self,
+        key: &str,
+        expected: Option<u64>,
+        entry: CacheEntry,
+    ) -> CacheResult<bool> {
+        if self.version(key)? != expected {
+            return Ok(false);
+        }
+        self.set(key, entry)?;
+        Ok(true)
+    }
+
+    /// Point `alias` at the stored entry `key`, replacing any previous target
+    ///
+    /// Fails with [`CacheError::KeyNotFound`] if `key` is not stored. Aliases
+    /// survive rewrites of `key` and are dropped together with it on delete.
+    fn set_alias(&self, _alias: &str, _key: &str) -> CacheResult<()> {
+        Err(CacheError::Config(ConfigIssue::new(
+            "backend",
+            "this storage backend does not support key aliases",
+            "use the optimized, sqlite, redb or memory backend",
+        )))
+    }
+    /// Key `alias` points at, if the alias and its entry both exist
+    fn resolve_alias(&self, _alias: &str) -> CacheResult<Option<String>> {
+        Ok(None)
+    }
+    /// Remove `alias`, returning whether it existed; its entry is untouched
+    fn remove_alias(&self, _alias: &str) -> CacheResult<bool> {
+        Ok(false)
+    }
+    /// Aliases pointing at `key`, in no particular order
+    fn aliases(&self, _key: &str) -> CacheResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
     fn keys(&self) -> CacheResult<Vec<String>>;
+    /// Key of the least (`last == false`) or most recently stored entry
+    fn peek_key(&self, last: bool) -> CacheResult<Option<String>>;
+    /// Up to `limit` (sequence number, key) pairs in store order, starting after `cursor`
+    ///
+    /// Pass `None` to start from the oldest (or, with `reverse`, newest) entry
+    /// and the last returned sequence number to continue.
+    fn key_page(
+        &self,
+        cursor: Option<i64>,
+        reverse: bool,
+        limit: usize,
+    ) -> CacheResult<Vec<(i64, String)>>;
+    /// Smallest (`last == false`) or largest key in `[start, end)`, by byte order
+    fn peek_key_in_range(&self, start: &str, end: &str, last: bool) -> CacheResult<Option<String>>;
     fn clear(&self) -> CacheResult<()>;
-    fn vacuum(&self) -> CacheResult<()>;
+    /// [`clear`](Self::clear), reporting data file removal as it goes
+    ///
+    /// Backends without data files of their own just clear.
+    fn clear_with_progress(&self, progress: &(dyn Fn(UnlinkProgress) + Sync)) -> CacheResult<()> {
+        let _ = progress;
+        self.clear()
+    }
+    /// Sync pending writes and reclaim space, reporting what was cleaned up
+    fn vacuum(&self) -> CacheResult<VacuumReport>;
     fn generate_filename(&self, key: &str) -> String;
     fn write_data_file(&self, filename: &str, data: &[u8]) -> CacheResult<()>;
     fn read_data_file(&self, filename: &str) -> CacheResult<Vec<u8>>;
+    /// Path of a file holding `key`'s stored bytes verbatim from the returned
+    /// offset to its end, if the backend keeps one
+    ///
+    /// Inline, compressed or otherwise transformed entries report `None`; callers
+    /// fall back to [`StorageBackend::get`].
+    fn data_file_path(&self, _key: &str) -> CacheResult<Option<(std::path::PathBuf, u64)>> {
+        Ok(None)
+    }
+    /// Reader over `key`'s value, if the backend keeps it in seekable
+    /// compressed frames, that decompresses only the frames read
+    ///
+    /// Other entries report `None`; callers fall back to
+    /// [`StorageBackend::get`].
+    fn open_frames(&self, _key: &str) -> CacheResult<Option<FramedReader<std::fs::File>>> {
+        Ok(None)
+    }
+    /// Store the contents of the file at `source` under `key` by hard-linking
+    /// it into the cache, or copying it where a link is impossible
+    ///
+    /// The value is never read into memory. Returns its size in bytes.
+    fn link_file(&self, _key: &str, _source: &std::path::Path) -> CacheResult<u64> {
+        Err(CacheError::Config(ConfigIssue::new(
+            "backend",
+            "this storage backend cannot adopt existing files",
+            "use the optimized backend",
+        )))
+    }
+    /// Start storing `key`'s value a piece at a time, for values too large to
+    /// hold in memory
+    ///
+    /// Nothing is stored until the writer is handed to
+    /// [`StorageBackend::commit_stream`]; dropping it discards the write.
+    fn write_stream(&self, _key: &str) -> CacheResult<DataFileWriter> {
+        Err(streaming_unsupported())
+    }
+    /// Store what `writer` wrote under its key, replacing any entry, and
+    /// return the value's size in bytes
+    fn commit_stream(&self, _writer: DataFileWriter) -> CacheResult<u64> {
+        Err(streaming_unsupported())
+    }
+    /// Serve each `(key, path)` pair from an existing file, without copying it
+    ///
+    /// The files stay owned by the caller: deleting or clearing the entries
+    /// leaves them on disk. Their contents are returned verbatim. Returns the
+    /// number of entries registered.
+    fn register_files(&self, _files: &[(String, std::path::PathBuf)]) -> CacheResult<u64> {
+        Err(CacheError::Config(ConfigIssue::new(
+            "backend",
+            "this storage backend can only serve files it wrote itself",
+            "use the optimized backend",
+        )))
+    }
+
+    /// Call `listener` with every key this storage drops on finding its entry
+    /// expired, in place of any listener given before
+    ///
+    /// Backends that leave expired entries for the caller to skip ignore it.
+    fn watch_expirations(&self, _listener: ExpiryListener) {}
+
+    /// Keep `key`'s value in the memory tiers it reaches while `pinned`,
+    /// whatever they drop to make room
+    ///
+    /// Backends without memory tiers of their own ignore it.
+    fn set_pinned(&self, _key: &str, _pinned: bool) {}
+
+    /// Drop every entry whose expiration time has passed, returning how many
+    /// were dropped
+    ///
+    /// Backends that only find expired entries as they are read drop none.
+    fn remove_expired(&self) -> CacheResult<u64> {
+        Ok(0)
+    }
+
+    /// Start background maintenance, such as compaction, only when `gate`
+    /// allows it, in place of any gate given before
+    fn gate_maintenance(&self, _gate: MaintenanceGate) {}
+
+    /// Drop entries that reads find expired, corrupt or missing only when
+    /// `gate` allows it, in place of any gate given before; otherwise such
+    /// reads miss and leave the entry where it is
+    fn gate_cleanup(&self, _gate: CleanupGate) {}
+
+    /// Whether the previous owner of this storage exited without closing
+    /// it, or `None` when that cannot be told
+    fn was_unclean_shutdown(&self) -> Option<bool> {
+        Some(false)
+    }
+
+    /// Take over in a forked child, before it first uses the storage it
+    /// inherited: start the background threads the fork did not copy, and
+    /// reopen what cannot be shared with the parent
+    ///
+    /// Only the first call in each child does anything; the default has
+    /// nothing to do.
+    fn after_fork(&self) {}
+
+    /// Check persisted entries and drop the ones that can no longer be served
+    fn verify_and_recover(&self) -> CacheResult<RecoveryReport> {
+        Ok(RecoveryReport::default())
+    }
+
+    /// Projected disk and memory use of `entries` entries with the given average sizes
+    ///
+    /// The default charges only the raw key and value bytes to disk; backends
+    /// override it with their own layout and in-memory overheads.
+    fn estimate_footprint(&self, entries: u64, key_size: usize, value_size: usize) -> Footprint {
+        Footprint {
+            disk_bytes: entries.saturating_mul((key_size as u64).saturating_add(value_size as u64)),
+            memory_bytes: 0,
+        }
+    }
+
+    /// Physical I/O performed so far; backends that do not track it report zeros
+    fn io_stats(&self) -> IoStats {
+        IoStats::default()
+    }
+
+    /// Bytes of values stored in each tier, for backends that keep count
+    ///
+    /// Counts follow the writes and removals made through this handle;
+    /// backends whose directory other processes also write recount on vacuum.
+    fn size(&self) -> Option<TierSizes> {
+        None
+    }
 
     /// Downcast to Any for accessing concrete type methods
     fn as_any(&self) -> &dyn std::any::Any;
 }
+
+/// Result of a [`StorageBackend::verify_and_recover`] pass
+#[derive(Debug, Clone, Default)]
+pub struct RecoveryReport {
+    pub entries_checked: u64,
+    pub entries_removed: u64,
+    /// Entries indexed again from data files the index had lost track of
+    pub entries_restored: u64,
+    pub integrity_ok: bool,
+}
+
+/// Result of a [`StorageBackend::vacuum`] pass
+#[derive(Debug, Clone, Default)]
+pub struct VacuumReport {
+    /// Data files no index entry pointed at, deleted
+    pub orphans_removed: u64,
+    /// Data files no index entry pointed at, indexed again under the key in their header
+    pub orphans_adopted: u64,
+    /// Temporary files left by interrupted writes, deleted
+    pub temp_files_removed: u64,
+    /// Data files longer than their indexed value, cut back to it
+    pub files_truncated: u64,
+    /// Disk space freed by truncating data files and punching holes in the
+    /// dead regions of segment files
+    pub bytes_reclaimed: u64,
+}
+
+/// Result of an [`OptimizedStorage::snapshot`]
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotReport {
+    /// Entries in the snapshot
+    pub entries: u64,
+    /// Data and segment files placed in the snapshot
+    pub files: u64,
+    /// Bytes copied for files that could not be hard-linked
+    pub bytes_copied: u64,
+}
+
+/// Storage cost reported by [`StorageBackend::estimate_footprint`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Footprint {
+    pub disk_bytes: u64,
+    pub memory_bytes: u64,
+}
+
+/// Bytes of stored values per tier, reported by [`StorageBackend::size`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TierSizes {
+    /// Values kept in the index itself
+    pub inline: u64,
+    /// Values packed into shared segment files
+    pub packed: u64,
+    /// Values in data files of their own, headers included; files registered
+    /// from elsewhere are not counted
+    pub files: u64,
+}
+
+impl TierSizes {
+    pub fn total(&self) -> u64 {
+        self.inline + self.packed + self.files
+    }
+}
+
+/// Write-side I/O counters reported by [`StorageBackend::io_stats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoStats {
+    /// Value bytes handed to the backend by writes
+    pub logical_bytes_written: u64,
+    /// Bytes the backend wrote to data files, its index and its log
+    pub disk_bytes_written: u64,
+    pub files_created: u64,
+    pub files_deleted: u64,
+    /// fsync calls made by the backend itself; SQLite's internal syncs are not counted
+    pub fsyncs: u64,
+}
+
+impl IoStats {
+    /// Disk bytes written per logical byte, or 0.0 before the first write
+    pub fn write_amplification(&self) -> f64 {
+        if self.logical_bytes_written == 0 {
+            0.0
+        } else {
+            self.disk_bytes_written as f64 / self.logical_bytes_written as f64
+        }
+    }
+}
@@ -0,0 +1,483 @@
+//! Codecs the optimized backend compresses large values with
+//!
+//! Every stored value records the codec it was written with: data file
+//! headers in their flags and index rows next to the path. A cache can
+//! therefore switch codecs between opens and still read everything it wrote
+//! before, each value with the codec that wrote it.
+//!
+//! Small values barely compress on their own, so they can instead be
+//! compressed with a zstd dictionary trained on a sample of them; see
+//! [`Dictionaries`]. Large ones can be cut into frames compressed one by one,
+//! so reading part of a value decompresses only that part; see
+//! [`crate::storage::frames`].
+
+use crate::error::{CacheError, CacheResult, ConfigIssue};
+use crate::storage::frames;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
+
+/// Zstandard level used when none is configured
+pub const DEFAULT_ZSTD_LEVEL: i32 = zstd::DEFAULT_COMPRESSION_LEVEL;
+/// Directory under the cache root holding trained dictionaries
+pub const DICTIONARY_DIR: &str = "dictionaries";
+/// Values sampled before the first dictionary is trained
+pub const DICTIONARY_SAMPLES: usize = 1000;
+/// Most bytes a trained dictionary takes
+const DICTIONARY_SIZE: usize = 16 * 1024;
+/// Dictionary version stored ahead of each dictionary-compressed value
+const VERSION_LEN: usize = 4;
+
+/// How a stored value is compressed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// Stored as given
+    None,
+    /// LZ4, fast enough to pay off on any disk
+    #[default]
+    Lz4,
+    /// Zstandard at a configurable level, for the smallest files on slow or
+    /// shared disks
+    Zstd,
+    /// Snappy, the cheapest to compress and decompress
+    Snappy,
+    /// Zstandard with a dictionary trained on the cache's small values,
+    /// whose version the stored bytes start with; not chosen directly but
+    /// used for values below the dictionary threshold
+    ZstdDictionary,
+    /// LZ4 in seekable frames, used for data files when seekable
+    /// compression is on
+    Lz4Frames,
+    /// Zstandard in seekable frames, used for data files when seekable
+    /// compression is on
+    ZstdFrames,
+}
+
+impl Compression {
+    /// Every codec, in the order of their ids
+    pub const ALL: [Compression; 7] = [
+        Compression::None,
+        Compression::Lz4,
+        Compression::Zstd,
+        Compression::Snappy,
+        Compression::ZstdDictionary,
+        Compression::Lz4Frames,
+        Compression::ZstdFrames,
+    ];
+
+    /// Codecs a cache can be configured with; the others are variants of
+    /// these chosen by other options
+    pub const CONFIGURABLE: [Compression; 4] = [
+        Compression::None,
+        Compression::Lz4,
+        Compression::Zstd,
+        Compression::Snappy,
+    ];
+
+    /// Name the codec is given by, as parsed by [`FromStr`]
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Lz4 => "lz4",
+            Compression::Zstd => "zstd",
+            Compression::Snappy => "snappy",
+            Compression::ZstdDictionary => "zstd-dictionary",
+            Compression::Lz4Frames => "lz4-frames",
+            Compression::ZstdFrames => "zstd-frames",
+        }
+    }
+
+    /// Id stored with each value
+    ///
+    /// 0 and 1 are the `false` and `true` of the compressed flag values were
+    /// stored with when LZ4 was the only codec, so those read back unchanged.
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    /// The codec stored as `id`
+    pub fn from_id(id: u8) -> CacheResult<Self> {
+        Self::ALL
+            .get(id as usize)
+            .copied()
+            .ok_or_else(|| CacheError::Corruption(format!("unknown compression codec id {}", id)))
+    }
+
+    pub fn is_compressed(self) -> bool {
+        self != Compression::None
+    }
+
+    /// The seekable variant of this codec, if it has one
+    pub fn framed(self) -> Option<Compression> {
+        match self {
+            Compression::Lz4 => Some(Compression::Lz4Frames),
+            Compression::Zstd => Some(Compression::ZstdFrames),
+            _ => None,
+        }
+    }
+
+    /// The codec each frame is compressed with, if this is a seekable one
+    pub fn frame_codec(self) -> Option<Compression> {
+        match self {
+            Compression::Lz4Frames => Some(Compression::Lz4),
+            Compression::ZstdFrames => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// `data` compressed with this codec; `level` only applies to zstd
+    ///
+    /// [`Compression::ZstdDictionary`] goes through [`Dictionaries`] instead.
+    pub fn compress(self, data: &[u8], level: i32) -> CacheResult<Vec<u8>> {
+        match self {
+            Compression::ZstdDictionary => Err(needs_dictionaries()),
+            Compression::None => Ok(data.to_vec()),
+            Compression::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            Compression::Zstd => zstd::bulk::compress(data, level).map_err(CacheError::Io),
+            Compression::Snappy => snap::raw::Encoder::new()
+                .compress_vec(data)
+                .map_err(|e| CacheError::Serialization(format!("Compression failed: {}", e))),
+            Compression::Lz4Frames => frames::compress(data, Compression::Lz4, level),
+            Compression::ZstdFrames => frames::compress(data, Compression::Zstd, level),
+        }
+    }
+
+    /// `data` as it was before this codec compressed it
+    pub fn decompress(self, data: &[u8]) -> CacheResult<Vec<u8>> {
+        let decompressed = match self {
+            Compression::None => return Ok(data.to_vec()),
+            Compression::ZstdDictionary => return Err(needs_dictionaries()),
+            Compression::Lz4Frames => return frames::decompress(data, Compression::Lz4),
+            Compression::ZstdFrames => return frames::decompress(data, Compression::Zstd),
+            Compression::Lz4 => {
+                lz4_flex::decompress_size_prepended(data).map_err(|e| e.to_string())
+            }
+            Compression::Zstd => zstd::stream::decode_all(data).map_err(|e| e.to_string()),
+            Compression::Snappy => snap::raw::Decoder::new()
+                .decompress_vec(data)
+                .map_err(|e| e.to_string()),
+        };
+        decompressed
+            .map_err(|e| CacheError::Deserialization(format!("Decompression failed: {}", e)))
+    }
+
+    /// Check that `level` suits this codec
+    pub fn check_level(self, level: i32) -> CacheResult<()> {
+        if self != Compression::Zstd {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "compression_level",
+                format!("{} compression has no levels", self.name()),
+                "set compression=\"zstd\", or leave compression_level out",
+            )));
+        }
+        let levels = zstd::compression_level_range();
+        if !levels.contains(&level) {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "compression_level",
+                format!("zstd has no compression level {}", level),
+                format!(
+                    "use a level from {} to {}; {} is the default",
+                    levels.start(),
+                    levels.end(),
+                    DEFAULT_ZSTD_LEVEL
+                ),
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Compression {
+    type Err = CacheError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::CONFIGURABLE
+            .into_iter()
+            .find(|codec| codec.name() == s)
+            .ok_or_else(|| {
+                CacheError::Config(ConfigIssue::new(
+                    "compression",
+                    format!("unknown compression codec {:?}", s),
+                    "use \"none\", \"lz4\", \"zstd\" or \"snappy\"",
+                ))
+            })
+    }
+}
+
+impl bincode::Encode for Compression {
+    fn encode<E: bincode::enc::Encoder>(
+        &self,
+        encoder: &mut E,
+    ) -> Result<(), bincode::error::EncodeError> {
+        self.id().encode(encoder)
+    }
+}
+
+impl<Context> bincode::Decode<Context> for Compression {
+    fn decode<D: bincode::de::Decoder<Context = Context>>(
+        decoder: &mut D,
+    ) -> Result<Self, bincode::error::DecodeError> {
+        let id = u8::decode(decoder)?;
+        Self::from_id(id).map_err(|e| bincode::error::DecodeError::OtherString(e.to_string()))
+    }
+}
+
+bincode::impl_borrow_decode!(Compression);
+
+/// A dictionary trained on `samples`, at most a tenth of their size so the
+/// trainer has enough data to pick from
+fn train(samples: &[Vec<u8>]) -> std::io::Result<Vec<u8>> {
+    let total = samples.iter().map(Vec::len).sum::<usize>();
+    zstd::dict::from_samples(samples, DICTIONARY_SIZE.min(total / 10))
+}
+
+fn needs_dictionaries() -> CacheError {
+    CacheError::Serialization("zstd dictionary compression needs the cache's dictionaries".into())
+}
+
+/// A trained dictionary, prepared for both directions
+struct Dictionary {
+    version: u32,
+    encoder: EncoderDictionary<'static>,
+    decoder: DecoderDictionary<'static>,
+}
+
+/// Zstd dictionaries trained on a cache's small values
+///
+/// Each is kept as `dictionaries/<version>.zdict` for as long as the cache
+/// exists, since the values compressed with it name its version; the newest
+/// compresses new values. Processes sharing a directory may each train one,
+/// and load the others' when they first read a value that needs it.
+pub struct Dictionaries {
+    directory: PathBuf,
+    level: i32,
+    versions: RwLock<HashMap<u32, Arc<Dictionary>>>,
+    current: RwLock<Option<Arc<Dictionary>>>,
+    samples: Mutex<Vec<Vec<u8>>>,
+}
+
+impl Dictionaries {
+    /// Load the dictionaries of the cache in `cache_dir`, compressing new
+    /// values at zstd `level`
+    pub fn open(cache_dir: &Path, level: i32) -> CacheResult<Self> {
+        let dictionaries = Self {
+            directory: cache_dir.join(DICTIONARY_DIR),
+            level,
+            versions: RwLock::new(HashMap::new()),
+            current: RwLock::new(None),
+            samples: Mutex::new(Vec::new()),
+        };
+        if let Some(newest) = dictionaries.stored_versions()?.into_iter().max() {
+            *dictionaries.current.write() = Some(dictionaries.load(newest)?);
+        }
+        Ok(dictionaries)
+    }
+
+    /// `data` compressed with the newest dictionary, behind its version, or
+    /// `None` before one has been trained
+    pub fn compress(&self, data: &[u8]) -> CacheResult<Option<Vec<u8>>> {
+        let Some(dictionary) = self.current.read().clone() else {
+            return Ok(None);
+        };
+        let mut compressor = zstd::bulk::Compressor::with_prepared_dictionary(&dictionary.encoder)?;
+        let frame = compressor.compress(data)?;
+        let mut stored = Vec::with_capacity(VERSION_LEN + frame.len());
+        stored.extend_from_slice(&dictionary.version.to_le_bytes());
+        stored.extend_from_slice(&frame);
+        Ok(Some(stored))
+    }
+
+    /// The value `stored` was compressed from, with the dictionary it names
+    pub fn decompress(&self, stored: &[u8]) -> CacheResult<Vec<u8>> {
+        if stored.len() < VERSION_LEN {
+            return Err(CacheError::Deserialization(
+                "dictionary-compressed value is missing its dictionary version".into(),
+            ));
+        }
+        let (version, frame) = stored.split_at(VERSION_LEN);
+        let version = u32::from_le_bytes(version.try_into().unwrap());
+        let loaded = self.versions.read().get(&version).cloned();
+        let dictionary = match loaded {
+            Some(dictionary) => dictionary,
+            None => self.load(version)?,
+        };
+        let mut decoder =
+            zstd::stream::read::Decoder::with_prepared_dictionary(frame, &dictionary.decoder)?;
+        let mut data = Vec::new();
+        decoder
+            .read_to_end(&mut data)
+            .map_err(|e| CacheError::Deserialization(format!("Decompression failed: {}", e)))?;
+        Ok(data)
+    }
+
+    /// Keep a copy of `data` to train the first dictionary on, training it
+    /// once enough values have been seen
+    ///
+    /// Returns the version trained, if this call trained one.
+    pub fn sample(&self, data: &[u8]) -> CacheResult<Option<u32>> {
+        if self.current.read().is_some() {
+            return Ok(None);
+        }
+        let samples = {
+            let mut samples = self.samples.lock();
+            samples.push(data.to_vec());
+            if samples.len() < DICTIONARY_SAMPLES {
+                return Ok(None);
+            }
+            std::mem::take(&mut *samples)
+        };
+        match train(&samples) {
+            Ok(trained) => self.store(&trained, samples.len()).map(Some),
+            // Too few distinct bytes to learn from; sample the next values
+            Err(e) => {
+                tracing::debug!("Could not train a zstd dictionary yet: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Persist `trained` under the next free version and make it current
+    fn store(&self, trained: &[u8], samples: usize) -> CacheResult<u32> {
+        std::fs::create_dir_all(&self.directory)?;
+        let mut version = self.stored_versions()?.into_iter().max().unwrap_or(0) + 1;
+        loop {
+            let mut file = tempfile::NamedTempFile::new_in(&self.directory)?;
+            std::io::Write::write_all(&mut file, trained)?;
+            match file.persist_noclobber(self.path(version)) {
+                Ok(_) => break,
+                // Another process trained one under this version first
+                Err(e) if e.error.kind() == std::io::ErrorKind::AlreadyExists => version += 1,
+                Err(e) => return Err(CacheError::Io(e.error)),
+            }
+        }
+        let dictionary = self.prepare(version, trained);
+        *self.current.write() = Some(dictionary);
+        tracing::info!(
+            "Trained zstd dictionary version {} on {} values",
+            version,
+            samples
+        );
+        Ok(version)
+    }
+
+    fn path(&self, version: u32) -> PathBuf {
+        self.directory.join(format!("{}.zdict", version))
+    }
+
+    /// Versions of the dictionaries stored in the directory
+    fn stored_versions(&self) -> CacheResult<Vec<u32>> {
+        let entries = match std::fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(CacheError::Io(e)),
+        };
+        Ok(entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                entry
+                    .file_name()
+                    .to_str()?
+                    .strip_suffix(".zdict")?
+                    .parse()
+                    .ok()
+            })
+            .collect())
+    }
+
+    fn load(&self, version: u32) -> CacheResult<Arc<Dictionary>> {
+        let trained = std::fs::read(self.path(version)).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                CacheError::Corruption(format!("zstd dictionary version {} is missing", version))
+            }
+            _ => CacheError::Io(e),
+        })?;
+        Ok(self.prepare(version, &trained))
+    }
+
+    fn prepare(&self, version: u32, trained: &[u8]) -> Arc<Dictionary> {
+        let dictionary = Arc::new(Dictionary {
+            version,
+            encoder: EncoderDictionary::copy(trained, self.level),
+            decoder: DecoderDictionary::copy(trained),
+        });
+        self.versions.write().insert(version, dictionary.clone());
+        dictionary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_codec_round_trips_and_keeps_its_id() {
+        let data = b"frame ".repeat(10_000);
+        for codec in Compression::ALL
+            .into_iter()
+            .filter(|codec| *codec != Compression::ZstdDictionary)
+        {
+            let stored = codec.compress(&data, DEFAULT_ZSTD_LEVEL).unwrap();
+            if codec.is_compressed() {
+                assert!(stored.len() < data.len() / 10, "{:?}", codec);
+            }
+            assert_eq!(codec.decompress(&stored).unwrap(), data);
+            assert_eq!(Compression::from_id(codec.id()).unwrap(), codec);
+        }
+        for codec in Compression::CONFIGURABLE {
+            assert_eq!(codec.name().parse::<Compression>().unwrap(), codec);
+        }
+        assert!("zstd-frames".parse::<Compression>().is_err());
+
+        // Rows written as a compressed flag decode as the codec it stood for
+        let config = bincode::config::standard();
+        let flag = bincode::encode_to_vec(true, config).unwrap();
+        let (codec, _): (Compression, usize) = bincode::decode_from_slice(&flag, config).unwrap();
+        assert_eq!(codec, Compression::Lz4);
+        assert!(Compression::from_id(9).is_err());
+    }
+
+    #[test]
+    fn dictionaries_are_trained_on_samples_and_kept_by_version() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let value = |i: usize| {
+            format!(
+                r#"{{"shot": "sq010_sh{:04}", "frames": [1001, {}], "status": "approved"}}"#,
+                i,
+                1001 + i
+            )
+            .into_bytes()
+        };
+        let dictionaries = Dictionaries::open(temp_dir.path(), DEFAULT_ZSTD_LEVEL).unwrap();
+        assert!(dictionaries.compress(&value(0)).unwrap().is_none());
+        let trained = (0..DICTIONARY_SAMPLES)
+            .filter_map(|i| dictionaries.sample(&value(i)).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(trained, vec![1]);
+
+        let stored = dictionaries.compress(&value(7)).unwrap().unwrap();
+        assert!(stored.len() < value(7).len() / 2);
+        assert_eq!(dictionaries.decompress(&stored).unwrap(), value(7));
+
+        // A newer version takes over; values name the one they need
+        let samples = (0..100).map(|i| value(i * 3)).collect::<Vec<_>>();
+        let trained = train(&samples).unwrap();
+        assert_eq!(dictionaries.store(&trained, samples.len()).unwrap(), 2);
+        let reopened = Dictionaries::open(temp_dir.path(), DEFAULT_ZSTD_LEVEL).unwrap();
+        let newer = reopened.compress(&value(7)).unwrap().unwrap();
+        assert_eq!(newer[..VERSION_LEN], 2u32.to_le_bytes());
+        assert_eq!(reopened.decompress(&stored).unwrap(), value(7));
+
+        std::fs::remove_file(temp_dir.path().join(DICTIONARY_DIR).join("1.zdict")).unwrap();
+        let reopened = Dictionaries::open(temp_dir.path(), DEFAULT_ZSTD_LEVEL).unwrap();
+        assert!(matches!(
+            reopened.decompress(&stored),
+            Err(CacheError::Corruption(_))
+        ));
+    }
+}
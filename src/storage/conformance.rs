@@ -0,0 +1,466 @@
+//! Reusable conformance suite for [`StorageBackend`] implementations
+//!
+//! Enable the `conformance` feature and call [`run_all`] (or the individual
+//! checks) from a backend's tests with a fresh, empty storage instance. Every
+//! check panics with a descriptive message on the first violation.
+
+use crate::serialization::CacheEntry;
+use crate::storage::StorageBackend;
+
+/// Payload large enough to cross the default inline/file threshold
+const LARGE_VALUE_SIZE: usize = 128 * 1024;
+
+fn entry(key: &str, data: &[u8]) -> CacheEntry {
+    CacheEntry::new_inline(key.to_string(), data.to_vec(), vec![], None)
+}
+
+fn read_value(storage: &dyn StorageBackend, key: &str) -> Option<Vec<u8>> {
+    let entry = storage
+        .get(key)
+        .unwrap_or_else(|e| panic!("get({:?}) failed: {}", key, e))?;
+    Some(entry_value(storage, &entry))
+}
+
+fn entry_value(storage: &dyn StorageBackend, entry: &CacheEntry) -> Vec<u8> {
+    match entry.get_data() {
+        Some(data) => data.to_vec(),
+        None => {
+            let filename = entry.get_filename().expect("entry has no data or file");
+            storage
+                .read_data_file(filename)
+                .unwrap_or_else(|e| panic!("read_data_file({:?}) failed: {}", filename, e))
+        }
+    }
+}
+
+/// Run every check in this suite against `storage`
+pub fn run_all(storage: &dyn StorageBackend) {
+    check_set_get(storage);
+    check_overwrite(storage);
+    check_large_values(storage);
+    check_missing_keys(storage);
+    check_delete(storage);
+    check_versions(storage);
+    check_aliases(storage);
+    check_keys(storage);
+    check_set_batch(storage);
+    check_get_many(storage);
+    check_expiry_metadata(storage);
+    check_vacuum(storage);
+    check_store_order(storage);
+    check_key_pages(storage);
+    check_key_range(storage);
+    check_clear(storage);
+}
+
+/// A stored value is returned unchanged and reported by `exists`
+pub fn check_set_get(storage: &dyn StorageBackend) {
+    storage
+        .set("conf:get", entry("conf:get", b"value"))
+        .unwrap();
+
+    assert!(storage.exists("conf:get").unwrap(), "set key must exist");
+    assert_eq!(
+        read_value(storage, "conf:get"),
+        Some(b"value".to_vec()),
+        "get must return the stored value"
+    );
+}
+
+/// Setting an existing key replaces its value
+pub fn check_overwrite(storage: &dyn StorageBackend) {
+    storage
+        .set("conf:overwrite", entry("conf:overwrite", b"old"))
+        .unwrap();
+    storage
+        .set("conf:overwrite", entry("conf:overwrite", b"new"))
+        .unwrap();
+
+    assert_eq!(
+        read_value(storage, "conf:overwrite"),
+        Some(b"new".to_vec()),
+        "overwrite must replace the previous value"
+    );
+}
+
+/// Values larger than typical inline thresholds round-trip intact
+pub fn check_large_values(storage: &dyn StorageBackend) {
+    let data: Vec<u8> = (0..LARGE_VALUE_SIZE).map(|i| (i % 251) as u8).collect();
+    storage
+        .set("conf:large", entry("conf:large", &data))
+        .unwrap();
+
+    assert_eq!(
+        read_value(storage, "conf:large"),
+        Some(data),
+        "large values must round-trip"
+    );
+
+    // Shrinking a value must not leave stale bytes behind
+    storage
+        .set("conf:large", entry("conf:large", b"small"))
+        .unwrap();
+    assert_eq!(read_value(storage, "conf:large"), Some(b"small".to_vec()));
+}
+
+/// Unknown keys are absent rather than errors
+pub fn check_missing_keys(storage: &dyn StorageBackend) {
+    assert!(storage.get("conf:missing").unwrap().is_none());
+    assert!(!storage.exists("conf:missing").unwrap());
+    assert!(
+        !storage.delete("conf:missing").unwrap(),
+        "deleting a missing key must report false"
+    );
+}
+
+/// Deleted keys are gone from every read path
+pub fn check_delete(storage: &dyn StorageBackend) {
+    storage
+        .set("conf:delete", entry("conf:delete", b"value"))
+        .unwrap();
+    let large = vec![7u8; LARGE_VALUE_SIZE];
+    storage
+        .set("conf:delete-large", entry("conf:delete-large", &large))
+        .unwrap();
+
+    for key in ["conf:delete", "conf:delete-large"] {
+        assert!(storage.delete(key).unwrap(), "delete must report true");
+        assert!(!storage.exists(key).unwrap(), "deleted key must not exist");
+        assert!(storage.get(key).unwrap().is_none(), "deleted key must miss");
+        assert!(
+            !storage.keys().unwrap().contains(&key.to_string()),
+            "deleted key must not be listed"
+        );
+    }
+}
+
+/// `keys` lists every live key exactly once
+pub fn check_keys(storage: &dyn StorageBackend) {
+    for i in 0..5 {
+        let key = format!("conf:keys:{}", i);
+        storage.set(&key, entry(&key, b"value")).unwrap();
+    }
+    storage
+        .set("conf:keys:0", entry("conf:keys:0", b"again"))
+        .unwrap();
+
+    let keys = storage.keys().unwrap();
+    for i in 0..5 {
+        let key = format!("conf:keys:{}", i);
+        assert_eq!(
+            keys.iter().filter(|k| **k == key).count(),
+            1,
+            "{} must be listed exactly once",
+            key
+        );
+    }
+}
+
+/// Batched writes behave like individual sets
+pub fn check_set_batch(storage: &dyn StorageBackend) {
+    storage
+        .set_batch(vec![
+            ("conf:batch:a".to_string(), b"one".to_vec()),
+            ("conf:batch:b".to_string(), vec![1u8; LARGE_VALUE_SIZE]),
+        ])
+        .unwrap();
+
+    assert_eq!(read_value(storage, "conf:batch:a"), Some(b"one".to_vec()));
+    assert_eq!(
+        read_value(storage, "conf:batch:b"),
+        Some(vec![1u8; LARGE_VALUE_SIZE])
+    );
+}
+
+/// `get_many` answers every key in request order, matching `get`
+pub fn check_get_many(storage: &dyn StorageBackend) {
+    let large = vec![9u8; LARGE_VALUE_SIZE];
+    storage
+        .set("conf:many:small", entry("conf:many:small", b"small"))
+        .unwrap();
+    storage
+        .set("conf:many:large", entry("conf:many:large", &large))
+        .unwrap();
+
+    let keys = [
+        "conf:many:large".to_string(),
+        "conf:many:missing".to_string(),
+        "conf:many:small".to_string(),
+    ];
+    let entries = storage.get_many(&keys).unwrap();
+    assert_eq!(entries.len(), keys.len(), "get_many must answer every key");
+    assert!(entries[1].is_none(), "missing keys must be None");
+    for (key, entry) in keys.iter().zip(&entries) {
+        assert_eq!(
+            entry.as_ref().map(|entry| entry_value(storage, entry)),
+            read_value(storage, key),
+            "get_many must agree with get for {}",
+            key
+        );
+    }
+
+    let prefetched = storage.prefetch_prefix("conf:many:").unwrap();
+    assert!(prefetched <= 2, "prefetch must stay within the prefix");
+    assert_eq!(read_value(storage, "conf:many:large"), Some(large));
+    assert_eq!(
+        read_value(storage, "conf:many:small"),
+        Some(b"small".to_vec())
+    );
+}
+
+/// Expiry time and tags stored with an entry are returned by `get`
+pub fn check_expiry_metadata(storage: &dyn StorageBackend) {
+    let expired = CacheEntry::new_inline(
+        "conf:expiry".to_string(),
+        b"value".to_vec(),
+        vec!["conf-tag".to_string()],
+        Some(1),
+    );
+    storage.set("conf:expiry", expired).unwrap();
+
+    if let Some(entry) = storage.get("conf:expiry").unwrap() {
+        assert_eq!(entry.expire_time, Some(1), "expire_time must round-trip");
+        assert_eq!(
+            entry.tags,
+            vec!["conf-tag".to_string()],
+            "tags must round-trip"
+        );
+        assert!(entry.is_expired(), "entry past its expire_time is expired");
+    }
+}
+
+/// `version` changes when a key is rewritten and only then
+pub fn check_versions(storage: &dyn StorageBackend) {
+    storage
+        .set("conf:version", entry("conf:version", b"first"))
+        .unwrap();
+    storage
+        .set("conf:version:other", entry("conf:version:other", b"other"))
+        .unwrap();
+    let first = storage.version("conf:version").unwrap();
+    assert!(first.is_some(), "a stored key must have a version");
+    assert_eq!(
+        storage.version("conf:version").unwrap(),
+        first,
+        "reading a version must not change it"
+    );
+
+    storage
+        .set("conf:version", entry("conf:version", b"second"))
+        .unwrap();
+    let second = storage.version("conf:version").unwrap();
+    assert!(second.is_some());
+    assert_ne!(second, first, "a rewrite must change the version");
+
+    let other = storage.version("conf:version:other").unwrap();
+    storage.delete("conf:version").unwrap();
+    assert_eq!(storage.version("conf:version").unwrap(), None);
+    assert_eq!(
+        storage.version("conf:version:other").unwrap(),
+        other,
+        "writes to other keys must not change a version"
+    );
+    assert_eq!(storage.version("conf:version:missing").unwrap(), None);
+}
+
+/// Aliases resolve to their entry, follow rewrites and go away with it
+pub fn check_aliases(storage: &dyn StorageBackend) {
+    storage
+        .set("conf:alias:a", entry("conf:alias:a", b"a"))
+        .unwrap();
+    storage
+        .set("conf:alias:b", entry("conf:alias:b", b"b"))
+        .unwrap();
+    assert!(
+        storage
+            .set_alias("conf:alias:x", "conf:alias:missing")
+            .is_err(),
+        "an alias needs a stored entry"
+    );
+
+    storage.set_alias("conf:alias:x", "conf:alias:a").unwrap();
+    storage.set_alias("conf:alias:y", "conf:alias:a").unwrap();
+    let resolve = |alias: &str| storage.resolve_alias(alias).unwrap();
+    let mut aliases = storage.aliases("conf:alias:a").unwrap();
+    aliases.sort();
+    assert_eq!(aliases, vec!["conf:alias:x", "conf:alias:y"]);
+    assert_eq!(resolve("conf:alias:x").as_deref(), Some("conf:alias:a"));
+    assert!(
+        !storage.exists("conf:alias:x").unwrap(),
+        "an alias is not an entry of its own"
+    );
+
+    storage
+        .set("conf:alias:a", entry("conf:alias:a", b"rewritten"))
+        .unwrap();
+    assert_eq!(
+        resolve("conf:alias:x").as_deref(),
+        Some("conf:alias:a"),
+        "aliases must survive rewrites"
+    );
+
+    storage.set_alias("conf:alias:y", "conf:alias:b").unwrap();
+    assert_eq!(
+        storage.aliases("conf:alias:a").unwrap(),
+        vec!["conf:alias:x"]
+    );
+    assert!(storage.remove_alias("conf:alias:y").unwrap());
+    assert!(!storage.remove_alias("conf:alias:y").unwrap());
+    assert_eq!(resolve("conf:alias:y"), None);
+    assert_eq!(
+        read_value(storage, "conf:alias:b"),
+        Some(b"b".to_vec()),
+        "removing an alias must keep its entry"
+    );
+
+    storage.delete("conf:alias:a").unwrap();
+    assert_eq!(resolve("conf:alias:x"), None);
+    assert!(storage.aliases("conf:alias:a").unwrap().is_empty());
+
+    storage.set_alias("conf:alias:z", "conf:alias:b").unwrap();
+    storage.clear().unwrap();
+    assert_eq!(resolve("conf:alias:z"), None);
+    storage
+        .set("conf:alias:b", entry("conf:alias:b", b"b"))
+        .unwrap();
+    assert_eq!(
+        resolve("conf:alias:z"),
+        None,
+        "clear must drop aliases, not just hide them"
+    );
+}
+
+/// Vacuum succeeds and preserves live entries
+pub fn check_vacuum(storage: &dyn StorageBackend) {
+    storage
+        .set("conf:vacuum", entry("conf:vacuum", b"value"))
+        .unwrap();
+    storage.vacuum().unwrap();
+
+    assert_eq!(read_value(storage, "conf:vacuum"), Some(b"value".to_vec()));
+}
+
+/// `peek_key` follows store order, and rewriting a key moves it to the end
+///
+/// Clears the storage first so the oldest entry is known.
+pub fn check_store_order(storage: &dyn StorageBackend) {
+    storage.clear().unwrap();
+    assert_eq!(
+        storage.peek_key(true).unwrap(),
+        None,
+        "empty storage has no keys"
+    );
+
+    for key in ["conf:order:a", "conf:order:b", "conf:order:c"] {
+        storage.set(key, entry(key, b"value")).unwrap();
+    }
+    assert_eq!(
+        storage.peek_key(false).unwrap().as_deref(),
+        Some("conf:order:a")
+    );
+    assert_eq!(
+        storage.peek_key(true).unwrap().as_deref(),
+        Some("conf:order:c")
+    );
+
+    storage
+        .set("conf:order:a", entry("conf:order:a", b"again"))
+        .unwrap();
+    assert_eq!(
+        storage.peek_key(false).unwrap().as_deref(),
+        Some("conf:order:b")
+    );
+    assert_eq!(
+        storage.peek_key(true).unwrap().as_deref(),
+        Some("conf:order:a")
+    );
+
+    storage.delete("conf:order:b").unwrap();
+    assert_eq!(
+        storage.peek_key(false).unwrap().as_deref(),
+        Some("conf:order:c")
+    );
+}
+
+/// `key_page` walks every key in store order, forwards and backwards
+///
+/// Clears the storage first so the full order is known.
+pub fn check_key_pages(storage: &dyn StorageBackend) {
+    storage.clear().unwrap();
+    let keys: Vec<String> = (0..7).map(|i| format!("conf:page:{}", 6 - i)).collect();
+    for key in &keys {
+        storage.set(key, entry(key, b"value")).unwrap();
+    }
+
+    let walk = |reverse: bool| {
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = storage.key_page(cursor, reverse, 3).unwrap();
+            assert!(page.len() <= 3, "key_page must honour its limit");
+            match page.last() {
+                Some((seq, _)) => cursor = Some(*seq),
+                None => break,
+            }
+            seen.extend(page.into_iter().map(|(_, key)| key));
+        }
+        seen
+    };
+
+    assert_eq!(walk(false), keys, "forward pages must follow store order");
+    let mut reversed = keys.clone();
+    reversed.reverse();
+    assert_eq!(
+        walk(true),
+        reversed,
+        "reverse pages must follow store order"
+    );
+}
+
+/// `peek_key_in_range` returns range bounds in byte order, end exclusive
+pub fn check_key_range(storage: &dyn StorageBackend) {
+    for key in ["conf:range:b", "conf:range:a", "conf:range:c", "conf:rangf"] {
+        storage.set(key, entry(key, b"value")).unwrap();
+    }
+
+    let first = storage
+        .peek_key_in_range("conf:range:", "conf:range;", false)
+        .unwrap();
+    let last = storage
+        .peek_key_in_range("conf:range:", "conf:range;", true)
+        .unwrap();
+    assert_eq!(first.as_deref(), Some("conf:range:a"));
+    assert_eq!(last.as_deref(), Some("conf:range:c"));
+
+    let upper_excluded = storage
+        .peek_key_in_range("conf:range:", "conf:range:c", true)
+        .unwrap();
+    assert_eq!(upper_excluded.as_deref(), Some("conf:range:b"));
+    assert_eq!(
+        storage
+            .peek_key_in_range("conf:range:x", "conf:range:z", false)
+            .unwrap(),
+        None
+    );
+}
+
+/// Clear removes every entry
+pub fn check_clear(storage: &dyn StorageBackend) {
+    storage
+        .set("conf:clear", entry("conf:clear", b"value"))
+        .unwrap();
+    storage
+        .set(
+            "conf:clear-large",
+            entry("conf:clear-large", &[3u8; LARGE_VALUE_SIZE]),
+        )
+        .unwrap();
+    storage.clear().unwrap();
+
+    assert!(
+        storage.keys().unwrap().is_empty(),
+        "clear must remove all keys"
+    );
+    assert!(storage.get("conf:clear").unwrap().is_none());
+    assert!(storage.get("conf:clear-large").unwrap().is_none());
+}
@@ -0,0 +1,374 @@
+//! Header at the start of every data file
+//!
+//! Data files are named after a hash of their key, so the name alone cannot
+//! say which key a file holds. The header records the key, how the payload is
+//! encoded and a checksum of the payload, which is enough to rebuild index
+//! entries from the data directory when the index is lost. Files written before the header existed
+//! start directly with their payload; they are still served through the index
+//! but cannot be recovered without it.
+
+use crate::error::{CacheError, CacheResult};
+use crate::storage::Compression;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: [u8; 4] = *b"DCDF";
+/// Version 1 headers carry no payload checksum
+const VERSION_1: u8 = 1;
+const VERSION: u8 = 2;
+const FLAG_COMPRESSED: u8 = 1;
+/// Compressed payloads keep their codec's id, less one, above the flag, so
+/// LZ4 files read the same as before there were other codecs and builds that
+/// only know LZ4 fail to decompress the rest instead of serving them raw
+const CODEC_SHIFT: u8 = 1;
+/// Magic, version, flags and key length
+const PREFIX_LEN: usize = 4 + 1 + 1 + 4;
+/// Longest key a header is trusted to hold
+const MAX_KEY_LEN: usize = 64 * 1024 * 1024;
+
+/// Key and payload encoding of one data file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataFileHeader {
+    pub key: String,
+    /// Codec the payload is compressed with
+    pub compression: Compression,
+    /// [`payload_checksum`] of the payload as recorded in the file, or `None`
+    /// for version 1 headers; [`DataFileHeader::frame`] always records a fresh one
+    pub checksum: Option<u32>,
+}
+
+impl DataFileHeader {
+    pub fn new(key: &str, compression: Compression) -> Self {
+        Self {
+            key: key.to_string(),
+            compression,
+            checksum: None,
+        }
+    }
+
+    /// Encoded length of this header
+    pub fn encoded_len(&self) -> usize {
+        fixed_len(VERSION) + self.key.len()
+    }
+
+    /// The header followed by `payload`, ready to be written as one file
+    pub fn frame(&self, payload: &[u8]) -> Vec<u8> {
+        let mut file = Vec::with_capacity(self.encoded_len() + payload.len());
+        self.encode_into(&mut file, payload_checksum(payload));
+        file.extend_from_slice(payload);
+        file
+    }
+
+    /// Append the encoded header, recording `checksum` for the payload
+    fn encode_into(&self, file: &mut Vec<u8>, checksum: u32) {
+        let flags = match self.compression {
+            Compression::None => 0,
+            codec => FLAG_COMPRESSED | (codec.id() - 1) << CODEC_SHIFT,
+        };
+        let start = file.len();
+        file.extend_from_slice(&MAGIC);
+        file.push(VERSION);
+        file.push(flags);
+        file.extend_from_slice(&(self.key.len() as u32).to_le_bytes());
+        file.extend_from_slice(&checksum.to_le_bytes());
+        let checksum = header_checksum(&file[start..], self.key.as_bytes());
+        file.extend_from_slice(&checksum);
+        file.extend_from_slice(self.key.as_bytes());
+    }
+
+    /// The header at the start of `file` and where its payload starts
+    ///
+    /// Returns `None` for files written without a header.
+    pub fn decode(file: &[u8]) -> CacheResult<Option<(Self, usize)>> {
+        let Some((fixed_len, key_len)) = Self::decode_prefix(file)? else {
+            return Ok(None);
+        };
+        let header = file.get(..fixed_len + key_len).ok_or_else(truncated)?;
+        Self::finish(header, fixed_len).map(Some)
+    }
+
+    /// Read just the header of the data file at `path`
+    pub fn read_from(path: &Path) -> CacheResult<Option<(Self, usize)>> {
+        let mut file = std::fs::File::open(path)?;
+        let mut prefix = [0u8; PREFIX_LEN];
+        match file.read_exact(&mut prefix) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(CacheError::Io(e)),
+        }
+        let Some((fixed_len, key_len)) = Self::decode_prefix(&prefix)? else {
+            return Ok(None);
+        };
+        let mut header = prefix.to_vec();
+        header.resize(fixed_len + key_len, 0);
+        file.read_exact(&mut header[PREFIX_LEN..])
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::UnexpectedEof => truncated(),
+                _ => CacheError::Io(e),
+            })?;
+        Self::finish(&header, fixed_len).map(Some)
+    }
+
+    /// Length of the fixed fields and of the key, or `None` without the magic
+    fn decode_prefix(file: &[u8]) -> CacheResult<Option<(usize, usize)>> {
+        if file.len() < PREFIX_LEN || file[..4] != MAGIC {
+            return Ok(None);
+        }
+        if file[4] != VERSION && file[4] != VERSION_1 {
+            return Err(CacheError::Corruption(format!(
+                "unsupported data file header version {}",
+                file[4]
+            )));
+        }
+        let key_len = u32::from_le_bytes(file[6..10].try_into().unwrap()) as usize;
+        if key_len > MAX_KEY_LEN {
+            return Err(CacheError::Corruption(format!(
+                "data file header claims a {} byte key",
+                key_len
+            )));
+        }
+        Ok(Some((fixed_len(file[4]), key_len)))
+    }
+
+    /// Check and decode a complete header: fixed fields, then the key
+    fn finish(header: &[u8], fixed_len: usize) -> CacheResult<(Self, usize)> {
+        let key_len = header.len() - fixed_len;
+        let (fixed, key) = header.split_at(fixed_len);
+        let (covered, checksum) = fixed.split_at(fixed_len - 4);
+        if header_checksum(covered, key) != checksum {
+            return Err(CacheError::Corruption(
+                "data file header checksum mismatch".into(),
+            ));
+        }
+        let key = String::from_utf8(key.to_vec())
+            .map_err(|_| CacheError::Corruption("data file key is not UTF-8".into()))?;
+        let checksum = (fixed[4] != VERSION_1)
+            .then(|| u32::from_le_bytes(fixed[PREFIX_LEN..PREFIX_LEN + 4].try_into().unwrap()));
+        let flags = fixed[5];
+        let compression = if flags & FLAG_COMPRESSED == 0 {
+            Compression::None
+        } else {
+            Compression::from_id((flags >> CODEC_SHIFT) + 1)?
+        };
+        let header = Self {
+            key,
+            compression,
+            checksum,
+        };
+        Ok((header, fixed_len + key_len))
+    }
+}
+
+/// Checksum of a stored payload, as recorded in headers and in the index
+pub fn payload_checksum(payload: &[u8]) -> u32 {
+    checksum_of(blake3::hash(payload))
+}
+
+fn checksum_of(hash: blake3::Hash) -> u32 {
+    u32::from_le_bytes(hash.as_bytes()[..4].try_into().unwrap())
+}
+
+/// Data file written a piece at a time, for values too large to hold in memory
+///
+/// The payload goes to a temporary file behind a header whose checksum is
+/// filled in by [`DataFileWriter::finish`], once the whole payload has been
+/// hashed. Payloads are stored uncompressed. Dropping an unfinished writer
+/// removes the temporary file.
+pub struct DataFileWriter {
+    header: DataFileHeader,
+    path: PathBuf,
+    file: Option<BufWriter<File>>,
+    hasher: blake3::Hasher,
+    payload_len: u64,
+}
+
+impl DataFileWriter {
+    /// Start `key`'s data file at `path`, which must not exist yet
+    pub fn create(path: PathBuf, key: &str) -> CacheResult<Self> {
+        let header = DataFileHeader::new(key, Compression::None);
+        let mut file = BufWriter::new(
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)?,
+        );
+        let mut placeholder = Vec::with_capacity(header.encoded_len());
+        header.encode_into(&mut placeholder, 0);
+        file.write_all(&placeholder)?;
+        Ok(Self {
+            header,
+            path,
+            file: Some(file),
+            hasher: blake3::Hasher::new(),
+            payload_len: 0,
+        })
+    }
+
+    /// Key the file is written for
+    pub fn key(&self) -> &str {
+        &self.header.key
+    }
+
+    /// Payload bytes written so far
+    pub fn payload_len(&self) -> u64 {
+        self.payload_len
+    }
+
+    /// Write the final header and flush, returning the file, its path, its
+    /// length and the payload checksum; the caller takes over the file
+    pub fn finish(mut self) -> CacheResult<(File, PathBuf, u64, u32)> {
+        let path = std::mem::take(&mut self.path);
+        let file = self.file.take().expect("an unfinished writer has its file");
+        let checksum = checksum_of(self.hasher.finalize());
+        let mut header = Vec::with_capacity(self.header.encoded_len());
+        self.header.encode_into(&mut header, checksum);
+        let finished = file
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .and_then(|mut file| {
+                file.seek(SeekFrom::Start(0))?;
+                file.write_all(&header)?;
+                Ok(file)
+            });
+        match finished {
+            Ok(file) => Ok((file, path, header.len() as u64 + self.payload_len, checksum)),
+            Err(e) => {
+                let _ = std::fs::remove_file(&path);
+                Err(CacheError::Io(e))
+            }
+        }
+    }
+}
+
+impl Write for DataFileWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let file = self
+            .file
+            .as_mut()
+            .expect("an unfinished writer has its file");
+        let written = file.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.payload_len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for DataFileWriter {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+impl std::fmt::Debug for DataFileWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DataFileWriter")
+            .field("key", &self.header.key)
+            .field("path", &self.path)
+            .field("payload_len", &self.payload_len)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Length of the fields before the key in a header of `version`: the prefix,
+/// the payload checksum from version 2 on, then the header checksum
+fn fixed_len(version: u8) -> usize {
+    match version {
+        VERSION_1 => PREFIX_LEN + 4,
+        _ => PREFIX_LEN + 4 + 4,
+    }
+}
+
+fn truncated() -> CacheError {
+    CacheError::Corruption("data file header is truncated".into())
+}
+
+fn header_checksum(fixed: &[u8], key: &[u8]) -> [u8; 4] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(fixed);
+    hasher.update(key);
+    let mut checksum = [0u8; 4];
+    checksum.copy_from_slice(&hasher.finalize().as_bytes()[..4]);
+    checksum
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_round_trips_and_detects_damage() {
+        let header = DataFileHeader::new("some/key", Compression::Zstd);
+        let file = header.frame(b"payload");
+
+        let (decoded, offset) = DataFileHeader::decode(&file).unwrap().unwrap();
+        assert_eq!(decoded.key, header.key);
+        assert_eq!(decoded.compression, Compression::Zstd);
+        assert_eq!(decoded.checksum, Some(payload_checksum(b"payload")));
+        assert_eq!(&file[offset..], b"payload");
+
+        // Files from before the header have none
+        assert!(DataFileHeader::decode(b"plain old payload")
+            .unwrap()
+            .is_none());
+
+        let fixed = fixed_len(VERSION);
+        let mut damaged = file.clone();
+        damaged[fixed] ^= 1;
+        assert!(matches!(
+            DataFileHeader::decode(&damaged),
+            Err(CacheError::Corruption(_))
+        ));
+        assert!(matches!(
+            DataFileHeader::decode(&file[..fixed + 2]),
+            Err(CacheError::Corruption(_))
+        ));
+    }
+
+    #[test]
+    fn streamed_files_match_framed_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("streamed.tmp");
+        let mut writer = DataFileWriter::create(path.clone(), "big").unwrap();
+        writer.write_all(b"pay").unwrap();
+        writer.write_all(b"load").unwrap();
+        let (_, finished, len, checksum) = writer.finish().unwrap();
+
+        let file = std::fs::read(&finished).unwrap();
+        assert_eq!(
+            file,
+            DataFileHeader::new("big", Compression::None).frame(b"payload")
+        );
+        assert_eq!(len, file.len() as u64);
+        assert_eq!(checksum, payload_checksum(b"payload"));
+
+        // An abandoned write leaves nothing behind
+        let abandoned = dir.path().join("abandoned.tmp");
+        let mut writer = DataFileWriter::create(abandoned.clone(), "big").unwrap();
+        writer.write_all(b"partial").unwrap();
+        drop(writer);
+        assert!(!abandoned.exists());
+    }
+
+    #[test]
+    fn version_1_headers_have_no_checksum() {
+        let mut file = MAGIC.to_vec();
+        file.extend_from_slice(&[VERSION_1, 0]);
+        file.extend_from_slice(&3u32.to_le_bytes());
+        let checksum = header_checksum(&file, b"key");
+        file.extend_from_slice(&checksum);
+        file.extend_from_slice(b"keypayload");
+
+        let (decoded, offset) = DataFileHeader::decode(&file).unwrap().unwrap();
+        assert_eq!(decoded, DataFileHeader::new("key", Compression::None));
+        assert_eq!(&file[offset..], b"payload");
+    }
+}
@@ -0,0 +1,438 @@
+//! Cross-process invalidation log for [`OptimizedStorage`](super::OptimizedStorage)
+//!
+//! Handles with the log enabled append a record to `events.log` for every
+//! key they set, delete or find expired and for every clear, and read what
+//! other handles appended before serving anything from their memory tiers,
+//! dropping what those hold for the keys named. Unlike watching `data/` this
+//! covers inline values too, and needs no change notification from the
+//! filesystem. Subscribers to the cache follow the log as well, through a
+//! handle of their own, to hear what other processes change.
+//!
+//! Layout, little endian: a header `magic: [u8; 4] | epoch: u64 | next_seq: u64`
+//! followed by records `kind: u8 | seq: u64 | origin: u64 | key_len: u32 |
+//! key | checksum: [u8; 8]`, where the checksum is the XXH3 hash of everything
+//! before it. Appends hold an exclusive lock on the file. The first append to
+//! find the log past `RESTART_BYTES` starts it over under a new epoch; a
+//! reader that finds the epoch changed, or a record out of sequence or
+//! corrupt, has missed events and drops its memory tiers whole.
+
+use crate::changes::{Change, ChangeFeed, ChangeKind};
+use crate::error::CacheResult;
+use fs4::fs_std::FileExt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Duration;
+
+/// File name of the log inside the cache directory
+pub const EVENT_LOG_FILE: &str = "events.log";
+
+const MAGIC: &[u8; 4] = b"DCEV";
+const HEADER_LEN: u64 = 20;
+const RECORD_HEADER_LEN: usize = 21;
+const CHECKSUM_LEN: usize = 8;
+/// Size at which the next append starts the log over
+const RESTART_BYTES: u64 = 4 * 1024 * 1024;
+
+const EVENT_SET: u8 = 1;
+const EVENT_DELETE: u8 = 2;
+const EVENT_CLEAR: u8 = 3;
+const EVENT_EXPIRE: u8 = 4;
+
+/// A change other handles must hear about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Event<'a> {
+    Set(&'a str),
+    Delete(&'a str),
+    Expire(&'a str),
+    Clear,
+}
+
+/// What a handle drops from its memory tiers after catching up
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Invalidation {
+    Keys(Vec<String>),
+    All,
+}
+
+/// A record as decoded from the log
+enum Decoded {
+    Record {
+        seq: u64,
+        origin: u64,
+        kind: u8,
+        key: String,
+        len: usize,
+    },
+    /// An append still under way
+    Incomplete,
+    Corrupt,
+}
+
+/// One handle's view of `events.log`: where it has read up to, and the id
+/// its own records carry so it can skip them
+pub(crate) struct EventLog {
+    file: File,
+    origin: u64,
+    epoch: u64,
+    /// End of the records read so far
+    offset: u64,
+    /// Sequence number of the next record expected
+    next_seq: u64,
+    /// Whether this handle started the log over before reading all of it
+    missed: bool,
+}
+
+impl EventLog {
+    /// Open the log in `directory`, creating it if needed; records already
+    /// in it are taken as read
+    pub(crate) fn open(directory: &Path) -> CacheResult<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(directory.join(EVENT_LOG_FILE))?;
+        let mut log = Self {
+            file,
+            origin: new_id(),
+            epoch: 0,
+            offset: 0,
+            next_seq: 0,
+            missed: false,
+        };
+
+        FileExt::lock_exclusive(&log.file)?;
+        let opened = log.read_header().and_then(|header| {
+            let (epoch, next_seq) = match header {
+                Some(header) => header,
+                None => log.start_over(new_id(), 0)?,
+            };
+            log.epoch = epoch;
+            log.next_seq = next_seq;
+            log.offset = log.file.metadata()?.len();
+            Ok(())
+        });
+        let _ = FileExt::unlock(&log.file);
+        opened?;
+        Ok(log)
+    }
+
+    /// Append `events` for the other handles to read
+    pub(crate) fn append(&mut self, events: &[Event<'_>]) -> CacheResult<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        FileExt::lock_exclusive(&self.file)?;
+        let appended = self.append_locked(events);
+        let _ = FileExt::unlock(&self.file);
+        appended
+    }
+
+    fn append_locked(&mut self, events: &[Event<'_>]) -> CacheResult<()> {
+        let header = self.read_header()?;
+        let mut len = self.file.metadata()?.len();
+        let (epoch, mut seq) = match header {
+            Some((epoch, seq)) if len < RESTART_BYTES => (epoch, seq),
+            header => {
+                // Whatever this handle has not read yet goes with the old log
+                let seq = header.map_or(self.next_seq, |(_, seq)| seq);
+                self.missed |=
+                    header.map(|(epoch, _)| epoch) != Some(self.epoch) || self.offset < len;
+                let restarted = self.start_over(new_id(), seq)?;
+                self.epoch = restarted.0;
+                self.offset = HEADER_LEN;
+                self.next_seq = seq;
+                len = HEADER_LEN;
+                restarted
+            }
+        };
+
+        let mut buf = Vec::new();
+        for event in events {
+            let (kind, key) = match event {
+                Event::Set(key) => (EVENT_SET, *key),
+                Event::Delete(key) => (EVENT_DELETE, *key),
+                Event::Expire(key) => (EVENT_EXPIRE, *key),
+                Event::Clear => (EVENT_CLEAR, ""),
+            };
+            let start = buf.len();
+            buf.push(kind);
+            buf.extend_from_slice(&seq.to_le_bytes());
+            buf.extend_from_slice(&self.origin.to_le_bytes());
+            buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+            buf.extend_from_slice(key.as_bytes());
+            let checksum = xxhash_rust::xxh3::xxh3_64(&buf[start..]);
+            buf.extend_from_slice(&checksum.to_le_bytes());
+            seq += 1;
+        }
+        self.file.seek(SeekFrom::Start(len))?;
+        self.file.write_all(&buf)?;
+        self.write_header(epoch, seq)
+    }
+
+    /// Another handle on the log in `directory`, reading from where this
+    /// one would and skipping the same records as its own
+    pub(crate) fn follow(&self, directory: &Path) -> CacheResult<Self> {
+        let mut follower = Self::open(directory)?;
+        follower.origin = self.origin;
+        Ok(follower)
+    }
+
+    /// Read the records other handles appended since the last call
+    pub(crate) fn catch_up(&mut self) -> CacheResult<Option<Invalidation>> {
+        let Some(records) = self.read_records()? else {
+            return Ok(Some(Invalidation::All));
+        };
+        Ok(if records.iter().any(|(kind, _)| *kind == EVENT_CLEAR) {
+            Some(Invalidation::All)
+        } else if records.is_empty() {
+            None
+        } else {
+            Some(Invalidation::Keys(
+                records.into_iter().map(|(_, key)| key).collect(),
+            ))
+        })
+    }
+
+    /// The changes other handles logged since the last call
+    pub(crate) fn changes(&mut self) -> CacheResult<Vec<Change>> {
+        let Some(records) = self.read_records()? else {
+            return Ok(vec![Change::new(ChangeKind::Clear, "")]);
+        };
+        Ok(records
+            .into_iter()
+            .map(|(kind, key)| {
+                let kind = match kind {
+                    EVENT_DELETE => ChangeKind::Delete,
+                    EVENT_EXPIRE => ChangeKind::Expire,
+                    EVENT_CLEAR => ChangeKind::Clear,
+                    _ => ChangeKind::Set,
+                };
+                Change::new(kind, key)
+            })
+            .collect())
+    }
+
+    /// Kind and key of the records other handles appended since the last
+    /// call, or `None` if some could not be read in order and were skipped
+    fn read_records(&mut self) -> CacheResult<Option<Vec<(u8, String)>>> {
+        let len = self.file.metadata()?.len();
+        if len == self.offset && !self.missed {
+            return Ok(Some(Vec::new()));
+        }
+        let header = self.read_header()?;
+        if self.missed || len < self.offset || header.map(|(epoch, _)| epoch) != Some(self.epoch) {
+            self.skip_to_end(header, len);
+            return Ok(None);
+        }
+
+        let mut bytes = Vec::with_capacity((len - self.offset) as usize);
+        self.file.seek(SeekFrom::Start(self.offset))?;
+        (&mut self.file)
+            .take(len - self.offset)
+            .read_to_end(&mut bytes)?;
+
+        let (mut records, mut read) = (Vec::new(), 0);
+        loop {
+            match decode(&bytes[read..]) {
+                Decoded::Record {
+                    seq,
+                    origin,
+                    kind,
+                    key,
+                    len: record_len,
+                } => {
+                    if seq != self.next_seq {
+                        self.skip_to_end(header, len);
+                        return Ok(None);
+                    }
+                    self.next_seq += 1;
+                    read += record_len;
+                    if origin != self.origin {
+                        records.push((kind, key));
+                    }
+                }
+                Decoded::Incomplete => break,
+                Decoded::Corrupt => {
+                    self.skip_to_end(header, len);
+                    return Ok(None);
+                }
+            }
+        }
+        self.offset += read as u64;
+        Ok(Some(records))
+    }
+
+    /// Give up on the records between here and `len`, which cannot be read
+    /// in order, and carry on from the end
+    fn skip_to_end(&mut self, header: Option<(u64, u64)>, len: u64) {
+        if let Some((epoch, next_seq)) = header {
+            self.epoch = epoch;
+            self.next_seq = next_seq;
+        }
+        self.offset = len;
+        self.missed = false;
+    }
+
+    /// Empty the log under a new `epoch`, numbering on from `next_seq`
+    fn start_over(&mut self, epoch: u64, next_seq: u64) -> CacheResult<(u64, u64)> {
+        self.file.set_len(0)?;
+        self.write_header(epoch, next_seq)?;
+        Ok((epoch, next_seq))
+    }
+
+    fn read_header(&mut self) -> CacheResult<Option<(u64, u64)>> {
+        let mut header = [0u8; HEADER_LEN as usize];
+        self.file.seek(SeekFrom::Start(0))?;
+        match self.file.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        if &header[..4] != MAGIC {
+            return Ok(None);
+        }
+        let epoch = u64::from_le_bytes(header[4..12].try_into().unwrap());
+        let next_seq = u64::from_le_bytes(header[12..20].try_into().unwrap());
+        Ok(Some((epoch, next_seq)))
+    }
+
+    fn write_header(&mut self, epoch: u64, next_seq: u64) -> CacheResult<()> {
+        let mut header = Vec::with_capacity(HEADER_LEN as usize);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&epoch.to_le_bytes());
+        header.extend_from_slice(&next_seq.to_le_bytes());
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        Ok(())
+    }
+}
+
+/// The log is polled: appends come with no notification to wait on
+impl ChangeFeed for EventLog {
+    fn next(&mut self, timeout: Duration) -> CacheResult<Vec<Change>> {
+        let changes = self.changes()?;
+        if changes.is_empty() {
+            std::thread::sleep(timeout);
+        }
+        Ok(changes)
+    }
+}
+
+fn new_id() -> u64 {
+    uuid::Uuid::new_v4().as_u64_pair().0
+}
+
+fn decode(bytes: &[u8]) -> Decoded {
+    let Some(header) = bytes.get(..RECORD_HEADER_LEN) else {
+        return Decoded::Incomplete;
+    };
+    let key_len = u32::from_le_bytes(header[17..21].try_into().unwrap()) as usize;
+    if key_len as u64 > RESTART_BYTES {
+        return Decoded::Corrupt;
+    }
+    let body_len = RECORD_HEADER_LEN + key_len;
+    let Some(record) = bytes.get(..body_len + CHECKSUM_LEN) else {
+        return Decoded::Incomplete;
+    };
+    let checksum = xxhash_rust::xxh3::xxh3_64(&record[..body_len]);
+    if record[body_len..] != checksum.to_le_bytes() {
+        return Decoded::Corrupt;
+    }
+    let Ok(key) = std::str::from_utf8(&record[RECORD_HEADER_LEN..body_len]) else {
+        return Decoded::Corrupt;
+    };
+    Decoded::Record {
+        seq: u64::from_le_bytes(header[1..9].try_into().unwrap()),
+        origin: u64::from_le_bytes(header[9..17].try_into().unwrap()),
+        kind: header[0],
+        key: key.to_string(),
+        len: body_len + CHECKSUM_LEN,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handles_read_each_others_events_but_not_their_own() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut first = EventLog::open(temp_dir.path()).unwrap();
+        let mut second = EventLog::open(temp_dir.path()).unwrap();
+
+        first
+            .append(&[Event::Set("a"), Event::Delete("b")])
+            .unwrap();
+        second.append(&[Event::Set("c")]).unwrap();
+        assert_eq!(
+            second.catch_up().unwrap(),
+            Some(Invalidation::Keys(vec!["a".into(), "b".into()]))
+        );
+        assert_eq!(second.catch_up().unwrap(), None);
+        assert_eq!(
+            first.catch_up().unwrap(),
+            Some(Invalidation::Keys(vec!["c".into()]))
+        );
+
+        second.append(&[Event::Clear]).unwrap();
+        assert_eq!(first.catch_up().unwrap(), Some(Invalidation::All));
+    }
+
+    #[test]
+    fn followers_see_what_other_handles_change() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut own = EventLog::open(temp_dir.path()).unwrap();
+        let mut follower = own.follow(temp_dir.path()).unwrap();
+        let mut other = EventLog::open(temp_dir.path()).unwrap();
+
+        own.append(&[Event::Set("mine")]).unwrap();
+        other
+            .append(&[Event::Set("a"), Event::Expire("b"), Event::Clear])
+            .unwrap();
+        assert_eq!(
+            follower.changes().unwrap(),
+            vec![
+                Change::new(ChangeKind::Set, "a"),
+                Change::new(ChangeKind::Expire, "b"),
+                Change::new(ChangeKind::Clear, ""),
+            ]
+        );
+        assert_eq!(follower.changes().unwrap(), vec![]);
+        // The follower's reads leave the handle it follows where it was
+        assert_eq!(own.catch_up().unwrap(), Some(Invalidation::All));
+    }
+
+    #[test]
+    fn readers_left_behind_by_a_restart_drop_everything() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let mut writer = EventLog::open(temp_dir.path()).unwrap();
+        let mut reader = EventLog::open(temp_dir.path()).unwrap();
+
+        let key = "k".repeat(64 * 1024);
+        while std::fs::metadata(temp_dir.path().join(EVENT_LOG_FILE))
+            .unwrap()
+            .len()
+            < RESTART_BYTES
+        {
+            writer.append(&[Event::Set(&key)]).unwrap();
+        }
+        writer.append(&[Event::Set("after")]).unwrap();
+        assert_eq!(reader.catch_up().unwrap(), Some(Invalidation::All));
+
+        writer.append(&[Event::Delete("next")]).unwrap();
+        assert_eq!(
+            reader.catch_up().unwrap(),
+            Some(Invalidation::Keys(vec!["next".into()]))
+        );
+        // The writer started the log over before reading all of it
+        assert_eq!(writer.catch_up().unwrap(), Some(Invalidation::All));
+        let mut other = EventLog::open(temp_dir.path()).unwrap();
+        other.append(&[Event::Set("x")]).unwrap();
+        assert_eq!(
+            writer.catch_up().unwrap(),
+            Some(Invalidation::Keys(vec!["x".into()]))
+        );
+    }
+}
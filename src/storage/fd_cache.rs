@@ -0,0 +1,117 @@
+//! Open descriptors of recently read data files
+//!
+//! Every cold read of a data file would otherwise open it, stat it and close
+//! it again, even when the same few files are read over and over.
+//! [`OpenFiles`] keeps a bounded number of them open, dropping the least
+//! recently used first, and reads them with positional reads so threads can
+//! share a descriptor.
+//!
+//! A descriptor stays on the file it was opened on, so whatever removes or
+//! replaces a data file must [`OpenFiles::forget`] its path. A file rewritten
+//! by another process is caught by checking the descriptor against the size
+//! and checksum the index row expects.
+
+use lru::LruCache;
+use parking_lot::Mutex;
+use std::fs::File;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+struct OpenFile {
+    file: File,
+    len: u64,
+    /// Payload checksum of the index row the file was opened for
+    checksum: u32,
+}
+
+/// Bounded LRU cache of open data files, keyed by path
+pub struct OpenFiles {
+    /// `None` when caching is disabled
+    files: Option<Mutex<LruCache<PathBuf, Arc<OpenFile>>>>,
+}
+
+impl OpenFiles {
+    /// Cache of up to `capacity` open files; 0 opens every file afresh
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            files: NonZeroUsize::new(capacity).map(|capacity| Mutex::new(LruCache::new(capacity))),
+        }
+    }
+
+    /// Contents of the data file at `path`, expected to be `len` bytes long
+    /// and to hold a payload with `checksum`
+    pub fn read(&self, path: &Path, len: u64, checksum: u32) -> std::io::Result<Vec<u8>> {
+        let Some(files) = &self.files else {
+            return std::fs::read(path);
+        };
+        let cached = files
+            .lock()
+            .get(path)
+            .filter(|open| open.len == len && open.checksum == checksum)
+            .cloned();
+        let open = match cached {
+            Some(open) => open,
+            None => {
+                let file = File::open(path)?;
+                let open = Arc::new(OpenFile {
+                    len: file.metadata()?.len(),
+                    file,
+                    checksum,
+                });
+                // A file of another length is not the one the row describes
+                if open.len == len {
+                    files.lock().put(path.to_path_buf(), Arc::clone(&open));
+                }
+                open
+            }
+        };
+        read_whole(&open.file, open.len)
+    }
+
+    /// Close the file at `path`, which is about to be removed or replaced
+    pub fn forget(&self, path: &Path) {
+        if let Some(files) = &self.files {
+            files.lock().pop(path);
+        }
+    }
+
+    /// Close every cached file
+    pub fn clear(&self) {
+        if let Some(files) = &self.files {
+            files.lock().clear();
+        }
+    }
+
+    /// Files currently held open
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.files.as_ref().map_or(0, |files| files.lock().len())
+    }
+}
+
+/// The first `len` bytes of `file`, or fewer if it has been truncated since
+fn read_whole(file: &File, len: u64) -> std::io::Result<Vec<u8>> {
+    let mut data = vec![0; len as usize];
+    let mut filled = 0;
+    while filled < data.len() {
+        match read_at(file, &mut data[filled..], filled as u64) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    data.truncate(filled);
+    Ok(data)
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
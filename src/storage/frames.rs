@@ -0,0 +1,259 @@
+//! Seekable layout for large compressed values
+//!
+//! A framed payload is the value cut into [`FRAME_SIZE`] pieces, each
+//! compressed on its own, behind a table giving every frame's stored length
+//! and checksum:
+//!
+//! ```text
+//! [frame size u32][value length u64][frame count u32]
+//! [stored length u32][checksum u32] per frame
+//! [frame 0][frame 1]...
+//! ```
+//!
+//! All integers are little-endian. Reading a range of the value only
+//! decompresses the frames it covers, which [`FramedReader`] does lazily.
+
+use crate::error::{CacheError, CacheResult};
+use crate::storage::data_file::payload_checksum;
+use crate::storage::Compression;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Bytes of the value held by each frame but the last
+pub const FRAME_SIZE: usize = 256 * 1024;
+/// Frame size, value length and frame count
+const TABLE_HEADER_LEN: usize = 4 + 8 + 4;
+/// Stored length and checksum of one frame
+const TABLE_ENTRY_LEN: usize = 4 + 4;
+
+/// `data` cut into frames, each compressed with `codec` at `level`
+pub fn compress(data: &[u8], codec: Compression, level: i32) -> CacheResult<Vec<u8>> {
+    let frames = data
+        .chunks(FRAME_SIZE)
+        .map(|chunk| codec.compress(chunk, level))
+        .collect::<CacheResult<Vec<_>>>()?;
+    let stored_len = frames.iter().map(Vec::len).sum::<usize>();
+    let mut stored =
+        Vec::with_capacity(TABLE_HEADER_LEN + frames.len() * TABLE_ENTRY_LEN + stored_len);
+    stored.extend_from_slice(&(FRAME_SIZE as u32).to_le_bytes());
+    stored.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    stored.extend_from_slice(&(frames.len() as u32).to_le_bytes());
+    for frame in &frames {
+        stored.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        stored.extend_from_slice(&payload_checksum(frame).to_le_bytes());
+    }
+    for frame in &frames {
+        stored.extend_from_slice(frame);
+    }
+    Ok(stored)
+}
+
+/// The whole value `stored` holds, its frames decompressed with `codec`
+pub fn decompress(stored: &[u8], codec: Compression) -> CacheResult<Vec<u8>> {
+    let mut reader = FramedReader::new(std::io::Cursor::new(stored), codec)?;
+    let mut data = Vec::with_capacity(reader.len() as usize);
+    reader
+        .read_to_end(&mut data)
+        .map_err(|e| CacheError::Deserialization(format!("Decompression failed: {}", e)))?;
+    Ok(data)
+}
+
+/// Where one frame is stored, relative to the first
+struct Frame {
+    offset: u64,
+    len: u32,
+    checksum: u32,
+}
+
+/// Reader over a framed value that decompresses a frame only once a read
+/// reaches it, and can seek anywhere in the value
+///
+/// Every frame is checked against its checksum as it is decompressed; a
+/// mismatch fails the read with [`std::io::ErrorKind::InvalidData`].
+pub struct FramedReader<R> {
+    inner: R,
+    codec: Compression,
+    /// Position in `inner` of the first frame
+    start: u64,
+    frame_size: u64,
+    len: u64,
+    frames: Vec<Frame>,
+    pos: u64,
+    /// The last frame decompressed, by index
+    current: Option<(usize, Vec<u8>)>,
+}
+
+impl<R: Read + Seek> FramedReader<R> {
+    /// Reader over the framed value starting at `inner`'s position, its
+    /// frames compressed with `codec`
+    pub fn new(mut inner: R, codec: Compression) -> CacheResult<Self> {
+        let mut header = [0u8; TABLE_HEADER_LEN];
+        inner.read_exact(&mut header).map_err(truncated)?;
+        let frame_size = u32::from_le_bytes(header[..4].try_into().unwrap()) as u64;
+        let len = u64::from_le_bytes(header[4..12].try_into().unwrap());
+        let count = u32::from_le_bytes(header[12..].try_into().unwrap()) as u64;
+        if frame_size == 0 || count != len.div_ceil(frame_size) {
+            return Err(CacheError::Corruption(format!(
+                "frame table claims {} frames of {} bytes for a {} byte value",
+                count, frame_size, len
+            )));
+        }
+        let table_len = count * TABLE_ENTRY_LEN as u64;
+        let table_start = inner.stream_position()?;
+        if table_start + table_len > inner.seek(SeekFrom::End(0))? {
+            return Err(CacheError::Corruption("frame table is truncated".into()));
+        }
+        inner.seek(SeekFrom::Start(table_start))?;
+
+        let mut table = vec![0u8; table_len as usize];
+        inner.read_exact(&mut table).map_err(truncated)?;
+        let mut offset = 0;
+        let frames = table
+            .chunks_exact(TABLE_ENTRY_LEN)
+            .map(|entry| {
+                let len = u32::from_le_bytes(entry[..4].try_into().unwrap());
+                let frame = Frame {
+                    offset,
+                    len,
+                    checksum: u32::from_le_bytes(entry[4..].try_into().unwrap()),
+                };
+                offset += len as u64;
+                frame
+            })
+            .collect();
+        Ok(Self {
+            start: inner.stream_position()?,
+            inner,
+            codec,
+            frame_size,
+            len,
+            frames,
+            pos: 0,
+            current: None,
+        })
+    }
+
+    /// Length of the whole value
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The decompressed frame at `index`, read and checked if it is not the
+    /// one held already
+    fn frame(&mut self, index: usize) -> std::io::Result<&[u8]> {
+        if self.current.as_ref().map(|(held, _)| *held) != Some(index) {
+            let frame = &self.frames[index];
+            let mut stored = vec![0u8; frame.len as usize];
+            self.inner
+                .seek(SeekFrom::Start(self.start + frame.offset))?;
+            self.inner.read_exact(&mut stored)?;
+            if payload_checksum(&stored) != frame.checksum {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("frame {} failed its checksum", index),
+                ));
+            }
+            let data = self
+                .codec
+                .decompress(&stored)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            self.current = Some((index, data));
+        }
+        Ok(&self.current.as_ref().unwrap().1)
+    }
+}
+
+impl<R: Read + Seek> Read for FramedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.len {
+            return Ok(0);
+        }
+        let index = (self.pos / self.frame_size) as usize;
+        let within = (self.pos % self.frame_size) as usize;
+        let frame = self.frame(index)?;
+        let available = frame.get(within..).unwrap_or_default();
+        if available.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("frame {} is shorter than the frame table says", index),
+            ));
+        }
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for FramedReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        self.pos = target.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "seek to a negative position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
+
+fn truncated(e: std::io::Error) -> CacheError {
+    match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => {
+            CacheError::Corruption("frame table is truncated".into())
+        }
+        _ => CacheError::Io(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_only_decompress_the_frames_they_cover() {
+        let data = (0..FRAME_SIZE * 3 + 1234)
+            .map(|i| (i / 7 % 251) as u8)
+            .collect::<Vec<_>>();
+        for codec in [Compression::Lz4, Compression::Zstd] {
+            let stored = compress(&data, codec, 3).unwrap();
+            assert!(stored.len() < data.len() / 4);
+            assert_eq!(decompress(&stored, codec).unwrap(), data);
+
+            let mut reader = FramedReader::new(std::io::Cursor::new(&stored), codec).unwrap();
+            assert_eq!(reader.len(), data.len() as u64);
+            let start = FRAME_SIZE * 2 - 10;
+            reader.seek(SeekFrom::Start(start as u64)).unwrap();
+            let mut range = vec![0u8; 20];
+            reader.read_exact(&mut range).unwrap();
+            assert_eq!(range, data[start..start + 20]);
+            assert_eq!(reader.current.as_ref().unwrap().0, 2);
+
+            reader.seek(SeekFrom::End(-5)).unwrap();
+            let mut tail = Vec::new();
+            reader.read_to_end(&mut tail).unwrap();
+            assert_eq!(tail, data[data.len() - 5..]);
+        }
+
+        // A damaged frame fails only the reads that reach it
+        let mut stored = compress(&data, Compression::Lz4, 3).unwrap();
+        let last = stored.len() - 1;
+        stored[last] ^= 0xff;
+        let mut reader =
+            FramedReader::new(std::io::Cursor::new(&stored), Compression::Lz4).unwrap();
+        let mut head = vec![0u8; 100];
+        reader.read_exact(&mut head).unwrap();
+        reader.seek(SeekFrom::End(-1)).unwrap();
+        let err = reader.read(&mut head).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(decompress(&stored, Compression::Lz4).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! Cross-process write locks on keys for [`OptimizedStorage`](super::OptimizedStorage)
+//!
+//! A data file's path follows from its key, so processes writing the same
+//! key replace the same file. A writer holds the key's lock from removing or
+//! replacing its file until the index row is committed, which keeps the last
+//! file to land matching the last row to commit. Readers only take it after a
+//! read failed its checksum, to tell a value replaced mid-read from a
+//! corrupted one.
+//!
+//! Keys hash onto stripes, each a [`ProcessLock`] named by a file under
+//! `locks/`; how many stripes there are follows the [`LockGranularity`]. The
+//! lock is taken afresh for every acquisition, so threads of one process
+//! exclude each other the same way processes do; a thread already holding a
+//! stripe takes it again for free.
+//!
+//! With `use_file_locking`, readers also hold the key's lock, shared, while
+//! they read its file, so they never see one half replaced on a network
+//! filesystem but do not hold each other up.
+//!
+//! In SMB mode no key is ever locked: writers publish data files by renaming
+//! them into place, and a reader that finds a file not matching its row
+//! takes it for a miss.
+
+use crate::error::{CacheError, CacheResult};
+use crate::process_lock::{LockBackend, LockGranularity, ProcessLock};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+/// Directory holding the stripe lock files inside the cache directory
+pub const LOCK_DIR: &str = "locks";
+
+/// Directory under [`LOCK_DIR`] holding the stripes of per-entry locks
+const ENTRY_STRIPE_DIR: &str = "keys";
+
+thread_local! {
+    /// Stripe files this thread holds locked, and whether shared
+    static HELD: RefCell<HashMap<PathBuf, bool>> = RefCell::new(HashMap::new());
+}
+
+/// The key locks of one cache directory
+pub struct KeyLocks {
+    directory: PathBuf,
+    backend: LockBackend,
+    granularity: LockGranularity,
+    /// False for [`KeyLocks::unlocked`]
+    enabled: bool,
+}
+
+/// Stripes held locked until dropped
+pub struct KeyGuard {
+    held: Vec<(ProcessLock, PathBuf)>,
+}
+
+impl KeyLocks {
+    pub fn open(
+        cache_directory: &Path,
+        backend: LockBackend,
+        granularity: LockGranularity,
+    ) -> CacheResult<Self> {
+        let mut directory = cache_directory.join(LOCK_DIR);
+        if granularity == LockGranularity::Entry {
+            directory.push(ENTRY_STRIPE_DIR);
+        }
+        std::fs::create_dir_all(&directory)?;
+        Ok(Self {
+            directory,
+            backend,
+            granularity,
+            enabled: true,
+        })
+    }
+
+    /// Key locks whose guards hold nothing, for SMB mode
+    pub fn unlocked() -> Self {
+        Self {
+            directory: PathBuf::new(),
+            backend: LockBackend::File,
+            granularity: LockGranularity::Shard,
+            enabled: false,
+        }
+    }
+
+    /// Lock `key` against writers in this and every other process
+    pub fn lock(&self, key: &str) -> CacheResult<KeyGuard> {
+        self.lock_all([key])
+    }
+
+    /// Lock every key of a batch
+    ///
+    /// Stripes are taken in order, so writers of overlapping batches never
+    /// wait on each other in a cycle.
+    pub fn lock_all<'a>(&self, keys: impl IntoIterator<Item = &'a str>) -> CacheResult<KeyGuard> {
+        self.take(keys, false)
+    }
+
+    /// Lock `key` against writers only, sharing it with other readers
+    ///
+    /// A thread holding it shared must let go before locking it for a write.
+    pub fn lock_shared(&self, key: &str) -> CacheResult<KeyGuard> {
+        self.take([key], true)
+    }
+
+    fn take<'a>(
+        &self,
+        keys: impl IntoIterator<Item = &'a str>,
+        shared: bool,
+    ) -> CacheResult<KeyGuard> {
+        let mut guard = KeyGuard { held: Vec::new() };
+        if !self.enabled {
+            return Ok(guard);
+        }
+        let stripes: BTreeSet<String> = keys.into_iter().map(|key| self.stripe(key)).collect();
+        for stripe in stripes {
+            let path = self.directory.join(stripe);
+            match HELD.with(|held| held.borrow().get(&path).copied()) {
+                // Waiting on ourselves would never end
+                Some(true) if !shared => {
+                    return Err(CacheError::Io(std::io::Error::other(format!(
+                        "{:?} is held shared by this thread",
+                        path
+                    ))))
+                }
+                Some(_) => continue,
+                None => {}
+            }
+            let lock = if shared {
+                ProcessLock::acquire_shared(self.backend, &path)?
+            } else {
+                ProcessLock::acquire(self.backend, &path)?
+            };
+            HELD.with(|held| held.borrow_mut().insert(path.clone(), shared));
+            guard.held.push((lock, path));
+        }
+        Ok(guard)
+    }
+
+    /// Name of the lock file `key` hashes onto
+    fn stripe(&self, key: &str) -> String {
+        let hash = xxhash_rust::xxh3::xxh3_64(key.as_bytes());
+        match self.granularity {
+            LockGranularity::Entry => format!("{:04x}.lock", hash % (1 << 16)),
+            LockGranularity::Shard => format!("{:02x}.lock", hash % 256),
+            LockGranularity::Cache => "cache.lock".to_string(),
+        }
+    }
+}
+
+impl Drop for KeyGuard {
+    fn drop(&mut self) {
+        for (_lock, path) in self.held.drain(..) {
+            // Thread-local storage is gone during thread teardown
+            let _ = HELD.try_with(|held| held.borrow_mut().remove(&path));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn a_locked_key_holds_off_other_writers_until_released() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let locks = std::sync::Arc::new(
+            KeyLocks::open(temp_dir.path(), LockBackend::File, LockGranularity::Shard).unwrap(),
+        );
+
+        let guard = locks.lock("key").unwrap();
+        // Taken again by its holder, also as part of a batch
+        drop(locks.lock_all(["other", "key"]).unwrap());
+
+        let (locked_tx, locked_rx) = mpsc::channel();
+        let other = {
+            let locks = locks.clone();
+            std::thread::spawn(move || {
+                let _guard = locks.lock("key").unwrap();
+                locked_tx.send(()).unwrap();
+            })
+        };
+        assert!(locked_rx.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(guard);
+        locked_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        other.join().unwrap();
+    }
+
+    #[test]
+    fn readers_share_keys_and_granularity_decides_what_collides() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let locks = std::sync::Arc::new(
+            KeyLocks::open(temp_dir.path(), LockBackend::File, LockGranularity::Cache).unwrap(),
+        );
+
+        let reader = locks.lock_shared("key").unwrap();
+        let (locked_tx, locked_rx) = mpsc::channel();
+        let other = {
+            let locks = locks.clone();
+            std::thread::spawn(move || {
+                drop(locks.lock_shared("key").unwrap());
+                locked_tx.send(()).unwrap();
+                // One lock covers every key
+                let _guard = locks.lock("other").unwrap();
+                locked_tx.send(()).unwrap();
+            })
+        };
+        locked_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(locked_rx.recv_timeout(Duration::from_millis(100)).is_err());
+        // A shared holder cannot also write
+        assert!(locks.lock("key").is_err());
+        drop(reader);
+        locked_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        other.join().unwrap();
+
+        let locks =
+            KeyLocks::open(temp_dir.path(), LockBackend::File, LockGranularity::Entry).unwrap();
+        drop(locks.lock("key").unwrap());
+        assert!(temp_dir
+            .path()
+            .join(LOCK_DIR)
+            .join(ENTRY_STRIPE_DIR)
+            .read_dir()
+            .unwrap()
+            .any(|entry| entry.unwrap().path().extension() == Some("lock".as_ref())));
+    }
+}
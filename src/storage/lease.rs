@@ -0,0 +1,687 @@
+//! Single-writer lease for a cache directory shared by several processes
+//!
+//! One process holds the lease recorded in `writer.lease` and writes; every
+//! other process opens the directory read-only, and its writes fail with
+//! [`CacheError::ReadOnly`]. Its reads miss expired and unreadable entries
+//! without dropping them, which is left to the holder. The holder renews the lease from a heartbeat
+//! thread a few times per lease period. A lease that was not renewed in time,
+//! because its holder died or hung, is taken over by the next process that
+//! writes, so a dead writer does not leave the cache read-only for good.
+//!
+//! The lease file is only read and replaced under an exclusive lock on
+//! `writer.lease.lock`, held just long enough to do so, and replaced by
+//! rename, so readers never see half of one. Expiry times are wall-clock
+//! times, so hosts sharing a directory over the network need clocks that
+//! agree to well within a lease period.
+//!
+//! The holder is recorded as `pid@host-nonce`, so a forked child is not
+//! taken for its parent and writes read-only until it takes a lease of its
+//! own. A lease whose holder on this
+//! host has exited is free at once, and the process registry releases the
+//! lease of a holder elsewhere once that holder's registration goes stale.
+//!
+//! [`MaintenanceLease`] elects a maintenance leader the same way through
+//! `maintenance.lease`, without a heartbeat: the holder renews it whenever
+//! its maintenance comes due, and once it stops doing so the next process
+//! whose maintenance comes due takes over.
+
+use crate::error::{CacheError, CacheResult};
+use crate::fork::ForkCheck;
+use crate::process_lock::{host_name, process_alive};
+use crate::serialization::CacheEntry;
+use crate::storage::{
+    DataFileWriter, ExpiryListener, Footprint, FramedReader, IoStats, MaintenanceGate,
+    RecoveryReport, StorageBackend, TierSizes, UnlinkProgress, VacuumReport,
+};
+use fs4::fs_std::FileExt;
+use parking_lot::{Condvar, Mutex};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const LEASE_FILE: &str = "writer.lease";
+const LEASE_LOCK_FILE: &str = "writer.lease.lock";
+const MAINTENANCE_FILE: &str = "maintenance.lease";
+const MAINTENANCE_LOCK_FILE: &str = "maintenance.lease.lock";
+/// Renewals per lease period, so a late heartbeat or two do not lose it
+const RENEWALS_PER_PERIOD: u32 = 3;
+
+/// Storage backend that only writes while this process holds the writer lease
+pub struct WriterLease {
+    inner: Arc<dyn StorageBackend>,
+    lease: Arc<Lease>,
+    heartbeat: Mutex<Option<Heartbeat>>,
+    fork: ForkCheck,
+}
+
+/// This process's view of the lease file
+struct Lease {
+    path: PathBuf,
+    lock_path: PathBuf,
+    /// Tells this opener from others in the same process
+    nonce: String,
+    period: Duration,
+    /// Milliseconds since the epoch our claim runs until; 0 when not held
+    held_until: AtomicU64,
+}
+
+/// The thread renewing a held lease
+struct Heartbeat {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Holder and expiry recorded in a lease file, if there is one
+fn read_lease(path: &Path) -> CacheResult<Option<(String, u64)>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    // A garbled lease counts as expired
+    Ok(contents
+        .split_once(' ')
+        .map(|(holder, expires)| (holder.to_string(), expires.trim().parse().unwrap_or(0))))
+}
+
+/// Process id and host of a lease holder
+fn holder_process(holder: &str) -> Option<(u32, &str)> {
+    let (pid, rest) = holder.split_once('@')?;
+    let (host, _nonce) = rest.rsplit_once('-')?;
+    Some((pid.parse().ok()?, host))
+}
+
+/// Whether the holder of a lease was a process on this host that has exited
+fn holder_exited(holder: &str) -> bool {
+    holder_process(holder).is_some_and(|(pid, host)| host == host_name() && !process_alive(pid))
+}
+
+/// Release the writer and maintenance leases of `directory` that process
+/// `pid` on `host` holds, once the process registry has found that process
+/// gone; returns whether it held any
+pub fn release_abandoned(directory: &Path, pid: u32, host: &str) -> CacheResult<bool> {
+    let mut released = false;
+    for (file, lock_file) in [
+        (LEASE_FILE, LEASE_LOCK_FILE),
+        (MAINTENANCE_FILE, MAINTENANCE_LOCK_FILE),
+    ] {
+        let path = directory.join(file);
+        if !path.exists() {
+            continue;
+        }
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(directory.join(lock_file))?;
+        FileExt::lock_exclusive(&lock)?;
+        if matches!(read_lease(&path)?, Some((holder, _)) if holder_process(&holder) == Some((pid, host)))
+        {
+            std::fs::remove_file(&path)?;
+            released = true;
+        }
+    }
+    Ok(released)
+}
+
+impl Lease {
+    fn new(directory: &Path, file: &str, lock_file: &str, period: Duration) -> Self {
+        Self {
+            path: directory.join(file),
+            lock_path: directory.join(lock_file),
+            nonce: uuid::Uuid::new_v4().simple().to_string(),
+            period,
+            held_until: AtomicU64::new(0),
+        }
+    }
+
+    /// Who we are in the lease file
+    fn holder(&self) -> String {
+        format!("{}@{}-{}", std::process::id(), host_name(), self.nonce)
+    }
+
+    /// Record this process as the holder for another period, unless another
+    /// process holds an unexpired lease; returns whether we hold it now
+    fn claim(&self) -> CacheResult<bool> {
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&self.lock_path)?;
+        FileExt::lock_exclusive(&lock)?;
+
+        let now = now_millis();
+        if let Some((holder, expires)) = read_lease(&self.path)? {
+            if holder != self.holder() && expires > now && !holder_exited(&holder) {
+                self.held_until.store(0, Ordering::Release);
+                return Ok(false);
+            }
+        }
+        let expires = now + self.period.as_millis() as u64;
+        let staged = self.path.with_extension("lease.tmp");
+        {
+            let mut file = File::create(&staged)?;
+            writeln!(file, "{} {}", self.holder(), expires)?;
+            file.sync_all()?;
+        }
+        std::fs::rename(&staged, &self.path)?;
+        self.held_until.store(expires, Ordering::Release);
+        Ok(true)
+    }
+
+    /// Give the lease up, if we still hold it
+    fn release(&self) -> CacheResult<()> {
+        if self.held_until.swap(0, Ordering::AcqRel) == 0 {
+            return Ok(());
+        }
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&self.lock_path)?;
+        FileExt::lock_exclusive(&lock)?;
+        if matches!(read_lease(&self.path)?, Some((holder, _)) if holder == self.holder()) {
+            std::fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+
+    /// Whether our last claim has not run out yet
+    fn is_held(&self) -> bool {
+        now_millis() < self.held_until.load(Ordering::Acquire)
+    }
+}
+
+impl WriterLease {
+    /// Take the writer lease of `directory` for `period` if it is free, or
+    /// open read-only if another process holds it
+    pub fn open(
+        directory: &Path,
+        period: Duration,
+        inner: Arc<dyn StorageBackend>,
+    ) -> CacheResult<Self> {
+        let writer = Self {
+            inner,
+            lease: Arc::new(Lease::new(directory, LEASE_FILE, LEASE_LOCK_FILE, period)),
+            heartbeat: Mutex::new(None),
+            fork: ForkCheck::new(),
+        };
+        // Reads of a process without the lease miss expired and unreadable
+        // entries but leave dropping them to the holder
+        let lease = writer.lease.clone();
+        writer.inner.gate_cleanup(Arc::new(move || lease.is_held()));
+        writer.try_acquire()?;
+        Ok(writer)
+    }
+
+    /// Whether this process holds the lease and may write
+    pub fn is_writer(&self) -> bool {
+        self.leave_parent_lease();
+        self.lease.is_held()
+    }
+
+    /// Fail with [`CacheError::ReadOnly`] unless this process holds the
+    /// lease, taking it over first if its holder let it run out
+    pub fn check_writable(&self) -> CacheResult<()> {
+        self.leave_parent_lease();
+        if self.lease.is_held() || self.try_acquire()? {
+            return Ok(());
+        }
+        Err(CacheError::ReadOnly(format!(
+            "another process holds the writer lease in {:?}",
+            self.lease.path
+        )))
+    }
+
+    /// Stop renewing and give the lease up; later writes fail unless the
+    /// lease is taken again
+    pub fn close(&self) {
+        self.leave_parent_lease();
+        drop(self.heartbeat.lock().take());
+        if let Err(e) = self.lease.release() {
+            tracing::warn!("Failed to release the writer lease: {}", e);
+        }
+    }
+
+    /// In a forked child, drop the claim inherited from the parent, whose
+    /// heartbeat thread does not exist here to be stopped
+    fn leave_parent_lease(&self) {
+        if self.fork.forked() {
+            std::mem::forget(self.heartbeat.lock().take());
+            self.lease.held_until.store(0, Ordering::Release);
+        }
+    }
+
+    fn try_acquire(&self) -> CacheResult<bool> {
+        let mut heartbeat = self.heartbeat.lock();
+        if self.lease.is_held() {
+            return Ok(true);
+        }
+        if !self.lease.claim()? {
+            return Ok(false);
+        }
+        // A heartbeat that lost the lease has stopped on its own
+        drop(heartbeat.take());
+        *heartbeat = Some(Heartbeat::start(self.lease.clone()));
+        Ok(true)
+    }
+}
+
+impl Heartbeat {
+    fn start(lease: Arc<Lease>) -> Self {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread = {
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                let interval = lease.period / RENEWALS_PER_PERIOD;
+                let (stopped, wake) = &*stop;
+                let mut stopped = stopped.lock();
+                while !*stopped {
+                    wake.wait_for(&mut stopped, interval);
+                    if *stopped {
+                        break;
+                    }
+                    match lease.claim() {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            tracing::warn!(
+                                "Lost the writer lease in {:?} to another process",
+                                lease.path
+                            );
+                            break;
+                        }
+                        // Retried at the next beat; writes stop once it runs out
+                        Err(e) => tracing::warn!("Failed to renew the writer lease: {}", e),
+                    }
+                }
+            })
+        };
+        Self {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        let (stopped, wake) = &*self.stop;
+        *stopped.lock() = true;
+        wake.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for WriterLease {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Lease on the maintenance of a directory shared by several processes
+///
+/// Only its holder runs the automatic vacuum, expiry sweep and segment
+/// compaction, so processes sharing the directory do not all do the same
+/// work at once and trip over each other.
+pub struct MaintenanceLease {
+    lease: Lease,
+    fork: ForkCheck,
+}
+
+impl MaintenanceLease {
+    /// Take the maintenance lease of `directory` for `period` if it is free
+    pub fn open(directory: &Path, period: Duration) -> CacheResult<Self> {
+        let maintenance = Self {
+            lease: Lease::new(directory, MAINTENANCE_FILE, MAINTENANCE_LOCK_FILE, period),
+            fork: ForkCheck::new(),
+        };
+        maintenance.lease.claim()?;
+        Ok(maintenance)
+    }
+
+    /// Whether this process holds the lease, without trying to take it
+    pub fn is_held(&self) -> bool {
+        self.leave_parent_lease();
+        self.lease.is_held()
+    }
+
+    /// Renew the lease, or take it over if its holder let it run out;
+    /// returns whether this process holds it now
+    pub fn claim(&self) -> CacheResult<bool> {
+        self.leave_parent_lease();
+        self.lease.claim()
+    }
+
+    /// The lease period
+    pub fn period(&self) -> Duration {
+        self.lease.period
+    }
+
+    /// Give the lease up, if this process holds it; the next process whose
+    /// maintenance comes due takes it
+    pub fn close(&self) {
+        self.leave_parent_lease();
+        if let Err(e) = self.lease.release() {
+            tracing::warn!("Failed to release the maintenance lease: {}", e);
+        }
+    }
+
+    /// A forked child does not hold its parent's claim
+    fn leave_parent_lease(&self) {
+        if self.fork.forked() {
+            self.lease.held_until.store(0, Ordering::Release);
+        }
+    }
+}
+
+impl Drop for MaintenanceLease {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl StorageBackend for WriterLease {
+    fn get(&self, key: &str) -> CacheResult<Option<CacheEntry>> {
+        self.inner.get(key)
+    }
+
+    fn peek(&self, key: &str) -> CacheResult<Option<CacheEntry>> {
+        self.inner.peek(key)
+    }
+
+    fn get_many(&self, keys: &[String]) -> CacheResult<Vec<Option<CacheEntry>>> {
+        self.inner.get_many(keys)
+    }
+
+    fn prefetch_prefix(&self, prefix: &str) -> CacheResult<usize> {
+        self.inner.prefetch_prefix(prefix)
+    }
+
+    fn set(&self, key: &str, entry: CacheEntry) -> CacheResult<()> {
+        self.check_writable()?;
+        self.inner.set(key, entry)
+    }
+
+    fn set_batch(&self, entries: Vec<(String, Vec<u8>)>) -> CacheResult<()> {
+        self.check_writable()?;
+        self.inner.set_batch(entries)
+    }
+
+    fn delete(&self, key: &str) -> CacheResult<bool> {
+        self.check_writable()?;
+        self.inner.delete(key)
+    }
+
+    fn evict(&self, key: &str) -> CacheResult<bool> {
+        self.check_writable()?;
+        self.inner.evict(key)
+    }
+
+    fn exists(&self, key: &str) -> CacheResult<bool> {
+        self.inner.exists(key)
+    }
+
+    fn exists_local(&self, key: &str) -> CacheResult<bool> {
+        self.inner.exists_local(key)
+    }
+
+    fn version(&self, key: &str) -> CacheResult<Option<u64>> {
+        self.inner.version(key)
+    }
+
+    fn set_alias(&self, alias: &str, key: &str) -> CacheResult<()> {
+        self.check_writable()?;
+        self.inner.set_alias(alias, key)
+    }
+
+    fn resolve_alias(&self, alias: &str) -> CacheResult<Option<String>> {
+        self.inner.resolve_alias(alias)
+    }
+
+    fn remove_alias(&self, alias: &str) -> CacheResult<bool> {
+        self.check_writable()?;
+        self.inner.remove_alias(alias)
+    }
+
+    fn aliases(&self, key: &str) -> CacheResult<Vec<String>> {
+        self.inner.aliases(key)
+    }
+
+    fn keys(&self) -> CacheResult<Vec<String>> {
+        self.inner.keys()
+    }
+
+    fn peek_key(&self, last: bool) -> CacheResult<Option<String>> {
+        self.inner.peek_key(last)
+    }
+
+    fn key_page(
+        &self,
+        cursor: Option<i64>,
+        reverse: bool,
+        limit: usize,
+    ) -> CacheResult<Vec<(i64, String)>> {
+        self.inner.key_page(cursor, reverse, limit)
+    }
+
+    fn peek_key_in_range(&self, start: &str, end: &str, last: bool) -> CacheResult<Option<String>> {
+        self.inner.peek_key_in_range(start, end, last)
+    }
+
+    fn clear(&self) -> CacheResult<()> {
+        self.check_writable()?;
+        self.inner.clear()
+    }
+
+    fn clear_with_progress(&self, progress: &(dyn Fn(UnlinkProgress) + Sync)) -> CacheResult<()> {
+        self.check_writable()?;
+        self.inner.clear_with_progress(progress)
+    }
+
+    fn vacuum(&self) -> CacheResult<VacuumReport> {
+        self.check_writable()?;
+        self.inner.vacuum()
+    }
+
+    fn generate_filename(&self, key: &str) -> String {
+        self.inner.generate_filename(key)
+    }
+
+    fn write_data_file(&self, filename: &str, data: &[u8]) -> CacheResult<()> {
+        self.check_writable()?;
+        self.inner.write_data_file(filename, data)
+    }
+
+    fn read_data_file(&self, filename: &str) -> CacheResult<Vec<u8>> {
+        self.inner.read_data_file(filename)
+    }
+
+    fn data_file_path(&self, key: &str) -> CacheResult<Option<(PathBuf, u64)>> {
+        self.inner.data_file_path(key)
+    }
+
+    fn open_frames(&self, key: &str) -> CacheResult<Option<FramedReader<File>>> {
+        self.inner.open_frames(key)
+    }
+
+    fn link_file(&self, key: &str, source: &Path) -> CacheResult<u64> {
+        self.check_writable()?;
+        self.inner.link_file(key, source)
+    }
+
+    fn write_stream(&self, key: &str) -> CacheResult<DataFileWriter> {
+        self.check_writable()?;
+        self.inner.write_stream(key)
+    }
+
+    fn commit_stream(&self, writer: DataFileWriter) -> CacheResult<u64> {
+        self.check_writable()?;
+        self.inner.commit_stream(writer)
+    }
+
+    fn register_files(&self, files: &[(String, PathBuf)]) -> CacheResult<u64> {
+        self.check_writable()?;
+        self.inner.register_files(files)
+    }
+
+    fn watch_expirations(&self, listener: ExpiryListener) {
+        self.inner.watch_expirations(listener)
+    }
+
+    fn set_pinned(&self, key: &str, pinned: bool) {
+        self.inner.set_pinned(key, pinned)
+    }
+
+    fn remove_expired(&self) -> CacheResult<u64> {
+        self.check_writable()?;
+        self.inner.remove_expired()
+    }
+
+    fn gate_maintenance(&self, gate: MaintenanceGate) {
+        self.inner.gate_maintenance(gate)
+    }
+
+    fn was_unclean_shutdown(&self) -> Option<bool> {
+        self.inner.was_unclean_shutdown()
+    }
+
+    fn after_fork(&self) {
+        self.leave_parent_lease();
+        self.inner.after_fork();
+    }
+
+    fn verify_and_recover(&self) -> CacheResult<RecoveryReport> {
+        self.check_writable()?;
+        self.inner.verify_and_recover()
+    }
+
+    fn estimate_footprint(&self, entries: u64, key_size: usize, value_size: usize) -> Footprint {
+        self.inner.estimate_footprint(entries, key_size, value_size)
+    }
+
+    fn io_stats(&self) -> IoStats {
+        self.inner.io_stats()
+    }
+
+    fn size(&self) -> Option<TierSizes> {
+        self.inner.size()
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self.inner.as_any()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::OptimizedStorage;
+    use tempfile::TempDir;
+
+    fn open(directory: &Path, period: Duration) -> WriterLease {
+        let storage = Arc::new(OptimizedStorage::new(directory).unwrap());
+        WriterLease::open(directory, period, storage).unwrap()
+    }
+
+    #[test]
+    fn one_process_at_a_time_leads_maintenance() {
+        let temp_dir = TempDir::new().unwrap();
+        let period = Duration::from_secs(30);
+        let leader = MaintenanceLease::open(temp_dir.path(), period).unwrap();
+        let other = MaintenanceLease::open(temp_dir.path(), period).unwrap();
+        assert!(leader.is_held());
+        assert!(!other.is_held());
+        assert!(leader.claim().unwrap());
+        assert!(!other.claim().unwrap());
+
+        // Given up, it goes to the next claimant
+        leader.close();
+        assert!(!leader.is_held());
+        assert!(other.claim().unwrap());
+        assert!(!leader.claim().unwrap());
+    }
+
+    #[test]
+    fn only_the_lease_holder_writes() {
+        let temp_dir = TempDir::new().unwrap();
+        let writer = open(temp_dir.path(), Duration::from_secs(30));
+        let reader = open(temp_dir.path(), Duration::from_secs(30));
+        assert!(writer.is_writer());
+        assert!(!reader.is_writer());
+
+        let entry = CacheEntry::new_inline("key".into(), b"value".to_vec(), vec![], None);
+        writer.set("key", entry).unwrap();
+        assert!(matches!(reader.delete("key"), Err(CacheError::ReadOnly(_))));
+        assert!(reader.get("key").unwrap().is_some());
+
+        // A lease given up goes to the next process that writes
+        writer.close();
+        assert!(reader.delete("key").unwrap());
+        assert!(reader.is_writer());
+        assert!(matches!(writer.delete("key"), Err(CacheError::ReadOnly(_))));
+    }
+
+    #[test]
+    fn only_the_lease_holder_drops_expired_entries() {
+        let temp_dir = TempDir::new().unwrap();
+        let writer = open(temp_dir.path(), Duration::from_secs(30));
+        let reader = open(temp_dir.path(), Duration::from_secs(30));
+        let rows = || {
+            rusqlite::Connection::open(temp_dir.path().join("index.sqlite3"))
+                .unwrap()
+                .query_row("SELECT COUNT(*) FROM cache_index", [], |row| {
+                    row.get::<_, i64>(0)
+                })
+                .unwrap()
+        };
+
+        let entry = CacheEntry::new_inline("key".into(), b"value".to_vec(), vec![], Some(1));
+        writer.set("key", entry).unwrap();
+        assert!(reader.get("key").unwrap().is_none());
+        assert!(reader.peek("key").unwrap().is_none());
+        assert!(reader.get_many(&["key".into()]).unwrap()[0].is_none());
+        assert_eq!(rows(), 1);
+
+        assert!(writer.get("key").unwrap().is_none());
+        assert_eq!(rows(), 0);
+    }
+
+    #[test]
+    fn a_lease_that_is_not_renewed_is_taken_over() {
+        let temp_dir = TempDir::new().unwrap();
+        let period = Duration::from_millis(300);
+        let writer = open(temp_dir.path(), period);
+        let reader = open(temp_dir.path(), period);
+
+        // Renewed by the heartbeat past its first period
+        std::thread::sleep(period * 2);
+        assert!(writer.is_writer());
+        assert!(matches!(
+            reader.check_writable(),
+            Err(CacheError::ReadOnly(_))
+        ));
+
+        // A hung writer's lease runs out
+        drop(writer.heartbeat.lock().take());
+        std::thread::sleep(period + Duration::from_millis(100));
+        assert!(!writer.is_writer());
+        reader.check_writable().unwrap();
+        assert!(matches!(
+            writer.check_writable(),
+            Err(CacheError::ReadOnly(_))
+        ));
+    }
+}
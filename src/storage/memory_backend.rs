@@ -0,0 +1,263 @@
+//! Storage backend that keeps everything in process memory
+//!
+//! Nothing is written to the filesystem, not even the cache directory, so the
+//! entries live exactly as long as the storage. Tests and short-lived workers
+//! get the same cache API and code path as a disk-backed cache without a
+//! directory to create and clean up.
+
+use crate::error::{CacheError, CacheResult};
+use crate::lock_order::{LockLevel, OrderedRwLock};
+use crate::serialization::{CacheEntry, StorageMode};
+use crate::storage::{Footprint, StorageBackend, VacuumReport};
+use std::collections::{BTreeMap, HashMap};
+
+/// Bookkeeping per entry besides its key and value: the entry itself and the
+/// nodes of both maps that reference it
+const ENTRY_OVERHEAD: u64 = 192;
+
+struct Stored {
+    seq: u64,
+    entry: CacheEntry,
+}
+
+#[derive(Default)]
+struct Entries {
+    /// Ordered by key, for range queries
+    by_key: BTreeMap<String, Stored>,
+    /// store sequence -> key
+    order: BTreeMap<u64, String>,
+    next_seq: u64,
+    /// alias -> key
+    aliases: HashMap<String, String>,
+    /// Contents of "data files" written through the backend
+    files: HashMap<String, Vec<u8>>,
+}
+
+impl Entries {
+    fn remove(&mut self, key: &str) -> bool {
+        let Some(stored) = self.by_key.remove(key) else {
+            return false;
+        };
+        self.order.remove(&stored.seq);
+        self.aliases.retain(|_, target| target != key);
+        true
+    }
+
+    fn insert(&mut self, key: &str, entry: CacheEntry) {
+        if let Some(stored) = self.by_key.get(key) {
+            self.order.remove(&stored.seq);
+        }
+        self.next_seq += 1;
+        let seq = self.next_seq;
+        self.order.insert(seq, key.to_string());
+        self.by_key.insert(key.to_string(), Stored { seq, entry });
+    }
+}
+
+/// Storage held entirely in memory and dropped with the instance
+pub struct MemoryStorage {
+    entries: OrderedRwLock<Entries>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self {
+            entries: OrderedRwLock::new(LockLevel::Index, Entries::default()),
+        }
+    }
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StorageBackend for MemoryStorage {
+    fn get(&self, key: &str) -> CacheResult<Option<CacheEntry>> {
+        Ok(self
+            .entries
+            .read()
+            .by_key
+            .get(key)
+            .map(|stored| stored.entry.clone()))
+    }
+
+    fn set(&self, key: &str, mut entry: CacheEntry) -> CacheResult<()> {
+        if let StorageMode::File(filename) = &entry.storage {
+            entry.storage = StorageMode::Inline(self.read_data_file(filename)?);
+        }
+        self.entries.write().insert(key, entry);
+        Ok(())
+    }
+
+    fn set_batch(&self, entries: Vec<(String, Vec<u8>)>) -> CacheResult<()> {
+        let mut stored = self.entries.write();
+        for (key, data) in entries {
+            let entry = CacheEntry::new_inline(key.clone(), data, vec![], None);
+            stored.insert(&key, entry);
+        }
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> CacheResult<bool> {
+        Ok(self.entries.write().remove(key))
+    }
+
+    fn exists(&self, key: &str) -> CacheResult<bool> {
+        Ok(self.entries.read().by_key.contains_key(key))
+    }
+
+    /// The entry's store sequence, which is never reused
+    fn version(&self, key: &str) -> CacheResult<Option<u64>> {
+        Ok(self.entries.read().by_key.get(key).map(|stored| stored.seq))
+    }
+
+    fn set_alias(&self, alias: &str, key: &str) -> CacheResult<()> {
+        let mut entries = self.entries.write();
+        if !entries.by_key.contains_key(key) {
+            return Err(CacheError::KeyNotFound(key.to_string()));
+        }
+        entries.aliases.insert(alias.to_string(), key.to_string());
+        Ok(())
+    }
+
+    fn resolve_alias(&self, alias: &str) -> CacheResult<Option<String>> {
+        Ok(self.entries.read().aliases.get(alias).cloned())
+    }
+
+    fn remove_alias(&self, alias: &str) -> CacheResult<bool> {
+        Ok(self.entries.write().aliases.remove(alias).is_some())
+    }
+
+    fn aliases(&self, key: &str) -> CacheResult<Vec<String>> {
+        Ok(self
+            .entries
+            .read()
+            .aliases
+            .iter()
+            .filter(|(_, target)| target.as_str() == key)
+            .map(|(alias, _)| alias.clone())
+            .collect())
+    }
+
+    fn keys(&self) -> CacheResult<Vec<String>> {
+        Ok(self.entries.read().by_key.keys().cloned().collect())
+    }
+
+    fn peek_key(&self, last: bool) -> CacheResult<Option<String>> {
+        let entries = self.entries.read();
+        let row = if last {
+            entries.order.last_key_value()
+        } else {
+            entries.order.first_key_value()
+        };
+        Ok(row.map(|(_, key)| key.clone()))
+    }
+
+    fn key_page(
+        &self,
+        cursor: Option<i64>,
+        reverse: bool,
+        limit: usize,
+    ) -> CacheResult<Vec<(i64, String)>> {
+        let entries = self.entries.read();
+        let to_pair = |(seq, key): (&u64, &String)| (*seq as i64, key.clone());
+        let page = match (cursor, reverse) {
+            (None, false) => entries.order.iter().take(limit).map(to_pair).collect(),
+            (None, true) => entries
+                .order
+                .iter()
+                .rev()
+                .take(limit)
+                .map(to_pair)
+                .collect(),
+            (Some(cursor), false) => entries
+                .order
+                .range(cursor.max(0) as u64 + 1..)
+                .take(limit)
+                .map(to_pair)
+                .collect(),
+            (Some(cursor), true) => entries
+                .order
+                .range(..cursor.max(0) as u64)
+                .rev()
+                .take(limit)
+                .map(to_pair)
+                .collect(),
+        };
+        Ok(page)
+    }
+
+    fn peek_key_in_range(&self, start: &str, end: &str, last: bool) -> CacheResult<Option<String>> {
+        if start >= end {
+            return Ok(None);
+        }
+        let entries = self.entries.read();
+        let mut keys = entries
+            .by_key
+            .range::<str, _>((
+                std::ops::Bound::Included(start),
+                std::ops::Bound::Excluded(end),
+            ))
+            .map(|(key, _)| key);
+        let key = if last { keys.next_back() } else { keys.next() };
+        Ok(key.cloned())
+    }
+
+    fn clear(&self) -> CacheResult<()> {
+        let mut entries = self.entries.write();
+        // Versions stay unique across a clear
+        *entries = Entries {
+            next_seq: entries.next_seq,
+            ..Entries::default()
+        };
+        Ok(())
+    }
+
+    fn vacuum(&self) -> CacheResult<VacuumReport> {
+        Ok(VacuumReport::default())
+    }
+
+    fn generate_filename(&self, key: &str) -> String {
+        format!("{}.dat", blake3::hash(key.as_bytes()).to_hex())
+    }
+
+    fn write_data_file(&self, filename: &str, data: &[u8]) -> CacheResult<()> {
+        self.entries
+            .write()
+            .files
+            .insert(filename.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    fn read_data_file(&self, filename: &str) -> CacheResult<Vec<u8>> {
+        self.entries
+            .read()
+            .files
+            .get(filename)
+            .cloned()
+            .ok_or_else(|| {
+                CacheError::Io(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("no data file {:?} in memory storage", filename),
+                ))
+            })
+    }
+
+    fn estimate_footprint(&self, entries: u64, key_size: usize, value_size: usize) -> Footprint {
+        // The key is held by both maps
+        let entry = (key_size as u64)
+            .saturating_mul(2)
+            .saturating_add(value_size as u64)
+            .saturating_add(ENTRY_OVERHEAD);
+        Footprint {
+            disk_bytes: 0,
+            memory_bytes: entries.saturating_mul(entry),
+        }
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
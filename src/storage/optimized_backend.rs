@@ -1,24 +1,110 @@
 use crate::error::{CacheError, CacheResult};
+use crate::fork::ForkCheck;
+use crate::lock_order::{LockLevel, OrderedMutex, OrderedMutexGuard, OrderedRwLock};
+use crate::process_lock::{LockBackend, LockGranularity};
 use crate::serialization::CacheEntry;
-use crate::storage::StorageBackend;
+use crate::storage::compression::{Dictionaries, DEFAULT_ZSTD_LEVEL, DICTIONARY_DIR};
+use crate::storage::data_file::{payload_checksum, DataFileHeader, DataFileWriter};
+use crate::storage::events::{Event, EventLog, Invalidation};
+use crate::storage::fd_cache::OpenFiles;
+use crate::storage::key_locks::KeyLocks;
+use crate::storage::remote::{RemoteTier, RemoteUploads};
+use crate::storage::segment::{PackedRef, SegmentStore, SEGMENT_DIR};
+use crate::storage::sharing::SharingRetry;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+use crate::storage::uring;
+use crate::storage::wal::{WalRecord, WalSyncPolicy, WriteAheadLog};
+use crate::storage::watcher::DirectoryWatcher;
+use crate::storage::{
+    CleanupGate, Compression, Durability, ExpiryListener, FileNaming, Footprint, FramedReader,
+    HotCachePolicy, IndexKey, IoStats, MaintenanceGate, QueueFullPolicy, RecoveryReport,
+    SnapshotReport, StorageBackend, TierSizes, UnlinkPool, UnlinkProgress, VacuumReport,
+};
+use crate::utils::{OpenMarker, SetupLock};
 use bytes::{Bytes, BytesMut};
 use dashmap::DashMap;
 use memmap2::Mmap;
 use parking_lot::{Mutex, RwLock};
 
-use rusqlite::{params, Connection, OptionalExtension};
+use rusqlite::{params, Connection, OptionalExtension, Transaction, TransactionBehavior};
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs::{File, OpenOptions};
-use std::io::{BufWriter, Write};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
 use std::sync::mpsc;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-const INDEX_TABLE_SQL: &str = "CREATE TABLE IF NOT EXISTS cache_index (key TEXT PRIMARY KEY, value BLOB NOT NULL, generation INTEGER NOT NULL DEFAULT 0)";
+const INDEX_TABLE_SQL: &str = "CREATE TABLE IF NOT EXISTS cache_index (key TEXT PRIMARY KEY, value BLOB NOT NULL, generation INTEGER NOT NULL DEFAULT 0, seq INTEGER)";
+const INDEX_SEQ_SQL: &str = "CREATE INDEX IF NOT EXISTS cache_index_seq ON cache_index (seq)";
+/// Last sequence number handed out, so deleting the newest rows never lets
+/// their numbers be reused
+const SEQ_TABLE_SQL: &str =
+    "CREATE TABLE IF NOT EXISTS cache_seq (id INTEGER PRIMARY KEY CHECK (id = 0), last INTEGER NOT NULL)";
+const SEQ_INIT_SQL: &str =
+    "INSERT OR IGNORE INTO cache_seq (id, last) SELECT 0, COALESCE(MAX(seq), 0) FROM cache_index";
+const SEQ_TRIGGER_SQL: &str =
+    "CREATE TRIGGER IF NOT EXISTS cache_index_seq_last AFTER INSERT ON cache_index \
+     BEGIN UPDATE cache_seq SET last = NEW.seq WHERE NEW.seq > last; END";
+const ALIAS_TABLE_SQL: &str =
+    "CREATE TABLE IF NOT EXISTS cache_alias (alias TEXT PRIMARY KEY, key TEXT NOT NULL)";
+const ALIAS_KEY_SQL: &str = "CREATE INDEX IF NOT EXISTS cache_alias_key ON cache_alias (key)";
+/// Upper bound on threads used for one batch of cold file reads
+const MAX_PARALLEL_READS: usize = 8;
+/// Alignment of the buffer, offset and length of every O_DIRECT write; 4 KiB
+/// covers the logical block size of current disks
+const DIRECT_IO_ALIGN: usize = 4096;
+/// Bytes staged per O_DIRECT write, so a large value never needs an aligned
+/// copy of its own
+const DIRECT_IO_CHUNK: usize = 1024 * 1024;
+/// Upper bound on entries loaded by one prefix prefetch
+const PREFETCH_LIMIT: usize = 256;
+/// Upper bound on stored bytes loaded by one prefix prefetch
+const PREFETCH_BYTES: u64 = 64 * 1024 * 1024;
+/// Log size that triggers a checkpoint on the next write
+const WAL_CHECKPOINT_BYTES: u64 = 64 * 1024 * 1024;
+/// Approximate SQLite cost of one index row beyond its key and value bytes
+/// (row header, B-tree cell pointers, rowid and the seq index entry)
+const INDEX_ROW_OVERHEAD: u64 = 48;
+/// Bytes of an encoded `FileInfo` besides the path itself, for an entry
+/// without tags or expiry
+const FILE_INFO_FIXED_SIZE: u64 = 38;
+/// Leading byte of an index value whose FileInfo carries a checksum
+const FILE_INFO_V2: u8 = 0xff;
+/// Leading byte of an index value whose FileInfo carries entry metadata
+const FILE_INFO_V3: u8 = 0xfe;
+/// Length of the `data/` prefix of a relative data file path
+const DATA_DIR_LEN: u64 = 5;
+/// Extra path length per fan-out level, `<2 hex>/`
+const FANOUT_LEVEL_LEN: u64 = 3;
+/// File under `data/` recording the fan-out its files are laid out for
+const DATA_LAYOUT_FILE: &str = ".layout";
+/// SQLite index inside the cache directory
+pub const INDEX_FILE: &str = "index.sqlite3";
+/// Most fan-out levels a data file path can have
+pub const MAX_DATA_FANOUT: usize = 4;
+/// In-memory cost of one map slot plus `String` and `Bytes`/`PathBuf` headers
+const MAP_ENTRY_OVERHEAD: u64 = 96;
+/// Filesystem allocation unit assumed for data files
+const FS_BLOCK_SIZE: u64 = 4096;
+/// Length of a packed value's index path, `pack://<segment>/<offset>`
+const PACKED_PATH_LEN: u64 = 24;
+
+/// Index row of a packed value: key, encoded row value, decoded info and location
+type PackedRow = (String, Vec<u8>, FileInfo, PackedRef);
+/// Index row to persist: key, info and the row's MAC when the index is signed
+type FileRow = (String, FileInfo, Option<[u8; 32]>);
+/// Inline value to persist: key, value and its entry metadata
+type InlineRow = (String, Bytes, EntryMeta);
+/// A raw index row: value, generation and MAC
+#[cfg(test)]
+type IndexRow = (Vec<u8>, i64, Option<Vec<u8>>);
+/// Next store-order sequence number; evaluated inside each write statement
+const NEXT_SEQ_SQL: &str = "(SELECT last + 1 FROM cache_seq)";
 
 /// High-performance optimized storage backend with multiple performance enhancements:
 /// - Memory-mapped files for large data
@@ -33,10 +119,14 @@ pub struct OptimizedStorage {
 
     // Multi-tier storage
     hot_cache: Arc<DashMap<String, HotEntry>>, // Frequently accessed inline data
-    warm_cache: Arc<DashMap<String, MmapEntry>>, // Memory-mapped files
-    cold_index: Arc<RwLock<DashMap<String, FileInfo>>>, // File metadata (in-memory cache)
+    hot_clock: AtomicU64, // Ticks once per use of a hot entry, or names where the next sweep starts
+    warm_cache: Arc<WarmCache>, // Memory-mapped files
+    warm_clock: AtomicU64, // Ticks once per use of a mapped file
+    cold_index: Arc<OrderedRwLock<DashMap<String, FileInfo>>>, // File metadata (in-memory cache)
+    open_files: Arc<OpenFiles>, // Descriptors of recently read data files
+    key_locks: KeyLocks,  // Writers of a key, across processes
 
-    index_db: Arc<Mutex<Connection>>,
+    index_db: Arc<OrderedMutex<Connection>>,
 
     // Performance optimizations
     #[allow(dead_code)]
@@ -48,52 +138,298 @@ pub struct OptimizedStorage {
 
     // Statistics
     stats: Arc<StorageStats>,
+    tier_bytes: TierBytes, // Live bytes per tier, for size()
+
+    // Unclean-shutdown detection
+    open_marker: Mutex<Option<OpenMarker>>,
+    was_unclean_shutdown: Option<bool>,
+
+    // Optional write-ahead log for crash-safe writes
+    wal: Option<OrderedMutex<WriteAheadLog>>,
+
+    // Zstd dictionaries small values are compressed with
+    dictionaries: Dictionaries,
+
+    // Pack files for values below `pack_threshold`, and their compaction
+    segments: Arc<SegmentStore>,
+    compactor: Arc<Compactor>,
+    compaction_thread: Mutex<Option<std::thread::JoinHandle<()>>>,
+
+    // Drops memory-tier entries whose data files other processes change
+    watcher: Mutex<Option<DirectoryWatcher>>,
+
+    // Changes made through this handle, and read from the others
+    events: Option<OrderedMutex<EventLog>>,
+
+    // Told about entries dropped for having expired
+    expirations: RwLock<Option<ExpiryListener>>,
+
+    // Evicted entries on their way to the remote tier
+    remote_uploads: Arc<RemoteUploads>,
+
+    // Kept in the hot and warm tiers however full they get
+    pinned: RwLock<HashSet<String>>,
+
+    // Asked before a background compaction starts
+    maintenance: RwLock<Option<MaintenanceGate>>,
+
+    // Asked before a read drops an entry it found expired or unreadable
+    cleanup: RwLock<Option<CleanupGate>>,
+
+    // Notices being used in a forked child, which has none of our threads
+    fork: ForkCheck,
 }
 
 #[derive(Clone)]
 pub struct StorageConfig {
-    pub hot_cache_size: usize,  // Max entries in hot cache
-    pub warm_cache_size: usize, // Max memory-mapped files
-    #[allow(dead_code)]
+    pub hot_cache_size: usize,            // Max entries in hot cache
+    pub hot_cache_policy: HotCachePolicy, // Which hot entries go once there are too many
+    pub warm_cache_size: usize,           // Max memory-mapped files
+    pub warm_cache_bytes: u64,            // Max bytes of memory-mapped files
+    pub open_files: usize, // Max data files kept open between reads; 0 reopens each time
     pub mmap_threshold: usize, // Size threshold for memory mapping
-    pub batch_size: usize,      // Write batch size
+    pub batch_size: usize, // Write batch size
+    pub write_queue_capacity: usize, // Most writes queued for the background writer
+    pub queue_full: QueueFullPolicy, // What a write does when the queue is full
     pub compression_threshold: usize, // Size threshold for compression
     pub use_compression: bool,
+    pub compression: Compression, // Codec new values are compressed with
+    pub compression_level: i32,   // Zstd level; other codecs have none
+    pub zstd_dictionary: bool,    // Compress small values with a dictionary trained on them
+    pub dictionary_threshold: usize, // Values below this use the dictionary
+    pub seekable_compression: bool, // Compress data files in frames that can be read alone
     pub sync_writes: bool,
+    pub durability: Durability, // How far writes get before they return
     pub disk_write_threshold: usize, // Size threshold for writing to disk (vs inline SQLite)
-    pub use_file_locking: bool,      // Enable file locking for NFS scenarios
+    pub use_file_locking: bool, // NFS-safe locking: lock files instead of flock, DELETE journal
+    pub wal: Option<WalSyncPolicy>, // Log writes ahead of applying them
+    pub pack_threshold: usize, // Values below this that miss the index share segment files; 0 disables
+    pub segment_size: u64,     // Size at which a segment file stops taking appends
+    pub compaction_ratio: f64, // Dead share of segment bytes that triggers compaction
+    pub data_fanout: usize,    // Directory levels under data/, two hex digits each; 0 is flat
+    pub file_naming: FileNaming, // How data files are named after their keys
+    pub unlink_workers: usize, // Threads removing data files on clear
+    pub unlink_rate: u64,      // Most data files removed per second on clear; 0 is unlimited
+    pub index_key: Option<IndexKey>, // Sign index rows and refuse rows that fail the check
+    pub atomic_writes: bool, // Write data files under a temporary name, then rename them into place
+    pub group_commit: Option<Duration>, // Gather fsynced writes for this long and sync them together
+    pub direct_io_threshold: Option<usize>, // Data files at least this large bypass the page cache
+    pub orphan_grace: Duration, // Least age of a data file vacuum may delete or adopt as an orphan
+    pub remote_tier: Option<Arc<dyn RemoteTier>>, // Object store evicted entries are pushed to
+    pub watch_directory: bool, // Drop memory-tier entries as other processes change their data files
+    pub event_log: bool, // Share sets and deletes through events.log and drop what other handles change
+    pub lock_backend: LockBackend, // What the setup and key locks shared with other processes are
+    pub lock_granularity: LockGranularity, // How many keys share a key lock
+    pub smb_mode: bool, // No byte-range or key locks; data files are published by rename and never kept open
+    pub sharing_retry: SharingRetry, // How file operations failing with a sharing violation are retried
+}
+
+impl StorageConfig {
+    /// Whether the directory may be on a network filesystem whose locks
+    /// and shared memory cannot be trusted
+    fn network_safe(&self) -> bool {
+        self.use_file_locking || self.smb_mode
+    }
 }
 
 impl Default for StorageConfig {
     fn default() -> Self {
         Self {
             hot_cache_size: 10_000,
+            hot_cache_policy: HotCachePolicy::Lru,
             warm_cache_size: 1_000,
+            warm_cache_bytes: 1 << 30, // 1GB
+            open_files: 256,
             mmap_threshold: 64 * 1024, // 64KB
             batch_size: 100,
+            write_queue_capacity: 1024,
+            queue_full: QueueFullPolicy::Block,
             compression_threshold: 32 * 1024, // 32KB
             use_compression: true,
+            compression: Compression::Lz4,
+            compression_level: DEFAULT_ZSTD_LEVEL,
+            zstd_dictionary: false,
+            dictionary_threshold: 8 * 1024,
+            seekable_compression: false,
             sync_writes: false,
+            durability: Durability::Flush,
             disk_write_threshold: 32 * 1024, // 32KB - smaller data stays inline in SQLite
             use_file_locking: false,         // Disabled by default for performance
+            wal: None,
+            pack_threshold: 0,
+            segment_size: 64 * 1024 * 1024,
+            compaction_ratio: 0.5,
+            data_fanout: 2,
+            file_naming: FileNaming::Blake3,
+            unlink_workers: 8,
+            unlink_rate: 0,
+            index_key: None,
+            atomic_writes: true,
+            group_commit: None,
+            direct_io_threshold: None,
+            orphan_grace: Duration::from_secs(3600),
+            remote_tier: None,
+            watch_directory: false,
+            event_log: false,
+            lock_backend: LockBackend::File,
+            lock_granularity: LockGranularity::Shard,
+            smb_mode: false,
+            sharing_retry: SharingRetry::default(),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 struct HotEntry {
     data: Bytes,
     generation: i64,
+    meta: EntryMeta,
+    /// Tick of the hot tier's clock the entry was last used at, or under
+    /// [`HotCachePolicy::Clock`] 1 if it was used since the last sweep
+    last_used: AtomicU64,
 }
 
-#[allow(dead_code)]
+impl Clone for HotEntry {
+    fn clone(&self) -> Self {
+        Self {
+            data: self.data.clone(),
+            generation: self.generation,
+            meta: self.meta.clone(),
+            last_used: AtomicU64::new(self.last_used.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl HotEntry {
+    fn new(data: Bytes, generation: i64, meta: EntryMeta) -> Self {
+        Self {
+            data,
+            generation,
+            meta,
+            last_used: AtomicU64::new(0),
+        }
+    }
+
+    fn to_entry(&self, key: &str) -> CacheEntry {
+        self.meta.entry(key, self.data.to_vec())
+    }
+}
+
+/// A data file mapped into memory by the warm tier
+///
+/// Another process can replace or rewrite the file behind the mapping, so
+/// it is only served while the file on disk keeps the identity it was mapped
+/// with and the index row still expects the same payload.
 #[derive(Debug)]
 struct MmapEntry {
-    mmap: Mmap,
-    size: usize,
+    data: Bytes,
+    path: PathBuf,
+    /// Payload checksum of the index row the file was mapped for
+    checksum: u32,
+    identity: FileIdentity,
+    /// Tick of the warm tier's clock the mapping was last used at
     last_accessed: AtomicU64,
 }
 
+/// Files the warm tier maps, and the bytes they span
+#[derive(Debug)]
+struct WarmCache {
+    entries: DashMap<String, MmapEntry>,
+    bytes: AtomicU64,
+}
+
+impl WarmCache {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: DashMap::with_capacity(capacity),
+            bytes: AtomicU64::new(0),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<dashmap::mapref::one::Ref<'_, String, MmapEntry>> {
+        self.entries.get(key)
+    }
+
+    #[cfg(test)]
+    fn contains_key(&self, key: &str) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    fn iter(&self) -> dashmap::iter::Iter<'_, String, MmapEntry> {
+        self.entries.iter()
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    fn insert(&self, key: String, mapped: MmapEntry) {
+        self.bytes
+            .fetch_add(mapped.data.len() as u64, Ordering::Relaxed);
+        if let Some(replaced) = self.entries.insert(key, mapped) {
+            self.bytes
+                .fetch_sub(replaced.data.len() as u64, Ordering::Relaxed);
+        }
+    }
+
+    fn remove(&self, key: &str) -> Option<MmapEntry> {
+        let (_, mapped) = self.entries.remove(key)?;
+        self.bytes
+            .fetch_sub(mapped.data.len() as u64, Ordering::Relaxed);
+        Some(mapped)
+    }
+
+    fn clear(&self) {
+        self.entries.retain(|_, mapped| {
+            self.bytes
+                .fetch_sub(mapped.data.len() as u64, Ordering::Relaxed);
+            false
+        });
+    }
+}
+
+/// What tells one version of a file from another without reading it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileIdentity {
+    len: u64,
+    modified: Option<SystemTime>,
+    #[cfg(unix)]
+    inode: (u64, u64),
+}
+
+impl FileIdentity {
+    fn of(metadata: &std::fs::Metadata) -> Self {
+        Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            #[cfg(unix)]
+            inode: {
+                use std::os::unix::fs::MetadataExt;
+                (metadata.dev(), metadata.ino())
+            },
+        }
+    }
+}
+
+/// Contents of a data file, and whether a warm-tier mapping served them
+struct FileBytes {
+    data: Bytes,
+    warm: bool,
+}
+
+impl From<Vec<u8>> for FileBytes {
+    fn from(data: Vec<u8>) -> Self {
+        Self {
+            data: Bytes::from(data),
+            warm: false,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, bincode::Encode, bincode::Decode)]
 struct FileInfo {
     path: PathBuf,
@@ -101,12 +437,108 @@ struct FileInfo {
     size: u64,
     #[allow(dead_code)]
     created_at: u64,
-    compressed: bool,
+    /// Codec the stored bytes are compressed with; rows from when LZ4 was
+    /// the only codec hold a compressed flag, which decodes as `None` or `Lz4`
+    compression: Compression,
+    /// [`payload_checksum`] of the stored bytes; `None` for entries written
+    /// before checksums and for files registered from elsewhere
+    #[serde(default)]
+    checksum: Option<u32>,
+    #[serde(default)]
+    meta: EntryMeta,
+}
+
+/// Tags, expiry and bookkeeping of the entry a row stores, kept in its
+/// FileInfo so every tier hands the entry back as it was set
+#[derive(
+    Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, bincode::Encode, bincode::Decode,
+)]
+struct EntryMeta {
+    tags: Vec<String>,
+    expire_time: Option<u64>,
+    created_at: u64,
+    access_count: u64,
+}
+
+impl EntryMeta {
+    /// Metadata of an entry created at `created_at` without tags or expiry
+    fn created(created_at: u64) -> Self {
+        Self {
+            created_at,
+            ..Default::default()
+        }
+    }
+
+    fn of(entry: &CacheEntry) -> Self {
+        Self {
+            tags: entry.tags.clone(),
+            expire_time: entry.expire_time,
+            created_at: entry.created_at,
+            access_count: entry.access_count,
+        }
+    }
+
+    /// Whether the entry's expire_time has passed at `now`
+    fn is_expired(&self, now: u64) -> bool {
+        self.expire_time
+            .is_some_and(|expire_time| now > expire_time)
+    }
+
+    /// The entry holding `data` under `key` with this metadata
+    fn entry(&self, key: &str, data: Vec<u8>) -> CacheEntry {
+        let mut entry =
+            CacheEntry::new_inline(key.to_string(), data, self.tags.clone(), self.expire_time);
+        entry.created_at = self.created_at;
+        entry.access_count = self.access_count;
+        entry
+    }
+}
+
+/// FileInfo as encoded before it carried entry metadata
+#[derive(bincode::Decode)]
+struct FileInfoV2 {
+    path: PathBuf,
+    size: u64,
+    created_at: u64,
+    compression: Compression,
+    checksum: Option<u32>,
+}
+
+/// FileInfo as encoded before it carried a checksum
+#[derive(bincode::Decode)]
+struct LegacyFileInfo {
+    path: PathBuf,
+    size: u64,
+    created_at: u64,
+    compression: Compression,
 }
 
+/// A decoded index row; file and packed rows keep their MAC, if any, to
+/// check against the stored bytes once those are read
 enum IndexEntry {
     Inline(HotEntry),
-    File(FileInfo),
+    File(FileInfo, Option<Vec<u8>>),
+    Packed(PackedEntry),
+}
+
+impl IndexEntry {
+    fn meta(&self) -> &EntryMeta {
+        match self {
+            IndexEntry::Inline(entry) => &entry.meta,
+            IndexEntry::File(file_info, _) => &file_info.meta,
+            IndexEntry::Packed(packed) => &packed.meta,
+        }
+    }
+}
+
+/// A packed value's index row, decoded
+struct PackedEntry {
+    location: PackedRef,
+    compression: Compression,
+    checksum: Option<u32>,
+    mac: Option<Vec<u8>>,
+    generation: i64,
+    meta: EntryMeta,
 }
 
 /// Buffer pool for reusing allocations
@@ -167,50 +599,199 @@ impl BufferPool {
     }
 }
 
+/// Distinguishes temporary files written at the same time by one process
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A name next to `path` for writing its replacement under; it ends in
+/// `.tmp`, which nothing in the data directory is ever read as
+///
+/// Processes on other machines sharing the directory may have the same pid,
+/// so names also carry a token drawn once per process.
+fn temp_path_for(path: &Path) -> PathBuf {
+    static PROCESS_TOKEN: OnceLock<String> = OnceLock::new();
+    let token =
+        PROCESS_TOKEN.get_or_init(|| uuid::Uuid::new_v4().simple().to_string()[..8].to_string());
+    path.with_extension(format!(
+        "{}-{}-{}.tmp",
+        token,
+        std::process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Data file name for `key` before names carried the whole hash
+///
+/// Entries written under these names keep them; they are only needed to
+/// recognise such files when the index is rebuilt from the data directory.
+fn legacy_filename(key: &str) -> String {
+    format!("{}.dat", &blake3::hash(key.as_bytes()).to_hex()[..16])
+}
+
+/// When a file's contents or links last changed
+///
+/// On Unix this is the inode change time, which a hard link into the data
+/// directory moves even though the file's modification time stays old.
+fn last_changed(metadata: &std::fs::Metadata) -> SystemTime {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let changed = UNIX_EPOCH
+            + Duration::from_secs(metadata.ctime().max(0) as u64)
+            + Duration::from_nanos(metadata.ctime_nsec().max(0) as u64);
+        changed.max(metadata.modified().unwrap_or(UNIX_EPOCH))
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.modified().unwrap_or(UNIX_EPOCH)
+    }
+}
+
+/// Hard-link `source` to `target`, or copy it if `copy` is set or linking
+/// fails, returning the bytes copied
+fn link_or_copy(source: &Path, target: &Path, copy: bool) -> std::io::Result<u64> {
+    if !copy {
+        match std::fs::hard_link(source, target) {
+            Ok(()) => return Ok(0),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(e),
+            // Another filesystem, or one without hard links
+            Err(_) => {}
+        }
+    }
+    std::fs::copy(source, target)
+}
+
+/// Replace the file at `path` with `data` by writing a temporary file next to
+/// it and renaming that over it, so a reader sees the old contents or the new
+/// and never a partly written file
+fn replace_file(path: &Path, data: &[u8], sync: bool) -> std::io::Result<()> {
+    let temp_path = temp_path_for(path);
+    let written = File::create(&temp_path).and_then(|mut file| {
+        file.write_all(data)?;
+        if sync {
+            file.sync_all()?;
+        }
+        Ok(())
+    });
+    match written.and_then(|()| std::fs::rename(&temp_path, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = std::fs::remove_file(&temp_path);
+            Err(e)
+        }
+    }
+}
+
 /// Batched write operations for better I/O performance
+///
+/// The queue holds at most [`StorageConfig::write_queue_capacity`] ops, so a
+/// burst of writes cannot outgrow memory while the worker falls behind.
 struct WriteBatcher {
-    sender: Mutex<Option<mpsc::Sender<WriteOp>>>,
+    sender: Mutex<Option<mpsc::SyncSender<WriteOp>>>,
     worker: Mutex<Option<std::thread::JoinHandle<()>>>,
+    options: BatcherOptions,
+    stats: Arc<StorageStats>,
+    queue_full: QueueFullPolicy,
+    /// Queued writes per path, tracked under [`QueueFullPolicy::Spill`] so a
+    /// spilled write never lands before an older queued one to the same file
+    pending: Option<Arc<Mutex<HashMap<PathBuf, usize>>>>,
 }
 
 #[derive(Debug)]
 enum WriteOp {
     Write { path: PathBuf, data: Bytes },
-    Delete { path: PathBuf },
+    Commit(Commit),
     Sync { done: mpsc::SyncSender<()> },
     Shutdown { done: mpsc::SyncSender<()> },
 }
 
+/// A synced write waiting for its group commit
+#[derive(Debug)]
+struct Commit {
+    path: PathBuf,
+    data: Bytes,
+    done: mpsc::SyncSender<std::io::Result<()>>,
+}
+
+/// How [`OptimizedStorage::write_data`] left a data file
+enum Written {
+    /// As far along as the configured durability asks
+    Done,
+    /// Queued; lands on the next [`WriteBatcher::sync`]
+    Queued,
+    /// In a group commit; durable once [`WriteBatcher::wait`] returns
+    Committing(mpsc::Receiver<std::io::Result<()>>),
+}
+
+/// How the batcher's worker writes files
+#[derive(Clone, Copy)]
+struct BatcherOptions {
+    batch_size: usize,
+    queue_capacity: usize,
+    queue_full: QueueFullPolicy,
+    atomic: bool,
+    /// How long a group commit waits for more synced writes
+    group_commit: Duration,
+    /// Whether a group commit also fsyncs the directories it wrote into
+    sync_directories: bool,
+}
+
 impl WriteBatcher {
-    fn new(_directory: PathBuf, batch_size: usize) -> Self {
-        let (sender, receiver) = mpsc::channel();
+    fn new(options: BatcherOptions, stats: Arc<StorageStats>) -> Self {
+        let pending = (options.queue_full == QueueFullPolicy::Spill)
+            .then(|| Arc::new(Mutex::new(HashMap::new())));
+        let (sender, worker) = Self::start(options, stats.clone(), pending.clone());
+
+        Self {
+            sender: Mutex::new(Some(sender)),
+            worker: Mutex::new(Some(worker)),
+            options,
+            stats,
+            queue_full: options.queue_full,
+            pending,
+        }
+    }
 
+    /// Start a worker, returning the sender of its queue
+    fn start(
+        options: BatcherOptions,
+        stats: Arc<StorageStats>,
+        worker_pending: Option<Arc<Mutex<HashMap<PathBuf, usize>>>>,
+    ) -> (mpsc::SyncSender<WriteOp>, std::thread::JoinHandle<()>) {
+        let (sender, receiver) = mpsc::sync_channel(options.queue_capacity);
         let worker = std::thread::spawn(move || {
+            let pending = worker_pending.as_deref();
+            let BatcherOptions {
+                batch_size, atomic, ..
+            } = options;
             let mut batch = Vec::with_capacity(batch_size);
             let mut writer_map: std::collections::HashMap<PathBuf, BufWriter<File>> =
                 std::collections::HashMap::new();
+            // An op that closed a group commit's window, handled next
+            let mut next = None;
 
-            while let Ok(op) = receiver.recv() {
+            while let Some(op) = next.take().or_else(|| receiver.recv().ok()) {
                 match op {
                     WriteOp::Write { path, data } => {
                         batch.push((path, data));
                         if batch.len() >= batch_size {
-                            Self::flush_batch(&mut batch, &mut writer_map);
+                            Self::flush(&mut batch, &mut writer_map, atomic, &stats, pending);
                         }
                     }
-                    WriteOp::Delete { path } => {
-                        Self::flush_batch(&mut batch, &mut writer_map);
-                        let _ = std::fs::remove_file(&path);
+                    WriteOp::Commit(commit) => {
+                        Self::flush(&mut batch, &mut writer_map, atomic, &stats, pending);
+                        let (group, closed_by) = Self::gather_group(commit, &receiver, &options);
+                        next = closed_by;
+                        Self::commit_group(group, &options, &stats);
                     }
                     WriteOp::Sync { done } => {
-                        Self::flush_batch(&mut batch, &mut writer_map);
+                        Self::flush(&mut batch, &mut writer_map, atomic, &stats, pending);
                         for writer in writer_map.values_mut() {
                             let _ = writer.flush();
                         }
                         let _ = done.send(());
                     }
                     WriteOp::Shutdown { done } => {
-                        Self::flush_batch(&mut batch, &mut writer_map);
+                        Self::flush(&mut batch, &mut writer_map, atomic, &stats, pending);
                         for writer in writer_map.values_mut() {
                             let _ = writer.flush();
                         }
@@ -220,43 +801,272 @@ impl WriteBatcher {
                 }
             }
 
-            Self::flush_batch(&mut batch, &mut writer_map);
+            Self::flush(&mut batch, &mut writer_map, atomic, &stats, pending);
         });
+        (sender, worker)
+    }
 
-        Self {
-            sender: Mutex::new(Some(sender)),
-            worker: Mutex::new(Some(worker)),
+    /// Start a fresh worker in a forked child, whose copy of the queue has
+    /// no worker reading it
+    ///
+    /// Writes still queued at the fork are the parent's to make.
+    fn restart_after_fork(&self) {
+        let mut sender = self.sender.lock();
+        if sender.is_none() {
+            return;
+        }
+        if let Some(pending) = &self.pending {
+            pending.lock().clear();
+        }
+        // The parent's thread, which does not exist here to be joined
+        std::mem::forget(self.worker.lock().take());
+        let (fresh, worker) = Self::start(self.options, self.stats.clone(), self.pending.clone());
+        *sender = Some(fresh);
+        *self.worker.lock() = Some(worker);
+    }
+
+    /// [`flush_batch`](Self::flush_batch), then release the flushed paths
+    /// for spilling
+    fn flush(
+        batch: &mut Vec<(PathBuf, Bytes)>,
+        writer_map: &mut std::collections::HashMap<PathBuf, BufWriter<File>>,
+        atomic: bool,
+        stats: &StorageStats,
+        pending: Option<&Mutex<HashMap<PathBuf, usize>>>,
+    ) {
+        let flushed: Vec<PathBuf> = match pending {
+            Some(_) => batch.iter().map(|(path, _)| path.clone()).collect(),
+            None => Vec::new(),
+        };
+        Self::flush_batch(batch, writer_map, atomic, stats);
+        if let Some(pending) = pending {
+            let mut pending = pending.lock();
+            for path in flushed {
+                if let Some(count) = pending.get_mut(&path) {
+                    *count -= 1;
+                    if *count == 0 {
+                        pending.remove(&path);
+                    }
+                }
+            }
         }
     }
 
     fn flush_batch(
         batch: &mut Vec<(PathBuf, Bytes)>,
         _writer_map: &mut std::collections::HashMap<PathBuf, BufWriter<File>>,
+        atomic: bool,
+        stats: &StorageStats,
     ) {
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if batch.len() > 1 {
+            Self::flush_batch_uring(batch, atomic, stats);
+            return;
+        }
+
         for (path, data) in batch.drain(..) {
-            if let Ok(file) = OpenOptions::new()
+            if atomic {
+                if replace_file(&path, &data, false).is_ok() {
+                    stats.record_file_created(data.len() as u64);
+                }
+            } else if let Ok(file) = OpenOptions::new()
                 .create(true)
                 .write(true)
                 .truncate(true)
                 .open(&path)
             {
                 let mut writer = BufWriter::new(file);
-                let _ = writer.write_all(&data);
-                let _ = writer.flush();
+                if writer.write_all(&data).and_then(|_| writer.flush()).is_ok() {
+                    stats.record_file_created(data.len() as u64);
+                }
             }
         }
     }
 
-    fn write_async(&self, path: PathBuf, data: Bytes) {
-        if let Some(sender) = self.sender.lock().as_ref() {
-            let _ = sender.send(WriteOp::Write { path, data });
+    /// Write a batch with one io_uring submission, or with std::fs where
+    /// io_uring is unavailable
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    fn flush_batch_uring(batch: &mut Vec<(PathBuf, Bytes)>, atomic: bool, stats: &StorageStats) {
+        let opened: Vec<_> = batch
+            .drain(..)
+            .filter_map(|(path, data)| {
+                let target = if atomic {
+                    temp_path_for(&path)
+                } else {
+                    path.clone()
+                };
+                let file = File::create(&target).ok()?;
+                Some((path, target, data, file))
+            })
+            .collect();
+        let writes: Vec<(&File, &[u8])> = opened
+            .iter()
+            .map(|(_, _, data, file)| (file, &data[..]))
+            .collect();
+        let results = uring::write_files(&writes).unwrap_or_else(|| {
+            writes
+                .iter()
+                .map(|&(mut file, data)| file.write_all(data))
+                .collect()
+        });
+
+        for ((path, target, data, _), result) in opened.into_iter().zip(results) {
+            let result = result.and_then(|()| {
+                if atomic {
+                    std::fs::rename(&target, &path)?;
+                }
+                Ok(())
+            });
+            match result {
+                Ok(()) => stats.record_file_created(data.len() as u64),
+                Err(_) if atomic => {
+                    let _ = std::fs::remove_file(&target);
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    /// Collect the synced writes arriving within the group commit window
+    /// after `first`, up to `batch_size` of them
+    ///
+    /// Any other op closes the window early and is returned to be handled
+    /// after the group.
+    fn gather_group(
+        first: Commit,
+        receiver: &mpsc::Receiver<WriteOp>,
+        options: &BatcherOptions,
+    ) -> (Vec<Commit>, Option<WriteOp>) {
+        let deadline = std::time::Instant::now() + options.group_commit;
+        let mut group = vec![first];
+        while group.len() < options.batch_size {
+            let wait = deadline.saturating_duration_since(std::time::Instant::now());
+            match receiver.recv_timeout(wait) {
+                Ok(WriteOp::Commit(commit)) => group.push(commit),
+                Ok(op) => return (group, Some(op)),
+                Err(_) => break,
+            }
+        }
+        (group, None)
+    }
+
+    /// Write every file in `group`, then sync them all in one pass, so the
+    /// disk sees one burst of flushes instead of one per write
+    ///
+    /// Atomic writes are renamed into place only once their contents are
+    /// synced, and the directories they land in are synced once each.
+    fn commit_group(group: Vec<Commit>, options: &BatcherOptions, stats: &StorageStats) {
+        let written: Vec<_> = group
+            .into_iter()
+            .map(|commit| {
+                let target = if options.atomic {
+                    temp_path_for(&commit.path)
+                } else {
+                    commit.path.clone()
+                };
+                let file = File::create(&target).and_then(|mut file| {
+                    file.write_all(&commit.data)?;
+                    Ok(file)
+                });
+                (commit, target, file)
+            })
+            .collect();
+
+        let mut directories = HashSet::new();
+        for (commit, target, file) in written {
+            let result = file.and_then(|file| {
+                file.sync_all()?;
+                stats.record_fsync();
+                if options.atomic {
+                    std::fs::rename(&target, &commit.path)?;
+                }
+                Ok(())
+            });
+            match &result {
+                Ok(()) => {
+                    stats.record_file_created(commit.data.len() as u64);
+                    if let Some(parent) = commit.path.parent() {
+                        directories.insert(parent.to_path_buf());
+                    }
+                }
+                Err(_) if options.atomic => {
+                    let _ = std::fs::remove_file(&target);
+                }
+                Err(_) => {}
+            }
+            let _ = commit.done.send(result);
         }
+
+        // Windows cannot open directories as files; NTFS journals their entries
+        #[cfg(unix)]
+        if options.sync_directories {
+            for directory in directories {
+                if File::open(&directory)
+                    .and_then(|dir| dir.sync_all())
+                    .is_ok()
+                {
+                    stats.record_fsync();
+                }
+            }
+        }
+    }
+
+    /// Queue a write, or act on a full queue as [`QueueFullPolicy`] says
+    ///
+    /// Returns the data back when the caller is to write it itself.
+    fn write_async(&self, path: PathBuf, data: Bytes) -> CacheResult<Option<Bytes>> {
+        let sender = self.sender.lock();
+        let Some(sender) = sender.as_ref() else {
+            return Ok(None);
+        };
+        if let Some(pending) = &self.pending {
+            *pending.lock().entry(path.clone()).or_default() += 1;
+        }
+        let op = match sender.try_send(WriteOp::Write { path, data }) {
+            Ok(()) | Err(mpsc::TrySendError::Disconnected(_)) => return Ok(None),
+            Err(mpsc::TrySendError::Full(op)) => op,
+        };
+        match (self.queue_full, op) {
+            (QueueFullPolicy::Error, _) => Err(CacheError::QueueFull),
+            (QueueFullPolicy::Spill, WriteOp::Write { path, data }) if self.release(&path) => {
+                Ok(Some(data))
+            }
+            (_, op) => {
+                let _ = sender.send(op);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Stop tracking `path` if the write being handed back is its only
+    /// queued one, so it can be written at once
+    fn release(&self, path: &Path) -> bool {
+        let Some(pending) = &self.pending else {
+            return false;
+        };
+        let mut pending = pending.lock();
+        if pending.get(path) != Some(&1) {
+            return false;
+        }
+        pending.remove(path);
+        true
     }
 
-    fn delete_async(&self, path: PathBuf) {
+    /// Hand a synced write to the next group commit
+    fn commit(&self, path: PathBuf, data: Bytes) -> Written {
+        let (done, receiver) = mpsc::sync_channel(1);
         if let Some(sender) = self.sender.lock().as_ref() {
-            let _ = sender.send(WriteOp::Delete { path });
+            let _ = sender.send(WriteOp::Commit(Commit { path, data, done }));
         }
+        Written::Committing(receiver)
+    }
+
+    /// Wait for a write handed to [`WriteBatcher::commit`] to be synced
+    fn wait(receiver: mpsc::Receiver<std::io::Result<()>>) -> CacheResult<()> {
+        receiver
+            .recv()
+            .unwrap_or_else(|_| Err(std::io::Error::other("write batcher has shut down")))
+            .map_err(CacheError::Io)
     }
 
     fn sync(&self) {
@@ -291,6 +1101,65 @@ struct StorageStats {
     writes: AtomicU64,
     bytes_written: AtomicU64,
     bytes_read: AtomicU64,
+    disk_bytes_written: AtomicU64,
+    files_created: AtomicU64,
+    files_deleted: AtomicU64,
+    fsyncs: AtomicU64,
+    compactions: AtomicU64,
+    bytes_reclaimed: AtomicU64,
+}
+
+/// Tier a stored value lives in
+#[derive(Debug, Clone, Copy)]
+enum Tier {
+    Inline,
+    Packed,
+    File,
+}
+
+/// Live bytes per tier, adjusted as index rows are written and removed
+#[derive(Default)]
+struct TierBytes {
+    inline: AtomicU64,
+    packed: AtomicU64,
+    files: AtomicU64,
+}
+
+impl TierBytes {
+    fn counter(&self, tier: Tier) -> &AtomicU64 {
+        match tier {
+            Tier::Inline => &self.inline,
+            Tier::Packed => &self.packed,
+            Tier::File => &self.files,
+        }
+    }
+
+    fn add(&self, (tier, bytes): (Tier, u64)) {
+        self.counter(tier).fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn sub(&self, (tier, bytes): (Tier, u64)) {
+        // Rows written by another process were never added here
+        let _ = self
+            .counter(tier)
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |live| {
+                Some(live.saturating_sub(bytes))
+            });
+    }
+
+    fn set(&self, sizes: TierSizes) {
+        self.inline.store(sizes.inline, Ordering::Relaxed);
+        self.packed.store(sizes.packed, Ordering::Relaxed);
+        self.files.store(sizes.files, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> TierSizes {
+        TierSizes {
+            inline: self.inline.load(Ordering::Relaxed),
+            packed: self.packed.load(Ordering::Relaxed),
+            files: self.files.load(Ordering::Relaxed),
+        }
+    }
 }
 
 impl StorageStats {
@@ -298,7 +1167,6 @@ impl StorageStats {
         self.hot_hits.fetch_add(1, Ordering::Relaxed);
     }
 
-    #[allow(dead_code)]
     fn record_warm_hit(&self) {
         self.warm_hits.fetch_add(1, Ordering::Relaxed);
     }
@@ -319,49 +1187,658 @@ impl StorageStats {
     fn record_read(&self, bytes: u64) {
         self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
     }
-}
 
-impl OptimizedStorage {
-    pub fn new<P: AsRef<Path>>(directory: P) -> CacheResult<Self> {
-        Self::with_config(directory, StorageConfig::default())
+    /// Bytes that reached the index, a data file or the write-ahead log
+    fn record_disk_write(&self, bytes: u64) {
+        self.disk_bytes_written.fetch_add(bytes, Ordering::Relaxed);
     }
 
-    pub fn with_config<P: AsRef<Path>>(directory: P, config: StorageConfig) -> CacheResult<Self> {
-        let directory = directory.as_ref().to_path_buf();
-        std::fs::create_dir_all(&directory).map_err(CacheError::Io)?;
-
-        let data_dir = directory.join("data");
-        std::fs::create_dir_all(&data_dir).map_err(CacheError::Io)?;
-
-        let index_db_path = directory.join("index.sqlite3");
-        let index_db = Self::open_index_connection_at(&index_db_path)?;
-        Self::initialize_index_connection(&index_db, config.use_file_locking)?;
-
-        let write_batcher = Arc::new(WriteBatcher::new(data_dir.clone(), config.batch_size));
+    fn record_file_created(&self, bytes: u64) {
+        self.files_created.fetch_add(1, Ordering::Relaxed);
+        self.record_disk_write(bytes);
+    }
 
-        let mut storage = Self {
-            directory,
-            hot_cache: Arc::new(DashMap::with_capacity(config.hot_cache_size)),
-            warm_cache: Arc::new(DashMap::with_capacity(config.warm_cache_size)),
-            cold_index: Arc::new(RwLock::new(DashMap::new())),
-            index_db: Arc::new(Mutex::new(index_db)),
-            buffer_pool: Arc::new(BufferPool::new()),
-            write_batcher,
-            config,
-            stats: Arc::new(StorageStats::default()),
-        };
+    fn record_file_deleted(&self) {
+        self.files_deleted.fetch_add(1, Ordering::Relaxed);
+    }
 
-        // Load existing index from SQLite
-        storage.rebuild_index_from_disk()?;
+    fn record_files_deleted(&self, count: u64) {
+        self.files_deleted.fetch_add(count, Ordering::Relaxed);
+    }
 
-        Ok(storage)
+    fn record_fsync(&self) {
+        self.fsyncs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_compaction(&self, bytes_reclaimed: u64) {
+        self.compactions.fetch_add(1, Ordering::Relaxed);
+        self.bytes_reclaimed
+            .fetch_add(bytes_reclaimed, Ordering::Relaxed);
+    }
+}
+
+/// Reclaims dead space in segment files, on demand or from a background thread
+struct Compactor {
+    index_db: Arc<OrderedMutex<Connection>>,
+    segments: Arc<SegmentStore>,
+    stats: Arc<StorageStats>,
+    /// Share of a segment that must be dead before it is rewritten
+    dead_ratio: f64,
+    /// Held for the whole of a run, so runs never overlap
+    lock: OrderedMutex<()>,
+    /// Set while a background run is queued or in progress
+    scheduled: AtomicBool,
+    /// Dead bytes left behind by the last run
+    dead_after_last_run: AtomicU64,
+}
+
+impl Compactor {
+    fn worth_compacting(&self, len: u64, live: u64) -> bool {
+        len.saturating_sub(live) as f64 >= self.dead_ratio * len as f64
+    }
+
+    /// Whether enough dead space has built up since the last run to start another
+    fn is_due(&self) -> bool {
+        let bytes = self.segments.bytes();
+        let dead = self.segments.dead_bytes().min(bytes);
+        // A segment's worth of new garbage, so a run that could not reclaim
+        // anything is not immediately retried on every write
+        dead.saturating_sub(self.dead_after_last_run.load(Ordering::Relaxed))
+            >= self.segments.segment_size()
+            && self.worth_compacting(bytes, bytes - dead)
+    }
+
+    /// Index rows of packed values, by the segment they are in
+    fn packed_rows(&self) -> CacheResult<HashMap<u32, Vec<PackedRow>>> {
+        let mut live: HashMap<u32, Vec<PackedRow>> = HashMap::new();
+        let conn = self.index_db.lock();
+        let mut stmt = conn
+            .prepare("SELECT key, value FROM cache_index")
+            .map_err(|e| OptimizedStorage::sqlite_error("Failed to query SQLite index", e))?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })
+            .map_err(|e| OptimizedStorage::sqlite_error("Failed to iterate SQLite index", e))?;
+        for row in rows {
+            let (key, value_bytes) = row.map_err(|e| {
+                OptimizedStorage::sqlite_error("Failed to read SQLite index row", e)
+            })?;
+            let (file_info, _) = OptimizedStorage::decode_file_info(&value_bytes)?;
+            if PackedRef::is_packed(&file_info.path) {
+                let location = PackedRef::parse(&file_info.path, file_info.size)?;
+                live.entry(location.segment).or_default().push((
+                    key,
+                    value_bytes,
+                    file_info,
+                    location,
+                ));
+            }
+        }
+        Ok(live)
+    }
+
+    /// Rewrite the live values of every sealed segment at least `dead_ratio`
+    /// dead into the active segment, then delete it
+    ///
+    /// The index only points at a copy once it is on disk, and rows rewritten
+    /// since the scan keep their new location. A reader in another process
+    /// that looked up an old location just before the move sees a miss.
+    /// Returns the number of bytes reclaimed.
+    fn run(&self) -> CacheResult<u64> {
+        let _running = self.lock.lock();
+        let segment_ids = self.segments.segment_ids()?;
+        if segment_ids.is_empty() {
+            return Ok(0);
+        }
+
+        let mut live = self.packed_rows()?;
+
+        let mut reclaimed = 0;
+        for segment in segment_ids {
+            let len = match std::fs::metadata(self.segments.path(segment)) {
+                Ok(metadata) => metadata.len(),
+                Err(_) => continue,
+            };
+            if !self.segments.is_sealed(len) {
+                continue;
+            }
+            let entries = live.remove(&segment).unwrap_or_default();
+            let live_bytes: u64 = entries.iter().map(|(_, _, _, location)| location.len).sum();
+            if !self.worth_compacting(len, live_bytes) {
+                continue;
+            }
+
+            let mut moves = Vec::with_capacity(entries.len());
+            let mut touched = HashSet::new();
+            for (key, old_value, mut file_info, location) in entries {
+                let data = match self.segments.read(location) {
+                    Ok(data) => data,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(CacheError::Io(e)),
+                };
+                let appended = self.segments.append(&data, false)?;
+                if appended.created {
+                    self.stats.record_file_created(data.len() as u64);
+                } else {
+                    self.stats.record_disk_write(data.len() as u64);
+                }
+                touched.insert(appended.location.segment);
+                file_info.path = appended.location.to_path();
+                let new_value = OptimizedStorage::encode_file_info(&file_info)?;
+                moves.push((key, old_value, new_value, data.len() as u64));
+            }
+
+            // The copies must be durable before the index points at them
+            for copy in touched {
+                File::open(self.segments.path(copy))?.sync_data()?;
+                self.stats.record_fsync();
+            }
+
+            let mut conn = self.index_db.lock();
+            let tx = OptimizedStorage::write_transaction(&mut conn)?;
+            for (key, old_value, new_value, copied) in &moves {
+                // Generation is kept: moving a value is not a rewrite
+                let moved = tx
+                    .execute(
+                        "UPDATE cache_index SET value = ?1 WHERE key = ?2 AND value = ?3",
+                        params![new_value, key, old_value],
+                    )
+                    .map_err(|e| {
+                        OptimizedStorage::sqlite_error("Failed to move SQLite index entry", e)
+                    })?;
+                if moved == 0 {
+                    // Rewritten or deleted since the scan, so the copy is garbage
+                    self.segments.mark_dead(*copied);
+                } else {
+                    self.stats
+                        .record_disk_write((key.len() + new_value.len()) as u64);
+                }
+            }
+            tx.commit().map_err(|e| {
+                OptimizedStorage::sqlite_error("Failed to commit SQLite transaction", e)
+            })?;
+            drop(conn);
+
+            self.segments
+                .remove(segment, len, len.saturating_sub(live_bytes))?;
+            self.stats.record_file_deleted();
+            reclaimed += len - live_bytes;
+        }
+
+        self.dead_after_last_run
+            .store(self.segments.dead_bytes(), Ordering::Relaxed);
+        self.stats.record_compaction(reclaimed);
+        if reclaimed > 0 {
+            tracing::info!("Compacted segments, reclaiming {} bytes", reclaimed);
+        }
+        Ok(reclaimed)
+    }
+
+    /// Punch holes over the dead regions of sealed segments not appended to
+    /// for `grace`, for those too live to be worth compacting
+    ///
+    /// A value appended just before the scan has no index row yet and would
+    /// look dead; the grace keeps segments that may hold one out of reach.
+    /// Offsets are unchanged, so the index needs no update. Returns the bytes
+    /// of disk space freed.
+    fn punch_dead_regions(&self, grace: Duration) -> CacheResult<u64> {
+        let _running = self.lock.lock();
+        let mut live = self.packed_rows()?;
+
+        let mut freed = 0;
+        for segment in self.segments.segment_ids()? {
+            let metadata = match std::fs::metadata(self.segments.path(segment)) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            let unchanged_for = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .unwrap_or(Duration::ZERO);
+            if !self.segments.is_sealed(metadata.len()) || unchanged_for < grace {
+                continue;
+            }
+
+            let mut used: Vec<Range<u64>> = live
+                .remove(&segment)
+                .unwrap_or_default()
+                .iter()
+                .map(|(_, _, _, location)| location.offset..location.offset + location.len)
+                .collect();
+            used.sort_by_key(|range| range.start);
+            let mut dead = Vec::new();
+            let mut start = 0;
+            for range in used {
+                if range.start > start {
+                    dead.push(start..range.start);
+                }
+                start = start.max(range.end);
+            }
+            if metadata.len() > start {
+                dead.push(start..metadata.len());
+            }
+            freed += self.segments.punch_holes(segment, &dead)?;
+        }
+        Ok(freed)
+    }
+}
+
+impl OptimizedStorage {
+    pub fn new<P: AsRef<Path>>(directory: P) -> CacheResult<Self> {
+        Self::with_config(directory, StorageConfig::default())
+    }
+
+    pub fn with_config<P: AsRef<Path>>(
+        directory: P,
+        mut config: StorageConfig,
+    ) -> CacheResult<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        std::fs::create_dir_all(&directory).map_err(CacheError::Io)?;
+        if config.smb_mode {
+            // A handle this process keeps open or mapped is what makes
+            // another machine's rename over the file fail
+            config.atomic_writes = true;
+            config.open_files = 0;
+            config.mmap_threshold = 0;
+        }
+        // Held until the storage is ready, so concurrent openers never
+        // create the schema, move the layout or replay the log together
+        let lock_backend = config.lock_backend.nfs_safe(config.network_safe());
+        let _setup = SetupLock::acquire(&directory, lock_backend)?;
+
+        let data_dir = directory.join("data");
+        std::fs::create_dir_all(&data_dir).map_err(CacheError::Io)?;
+        let key_locks = if config.smb_mode {
+            KeyLocks::unlocked()
+        } else {
+            KeyLocks::open(&directory, lock_backend, config.lock_granularity)?
+        };
+
+        let index_db_path = directory.join(INDEX_FILE);
+        let index_was_missing = !index_db_path.exists();
+        let index_db = Self::open_index_connection_at(&index_db_path)?;
+        Self::initialize_index_connection(&index_db, config.network_safe(), config.durability)?;
+
+        let stats = Arc::new(StorageStats::default());
+        let write_batcher = Arc::new(WriteBatcher::new(
+            BatcherOptions {
+                batch_size: config.batch_size,
+                queue_capacity: config.write_queue_capacity,
+                queue_full: config.queue_full,
+                atomic: config.atomic_writes,
+                group_commit: config.group_commit.unwrap_or_default(),
+                sync_directories: config.durability == Durability::FsyncDir,
+            },
+            stats.clone(),
+        ));
+        let segments = Arc::new(SegmentStore::open(&directory, config.segment_size)?);
+        // Loaded whatever the mode, since values may already need them
+        let dictionaries = Dictionaries::open(&directory, config.compression_level)?;
+        let (open_marker, was_unclean_shutdown) = match OpenMarker::acquire(&directory)? {
+            Some((marker, unclean)) => (Some(marker), Some(unclean)),
+            None => {
+                tracing::warn!(
+                    "Cannot lock files in {:?}; whether its previous owner closed it cleanly is unknown",
+                    directory
+                );
+                (None, None)
+            }
+        };
+        if was_unclean_shutdown == Some(true) {
+            tracing::warn!(
+                "Cache directory {:?} was not closed cleanly by its previous owner",
+                directory
+            );
+        }
+
+        let index_db = Arc::new(OrderedMutex::new(LockLevel::Index, index_db));
+        let compactor = Arc::new(Compactor {
+            index_db: index_db.clone(),
+            segments: segments.clone(),
+            stats: stats.clone(),
+            dead_ratio: config.compaction_ratio,
+            lock: OrderedMutex::new(LockLevel::Compaction, ()),
+            scheduled: AtomicBool::new(false),
+            dead_after_last_run: AtomicU64::new(0),
+        });
+
+        let mut storage = Self {
+            directory,
+            hot_cache: Arc::new(DashMap::with_capacity(config.hot_cache_size)),
+            hot_clock: AtomicU64::new(0),
+            warm_clock: AtomicU64::new(0),
+            warm_cache: Arc::new(WarmCache::with_capacity(config.warm_cache_size)),
+            cold_index: Arc::new(OrderedRwLock::new(LockLevel::ColdIndex, DashMap::new())),
+            open_files: Arc::new(OpenFiles::new(config.open_files)),
+            key_locks,
+            index_db,
+            buffer_pool: Arc::new(BufferPool::new()),
+            write_batcher,
+            config,
+            stats,
+            tier_bytes: TierBytes::default(),
+            open_marker: Mutex::new(open_marker),
+            was_unclean_shutdown,
+            wal: None,
+            dictionaries,
+            segments,
+            compactor,
+            compaction_thread: Mutex::new(None),
+            watcher: Mutex::new(None),
+            events: None,
+            expirations: RwLock::new(None),
+            remote_uploads: Arc::new(RemoteUploads::new()),
+            pinned: RwLock::new(HashSet::new()),
+            maintenance: RwLock::new(None),
+            cleanup: RwLock::new(None),
+            fork: ForkCheck::new(),
+        };
+
+        storage.migrate_data_layout()?;
+
+        // Load existing index from SQLite
+        storage.rebuild_index_from_disk()?;
+        if index_was_missing {
+            // A lost or deleted index leaves the data files behind
+            storage.restore_from_data_files()?;
+        }
+
+        if let Some(policy) = storage.config.wal {
+            let wal = storage.open_wal(policy)?;
+            storage.wal = Some(OrderedMutex::new(LockLevel::WriteAheadLog, wal));
+        }
+
+        if storage.config.watch_directory {
+            *storage.watcher.get_mut() = Some(storage.watch_data_files()?);
+        }
+        if storage.config.event_log {
+            let events = EventLog::open(&storage.directory)?;
+            storage.events = Some(OrderedMutex::new(LockLevel::EventLog, events));
+        }
+
+        Ok(storage)
+    }
+
+    /// Close the write-ahead log without checkpointing it, as a crash would
+    #[cfg(test)]
+    pub(crate) fn release_wal(&self) {
+        if let Some(wal) = &self.wal {
+            wal.lock().detach();
+        }
+    }
+
+    /// Take over in a forked child from the parent whose copy of the storage
+    /// it inherited, the first time it is called there
+    ///
+    /// The batcher and watcher get threads of their own, the SQLite index a
+    /// connection of its own, and the child marks the directory open for
+    /// itself and takes the write-ahead log if no other handle owns it.
+    /// What the parent's threads and connection left behind is
+    /// forgotten rather than closed, since closing it here would tear down
+    /// the parent's.
+    pub fn after_fork(&self) {
+        if !self.fork.forked() {
+            return;
+        }
+        self.write_batcher.restart_after_fork();
+        if let Some(compaction) = self.compaction_thread.lock().take() {
+            std::mem::forget(compaction);
+            self.compactor.scheduled.store(false, Ordering::Release);
+        }
+        match Self::open_index_connection_at(&self.directory.join(INDEX_FILE)).and_then(|conn| {
+            Self::initialize_index_connection(
+                &conn,
+                self.config.network_safe(),
+                self.config.durability,
+            )?;
+            Ok(conn)
+        }) {
+            Ok(conn) => std::mem::forget(std::mem::replace(&mut *self.index_db.lock(), conn)),
+            Err(e) => tracing::warn!("Failed to reopen the index after fork: {}", e),
+        }
+        if let (Some(wal), Some(policy)) = (&self.wal, self.config.wal) {
+            // Writes go unlogged until the child owns a log of its own
+            wal.lock().detach();
+            match self.open_wal(policy) {
+                Ok(own) => *wal.lock() = own,
+                Err(e) => tracing::warn!("Failed to reopen the write-ahead log after fork: {}", e),
+            }
+        }
+        {
+            let mut marker = self.open_marker.lock();
+            if let Some(mut inherited) = marker.take() {
+                inherited.disown();
+                match OpenMarker::acquire(&self.directory) {
+                    Ok(own) => *marker = own.map(|(own, _)| own),
+                    Err(e) => tracing::warn!("Failed to mark the cache open after fork: {}", e),
+                }
+            }
+        }
+        let mut watcher = self.watcher.lock();
+        if let Some(inherited) = watcher.take() {
+            std::mem::forget(inherited);
+            match self.watch_data_files() {
+                Ok(own) => *watcher = Some(own),
+                Err(e) => tracing::warn!("Failed to watch {:?} after fork: {}", self.directory, e),
+            }
+        }
+    }
+
+    /// Watch `data/` and drop what the memory tiers hold for data files as
+    /// they change
+    ///
+    /// The cold index only records where a key's file is, and is kept: the
+    /// next read finds the change through the index row as it always does.
+    fn watch_data_files(&self) -> CacheResult<DirectoryWatcher> {
+        let hot_cache = self.hot_cache.clone();
+        let warm_cache = self.warm_cache.clone();
+        let cold_index = self.cold_index.clone();
+        let open_files = self.open_files.clone();
+        DirectoryWatcher::new(&self.directory.join("data"), move |paths| {
+            let changed: HashSet<&Path> = paths.iter().map(PathBuf::as_path).collect();
+            for path in &changed {
+                open_files.forget(path);
+            }
+            let mut keys: Vec<String> = warm_cache
+                .iter()
+                .filter(|mapped| changed.contains(mapped.path.as_path()))
+                .map(|mapped| mapped.key().clone())
+                .collect();
+            keys.extend(
+                cold_index
+                    .read()
+                    .iter()
+                    .filter(|file_info| changed.contains(file_info.path.as_path()))
+                    .map(|file_info| file_info.key().clone()),
+            );
+            for key in keys {
+                hot_cache.remove(&key);
+                warm_cache.remove(&key);
+            }
+        })
+    }
+
+    /// Tell the other handles of the cache about `events`, if the event log
+    /// is enabled
+    ///
+    /// The change has been made by then; a failure only leaves the others to
+    /// find it on their next read of the key, as they would without the log.
+    fn publish(&self, events: &[Event<'_>]) {
+        if let Some(log) = &self.events {
+            if let Err(e) = log.lock().append(events) {
+                tracing::warn!("Failed to append to the event log: {}", e);
+            }
+        }
+    }
+
+    /// A handle on the event log that reads what other handles change from
+    /// now on, if the log is enabled
+    pub(crate) fn follow_events(&self) -> CacheResult<Option<EventLog>> {
+        self.events
+            .as_ref()
+            .map(|log| log.lock().follow(&self.directory))
+            .transpose()
+    }
+
+    /// Drop what the memory tiers hold for keys other handles changed since
+    /// the last call
+    fn catch_up_events(&self) {
+        let Some(log) = &self.events else {
+            return;
+        };
+        let invalidation = match log.lock().catch_up() {
+            Ok(invalidation) => invalidation,
+            Err(e) => {
+                tracing::warn!("Failed to read the event log: {}", e);
+                Some(Invalidation::All)
+            }
+        };
+        match invalidation {
+            Some(Invalidation::Keys(keys)) => {
+                for key in keys {
+                    self.hot_cache.remove(&key);
+                    self.warm_cache.remove(&key);
+                }
+            }
+            Some(Invalidation::All) => self.clear_memory_tiers(),
+            None => {}
+        }
+    }
+
+    /// Re-apply logged writes left by a previous owner, then checkpoint them
+    ///
+    /// Replaying the whole log in order is idempotent, so it does not matter
+    /// which of the writes had already reached the index before the crash.
+    /// Open the write-ahead log and replay what it holds, unless another
+    /// handle owns it
+    fn open_wal(&self, policy: WalSyncPolicy) -> CacheResult<WriteAheadLog> {
+        let (mut wal, records) =
+            WriteAheadLog::open(&self.directory, policy, self.config.batch_size)?;
+        if !records.is_empty() {
+            tracing::warn!(
+                "Replaying {} write-ahead log records in {:?}",
+                records.len(),
+                self.directory
+            );
+            self.replay_wal(&mut wal, records)?;
+        }
+        Ok(wal)
+    }
+
+    fn replay_wal(&self, wal: &mut WriteAheadLog, records: Vec<WalRecord>) -> CacheResult<()> {
+        for record in records {
+            match record {
+                WalRecord::Put { key, data } => {
+                    self.set_data(&key, &data)?;
+                    wal.mark_dirty(self.build_file_path(&key));
+                }
+                // Logged deletes include evictions, whose pushed copy must stay
+                WalRecord::Delete { key } => {
+                    self.delete_local(&key)?;
+                }
+                WalRecord::Clear => self.clear()?,
+            }
+        }
+        self.checkpoint_wal(wal)
+    }
+
+    /// Append `records` to the write-ahead log, if enabled
+    ///
+    /// The returned guard keeps the log locked while the caller applies the
+    /// records, so the log order matches the order writes took effect.
+    fn log_writes(
+        &self,
+        records: &[WalRecord],
+    ) -> CacheResult<Option<OrderedMutexGuard<'_, WriteAheadLog>>> {
+        let Some(wal) = &self.wal else {
+            return Ok(None);
+        };
+        let mut wal = wal.lock();
+        let len_before = wal.len();
+        if wal.append(records)? {
+            self.stats.record_fsync();
+        }
+        self.stats.record_disk_write(wal.len() - len_before);
+        Ok(Some(wal))
+    }
+
+    /// Checkpoint once the log has grown past `WAL_CHECKPOINT_BYTES`
+    fn finish_logged_write(
+        &self,
+        wal: Option<OrderedMutexGuard<'_, WriteAheadLog>>,
+    ) -> CacheResult<()> {
+        match wal {
+            Some(mut wal) if wal.len() >= WAL_CHECKPOINT_BYTES => self.checkpoint_wal(&mut wal),
+            _ => Ok(()),
+        }
+    }
+
+    /// Make every applied write durable, then empty the log
+    fn checkpoint_wal(&self, wal: &mut WriteAheadLog) -> CacheResult<()> {
+        self.write_batcher.sync();
+        let dirty = wal.take_dirty_files();
+        for path in &dirty {
+            match File::open(path) {
+                Ok(file) => {
+                    file.sync_all()?;
+                    self.stats.record_fsync();
+                }
+                // Inline entries and files deleted since have nothing to sync
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(CacheError::Io(e)),
+            }
+        }
+        #[cfg(unix)]
+        {
+            // New files are durable once the fan-out directories naming them are
+            let mut directories = std::collections::BTreeSet::from([self.directory.join("data")]);
+            directories.extend(
+                dirty
+                    .iter()
+                    .filter_map(|path| path.parent())
+                    .map(Path::to_path_buf),
+            );
+            for directory in directories {
+                if let Ok(directory) = File::open(directory) {
+                    directory.sync_all()?;
+                    self.stats.record_fsync();
+                }
+            }
+            if let Ok(segments) = File::open(self.segments.directory()) {
+                segments.sync_all()?;
+                self.stats.record_fsync();
+            }
+        }
+
+        self.index_db
+            .lock()
+            .query_row("PRAGMA wal_checkpoint(FULL)", [], |_| Ok(()))
+            .map_err(|e| Self::sqlite_error("Failed to checkpoint SQLite index", e))?;
+        wal.reset()?;
+        self.stats.record_fsync();
+        Ok(())
+    }
+
+    /// Checkpoint the write-ahead log, if enabled, logging rather than returning errors
+    fn close_wal(&self) {
+        if let Some(wal) = &self.wal {
+            if let Err(e) = self.checkpoint_wal(&mut wal.lock()) {
+                tracing::error!("Failed to checkpoint write-ahead log: {}", e);
+            }
+        }
     }
 
     fn sqlite_error(context: &str, error: rusqlite::Error) -> CacheError {
         CacheError::Io(std::io::Error::other(format!("{}: {}", context, error)))
     }
 
-    fn initialize_index_connection(conn: &Connection, nfs_safe: bool) -> CacheResult<()> {
+    fn initialize_index_connection(
+        conn: &Connection,
+        nfs_safe: bool,
+        durability: Durability,
+    ) -> CacheResult<()> {
         if nfs_safe {
             conn.pragma_update(None, "journal_mode", "DELETE")
                 .map_err(|e| Self::sqlite_error("Failed to enable SQLite rollback journal", e))?;
@@ -374,18 +1851,37 @@ impl OptimizedStorage {
         } else {
             conn.pragma_update(None, "journal_mode", "WAL")
                 .map_err(|e| Self::sqlite_error("Failed to enable SQLite WAL", e))?;
-            conn.pragma_update(None, "synchronous", "NORMAL")
+            conn.pragma_update(None, "synchronous", durability.sqlite_synchronous())
                 .map_err(|e| {
-                    Self::sqlite_error("Failed to configure SQLite normal synchronous mode", e)
+                    Self::sqlite_error("Failed to configure SQLite synchronous mode", e)
                 })?;
         }
         conn.execute(INDEX_TABLE_SQL, [])
             .map_err(|e| Self::sqlite_error("Failed to create SQLite index table", e))?;
-        Self::ensure_generation_column(conn)?;
+        Self::ensure_column(conn, "generation", "INTEGER NOT NULL DEFAULT 0")?;
+        Self::ensure_column(conn, "mac", "BLOB")?;
+        if Self::ensure_column(conn, "seq", "INTEGER")? {
+            // Older indices have no sequence numbers; rowid is the closest store order
+            conn.execute("UPDATE cache_index SET seq = rowid WHERE seq IS NULL", [])
+                .map_err(|e| Self::sqlite_error("Failed to backfill SQLite sequence numbers", e))?;
+        }
+        conn.execute(INDEX_SEQ_SQL, [])
+            .map_err(|e| Self::sqlite_error("Failed to create SQLite sequence index", e))?;
+        conn.execute(SEQ_TABLE_SQL, [])
+            .map_err(|e| Self::sqlite_error("Failed to create SQLite sequence table", e))?;
+        conn.execute(SEQ_INIT_SQL, [])
+            .map_err(|e| Self::sqlite_error("Failed to initialize SQLite sequence", e))?;
+        conn.execute(SEQ_TRIGGER_SQL, [])
+            .map_err(|e| Self::sqlite_error("Failed to create SQLite sequence trigger", e))?;
+        conn.execute(ALIAS_TABLE_SQL, [])
+            .map_err(|e| Self::sqlite_error("Failed to create SQLite alias table", e))?;
+        conn.execute(ALIAS_KEY_SQL, [])
+            .map_err(|e| Self::sqlite_error("Failed to create SQLite alias index", e))?;
         Ok(())
     }
 
-    fn ensure_generation_column(conn: &Connection) -> CacheResult<()> {
+    /// Add `column` to the index table if missing; returns whether it was added
+    fn ensure_column(conn: &Connection, column: &str, definition: &str) -> CacheResult<bool> {
         let mut stmt = conn
             .prepare("PRAGMA table_info(cache_index)")
             .map_err(|e| Self::sqlite_error("Failed to inspect SQLite index schema", e))?;
@@ -393,20 +1889,33 @@ impl OptimizedStorage {
             .query_map([], |row| row.get::<_, String>(1))
             .map_err(|e| Self::sqlite_error("Failed to query SQLite index schema", e))?;
 
-        for column in columns {
-            if column.map_err(|e| Self::sqlite_error("Failed to read SQLite index schema", e))?
-                == "generation"
+        for existing in columns {
+            if existing.map_err(|e| Self::sqlite_error("Failed to read SQLite index schema", e))?
+                == column
             {
-                return Ok(());
+                return Ok(false);
             }
         }
 
         conn.execute(
-            "ALTER TABLE cache_index ADD COLUMN generation INTEGER NOT NULL DEFAULT 0",
+            &format!(
+                "ALTER TABLE cache_index ADD COLUMN {} {}",
+                column, definition
+            ),
             [],
         )
-        .map_err(|e| Self::sqlite_error("Failed to add SQLite generation column", e))?;
-        Ok(())
+        .map_err(|e| Self::sqlite_error("Failed to add SQLite index column", e))?;
+        Ok(true)
+    }
+
+    /// Begin an index transaction that takes the write lock up front
+    ///
+    /// A deferred transaction that reads before it writes cannot wait for a
+    /// writer in another process: SQLite fails it at once once that writer
+    /// has committed. Taking the lock first lets it wait out the busy timeout.
+    fn write_transaction(conn: &mut Connection) -> CacheResult<Transaction<'_>> {
+        conn.transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(|e| Self::sqlite_error("Failed to begin SQLite transaction", e))
     }
 
     fn open_index_connection_at(path: &Path) -> CacheResult<Connection> {
@@ -421,7 +1930,7 @@ impl OptimizedStorage {
     fn rebuild_index_from_disk(&mut self) -> CacheResult<()> {
         let conn = self.index_db.lock();
         let mut stmt = conn
-            .prepare("SELECT key, value, generation FROM cache_index")
+            .prepare("SELECT key, value, generation, mac FROM cache_index")
             .map_err(|e| Self::sqlite_error("Failed to query SQLite index", e))?;
         let rows = stmt
             .query_map([], |row| {
@@ -429,6 +1938,7 @@ impl OptimizedStorage {
                     row.get::<_, String>(0)?,
                     row.get::<_, Vec<u8>>(1)?,
                     row.get::<_, i64>(2)?,
+                    row.get::<_, Option<Vec<u8>>>(3)?,
                 ))
             })
             .map_err(|e| Self::sqlite_error("Failed to iterate SQLite index", e))?;
@@ -436,35 +1946,43 @@ impl OptimizedStorage {
         let index = self.cold_index.write();
         let mut loaded_count = 0;
         let mut skipped_count = 0;
+        let mut packed_bytes = 0;
+        let counted = TierBytes::default();
 
         for row in rows {
-            let (key, value_bytes, generation) =
+            let (key, value_bytes, generation, mac) =
                 row.map_err(|e| Self::sqlite_error("Failed to read SQLite index row", e))?;
-            let (file_info, decoded_len): (FileInfo, usize) =
-                bincode::decode_from_slice(value_bytes.as_slice(), bincode::config::standard())
-                    .map_err(|e| {
-                        CacheError::Io(std::io::Error::other(format!(
-                            "Failed to deserialize FileInfo: {}",
-                            e
-                        )))
-                    })?;
+            if let Some(size) = self.row_size(&value_bytes) {
+                counted.add(size);
+            }
+            let (mut file_info, decoded_len) = Self::decode_file_info(&value_bytes)?;
 
             if file_info.path.to_string_lossy().starts_with("memory://") {
-                if value_bytes.len() > decoded_len {
-                    self.hot_cache.insert(
-                        key,
-                        HotEntry {
-                            data: Bytes::copy_from_slice(&value_bytes[decoded_len..]),
-                            generation,
-                        },
-                    );
+                let stored = &value_bytes[decoded_len..];
+                // Rows that fail their signature are left for a read to report
+                let data = (!stored.is_empty())
+                    .then(|| {
+                        self.verify_row(&key, file_info.compression, stored, mac.as_deref())
+                            .and_then(|()| self.decompress_if_needed(stored, file_info.compression))
+                            .ok()
+                    })
+                    .flatten();
+                if let Some(data) = data {
+                    self.insert_hot(key, HotEntry::new(data, generation, file_info.meta));
                     loaded_count += 1;
                 } else {
                     skipped_count += 1;
                 }
                 continue;
             }
+            if PackedRef::is_packed(&file_info.path) {
+                // Packed values are read through the index, never the cold tier
+                packed_bytes += file_info.size;
+                loaded_count += 1;
+                continue;
+            }
 
+            file_info.path = self.resolve_data_path(&file_info.path);
             if file_info.path.exists() {
                 index.insert(key, file_info);
                 loaded_count += 1;
@@ -472,6 +1990,8 @@ impl OptimizedStorage {
                 skipped_count += 1;
             }
         }
+        self.segments.set_live_bytes(packed_bytes);
+        self.tier_bytes.set(counted.get());
 
         tracing::debug!(
             "Loaded {} entries from SQLite index, skipped {} missing files",
@@ -484,11 +2004,21 @@ impl OptimizedStorage {
 
     /// Close background resources and flush in-memory data.
     pub fn close_db(&self) {
+        self.after_fork();
         self.write_batcher.shutdown();
+        self.close_wal();
+        self.open_files.clear();
+        if let Some(remote) = &self.config.remote_tier {
+            self.remote_uploads.flush(remote.as_ref());
+        }
 
         if let Err(e) = self.flush_memory_caches() {
             tracing::error!("Failed to flush memory caches during close: {}", e);
         }
+
+        if let Some(mut marker) = self.open_marker.lock().take() {
+            marker.release();
+        }
     }
 
     /// Flush in-flight file writes. Cache entries are already persisted when set.
@@ -496,528 +2026,3289 @@ impl OptimizedStorage {
         Ok(())
     }
 
-    /// Persist the cold index to SQLite.
-    fn persist_index(&self) -> CacheResult<()> {
-        let index = self.cold_index.read();
-        let file_infos: Vec<(String, FileInfo)> = index
-            .iter()
-            .map(|entry| (entry.key().clone(), entry.value().clone()))
-            .collect();
-        drop(index);
-        self.persist_file_infos(&file_infos)
-    }
-
-    fn encode_inline_entry(key: &str, data: &[u8]) -> CacheResult<Vec<u8>> {
+    /// Index value of an inline entry: its FileInfo, then `stored`, the
+    /// value as compressed with `compression`
+    fn encode_inline_entry(
+        key: &str,
+        stored: &[u8],
+        compression: Compression,
+        meta: &EntryMeta,
+    ) -> CacheResult<Vec<u8>> {
         let file_info = FileInfo {
             path: PathBuf::from(format!("memory://{}", key)),
-            size: data.len() as u64,
+            size: stored.len() as u64,
             created_at: Self::get_current_timestamp(),
-            compressed: false,
+            compression,
+            checksum: None,
+            meta: meta.clone(),
         };
-        let mut value_bytes = bincode::encode_to_vec(&file_info, bincode::config::standard())
-            .map_err(|e| {
-                CacheError::Io(std::io::Error::other(format!(
-                    "Failed to serialize FileInfo: {}",
-                    e
-                )))
-            })?;
-        value_bytes.extend_from_slice(data);
+        let mut value_bytes = Self::encode_file_info(&file_info)?;
+        value_bytes.extend_from_slice(stored);
         Ok(value_bytes)
     }
 
-    fn persist_inline_entries(&self, entries: &[(String, Bytes)]) -> CacheResult<()> {
+    fn persist_inline_entries(&self, entries: &[InlineRow]) -> CacheResult<()> {
         if entries.is_empty() {
             return Ok(());
         }
 
         let mut conn = self.index_db.lock();
-        let tx = conn
-            .transaction()
-            .map_err(|e| Self::sqlite_error("Failed to begin SQLite transaction", e))?;
+        let tx = Self::write_transaction(&mut conn)?;
+        let (mut replaced, mut written) = (Vec::new(), Vec::new());
         {
             let mut stmt = tx
-                .prepare("INSERT OR REPLACE INTO cache_index (key, value, generation) VALUES (?1, ?2, ?3)")
+                .prepare(&format!(
+                    "INSERT OR REPLACE INTO cache_index (key, value, generation, seq, mac) VALUES (?1, ?2, ?3, {}, ?4)",
+                    NEXT_SEQ_SQL
+                ))
                 .map_err(|e| Self::sqlite_error("Failed to prepare inline SQLite entry", e))?;
-            for (key, data) in entries {
+            for (key, data, meta) in entries {
                 let generation = Self::new_generation();
-                let value_bytes = Self::encode_inline_entry(key, data)?;
-                stmt.execute(params![key.as_str(), value_bytes, generation])
+                let compressed = self.compress_with_dictionary(data);
+                let (stored, compression) = match &compressed {
+                    Some(compressed) => (&compressed[..], Compression::ZstdDictionary),
+                    None => (&data[..], Compression::None),
+                };
+                let value_bytes = Self::encode_inline_entry(key, stored, compression, meta)?;
+                let mac = self.sign_row(key, compression, stored);
+                replaced.extend(Self::current_row(&tx, key)?);
+                stmt.execute(params![key.as_str(), value_bytes, generation, mac])
                     .map_err(|e| Self::sqlite_error("Failed to persist inline SQLite entry", e))?;
-                self.hot_cache.insert(
+                self.stats
+                    .record_disk_write((key.len() + value_bytes.len()) as u64);
+                written.push(value_bytes);
+                self.insert_hot(
                     key.clone(),
-                    HotEntry {
-                        data: data.clone(),
-                        generation,
-                    },
+                    HotEntry::new(data.clone(), generation, meta.clone()),
                 );
             }
         }
         tx.commit()
             .map_err(|e| Self::sqlite_error("Failed to commit SQLite transaction", e))?;
+        self.recount_rows(&replaced, &written);
         Ok(())
     }
 
-    fn decode_index_entry(value_bytes: &[u8], generation: i64) -> CacheResult<IndexEntry> {
-        let (file_info, decoded_len): (FileInfo, usize) =
-            bincode::decode_from_slice(value_bytes, bincode::config::standard()).map_err(|e| {
-                CacheError::Io(std::io::Error::other(format!(
-                    "Failed to deserialize FileInfo: {}",
-                    e
-                )))
-            })?;
-
-        if file_info.path.to_string_lossy().starts_with("memory://") {
-            if value_bytes.len() <= decoded_len {
-                return Err(CacheError::Io(std::io::Error::other(
-                    "Inline SQLite entry is missing data bytes",
-                )));
-            }
-            Ok(IndexEntry::Inline(HotEntry {
-                data: Bytes::copy_from_slice(&value_bytes[decoded_len..]),
-                generation,
-            }))
-        } else {
-            Ok(IndexEntry::File(file_info))
-        }
-    }
-
-    fn read_index_generation(&self, key: &str) -> CacheResult<Option<i64>> {
-        let conn = self.index_db.lock();
-        conn.query_row(
-            "SELECT generation FROM cache_index WHERE key = ?1",
+    /// Index value stored for `key`, read inside a write transaction
+    fn current_row(tx: &rusqlite::Transaction<'_>, key: &str) -> CacheResult<Option<Vec<u8>>> {
+        tx.query_row(
+            "SELECT value FROM cache_index WHERE key = ?1",
             params![key],
             |row| row.get(0),
         )
         .optional()
-        .map_err(|e| Self::sqlite_error("Failed to read SQLite index generation", e))
+        .map_err(|e| Self::sqlite_error("Failed to read SQLite index entry", e))
     }
 
-    fn read_index_entry(&self, key: &str) -> CacheResult<Option<IndexEntry>> {
-        let conn = self.index_db.lock();
-        let row: Option<(Vec<u8>, i64)> = conn
-            .query_row(
-                "SELECT value, generation FROM cache_index WHERE key = ?1",
-                params![key],
-                |row| Ok((row.get(0)?, row.get(1)?)),
-            )
-            .optional()
-            .map_err(|e| Self::sqlite_error("Failed to read SQLite index entry", e))?;
-        drop(conn);
+    /// Tier and stored size of the value an index row describes; `None` for
+    /// files registered from elsewhere and rows that do not decode
+    fn row_size(&self, value_bytes: &[u8]) -> Option<(Tier, u64)> {
+        let (file_info, _) = Self::decode_file_info(value_bytes).ok()?;
+        if file_info.path.to_string_lossy().starts_with("memory://") {
+            Some((Tier::Inline, file_info.size))
+        } else if PackedRef::is_packed(&file_info.path) {
+            Some((Tier::Packed, file_info.size))
+        } else if self.owns_file(&self.directory.join(&file_info.path)) {
+            Some((Tier::File, file_info.size))
+        } else {
+            None
+        }
+    }
 
-        row.map(|(value_bytes, generation)| Self::decode_index_entry(&value_bytes, generation))
-            .transpose()
+    fn count_row(&self, value_bytes: &[u8]) {
+        if let Some(size) = self.row_size(value_bytes) {
+            self.tier_bytes.add(size);
+        }
     }
 
-    fn read_file_entry(&self, key: &str, file_info: FileInfo) -> CacheResult<Option<CacheEntry>> {
-        match std::fs::read(&file_info.path) {
-            Ok(raw_data) => {
-                self.stats.record_cold_hit();
-                let data = self.decompress_if_needed(&raw_data, file_info.compressed)?;
-                self.stats.record_read(data.len() as u64);
-                Ok(Some(CacheEntry::new_inline(
-                    key.to_string(),
-                    data.to_vec(),
-                    vec![],
-                    None,
-                )))
-            }
-            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
-                self.hot_cache.remove(key);
-                self.warm_cache.remove(key);
-                self.cold_index.write().remove(key);
-                self.stats.record_miss();
-                Ok(None)
-            }
-            Err(err) => Err(CacheError::Io(err)),
+    fn uncount_row(&self, value_bytes: &[u8]) {
+        if let Some(size) = self.row_size(value_bytes) {
+            self.tier_bytes.sub(size);
         }
     }
 
-    fn get_current_timestamp() -> u64 {
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs()
+    /// Account for rows that replaced others in one transaction
+    fn recount_rows(&self, replaced: &[Vec<u8>], written: &[Vec<u8>]) {
+        replaced.iter().for_each(|row| self.uncount_row(row));
+        written.iter().for_each(|row| self.count_row(row));
     }
 
-    fn new_generation() -> i64 {
-        let duration = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default();
-        duration
-            .as_secs()
-            .saturating_mul(1_000_000_000)
-            .saturating_add(u64::from(duration.subsec_nanos())) as i64
+    /// Count the live bytes of every tier afresh from the index, picking up
+    /// rows other processes wrote or removed
+    fn recount_tiers(&self) -> CacheResult<()> {
+        let conn = self.index_db.lock();
+        let mut stmt = conn
+            .prepare("SELECT value FROM cache_index")
+            .map_err(|e| Self::sqlite_error("Failed to query SQLite index", e))?;
+        let rows = stmt
+            .query_map([], |row| row.get::<_, Vec<u8>>(0))
+            .map_err(|e| Self::sqlite_error("Failed to iterate SQLite index", e))?;
+        let counted = TierBytes::default();
+        for row in rows {
+            let value_bytes =
+                row.map_err(|e| Self::sqlite_error("Failed to read SQLite index row", e))?;
+            if let Some(size) = self.row_size(&value_bytes) {
+                counted.add(size);
+            }
+        }
+        self.tier_bytes.set(counted.get());
+        Ok(())
     }
 
-    fn build_file_path(&self, key: &str) -> PathBuf {
-        let hash = blake3::hash(key.as_bytes());
-        let hex_hash = hash.to_hex();
-        self.directory
-            .join("data")
-            .join(format!("{}.dat", &hex_hash[..16]))
+    /// Decode the FileInfo at the start of an index value, returning it and its encoded length
+    ///
+    /// Current values start with [`FILE_INFO_V3`] and older ones with
+    /// [`FILE_INFO_V2`], neither of which can begin a legacy value: that
+    /// starts with the path's length as a bincode varint, where the tag byte
+    /// 255 is unused and 254 introduces a 128-bit length. Rows from before
+    /// metadata read back without tags or expiry.
+    fn decode_file_info(value_bytes: &[u8]) -> CacheResult<(FileInfo, usize)> {
+        let decoded = match value_bytes.split_first() {
+            Some((&FILE_INFO_V3, rest)) => {
+                bincode::decode_from_slice(rest, bincode::config::standard())
+                    .map(|(file_info, len)| (file_info, len + 1))
+            }
+            Some((&FILE_INFO_V2, rest)) => {
+                bincode::decode_from_slice(rest, bincode::config::standard()).map(
+                    |(v2, len): (FileInfoV2, usize)| {
+                        let file_info = FileInfo {
+                            path: v2.path,
+                            size: v2.size,
+                            created_at: v2.created_at,
+                            compression: v2.compression,
+                            checksum: v2.checksum,
+                            meta: EntryMeta::created(v2.created_at),
+                        };
+                        (file_info, len + 1)
+                    },
+                )
+            }
+            _ => bincode::decode_from_slice(value_bytes, bincode::config::standard()).map(
+                |(legacy, len): (LegacyFileInfo, usize)| {
+                    let file_info = FileInfo {
+                        path: legacy.path,
+                        size: legacy.size,
+                        created_at: legacy.created_at,
+                        compression: legacy.compression,
+                        checksum: None,
+                        meta: EntryMeta::created(legacy.created_at),
+                    };
+                    (file_info, len)
+                },
+            ),
+        };
+        decoded.map_err(|e| {
+            CacheError::Io(std::io::Error::other(format!(
+                "Failed to deserialize FileInfo: {}",
+                e
+            )))
+        })
     }
 
-    fn remove_existing_persisted_entry(&self, key: &str) -> CacheResult<bool> {
-        let mut removed_file = false;
-        if let Some((_, file_info)) = self.cold_index.write().remove(key) {
-            if !file_info.path.to_string_lossy().starts_with("memory://") {
-                self.write_batcher.sync();
-                match std::fs::remove_file(&file_info.path) {
-                    Ok(_) => removed_file = true,
-                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
-                    Err(err) => return Err(CacheError::Io(err)),
+    fn encode_file_info(file_info: &FileInfo) -> CacheResult<Vec<u8>> {
+        let mut value_bytes = vec![FILE_INFO_V3];
+        bincode::encode_into_std_write(file_info, &mut value_bytes, bincode::config::standard())
+            .map_err(|e| {
+                CacheError::Io(std::io::Error::other(format!(
+                    "Failed to serialize FileInfo: {}",
+                    e
+                )))
+            })?;
+        Ok(value_bytes)
+    }
+
+    /// Path of a data file as stored in the index: relative to the cache root when possible
+    fn portable_path(&self, path: &Path) -> PathBuf {
+        path.strip_prefix(&self.directory)
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|_| path.to_path_buf())
+    }
+
+    /// Resolve an indexed data file path against the current cache root
+    ///
+    /// Legacy indices hold absolute paths; when the directory has moved those
+    /// are looked up by file name where the current fan-out puts it instead.
+    fn resolve_data_path(&self, path: &Path) -> PathBuf {
+        if path.is_relative() {
+            return self.directory.join(path);
+        }
+        if path.exists() {
+            return path.to_path_buf();
+        }
+        match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => {
+                let relocated = self.data_path(name);
+                if relocated.exists() {
+                    relocated
+                } else {
+                    path.to_path_buf()
                 }
             }
+            None => path.to_path_buf(),
         }
+    }
 
-        let conn = self.index_db.lock();
-        conn.execute("DELETE FROM cache_index WHERE key = ?1", params![key])
-            .map_err(|e| Self::sqlite_error("Failed to remove SQLite index entry", e))?;
-        Ok(removed_file)
+    /// Where a data file named `filename` lives under the configured fan-out
+    ///
+    /// Each level is a directory named after the next two hex digits of the
+    /// name, so with two levels `abcd0123….dat` is `data/ab/cd/abcd0123….dat`.
+    fn data_path(&self, filename: &str) -> PathBuf {
+        let mut path = self.directory.join("data");
+        let digits = FileNaming::hash_digits(filename);
+        for level in 0..self.config.data_fanout {
+            if let Some(prefix) = digits.get(2 * level..2 * level + 2) {
+                path.push(prefix);
+            }
+        }
+        path.join(filename)
     }
 
-    fn persist_file_infos(&self, file_infos: &[(String, FileInfo)]) -> CacheResult<()> {
-        if file_infos.is_empty() {
-            return Ok(());
+    /// Move data files laid out for another fan-out to where the configured one puts them
+    ///
+    /// `data/.layout` records the fan-out the files were written for; caches
+    /// older than the marker are flat. Files move first and index rows are
+    /// rewritten after, and the marker only changes once both are done, so an
+    /// interrupted migration finishes on the next open. Every process sharing
+    /// the directory must use the same fan-out. Returns the number of files moved.
+    fn migrate_data_layout(&self) -> CacheResult<u64> {
+        let data_dir = self.directory.join("data");
+        let marker = data_dir.join(DATA_LAYOUT_FILE);
+        let recorded = std::fs::read_to_string(&marker)
+            .ok()
+            .and_then(|layout| layout.trim().parse::<usize>().ok());
+        if recorded == Some(self.config.data_fanout) {
+            return Ok(0);
+        }
+
+        let mut moved = 0;
+        let mut visited = Vec::new();
+        let mut pending = vec![data_dir.clone()];
+        while let Some(directory) = pending.pop() {
+            for entry in std::fs::read_dir(&directory)? {
+                let entry = entry?;
+                let path = entry.path();
+                if entry.file_type()?.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                    continue;
+                };
+                if !name.ends_with(".dat") {
+                    continue;
+                }
+                let target = self.data_path(name);
+                if target != path {
+                    if let Some(parent) = target.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    std::fs::rename(&path, &target)?;
+                    moved += 1;
+                }
+            }
+            visited.push(directory);
         }
+        // Deepest first, so emptied parents go too; the rest are still in use
+        for directory in visited.iter().rev().filter(|dir| **dir != data_dir) {
+            let _ = std::fs::remove_dir(directory);
+        }
+
+        let rewritten = self.rewrite_data_paths()?;
+        std::fs::write(&marker, self.config.data_fanout.to_string())?;
+        if moved > 0 || rewritten > 0 {
+            tracing::info!(
+                "Moved {} data files and {} index entries to a {}-level fan-out",
+                moved,
+                rewritten,
+                self.config.data_fanout
+            );
+        }
+        Ok(moved)
+    }
 
+    /// Point index rows at their data files' place in the configured fan-out
+    fn rewrite_data_paths(&self) -> CacheResult<u64> {
         let mut conn = self.index_db.lock();
-        let tx = conn
-            .transaction()
-            .map_err(|e| Self::sqlite_error("Failed to begin SQLite transaction", e))?;
+        let tx = Self::write_transaction(&mut conn)?;
 
+        let mut rewrites = Vec::new();
         {
             let mut stmt = tx
-                .prepare("INSERT OR REPLACE INTO cache_index (key, value, generation) VALUES (?1, ?2, ?3)")
-                .map_err(|e| Self::sqlite_error("Failed to prepare SQLite file info", e))?;
-            for (key, file_info) in file_infos {
-                let value_bytes = bincode::encode_to_vec(file_info, bincode::config::standard())
-                    .map_err(|e| {
-                        CacheError::Io(std::io::Error::other(format!(
-                            "Failed to serialize FileInfo: {}",
-                            e
-                        )))
-                    })?;
-                stmt.execute(params![key.as_str(), value_bytes, Self::new_generation()])
-                    .map_err(|e| Self::sqlite_error("Failed to persist SQLite file info", e))?;
+                .prepare("SELECT key, value FROM cache_index")
+                .map_err(|e| Self::sqlite_error("Failed to query SQLite index", e))?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+                })
+                .map_err(|e| Self::sqlite_error("Failed to iterate SQLite index", e))?;
+
+            for row in rows {
+                let (key, value_bytes) =
+                    row.map_err(|e| Self::sqlite_error("Failed to read SQLite index row", e))?;
+                let (mut file_info, decoded_len) = Self::decode_file_info(&value_bytes)?;
+                if file_info.path.to_string_lossy().starts_with("memory://")
+                    || PackedRef::is_packed(&file_info.path)
+                {
+                    continue;
+                }
+                let Some(name) = file_info.path.file_name().and_then(|name| name.to_str()) else {
+                    continue;
+                };
+
+                let target = self.data_path(name);
+                let portable = self.portable_path(&target);
+                if file_info.path == portable || !target.exists() {
+                    continue;
+                }
+                file_info.path = portable;
+                let mut new_value = Self::encode_file_info(&file_info)?;
+                new_value.extend_from_slice(&value_bytes[decoded_len..]);
+                rewrites.push((key, new_value));
             }
         }
 
+        for (key, value) in &rewrites {
+            // UPDATE keeps the sequence number and generation, so moving is not a rewrite
+            tx.execute(
+                "UPDATE cache_index SET value = ?1 WHERE key = ?2",
+                params![value, key],
+            )
+            .map_err(|e| Self::sqlite_error("Failed to rewrite SQLite index entry", e))?;
+        }
         tx.commit()
             .map_err(|e| Self::sqlite_error("Failed to commit SQLite transaction", e))?;
-        Ok(())
+        Ok(rewrites.len() as u64)
     }
 
-    /// Compress data if it provides significant space savings
-    fn compress_if_beneficial(&self, data: &[u8]) -> (Bytes, bool) {
-        if !self.config.use_compression || data.len() < self.config.compression_threshold {
-            return (Bytes::copy_from_slice(data), false);
+    /// Index the data files the index has no row for, by the key in their header
+    ///
+    /// Restored entries are stored in the order their files were last written.
+    /// Files from before data file headers, files with a damaged header and
+    /// files that are not where the configured fan-out puts their key are left
+    /// alone. Returns the number of entries restored.
+    pub fn restore_from_data_files(&self) -> CacheResult<u64> {
+        if self.config.index_key.is_some() {
+            // A data file's header is not signed, so its row cannot be trusted back
+            tracing::debug!("Not restoring index entries: the index is signed");
+            return Ok(0);
         }
+        self.write_batcher.sync();
 
-        // Use LZ4 for fast compression
-        match lz4_flex::compress_prepend_size(data) {
-            compressed if compressed.len() < data.len() * 9 / 10 => (Bytes::from(compressed), true),
-            _ => (Bytes::copy_from_slice(data), false),
-        }
-    }
+        let indexed: HashSet<String> = {
+            let conn = self.index_db.lock();
+            let mut stmt = conn
+                .prepare("SELECT key FROM cache_index")
+                .map_err(|e| Self::sqlite_error("Failed to query SQLite index", e))?;
+            let keys = stmt
+                .query_map([], |row| row.get::<_, String>(0))
+                .map_err(|e| Self::sqlite_error("Failed to iterate SQLite index", e))?
+                .collect::<Result<_, _>>()
+                .map_err(|e| Self::sqlite_error("Failed to read SQLite index row", e))?;
+            keys
+        };
 
-    /// Decompress data if it was previously compressed
-    fn decompress_if_needed(&self, data: &[u8], is_compressed: bool) -> CacheResult<Bytes> {
-        if !is_compressed {
-            return Ok(Bytes::copy_from_slice(data));
+        let mut found = Vec::new();
+        for (path, metadata) in self.data_dir_files()? {
+            if path.extension() != Some("dat".as_ref()) {
+                continue;
+            }
+            if let Some(restorable) = self.restorable_data_file(path, &metadata, &indexed) {
+                found.push(restorable);
+            }
         }
-
-        match lz4_flex::decompress_size_prepended(data) {
-            Ok(decompressed) => Ok(Bytes::from(decompressed)),
-            Err(e) => Err(CacheError::Deserialization(format!(
-                "Decompression failed: {}",
-                e
-            ))),
+        let restored = self.restore_data_files(found)?;
+        if !restored.is_empty() {
+            tracing::info!(
+                "Restored {} index entries from data files in {:?}",
+                restored.len(),
+                self.directory
+            );
         }
+        Ok(restored.len() as u64)
     }
 
-    /// Remove least recently used entries from hot cache when it's full
-    fn cleanup_hot_cache(&self) {
-        if self.hot_cache.len() > self.config.hot_cache_size {
-            // Simple eviction: remove 10% of entries
-            let entries_to_remove = self.config.hot_cache_size / 10;
-            let mut removed_count = 0;
-
-            self.hot_cache.retain(|_, _| {
-                if removed_count < entries_to_remove {
-                    removed_count += 1;
-                    false
-                } else {
-                    true
+    /// Every file under `data/`, with its metadata
+    ///
+    /// Files removed while the directory is walked are left out.
+    fn data_dir_files(&self) -> CacheResult<Vec<(PathBuf, std::fs::Metadata)>> {
+        let mut files = Vec::new();
+        let mut pending = vec![self.directory.join("data")];
+        while let Some(directory) = pending.pop() {
+            for entry in std::fs::read_dir(&directory)? {
+                let entry = entry?;
+                let path = entry.path();
+                if entry.file_type()?.is_dir() {
+                    pending.push(path);
+                    continue;
                 }
-            });
+                match entry.metadata() {
+                    Ok(metadata) => files.push((path, metadata)),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(CacheError::Io(e)),
+                }
+            }
         }
+        Ok(files)
     }
 
-    /// Remove old entries from warm cache based on access time
-    fn cleanup_warm_cache(&self) {
-        if self.warm_cache.len() > self.config.warm_cache_size {
-            let current_time = Self::get_current_timestamp();
-            let mut keys_to_remove = Vec::new();
-
-            // Find entries that haven't been accessed in 5 minutes
-            for entry in self.warm_cache.iter() {
-                let last_accessed = entry.value().last_accessed.load(Ordering::Relaxed);
-                if current_time - last_accessed > 300 {
-                    // 5 minutes
-                    keys_to_remove.push(entry.key().clone());
-                }
+    /// The key and FileInfo to index the data file at `path` under, if its
+    /// header names a key that is not in `indexed` and that the configured
+    /// fan-out puts at `path` under some naming scheme
+    fn restorable_data_file(
+        &self,
+        path: PathBuf,
+        metadata: &std::fs::Metadata,
+        indexed: &HashSet<String>,
+    ) -> Option<(SystemTime, String, FileInfo)> {
+        let header = match DataFileHeader::read_from(&path) {
+            Ok(Some((header, _))) => header,
+            Ok(None) => return None,
+            Err(e) => {
+                tracing::warn!("Not restoring data file {:?}: {}", path, e);
+                return None;
             }
+        };
+        let named_for_key = |name: String| path == self.data_path(&name);
+        if indexed.contains(&header.key)
+            || !(FileNaming::ALL
+                .iter()
+                .any(|naming| named_for_key(naming.file_name(&header.key)))
+                || named_for_key(legacy_filename(&header.key)))
+        {
+            return None;
+        }
+        let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+        let created_at = modified
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        // Tags and expiry live in the index, so a restored entry has neither
+        let file_info = FileInfo {
+            path,
+            size: metadata.len(),
+            created_at,
+            compression: header.compression,
+            checksum: header.checksum,
+            meta: EntryMeta::created(created_at),
+        };
+        Some((modified, header.key, file_info))
+    }
+
+    /// Index files found by [`restorable_data_file`](Self::restorable_data_file),
+    /// oldest first, returning the rows written
+    fn restore_data_files(
+        &self,
+        mut found: Vec<(SystemTime, String, FileInfo)>,
+    ) -> CacheResult<Vec<FileRow>> {
+        found.sort_by_key(|(modified, _, _)| *modified);
+        let restored: Vec<FileRow> = found
+            .into_iter()
+            .map(|(_, key, file_info)| (key, file_info, None))
+            .collect();
 
-            // Remove up to 10% of cache entries
-            let max_removals = self.config.warm_cache_size / 10;
-            for key in keys_to_remove.into_iter().take(max_removals) {
-                self.warm_cache.remove(&key);
+        {
+            let index = self.cold_index.write();
+            for (key, file_info, _) in &restored {
+                index.insert(key.clone(), file_info.clone());
             }
         }
+        self.persist_file_infos(&restored)?;
+        Ok(restored)
     }
-}
 
-impl StorageBackend for OptimizedStorage {
-    fn get(&self, key: &str) -> CacheResult<Option<CacheEntry>> {
-        if let Some(entry) = self.hot_cache.get(key) {
-            match self.read_index_generation(key)? {
-                Some(generation) if generation == entry.generation => {
-                    self.stats.record_hot_hit();
-                    self.stats.record_read(entry.data.len() as u64);
-                    return Ok(Some(CacheEntry::new_inline(
-                        key.to_string(),
-                        entry.data.to_vec(),
-                        vec![],
-                        None,
-                    )));
-                }
-                _ => {
-                    drop(entry);
-                    self.hot_cache.remove(key);
+    /// Delete or restore data files no index row points at, delete
+    /// temporary files left by interrupted writes, and cut data files longer
+    /// than their indexed value back to it
+    ///
+    /// Only files unchanged for `orphan_grace` are touched, so a file written
+    /// or linked just before its index row, by this or another process, is
+    /// not taken for an orphan. An orphan that
+    /// [`restore_from_data_files`](Self::restore_from_data_files) would index
+    /// is restored; any other is deleted. Only files the cache wrote itself,
+    /// with a checksum in their row, are truncated.
+    fn collect_orphans(&self) -> CacheResult<VacuumReport> {
+        self.write_batcher.sync();
+        let mut report = VacuumReport::default();
+
+        // Listed before the index is read, so a file whose row lands in
+        // between counts as referenced
+        let files = self.data_dir_files()?;
+        let mut indexed = HashSet::new();
+        // Indexed key and length of each referenced file, when it is sure
+        let mut referenced = HashMap::new();
+        {
+            let conn = self.index_db.lock();
+            let mut stmt = conn
+                .prepare("SELECT key, value, generation, mac FROM cache_index")
+                .map_err(|e| Self::sqlite_error("Failed to query SQLite index", e))?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Vec<u8>>(1)?,
+                        row.get::<_, i64>(2)?,
+                        row.get::<_, Option<Vec<u8>>>(3)?,
+                    ))
+                })
+                .map_err(|e| Self::sqlite_error("Failed to iterate SQLite index", e))?;
+            for row in rows {
+                let (key, value_bytes, generation, mac) =
+                    row.map_err(|e| Self::sqlite_error("Failed to read SQLite index row", e))?;
+                match self.decode_index_entry(&key, &value_bytes, generation, mac) {
+                    Ok(IndexEntry::File(file_info, _)) => {
+                        if let Some(name) = file_info.path.file_name() {
+                            let indexed_len =
+                                file_info.checksum.map(|_| (key.clone(), file_info.size));
+                            referenced.insert(name.to_os_string(), indexed_len);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        // The row's file cannot be told apart from an orphan
+                        tracing::warn!("Not collecting orphaned data files: {}: {}", key, e);
+                        return Ok(report);
+                    }
                 }
+                indexed.insert(key);
             }
         }
 
-        match self.read_index_entry(key)? {
-            Some(IndexEntry::Inline(entry)) => {
-                self.stats.record_hot_hit();
-                self.stats.record_read(entry.data.len() as u64);
-                self.hot_cache.insert(key.to_string(), entry.clone());
-                Ok(Some(CacheEntry::new_inline(
-                    key.to_string(),
-                    entry.data.to_vec(),
-                    vec![],
-                    None,
-                )))
+        let mut found = Vec::new();
+        for (path, metadata) in files {
+            let unchanged_for = last_changed(&metadata).elapsed().unwrap_or(Duration::ZERO);
+            if unchanged_for < self.config.orphan_grace {
+                continue;
             }
-            Some(IndexEntry::File(file_info)) => {
-                self.cold_index
-                    .write()
-                    .insert(key.to_string(), file_info.clone());
-                self.read_file_entry(key, file_info)
+            let is_orphan = match path.extension().and_then(|ext| ext.to_str()) {
+                Some("tmp") => {
+                    if std::fs::remove_file(&path).is_ok() {
+                        report.temp_files_removed += 1;
+                    }
+                    continue;
+                }
+                Some("dat") => match path.file_name().and_then(|name| referenced.get(name)) {
+                    Some(Some((key, len))) if metadata.len() > *len => {
+                        self.truncate_data_file(key, &path, *len)?;
+                        report.files_truncated += 1;
+                        report.bytes_reclaimed += metadata.len() - len;
+                        continue;
+                    }
+                    Some(_) => false,
+                    None => true,
+                },
+                _ => false,
+            };
+            if !is_orphan {
+                continue;
             }
-            None => {
-                self.hot_cache.remove(key);
-                self.warm_cache.remove(key);
-                self.cold_index.write().remove(key);
-                self.stats.record_miss();
-                Ok(None)
+            let restorable = match self.config.index_key {
+                // A data file's header is not signed, so its row cannot be trusted back
+                Some(_) => None,
+                None => self.restorable_data_file(path.clone(), &metadata, &indexed),
+            };
+            match restorable {
+                Some(restorable) => found.push(restorable),
+                None => {
+                    self.open_files.forget(&path);
+                    if std::fs::remove_file(&path).is_ok() {
+                        self.stats.record_file_deleted();
+                        report.orphans_removed += 1;
+                    }
+                }
             }
         }
+        report.orphans_adopted = self.restore_data_files(found)?.len() as u64;
+
+        if report.files_truncated > 0 {
+            tracing::info!(
+                "Truncated {} oversized data files in {:?}",
+                report.files_truncated,
+                self.directory
+            );
+        }
+        if report.orphans_removed + report.orphans_adopted + report.temp_files_removed > 0 {
+            tracing::info!(
+                "Removed {} orphaned data files, restored {} and removed {} temporary files in {:?}",
+                report.orphans_removed,
+                report.orphans_adopted,
+                report.temp_files_removed,
+                self.directory
+            );
+        }
+        Ok(report)
     }
 
-    fn set(&self, key: &str, entry: CacheEntry) -> CacheResult<()> {
-        let data = match &entry.storage {
-            crate::serialization::StorageMode::Inline(data) => data,
-            crate::serialization::StorageMode::File(filename) => {
-                // Read file data
-                let file_path = self.directory.join("data").join(filename);
-                return match std::fs::read(&file_path) {
-                    Ok(file_data) => self.set_data(key, &file_data),
-                    Err(e) => Err(CacheError::Io(e)),
+    /// Cut `key`'s data file back to the `len` bytes its index row holds
+    ///
+    /// The key's mapping and descriptor go first, since reading a mapping
+    /// past the new end of its file faults.
+    fn truncate_data_file(&self, key: &str, path: &Path, len: u64) -> CacheResult<()> {
+        // A writer may have replaced the file since the index was read
+        let _key_lock = self.key_locks.lock(key)?;
+        if self
+            .indexed_file(key)?
+            .is_none_or(|file_info| file_info.path != path || file_info.size != len)
+        {
+            return Ok(());
+        }
+        self.hot_cache.remove(key);
+        self.warm_cache.remove(key);
+        self.open_files.forget(path);
+        OpenOptions::new().write(true).open(path)?.set_len(len)?;
+        Ok(())
+    }
+
+    /// Rewrite absolute data file paths in the index as paths relative to the cache root
+    ///
+    /// Returns the number of index rows rewritten.
+    pub fn relocate(&self) -> CacheResult<u64> {
+        self.write_batcher.sync();
+
+        let mut conn = self.index_db.lock();
+        let tx = Self::write_transaction(&mut conn)?;
+
+        let mut rewrites = Vec::new();
+        {
+            let mut stmt = tx
+                .prepare("SELECT key, value FROM cache_index")
+                .map_err(|e| Self::sqlite_error("Failed to query SQLite index", e))?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+                })
+                .map_err(|e| Self::sqlite_error("Failed to iterate SQLite index", e))?;
+
+            for row in rows {
+                let (key, value_bytes) =
+                    row.map_err(|e| Self::sqlite_error("Failed to read SQLite index row", e))?;
+                let (mut file_info, decoded_len) = Self::decode_file_info(&value_bytes)?;
+                if !file_info.path.is_absolute() {
+                    continue;
+                }
+
+                let resolved = self.resolve_data_path(&file_info.path);
+                file_info.path = match resolved.strip_prefix(&self.directory) {
+                    Ok(relative) => relative.to_path_buf(),
+                    Err(_) => continue,
                 };
+
+                // Inline entries keep their data bytes after the encoded FileInfo
+                let mut new_value = Self::encode_file_info(&file_info)?;
+                new_value.extend_from_slice(&value_bytes[decoded_len..]);
+                rewrites.push((key, new_value, resolved));
             }
-        };
+        }
+
+        for (key, value, _) in &rewrites {
+            // UPDATE keeps the sequence number, so store order is preserved
+            tx.execute(
+                "UPDATE cache_index SET value = ?1 WHERE key = ?2",
+                params![value, key],
+            )
+            .map_err(|e| Self::sqlite_error("Failed to rewrite SQLite index entry", e))?;
+        }
+        tx.commit()
+            .map_err(|e| Self::sqlite_error("Failed to commit SQLite transaction", e))?;
+        drop(conn);
+
+        let cold_index = self.cold_index.read();
+        for (key, _, resolved) in &rewrites {
+            if let Some(mut file_info) = cold_index.get_mut(key) {
+                file_info.path = resolved.clone();
+            }
+        }
 
-        self.set_data(key, data)
+        tracing::info!("Relocated {} index entries", rewrites.len());
+        Ok(rewrites.len() as u64)
     }
 
-    fn set_batch(&self, entries: Vec<(String, Vec<u8>)>) -> CacheResult<()> {
-        if entries.is_empty() {
-            return Ok(());
+    /// Write a point-in-time copy of the cache to `destination`, which must be
+    /// empty or not exist yet
+    ///
+    /// Queued writes are flushed and the index is copied in one SQLite read,
+    /// so the snapshot holds exactly the entries stored at that moment. Data
+    /// and segment files are then hard-linked, or copied where linking is not
+    /// possible, with the index free again, so readers and writers carry on
+    /// meanwhile. A data file rewritten or removed before it was linked
+    /// belongs to a later write, and its entry is left out of the snapshot.
+    /// Files registered from outside the cache are referenced, not copied.
+    pub fn snapshot(&self, destination: &Path) -> CacheResult<SnapshotReport> {
+        if std::fs::read_dir(destination).is_ok_and(|mut entries| entries.next().is_some()) {
+            return Err(CacheError::Io(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("snapshot destination {:?} is not empty", destination),
+            )));
         }
+        std::fs::create_dir_all(destination)?;
+        let index_path = destination.join(INDEX_FILE);
+        let Some(index_path_str) = index_path.to_str() else {
+            return Err(CacheError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("snapshot destination {:?} is not UTF-8", destination),
+            )));
+        };
 
-        let mut file_infos = Vec::new();
-        let mut inline_entries = Vec::new();
-        let mut has_async_file_writes = false;
+        self.write_batcher.sync();
+        // Keeps this process from compacting away segments the copied index points into
+        let _compaction = self.compactor.lock.lock();
+        self.index_db
+            .lock()
+            .execute("VACUUM INTO ?1", params![index_path_str])
+            .map_err(|e| Self::sqlite_error("Failed to copy SQLite index", e))?;
+
+        let mut report = SnapshotReport::default();
+        let mut conn = Self::open_index_connection_at(&index_path)?;
+        let mut stale = Vec::new();
+        let mut rewrites = Vec::new();
+        let mut segments: HashMap<u32, Vec<String>> = HashMap::new();
+        {
+            let mut stmt = conn
+                .prepare("SELECT key, value FROM cache_index")
+                .map_err(|e| Self::sqlite_error("Failed to query SQLite snapshot index", e))?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+                })
+                .map_err(|e| Self::sqlite_error("Failed to iterate SQLite snapshot index", e))?;
+            for row in rows {
+                let (key, value_bytes) =
+                    row.map_err(|e| Self::sqlite_error("Failed to read SQLite snapshot row", e))?;
+                let (mut file_info, decoded_len) = Self::decode_file_info(&value_bytes)?;
+                if file_info.path.to_string_lossy().starts_with("memory://") {
+                    continue;
+                }
+                if PackedRef::is_packed(&file_info.path) {
+                    let location = PackedRef::parse(&file_info.path, file_info.size)?;
+                    segments.entry(location.segment).or_default().push(key);
+                    continue;
+                }
 
-        for (key, data) in entries {
-            let data_size = data.len();
-            self.stats.record_write(data_size as u64);
+                let source = self.resolve_data_path(&file_info.path);
+                let Ok(relative) = source.strip_prefix(&self.directory) else {
+                    continue;
+                };
+                if !self.owns_file(&source) {
+                    continue;
+                }
+                let target = destination.join(relative);
+                match self.snapshot_data_file(&source, &target, file_info.checksum)? {
+                    Some(copied) => {
+                        report.files += 1;
+                        report.bytes_copied += copied;
+                        // Legacy absolute paths would point back into this cache
+                        if file_info.path.is_absolute() {
+                            file_info.path = relative.to_path_buf();
+                            let mut value = Self::encode_file_info(&file_info)?;
+                            value.extend_from_slice(&value_bytes[decoded_len..]);
+                            rewrites.push((key, value));
+                        }
+                    }
+                    None => stale.push(key),
+                }
+            }
+        }
 
-            self.hot_cache.remove(&key);
-            self.warm_cache.remove(&key);
-            self.remove_existing_persisted_entry(&key)?;
+        if !segments.is_empty() {
+            std::fs::create_dir_all(destination.join(SEGMENT_DIR))?;
+        }
+        for (segment, keys) in segments {
+            let source = self.segments.path(segment);
+            let target = destination
+                .join(SEGMENT_DIR)
+                .join(source.file_name().unwrap_or_default());
+            // Segments are only ever appended to, so linking one mid-append is harmless
+            match link_or_copy(&source, &target, false) {
+                Ok(copied) => {
+                    report.files += 1;
+                    report.bytes_copied += copied;
+                }
+                // Compacted by another process after the index was copied
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => stale.extend(keys),
+                Err(e) => return Err(CacheError::Io(e)),
+            }
+        }
 
-            if data_size < self.config.disk_write_threshold {
-                inline_entries.push((key, Bytes::from(data)));
-                continue;
+        let layout = self.directory.join("data").join(DATA_LAYOUT_FILE);
+        if layout.exists() {
+            std::fs::create_dir_all(destination.join("data"))?;
+            std::fs::copy(&layout, destination.join("data").join(DATA_LAYOUT_FILE))?;
+        }
+        // Dictionaries are never rewritten, so linking them is safe
+        if let Ok(dictionaries) = std::fs::read_dir(self.directory.join(DICTIONARY_DIR)) {
+            std::fs::create_dir_all(destination.join(DICTIONARY_DIR))?;
+            for dictionary in dictionaries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_name().to_string_lossy().ends_with(".zdict"))
+            {
+                let target = destination
+                    .join(DICTIONARY_DIR)
+                    .join(dictionary.file_name());
+                report.bytes_copied += link_or_copy(&dictionary.path(), &target, false)?;
+                report.files += 1;
             }
+        }
 
-            let (compressed_data, is_compressed) = self.compress_if_beneficial(&data);
-            let file_path = self.build_file_path(&key);
-            let file_info = FileInfo {
-                path: file_path.clone(),
-                size: compressed_data.len() as u64,
-                created_at: Self::get_current_timestamp(),
-                compressed: is_compressed,
-            };
+        let tx = Self::write_transaction(&mut conn)?;
+        for key in &stale {
+            tx.execute("DELETE FROM cache_index WHERE key = ?1", params![key])
+                .map_err(|e| Self::sqlite_error("Failed to remove SQLite snapshot entry", e))?;
+            tx.execute("DELETE FROM cache_alias WHERE key = ?1", params![key])
+                .map_err(|e| Self::sqlite_error("Failed to remove SQLite snapshot alias", e))?;
+        }
+        for (key, value) in &rewrites {
+            tx.execute(
+                "UPDATE cache_index SET value = ?1 WHERE key = ?2",
+                params![value, key],
+            )
+            .map_err(|e| Self::sqlite_error("Failed to rewrite SQLite snapshot entry", e))?;
+        }
+        report.entries = tx
+            .query_row("SELECT COUNT(*) FROM cache_index", [], |row| {
+                row.get::<_, i64>(0)
+            })
+            .map_err(|e| Self::sqlite_error("Failed to count SQLite snapshot entries", e))?
+            as u64;
+        tx.commit()
+            .map_err(|e| Self::sqlite_error("Failed to commit SQLite transaction", e))?;
 
-            self.cold_index
-                .write()
-                .insert(key.clone(), file_info.clone());
+        tracing::info!(
+            "Snapshot of {} entries written to {:?}, {} left out as rewritten since",
+            report.entries,
+            destination,
+            stale.len()
+        );
+        Ok(report)
+    }
 
-            if self.config.use_file_locking {
-                self.write_with_lock(&file_path, &compressed_data)?;
-            } else if self.config.sync_writes || data_size > 1024 * 1024 {
-                std::fs::write(&file_path, &compressed_data).map_err(CacheError::Io)?;
-            } else {
-                self.write_batcher.write_async(file_path, compressed_data);
-                has_async_file_writes = true;
-            }
+    /// Place the data file at `source` in a snapshot at `target`, returning
+    /// the bytes copied, or `None` if it no longer holds the indexed value
+    fn snapshot_data_file(
+        &self,
+        source: &Path,
+        target: &Path,
+        checksum: Option<u32>,
+    ) -> CacheResult<Option<u64>> {
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Without atomic writes a file is rewritten in place, which a link would share
+        let copy = !self.config.atomic_writes;
+        let copied = match link_or_copy(source, target, copy) {
+            Ok(copied) => copied,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(CacheError::Io(e)),
+        };
+        let Some(checksum) = checksum else {
+            return Ok(Some(copied));
+        };
 
-            file_infos.push((key, file_info));
+        // A linked file is never modified, so its header tells which write it
+        // holds; a copy may have caught a rewrite halfway and is checked whole
+        let current = if copied > 0 {
+            let file = std::fs::read(target)?;
+            DataFileHeader::decode(&file)
+                .ok()
+                .flatten()
+                .map(|(_, offset)| payload_checksum(&file[offset..]))
+        } else {
+            DataFileHeader::read_from(target)
+                .ok()
+                .flatten()
+                .and_then(|(header, _)| header.checksum)
+        };
+        if current == Some(checksum) {
+            Ok(Some(copied))
+        } else {
+            std::fs::remove_file(target)?;
+            Ok(None)
         }
+    }
 
-        self.cleanup_hot_cache();
-        self.persist_inline_entries(&inline_entries)?;
-        if has_async_file_writes {
-            self.write_batcher.sync();
+    /// Decode `key`'s index row; inline values are checked against `mac` here,
+    /// the others when their bytes are read
+    fn decode_index_entry(
+        &self,
+        key: &str,
+        value_bytes: &[u8],
+        generation: i64,
+        mac: Option<Vec<u8>>,
+    ) -> CacheResult<IndexEntry> {
+        let (mut file_info, decoded_len) = Self::decode_file_info(value_bytes)?;
+
+        if file_info.path.to_string_lossy().starts_with("memory://") {
+            if value_bytes.len() <= decoded_len {
+                return Err(CacheError::Io(std::io::Error::other(
+                    "Inline SQLite entry is missing data bytes",
+                )));
+            }
+            let stored = &value_bytes[decoded_len..];
+            self.verify_row(key, file_info.compression, stored, mac.as_deref())?;
+            Ok(IndexEntry::Inline(HotEntry::new(
+                self.decompress_if_needed(stored, file_info.compression)?,
+                generation,
+                file_info.meta,
+            )))
+        } else if PackedRef::is_packed(&file_info.path) {
+            Ok(IndexEntry::Packed(PackedEntry {
+                location: PackedRef::parse(&file_info.path, file_info.size)?,
+                compression: file_info.compression,
+                checksum: file_info.checksum,
+                mac,
+                generation,
+                meta: file_info.meta,
+            }))
+        } else {
+            file_info.path = self.resolve_data_path(&file_info.path);
+            Ok(IndexEntry::File(file_info, mac))
+        }
+    }
+
+    fn read_index_generation(&self, key: &str) -> CacheResult<Option<i64>> {
+        let conn = self.index_db.lock();
+        conn.query_row(
+            "SELECT generation FROM cache_index WHERE key = ?1",
+            params![key],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| Self::sqlite_error("Failed to read SQLite index generation", e))
+    }
+
+    /// The data file `key`'s index row points at, read without decoding
+    /// or checking the rest of the row; `None` for inline and packed rows
+    fn indexed_file(&self, key: &str) -> CacheResult<Option<FileInfo>> {
+        let conn = self.index_db.lock();
+        let value_bytes: Option<Vec<u8>> = conn
+            .query_row(
+                "SELECT value FROM cache_index WHERE key = ?1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| Self::sqlite_error("Failed to read SQLite index entry", e))?;
+        drop(conn);
+
+        let Some(value_bytes) = value_bytes else {
+            return Ok(None);
+        };
+        let (mut file_info, _) = Self::decode_file_info(&value_bytes)?;
+        let path = file_info.path.to_string_lossy();
+        if path.starts_with("memory://") || PackedRef::is_packed(&file_info.path) {
+            return Ok(None);
+        }
+        file_info.path = self.resolve_data_path(&file_info.path);
+        Ok(Some(file_info))
+    }
+
+    /// Whether `key`'s row has moved on from `file_info` after a read of
+    /// its file failed, with the key locked by the caller
+    ///
+    /// Writers replace a key's file before they commit its row, so a read in
+    /// between finds bytes the row it read does not describe. Once the key
+    /// is locked the writer is done, and a row that still indexes the same
+    /// file means the file itself is at fault.
+    fn row_moved_on(&self, key: &str, file_info: &FileInfo) -> CacheResult<bool> {
+        Ok(self.indexed_file(key)?.is_none_or(|current| {
+            current.path != file_info.path
+                || current.checksum != file_info.checksum
+                || current.created_at != file_info.created_at
+        }))
+    }
+
+    /// Decoded index row of `key`; `None` when there is none or its entry
+    /// has expired, in which case the row goes
+    fn read_index_entry(&self, key: &str) -> CacheResult<Option<IndexEntry>> {
+        let conn = self.index_db.lock();
+        let row: Option<(Vec<u8>, i64, Option<Vec<u8>>)> = conn
+            .query_row(
+                "SELECT value, generation, mac FROM cache_index WHERE key = ?1",
+                params![key],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()
+            .map_err(|e| Self::sqlite_error("Failed to read SQLite index entry", e))?;
+        drop(conn);
+
+        let Some((value_bytes, generation, mac)) = row else {
+            return Ok(None);
+        };
+        match self.decode_index_entry(key, &value_bytes, generation, mac) {
+            Err(CacheError::Tampered(key)) => {
+                self.discard_tampered(&key)?;
+                Err(CacheError::Tampered(key))
+            }
+            Ok(entry) if entry.meta().is_expired(Self::get_current_timestamp()) => {
+                self.discard_expired(key, generation)?;
+                Ok(None)
+            }
+            entry => entry.map(Some),
+        }
+    }
+
+    fn read_file_entry(
+        &self,
+        key: &str,
+        file_info: FileInfo,
+        mac: Option<&[u8]>,
+        touch: bool,
+    ) -> CacheResult<Option<CacheEntry>> {
+        // Keeps a writer from replacing the file under us where renames are
+        // not atomic to readers, as on NFS
+        let key_lock = self
+            .config
+            .use_file_locking
+            .then(|| self.key_locks.lock_shared(key))
+            .transpose()?;
+        let read = self
+            .config
+            .sharing_retry
+            .run(|| self.read_file(key, &file_info, touch));
+        drop(key_lock);
+        self.finish_file_read(key, read, file_info, mac, touch)
+    }
+
+    /// Contents of `key`'s data file, which `file_info` indexes, mapped by
+    /// the warm tier when it is large enough or else through the cache of
+    /// open files
+    ///
+    /// Files without a checksum, such as registered ones, cannot be told
+    /// apart from a replacement and are opened afresh. Without `touch` the
+    /// read leaves the warm tier's recency order as it was.
+    fn read_file(
+        &self,
+        key: &str,
+        file_info: &FileInfo,
+        touch: bool,
+    ) -> std::io::Result<FileBytes> {
+        match file_info.checksum {
+            Some(checksum)
+                if self.config.mmap_threshold > 0
+                    && file_info.size >= self.config.mmap_threshold as u64 =>
+            {
+                self.read_mapped(key, file_info, checksum, touch)
+            }
+            Some(checksum) => self
+                .open_files
+                .read(&file_info.path, file_info.size, checksum)
+                .map(FileBytes::from),
+            None => std::fs::read(&file_info.path).map(FileBytes::from),
+        }
+    }
+
+    /// Contents of `key`'s data file through its warm-tier mapping
+    ///
+    /// The file is checked against the mapping on every access and mapped
+    /// again once it has changed, so a file replaced or rewritten by another
+    /// process is never served from a stale mapping. Without `touch` the
+    /// mapping is not marked as used, and a new one is not kept.
+    fn read_mapped(
+        &self,
+        key: &str,
+        file_info: &FileInfo,
+        checksum: u32,
+        touch: bool,
+    ) -> std::io::Result<FileBytes> {
+        let identity = FileIdentity::of(&std::fs::metadata(&file_info.path)?);
+        if let Some(mapped) = self.warm_cache.get(key) {
+            if mapped.path == file_info.path
+                && mapped.checksum == checksum
+                && mapped.identity == identity
+            {
+                if touch {
+                    mapped
+                        .last_accessed
+                        .store(self.warm_tick(), Ordering::Relaxed);
+                }
+                return Ok(FileBytes {
+                    data: mapped.data.clone(),
+                    warm: true,
+                });
+            }
+        }
+        self.warm_cache.remove(key);
+
+        let file = File::open(&file_info.path)?;
+        let metadata = file.metadata()?;
+        // A file of another length is not the one the row describes; it is
+        // read once for decoding to report, not kept mapped
+        if metadata.len() != file_info.size {
+            return std::fs::read(&file_info.path).map(FileBytes::from);
+        }
+        // SAFETY: the mapping is only read while the file keeps the identity
+        // it was mapped with. This process replaces data files by rename or
+        // drops the mapping before rewriting them; another process rewriting
+        // a file in place is caught on its next access.
+        let mmap = unsafe { Mmap::map(&file)? };
+        let data = Bytes::from_owner(mmap);
+        if !touch {
+            return Ok(FileBytes { data, warm: false });
+        }
+        self.warm_cache.insert(
+            key.to_string(),
+            MmapEntry {
+                data: data.clone(),
+                path: file_info.path.clone(),
+                checksum,
+                identity: FileIdentity::of(&metadata),
+                last_accessed: AtomicU64::new(self.warm_tick()),
+            },
+        );
+        self.cleanup_warm_cache();
+        Ok(FileBytes { data, warm: false })
+    }
+
+    /// Decode the outcome of reading `key`'s data file into an entry
+    fn finish_file_read(
+        &self,
+        key: &str,
+        read: std::io::Result<FileBytes>,
+        file_info: FileInfo,
+        mac: Option<&[u8]>,
+        touch: bool,
+    ) -> CacheResult<Option<CacheEntry>> {
+        let read = match read {
+            // A relaxed write may still be queued
+            Err(err)
+                if err.kind() == std::io::ErrorKind::NotFound
+                    && self.config.durability == Durability::Relaxed =>
+            {
+                self.write_batcher.sync();
+                std::fs::read(&file_info.path).map(FileBytes::from)
+            }
+            read => read,
+        };
+        match read {
+            Ok(file) => {
+                if file.warm {
+                    self.stats.record_warm_hit();
+                } else {
+                    self.stats.record_cold_hit();
+                }
+                let decoded = self.decode_data_file(key, &file.data, &file_info, mac);
+                let _key_lock = match &decoded {
+                    Err(CacheError::Corrupted(_) | CacheError::Tampered(_)) => {
+                        let key_lock = self.key_locks.lock(key)?;
+                        if self.row_moved_on(key, &file_info)? {
+                            return self.get_local(key, touch);
+                        }
+                        Some(key_lock)
+                    }
+                    _ => None,
+                };
+                let data = match decoded {
+                    Err(CacheError::Corrupted(key)) => {
+                        if let Some(entry) = self.restore_from_wal(&key)? {
+                            return Ok(Some(entry));
+                        }
+                        if self.config.smb_mode {
+                            // Without key locks, another process may have
+                            // renamed its file into place and not yet
+                            // committed the row; removing either would lose it
+                            self.stats.record_miss();
+                            return Ok(None);
+                        }
+                        self.discard_corrupted(&key)?;
+                        return Err(CacheError::Corrupted(key));
+                    }
+                    Err(CacheError::Tampered(key)) => {
+                        self.discard_tampered(&key)?;
+                        return Err(CacheError::Tampered(key));
+                    }
+                    Err(CacheError::Collided(key)) => {
+                        self.discard_collided(&key)?;
+                        return Err(CacheError::Collided(key));
+                    }
+                    decoded => decoded?,
+                };
+                self.stats.record_read(data.len() as u64);
+                Ok(Some(file_info.meta.entry(key, data.to_vec())))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                // Removed by a writer about to put the key's next file in place
+                let _key_lock = self.key_locks.lock(key)?;
+                if self.row_moved_on(key, &file_info)? {
+                    return self.get_local(key, touch);
+                }
+                if self.config.smb_mode {
+                    // Without key locks the writer may not have committed
+                    // its row for the next file yet; leave the row to it
+                    self.hot_cache.remove(key);
+                    self.warm_cache.remove(key);
+                    self.cold_index.write().remove(key);
+                } else {
+                    self.discard_missing(key)?;
+                }
+                self.stats.record_miss();
+                Ok(None)
+            }
+            Err(err) => Err(CacheError::Io(err)),
+        }
+    }
+
+    /// Read a value out of its segment, keeping it in the hot tier if `touch`
+    fn read_packed_entry(
+        &self,
+        key: &str,
+        packed: PackedEntry,
+        touch: bool,
+    ) -> CacheResult<Option<CacheEntry>> {
+        match self.segments.read(packed.location) {
+            Ok(raw_data) => {
+                self.stats.record_cold_hit();
+                let mac = packed.mac.as_deref();
+                if let Err(e) = self.verify_row(key, packed.compression, &raw_data, mac) {
+                    self.discard_tampered(key)?;
+                    return Err(e);
+                }
+                if let Err(e) = Self::verify_checksum(key, &raw_data, packed.checksum) {
+                    if let Some(entry) = self.restore_from_wal(key)? {
+                        return Ok(Some(entry));
+                    }
+                    self.discard_corrupted(key)?;
+                    return Err(e);
+                }
+                let data = self.decompress_if_needed(&raw_data, packed.compression)?;
+                self.stats.record_read(data.len() as u64);
+                let hot = HotEntry::new(data, packed.generation, packed.meta);
+                let entry = hot.to_entry(key);
+                if touch {
+                    self.insert_hot(key.to_string(), hot);
+                }
+                Ok(Some(entry))
+            }
+            // Compaction in another process moved the value after we read its row
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    std::io::ErrorKind::NotFound | std::io::ErrorKind::UnexpectedEof
+                ) =>
+            {
+                self.stats.record_miss();
+                Ok(None)
+            }
+            Err(err) => Err(CacheError::Io(err)),
+        }
+    }
+
+    fn get_current_timestamp() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+
+    /// Nanosecond timestamp, bumped past the last one handed out so that
+    /// back-to-back writes in this process never share a generation
+    fn new_generation() -> i64 {
+        static LAST_GENERATION: AtomicI64 = AtomicI64::new(0);
+
+        let duration = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let now = duration
+            .as_secs()
+            .saturating_mul(1_000_000_000)
+            .saturating_add(u64::from(duration.subsec_nanos())) as i64;
+        let previous = LAST_GENERATION
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+                Some(now.max(last + 1))
+            })
+            .unwrap_or_default();
+        now.max(previous + 1)
+    }
+
+    fn build_file_path(&self, key: &str) -> PathBuf {
+        self.data_path(&self.generate_filename(key))
+    }
+
+    /// Data file path for `key`, with its fan-out directories created
+    fn prepare_file_path(&self, key: &str) -> CacheResult<PathBuf> {
+        let file_path = self.build_file_path(key);
+        if let Some(parent) = file_path.parent() {
+            if !parent.exists() {
+                std::fs::create_dir_all(parent).map_err(CacheError::Io)?;
+                // New fan-out directories need their own names made durable
+                let data_dir = self.directory.join("data");
+                for created in parent.ancestors().skip(1) {
+                    self.sync_directory(created)?;
+                    if created == data_dir {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(file_path)
+    }
+
+    /// Write a data file as far as the configured [`Durability`] asks, or
+    /// hand it to the write batcher
+    fn write_data(&self, path: &Path, data: Bytes, direct: bool) -> CacheResult<Written> {
+        let durability = self.config.durability;
+        let synced = self.config.use_file_locking || durability >= Durability::Fsync;
+        if self.config.use_file_locking && !self.config.atomic_writes {
+            self.write_with_lock(path, &data)?;
+        } else if self
+            .config
+            .direct_io_threshold
+            .is_some_and(|threshold| data.len() >= threshold)
+        {
+            self.write_direct(path, &data, synced)?;
+            self.stats.record_file_created(data.len() as u64);
+            if synced {
+                self.stats.record_fsync();
+            }
+        } else if synced && self.config.group_commit.is_some() {
+            return Ok(self.write_batcher.commit(path.to_path_buf(), data));
+        } else if synced {
+            // A rename needs no lock to keep concurrent writers from
+            // interleaving, but file locking still implies a synced write
+            self.write_file(path, &data, true)?;
+            self.stats.record_file_created(data.len() as u64);
+            self.stats.record_fsync();
+        } else if direct || self.config.sync_writes {
+            self.write_file(path, &data, false)?;
+            self.stats.record_file_created(data.len() as u64);
+        } else {
+            // A full queue may hand the write back to be made here
+            let Some(data) = self.write_batcher.write_async(path.to_path_buf(), data)? else {
+                return Ok(Written::Queued);
+            };
+            self.write_file(path, &data, false)?;
+            self.stats.record_file_created(data.len() as u64);
+        }
+        if let Some(parent) = path.parent() {
+            self.sync_directory(parent)?;
+        }
+        Ok(Written::Done)
+    }
+
+    /// Under [`Durability::FsyncDir`], fsync `directory` so the names of files
+    /// just created in it survive power loss
+    fn sync_directory(&self, directory: &Path) -> CacheResult<()> {
+        if self.config.durability != Durability::FsyncDir {
+            return Ok(());
+        }
+        // Windows cannot open directories as files; NTFS journals their entries
+        #[cfg(unix)]
+        {
+            File::open(directory)?.sync_all()?;
+            self.stats.record_fsync();
+        }
+        #[cfg(not(unix))]
+        let _ = directory;
+        Ok(())
+    }
+
+    /// Whether `path` is a data file the cache wrote, as opposed to one it
+    /// was pointed at by [`StorageBackend::register_files`]
+    fn owns_file(&self, path: &Path) -> bool {
+        path.starts_with(self.directory.join("data"))
+    }
+
+    fn remove_existing_persisted_entry(&self, key: &str) -> CacheResult<bool> {
+        let mut removed_file = false;
+        // The key's next data file goes to the same path
+        self.open_files.forget(&self.build_file_path(key));
+        if let Some((_, file_info)) = self.cold_index.write().remove(key) {
+            self.open_files.forget(&file_info.path);
+            if self.owns_file(&file_info.path) {
+                self.write_batcher.sync();
+                let removed = self
+                    .config
+                    .sharing_retry
+                    .run(|| std::fs::remove_file(&file_info.path));
+                match removed {
+                    Ok(_) => {
+                        removed_file = true;
+                        self.stats.record_file_deleted();
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(err) => return Err(CacheError::Io(err)),
+                }
+            }
+        }
+
+        let conn = self.index_db.lock();
+        let removed = conn
+            .query_row(
+                "DELETE FROM cache_index WHERE key = ?1 RETURNING value",
+                params![key],
+                |row| row.get::<_, Vec<u8>>(0),
+            )
+            .optional()
+            .map_err(|e| Self::sqlite_error("Failed to remove SQLite index entry", e))?;
+        drop(conn);
+        if let Some(value_bytes) = removed {
+            self.release_row(&value_bytes);
+        }
+        Ok(removed_file)
+    }
+
+    /// Account for a removed index row: its bytes leave their tier, and
+    /// packed bytes count as dead
+    fn release_row(&self, value_bytes: &[u8]) {
+        self.uncount_row(value_bytes);
+        if let Ok((file_info, _)) = Self::decode_file_info(value_bytes) {
+            if PackedRef::is_packed(&file_info.path) {
+                self.segments.mark_dead(file_info.size);
+            }
+        }
+    }
+
+    fn persist_file_infos(&self, file_infos: &[FileRow]) -> CacheResult<()> {
+        if file_infos.is_empty() {
+            return Ok(());
+        }
+
+        let mut conn = self.index_db.lock();
+        let tx = Self::write_transaction(&mut conn)?;
+        let (mut replaced, mut written) = (Vec::new(), Vec::new());
+        {
+            let mut stmt = tx
+                // Upsert rather than REPLACE so re-persisting an entry keeps its
+                // sequence number, and its MAC when given none
+                .prepare(&format!(
+                    "INSERT INTO cache_index (key, value, generation, seq, mac) VALUES (?1, ?2, ?3, {}, ?4) \
+                     ON CONFLICT(key) DO UPDATE SET value = excluded.value, generation = excluded.generation, \
+                     mac = COALESCE(excluded.mac, mac)",
+                    NEXT_SEQ_SQL
+                ))
+                .map_err(|e| Self::sqlite_error("Failed to prepare SQLite file info", e))?;
+            for (key, file_info, mac) in file_infos {
+                let portable = FileInfo {
+                    path: self.portable_path(&file_info.path),
+                    ..file_info.clone()
+                };
+                let value_bytes = Self::encode_file_info(&portable)?;
+                replaced.extend(Self::current_row(&tx, key)?);
+                stmt.execute(params![
+                    key.as_str(),
+                    value_bytes,
+                    Self::new_generation(),
+                    mac.as_ref().map(|mac| mac.as_slice())
+                ])
+                .map_err(|e| Self::sqlite_error("Failed to persist SQLite file info", e))?;
+                self.stats
+                    .record_disk_write((key.len() + value_bytes.len()) as u64);
+                written.push(value_bytes);
+            }
+        }
+
+        tx.commit()
+            .map_err(|e| Self::sqlite_error("Failed to commit SQLite transaction", e))?;
+        self.recount_rows(&replaced, &written);
+        Ok(())
+    }
+
+    /// Compress data with the configured codec if it provides significant
+    /// space savings, returning the codec the bytes ended up stored with
+    fn compress_if_beneficial(&self, data: &[u8]) -> (Bytes, Compression) {
+        if let Some(compressed) = self.compress_with_dictionary(data) {
+            return (compressed, Compression::ZstdDictionary);
+        }
+        self.compress_with(data, self.config.compression)
+    }
+
+    /// Compress the value of a data file, in seekable frames when that is on
+    fn compress_data_file(&self, data: &[u8]) -> (Bytes, Compression) {
+        match self.config.compression.framed() {
+            Some(framed) if self.config.seekable_compression => self.compress_with(data, framed),
+            _ => self.compress_if_beneficial(data),
+        }
+    }
+
+    /// Compress data with `codec` if it provides significant space savings
+    fn compress_with(&self, data: &[u8], codec: Compression) -> (Bytes, Compression) {
+        if !self.config.use_compression
+            || !codec.is_compressed()
+            || data.len() < self.config.compression_threshold
+        {
+            return (Bytes::copy_from_slice(data), Compression::None);
+        }
+
+        match codec.compress(data, self.config.compression_level) {
+            Ok(compressed) if compressed.len() < data.len() * 9 / 10 => {
+                (Bytes::from(compressed), codec)
+            }
+            _ => (Bytes::copy_from_slice(data), Compression::None),
+        }
+    }
+
+    /// Compress a value below the dictionary threshold with the trained
+    /// dictionary, if the mode is on, one has been trained and it saves space
+    ///
+    /// Values seen before then are sampled to train the dictionary on.
+    fn compress_with_dictionary(&self, data: &[u8]) -> Option<Bytes> {
+        if !self.config.zstd_dictionary || data.len() >= self.config.dictionary_threshold {
+            return None;
+        }
+        if let Err(e) = self.dictionaries.sample(data) {
+            tracing::warn!("Failed to store a trained zstd dictionary: {}", e);
+        }
+        match self.dictionaries.compress(data) {
+            Ok(Some(compressed)) if compressed.len() < data.len() => Some(Bytes::from(compressed)),
+            _ => None,
+        }
+    }
+
+    /// Decompress data stored with `compression`
+    fn decompress_if_needed(&self, data: &[u8], compression: Compression) -> CacheResult<Bytes> {
+        match compression {
+            Compression::None => Ok(Bytes::copy_from_slice(data)),
+            Compression::ZstdDictionary => self.dictionaries.decompress(data).map(Bytes::from),
+            codec => codec.decompress(data).map(Bytes::from),
+        }
+    }
+
+    /// Contents of `key`'s data file at `path`: its header, then the value,
+    /// compressed if that pays; and the FileInfo indexing it
+    fn encode_data_file(
+        &self,
+        key: &str,
+        data: &[u8],
+        path: PathBuf,
+        meta: EntryMeta,
+    ) -> (Bytes, FileInfo) {
+        let (payload, compression) = self.compress_data_file(data);
+        let file = DataFileHeader::new(key, compression).frame(&payload);
+        let file_info = FileInfo {
+            path,
+            size: file.len() as u64,
+            created_at: Self::get_current_timestamp(),
+            compression,
+            checksum: Some(payload_checksum(&payload)),
+            meta,
+        };
+        (Bytes::from(file), file_info)
+    }
+
+    /// Value held in `key`'s data file contents, checked against the row's
+    /// MAC when the index is signed and against its checksum
+    ///
+    /// Files written before data file headers existed are all payload, with
+    /// only the index to say how it is compressed.
+    fn decode_data_file(
+        &self,
+        key: &str,
+        file: &[u8],
+        file_info: &FileInfo,
+        mac: Option<&[u8]>,
+    ) -> CacheResult<Bytes> {
+        self.verify_row(key, file_info.compression, file, mac)?;
+        // A header cut short by an interrupted write fails like the payload
+        let header = DataFileHeader::decode(file).map_err(|e| match e {
+            CacheError::Corruption(_) => CacheError::Corrupted(key.to_string()),
+            e => e,
+        })?;
+        let (payload, compression, recorded) = match header {
+            Some((header, _)) if header.key != key => {
+                return Err(CacheError::Collided(key.to_string()));
+            }
+            Some((header, offset)) => (&file[offset..], header.compression, header.checksum),
+            None => (file, file_info.compression, None),
+        };
+        // Files the cache wrote itself are indexed with their length
+        if file_info.checksum.is_some() && file.len() as u64 != file_info.size {
+            tracing::warn!(
+                "Data file of {:?} holds {} bytes where {} were written",
+                key,
+                file.len(),
+                file_info.size
+            );
+            return Err(CacheError::Corrupted(key.to_string()));
+        }
+        Self::verify_checksum(key, payload, file_info.checksum.or(recorded))?;
+        self.decompress_if_needed(payload, compression)
+    }
+
+    /// Fail with [`CacheError::Corrupted`] unless `payload` matches `checksum`
+    fn verify_checksum(key: &str, payload: &[u8], checksum: Option<u32>) -> CacheResult<()> {
+        match checksum {
+            Some(checksum) if payload_checksum(payload) != checksum => {
+                Err(CacheError::Corrupted(key.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// MAC of `key`'s stored bytes, if the index is signed
+    ///
+    /// A data file is signed whole, header included, and a packed value as its
+    /// segment bytes; the path is left out so moving the bytes keeps the MAC.
+    fn sign_row(&self, key: &str, compression: Compression, stored: &[u8]) -> Option<[u8; 32]> {
+        self.config
+            .index_key
+            .as_ref()
+            .map(|index_key| index_key.sign(key, compression, stored))
+    }
+
+    /// MAC of the file at `path` served whole as `key`, if the index is signed
+    fn sign_file(&self, key: &str, path: &Path) -> CacheResult<Option<[u8; 32]>> {
+        let Some(index_key) = &self.config.index_key else {
+            return Ok(None);
+        };
+        let mut hasher = index_key.hasher(key, Compression::None);
+        hasher.update_reader(File::open(path)?)?;
+        Ok(Some(*hasher.finalize().as_bytes()))
+    }
+
+    /// Fail with [`CacheError::Tampered`] if the index is signed and `mac`
+    /// does not sign `key`'s stored bytes
+    fn verify_row(
+        &self,
+        key: &str,
+        compression: Compression,
+        stored: &[u8],
+        mac: Option<&[u8]>,
+    ) -> CacheResult<()> {
+        match &self.config.index_key {
+            Some(index_key) if !index_key.verify(key, compression, stored, mac) => {
+                Err(CacheError::Tampered(key.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether reads may drop the entries they find expired or unreadable,
+    /// rather than only missing them
+    fn may_clean_up(&self) -> bool {
+        self.cleanup.read().as_ref().is_none_or(|gate| gate())
+    }
+
+    /// Drop the row of an entry that failed its signature check
+    ///
+    /// Unlike [`Self::discard_corrupted`] no file is removed: the row cannot be
+    /// trusted to say which file is the entry's.
+    fn discard_tampered(&self, key: &str) -> CacheResult<()> {
+        self.hot_cache.remove(key);
+        self.warm_cache.remove(key);
+        self.cold_index.write().remove(key);
+        if !self.may_clean_up() {
+            return Ok(());
+        }
+        tracing::warn!(
+            "Dropping entry {:?}: index row failed its signature check",
+            key
+        );
+        let removed = self
+            .index_db
+            .lock()
+            .query_row(
+                "DELETE FROM cache_index WHERE key = ?1 RETURNING value",
+                params![key],
+                |row| row.get::<_, Vec<u8>>(0),
+            )
+            .optional()
+            .map_err(|e| Self::sqlite_error("Failed to remove SQLite index entry", e))?;
+        if let Some(value_bytes) = removed {
+            self.release_row(&value_bytes);
+        }
+        Ok(())
+    }
+
+    /// Whether the data file `file_info` indexes still has the length it was
+    /// written with, or is one whose length was never recorded
+    ///
+    /// A missing file counts as intact, for the read that follows to report.
+    fn has_indexed_length(file_info: &FileInfo) -> CacheResult<bool> {
+        if file_info.checksum.is_none() {
+            return Ok(true);
+        }
+        match std::fs::metadata(&file_info.path) {
+            Ok(metadata) => Ok(metadata.len() == file_info.size),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(true),
+            Err(e) => Err(CacheError::Io(e)),
+        }
+    }
+
+    /// Write `key`'s value again from the write-ahead log, when its stored
+    /// copy failed its checksum and the log still holds the write
+    ///
+    /// The entry keeps the tags and expiry of its index row.
+    fn restore_from_wal(&self, key: &str) -> CacheResult<Option<CacheEntry>> {
+        let Some(wal) = self.wal.as_ref().filter(|_| self.may_clean_up()) else {
+            return Ok(None);
+        };
+        let Some(data) = wal.lock().last_put(key)? else {
+            return Ok(None);
+        };
+        tracing::warn!(
+            "Restoring entry {:?} from the write-ahead log: stored value failed its checksum",
+            key
+        );
+        let meta = match self.read_index_entry(key)? {
+            Some(entry) => entry.meta().clone(),
+            None => EntryMeta::created(Self::get_current_timestamp()),
+        };
+        self.set_entry_data(key, &data, meta.clone())?;
+        Ok(Some(meta.entry(key, data)))
+    }
+
+    /// Forget an entry whose stored bytes failed their checksum, removing its data file
+    fn discard_corrupted(&self, key: &str) -> CacheResult<()> {
+        self.hot_cache.remove(key);
+        self.warm_cache.remove(key);
+        if !self.may_clean_up() {
+            return Ok(());
+        }
+        tracing::warn!("Dropping entry {:?}: stored value failed its checksum", key);
+        self.remove_existing_persisted_entry(key)?;
+        Ok(())
+    }
+
+    /// Drop `key`'s entry once its expire_time has passed, unless it was
+    /// rewritten since `generation` was read; returns whether it was dropped
+    ///
+    /// An older copy in the remote tier goes too, so it is not served in
+    /// the expired entry's place.
+    fn discard_expired(&self, key: &str, generation: i64) -> CacheResult<bool> {
+        self.hot_cache
+            .remove_if(key, |_, entry| entry.generation == generation);
+        self.warm_cache.remove(key);
+        if !self.may_clean_up() {
+            return Ok(false);
+        }
+        let removed = self
+            .index_db
+            .lock()
+            .query_row(
+                "DELETE FROM cache_index WHERE key = ?1 AND generation = ?2 RETURNING value",
+                params![key, generation],
+                |row| row.get::<_, Vec<u8>>(0),
+            )
+            .optional()
+            .map_err(|e| Self::sqlite_error("Failed to remove SQLite index entry", e))?;
+        let Some(value_bytes) = removed else {
+            return Ok(false);
+        };
+        self.cold_index.write().remove(key);
+        self.remove_row_file(&value_bytes);
+        self.release_row(&value_bytes);
+        if let Some(remote) = &self.config.remote_tier {
+            let name = self.remote_name(key);
+            self.remote_uploads.cancel(&name);
+            remote.delete(&name)?;
+        }
+        self.publish(&[Event::Expire(key)]);
+        if let Some(listener) = self.expirations.read().as_ref() {
+            listener(key);
+        }
+        Ok(true)
+    }
+
+    /// Drop `key`'s entry, whose data file now holds another key's value
+    ///
+    /// Only the row goes: the file is the other key's.
+    fn discard_collided(&self, key: &str) -> CacheResult<()> {
+        self.hot_cache.remove(key);
+        self.warm_cache.remove(key);
+        self.cold_index.write().remove(key);
+        if !self.may_clean_up() {
+            return Ok(());
+        }
+        tracing::warn!(
+            "Dropping entry {:?}: its data file was overwritten by a key with the same file name",
+            key
+        );
+        let removed = self
+            .index_db
+            .lock()
+            .query_row(
+                "DELETE FROM cache_index WHERE key = ?1 RETURNING value",
+                params![key],
+                |row| row.get::<_, Vec<u8>>(0),
+            )
+            .optional()
+            .map_err(|e| Self::sqlite_error("Failed to remove SQLite index entry", e))?;
+        if let Some(value_bytes) = removed {
+            self.release_row(&value_bytes);
+        }
+        Ok(())
+    }
+
+    /// Forget an entry whose data file is gone, so its row is not found again
+    fn discard_missing(&self, key: &str) -> CacheResult<()> {
+        self.hot_cache.remove(key);
+        self.warm_cache.remove(key);
+        self.cold_index.write().remove(key);
+        if !self.may_clean_up() {
+            return Ok(());
+        }
+        tracing::warn!("Dropping entry {:?}: its data file is missing", key);
+        let removed = self
+            .index_db
+            .lock()
+            .query_row(
+                "DELETE FROM cache_index WHERE key = ?1 RETURNING value",
+                params![key],
+                |row| row.get::<_, Vec<u8>>(0),
+            )
+            .optional()
+            .map_err(|e| Self::sqlite_error("Failed to remove SQLite index entry", e))?;
+        if let Some(value_bytes) = removed {
+            self.release_row(&value_bytes);
+        }
+        Ok(())
+    }
+
+    /// Read a batch of data files on up to `MAX_PARALLEL_READS` threads
+    ///
+    /// Results come back in the same order as `reads`.
+    fn read_files_parallel<K, R, T, F>(
+        &self,
+        reads: Vec<(K, String, R)>,
+        read: F,
+    ) -> Vec<(K, String, T)>
+    where
+        K: Send,
+        R: Send,
+        T: Send,
+        F: Fn(&str, R) -> T + Sync,
+    {
+        if reads.len() <= 1 {
+            return reads
+                .into_iter()
+                .map(|(tag, key, file_info)| {
+                    let value = read(&key, file_info);
+                    (tag, key, value)
+                })
+                .collect();
+        }
+
+        let chunk_size = reads.len().div_ceil(MAX_PARALLEL_READS);
+        let mut chunks = Vec::new();
+        let mut reads = reads.into_iter().peekable();
+        while reads.peek().is_some() {
+            chunks.push(reads.by_ref().take(chunk_size).collect::<Vec<_>>());
+        }
+
+        let read = &read;
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = chunks
+                .into_iter()
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .into_iter()
+                            .map(|(tag, key, file_info)| {
+                                let value = read(&key, file_info);
+                                (tag, key, value)
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("cache read thread panicked"))
+                .collect()
+        })
+    }
+
+    /// Put `entry` in the hot tier as its most recently used entry
+    fn insert_hot(&self, key: String, entry: HotEntry) {
+        let used = match self.config.hot_cache_policy {
+            HotCachePolicy::Lru => self.hot_clock.fetch_add(1, Ordering::Relaxed) + 1,
+            HotCachePolicy::Clock => 1,
+        };
+        entry.last_used.store(used, Ordering::Relaxed);
+        self.hot_cache.insert(key, entry);
+    }
+
+    /// Note a use of the hot `entry` and copy it out
+    fn touch_hot(&self, entry: &HotEntry) -> HotEntry {
+        match self.config.hot_cache_policy {
+            HotCachePolicy::Lru => {
+                let tick = self.hot_clock.fetch_add(1, Ordering::Relaxed) + 1;
+                entry.last_used.store(tick, Ordering::Relaxed);
+            }
+            // Only the first use since a sweep writes to the entry
+            HotCachePolicy::Clock => {
+                if entry.last_used.load(Ordering::Relaxed) == 0 {
+                    entry.last_used.store(1, Ordering::Relaxed);
+                }
+            }
+        }
+        entry.clone()
+    }
+
+    /// Once the hot tier outgrows `hot_cache_size`, drop what is over and a
+    /// tenth more, least recently used first; pinned entries stay
+    fn cleanup_hot_cache(&self) {
+        let len = self.hot_cache.len();
+        if len <= self.config.hot_cache_size {
+            return;
+        }
+        let excess = len - self.config.hot_cache_size + self.config.hot_cache_size / 10;
+        let pinned = self.pinned.read();
+        let victims = match self.config.hot_cache_policy {
+            HotCachePolicy::Lru => {
+                let mut used: Vec<(u64, String)> = self
+                    .hot_cache
+                    .iter()
+                    .filter(|entry| !pinned.contains(entry.key()))
+                    .map(|entry| (entry.last_used.load(Ordering::Relaxed), entry.key().clone()))
+                    .collect();
+                if excess < used.len() {
+                    used.select_nth_unstable_by_key(excess, |(tick, _)| *tick);
+                    used.truncate(excess);
+                }
+                used.into_iter().map(|(_, key)| key).collect()
+            }
+            HotCachePolicy::Clock => self.sweep_hot_cache(excess, &pinned),
+        };
+        for key in victims {
+            self.hot_cache.remove(&key);
+        }
+    }
+
+    /// Up to `count` hot entries not used since the sweep last passed them
+    ///
+    /// The sweep starts where the last one stopped and clears the mark of
+    /// every used entry it passes, so those go on its next pass unless they
+    /// are used again; a second lap takes them if there are not enough
+    /// unused entries.
+    fn sweep_hot_cache(&self, count: usize, pinned: &HashSet<String>) -> Vec<String> {
+        let keys: Vec<String> = self
+            .hot_cache
+            .iter()
+            .filter(|entry| !pinned.contains(entry.key()))
+            .map(|entry| entry.key().clone())
+            .collect();
+        if keys.is_empty() {
+            return Vec::new();
+        }
+        let start = self.hot_clock.load(Ordering::Relaxed) as usize % keys.len();
+        let mut victims = HashSet::new();
+        let mut passed = 0;
+        while victims.len() < count && passed < 2 * keys.len() {
+            let key = &keys[(start + passed) % keys.len()];
+            passed += 1;
+            let unused = self
+                .hot_cache
+                .get(key)
+                .is_some_and(|entry| entry.last_used.swap(0, Ordering::Relaxed) == 0);
+            if unused {
+                victims.insert(key.clone());
+            }
+        }
+        self.hot_clock
+            .store(((start + passed) % keys.len()) as u64, Ordering::Relaxed);
+        victims.into_iter().collect()
+    }
+
+    /// Next tick of the warm tier's clock
+    fn warm_tick(&self) -> u64 {
+        self.warm_clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Once the warm tier holds more than `warm_cache_size` files or
+    /// `warm_cache_bytes` bytes, unmap the least recently used until it is
+    /// back under a tenth below whichever it was over; pinned files stay
+    fn cleanup_warm_cache(&self) {
+        let len = self.warm_cache.len();
+        let bytes = self.warm_cache.bytes();
+        let (max_len, max_bytes) = (self.config.warm_cache_size, self.config.warm_cache_bytes);
+        if len <= max_len && bytes <= max_bytes {
+            return;
+        }
+        let target_len = if len > max_len {
+            max_len - max_len / 10
+        } else {
+            len
+        };
+        let target_bytes = if bytes > max_bytes {
+            max_bytes - max_bytes / 10
+        } else {
+            bytes
+        };
+
+        let pinned = self.pinned.read();
+        let mut used: Vec<(u64, u64, String)> = self
+            .warm_cache
+            .iter()
+            .filter(|mapped| !pinned.contains(mapped.key()))
+            .map(|mapped| {
+                (
+                    mapped.last_accessed.load(Ordering::Relaxed),
+                    mapped.data.len() as u64,
+                    mapped.key().clone(),
+                )
+            })
+            .collect();
+        used.sort_unstable_by_key(|(tick, _, _)| *tick);
+        let (mut len, mut bytes) = (len, bytes);
+        for (_, size, key) in used {
+            if len <= target_len && bytes <= target_bytes {
+                break;
+            }
+            if self.warm_cache.remove(&key).is_some() {
+                len -= 1;
+                bytes = bytes.saturating_sub(size);
+            }
+        }
+    }
+}
+
+impl OptimizedStorage {
+    /// Read `key` from the local tiers only; without `touch` the read is not
+    /// a use of the key, leaving the hot and warm tiers as they were
+    fn get_local(&self, key: &str, touch: bool) -> CacheResult<Option<CacheEntry>> {
+        self.catch_up_events();
+        // Copy the entry out: a shard guard held across the index lock deadlocks
+        // against writers, which take the index lock first
+        let hot = self.hot_cache.get(key).map(|entry| match touch {
+            true => self.touch_hot(&entry),
+            false => entry.clone(),
+        });
+        if let Some(entry) = hot {
+            match self.read_index_generation(key)? {
+                Some(generation) if generation == entry.generation => {
+                    if entry.meta.is_expired(Self::get_current_timestamp()) {
+                        self.discard_expired(key, generation)?;
+                        self.stats.record_miss();
+                        return Ok(None);
+                    }
+                    self.stats.record_hot_hit();
+                    self.stats.record_read(entry.data.len() as u64);
+                    return Ok(Some(entry.to_entry(key)));
+                }
+                _ => {
+                    self.hot_cache.remove(key);
+                }
+            }
+        }
+
+        match self.read_index_entry(key)? {
+            Some(IndexEntry::Inline(entry)) => {
+                self.stats.record_hot_hit();
+                self.stats.record_read(entry.data.len() as u64);
+                let found = entry.to_entry(key);
+                if touch {
+                    self.insert_hot(key.to_string(), entry);
+                }
+                Ok(Some(found))
+            }
+            Some(IndexEntry::File(file_info, mac)) => {
+                self.cold_index
+                    .write()
+                    .insert(key.to_string(), file_info.clone());
+                self.read_file_entry(key, file_info, mac.as_deref(), touch)
+            }
+            Some(IndexEntry::Packed(packed)) => self.read_packed_entry(key, packed, touch),
+            None => {
+                self.hot_cache.remove(key);
+                self.warm_cache.remove(key);
+                self.cold_index.write().remove(key);
+                self.stats.record_miss();
+                Ok(None)
+            }
+        }
+    }
+
+    /// Remove `key` from the local tiers, leaving any remote copy alone
+    fn delete_local(&self, key: &str) -> CacheResult<bool> {
+        let _key_lock = self.key_locks.lock(key)?;
+        let _wal = self.log_writes(&[WalRecord::Delete {
+            key: key.to_string(),
+        }])?;
+
+        // The memory tiers may be stale when another handle wrote the key,
+        // so only the row decides whether it existed and where its data is
+        self.hot_cache.remove(key);
+        self.warm_cache.remove(key);
+        self.cold_index.write().remove(key);
+
+        let mut conn = self.index_db.lock();
+        let tx = Self::write_transaction(&mut conn)?;
+        let deleted = tx
+            .query_row(
+                "DELETE FROM cache_index WHERE key = ?1 RETURNING value",
+                params![key],
+                |row| row.get::<_, Vec<u8>>(0),
+            )
+            .optional()
+            .map_err(|e| Self::sqlite_error("Failed to delete SQLite index entry", e))?;
+        tx.execute("DELETE FROM cache_alias WHERE key = ?1", params![key])
+            .map_err(|e| Self::sqlite_error("Failed to delete SQLite aliases", e))?;
+        tx.commit()
+            .map_err(|e| Self::sqlite_error("Failed to commit SQLite transaction", e))?;
+        drop(conn);
+
+        let Some(value_bytes) = deleted else {
+            return Ok(false);
+        };
+        self.remove_row_file(&value_bytes);
+        self.release_row(&value_bytes);
+        self.publish(&[Event::Delete(key)]);
+        self.maybe_compact();
+        Ok(true)
+    }
+
+    /// Remove the data file a deleted index row pointed at
+    ///
+    /// Registered files stay where they are; only the entry goes. Removed
+    /// before returning: a later write of the key, by this handle or another,
+    /// reuses the path and must not lose its file.
+    fn remove_row_file(&self, value_bytes: &[u8]) {
+        let Ok((file_info, _)) = Self::decode_file_info(value_bytes) else {
+            return;
+        };
+        if PackedRef::is_packed(&file_info.path) {
+            return;
+        }
+        let path = self.resolve_data_path(&file_info.path);
+        self.open_files.forget(&path);
+        if self.owns_file(&path) {
+            self.write_batcher.sync();
+            match std::fs::remove_file(&path) {
+                Ok(()) => self.stats.record_file_deleted(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => tracing::warn!("Failed to remove data file {:?}: {}", path, e),
+            }
+        }
+    }
+
+    /// Name of `key`'s object in the remote tier; always the BLAKE3 name, so
+    /// processes naming their data files differently share the objects
+    fn remote_name(&self, key: &str) -> String {
+        FileNaming::Blake3.file_name(key)
+    }
+
+    /// `key`'s value as the remote tier keeps it: a framed data file,
+    /// preceded by its MAC when the index is signed
+    fn remote_object(&self, key: &str, data: &[u8]) -> Vec<u8> {
+        let (payload, compression) = self.compress_if_beneficial(data);
+        let file = DataFileHeader::new(key, compression).frame(&payload);
+        let mut object = Vec::with_capacity(32 + file.len());
+        if let Some(mac) = self.sign_row(key, compression, &file) {
+            object.extend_from_slice(&mac);
+        }
+        object.extend_from_slice(&file);
+        object
+    }
+
+    /// Read `key` from the remote tier, if there is one
+    ///
+    /// The value is served from the object and not copied back into the
+    /// local tiers; writing it again brings it back under local eviction.
+    fn get_remote(&self, key: &str) -> CacheResult<Option<CacheEntry>> {
+        let Some(remote) = &self.config.remote_tier else {
+            return Ok(None);
+        };
+        let name = self.remote_name(key);
+        let object = match self.remote_uploads.get(&name) {
+            Some(object) => object,
+            None => match remote.get(&name)? {
+                Some(object) => Bytes::from(object),
+                None => return Ok(None),
+            },
+        };
+        match self.decode_remote_object(key, &object) {
+            Ok(data) => {
+                self.stats.record_cold_hit();
+                self.stats.record_read(data.len() as u64);
+                Ok(Some(CacheEntry::new_inline(
+                    key.to_string(),
+                    data.to_vec(),
+                    vec![],
+                    None,
+                )))
+            }
+            Err(e @ CacheError::Corrupted(_)) => {
+                self.remote_uploads.cancel(&name);
+                remote.delete(&name)?;
+                Err(e)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Fill in the entries of `keys` the local tiers missed from the remote tier
+    fn get_remote_misses(
+        &self,
+        keys: &[String],
+        mut results: Vec<Option<CacheEntry>>,
+    ) -> CacheResult<Vec<Option<CacheEntry>>> {
+        if self.config.remote_tier.is_some() {
+            for (result, key) in results.iter_mut().zip(keys) {
+                if result.is_none() {
+                    *result = self.get_remote(key)?;
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    fn decode_remote_object(&self, key: &str, object: &[u8]) -> CacheResult<Bytes> {
+        let (mac, file) = match &self.config.index_key {
+            Some(_) if object.len() < 32 => return Err(CacheError::Tampered(key.to_string())),
+            Some(_) => (Some(&object[..32]), &object[32..]),
+            None => (None, object),
+        };
+        let Some((header, offset)) = DataFileHeader::decode(file)? else {
+            return Err(CacheError::Corrupted(key.to_string()));
+        };
+        if header.key != key {
+            return Err(CacheError::Collided(key.to_string()));
+        }
+        self.verify_row(key, header.compression, file, mac)?;
+        Self::verify_checksum(key, &file[offset..], header.checksum)?;
+        self.decompress_if_needed(&file[offset..], header.compression)
+    }
+}
+
+impl StorageBackend for OptimizedStorage {
+    fn get(&self, key: &str) -> CacheResult<Option<CacheEntry>> {
+        match self.get_local(key, true)? {
+            Some(entry) => Ok(Some(entry)),
+            None => self.get_remote(key),
+        }
+    }
+
+    fn peek(&self, key: &str) -> CacheResult<Option<CacheEntry>> {
+        match self.get_local(key, false)? {
+            Some(entry) => Ok(Some(entry)),
+            None => self.get_remote(key),
+        }
+    }
+
+    fn get_many(&self, keys: &[String]) -> CacheResult<Vec<Option<CacheEntry>>> {
+        self.catch_up_events();
+        let mut results = vec![None; keys.len()];
+        let mut cold_reads = Vec::new();
+        let now = Self::get_current_timestamp();
+
+        for (slot, key) in keys.iter().enumerate() {
+            if let Some(entry) = self.hot_cache.get(key).map(|entry| self.touch_hot(&entry)) {
+                if self.read_index_generation(key)? == Some(entry.generation) {
+                    if entry.meta.is_expired(now) {
+                        self.discard_expired(key, entry.generation)?;
+                        self.stats.record_miss();
+                        continue;
+                    }
+                    self.stats.record_hot_hit();
+                    self.stats.record_read(entry.data.len() as u64);
+                    results[slot] = Some(entry.to_entry(key));
+                    continue;
+                }
+                self.hot_cache.remove(key);
+            }
+
+            match self.read_index_entry(key)? {
+                Some(IndexEntry::Inline(entry)) => {
+                    self.stats.record_hot_hit();
+                    self.stats.record_read(entry.data.len() as u64);
+                    results[slot] = Some(entry.to_entry(key));
+                    self.insert_hot(key.clone(), entry);
+                }
+                Some(IndexEntry::File(file_info, mac)) => {
+                    self.cold_index
+                        .write()
+                        .insert(key.clone(), file_info.clone());
+                    cold_reads.push((slot, key.clone(), (file_info, mac)));
+                }
+                Some(IndexEntry::Packed(packed)) => {
+                    results[slot] = self.read_packed_entry(key, packed, true)?;
+                }
+                None => {
+                    self.hot_cache.remove(key);
+                    self.warm_cache.remove(key);
+                    self.cold_index.write().remove(key);
+                    self.stats.record_miss();
+                }
+            }
+        }
+
+        // One submission covers the whole batch where io_uring is available
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if cold_reads.len() > 1 {
+            let paths: Vec<&Path> = cold_reads
+                .iter()
+                .map(|(_, _, (file_info, _))| file_info.path.as_path())
+                .collect();
+            if let Some(reads) = uring::read_files(&paths) {
+                for ((slot, key, (file_info, mac)), read) in cold_reads.into_iter().zip(reads) {
+                    results[slot] = self.finish_file_read(
+                        &key,
+                        read.map(FileBytes::from),
+                        file_info,
+                        mac.as_deref(),
+                        true,
+                    )?;
+                }
+                return self.get_remote_misses(keys, results);
+            }
+        }
+
+        // Cold reads are independent files, so overlap their I/O
+        for (slot, _, entry) in self.read_files_parallel(cold_reads, |key, (file_info, mac)| {
+            self.read_file_entry(key, file_info, mac.as_deref(), true)
+        }) {
+            results[slot] = entry?;
+        }
+
+        self.get_remote_misses(keys, results)
+    }
+
+    /// Loads at most [`PREFETCH_LIMIT`] entries and [`PREFETCH_BYTES`]
+    /// stored bytes; files large enough for the warm tier to map are left to
+    /// it rather than copied into the hot tier
+    fn prefetch_prefix(&self, prefix: &str) -> CacheResult<usize> {
+        let end = format!("{}\u{10FFFF}", prefix);
+        let conn = self.index_db.lock();
+        let mut stmt = conn
+            .prepare(
+                "SELECT key, value, generation, mac FROM cache_index \
+                 WHERE key >= ?1 AND key < ?2 ORDER BY key LIMIT ?3",
+            )
+            .map_err(|e| Self::sqlite_error("Failed to query SQLite prefetch range", e))?;
+        let rows = stmt
+            .query_map(params![prefix, end, PREFETCH_LIMIT as i64], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Vec<u8>>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, Option<Vec<u8>>>(3)?,
+                ))
+            })
+            .map_err(|e| Self::sqlite_error("Failed to iterate SQLite prefetch range", e))?;
+        let mut rows_read = Vec::new();
+        for row in rows {
+            rows_read.push(row.map_err(|e| Self::sqlite_error("Failed to read SQLite row", e))?);
+        }
+        drop(stmt);
+        drop(conn);
+
+        let mut loaded = 0;
+        let mut cold_reads = Vec::new();
+        let now = Self::get_current_timestamp();
+        let mut budget = PREFETCH_BYTES;
+        let mapped =
+            |size: u64| self.config.mmap_threshold > 0 && size >= self.config.mmap_threshold as u64;
+        for (key, value_bytes, generation, mac) in rows_read {
+            let fresh = self
+                .hot_cache
+                .get(&key)
+                .is_some_and(|entry| entry.generation == generation);
+            if fresh {
+                continue;
+            }
+            match self.decode_index_entry(&key, &value_bytes, generation, mac) {
+                Ok(entry) if entry.meta().is_expired(now) => {
+                    self.discard_expired(&key, generation)?;
+                }
+                Ok(IndexEntry::Inline(entry)) => {
+                    budget = budget.saturating_sub(entry.data.len() as u64);
+                    self.insert_hot(key, entry);
+                    loaded += 1;
+                }
+                Ok(IndexEntry::File(file_info, _)) if mapped(file_info.size) => {}
+                Ok(IndexEntry::File(file_info, mac)) => {
+                    if file_info.size > budget {
+                        continue;
+                    }
+                    budget -= file_info.size;
+                    cold_reads.push((generation, key, (file_info, mac)))
+                }
+                Ok(IndexEntry::Packed(packed)) => {
+                    if packed.location.len > budget {
+                        continue;
+                    }
+                    budget -= packed.location.len;
+                    if self.read_packed_entry(&key, packed, true)?.is_some() {
+                        loaded += 1;
+                    }
+                }
+                Err(CacheError::Tampered(_)) => self.discard_tampered(&key)?,
+                Err(e) => return Err(e),
+            }
+        }
+
+        // File data is promoted into the hot tier under the generation it was read at,
+        // so a later write makes the prefetched copy stale rather than wrong
+        for (generation, key, data) in
+            self.read_files_parallel(cold_reads, |key, (file_info, mac)| {
+                let file = self
+                    .read_file(key, &file_info, true)
+                    .map_err(CacheError::Io)?;
+                let data = self.decode_data_file(key, &file.data, &file_info, mac.as_deref())?;
+                Ok((data, file_info.meta))
+            })
+        {
+            // A file that vanished or fails to decode is left for a regular get to report
+            match data {
+                Ok((data, meta)) => {
+                    self.insert_hot(key, HotEntry::new(data, generation, meta));
+                    loaded += 1;
+                }
+                Err(CacheError::Corrupted(_)) => {
+                    if self.restore_from_wal(&key)?.is_none() {
+                        self.discard_corrupted(&key)?;
+                    }
+                }
+                Err(CacheError::Tampered(_)) => self.discard_tampered(&key)?,
+                Err(CacheError::Collided(_)) => self.discard_collided(&key)?,
+                Err(_) => {}
+            }
+        }
+        self.cleanup_hot_cache();
+
+        Ok(loaded)
+    }
+
+    fn set(&self, key: &str, entry: CacheEntry) -> CacheResult<()> {
+        let meta = EntryMeta::of(&entry);
+        let data = match &entry.storage {
+            crate::serialization::StorageMode::Inline(data) => data,
+            crate::serialization::StorageMode::File(filename) => {
+                // Read file data
+                let file_path = self.data_path(filename);
+                return match std::fs::read(&file_path) {
+                    Ok(file_data) => self.set_entry_data(key, &file_data, meta),
+                    Err(e) => Err(CacheError::Io(e)),
+                };
+            }
+        };
+
+        self.set_entry_data(key, data, meta)
+    }
+
+    fn set_batch(&self, entries: Vec<(String, Vec<u8>)>) -> CacheResult<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        // Rows are written after the whole batch, so an earlier write of a
+        // key would land over a later one
+        let mut seen = HashSet::with_capacity(entries.len());
+        let mut entries: Vec<_> = entries
+            .into_iter()
+            .rev()
+            .filter(|(key, _)| seen.insert(key.clone()))
+            .collect();
+        entries.reverse();
+
+        let _key_lock = self
+            .key_locks
+            .lock_all(entries.iter().map(|(key, _)| key.as_str()))?;
+        let logging = self.wal.as_ref().is_some_and(|wal| wal.lock().is_owned());
+        let records: Vec<WalRecord> = if logging {
+            entries
+                .iter()
+                .map(|(key, data)| WalRecord::Put {
+                    key: key.clone(),
+                    data: data.clone(),
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let mut wal = self.log_writes(&records)?;
+
+        let mut file_infos = Vec::new();
+        let mut inline_entries = Vec::new();
+        let mut has_async_file_writes = false;
+        let mut commits = Vec::new();
+        let meta = EntryMeta::created(Self::get_current_timestamp());
+
+        for (key, data) in entries {
+            let data_size = data.len();
+            self.stats.record_write(data_size as u64);
+
+            self.hot_cache.remove(&key);
+            self.warm_cache.remove(&key);
+            self.remove_existing_persisted_entry(&key)?;
+
+            if data_size < self.config.disk_write_threshold {
+                inline_entries.push((key, Bytes::from(data), meta.clone()));
+                continue;
+            }
+            if data_size < self.config.pack_threshold {
+                let (file_info, mac) =
+                    self.pack_value(&key, &data, meta.clone(), wal.as_deref_mut())?;
+                file_infos.push((key, file_info, mac));
+                continue;
+            }
+
+            let file_path = self.prepare_file_path(&key)?;
+            let (compressed_data, file_info) =
+                self.encode_data_file(&key, &data, file_path.clone(), meta.clone());
+            let mac = self.sign_row(&key, file_info.compression, &compressed_data);
+
+            self.cold_index
+                .write()
+                .insert(key.clone(), file_info.clone());
+
+            match self.write_data(&file_path, compressed_data, data_size > 1024 * 1024)? {
+                Written::Done => {}
+                Written::Queued => has_async_file_writes = true,
+                Written::Committing(done) => commits.push(done),
+            }
+            if let Some(wal) = wal.as_mut() {
+                wal.mark_dirty(file_path);
+            }
+
+            file_infos.push((key, file_info, mac));
+        }
+
+        self.cleanup_hot_cache();
+        self.persist_inline_entries(&inline_entries)?;
+        // Relaxed writes are published before they land; a read that finds
+        // no file yet waits for the batcher
+        if has_async_file_writes && self.config.durability != Durability::Relaxed {
+            self.write_batcher.sync();
+        }
+        // Every file goes into the batch's group commits before any is waited for
+        for done in commits {
+            WriteBatcher::wait(done)?;
+        }
+        self.persist_file_infos(&file_infos)?;
+        let events: Vec<Event<'_>> = inline_entries
+            .iter()
+            .map(|(key, _, _)| Event::Set(key))
+            .chain(file_infos.iter().map(|(key, _, _)| Event::Set(key)))
+            .collect();
+        self.publish(&events);
+
+        self.maybe_compact();
+        self.finish_logged_write(wal)
+    }
+
+    fn delete(&self, key: &str) -> CacheResult<bool> {
+        let Some(remote) = &self.config.remote_tier else {
+            return self.delete_local(key);
+        };
+        // The remote copy goes first, so a crash in between cannot bring it back
+        let name = self.remote_name(key);
+        let queued = self.remote_uploads.cancel(&name);
+        let pushed = !self.exists_local(key)? && (queued || remote.exists(&name)?);
+        remote.delete(&name)?;
+        Ok(self.delete_local(key)? || pushed)
+    }
+
+    /// Move `key` to the remote tier when there is one, instead of dropping it
+    ///
+    /// The value is uploaded in the background; until it is, reads are
+    /// served from the queued copy.
+    fn evict(&self, key: &str) -> CacheResult<bool> {
+        let Some(remote) = &self.config.remote_tier else {
+            return self.delete(key);
+        };
+        match self.get_local(key, false) {
+            Ok(Some(entry)) => {
+                let data = entry.get_data().unwrap_or_default();
+                let object = self.remote_object(key, data);
+                self.remote_uploads
+                    .push(remote, self.remote_name(key), object)?;
+            }
+            Ok(None) => return Ok(false),
+            // An unreadable value is not pushed, and an older pushed one must not come back
+            Err(CacheError::Corrupted(_) | CacheError::Tampered(_) | CacheError::Collided(_)) => {
+                let name = self.remote_name(key);
+                self.remote_uploads.cancel(&name);
+                remote.delete(&name)?;
+            }
+            Err(e) => return Err(e),
+        }
+        self.delete_local(key)
+    }
+
+    fn set_alias(&self, alias: &str, key: &str) -> CacheResult<()> {
+        let conn = self.index_db.lock();
+        // One statement, so the entry cannot vanish between the check and the insert
+        let inserted = conn
+            .execute(
+                "INSERT INTO cache_alias (alias, key) SELECT ?1, key FROM cache_index WHERE key = ?2 \
+                 ON CONFLICT(alias) DO UPDATE SET key = excluded.key",
+                params![alias, key],
+            )
+            .map_err(|e| Self::sqlite_error("Failed to store SQLite alias", e))?;
+        if inserted == 0 {
+            return Err(CacheError::KeyNotFound(key.to_string()));
+        }
+        Ok(())
+    }
+
+    fn resolve_alias(&self, alias: &str) -> CacheResult<Option<String>> {
+        let conn = self.index_db.lock();
+        conn.query_row(
+            "SELECT a.key FROM cache_alias a JOIN cache_index i ON i.key = a.key WHERE a.alias = ?1",
+            params![alias],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| Self::sqlite_error("Failed to resolve SQLite alias", e))
+    }
+
+    fn remove_alias(&self, alias: &str) -> CacheResult<bool> {
+        let conn = self.index_db.lock();
+        let removed = conn
+            .execute("DELETE FROM cache_alias WHERE alias = ?1", params![alias])
+            .map_err(|e| Self::sqlite_error("Failed to remove SQLite alias", e))?;
+        Ok(removed > 0)
+    }
+
+    fn aliases(&self, key: &str) -> CacheResult<Vec<String>> {
+        let conn = self.index_db.lock();
+        let mut stmt = conn
+            .prepare("SELECT alias FROM cache_alias WHERE key = ?1")
+            .map_err(|e| Self::sqlite_error("Failed to prepare SQLite alias query", e))?;
+        let aliases = stmt
+            .query_map(params![key], |row| row.get(0))
+            .map_err(|e| Self::sqlite_error("Failed to query SQLite aliases", e))?
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(|e| Self::sqlite_error("Failed to read SQLite alias", e))?;
+        Ok(aliases)
+    }
+
+    /// The entry's index generation, rewritten on every set
+    fn version(&self, key: &str) -> CacheResult<Option<u64>> {
+        Ok(self
+            .read_index_generation(key)?
+            .map(|generation| generation as u64))
+    }
+
+    fn exists_local(&self, key: &str) -> CacheResult<bool> {
+        let conn = self.index_db.lock();
+        let exists: Option<i32> = conn
+            .query_row(
+                "SELECT 1 FROM cache_index WHERE key = ?1 LIMIT 1",
+                params![key],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| Self::sqlite_error("Failed to check SQLite index entry", e))?;
+        Ok(exists.is_some())
+    }
+
+    fn exists(&self, key: &str) -> CacheResult<bool> {
+        if self.exists_local(key)? {
+            return Ok(true);
+        }
+        match &self.config.remote_tier {
+            Some(remote) => {
+                let name = self.remote_name(key);
+                Ok(self.remote_uploads.get(&name).is_some() || remote.exists(&name)?)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn keys(&self) -> CacheResult<Vec<String>> {
+        let conn = self.index_db.lock();
+        let mut stmt = conn
+            .prepare("SELECT key FROM cache_index")
+            .map_err(|e| Self::sqlite_error("Failed to query SQLite index keys", e))?;
+        let rows = stmt
+            .query_map([], |row| row.get::<_, String>(0))
+            .map_err(|e| Self::sqlite_error("Failed to iterate SQLite index keys", e))?;
+        let mut keys = Vec::new();
+        for row in rows {
+            keys.push(row.map_err(|e| Self::sqlite_error("Failed to read SQLite key", e))?);
+        }
+
+        Ok(keys)
+    }
+
+    fn peek_key(&self, last: bool) -> CacheResult<Option<String>> {
+        let sql = if last {
+            "SELECT key FROM cache_index ORDER BY seq DESC LIMIT 1"
+        } else {
+            "SELECT key FROM cache_index ORDER BY seq ASC LIMIT 1"
+        };
+        let conn = self.index_db.lock();
+        conn.query_row(sql, [], |row| row.get(0))
+            .optional()
+            .map_err(|e| Self::sqlite_error("Failed to query SQLite index order", e))
+    }
+
+    fn key_page(
+        &self,
+        cursor: Option<i64>,
+        reverse: bool,
+        limit: usize,
+    ) -> CacheResult<Vec<(i64, String)>> {
+        let (sql, cursor) = if reverse {
+            (
+                "SELECT seq, key FROM cache_index WHERE seq < ?1 ORDER BY seq DESC LIMIT ?2",
+                cursor.unwrap_or(i64::MAX),
+            )
+        } else {
+            (
+                "SELECT seq, key FROM cache_index WHERE seq > ?1 ORDER BY seq ASC LIMIT ?2",
+                cursor.unwrap_or(i64::MIN),
+            )
+        };
+        let conn = self.index_db.lock();
+        let mut stmt = conn
+            .prepare(sql)
+            .map_err(|e| Self::sqlite_error("Failed to query SQLite key page", e))?;
+        let rows = stmt
+            .query_map(params![cursor, limit as i64], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })
+            .map_err(|e| Self::sqlite_error("Failed to iterate SQLite key page", e))?;
+        let mut page = Vec::new();
+        for row in rows {
+            page.push(row.map_err(|e| Self::sqlite_error("Failed to read SQLite key", e))?);
+        }
+        Ok(page)
+    }
+
+    fn peek_key_in_range(&self, start: &str, end: &str, last: bool) -> CacheResult<Option<String>> {
+        let sql = if last {
+            "SELECT key FROM cache_index WHERE key >= ?1 AND key < ?2 ORDER BY key DESC LIMIT 1"
+        } else {
+            "SELECT key FROM cache_index WHERE key >= ?1 AND key < ?2 ORDER BY key ASC LIMIT 1"
+        };
+        let conn = self.index_db.lock();
+        conn.query_row(sql, params![start, end], |row| row.get(0))
+            .optional()
+            .map_err(|e| Self::sqlite_error("Failed to scan SQLite index range", e))
+    }
+
+    fn estimate_footprint(&self, entries: u64, key_size: usize, value_size: usize) -> Footprint {
+        let key = key_size as u64;
+        let value = value_size as u64;
+        // The key is stored in the row and again in the primary key index
+        let index_row = key
+            .saturating_mul(2)
+            .saturating_add(INDEX_ROW_OVERHEAD + FILE_INFO_FIXED_SIZE);
+
+        if value_size < self.config.disk_write_threshold {
+            // Inline rows carry the value after a `memory://<key>` FileInfo, and
+            // only the hot tier keeps copies in memory
+            let row = index_row
+                .saturating_add("memory://".len() as u64)
+                .saturating_add(key)
+                .saturating_add(value);
+            let resident = entries.min(self.config.hot_cache_size as u64);
+            Footprint {
+                disk_bytes: entries.saturating_mul(row),
+                memory_bytes: resident
+                    .saturating_mul(key.saturating_add(value).saturating_add(MAP_ENTRY_OVERHEAD)),
+            }
+        } else if value_size < self.config.pack_threshold {
+            // Packed values share segment files, so there is no block rounding
+            // and nothing stays in memory
+            Footprint {
+                disk_bytes: entries.saturating_mul(
+                    index_row
+                        .saturating_add(PACKED_PATH_LEN)
+                        .saturating_add(value),
+                ),
+                memory_bytes: 0,
+            }
+        } else {
+            // Compression is not assumed, so file sizes are an upper bound. Every
+            // file-backed key keeps its FileInfo in the cold index.
+            let file = value.div_ceil(FS_BLOCK_SIZE).saturating_mul(FS_BLOCK_SIZE);
+            let name = self.generate_filename(&"k".repeat(key_size)).len() as u64;
+            let path = (self.config.data_fanout as u64)
+                .saturating_mul(FANOUT_LEVEL_LEN)
+                .saturating_add(DATA_DIR_LEN + name);
+            Footprint {
+                disk_bytes: entries
+                    .saturating_mul(index_row.saturating_add(path).saturating_add(file)),
+                memory_bytes: entries.saturating_mul(
+                    key.saturating_add(path)
+                        .saturating_add(FILE_INFO_FIXED_SIZE + MAP_ENTRY_OVERHEAD),
+                ),
+            }
+        }
+    }
+
+    fn clear(&self) -> CacheResult<()> {
+        self.clear_with_progress(&|_| {})
+    }
+
+    fn clear_with_progress(&self, progress: &(dyn Fn(UnlinkProgress) + Sync)) -> CacheResult<()> {
+        if let Some(remote) = &self.config.remote_tier {
+            self.remote_uploads.clear();
+            remote.clear()?;
+        }
+        let _wal = self.log_writes(&[WalRecord::Clear])?;
+        self.hot_cache.clear();
+        self.warm_cache.clear();
+
+        // Clear cold storage
+        let paths: Vec<PathBuf> = {
+            let cold_index = self.cold_index.write();
+            let paths = cold_index
+                .iter()
+                .map(|entry| entry.value().path.clone())
+                .filter(|path| self.owns_file(path))
+                .collect();
+            cold_index.clear();
+            paths
+        };
+
+        // Let queued writes land first so none of them recreates a removed file
+        self.write_batcher.sync();
+        self.open_files.clear();
+        let unlink = UnlinkPool::new(self.config.unlink_workers, self.config.unlink_rate);
+        let done = unlink.unlink(&paths, progress);
+        self.stats.record_files_deleted(done.removed);
+
+        let mut conn = self.index_db.lock();
+        let tx = Self::write_transaction(&mut conn)?;
+        tx.execute("DELETE FROM cache_index", [])
+            .map_err(|e| Self::sqlite_error("Failed to clear SQLite index", e))?;
+        tx.execute("DELETE FROM cache_alias", [])
+            .map_err(|e| Self::sqlite_error("Failed to clear SQLite aliases", e))?;
+        tx.commit()
+            .map_err(|e| Self::sqlite_error("Failed to commit SQLite transaction", e))?;
+        drop(conn);
+        self.tier_bytes.set(TierSizes::default());
+        self.publish(&[Event::Clear]);
+
+        let _compaction = self.compactor.lock.lock();
+        for _ in 0..self.segments.remove_all()? {
+            self.stats.record_file_deleted();
+        }
+
+        Ok(())
+    }
+
+    fn vacuum(&self) -> CacheResult<VacuumReport> {
+        // Force cleanup of old entries
+        self.cleanup_hot_cache();
+        self.cleanup_warm_cache();
+
+        // Sync pending writes
+        self.write_batcher.sync();
+
+        self.compact_segments()?;
+
+        let mut report = self.collect_orphans()?;
+        report.bytes_reclaimed += self
+            .compactor
+            .punch_dead_regions(self.config.orphan_grace)?;
+        self.recount_tiers()?;
+        Ok(report)
+    }
+
+    fn generate_filename(&self, key: &str) -> String {
+        self.config.file_naming.file_name(key)
+    }
+
+    fn write_data_file(&self, filename: &str, data: &[u8]) -> CacheResult<()> {
+        let file_path = self.data_path(filename);
+        if let Some(parent) = file_path.parent() {
+            std::fs::create_dir_all(parent).map_err(CacheError::Io)?;
+        }
+        std::fs::write(&file_path, data).map_err(CacheError::Io)?;
+        self.stats.record_file_created(data.len() as u64);
+        Ok(())
+    }
+
+    fn read_data_file(&self, filename: &str) -> CacheResult<Vec<u8>> {
+        let file_path = self.data_path(filename);
+        std::fs::read(&file_path).map_err(CacheError::Io)
+    }
+
+    fn data_file_path(&self, key: &str) -> CacheResult<Option<(PathBuf, u64)>> {
+        if self.config.index_key.is_some() {
+            // A caller reading the file directly would skip the signature check
+            return Ok(None);
+        }
+        match self.read_index_entry(key)? {
+            Some(IndexEntry::File(file_info, _)) if !file_info.compression.is_compressed() => {
+                if !Self::has_indexed_length(&file_info)? {
+                    // Left for get() to report and drop
+                    return Ok(None);
+                }
+                match DataFileHeader::read_from(&file_info.path) {
+                    // Left for get() to report and drop
+                    Ok(Some((header, _))) if header.key != key => Ok(None),
+                    Ok(header) => {
+                        let offset = header.map_or(0, |(_, offset)| offset as u64);
+                        Ok(Some((file_info.path, offset)))
+                    }
+                    Err(CacheError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                    Err(e) => Err(e),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn open_frames(&self, key: &str) -> CacheResult<Option<FramedReader<File>>> {
+        if self.config.index_key.is_some() {
+            // Partial reads would skip the signature check over the whole file
+            return Ok(None);
+        }
+        let file_info = match self.read_index_entry(key)? {
+            Some(IndexEntry::File(file_info, _))
+                if file_info.compression.frame_codec().is_some() =>
+            {
+                file_info
+            }
+            _ => return Ok(None),
+        };
+        let mut file = match File::open(&file_info.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(CacheError::Io(e)),
+        };
+        if file.metadata()?.len() != file_info.size {
+            // Cut short or replaced; left for get() to report and drop
+            return Ok(None);
+        }
+        let offset = match DataFileHeader::read_from(&file_info.path)? {
+            Some((header, offset)) if header.key == key => offset,
+            // Left for get() to report and drop
+            _ => return Ok(None),
+        };
+        file.seek(SeekFrom::Start(offset as u64))?;
+        let codec = file_info.compression.frame_codec().unwrap();
+        FramedReader::new(file, codec).map(Some)
+    }
+
+    fn register_files(&self, files: &[(String, PathBuf)]) -> CacheResult<u64> {
+        let now = Self::get_current_timestamp();
+        let mut registered = Vec::with_capacity(files.len());
+        for (key, path) in files {
+            let path = std::path::absolute(path)?;
+            let metadata = std::fs::metadata(&path)?;
+            if !metadata.is_file() {
+                return Err(CacheError::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("{:?} is not a regular file", path),
+                )));
+            }
+            let mac = self.sign_file(key, &path)?;
+            registered.push((
+                key.clone(),
+                FileInfo {
+                    path,
+                    size: metadata.len(),
+                    created_at: now,
+                    compression: Compression::None,
+                    checksum: None,
+                    meta: EntryMeta::created(now),
+                },
+                mac,
+            ));
+        }
+
+        let _key_lock = self
+            .key_locks
+            .lock_all(registered.iter().map(|(key, _, _)| key.as_str()))?;
+        for (key, file_info, _) in &registered {
+            self.hot_cache.remove(key);
+            self.warm_cache.remove(key);
+            self.remove_existing_persisted_entry(key)?;
+            self.cold_index
+                .write()
+                .insert(key.clone(), file_info.clone());
+        }
+        self.persist_file_infos(&registered)?;
+        let events: Vec<Event<'_>> = registered
+            .iter()
+            .map(|(key, _, _)| Event::Set(key))
+            .collect();
+        self.publish(&events);
+        Ok(registered.len() as u64)
+    }
+
+    fn link_file(&self, key: &str, source: &Path) -> CacheResult<u64> {
+        let metadata = std::fs::metadata(source)?;
+        if !metadata.is_file() {
+            return Err(CacheError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{:?} is not a regular file", source),
+            )));
+        }
+        let size = metadata.len();
+        if (size as usize)
+            < self
+                .config
+                .disk_write_threshold
+                .max(self.config.pack_threshold)
+        {
+            // Values this small are kept inline or packed, not in a file of their own
+            self.set_data(key, &std::fs::read(source)?)?;
+            return Ok(size);
+        }
+
+        // No record is logged: the value is never read, so the log is
+        // checkpointed once the link and its index row are in place
+        let _key_lock = self.key_locks.lock(key)?;
+        let mut wal = self.wal.as_ref().map(|wal| wal.lock());
+        self.hot_cache.remove(key);
+        self.warm_cache.remove(key);
+        self.remove_existing_persisted_entry(key)?;
+
+        let file_path = self.prepare_file_path(key)?;
+        match std::fs::hard_link(source, &file_path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(CacheError::Io(e)),
+            Err(e) => {
+                tracing::debug!("Copying {:?} into the cache, cannot link it: {}", source, e);
+                std::fs::copy(source, &file_path)?;
+                self.stats.record_file_created(size);
+            }
+        }
+        if self.config.durability >= Durability::Fsync {
+            File::open(&file_path)?.sync_all()?;
+            self.stats.record_fsync();
+        }
+        if let Some(parent) = file_path.parent() {
+            self.sync_directory(parent)?;
+        }
+
+        let created_at = Self::get_current_timestamp();
+        let file_info = FileInfo {
+            path: file_path.clone(),
+            size,
+            created_at,
+            compression: Compression::None,
+            checksum: None,
+            meta: EntryMeta::created(created_at),
+        };
+        let mac = self.sign_file(key, &file_path)?;
+        self.cold_index
+            .write()
+            .insert(key.to_string(), file_info.clone());
+        self.persist_file_infos(&[(key.to_string(), file_info, mac)])?;
+        self.stats.record_write(size);
+        self.publish(&[Event::Set(key)]);
+
+        if let Some(wal) = wal.as_mut() {
+            wal.mark_dirty(file_path);
+            self.checkpoint_wal(wal)?;
         }
-        self.persist_file_infos(&file_infos)?;
-
-        Ok(())
+        Ok(size)
     }
 
-    fn delete(&self, key: &str) -> CacheResult<bool> {
-        let mut found = false;
+    fn write_stream(&self, key: &str) -> CacheResult<DataFileWriter> {
+        // Written next to its final name, so committing is a rename; a write
+        // paused for longer than `orphan_grace` may be collected by vacuum
+        let file_path = self.prepare_file_path(key)?;
+        DataFileWriter::create(temp_path_for(&file_path), key)
+    }
 
-        // Remove from all cache levels
-        if self.hot_cache.remove(key).is_some() {
-            found = true;
+    fn commit_stream(&self, writer: DataFileWriter) -> CacheResult<u64> {
+        let key = writer.key().to_string();
+        let size = writer.payload_len();
+        let (file, temp_path, len, checksum) = writer.finish()?;
+        if (size as usize)
+            < self
+                .config
+                .disk_write_threshold
+                .max(self.config.pack_threshold)
+        {
+            // Values this small are kept inline or packed, not in a file of their own
+            drop(file);
+            let framed = std::fs::read(&temp_path);
+            let _ = std::fs::remove_file(&temp_path);
+            let framed = framed?;
+            let offset = DataFileHeader::decode(&framed)?.map_or(0, |(_, offset)| offset);
+            self.set_data(&key, &framed[offset..])?;
+            return Ok(size);
         }
-
-        if self.warm_cache.remove(key).is_some() {
-            found = true;
+        if self.config.durability >= Durability::Fsync {
+            if let Err(e) = file.sync_all() {
+                let _ = std::fs::remove_file(&temp_path);
+                return Err(CacheError::Io(e));
+            }
+            self.stats.record_fsync();
         }
-
-        let mut delete_sqlite_entry = !found;
-        let mut removed_cold_entry = false;
-        if let Some((_, file_info)) = self.cold_index.write().remove(key) {
-            removed_cold_entry = true;
-            found = true;
-            delete_sqlite_entry =
-                file_info.compressed || file_info.size as usize >= self.config.disk_write_threshold;
-            self.write_batcher.delete_async(file_info.path);
+        drop(file);
+        self.stats.record_file_created(len);
+
+        // Like a linked file, the value is never logged
+        let _key_lock = self.key_locks.lock(&key)?;
+        let mut wal = self.wal.as_ref().map(|wal| wal.lock());
+        self.hot_cache.remove(&key);
+        self.warm_cache.remove(&key);
+        self.remove_existing_persisted_entry(&key)?;
+
+        let file_path = self.prepare_file_path(&key)?;
+        let renamed = self
+            .config
+            .sharing_retry
+            .run(|| std::fs::rename(&temp_path, &file_path));
+        if let Err(e) = renamed {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(CacheError::Io(e));
         }
-
-        if found && !removed_cold_entry {
-            delete_sqlite_entry = true;
+        if let Some(parent) = file_path.parent() {
+            self.sync_directory(parent)?;
         }
 
-        if !delete_sqlite_entry {
-            return Ok(found);
+        let created_at = Self::get_current_timestamp();
+        let file_info = FileInfo {
+            path: file_path.clone(),
+            size: len,
+            created_at,
+            compression: Compression::None,
+            checksum: Some(checksum),
+            meta: EntryMeta::created(created_at),
+        };
+        let mac = self.sign_file(&key, &file_path)?;
+        self.cold_index
+            .write()
+            .insert(key.clone(), file_info.clone());
+        self.persist_file_infos(&[(key.clone(), file_info, mac)])?;
+        self.stats.record_write(len);
+        self.publish(&[Event::Set(&key)]);
+
+        if let Some(wal) = wal.as_mut() {
+            wal.mark_dirty(file_path);
+            self.checkpoint_wal(wal)?;
         }
+        Ok(size)
+    }
 
-        let conn = self.index_db.lock();
-        let deleted = conn
-            .execute("DELETE FROM cache_index WHERE key = ?1", params![key])
-            .map_err(|e| Self::sqlite_error("Failed to delete SQLite index entry", e))?;
-
-        Ok(found || deleted > 0)
+    fn watch_expirations(&self, listener: ExpiryListener) {
+        *self.expirations.write() = Some(listener);
     }
 
-    fn exists(&self, key: &str) -> CacheResult<bool> {
-        let conn = self.index_db.lock();
-        let exists: Option<i32> = conn
-            .query_row(
-                "SELECT 1 FROM cache_index WHERE key = ?1 LIMIT 1",
-                params![key],
-                |row| row.get(0),
-            )
-            .optional()
-            .map_err(|e| Self::sqlite_error("Failed to check SQLite index entry", e))?;
-        Ok(exists.is_some())
+    fn set_pinned(&self, key: &str, pinned: bool) {
+        if pinned {
+            self.pinned.write().insert(key.to_string());
+        } else {
+            self.pinned.write().remove(key);
+        }
     }
 
-    fn keys(&self) -> CacheResult<Vec<String>> {
-        let conn = self.index_db.lock();
-        let mut stmt = conn
-            .prepare("SELECT key FROM cache_index")
-            .map_err(|e| Self::sqlite_error("Failed to query SQLite index keys", e))?;
-        let rows = stmt
-            .query_map([], |row| row.get::<_, String>(0))
-            .map_err(|e| Self::sqlite_error("Failed to iterate SQLite index keys", e))?;
-        let mut keys = Vec::new();
-        for row in rows {
-            keys.push(row.map_err(|e| Self::sqlite_error("Failed to read SQLite key", e))?);
+    fn remove_expired(&self) -> CacheResult<u64> {
+        let now = Self::get_current_timestamp();
+        let expired = {
+            let conn = self.index_db.lock();
+            let mut stmt = conn
+                .prepare("SELECT key, value, generation FROM cache_index")
+                .map_err(|e| Self::sqlite_error("Failed to query SQLite index", e))?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Vec<u8>>(1)?,
+                        row.get::<_, i64>(2)?,
+                    ))
+                })
+                .map_err(|e| Self::sqlite_error("Failed to iterate SQLite index", e))?;
+            let mut expired = Vec::new();
+            for row in rows {
+                let (key, value_bytes, generation) =
+                    row.map_err(|e| Self::sqlite_error("Failed to read SQLite index row", e))?;
+                if Self::decode_file_info(&value_bytes)
+                    .is_ok_and(|(file_info, _)| file_info.meta.is_expired(now))
+                {
+                    expired.push((key, generation));
+                }
+            }
+            expired
+        };
+
+        let mut removed = 0;
+        for (key, generation) in expired {
+            if self.discard_expired(&key, generation)? {
+                removed += 1;
+            }
         }
+        Ok(removed)
+    }
 
-        Ok(keys)
+    fn gate_maintenance(&self, gate: MaintenanceGate) {
+        *self.maintenance.write() = Some(gate);
     }
 
-    fn clear(&self) -> CacheResult<()> {
-        self.hot_cache.clear();
-        self.warm_cache.clear();
+    fn gate_cleanup(&self, gate: CleanupGate) {
+        *self.cleanup.write() = Some(gate);
+    }
 
-        // Clear cold storage
-        let cold_index = self.cold_index.read();
-        for entry in cold_index.iter() {
-            let file_path = &entry.value().path;
-            self.write_batcher.delete_async(file_path.clone());
-        }
-        drop(cold_index);
+    fn was_unclean_shutdown(&self) -> Option<bool> {
+        self.was_unclean_shutdown
+    }
 
-        self.cold_index.write().clear();
+    fn after_fork(&self) {
+        OptimizedStorage::after_fork(self)
+    }
 
-        // Force sync to ensure all deletes are processed
+    fn verify_and_recover(&self) -> CacheResult<RecoveryReport> {
+        // Make sure queued file writes are on disk before checking them
         self.write_batcher.sync();
 
         let conn = self.index_db.lock();
-        conn.execute("DELETE FROM cache_index", [])
-            .map_err(|e| Self::sqlite_error("Failed to clear SQLite index", e))?;
+        let integrity: String = conn
+            .query_row("PRAGMA quick_check", [], |row| row.get(0))
+            .map_err(|e| Self::sqlite_error("Failed to check SQLite index integrity", e))?;
 
-        Ok(())
-    }
+        let mut stmt = conn
+            .prepare("SELECT key, value, generation, mac FROM cache_index")
+            .map_err(|e| Self::sqlite_error("Failed to query SQLite index", e))?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Vec<u8>>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, Option<Vec<u8>>>(3)?,
+                ))
+            })
+            .map_err(|e| Self::sqlite_error("Failed to iterate SQLite index", e))?;
 
-    fn vacuum(&self) -> CacheResult<()> {
-        // Force cleanup of old entries
-        self.cleanup_hot_cache();
-        self.cleanup_warm_cache();
+        let mut report = RecoveryReport {
+            integrity_ok: integrity == "ok",
+            ..Default::default()
+        };
+        let mut broken_keys = Vec::new();
+        for row in rows {
+            let (key, value_bytes, generation, mac) =
+                row.map_err(|e| Self::sqlite_error("Failed to read SQLite index row", e))?;
+            report.entries_checked += 1;
 
-        // Sync pending writes
-        self.write_batcher.sync();
+            let servable = match self.decode_index_entry(&key, &value_bytes, generation, mac) {
+                Ok(IndexEntry::Inline(_)) => true,
+                Ok(IndexEntry::File(file_info, _)) => file_info.path.is_file(),
+                Ok(IndexEntry::Packed(packed)) => self.segments.contains(packed.location),
+                Err(_) => false,
+            };
+            if !servable {
+                broken_keys.push(key);
+            }
+        }
+        drop(stmt);
 
-        // Persist index to disk for recovery after restart
-        self.persist_index()?;
+        for key in &broken_keys {
+            conn.execute("DELETE FROM cache_index WHERE key = ?1", params![key])
+                .map_err(|e| Self::sqlite_error("Failed to remove SQLite index entry", e))?;
+        }
+        drop(conn);
 
-        Ok(())
-    }
+        for key in &broken_keys {
+            self.hot_cache.remove(key);
+            self.warm_cache.remove(key);
+            self.cold_index.write().remove(key);
+        }
+        report.entries_removed = broken_keys.len() as u64;
+        report.entries_restored = self.restore_from_data_files()?;
+        self.recount_tiers()?;
+
+        tracing::info!(
+            "Verified {} entries, removed {} unrecoverable entries, restored {} from data files",
+            report.entries_checked,
+            report.entries_removed,
+            report.entries_restored
+        );
 
-    fn generate_filename(&self, key: &str) -> String {
-        let hash = blake3::hash(key.as_bytes());
-        format!("{}.dat", &hash.to_hex()[..16])
+        Ok(report)
     }
 
-    fn write_data_file(&self, filename: &str, data: &[u8]) -> CacheResult<()> {
-        let file_path = self.directory.join("data").join(filename);
-        std::fs::write(&file_path, data).map_err(CacheError::Io)
+    fn size(&self) -> Option<TierSizes> {
+        Some(self.tier_bytes.get())
     }
 
-    fn read_data_file(&self, filename: &str) -> CacheResult<Vec<u8>> {
-        let file_path = self.directory.join("data").join(filename);
-        std::fs::read(&file_path).map_err(CacheError::Io)
+    fn io_stats(&self) -> IoStats {
+        IoStats {
+            logical_bytes_written: self.stats.bytes_written.load(Ordering::Relaxed),
+            disk_bytes_written: self.stats.disk_bytes_written.load(Ordering::Relaxed),
+            files_created: self.stats.files_created.load(Ordering::Relaxed),
+            files_deleted: self.stats.files_deleted.load(Ordering::Relaxed),
+            fsyncs: self.stats.fsyncs.load(Ordering::Relaxed),
+        }
     }
 
     fn as_any(&self) -> &dyn std::any::Any {
@@ -1028,6 +5319,16 @@ impl StorageBackend for OptimizedStorage {
 impl OptimizedStorage {
     /// Set data with optimized storage strategy
     fn set_data(&self, key: &str, data: &[u8]) -> CacheResult<()> {
+        self.set_entry_data(key, data, EntryMeta::created(Self::get_current_timestamp()))
+    }
+
+    /// [`set_data`](Self::set_data), storing `meta` with the value
+    fn set_entry_data(&self, key: &str, data: &[u8], meta: EntryMeta) -> CacheResult<()> {
+        let _key_lock = self.key_locks.lock(key)?;
+        let mut wal = self.log_writes(&[WalRecord::Put {
+            key: key.to_string(),
+            data: data.to_vec(),
+        }])?;
         let data_size = data.len();
         self.stats.record_write(data_size as u64);
 
@@ -1038,43 +5339,210 @@ impl OptimizedStorage {
 
         if data_size < self.config.disk_write_threshold {
             let bytes = Bytes::copy_from_slice(data);
-            self.persist_inline_entries(&[(key.to_string(), bytes)])?;
+            self.persist_inline_entries(&[(key.to_string(), bytes, meta)])?;
             self.cleanup_hot_cache();
+        } else if data_size < self.config.pack_threshold {
+            let (file_info, mac) = self.pack_value(key, data, meta, wal.as_deref_mut())?;
+            self.persist_file_infos(&[(key.to_string(), file_info, mac)])?;
         } else {
             // Large data: compress and store to disk (>= disk_write_threshold)
-            let (compressed_data, is_compressed) = self.compress_if_beneficial(data);
-            let file_path = self.build_file_path(key);
+            let file_path = self.prepare_file_path(key)?;
+            let (compressed_data, file_info) =
+                self.encode_data_file(key, data, file_path.clone(), meta);
+            let mac = self.sign_row(key, file_info.compression, &compressed_data);
 
             // Store file info in cold index
-            let file_info = FileInfo {
-                path: file_path.clone(),
-                size: compressed_data.len() as u64,
-                created_at: Self::get_current_timestamp(),
-                compressed: is_compressed,
-            };
             self.cold_index
                 .write()
                 .insert(key.to_string(), file_info.clone());
 
-            // Write to disk with optional file locking
-            if self.config.use_file_locking {
-                // Use file locking for NFS scenarios
-                self.write_with_lock(&file_path, &compressed_data)?;
-            } else if self.config.sync_writes || data_size > 1024 * 1024 {
-                // Large files or sync mode: write immediately
-                std::fs::write(&file_path, &compressed_data).map_err(CacheError::Io)?;
-            } else {
-                // Async write for better performance, then wait before publishing metadata.
-                self.write_batcher.write_async(file_path, compressed_data);
-                self.write_batcher.sync();
+            // Large files are written immediately; others go through the batcher,
+            // which is waited for before publishing metadata unless durability is relaxed
+            match self.write_data(&file_path, compressed_data, data_size > 1024 * 1024)? {
+                Written::Queued if self.config.durability != Durability::Relaxed => {
+                    self.write_batcher.sync();
+                }
+                Written::Committing(done) => WriteBatcher::wait(done)?,
+                _ => {}
             }
+            if let Some(wal) = wal.as_mut() {
+                wal.mark_dirty(file_path);
+            }
+
+            self.persist_file_infos(&[(key.to_string(), file_info, mac)])?;
+        }
+        self.publish(&[Event::Set(key)]);
+
+        self.maybe_compact();
+        self.finish_logged_write(wal)
+    }
+
+    /// Append `key`'s value to the active segment, returning the FileInfo that
+    /// indexes it and the row's MAC
+    fn pack_value(
+        &self,
+        key: &str,
+        data: &[u8],
+        meta: EntryMeta,
+        wal: Option<&mut WriteAheadLog>,
+    ) -> CacheResult<(FileInfo, Option<[u8; 32]>)> {
+        let (compressed_data, compression) = self.compress_if_beneficial(data);
+        let sync = self.config.sync_writes || self.config.durability >= Durability::Fsync;
+        let appended = self.segments.append(&compressed_data, sync)?;
+        if appended.created {
+            self.stats.record_file_created(compressed_data.len() as u64);
+            self.sync_directory(self.segments.directory())?;
+        } else {
+            self.stats.record_disk_write(compressed_data.len() as u64);
+        }
+        if sync {
+            self.stats.record_fsync();
+        }
+        if let Some(wal) = wal {
+            wal.mark_dirty(self.segments.path(appended.location.segment));
+        }
+
+        let file_info = FileInfo {
+            path: appended.location.to_path(),
+            size: appended.location.len,
+            created_at: Self::get_current_timestamp(),
+            compression,
+            checksum: Some(payload_checksum(&compressed_data)),
+            meta,
+        };
+        Ok((file_info, self.sign_row(key, compression, &compressed_data)))
+    }
+
+    /// Reclaim dead space in segment files now, returning the bytes reclaimed
+    ///
+    /// Waits for a background compaction in progress. [`StorageBackend::vacuum`]
+    /// calls this too.
+    pub fn compact_segments(&self) -> CacheResult<u64> {
+        self.compactor.run()
+    }
+
+    /// Start a background compaction once dead space passes `compaction_ratio`
+    fn maybe_compact(&self) {
+        if self.config.pack_threshold == 0 || !self.compactor.is_due() {
+            return;
+        }
+        if self.maintenance.read().as_ref().is_some_and(|gate| !gate())
+            || self.compactor.scheduled.swap(true, Ordering::AcqRel)
+        {
+            return;
+        }
 
-            self.persist_file_infos(&[(key.to_string(), file_info)])?;
+        let compactor = self.compactor.clone();
+        let handle = std::thread::spawn(move || {
+            if let Err(e) = compactor.run() {
+                tracing::warn!("Background segment compaction failed: {}", e);
+            }
+            compactor.scheduled.store(false, Ordering::Release);
+        });
+        if let Some(previous) = self.compaction_thread.lock().replace(handle) {
+            // Finished already: `scheduled` was clear
+            let _ = previous.join();
         }
+    }
 
+    /// Write a data file in one go, replacing it atomically if so configured
+    fn write_file(&self, path: &Path, data: &[u8], sync: bool) -> CacheResult<()> {
+        if self.config.atomic_writes {
+            return self
+                .config
+                .sharing_retry
+                .run(|| replace_file(path, data, sync))
+                .map_err(CacheError::Io);
+        }
+        let mut file = File::create(path)?;
+        file.write_all(data)?;
+        if sync {
+            file.sync_all()?;
+        }
         Ok(())
     }
 
+    /// Write a data file with O_DIRECT, so a large value does not push other
+    /// processes' data out of the page cache
+    ///
+    /// The value is staged through an aligned buffer from the pool a chunk at
+    /// a time; the last chunk is padded out to the alignment and the file cut
+    /// back to the value's length. Filesystems that refuse O_DIRECT, such as
+    /// tmpfs, get a regular write instead.
+    #[cfg(target_os = "linux")]
+    fn write_direct(&self, path: &Path, data: &[u8], sync: bool) -> CacheResult<()> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let atomic = self.config.atomic_writes;
+        let target = if atomic {
+            temp_path_for(path)
+        } else {
+            path.to_path_buf()
+        };
+        let file = match OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .custom_flags(libc::O_DIRECT)
+            .open(&target)
+        {
+            Ok(file) => file,
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+                return self.write_file(path, data, sync)
+            }
+            Err(e) => return Err(CacheError::Io(e)),
+        };
+
+        let result = self.write_aligned(&file, data).and_then(|()| {
+            file.set_len(data.len() as u64)?;
+            if sync {
+                file.sync_all()?;
+            }
+            if atomic {
+                std::fs::rename(&target, path)?;
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                if atomic {
+                    let _ = std::fs::remove_file(&target);
+                }
+                // Opening with O_DIRECT can succeed where the writes cannot
+                if e.raw_os_error() == Some(libc::EINVAL) {
+                    self.write_file(path, data, sync)
+                } else {
+                    Err(CacheError::Io(e))
+                }
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn write_direct(&self, path: &Path, data: &[u8], sync: bool) -> CacheResult<()> {
+        self.write_file(path, data, sync)
+    }
+
+    /// Write `data` to a file opened with O_DIRECT, in aligned chunks
+    #[cfg(target_os = "linux")]
+    fn write_aligned(&self, mut file: &File, data: &[u8]) -> std::io::Result<()> {
+        let mut buffer = self
+            .buffer_pool
+            .get_buffer(DIRECT_IO_CHUNK + DIRECT_IO_ALIGN);
+        buffer.resize(DIRECT_IO_CHUNK + DIRECT_IO_ALIGN, 0);
+        let start = buffer.as_ptr().align_offset(DIRECT_IO_ALIGN);
+        let result = data.chunks(DIRECT_IO_CHUNK).try_for_each(|chunk| {
+            let staged = &mut buffer[start..start + chunk.len().next_multiple_of(DIRECT_IO_ALIGN)];
+            let (value, padding) = staged.split_at_mut(chunk.len());
+            value.copy_from_slice(chunk);
+            padding.fill(0);
+            file.write_all(staged)
+        });
+        self.buffer_pool.return_buffer(buffer);
+        result
+    }
+
     /// Write data to file with exclusive lock (for NFS scenarios)
     fn write_with_lock(&self, file_path: &Path, data: &[u8]) -> CacheResult<()> {
         use fs4::fs_std::FileExt;
@@ -1107,6 +5575,8 @@ impl OptimizedStorage {
 
         // Sync to disk to ensure data is written
         file.sync_all().map_err(CacheError::Io)?;
+        self.stats.record_file_created(data.len() as u64);
+        self.stats.record_fsync();
 
         // Lock is automatically released when file is dropped
         Ok(())
@@ -1126,6 +5596,10 @@ impl OptimizedStorage {
             hot_cache_size: self.hot_cache.len(),
             warm_cache_size: self.warm_cache.len(),
             cold_index_size: self.cold_index.read().len(),
+            segment_bytes: self.segments.bytes(),
+            segment_dead_bytes: self.segments.dead_bytes(),
+            compactions: self.stats.compactions.load(Ordering::Relaxed),
+            bytes_reclaimed: self.stats.bytes_reclaimed.load(Ordering::Relaxed),
         }
     }
 
@@ -1137,13 +5611,192 @@ impl OptimizedStorage {
         }
         Ok(())
     }
+
+    /// Drop every value held in memory, so the next reads go to disk
+    pub(crate) fn clear_memory_tiers(&self) {
+        self.hot_cache.clear();
+        self.warm_cache.clear();
+        self.open_files.clear();
+    }
+
+    /// Wait until evicted entries are in the remote tier
+    #[cfg(test)]
+    pub(crate) fn wait_for_uploads(&self) {
+        if let Some(remote) = &self.config.remote_tier {
+            self.remote_uploads.flush(remote.as_ref());
+        }
+    }
+
+    /// Whether the hot and the warm tier hold `key`
+    #[cfg(test)]
+    pub(crate) fn tiers_holding(&self, key: &str) -> (bool, bool) {
+        (
+            self.hot_cache.contains_key(key),
+            self.warm_cache.contains_key(key),
+        )
+    }
+
+    /// Every index row by key, with its generation and MAC
+    #[cfg(test)]
+    fn index_rows(&self) -> HashMap<String, IndexRow> {
+        let conn = self.index_db.lock();
+        let mut stmt = conn
+            .prepare("SELECT key, value, generation, mac FROM cache_index")
+            .unwrap();
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get(0)?, (row.get(1)?, row.get(2)?, row.get(3)?)))
+            })
+            .unwrap();
+        rows.map(Result::unwrap).collect()
+    }
+
+    /// Hot entries at the generation of their key's row that hold other
+    /// bytes than the row
+    #[cfg(test)]
+    pub(crate) fn hot_tier_violations(&self) -> Vec<String> {
+        let rows = self.index_rows();
+        let mut violations = Vec::new();
+
+        let hot: Vec<(String, HotEntry)> = self
+            .hot_cache
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        for (key, entry) in hot {
+            // Older generations are dropped when read, never served
+            let Some((value_bytes, generation, mac)) = rows.get(&key) else {
+                continue;
+            };
+            if *generation != entry.generation {
+                continue;
+            }
+            let stored = match self.decode_index_entry(&key, value_bytes, *generation, mac.clone())
+            {
+                Ok(IndexEntry::Inline(row)) => Some(row.data),
+                Ok(IndexEntry::File(file_info, mac)) => {
+                    std::fs::read(&file_info.path).ok().and_then(|raw| {
+                        self.decode_data_file(&key, &raw, &file_info, mac.as_deref())
+                            .ok()
+                    })
+                }
+                Ok(IndexEntry::Packed(packed)) => self
+                    .segments
+                    .read(packed.location)
+                    .ok()
+                    .and_then(|raw| self.decompress_if_needed(&raw, packed.compression).ok()),
+                Err(e) => {
+                    violations.push(format!("{}: undecodable row: {}", key, e));
+                    continue;
+                }
+            };
+            if stored.as_deref() != Some(&entry.data[..]) {
+                violations.push(format!(
+                    "{}: hot tier holds other bytes than its row at generation {}",
+                    key, generation
+                ));
+            }
+        }
+        violations
+    }
+
+    /// Disagreement between the warm tier's byte count and its mappings
+    #[cfg(test)]
+    pub(crate) fn warm_tier_violations(&self) -> Vec<String> {
+        let mapped: u64 = self
+            .warm_cache
+            .iter()
+            .map(|mapped| mapped.data.len() as u64)
+            .sum();
+        let counted = self.warm_cache.bytes();
+        if counted == mapped {
+            return Vec::new();
+        }
+        vec![format!("{} warm bytes counted, {} mapped", counted, mapped)]
+    }
+
+    /// Cold entries for keys whose row is not that data file
+    ///
+    /// Only holds while no other handle writes to the directory: the cold
+    /// tier is not told about their writes.
+    #[cfg(test)]
+    pub(crate) fn cold_tier_violations(&self) -> Vec<String> {
+        let rows = self.index_rows();
+        let mut violations = Vec::new();
+        let cold: Vec<(String, FileInfo)> = self
+            .cold_index
+            .read()
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        for (key, file_info) in cold {
+            let row = rows.get(&key).map(|(value_bytes, generation, mac)| {
+                self.decode_index_entry(&key, value_bytes, *generation, mac.clone())
+            });
+            match row {
+                Some(Ok(IndexEntry::File(row, _)))
+                    if row.path == file_info.path && row.checksum == file_info.checksum => {}
+                _ => violations.push(format!(
+                    "{}: cold tier points at {:?}, which is not its row",
+                    key, file_info.path
+                )),
+            }
+        }
+        violations
+    }
+
+    /// Ways the segment byte counts disagree with the packed rows in the index
+    /// and the segment files on disk
+    #[cfg(test)]
+    pub(crate) fn segment_accounting_violations(&self) -> Vec<String> {
+        let _compaction = self.compactor.lock.lock();
+        let mut live = 0;
+        for (_, (value_bytes, _, _)) in self.index_rows() {
+            let (file_info, _) = Self::decode_file_info(&value_bytes).unwrap();
+            if PackedRef::is_packed(&file_info.path) {
+                live += file_info.size;
+            }
+        }
+        let on_disk: u64 = self
+            .segments
+            .segment_ids()
+            .unwrap()
+            .into_iter()
+            .filter_map(|segment| std::fs::metadata(self.segments.path(segment)).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+
+        let (bytes, dead) = (self.segments.bytes(), self.segments.dead_bytes());
+        let mut violations = Vec::new();
+        if bytes != on_disk {
+            violations.push(format!(
+                "{} segment bytes counted, {} on disk",
+                bytes, on_disk
+            ));
+        }
+        if dead > bytes {
+            violations.push(format!("{} dead segment bytes out of {}", dead, bytes));
+        } else if bytes - dead != live {
+            violations.push(format!(
+                "{} live segment bytes counted, {} referenced by the index",
+                bytes - dead,
+                live
+            ));
+        }
+        violations
+    }
 }
 
 impl Drop for OptimizedStorage {
     fn drop(&mut self) {
+        self.after_fork();
+        if let Some(compaction) = self.compaction_thread.lock().take() {
+            let _ = compaction.join();
+        }
+        // Rows are written with every set; writing the cold tier back here
+        // would bring back entries deleted since it was loaded
         self.write_batcher.shutdown();
-        // Ensure index is persisted when storage is dropped
-        let _ = self.persist_index();
+        self.close_wal();
     }
 }
 
@@ -1160,4 +5813,288 @@ pub struct StorageStatistics {
     pub hot_cache_size: usize,
     pub warm_cache_size: usize,
     pub cold_index_size: usize,
+    /// Bytes held by segment files, and how many of them no entry uses
+    pub segment_bytes: u64,
+    pub segment_dead_bytes: u64,
+    /// Compaction runs so far and the segment bytes they freed
+    pub compactions: u64,
+    pub bytes_reclaimed: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// A batcher whose worker is stuck opening a FIFO nobody reads yet
+    #[cfg(unix)]
+    fn stalled_batcher(dir: &Path, queue_full: QueueFullPolicy) -> (WriteBatcher, PathBuf) {
+        let fifo = dir.join("stall");
+        let name = std::ffi::CString::new(fifo.to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(name.as_ptr(), 0o600) }, 0);
+        let options = BatcherOptions {
+            batch_size: 1,
+            queue_capacity: 1,
+            queue_full,
+            atomic: false,
+            group_commit: Duration::ZERO,
+            sync_directories: false,
+        };
+        let batcher = WriteBatcher::new(options, Arc::new(StorageStats::default()));
+        let stalled = batcher.write_async(fifo.clone(), Bytes::from_static(b"x"));
+        assert!(matches!(stalled, Ok(None)));
+        (batcher, fifo)
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn full_write_queue_applies_its_policy() {
+        let temp_dir = TempDir::new().unwrap();
+        let (batcher, fifo) = stalled_batcher(temp_dir.path(), QueueFullPolicy::Error);
+        // The queue holds one write; the worker may not have taken the first yet
+        let refused = (0..3).find_map(|i| {
+            let path = temp_dir.path().join(format!("{}.dat", i));
+            batcher.write_async(path, Bytes::from_static(b"v")).err()
+        });
+        assert!(matches!(refused, Some(CacheError::QueueFull)));
+        std::fs::read(&fifo).unwrap();
+        batcher.shutdown();
+
+        let temp_dir = TempDir::new().unwrap();
+        let (batcher, fifo) = stalled_batcher(temp_dir.path(), QueueFullPolicy::Spill);
+        let spilled = (0..3).find_map(|i| {
+            let path = temp_dir.path().join(format!("{}.dat", i));
+            batcher.write_async(path, Bytes::from_static(b"v")).unwrap()
+        });
+        assert_eq!(spilled.as_deref(), Some(&b"v"[..]));
+        std::fs::read(&fifo).unwrap();
+        batcher.sync();
+        // Every queued write was flushed, so no path is held back from spilling
+        assert!(batcher.pending.as_ref().unwrap().lock().is_empty());
+        batcher.shutdown();
+    }
+
+    #[test]
+    fn replace_file_swaps_whole_contents() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("value.dat");
+        std::fs::write(&path, b"old contents").unwrap();
+        #[cfg(unix)]
+        let mut reader = File::open(&path).unwrap();
+
+        replace_file(&path, b"new", true).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        // Nothing is left behind under a temporary name
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+
+        // A reader that opened the old file is never cut short
+        #[cfg(unix)]
+        {
+            use std::io::Read;
+            let mut old = Vec::new();
+            reader.read_to_end(&mut old).unwrap();
+            assert_eq!(old, b"old contents");
+        }
+    }
+
+    #[test]
+    fn cold_reads_reuse_open_files_until_they_are_replaced() {
+        let temp_dir = TempDir::new().unwrap();
+        // Mapped files are kept by the warm tier instead
+        let config = || StorageConfig {
+            mmap_threshold: 0,
+            ..Default::default()
+        };
+        let storage = OptimizedStorage::with_config(temp_dir.path(), config()).unwrap();
+        let other = OptimizedStorage::with_config(temp_dir.path(), config()).unwrap();
+        let value = |fill: u8| vec![fill; 256 * 1024];
+
+        storage.set_data("key", &value(1)).unwrap();
+        for _ in 0..3 {
+            let entry = storage.get("key").unwrap().unwrap();
+            assert_eq!(entry.get_data().unwrap(), value(1).as_slice());
+        }
+        assert_eq!(storage.open_files.len(), 1);
+
+        // Overwritten here, or by another handle behind this one's back
+        storage.set_data("key", &value(2)).unwrap();
+        assert_eq!(storage.open_files.len(), 0);
+        let entry = storage.get("key").unwrap().unwrap();
+        assert_eq!(entry.get_data().unwrap(), value(2).as_slice());
+        other.set_data("key", &value(3)).unwrap();
+        let entry = storage.get("key").unwrap().unwrap();
+        assert_eq!(entry.get_data().unwrap(), value(3).as_slice());
+
+        assert!(storage.delete("key").unwrap());
+        assert_eq!(storage.open_files.len(), 0);
+    }
+
+    #[test]
+    fn relocate_rewrites_legacy_absolute_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        let original = temp_dir.path().join("original");
+        let moved = temp_dir.path().join("moved");
+        let large = vec![9u8; 128 * 1024];
+        {
+            let storage = OptimizedStorage::new(&original).unwrap();
+            let entry = CacheEntry::new_inline("large".to_string(), large.clone(), vec![], None);
+            storage.set("large", entry).unwrap();
+        }
+
+        // Rewrite the row the way older versions stored it: as an absolute path
+        {
+            let conn = Connection::open(original.join("index.sqlite3")).unwrap();
+            let value: Vec<u8> = conn
+                .query_row(
+                    "SELECT value FROM cache_index WHERE key = 'large'",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            let (mut file_info, _) = OptimizedStorage::decode_file_info(&value).unwrap();
+            assert!(file_info.path.is_relative());
+            file_info.path = original.join(&file_info.path);
+            let legacy = OptimizedStorage::encode_file_info(&file_info).unwrap();
+            conn.execute(
+                "UPDATE cache_index SET value = ?1 WHERE key = 'large'",
+                params![legacy],
+            )
+            .unwrap();
+        }
+
+        std::fs::rename(&original, &moved).unwrap();
+
+        let storage = OptimizedStorage::new(&moved).unwrap();
+        assert_eq!(storage.relocate().unwrap(), 1);
+        assert_eq!(storage.relocate().unwrap(), 0);
+        let entry = storage
+            .get("large")
+            .unwrap()
+            .expect("entry lost after move");
+        assert_eq!(entry.get_data(), Some(large.as_slice()));
+    }
+
+    #[test]
+    fn snapshot_is_a_point_in_time_copy() {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        let destination = temp_dir.path().join("snapshot");
+        let config = StorageConfig {
+            pack_threshold: 64 * 1024,
+            ..Default::default()
+        };
+        let storage = OptimizedStorage::with_config(&source, config).unwrap();
+        let values = [
+            ("inline", vec![1u8; 100]),
+            ("packed", vec![2u8; 40 * 1024]),
+            ("file", vec![3u8; 256 * 1024]),
+        ];
+        for (key, value) in &values {
+            let entry = CacheEntry::new_inline(key.to_string(), value.clone(), vec![], None);
+            storage.set(key, entry).unwrap();
+        }
+        storage.set_alias("alias", "file").unwrap();
+
+        let report = storage.snapshot(&destination).unwrap();
+        assert_eq!(report.entries, 3);
+        assert_eq!(report.files, 2);
+        assert!(storage.snapshot(&destination).is_err());
+
+        // Later writes leave the snapshot alone
+        let entry = CacheEntry::new_inline("file".into(), vec![4u8; 256 * 1024], vec![], None);
+        storage.set("file", entry).unwrap();
+        storage.delete("packed").unwrap();
+
+        let snapshot = OptimizedStorage::new(&destination).unwrap();
+        for (key, value) in &values {
+            let entry = snapshot
+                .get(key)
+                .unwrap()
+                .expect("entry missing from snapshot");
+            assert_eq!(entry.get_data(), Some(value.as_slice()));
+        }
+        assert_eq!(
+            snapshot.resolve_alias("alias").unwrap().as_deref(),
+            Some("file")
+        );
+    }
+
+    #[test]
+    fn reopening_moves_data_files_to_the_configured_fanout() {
+        let temp_dir = TempDir::new().unwrap();
+        let data_dir = temp_dir.path().join("data");
+        let flat = StorageConfig {
+            data_fanout: 0,
+            ..Default::default()
+        };
+        let value = |i: u8| vec![i; 64 * 1024];
+        let data_files = || {
+            let mut files = Vec::new();
+            let mut pending = vec![data_dir.clone()];
+            while let Some(directory) = pending.pop() {
+                for entry in std::fs::read_dir(directory).unwrap() {
+                    let path = entry.unwrap().path();
+                    if path.is_dir() {
+                        pending.push(path);
+                    } else if path.extension().is_some_and(|ext| ext == "dat") {
+                        files.push(path.strip_prefix(&data_dir).unwrap().to_path_buf());
+                    }
+                }
+            }
+            files
+        };
+
+        {
+            let storage = OptimizedStorage::with_config(temp_dir.path(), flat.clone()).unwrap();
+            for i in 0..8 {
+                let entry = CacheEntry::new_inline(String::new(), value(i), vec![], None);
+                storage.set(&format!("large:{}", i), entry).unwrap();
+            }
+        }
+        let versions: Vec<_> = {
+            let conn = Connection::open(temp_dir.path().join("index.sqlite3")).unwrap();
+            (0..8)
+                .map(|i| {
+                    conn.query_row(
+                        "SELECT generation FROM cache_index WHERE key = ?1",
+                        params![format!("large:{}", i)],
+                        |row| row.get::<_, i64>(0),
+                    )
+                    .unwrap() as u64
+                })
+                .collect()
+        };
+        assert!(data_files()
+            .iter()
+            .all(|path| path.components().count() == 1));
+        // Caches from before the layout marker are flat
+        std::fs::remove_file(data_dir.join(DATA_LAYOUT_FILE)).unwrap();
+
+        {
+            let storage = OptimizedStorage::new(temp_dir.path()).unwrap();
+            let files = data_files();
+            assert_eq!(files.len(), 8);
+            for path in &files {
+                let name = path.file_name().unwrap().to_str().unwrap();
+                let expected = Path::new(&name[..2]).join(&name[2..4]).join(name);
+                assert_eq!(path, &expected);
+            }
+            for i in 0..8 {
+                let key = format!("large:{}", i);
+                let entry = storage.get(&key).unwrap().expect("entry lost in migration");
+                assert_eq!(entry.get_data(), Some(value(i).as_slice()));
+                // Moving a file is not a rewrite
+                assert_eq!(storage.version(&key).unwrap(), Some(versions[i as usize]));
+            }
+        }
+
+        // Going back to a flat layout works the same way and drops the emptied directories
+        let storage = OptimizedStorage::with_config(temp_dir.path(), flat).unwrap();
+        assert!(data_files()
+            .iter()
+            .all(|path| path.components().count() == 1));
+        assert_eq!(std::fs::read_dir(&data_dir).unwrap().count(), 8 + 1);
+        let entry = storage.get("large:3").unwrap().unwrap();
+        assert_eq!(entry.get_data(), Some(value(3).as_slice()));
+    }
 }
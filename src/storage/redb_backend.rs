@@ -0,0 +1,501 @@
+//! Storage backend on the redb embedded key-value store
+//!
+//! Every entry, metadata and value alike, is a row in one B-tree file, so
+//! workloads with millions of tiny entries avoid a file per key and an SQL
+//! index round trip per lookup. redb holds an exclusive lock on its file:
+//! one process at a time may open a cache directory with this backend.
+
+use crate::error::{CacheError, CacheResult};
+use crate::lock_order::{LockLevel, OrderedRwLock};
+use crate::serialization::{CacheEntry, StorageMode};
+use crate::storage::{StorageBackend, VacuumReport};
+use redb::{
+    Database, Durability, MultimapTableDefinition, ReadableDatabase, ReadableTable,
+    TableDefinition, WriteTransaction,
+};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Database file inside the cache directory
+pub const REDB_FILE_NAME: &str = "cache.redb";
+
+/// Store sequence, created_at, expire_time, tags and value of one entry
+type EntryRow = (u64, u64, Option<u64>, Vec<&'static str>, &'static [u8]);
+
+const ENTRIES: TableDefinition<&str, EntryRow> = TableDefinition::new("entries");
+/// store sequence -> key, for store-order queries
+const STORE_ORDER: TableDefinition<u64, &str> = TableDefinition::new("store_order");
+const META: TableDefinition<&str, u64> = TableDefinition::new("meta");
+/// alias -> key, and the reverse mapping used to drop aliases with their key
+const ALIASES: TableDefinition<&str, &str> = TableDefinition::new("aliases");
+const KEY_ALIASES: MultimapTableDefinition<&str, &str> =
+    MultimapTableDefinition::new("key_aliases");
+const NEXT_SEQ: &str = "next_seq";
+
+/// Storage backed by a single redb database file
+pub struct RedbStorage {
+    directory: PathBuf,
+    /// `None` once closed; compaction needs the database exclusively
+    db: OrderedRwLock<Option<Database>>,
+    sync_writes: bool,
+    batch_size: usize,
+    commits: AtomicUsize,
+    /// Whether commits since the last durable one could still be lost
+    unsynced: AtomicBool,
+}
+
+impl RedbStorage {
+    /// Open the database with every commit made durable before it returns
+    pub fn new<P: AsRef<Path>>(directory: P) -> CacheResult<Self> {
+        Self::with_config(directory, true, 1)
+    }
+
+    /// Open the database in `directory`, creating it if needed
+    ///
+    /// Without `sync_writes`, only every `batch_size`-th commit (and the one
+    /// made on close) is fsynced; a crash can lose the commits in between but
+    /// never leaves the database inconsistent.
+    pub fn with_config<P: AsRef<Path>>(
+        directory: P,
+        sync_writes: bool,
+        batch_size: usize,
+    ) -> CacheResult<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        std::fs::create_dir_all(&directory).map_err(CacheError::Io)?;
+
+        let db = Database::create(directory.join(REDB_FILE_NAME))
+            .map_err(|e| Self::redb_error("Failed to open redb database", e))?;
+        let txn = db
+            .begin_write()
+            .map_err(|e| Self::redb_error("Failed to begin redb transaction", e))?;
+        for result in [
+            txn.open_table(ENTRIES).map(drop),
+            txn.open_table(STORE_ORDER).map(drop),
+            txn.open_table(META).map(drop),
+            txn.open_table(ALIASES).map(drop),
+            txn.open_multimap_table(KEY_ALIASES).map(drop),
+        ] {
+            result.map_err(|e| Self::redb_error("Failed to create redb tables", e))?;
+        }
+        txn.commit()
+            .map_err(|e| Self::redb_error("Failed to commit redb transaction", e))?;
+
+        Ok(Self {
+            directory,
+            db: OrderedRwLock::new(LockLevel::Index, Some(db)),
+            sync_writes,
+            batch_size: batch_size.max(1),
+            commits: AtomicUsize::new(0),
+            unsynced: AtomicBool::new(false),
+        })
+    }
+
+    fn redb_error(context: &str, error: impl std::fmt::Display) -> CacheError {
+        CacheError::Io(std::io::Error::other(format!("{}: {}", context, error)))
+    }
+
+    fn closed() -> CacheError {
+        CacheError::Io(std::io::Error::other("redb database is closed"))
+    }
+
+    fn now() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+
+    /// Run `apply` in a write transaction and commit it
+    fn write<T>(&self, apply: impl FnOnce(&WriteTransaction) -> CacheResult<T>) -> CacheResult<T> {
+        let db = self.db.read();
+        let db = db.as_ref().ok_or_else(Self::closed)?;
+        let mut txn = db
+            .begin_write()
+            .map_err(|e| Self::redb_error("Failed to begin redb transaction", e))?;
+
+        let commit = self.commits.fetch_add(1, Ordering::Relaxed) + 1;
+        let durable = self.sync_writes || commit.is_multiple_of(self.batch_size);
+        if !durable {
+            txn.set_durability(Durability::None)
+                .map_err(|e| Self::redb_error("Failed to relax redb durability", e))?;
+        }
+
+        let result = apply(&txn)?;
+        txn.commit()
+            .map_err(|e| Self::redb_error("Failed to commit redb transaction", e))?;
+        self.unsynced.store(!durable, Ordering::Relaxed);
+        Ok(result)
+    }
+
+    /// Run `read` against a read transaction's view of `table`
+    fn read<K, V, T>(
+        &self,
+        table: TableDefinition<K, V>,
+        read: impl FnOnce(&redb::ReadOnlyTable<K, V>) -> Result<T, redb::StorageError>,
+    ) -> CacheResult<T>
+    where
+        K: redb::Key + 'static,
+        V: redb::Value + 'static,
+    {
+        let db = self.db.read();
+        let db = db.as_ref().ok_or_else(Self::closed)?;
+        let txn = db
+            .begin_read()
+            .map_err(|e| Self::redb_error("Failed to begin redb read", e))?;
+        let table = txn
+            .open_table(table)
+            .map_err(|e| Self::redb_error("Failed to open redb table", e))?;
+        read(&table).map_err(|e| Self::redb_error("Failed to read redb table", e))
+    }
+
+    /// Remove `key` from both tables, returning whether it was present
+    fn remove_entry(txn: &WriteTransaction, key: &str) -> CacheResult<bool> {
+        let mut entries = txn
+            .open_table(ENTRIES)
+            .map_err(|e| Self::redb_error("Failed to open redb entries", e))?;
+        let removed = entries
+            .remove(key)
+            .map_err(|e| Self::redb_error("Failed to remove redb entry", e))?
+            .map(|old| old.value().0);
+        let Some(seq) = removed else {
+            return Ok(false);
+        };
+        txn.open_table(STORE_ORDER)
+            .and_then(|mut order| order.remove(seq).map(drop).map_err(Into::into))
+            .map_err(|e| Self::redb_error("Failed to remove redb store order", e))?;
+        Ok(true)
+    }
+
+    /// Remove every alias pointing at `key`
+    fn remove_aliases_of(txn: &WriteTransaction, key: &str) -> CacheResult<()> {
+        let mut key_aliases = txn
+            .open_multimap_table(KEY_ALIASES)
+            .map_err(|e| Self::redb_error("Failed to open redb aliases", e))?;
+        let aliases = key_aliases
+            .remove_all(key)
+            .map_err(|e| Self::redb_error("Failed to remove redb aliases", e))?
+            .map(|alias| alias.map(|alias| alias.value().to_string()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Self::redb_error("Failed to read redb alias", e))?;
+        let mut alias_table = txn
+            .open_table(ALIASES)
+            .map_err(|e| Self::redb_error("Failed to open redb aliases", e))?;
+        for alias in aliases {
+            alias_table
+                .remove(alias.as_str())
+                .map_err(|e| Self::redb_error("Failed to remove redb alias", e))?;
+        }
+        Ok(())
+    }
+
+    /// Store `data` under `key` at the end of store order
+    fn insert_entry(
+        txn: &WriteTransaction,
+        key: &str,
+        data: &[u8],
+        created_at: u64,
+        expire_time: Option<u64>,
+        tags: &[String],
+    ) -> CacheResult<()> {
+        Self::remove_entry(txn, key)?;
+
+        let mut meta = txn
+            .open_table(META)
+            .map_err(|e| Self::redb_error("Failed to open redb metadata", e))?;
+        let seq = meta
+            .get(NEXT_SEQ)
+            .map_err(|e| Self::redb_error("Failed to read redb sequence", e))?
+            .map_or(1, |seq| seq.value());
+        meta.insert(NEXT_SEQ, seq + 1)
+            .map_err(|e| Self::redb_error("Failed to advance redb sequence", e))?;
+
+        let tags: Vec<&str> = tags.iter().map(String::as_str).collect();
+        txn.open_table(ENTRIES)
+            .and_then(|mut entries| {
+                entries
+                    .insert(key, (seq, created_at, expire_time, tags, data))
+                    .map(drop)
+                    .map_err(Into::into)
+            })
+            .map_err(|e| Self::redb_error("Failed to insert redb entry", e))?;
+        txn.open_table(STORE_ORDER)
+            .and_then(|mut order| order.insert(seq, key).map(drop).map_err(Into::into))
+            .map_err(|e| Self::redb_error("Failed to insert redb store order", e))?;
+        Ok(())
+    }
+
+    /// Make commits left unsynced by the batch interval durable
+    fn sync(&self, db: &Database) -> CacheResult<()> {
+        if !self.unsynced.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        db.begin_write()
+            .map_err(|e| Self::redb_error("Failed to begin redb transaction", e))?
+            .commit()
+            .map_err(|e| Self::redb_error("Failed to commit redb transaction", e))?;
+        self.unsynced.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Make outstanding commits durable and release the database file lock
+    ///
+    /// Later operations fail; reopen the directory to use it again.
+    pub fn close_db(&self) {
+        if let Some(db) = self.db.write().take() {
+            if let Err(e) = self.sync(&db) {
+                tracing::error!("Failed to sync redb database on close: {}", e);
+            }
+        }
+    }
+}
+
+impl StorageBackend for RedbStorage {
+    fn get(&self, key: &str) -> CacheResult<Option<CacheEntry>> {
+        self.read(ENTRIES, |entries| {
+            Ok(entries.get(key)?.map(|row| {
+                let (_, created_at, expire_time, tags, data) = row.value();
+                let mut entry = CacheEntry::new_inline(
+                    key.to_string(),
+                    data.to_vec(),
+                    tags.into_iter().map(str::to_string).collect(),
+                    expire_time,
+                );
+                entry.created_at = created_at;
+                entry
+            }))
+        })
+    }
+
+    fn set(&self, key: &str, entry: CacheEntry) -> CacheResult<()> {
+        let data = match &entry.storage {
+            StorageMode::Inline(data) => data.clone(),
+            StorageMode::File(filename) => self.read_data_file(filename)?,
+        };
+        self.write(|txn| {
+            Self::insert_entry(
+                txn,
+                key,
+                &data,
+                entry.created_at,
+                entry.expire_time,
+                &entry.tags,
+            )
+        })
+    }
+
+    fn set_batch(&self, entries: Vec<(String, Vec<u8>)>) -> CacheResult<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let now = Self::now();
+        self.write(|txn| {
+            for (key, data) in &entries {
+                Self::insert_entry(txn, key, data, now, None, &[])?;
+            }
+            Ok(())
+        })
+    }
+
+    fn delete(&self, key: &str) -> CacheResult<bool> {
+        self.write(|txn| {
+            let removed = Self::remove_entry(txn, key)?;
+            if removed {
+                Self::remove_aliases_of(txn, key)?;
+            }
+            Ok(removed)
+        })
+    }
+
+    fn set_alias(&self, alias: &str, key: &str) -> CacheResult<()> {
+        self.write(|txn| {
+            let stored = txn
+                .open_table(ENTRIES)
+                .and_then(|entries| Ok(entries.get(key)?.is_some()))
+                .map_err(|e| Self::redb_error("Failed to read redb entry", e))?;
+            if !stored {
+                return Err(CacheError::KeyNotFound(key.to_string()));
+            }
+
+            let previous = txn
+                .open_table(ALIASES)
+                .and_then(|mut aliases| {
+                    Ok(aliases
+                        .insert(alias, key)?
+                        .map(|previous| previous.value().to_string()))
+                })
+                .map_err(|e| Self::redb_error("Failed to store redb alias", e))?;
+            let mut key_aliases = txn
+                .open_multimap_table(KEY_ALIASES)
+                .map_err(|e| Self::redb_error("Failed to open redb aliases", e))?;
+            if let Some(previous) = previous {
+                key_aliases
+                    .remove(previous.as_str(), alias)
+                    .map_err(|e| Self::redb_error("Failed to remove redb alias", e))?;
+            }
+            key_aliases
+                .insert(key, alias)
+                .map_err(|e| Self::redb_error("Failed to store redb alias", e))?;
+            Ok(())
+        })
+    }
+
+    fn resolve_alias(&self, alias: &str) -> CacheResult<Option<String>> {
+        self.read(ALIASES, |aliases| {
+            Ok(aliases.get(alias)?.map(|key| key.value().to_string()))
+        })
+    }
+
+    fn remove_alias(&self, alias: &str) -> CacheResult<bool> {
+        self.write(|txn| {
+            let key = txn
+                .open_table(ALIASES)
+                .and_then(|mut aliases| {
+                    Ok(aliases.remove(alias)?.map(|key| key.value().to_string()))
+                })
+                .map_err(|e| Self::redb_error("Failed to remove redb alias", e))?;
+            let Some(key) = key else {
+                return Ok(false);
+            };
+            txn.open_multimap_table(KEY_ALIASES)
+                .and_then(|mut key_aliases| Ok(key_aliases.remove(key.as_str(), alias)?))
+                .map_err(|e| Self::redb_error("Failed to remove redb alias", e))?;
+            Ok(true)
+        })
+    }
+
+    fn aliases(&self, key: &str) -> CacheResult<Vec<String>> {
+        let db = self.db.read();
+        let db = db.as_ref().ok_or_else(Self::closed)?;
+        let txn = db
+            .begin_read()
+            .map_err(|e| Self::redb_error("Failed to begin redb read", e))?;
+        let key_aliases = txn
+            .open_multimap_table(KEY_ALIASES)
+            .map_err(|e| Self::redb_error("Failed to open redb aliases", e))?;
+        key_aliases
+            .get(key)
+            .and_then(|aliases| {
+                aliases
+                    .map(|alias| alias.map(|alias| alias.value().to_string()))
+                    .collect()
+            })
+            .map_err(|e| Self::redb_error("Failed to read redb aliases", e))
+    }
+
+    /// The entry's store sequence, which is never reused
+    fn version(&self, key: &str) -> CacheResult<Option<u64>> {
+        self.read(ENTRIES, |entries| {
+            Ok(entries.get(key)?.map(|row| row.value().0))
+        })
+    }
+
+    fn exists(&self, key: &str) -> CacheResult<bool> {
+        self.read(ENTRIES, |entries| Ok(entries.get(key)?.is_some()))
+    }
+
+    fn keys(&self) -> CacheResult<Vec<String>> {
+        self.read(ENTRIES, |entries| {
+            entries
+                .iter()?
+                .map(|row| row.map(|(key, _)| key.value().to_string()))
+                .collect()
+        })
+    }
+
+    fn peek_key(&self, last: bool) -> CacheResult<Option<String>> {
+        self.read(STORE_ORDER, |order| {
+            let row = if last { order.last()? } else { order.first()? };
+            Ok(row.map(|(_, key)| key.value().to_string()))
+        })
+    }
+
+    fn key_page(
+        &self,
+        cursor: Option<i64>,
+        reverse: bool,
+        limit: usize,
+    ) -> CacheResult<Vec<(i64, String)>> {
+        self.read(STORE_ORDER, |order| {
+            let rows = match cursor {
+                None => order.iter()?,
+                Some(cursor) if reverse => order.range(..cursor.max(0) as u64)?,
+                Some(cursor) => order.range(cursor.max(0) as u64 + 1..)?,
+            };
+            let to_pair = |row: Result<_, redb::StorageError>| {
+                row.map(
+                    |(seq, key): (redb::AccessGuard<u64>, redb::AccessGuard<&str>)| {
+                        (seq.value() as i64, key.value().to_string())
+                    },
+                )
+            };
+            if reverse {
+                rows.rev().take(limit).map(to_pair).collect()
+            } else {
+                rows.take(limit).map(to_pair).collect()
+            }
+        })
+    }
+
+    fn peek_key_in_range(&self, start: &str, end: &str, last: bool) -> CacheResult<Option<String>> {
+        if start >= end {
+            return Ok(None);
+        }
+        self.read(ENTRIES, |entries| {
+            let mut rows = entries.range(start..end)?;
+            let row = if last { rows.next_back() } else { rows.next() };
+            row.transpose()
+                .map(|row| row.map(|(key, _)| key.value().to_string()))
+        })
+    }
+
+    fn clear(&self) -> CacheResult<()> {
+        self.write(|txn| {
+            txn.open_table(ENTRIES)
+                .and_then(|mut entries| entries.retain(|_, _| false).map_err(Into::into))
+                .map_err(|e| Self::redb_error("Failed to clear redb entries", e))?;
+            txn.open_table(STORE_ORDER)
+                .and_then(|mut order| order.retain(|_, _| false).map_err(Into::into))
+                .map_err(|e| Self::redb_error("Failed to clear redb store order", e))?;
+            txn.open_table(ALIASES)
+                .and_then(|mut aliases| aliases.retain(|_, _| false).map_err(Into::into))
+                .map_err(|e| Self::redb_error("Failed to clear redb aliases", e))?;
+            txn.delete_multimap_table(KEY_ALIASES)
+                .and_then(|_| txn.open_multimap_table(KEY_ALIASES).map(drop))
+                .map_err(|e| Self::redb_error("Failed to clear redb aliases", e))
+        })
+    }
+
+    fn vacuum(&self) -> CacheResult<VacuumReport> {
+        let mut db = self.db.write();
+        let db = db.as_mut().ok_or_else(Self::closed)?;
+        // Compaction only runs once no commit is waiting to become durable
+        self.sync(db)?;
+        db.compact()
+            .map_err(|e| Self::redb_error("Failed to compact redb database", e))?;
+        Ok(VacuumReport::default())
+    }
+
+    fn generate_filename(&self, key: &str) -> String {
+        format!("{}.dat", blake3::hash(key.as_bytes()).to_hex())
+    }
+
+    fn write_data_file(&self, filename: &str, data: &[u8]) -> CacheResult<()> {
+        let data_dir = self.directory.join("data");
+        std::fs::create_dir_all(&data_dir).map_err(CacheError::Io)?;
+        std::fs::write(data_dir.join(filename), data).map_err(CacheError::Io)
+    }
+
+    fn read_data_file(&self, filename: &str) -> CacheResult<Vec<u8>> {
+        std::fs::read(self.directory.join("data").join(filename)).map_err(CacheError::Io)
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+impl Drop for RedbStorage {
+    fn drop(&mut self) {
+        self.close_db();
+    }
+}
@@ -0,0 +1,266 @@
+//! Remote cold tier behind [`OptimizedStorage`](super::OptimizedStorage)
+//!
+//! Entries the cache evicts are pushed to an object store instead of being
+//! dropped, and reads that miss every local tier fall through to it, so the
+//! cache holds far more than its disk. Objects are named after the hash of
+//! their key, like data files, and hold the same header-framed bytes, so a
+//! fetched object is checked against its key and checksum before it is used.
+//!
+//! [`DirectoryTier`] keeps objects in a directory, such as a mounted share;
+//! with the `s3` feature, [`S3Tier`] keeps them in an S3-compatible bucket.
+//!
+//! Evictions do not wait on the store: [`RemoteUploads`] pushes evicted
+//! objects from a thread of its own, and reads are served from an object
+//! still waiting to go.
+
+use crate::error::{CacheError, CacheResult, ConfigIssue};
+use crate::fork;
+use bytes::Bytes;
+use parking_lot::{Condvar, Mutex};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Weak};
+
+#[cfg(feature = "s3")]
+mod s3;
+#[cfg(feature = "s3")]
+pub use s3::S3Tier;
+
+/// Object store the cache pushes evicted entries to
+///
+/// Implementations are called from the cache's own threads and should
+/// retry transient failures themselves; an error fails the read or delete
+/// that needed the store, and an evicted entry that fails to upload is lost.
+pub trait RemoteTier: Send + Sync + std::fmt::Debug {
+    /// Store `data` as `name`, replacing any object there
+    fn put(&self, name: &str, data: &[u8]) -> CacheResult<()>;
+    /// Contents of `name`, if the store has it
+    fn get(&self, name: &str) -> CacheResult<Option<Vec<u8>>>;
+    fn exists(&self, name: &str) -> CacheResult<bool>;
+    /// Remove `name`; removing an object that is not there is not an error
+    fn delete(&self, name: &str) -> CacheResult<()>;
+    /// Remove every object of this tier
+    fn clear(&self) -> CacheResult<()>;
+}
+
+/// Remote tier for a location given as a string
+///
+/// `s3://bucket/prefix` opens an [`S3Tier`] configured from the standard
+/// `AWS_*` environment variables and needs the `s3` feature; anything else
+/// is taken as a directory for a [`DirectoryTier`].
+pub fn open_remote_tier(location: &str) -> CacheResult<Arc<dyn RemoteTier>> {
+    if let Some(bucket_and_prefix) = location.strip_prefix("s3://") {
+        #[cfg(feature = "s3")]
+        return Ok(Arc::new(S3Tier::from_env(bucket_and_prefix)?));
+        #[cfg(not(feature = "s3"))]
+        {
+            let _ = bucket_and_prefix;
+            return Err(CacheError::Config(ConfigIssue::new(
+                "remote_tier",
+                "This build of diskcache_rs has no S3 support",
+                "Build with the \"s3\" feature, or use a directory as the remote tier",
+            )));
+        }
+    }
+    if location.contains("://") {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "remote_tier",
+            format!("Unsupported remote tier {:?}", location),
+            "Use s3://bucket/prefix or a directory path",
+        )));
+    }
+    Ok(Arc::new(DirectoryTier::new(location)?))
+}
+
+/// Objects of evicted entries waiting to be pushed to a remote tier
+#[derive(Default)]
+pub struct RemoteUploads {
+    queued: Mutex<Uploads>,
+    /// Signalled when an upload is done
+    uploaded: Condvar,
+}
+
+#[derive(Default)]
+struct Uploads {
+    order: VecDeque<String>,
+    objects: HashMap<String, Bytes>,
+    /// Object being uploaded, still served to readers
+    uploading: Option<(String, Bytes)>,
+    /// Fork generation of the running thread, if one runs
+    running: Option<u64>,
+}
+
+impl RemoteUploads {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queue `object` to be stored as `name` in `remote`, replacing what is
+    /// queued under that name, and start a thread to upload it unless one
+    /// runs already
+    pub fn push(
+        self: &Arc<Self>,
+        remote: &Arc<dyn RemoteTier>,
+        name: String,
+        object: Vec<u8>,
+    ) -> CacheResult<()> {
+        let mut queued = self.queued.lock();
+        if queued
+            .objects
+            .insert(name.clone(), Bytes::from(object))
+            .is_none()
+        {
+            queued.order.push_back(name);
+        }
+        let generation = fork::generation();
+        if queued.running == Some(generation) {
+            return Ok(());
+        }
+        let uploads = Arc::downgrade(self);
+        let remote = remote.clone();
+        std::thread::Builder::new()
+            .name("cache-upload".into())
+            .spawn(move || Self::run(uploads, remote))?;
+        queued.running = Some(generation);
+        Ok(())
+    }
+
+    fn run(uploads: Weak<Self>, remote: Arc<dyn RemoteTier>) {
+        loop {
+            let Some(uploads) = uploads.upgrade() else {
+                return;
+            };
+            let (name, object) = {
+                let mut queued = uploads.queued.lock();
+                let Some(name) = queued.order.pop_front() else {
+                    queued.running = None;
+                    return;
+                };
+                let object = queued
+                    .objects
+                    .remove(&name)
+                    .expect("queued names have an object");
+                queued.uploading = Some((name.clone(), object.clone()));
+                (name, object)
+            };
+            if let Err(e) = remote.put(&name, &object) {
+                tracing::warn!("Failed to push {} to the remote tier: {}", name, e);
+            }
+            uploads.queued.lock().uploading = None;
+            uploads.uploaded.notify_all();
+        }
+    }
+
+    /// The object waiting to be stored as `name`, if any
+    pub fn get(&self, name: &str) -> Option<Bytes> {
+        let queued = self.queued.lock();
+        match &queued.uploading {
+            Some((uploading, object)) if uploading == name => Some(object.clone()),
+            _ => queued.objects.get(name).cloned(),
+        }
+    }
+
+    /// Drop the object waiting to be stored as `name`, waiting until its
+    /// upload is done if it started, so the store can then remove it for
+    /// good; returns whether there was one
+    pub fn cancel(&self, name: &str) -> bool {
+        let mut queued = self.queued.lock();
+        let mut found = false;
+        while queued
+            .uploading
+            .as_ref()
+            .is_some_and(|(uploading, _)| uploading == name)
+        {
+            found = true;
+            self.uploaded.wait(&mut queued);
+        }
+        if queued.objects.remove(name).is_some() {
+            queued.order.retain(|queued| queued != name);
+            found = true;
+        }
+        found
+    }
+
+    /// Drop every waiting object, waiting for the upload in progress
+    pub fn clear(&self) {
+        let mut queued = self.queued.lock();
+        queued.order.clear();
+        queued.objects.clear();
+        while queued.uploading.is_some() {
+            self.uploaded.wait(&mut queued);
+        }
+    }
+
+    /// Wait until every waiting object is uploaded, uploading them here if
+    /// no thread runs to, as in a forked child
+    pub fn flush(&self, remote: &dyn RemoteTier) {
+        let mut queued = self.queued.lock();
+        if queued.running != Some(fork::generation()) {
+            while let Some(name) = queued.order.pop_front() {
+                if let Some(object) = queued.objects.remove(&name) {
+                    if let Err(e) = remote.put(&name, &object) {
+                        tracing::warn!("Failed to push {} to the remote tier: {}", name, e);
+                    }
+                }
+            }
+            return;
+        }
+        while !queued.order.is_empty() || queued.uploading.is_some() {
+            self.uploaded.wait(&mut queued);
+        }
+    }
+}
+
+/// Remote tier keeping each object as a file in one directory
+#[derive(Debug)]
+pub struct DirectoryTier {
+    root: PathBuf,
+}
+
+impl DirectoryTier {
+    pub fn new(root: impl Into<PathBuf>) -> CacheResult<Self> {
+        let root = root.into();
+        std::fs::create_dir_all(&root)?;
+        Ok(Self { root })
+    }
+}
+
+impl RemoteTier for DirectoryTier {
+    fn put(&self, name: &str, data: &[u8]) -> CacheResult<()> {
+        // Readers never see a partly written object
+        let mut file = tempfile::NamedTempFile::new_in(&self.root)?;
+        std::io::Write::write_all(&mut file, data)?;
+        file.persist(self.root.join(name))
+            .map_err(|e| CacheError::Io(e.error))?;
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> CacheResult<Option<Vec<u8>>> {
+        match std::fs::read(self.root.join(name)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(CacheError::Io(e)),
+        }
+    }
+
+    fn exists(&self, name: &str) -> CacheResult<bool> {
+        Ok(self.root.join(name).try_exists()?)
+    }
+
+    fn delete(&self, name: &str) -> CacheResult<()> {
+        match std::fs::remove_file(self.root.join(name)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(CacheError::Io(e)),
+            _ => Ok(()),
+        }
+    }
+
+    fn clear(&self) -> CacheResult<()> {
+        for entry in std::fs::read_dir(&self.root)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                self.delete(&entry.file_name().to_string_lossy())?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,653 @@
+//! Storage backend on one preallocated ring file
+//!
+//! The cache directory holds a single `ring.dat` of a fixed size, set by
+//! `ring_capacity_bytes` when it is created. Entries are appended as records
+//! at the write head; once the head reaches the end it wraps around, and new
+//! records overwrite the oldest ones, whose entries are dropped. Nothing else
+//! is ever created or deleted, so the cache never outgrows its allocation and
+//! causes no file churn, which suits telemetry and event buffers.
+//!
+//! Record offsets are indexed in memory and rebuilt on open by reading the
+//! ring from the head (the oldest record) around to the head again. Deletes
+//! append a small tombstone record so they survive a reopen. The ring holds
+//! an exclusive lock on its file: one process at a time may open it.
+
+use crate::error::{CacheError, CacheResult, ConfigIssue};
+use crate::lock_order::{LockLevel, OrderedMutex};
+use crate::serialization::{CacheEntry, StorageMode};
+use crate::storage::data_file::payload_checksum;
+use crate::storage::{Footprint, StorageBackend, VacuumReport};
+use fs4::fs_std::FileExt;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Ring file inside the cache directory
+pub const RING_FILE_NAME: &str = "ring.dat";
+/// Smallest ring that holds a handful of records
+pub const MIN_RING_CAPACITY: u64 = 4096;
+
+const RING_MAGIC: &[u8; 8] = b"DCRSRING";
+const RING_VERSION: u32 = 1;
+/// Bytes before the first record: magic, version, capacity, head and next sequence
+const HEADER_LEN: u64 = 64;
+/// File offset of the head, followed by the next sequence number
+const HEAD_FIELD: u64 = 24;
+/// Record length, metadata length, value length and checksum
+const RECORD_HEADER_LEN: u64 = 16;
+/// In-memory cost of one entry in the three maps, besides its key
+const ENTRY_OVERHEAD: u64 = 160;
+
+#[derive(Debug, bincode::Encode, bincode::Decode)]
+enum RecordKind {
+    Put {
+        created_at: u64,
+        expire_time: Option<u64>,
+        tags: Vec<String>,
+    },
+    Delete,
+}
+
+#[derive(Debug, bincode::Encode, bincode::Decode)]
+struct RecordMeta {
+    key: String,
+    seq: u64,
+    kind: RecordKind,
+}
+
+/// A record read back from the ring
+struct Record {
+    /// Bytes the record takes in the ring, padding included
+    len: u64,
+    meta: RecordMeta,
+    value: Vec<u8>,
+}
+
+/// What a read at an offset found
+enum Found {
+    Record(Record),
+    /// A filler left where a shorter record overwrote a longer one
+    Filler(u64),
+    /// Nothing more until the end of the ring
+    End,
+}
+
+struct Slot {
+    offset: u64,
+    seq: u64,
+}
+
+struct Ring {
+    file: File,
+    capacity: u64,
+    /// Offset the next record is written at; the oldest record starts here
+    head: u64,
+    next_seq: u64,
+    /// Ordered by key, for range queries
+    by_key: BTreeMap<String, Slot>,
+    /// store sequence -> key
+    order: BTreeMap<u64, String>,
+    /// offset of each live record -> key, to find the entries a write overwrites
+    by_offset: BTreeMap<u64, String>,
+}
+
+impl Ring {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(HEADER_LEN + offset))?;
+        self.file.read_exact(buf)
+    }
+
+    fn write_at(&mut self, offset: u64, buf: &[u8]) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(HEADER_LEN + offset))?;
+        self.file.write_all(buf)
+    }
+
+    /// Length field of the record at `offset`; 0 when the ring ends there
+    fn record_len_at(&mut self, offset: u64) -> std::io::Result<u64> {
+        if offset + 4 > self.capacity {
+            return Ok(0);
+        }
+        let mut len = [0; 4];
+        self.read_at(offset, &mut len)?;
+        let len = u32::from_le_bytes(len) as u64;
+        // A length that runs past the end is not a record boundary
+        if len < RECORD_HEADER_LEN || offset + len > self.capacity {
+            return Ok(0);
+        }
+        Ok(len)
+    }
+
+    /// Read the record at `offset`, failing with [`CacheError::Corruption`]
+    /// if it does not match its checksum
+    fn read_record(&mut self, offset: u64) -> CacheResult<Found> {
+        let len = self.record_len_at(offset)?;
+        if len == 0 {
+            return Ok(Found::End);
+        }
+        let mut header = [0; RECORD_HEADER_LEN as usize];
+        self.read_at(offset, &mut header)?;
+        let field = |i: usize| u32::from_le_bytes(header[4 * i..4 * i + 4].try_into().unwrap());
+        let (meta_len, value_len, checksum) = (field(1) as u64, field(2) as u64, field(3));
+        if meta_len == 0 {
+            return Ok(Found::Filler(len));
+        }
+        if RECORD_HEADER_LEN + meta_len + value_len > len {
+            return Err(CacheError::Corruption(format!(
+                "Ring record at {} overruns its length",
+                offset
+            )));
+        }
+
+        let mut body = vec![0; (meta_len + value_len) as usize];
+        self.read_at(offset + RECORD_HEADER_LEN, &mut body)?;
+        if payload_checksum(&body) != checksum {
+            return Err(CacheError::Corruption(format!(
+                "Ring record at {} fails its checksum",
+                offset
+            )));
+        }
+        let value = body.split_off(meta_len as usize);
+        let (meta, _) = bincode::decode_from_slice(&body, bincode::config::standard())
+            .map_err(|e| CacheError::Deserialization(format!("ring record: {}", e)))?;
+        Ok(Found::Record(Record { len, meta, value }))
+    }
+
+    /// Drop the entries whose records start in `range`
+    fn evict(&mut self, range: Range<u64>) {
+        let evicted: Vec<u64> = self.by_offset.range(range).map(|(&o, _)| o).collect();
+        for offset in evicted {
+            if let Some(key) = self.by_offset.remove(&offset) {
+                if let Some(slot) = self.by_key.remove(&key) {
+                    self.order.remove(&slot.seq);
+                }
+            }
+        }
+    }
+
+    /// Drop `key`'s entry, leaving its record as dead bytes
+    fn forget(&mut self, key: &str) -> bool {
+        let Some(slot) = self.by_key.remove(key) else {
+            return false;
+        };
+        self.order.remove(&slot.seq);
+        self.by_offset.remove(&slot.offset);
+        true
+    }
+
+    fn remember(&mut self, key: &str, offset: u64, seq: u64) {
+        self.forget(key);
+        self.by_key.insert(key.to_string(), Slot { offset, seq });
+        self.order.insert(seq, key.to_string());
+        self.by_offset.insert(offset, key.to_string());
+    }
+
+    /// Write a record at the head, overwriting the oldest records as needed,
+    /// and return its offset
+    fn append(&mut self, key: &str, kind: RecordKind, value: &[u8]) -> CacheResult<(u64, u64)> {
+        let seq = self.next_seq;
+        let meta = bincode::encode_to_vec(
+            RecordMeta {
+                key: key.to_string(),
+                seq,
+                kind,
+            },
+            bincode::config::standard(),
+        )
+        .map_err(|e| CacheError::Serialization(format!("ring record: {}", e)))?;
+        let need = RECORD_HEADER_LEN + meta.len() as u64 + value.len() as u64;
+        if need > self.capacity || need > u32::MAX as u64 {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "ring_capacity_bytes",
+                format!(
+                    "An entry of {} bytes does not fit in a ring of {} bytes",
+                    need, self.capacity
+                ),
+                "Raise ring_capacity_bytes for a new cache, or keep values this large in another cache",
+            )));
+        }
+
+        let mut offset = self.head;
+        if offset + need > self.capacity {
+            // The rest of the ring is too short: abandon it and start over at the front
+            self.evict(offset..self.capacity);
+            if offset + 4 <= self.capacity {
+                self.write_at(offset, &0u32.to_le_bytes())?;
+            }
+            offset = 0;
+        }
+
+        // Whole records are overwritten, so find where the last one touched ends
+        let mut covered = 0;
+        let mut reached_end = false;
+        while covered < need {
+            let len = self.record_len_at(offset + covered)?;
+            if len == 0 {
+                reached_end = true;
+                break;
+            }
+            covered += len;
+        }
+        let overwritten_end = if reached_end {
+            self.capacity
+        } else {
+            offset + covered
+        };
+        self.evict(offset..overwritten_end);
+
+        // A leftover too short for a filler is absorbed as padding
+        let len = if !reached_end && covered - need < RECORD_HEADER_LEN {
+            covered
+        } else {
+            need
+        };
+        let mut record = Vec::with_capacity(len as usize);
+        record.extend_from_slice(&(len as u32).to_le_bytes());
+        record.extend_from_slice(&(meta.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        record.extend_from_slice(&[0; 4]);
+        record.extend_from_slice(&meta);
+        record.extend_from_slice(value);
+        let checksum = payload_checksum(&record[RECORD_HEADER_LEN as usize..]);
+        record[12..16].copy_from_slice(&checksum.to_le_bytes());
+        record.resize(len as usize, 0);
+
+        if reached_end {
+            if offset + len + 4 <= self.capacity {
+                record.extend_from_slice(&0u32.to_le_bytes());
+            }
+        } else if covered > len {
+            let mut filler = [0; RECORD_HEADER_LEN as usize];
+            filler[..4].copy_from_slice(&((covered - len) as u32).to_le_bytes());
+            record.extend_from_slice(&filler);
+        }
+        self.write_at(offset, &record)?;
+
+        self.head = offset + len;
+        self.next_seq += 1;
+        self.write_head()?;
+        Ok((offset, seq))
+    }
+
+    fn put(
+        &mut self,
+        key: &str,
+        value: &[u8],
+        created_at: u64,
+        expire_time: Option<u64>,
+        tags: Vec<String>,
+    ) -> CacheResult<()> {
+        let kind = RecordKind::Put {
+            created_at,
+            expire_time,
+            tags,
+        };
+        let (offset, seq) = self.append(key, kind, value)?;
+        self.remember(key, offset, seq);
+        Ok(())
+    }
+
+    fn write_head(&mut self) -> std::io::Result<()> {
+        let mut fields = [0; 16];
+        fields[..8].copy_from_slice(&self.head.to_le_bytes());
+        fields[8..].copy_from_slice(&self.next_seq.to_le_bytes());
+        self.file.seek(SeekFrom::Start(HEAD_FIELD))?;
+        self.file.write_all(&fields)
+    }
+
+    /// Rebuild the index from the records between the head and the end of
+    /// the ring, then from the front up to the head
+    ///
+    /// A damaged record cannot be stepped over, so reading that part of the
+    /// ring stops there; only the records past it are lost.
+    fn scan(&mut self) -> CacheResult<()> {
+        let mut records = Vec::new();
+        for (start, end) in [(self.head, self.capacity), (0, self.head)] {
+            let mut offset = start;
+            while offset < end {
+                match self.read_record(offset) {
+                    Ok(Found::Record(record)) => {
+                        let len = record.len;
+                        records.push((offset, record.meta));
+                        offset += len;
+                    }
+                    Ok(Found::Filler(len)) => offset += len,
+                    Ok(Found::End) => break,
+                    Err(e) => {
+                        tracing::warn!("Stopped reading the ring at offset {}: {}", offset, e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        records.sort_by_key(|(_, meta)| meta.seq);
+        for (offset, meta) in records {
+            self.next_seq = self.next_seq.max(meta.seq + 1);
+            match meta.kind {
+                RecordKind::Put { .. } => self.remember(&meta.key, offset, meta.seq),
+                RecordKind::Delete => {
+                    self.forget(&meta.key);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Storage in one fixed-size ring file that overwrites its oldest entries
+pub struct RingStorage {
+    directory: PathBuf,
+    sync_writes: bool,
+    /// `None` once closed, which releases the file lock
+    ring: OrderedMutex<Option<Ring>>,
+}
+
+impl RingStorage {
+    /// Open the ring in `directory`, creating it with `capacity` bytes for
+    /// records if it does not exist yet
+    ///
+    /// An existing ring keeps the capacity it was created with; asking for
+    /// another one is refused rather than silently ignored. With
+    /// `sync_writes`, every write is fsynced before it returns.
+    pub fn with_config<P: AsRef<Path>>(
+        directory: P,
+        capacity: u64,
+        sync_writes: bool,
+    ) -> CacheResult<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        std::fs::create_dir_all(&directory)?;
+        let path = directory.join(RING_FILE_NAME);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        if !FileExt::try_lock_exclusive(&file).unwrap_or(false) {
+            return Err(CacheError::Lock(format!(
+                "{:?} is open in another process",
+                path
+            )));
+        }
+
+        let mut ring = Ring {
+            file,
+            capacity,
+            head: 0,
+            next_seq: 1,
+            by_key: BTreeMap::new(),
+            order: BTreeMap::new(),
+            by_offset: BTreeMap::new(),
+        };
+        if ring.file.metadata()?.len() == 0 {
+            let mut header = [0; HEADER_LEN as usize];
+            header[..8].copy_from_slice(RING_MAGIC);
+            header[8..12].copy_from_slice(&RING_VERSION.to_le_bytes());
+            header[16..24].copy_from_slice(&capacity.to_le_bytes());
+            ring.file.write_all(&header)?;
+            ring.write_head()?;
+            // Allocated up front, so a full disk shows up now rather than mid-write
+            ring.file.set_len(HEADER_LEN + capacity)?;
+            ring.file.sync_all()?;
+        } else {
+            let mut header = [0; HEADER_LEN as usize];
+            ring.file.seek(SeekFrom::Start(0))?;
+            ring.file
+                .read_exact(&mut header)
+                .map_err(|_| not_a_ring(&path))?;
+            let field = |range: Range<usize>| u64::from_le_bytes(header[range].try_into().unwrap());
+            if &header[..8] != RING_MAGIC
+                || u32::from_le_bytes(header[8..12].try_into().unwrap()) != RING_VERSION
+            {
+                return Err(not_a_ring(&path));
+            }
+            let existing = field(16..24);
+            if existing != capacity {
+                return Err(CacheError::Config(ConfigIssue::new(
+                    "ring_capacity_bytes",
+                    format!(
+                        "The ring in {:?} was created with {} bytes, not {}",
+                        directory, existing, capacity
+                    ),
+                    format!(
+                        "Pass ring_capacity_bytes={} to open it, or use a new directory",
+                        existing
+                    ),
+                )));
+            }
+            ring.head = field(24..32).min(capacity);
+            ring.next_seq = field(32..40).max(1);
+            ring.scan()?;
+        }
+
+        Ok(Self {
+            directory,
+            sync_writes,
+            ring: OrderedMutex::new(LockLevel::Index, Some(ring)),
+        })
+    }
+
+    /// Bytes the ring holds records in
+    pub fn capacity(&self) -> CacheResult<u64> {
+        self.with_ring(|ring| Ok(ring.capacity))
+    }
+
+    /// Sync the ring and release its file, so another handle can open it
+    pub fn close_db(&self) {
+        if let Some(ring) = self.ring.lock().take() {
+            if let Err(e) = ring.file.sync_data() {
+                tracing::error!("Failed to sync ring file on close: {}", e);
+            }
+        }
+    }
+
+    fn with_ring<T>(&self, f: impl FnOnce(&mut Ring) -> CacheResult<T>) -> CacheResult<T> {
+        let mut ring = self.ring.lock();
+        let ring = ring
+            .as_mut()
+            .ok_or_else(|| CacheError::Io(std::io::Error::other("ring file is closed")))?;
+        f(ring)
+    }
+
+    /// Run a write, then fsync it if every write must be durable
+    fn write<T>(&self, f: impl FnOnce(&mut Ring) -> CacheResult<T>) -> CacheResult<T> {
+        self.with_ring(|ring| {
+            let result = f(ring)?;
+            if self.sync_writes {
+                ring.file.sync_data()?;
+            }
+            Ok(result)
+        })
+    }
+}
+
+fn not_a_ring(path: &Path) -> CacheError {
+    CacheError::Corruption(format!("{:?} is not a cache ring file", path))
+}
+
+impl StorageBackend for RingStorage {
+    fn get(&self, key: &str) -> CacheResult<Option<CacheEntry>> {
+        self.with_ring(|ring| {
+            let Some(offset) = ring.by_key.get(key).map(|slot| slot.offset) else {
+                return Ok(None);
+            };
+            let record = match ring.read_record(offset) {
+                Ok(Found::Record(record)) if record.meta.key == key => record,
+                Ok(_) | Err(CacheError::Corruption(_)) => {
+                    ring.forget(key);
+                    return Err(CacheError::Corrupted(key.to_string()));
+                }
+                Err(e) => return Err(e),
+            };
+            let RecordKind::Put {
+                created_at,
+                expire_time,
+                tags,
+            } = record.meta.kind
+            else {
+                ring.forget(key);
+                return Ok(None);
+            };
+            let mut entry =
+                CacheEntry::new_inline(key.to_string(), record.value, tags, expire_time);
+            entry.created_at = created_at;
+            Ok(Some(entry))
+        })
+    }
+
+    fn set(&self, key: &str, entry: CacheEntry) -> CacheResult<()> {
+        let data = match &entry.storage {
+            StorageMode::Inline(data) => data.clone(),
+            StorageMode::File(filename) => self.read_data_file(filename)?,
+        };
+        self.write(|ring| ring.put(key, &data, entry.created_at, entry.expire_time, entry.tags))
+    }
+
+    fn set_batch(&self, entries: Vec<(String, Vec<u8>)>) -> CacheResult<()> {
+        let now = crate::utils::current_timestamp();
+        self.write(|ring| {
+            for (key, data) in &entries {
+                ring.put(key, data, now, None, Vec::new())?;
+            }
+            Ok(())
+        })
+    }
+
+    fn delete(&self, key: &str) -> CacheResult<bool> {
+        self.write(|ring| {
+            if !ring.by_key.contains_key(key) {
+                return Ok(false);
+            }
+            ring.append(key, RecordKind::Delete, &[])?;
+            // The tombstone may have overwritten the record itself
+            ring.forget(key);
+            Ok(true)
+        })
+    }
+
+    fn exists(&self, key: &str) -> CacheResult<bool> {
+        self.with_ring(|ring| Ok(ring.by_key.contains_key(key)))
+    }
+
+    /// The entry's store sequence, which is never reused
+    fn version(&self, key: &str) -> CacheResult<Option<u64>> {
+        self.with_ring(|ring| Ok(ring.by_key.get(key).map(|slot| slot.seq)))
+    }
+
+    fn keys(&self) -> CacheResult<Vec<String>> {
+        self.with_ring(|ring| Ok(ring.by_key.keys().cloned().collect()))
+    }
+
+    fn peek_key(&self, last: bool) -> CacheResult<Option<String>> {
+        self.with_ring(|ring| {
+            let row = if last {
+                ring.order.last_key_value()
+            } else {
+                ring.order.first_key_value()
+            };
+            Ok(row.map(|(_, key)| key.clone()))
+        })
+    }
+
+    fn key_page(
+        &self,
+        cursor: Option<i64>,
+        reverse: bool,
+        limit: usize,
+    ) -> CacheResult<Vec<(i64, String)>> {
+        self.with_ring(|ring| {
+            let to_pair = |(seq, key): (&u64, &String)| (*seq as i64, key.clone());
+            let page = match (cursor, reverse) {
+                (None, false) => ring.order.iter().take(limit).map(to_pair).collect(),
+                (None, true) => ring.order.iter().rev().take(limit).map(to_pair).collect(),
+                (Some(cursor), false) => ring
+                    .order
+                    .range(cursor.max(0) as u64 + 1..)
+                    .take(limit)
+                    .map(to_pair)
+                    .collect(),
+                (Some(cursor), true) => ring
+                    .order
+                    .range(..cursor.max(0) as u64)
+                    .rev()
+                    .take(limit)
+                    .map(to_pair)
+                    .collect(),
+            };
+            Ok(page)
+        })
+    }
+
+    fn peek_key_in_range(&self, start: &str, end: &str, last: bool) -> CacheResult<Option<String>> {
+        if start >= end {
+            return Ok(None);
+        }
+        self.with_ring(|ring| {
+            let mut keys = ring
+                .by_key
+                .range::<str, _>((
+                    std::ops::Bound::Included(start),
+                    std::ops::Bound::Excluded(end),
+                ))
+                .map(|(key, _)| key);
+            let key = if last { keys.next_back() } else { keys.next() };
+            Ok(key.cloned())
+        })
+    }
+
+    fn clear(&self) -> CacheResult<()> {
+        self.write(|ring| {
+            // Records past an end marker are never read, so one at the front empties the ring
+            ring.write_at(0, &0u32.to_le_bytes())?;
+            ring.head = 0;
+            ring.write_head()?;
+            ring.by_key.clear();
+            ring.order.clear();
+            ring.by_offset.clear();
+            Ok(())
+        })
+    }
+
+    fn vacuum(&self) -> CacheResult<VacuumReport> {
+        self.with_ring(|ring| Ok(ring.file.sync_data()?))?;
+        Ok(VacuumReport::default())
+    }
+
+    fn generate_filename(&self, key: &str) -> String {
+        format!("{}.dat", blake3::hash(key.as_bytes()).to_hex())
+    }
+
+    fn write_data_file(&self, filename: &str, data: &[u8]) -> CacheResult<()> {
+        let data_dir = self.directory.join("data");
+        std::fs::create_dir_all(&data_dir).map_err(CacheError::Io)?;
+        std::fs::write(data_dir.join(filename), data).map_err(CacheError::Io)
+    }
+
+    fn read_data_file(&self, filename: &str) -> CacheResult<Vec<u8>> {
+        std::fs::read(self.directory.join("data").join(filename)).map_err(CacheError::Io)
+    }
+
+    /// The ring is allocated whole when it is created, however few entries it holds
+    fn estimate_footprint(&self, entries: u64, key_size: usize, _value_size: usize) -> Footprint {
+        Footprint {
+            disk_bytes: self.capacity().unwrap_or(0).saturating_add(HEADER_LEN),
+            memory_bytes: entries.saturating_mul(
+                (key_size as u64)
+                    .saturating_mul(3)
+                    .saturating_add(ENTRY_OVERHEAD),
+            ),
+        }
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+impl Drop for RingStorage {
+    fn drop(&mut self) {
+        self.close_db();
+    }
+}
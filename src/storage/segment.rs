@@ -0,0 +1,310 @@
+//! Append-only segment files for [`OptimizedStorage`](super::OptimizedStorage)
+//!
+//! Values too large for the index but small enough to make a file each
+//! wasteful are appended to `segments/<id>.seg` instead, and the index row
+//! records where they landed. A segment is sealed once it reaches the
+//! configured size; writers then move on to the next id. Overwritten and
+//! deleted values leave dead bytes behind, which compaction reclaims by
+//! copying the live values of a mostly-dead sealed segment forward and
+//! deleting it. The store keeps a running count of total and dead bytes so
+//! the owner can tell when that is worth doing; both are this process's view
+//! and are recomputed from the index on open.
+//!
+//! Appends take an exclusive lock on the segment file, so processes sharing
+//! the directory never interleave their writes.
+
+use crate::error::{CacheError, CacheResult};
+use fs4::fs_std::FileExt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// Directory holding the segment files inside the cache directory
+pub const SEGMENT_DIR: &str = "segments";
+
+/// Index paths of packed values are `pack://<segment>/<offset>`
+const PACKED_SCHEME: &str = "pack://";
+
+/// Where a packed value lives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackedRef {
+    pub segment: u32,
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl PackedRef {
+    /// Whether an indexed data path points into a segment
+    pub fn is_packed(path: &Path) -> bool {
+        path.to_string_lossy().starts_with(PACKED_SCHEME)
+    }
+
+    /// Location encoded in an indexed data path, given the value length
+    pub fn parse(path: &Path, len: u64) -> CacheResult<Self> {
+        let path = path.to_string_lossy();
+        let location = path
+            .strip_prefix(PACKED_SCHEME)
+            .and_then(|rest| rest.split_once('/'))
+            .and_then(|(segment, offset)| Some((segment.parse().ok()?, offset.parse().ok()?)));
+        match location {
+            Some((segment, offset)) => Ok(Self {
+                segment,
+                offset,
+                len,
+            }),
+            None => Err(CacheError::Corruption(format!(
+                "Malformed segment location {:?}",
+                path
+            ))),
+        }
+    }
+
+    /// Path recorded in the index for this location
+    pub fn to_path(self) -> PathBuf {
+        PathBuf::from(format!("{}{}/{}", PACKED_SCHEME, self.segment, self.offset))
+    }
+}
+
+/// Outcome of one append
+pub struct Appended {
+    pub location: PackedRef,
+    /// Whether the append started a new segment file
+    pub created: bool,
+}
+
+/// The segment files of one cache directory
+pub struct SegmentStore {
+    directory: PathBuf,
+    segment_size: u64,
+    /// Segment this process appends to; only ever moves forward
+    active: AtomicU32,
+    /// Bytes in all segment files
+    bytes: AtomicU64,
+    /// Bytes no index row points at any more
+    dead: AtomicU64,
+}
+
+impl SegmentStore {
+    pub fn open(cache_directory: &Path, segment_size: u64) -> CacheResult<Self> {
+        // The directory is created by the first append
+        let directory = cache_directory.join(SEGMENT_DIR);
+        let store = Self {
+            directory,
+            segment_size: segment_size.max(1),
+            active: AtomicU32::new(0),
+            bytes: AtomicU64::new(0),
+            dead: AtomicU64::new(0),
+        };
+        let mut newest = 0;
+        for segment in store.segment_ids()? {
+            newest = newest.max(segment);
+            if let Ok(metadata) = std::fs::metadata(store.path(segment)) {
+                store.bytes.fetch_add(metadata.len(), Ordering::Relaxed);
+            }
+        }
+        store.active.store(newest, Ordering::Relaxed);
+        Ok(store)
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn path(&self, segment: u32) -> PathBuf {
+        self.directory.join(format!("{:08}.seg", segment))
+    }
+
+    /// Ids of the segment files on disk, in no particular order
+    pub fn segment_ids(&self) -> CacheResult<Vec<u32>> {
+        let mut ids = Vec::new();
+        let entries = match std::fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(ids),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let name = entry?.file_name();
+            let id = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".seg"))
+                .and_then(|id| id.parse::<u32>().ok());
+            ids.extend(id);
+        }
+        Ok(ids)
+    }
+
+    /// Whether a segment of `len` bytes takes no more appends
+    pub fn is_sealed(&self, len: u64) -> bool {
+        len >= self.segment_size
+    }
+
+    pub fn segment_size(&self) -> u64 {
+        self.segment_size
+    }
+
+    /// Bytes held by all segment files
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Bytes in segment files that belong to overwritten or deleted values
+    pub fn dead_bytes(&self) -> u64 {
+        self.dead.load(Ordering::Relaxed)
+    }
+
+    /// Record that a packed value of `len` bytes is no longer referenced
+    pub fn mark_dead(&self, len: u64) {
+        self.dead.fetch_add(len, Ordering::Relaxed);
+    }
+
+    /// Reset the dead byte count given the bytes the index still points at
+    pub fn set_live_bytes(&self, live: u64) {
+        self.dead
+            .store(self.bytes().saturating_sub(live), Ordering::Relaxed);
+    }
+
+    /// Append `data` to the active segment, optionally syncing it to disk
+    pub fn append(&self, data: &[u8], sync: bool) -> CacheResult<Appended> {
+        loop {
+            let segment = self.active.load(Ordering::Relaxed);
+            let mut file = match self.open_for_append(segment) {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    std::fs::create_dir_all(&self.directory)?;
+                    self.open_for_append(segment)?
+                }
+                file => file?,
+            };
+            file.lock_exclusive().map_err(|e| {
+                CacheError::Io(std::io::Error::other(format!(
+                    "Failed to lock segment {}: {}",
+                    segment, e
+                )))
+            })?;
+
+            let offset = file.metadata()?.len();
+            if self.is_sealed(offset) {
+                // Another writer filled it; whoever gets here first moves everyone on
+                let _ = self.active.compare_exchange(
+                    segment,
+                    segment + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                );
+                continue;
+            }
+
+            file.write_all(data)?;
+            self.bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+            if sync {
+                file.sync_data()?;
+            }
+            return Ok(Appended {
+                location: PackedRef {
+                    segment,
+                    offset,
+                    len: data.len() as u64,
+                },
+                created: offset == 0,
+            });
+        }
+    }
+
+    fn open_for_append(&self, segment: u32) -> std::io::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(segment))
+    }
+
+    /// Read a packed value back
+    pub fn read(&self, location: PackedRef) -> std::io::Result<Vec<u8>> {
+        let mut file = File::open(self.path(location.segment))?;
+        file.seek(SeekFrom::Start(location.offset))?;
+        let mut data = vec![0; location.len as usize];
+        file.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Whether the bytes at `location` exist on disk
+    pub fn contains(&self, location: PackedRef) -> bool {
+        std::fs::metadata(self.path(location.segment))
+            .is_ok_and(|metadata| metadata.len() >= location.offset + location.len)
+    }
+
+    /// Free the disk blocks under `ranges` of a segment, keeping its length
+    /// and every offset in it
+    ///
+    /// Returns the bytes the filesystem gave back, which is nothing for
+    /// ranges punched before or on filesystems without hole punching.
+    #[cfg(target_os = "linux")]
+    pub fn punch_holes(&self, segment: u32, ranges: &[Range<u64>]) -> std::io::Result<u64> {
+        use std::os::unix::fs::MetadataExt;
+        use std::os::unix::io::AsRawFd;
+
+        let file = OpenOptions::new().write(true).open(self.path(segment))?;
+        let allocated = |file: &File| file.metadata().map(|metadata| metadata.blocks() * 512);
+        let before = allocated(&file)?;
+        for range in ranges.iter().filter(|range| !range.is_empty()) {
+            let result = unsafe {
+                libc::fallocate(
+                    file.as_raw_fd(),
+                    libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                    range.start as libc::off_t,
+                    (range.end - range.start) as libc::off_t,
+                )
+            };
+            if result != 0 {
+                let e = std::io::Error::last_os_error();
+                return match e.raw_os_error() {
+                    Some(libc::EOPNOTSUPP) => Ok(0),
+                    _ => Err(e),
+                };
+            }
+        }
+        Ok(before.saturating_sub(allocated(&file)?))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn punch_holes(&self, _segment: u32, _ranges: &[Range<u64>]) -> std::io::Result<u64> {
+        Ok(0)
+    }
+
+    /// Delete a segment of `len` bytes, `dead` of which were dead
+    pub fn remove(&self, segment: u32, len: u64, dead: u64) -> std::io::Result<()> {
+        match std::fs::remove_file(self.path(segment)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let _ = self
+            .bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bytes| {
+                Some(bytes.saturating_sub(len))
+            });
+        let _ = self
+            .dead
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bytes| {
+                Some(bytes.saturating_sub(dead))
+            });
+        Ok(())
+    }
+
+    /// Delete every segment file
+    ///
+    /// Ids keep counting up from the active segment, so a compaction still
+    /// holding an old id never mistakes a fresh segment for the one it emptied.
+    pub fn remove_all(&self) -> CacheResult<u64> {
+        let mut removed = 0;
+        for segment in self.segment_ids()? {
+            match std::fs::remove_file(self.path(segment)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => removed += 1,
+            }
+        }
+        self.active.fetch_add(1, Ordering::Relaxed);
+        self.bytes.store(0, Ordering::Relaxed);
+        self.dead.store(0, Ordering::Relaxed);
+        Ok(removed)
+    }
+}
@@ -0,0 +1,150 @@
+//! Retrying file operations that collide with another process's open handle
+//!
+//! Windows refuses to rename, remove or open a file another handle has open
+//! without the matching share mode, failing with `ERROR_SHARING_VIOLATION`
+//! or `ERROR_LOCK_VIOLATION`. On SMB shares that handle may belong to another
+//! machine, or to an antivirus scanner or the SMB client's own caching, and
+//! is usually gone a few milliseconds later. [`SharingRetry`] runs an
+//! operation again after a short, jittered and growing delay for as long as
+//! it fails that way, up to a bounded number of attempts; other errors are
+//! returned at once. Elsewhere nothing counts as a sharing violation, so the
+//! retry never delays an operation.
+
+use std::io;
+use std::time::Duration;
+
+/// `ERROR_SHARING_VIOLATION`
+#[cfg(windows)]
+const ERROR_SHARING_VIOLATION: i32 = 32;
+/// `ERROR_LOCK_VIOLATION`
+#[cfg(windows)]
+const ERROR_LOCK_VIOLATION: i32 = 33;
+
+/// How often, and how patiently, a file operation failing with a sharing
+/// violation is retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SharingRetry {
+    /// Most times the operation runs, the first included
+    pub attempts: u32,
+    /// Delay before the first retry, doubled for each one after it
+    pub initial_delay: Duration,
+    /// Longest delay between two attempts
+    pub max_delay: Duration,
+}
+
+impl Default for SharingRetry {
+    fn default() -> Self {
+        Self {
+            attempts: 5,
+            initial_delay: Duration::from_millis(10),
+            max_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+impl SharingRetry {
+    /// Run `op`, again after a backoff while it fails with a sharing violation
+    pub fn run<T>(&self, op: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        self.run_while(op, is_sharing_violation)
+    }
+
+    fn run_while<T>(
+        &self,
+        mut op: impl FnMut() -> io::Result<T>,
+        retryable: impl Fn(&io::Error) -> bool,
+    ) -> io::Result<T> {
+        let mut delay = self.initial_delay;
+        let mut attempt = 1;
+        loop {
+            match op() {
+                Err(e) if attempt < self.attempts && retryable(&e) => {
+                    tracing::debug!("Sharing violation (attempt {}), retrying: {}", attempt, e);
+                    std::thread::sleep(jittered(delay));
+                    delay = (delay * 2).min(self.max_delay);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Whether `error` is Windows refusing access to a file another handle holds
+pub fn is_sharing_violation(error: &io::Error) -> bool {
+    #[cfg(windows)]
+    {
+        matches!(
+            error.raw_os_error(),
+            Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION)
+        )
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = error;
+        false
+    }
+}
+
+/// A delay between half of `delay` and all of it, so processes that collided
+/// once do not retry in step and collide again
+fn jittered(delay: Duration) -> Duration {
+    let fraction = uuid::Uuid::new_v4().as_u64_pair().0 as f64 / u64::MAX as f64;
+    delay.mul_f64(0.5 + fraction / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn busy() -> io::Error {
+        io::Error::new(io::ErrorKind::PermissionDenied, "busy")
+    }
+
+    fn retry(attempts: u32) -> SharingRetry {
+        SharingRetry {
+            attempts,
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(2),
+        }
+    }
+
+    #[test]
+    fn retries_sharing_violations_up_to_the_attempt_limit() {
+        let calls = Cell::new(0);
+        let succeeded = retry(5).run_while(
+            || {
+                calls.set(calls.get() + 1);
+                if calls.get() < 3 {
+                    Err(busy())
+                } else {
+                    Ok(calls.get())
+                }
+            },
+            |e| e.kind() == io::ErrorKind::PermissionDenied,
+        );
+        assert_eq!(succeeded.unwrap(), 3);
+
+        calls.set(0);
+        let failed = retry(4).run_while(
+            || -> io::Result<()> {
+                calls.set(calls.get() + 1);
+                Err(busy())
+            },
+            |e| e.kind() == io::ErrorKind::PermissionDenied,
+        );
+        assert!(failed.is_err());
+        assert_eq!(calls.get(), 4);
+    }
+
+    #[test]
+    fn other_errors_are_returned_at_once() {
+        let calls = Cell::new(0);
+        let failed = retry(5).run(|| -> io::Result<()> {
+            calls.set(calls.get() + 1);
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        });
+        assert_eq!(failed.unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(calls.get(), 1);
+    }
+}
@@ -0,0 +1,673 @@
+//! Storage backend that shares python-diskcache's own `cache.db`
+//!
+//! Rows live in diskcache's `Cache` table and large values in its
+//! `xx/yy/<hex>.val` files, so diskcache and diskcache_rs processes can use
+//! one directory at the same time during a migration. Only rows keyed by a
+//! Python `str` (stored as TEXT with `raw = 1`) are visible here; bytes,
+//! integer and pickled keys written by diskcache are left alone.
+//!
+//! Values are mapped onto diskcache's storage modes:
+//! - payloads carrying the Python wrapper's pickle prefix are stored as
+//!   `MODE_PICKLE`, so diskcache readers get the original object back
+//! - payloads carrying the wrapper's raw-bytes prefix, and plain Rust byte
+//!   strings, are stored as `MODE_RAW` blobs or `MODE_BINARY` files
+//!
+//! Reading goes the other way. diskcache's `str`, `int` and `float` values
+//! come back as pickles so the Python wrapper restores their types; blobs
+//! come back verbatim.
+
+use crate::error::{CacheError, CacheResult};
+use crate::lock_order::{LockLevel, OrderedMutex};
+use crate::serialization::CacheEntry;
+use crate::storage::{Durability, StorageBackend, UnlinkPool, UnlinkProgress, VacuumReport};
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Database file python-diskcache keeps in the cache directory
+pub const DISKCACHE_DB_NAME: &str = "cache.db";
+
+// Storage modes from diskcache.core
+const MODE_RAW: i64 = 1;
+const MODE_BINARY: i64 = 2;
+const MODE_TEXT: i64 = 3;
+const MODE_PICKLE: i64 = 4;
+
+// Value prefixes written by the Python `Cache` wrapper (python/diskcache_rs/cache.py)
+const RAW_BYTES_PREFIX: &[u8] = b"\x00diskcache_rs:bytes\x00";
+const PICKLE_PREFIX: &[u8] = b"\x00diskcache_rs:pickle\x00";
+
+/// Rows this backend can address: `str` keys stored raw
+const VISIBLE_ROWS: &str = "raw = 1 AND typeof(key) = 'text'";
+
+/// Schema statements python-diskcache runs when it creates a cache
+///
+/// The triggers keep the `count` and `size` settings current, which
+/// diskcache relies on for culling.
+const SCHEMA_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS Settings (key TEXT NOT NULL UNIQUE, value);
+    CREATE TABLE IF NOT EXISTS Cache (
+        rowid INTEGER PRIMARY KEY,
+        key BLOB,
+        raw INTEGER,
+        store_time REAL,
+        expire_time REAL,
+        access_time REAL,
+        access_count INTEGER DEFAULT 0,
+        tag BLOB,
+        size INTEGER DEFAULT 0,
+        mode INTEGER DEFAULT 0,
+        filename TEXT,
+        value BLOB);
+    CREATE UNIQUE INDEX IF NOT EXISTS Cache_key_raw ON Cache(key, raw);
+    CREATE INDEX IF NOT EXISTS Cache_expire_time ON Cache (expire_time);
+    CREATE INDEX IF NOT EXISTS Cache_store_time ON Cache (store_time);
+    CREATE TRIGGER IF NOT EXISTS Settings_count_insert
+        AFTER INSERT ON Cache FOR EACH ROW BEGIN
+        UPDATE Settings SET value = value + 1 WHERE key = 'count'; END;
+    CREATE TRIGGER IF NOT EXISTS Settings_count_delete
+        AFTER DELETE ON Cache FOR EACH ROW BEGIN
+        UPDATE Settings SET value = value - 1 WHERE key = 'count'; END;
+    CREATE TRIGGER IF NOT EXISTS Settings_size_insert
+        AFTER INSERT ON Cache FOR EACH ROW BEGIN
+        UPDATE Settings SET value = value + NEW.size WHERE key = 'size'; END;
+    CREATE TRIGGER IF NOT EXISTS Settings_size_update
+        AFTER UPDATE ON Cache FOR EACH ROW BEGIN
+        UPDATE Settings SET value = value + NEW.size - OLD.size WHERE key = 'size'; END;
+    CREATE TRIGGER IF NOT EXISTS Settings_size_delete
+        AFTER DELETE ON Cache FOR EACH ROW BEGIN
+        UPDATE Settings SET value = value - OLD.size WHERE key = 'size'; END;
+    INSERT OR IGNORE INTO Settings VALUES ('count', 0);
+    INSERT OR IGNORE INTO Settings VALUES ('size', 0);
+    INSERT OR IGNORE INTO Settings VALUES ('hits', 0);
+    INSERT OR IGNORE INTO Settings VALUES ('misses', 0);
+    INSERT OR IGNORE INTO Settings VALUES ('eviction_policy', 'least-recently-stored');
+";
+
+/// Key aliases live in a table of our own, which diskcache never reads
+const ALIAS_SCHEMA_SQL: &str = "
+    CREATE TABLE IF NOT EXISTS DiskcacheRsAlias (alias TEXT PRIMARY KEY, key TEXT NOT NULL);
+    CREATE INDEX IF NOT EXISTS DiskcacheRsAlias_key ON DiskcacheRsAlias (key);
+";
+
+/// A value in diskcache's representation, ready for a `Cache` row
+struct StoredValue {
+    mode: i64,
+    bytes: Vec<u8>,
+}
+
+/// Storage backed by python-diskcache's `cache.db` schema
+pub struct SqliteStorage {
+    directory: PathBuf,
+    conn: OrderedMutex<Connection>,
+    min_file_size: usize,
+    unlink: UnlinkPool,
+}
+
+impl SqliteStorage {
+    /// Open (or create) the diskcache database in `directory`
+    ///
+    /// Values of at least 32 KiB go to separate files, matching diskcache's
+    /// default `disk_min_file_size`.
+    pub fn new<P: AsRef<Path>>(directory: P) -> CacheResult<Self> {
+        Self::with_min_file_size(directory, 32 * 1024)
+    }
+
+    /// Open the database, storing values of at least `min_file_size` bytes in files
+    pub fn with_min_file_size<P: AsRef<Path>>(
+        directory: P,
+        min_file_size: usize,
+    ) -> CacheResult<Self> {
+        let directory = directory.as_ref().to_path_buf();
+        std::fs::create_dir_all(&directory).map_err(CacheError::Io)?;
+
+        let conn = Connection::open(directory.join(DISKCACHE_DB_NAME))
+            .map_err(|e| Self::sqlite_error("Failed to open diskcache database", e))?;
+        // diskcache's default `timeout` is 60 seconds
+        conn.busy_timeout(Duration::from_secs(60))
+            .map_err(|e| Self::sqlite_error("Failed to set SQLite busy timeout", e))?;
+        // auto_vacuum only takes effect before the first table is created
+        conn.pragma_update(None, "auto_vacuum", 1)
+            .map_err(|e| Self::sqlite_error("Failed to configure SQLite auto_vacuum", e))?;
+        conn.pragma_update(None, "journal_mode", "wal")
+            .map_err(|e| Self::sqlite_error("Failed to enable SQLite WAL", e))?;
+        conn.pragma_update(None, "synchronous", 1)
+            .map_err(|e| Self::sqlite_error("Failed to configure SQLite synchronous mode", e))?;
+        conn.execute_batch(SCHEMA_SQL)
+            .map_err(|e| Self::sqlite_error("Failed to create diskcache schema", e))?;
+        conn.execute_batch(ALIAS_SCHEMA_SQL)
+            .map_err(|e| Self::sqlite_error("Failed to create alias table", e))?;
+
+        Ok(Self {
+            directory,
+            conn: OrderedMutex::new(LockLevel::Index, conn),
+            min_file_size,
+            unlink: UnlinkPool::default(),
+        })
+    }
+
+    /// Remove value files with `unlink` when clearing
+    pub fn with_unlink_pool(mut self, unlink: UnlinkPool) -> Self {
+        self.unlink = unlink;
+        self
+    }
+
+    /// Commit with the SQLite `synchronous` setting matching `durability`
+    ///
+    /// Value files are left to the page cache at every level; this backend
+    /// keeps python-diskcache's file handling.
+    pub fn with_durability(self, durability: Durability) -> CacheResult<Self> {
+        self.conn
+            .lock()
+            .pragma_update(None, "synchronous", durability.sqlite_synchronous())
+            .map_err(|e| Self::sqlite_error("Failed to configure SQLite synchronous mode", e))?;
+        Ok(self)
+    }
+
+    fn sqlite_error(context: &str, error: rusqlite::Error) -> CacheError {
+        CacheError::Io(std::io::Error::other(format!("{}: {}", context, error)))
+    }
+
+    fn now() -> f64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64()
+    }
+
+    /// Translate a payload into the mode diskcache would have stored it with
+    fn encode_value(data: &[u8]) -> StoredValue {
+        if let Some(pickled) = data.strip_prefix(PICKLE_PREFIX) {
+            StoredValue {
+                mode: MODE_PICKLE,
+                bytes: pickled.to_vec(),
+            }
+        } else {
+            let bytes = data.strip_prefix(RAW_BYTES_PREFIX).unwrap_or(data);
+            StoredValue {
+                mode: MODE_RAW,
+                bytes: bytes.to_vec(),
+            }
+        }
+    }
+
+    fn pickled<T: serde::Serialize>(value: &T) -> CacheResult<Vec<u8>> {
+        let pickle = serde_pickle::to_vec(value, serde_pickle::SerOptions::new())
+            .map_err(|e| CacheError::Serialization(e.to_string()))?;
+        Ok([PICKLE_PREFIX, pickle.as_slice()].concat())
+    }
+
+    /// Payload of a row, reading its value file if it has one
+    fn decode_value(
+        &self,
+        mode: i64,
+        filename: Option<&str>,
+        value: ValueRef<'_>,
+    ) -> CacheResult<Vec<u8>> {
+        match (mode, filename) {
+            (MODE_BINARY, Some(filename)) => self.read_data_file(filename),
+            (MODE_TEXT, Some(filename)) => {
+                let text = String::from_utf8(self.read_data_file(filename)?)
+                    .map_err(|e| CacheError::Deserialization(e.to_string()))?;
+                Self::pickled(&text)
+            }
+            (MODE_PICKLE, Some(filename)) => {
+                Ok([PICKLE_PREFIX, self.read_data_file(filename)?.as_slice()].concat())
+            }
+            (MODE_PICKLE, None) => match value {
+                ValueRef::Blob(pickle) => Ok([PICKLE_PREFIX, pickle].concat()),
+                other => Err(CacheError::Deserialization(format!(
+                    "pickled diskcache value stored as {:?}",
+                    other.data_type()
+                ))),
+            },
+            (MODE_RAW, None) => match value {
+                ValueRef::Blob(bytes) => Ok(bytes.to_vec()),
+                ValueRef::Text(text) => Self::pickled(&String::from_utf8_lossy(text)),
+                ValueRef::Integer(number) => Self::pickled(&number),
+                ValueRef::Real(number) => Self::pickled(&number),
+                ValueRef::Null => Err(CacheError::Deserialization(
+                    "raw diskcache value is NULL".to_string(),
+                )),
+            },
+            (mode, filename) => Err(CacheError::Deserialization(format!(
+                "unsupported diskcache storage mode {} (file: {:?})",
+                mode, filename
+            ))),
+        }
+    }
+
+    fn read_row(&self, key: &str, row: &Row<'_>) -> CacheResult<CacheEntry> {
+        let column = |e| Self::sqlite_error("Failed to read diskcache row", e);
+        let store_time: Option<f64> = row.get(0).map_err(column)?;
+        let expire_time: Option<f64> = row.get(1).map_err(column)?;
+        let access_time: Option<f64> = row.get(2).map_err(column)?;
+        let access_count: Option<i64> = row.get(3).map_err(column)?;
+        let tag = match row.get_ref(4).map_err(column)? {
+            ValueRef::Text(tag) => vec![String::from_utf8_lossy(tag).into_owned()],
+            _ => vec![],
+        };
+        let mode: i64 = row.get(5).map_err(column)?;
+        let filename: Option<String> = row.get(6).map_err(column)?;
+        let value = row.get_ref(7).map_err(column)?;
+
+        let data = self.decode_value(mode, filename.as_deref(), value)?;
+        let mut entry = CacheEntry::new_inline(
+            key.to_string(),
+            data,
+            tag,
+            expire_time.map(|time| time as u64),
+        );
+        if let Some(store_time) = store_time {
+            entry.created_at = store_time as u64;
+        }
+        entry.accessed_at = access_time.map_or(entry.created_at, |time| time as u64);
+        entry.access_count = access_count.unwrap_or(0) as u64;
+        Ok(entry)
+    }
+
+    /// Replace the row for `key`, returning the value file it pointed at
+    ///
+    /// The old row is deleted rather than updated so the key moves to the end
+    /// of store order.
+    fn write_row(
+        &self,
+        tx: &Transaction<'_>,
+        key: &str,
+        data: &[u8],
+        tag: Option<&str>,
+        expire_time: Option<u64>,
+    ) -> CacheResult<Option<String>> {
+        let previous = Self::delete_row(tx, key)?;
+        let StoredValue { mode, bytes } = Self::encode_value(data);
+
+        let now = Self::now();
+        let (mode, size, filename, value) = if bytes.len() >= self.min_file_size {
+            let filename = self.generate_filename(key);
+            self.write_data_file(&filename, &bytes)?;
+            let mode = if mode == MODE_RAW { MODE_BINARY } else { mode };
+            (mode, bytes.len() as i64, Some(filename), None)
+        } else {
+            // diskcache only counts file-backed values towards `size`
+            (mode, 0, None, Some(bytes))
+        };
+
+        tx.execute(
+            "INSERT INTO Cache (key, raw, store_time, expire_time, access_time, \
+             access_count, tag, size, mode, filename, value) \
+             VALUES (?1, 1, ?2, ?3, ?2, 0, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                key,
+                now,
+                expire_time.map(|time| time as f64),
+                tag,
+                size,
+                mode,
+                filename,
+                value
+            ],
+        )
+        .map_err(|e| Self::sqlite_error("Failed to insert diskcache row", e))?;
+        Ok(previous)
+    }
+
+    /// Delete the row for `key`, returning whether it existed and its value file
+    fn delete_row(tx: &Transaction<'_>, key: &str) -> CacheResult<Option<String>> {
+        let row: Option<(i64, Option<String>)> = tx
+            .query_row(
+                &format!(
+                    "SELECT rowid, filename FROM Cache WHERE key = ?1 AND {}",
+                    VISIBLE_ROWS
+                ),
+                params![key],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|e| Self::sqlite_error("Failed to look up diskcache row", e))?;
+        let Some((rowid, filename)) = row else {
+            return Ok(None);
+        };
+        tx.execute("DELETE FROM Cache WHERE rowid = ?1", params![rowid])
+            .map_err(|e| Self::sqlite_error("Failed to delete diskcache row", e))?;
+        // An empty name marks an inline row; the caller only needs to know it existed
+        Ok(Some(filename.unwrap_or_default()))
+    }
+
+    /// Remove value files whose rows are gone, ignoring files already removed
+    fn remove_files<I: IntoIterator<Item = String>>(&self, filenames: I) {
+        for filename in filenames.into_iter().filter(|name| !name.is_empty()) {
+            match std::fs::remove_file(self.directory.join(&filename)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => tracing::warn!("Failed to remove value file {}: {}", filename, e),
+            }
+        }
+    }
+
+    fn with_transaction<T>(
+        &self,
+        apply: impl FnOnce(&Transaction<'_>) -> CacheResult<T>,
+    ) -> CacheResult<T> {
+        let mut conn = self.conn.lock();
+        let tx = conn
+            .transaction()
+            .map_err(|e| Self::sqlite_error("Failed to begin SQLite transaction", e))?;
+        let result = apply(&tx)?;
+        tx.commit()
+            .map_err(|e| Self::sqlite_error("Failed to commit SQLite transaction", e))?;
+        Ok(result)
+    }
+
+    fn query_keys(&self, sql: &str, params: impl rusqlite::Params) -> CacheResult<Vec<String>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn
+            .prepare(sql)
+            .map_err(|e| Self::sqlite_error("Failed to query diskcache keys", e))?;
+        let rows = stmt
+            .query_map(params, |row| row.get::<_, String>(0))
+            .map_err(|e| Self::sqlite_error("Failed to iterate diskcache keys", e))?;
+        let mut keys = Vec::new();
+        for row in rows {
+            keys.push(row.map_err(|e| Self::sqlite_error("Failed to read diskcache key", e))?);
+        }
+        Ok(keys)
+    }
+}
+
+impl StorageBackend for SqliteStorage {
+    fn get(&self, key: &str) -> CacheResult<Option<CacheEntry>> {
+        let conn = self.conn.lock();
+        // Like diskcache, rows past their expire_time are treated as missing
+        let mut stmt = conn
+            .prepare_cached(&format!(
+                "SELECT store_time, expire_time, access_time, access_count, tag, mode, \
+                 filename, value FROM Cache WHERE key = ?1 AND {} \
+                 AND (expire_time IS NULL OR expire_time > ?2)",
+                VISIBLE_ROWS
+            ))
+            .map_err(|e| Self::sqlite_error("Failed to prepare diskcache lookup", e))?;
+        let mut rows = stmt
+            .query(params![key, Self::now()])
+            .map_err(|e| Self::sqlite_error("Failed to query diskcache row", e))?;
+        match rows
+            .next()
+            .map_err(|e| Self::sqlite_error("Failed to read diskcache row", e))?
+        {
+            Some(row) => match self.read_row(key, row) {
+                Ok(entry) => Ok(Some(entry)),
+                // diskcache may cull the row and its file between our lookup and read
+                Err(CacheError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn set(&self, key: &str, entry: CacheEntry) -> CacheResult<()> {
+        let data = match &entry.storage {
+            crate::serialization::StorageMode::Inline(data) => data.clone(),
+            crate::serialization::StorageMode::File(filename) => self.read_data_file(filename)?,
+        };
+        let tag = entry.tags.first().map(String::as_str);
+        let previous =
+            self.with_transaction(|tx| self.write_row(tx, key, &data, tag, entry.expire_time))?;
+        self.remove_files(previous);
+        Ok(())
+    }
+
+    fn set_batch(&self, entries: Vec<(String, Vec<u8>)>) -> CacheResult<()> {
+        let previous = self.with_transaction(|tx| {
+            let mut previous = Vec::new();
+            for (key, data) in &entries {
+                previous.extend(self.write_row(tx, key, data, None, None)?);
+            }
+            Ok(previous)
+        })?;
+        self.remove_files(previous);
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> CacheResult<bool> {
+        let previous = self.with_transaction(|tx| {
+            let previous = Self::delete_row(tx, key)?;
+            tx.execute("DELETE FROM DiskcacheRsAlias WHERE key = ?1", params![key])
+                .map_err(|e| Self::sqlite_error("Failed to delete aliases", e))?;
+            Ok(previous)
+        })?;
+        let found = previous.is_some();
+        self.remove_files(previous);
+        Ok(found)
+    }
+
+    fn set_alias(&self, alias: &str, key: &str) -> CacheResult<()> {
+        let conn = self.conn.lock();
+        let inserted = conn
+            .execute(
+                &format!(
+                    "INSERT INTO DiskcacheRsAlias (alias, key) SELECT ?1, key FROM Cache \
+                     WHERE key = ?2 AND {} AND (expire_time IS NULL OR expire_time > ?3) \
+                     ON CONFLICT(alias) DO UPDATE SET key = excluded.key",
+                    VISIBLE_ROWS
+                ),
+                params![alias, key, Self::now()],
+            )
+            .map_err(|e| Self::sqlite_error("Failed to store alias", e))?;
+        if inserted == 0 {
+            return Err(CacheError::KeyNotFound(key.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Aliases left behind by diskcache processes deleting their entry stay hidden
+    fn resolve_alias(&self, alias: &str) -> CacheResult<Option<String>> {
+        let conn = self.conn.lock();
+        conn.query_row(
+            &format!(
+                "SELECT key FROM DiskcacheRsAlias AS a WHERE alias = ?1 AND EXISTS \
+                 (SELECT 1 FROM Cache WHERE Cache.key = a.key AND {} \
+                 AND (expire_time IS NULL OR expire_time > ?2))",
+                VISIBLE_ROWS
+            ),
+            params![alias, Self::now()],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| Self::sqlite_error("Failed to resolve alias", e))
+    }
+
+    fn remove_alias(&self, alias: &str) -> CacheResult<bool> {
+        let conn = self.conn.lock();
+        let removed = conn
+            .execute(
+                "DELETE FROM DiskcacheRsAlias WHERE alias = ?1",
+                params![alias],
+            )
+            .map_err(|e| Self::sqlite_error("Failed to remove alias", e))?;
+        Ok(removed > 0)
+    }
+
+    fn aliases(&self, key: &str) -> CacheResult<Vec<String>> {
+        let conn = self.conn.lock();
+        let mut stmt = conn
+            .prepare("SELECT alias FROM DiskcacheRsAlias WHERE key = ?1")
+            .map_err(|e| Self::sqlite_error("Failed to prepare alias query", e))?;
+        let aliases = stmt
+            .query_map(params![key], |row| row.get(0))
+            .map_err(|e| Self::sqlite_error("Failed to query aliases", e))?
+            .collect::<Result<Vec<String>, _>>()
+            .map_err(|e| Self::sqlite_error("Failed to read alias", e))?;
+        Ok(aliases)
+    }
+
+    /// Derived from the row id and store time, both of which a rewrite replaces
+    fn version(&self, key: &str) -> CacheResult<Option<u64>> {
+        let conn = self.conn.lock();
+        let row: Option<(i64, Option<f64>)> = conn
+            .query_row(
+                &format!(
+                    "SELECT rowid, store_time FROM Cache WHERE key = ?1 AND {} \
+                     AND (expire_time IS NULL OR expire_time > ?2)",
+                    VISIBLE_ROWS
+                ),
+                params![key, Self::now()],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|e| Self::sqlite_error("Failed to read diskcache row version", e))?;
+        Ok(row.map(|(rowid, store_time)| {
+            let mut stamp = [0u8; 16];
+            stamp[..8].copy_from_slice(&rowid.to_le_bytes());
+            stamp[8..].copy_from_slice(&store_time.unwrap_or_default().to_le_bytes());
+            let hash = blake3::hash(&stamp);
+            let mut version = [0u8; 8];
+            version.copy_from_slice(&hash.as_bytes()[..8]);
+            u64::from_le_bytes(version)
+        }))
+    }
+
+    fn exists(&self, key: &str) -> CacheResult<bool> {
+        let conn = self.conn.lock();
+        let exists: Option<i32> = conn
+            .query_row(
+                &format!(
+                    "SELECT 1 FROM Cache WHERE key = ?1 AND {} \
+                     AND (expire_time IS NULL OR expire_time > ?2)",
+                    VISIBLE_ROWS
+                ),
+                params![key, Self::now()],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| Self::sqlite_error("Failed to check diskcache row", e))?;
+        Ok(exists.is_some())
+    }
+
+    fn keys(&self) -> CacheResult<Vec<String>> {
+        self.query_keys(
+            &format!(
+                "SELECT key FROM Cache WHERE {} ORDER BY rowid",
+                VISIBLE_ROWS
+            ),
+            [],
+        )
+    }
+
+    fn peek_key(&self, last: bool) -> CacheResult<Option<String>> {
+        let order = if last { "DESC" } else { "ASC" };
+        let keys = self.query_keys(
+            &format!(
+                "SELECT key FROM Cache WHERE {} ORDER BY rowid {} LIMIT 1",
+                VISIBLE_ROWS, order
+            ),
+            [],
+        )?;
+        Ok(keys.into_iter().next())
+    }
+
+    fn key_page(
+        &self,
+        cursor: Option<i64>,
+        reverse: bool,
+        limit: usize,
+    ) -> CacheResult<Vec<(i64, String)>> {
+        let (comparison, order, start) = if reverse {
+            ("<", "DESC", cursor.unwrap_or(i64::MAX))
+        } else {
+            (">", "ASC", cursor.unwrap_or(i64::MIN))
+        };
+        let conn = self.conn.lock();
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT rowid, key FROM Cache WHERE {} AND rowid {} ?1 ORDER BY rowid {} LIMIT ?2",
+                VISIBLE_ROWS, comparison, order
+            ))
+            .map_err(|e| Self::sqlite_error("Failed to query diskcache key page", e))?;
+        let rows = stmt
+            .query_map(params![start, limit as i64], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })
+            .map_err(|e| Self::sqlite_error("Failed to iterate diskcache key page", e))?;
+        let mut page = Vec::with_capacity(limit);
+        for row in rows {
+            page.push(row.map_err(|e| Self::sqlite_error("Failed to read diskcache key", e))?);
+        }
+        Ok(page)
+    }
+
+    fn peek_key_in_range(&self, start: &str, end: &str, last: bool) -> CacheResult<Option<String>> {
+        let order = if last { "DESC" } else { "ASC" };
+        let keys = self.query_keys(
+            &format!(
+                "SELECT key FROM Cache WHERE {} AND key >= ?1 AND key < ?2 \
+                 ORDER BY key {} LIMIT 1",
+                VISIBLE_ROWS, order
+            ),
+            params![start, end],
+        )?;
+        Ok(keys.into_iter().next())
+    }
+
+    fn clear(&self) -> CacheResult<()> {
+        self.clear_with_progress(&|_| {})
+    }
+
+    fn clear_with_progress(&self, progress: &(dyn Fn(UnlinkProgress) + Sync)) -> CacheResult<()> {
+        let filenames = self.with_transaction(|tx| {
+            let mut stmt = tx
+                .prepare("SELECT filename FROM Cache WHERE filename IS NOT NULL")
+                .map_err(|e| Self::sqlite_error("Failed to query diskcache files", e))?;
+            let rows = stmt
+                .query_map([], |row| row.get::<_, String>(0))
+                .map_err(|e| Self::sqlite_error("Failed to iterate diskcache files", e))?;
+            let mut filenames = Vec::new();
+            for row in rows {
+                filenames
+                    .push(row.map_err(|e| Self::sqlite_error("Failed to read diskcache file", e))?);
+            }
+            tx.execute("DELETE FROM Cache", [])
+                .map_err(|e| Self::sqlite_error("Failed to clear diskcache rows", e))?;
+            tx.execute("DELETE FROM DiskcacheRsAlias", [])
+                .map_err(|e| Self::sqlite_error("Failed to clear aliases", e))?;
+            Ok(filenames)
+        })?;
+        let paths: Vec<_> = filenames
+            .iter()
+            .filter(|name| !name.is_empty())
+            .map(|name| self.directory.join(name))
+            .collect();
+        self.unlink.unlink(&paths, progress);
+        Ok(())
+    }
+
+    fn vacuum(&self) -> CacheResult<VacuumReport> {
+        // Full VACUUM would block diskcache processes sharing the database
+        self.conn
+            .lock()
+            .query_row("PRAGMA wal_checkpoint(PASSIVE)", [], |_| Ok(()))
+            .map_err(|e| Self::sqlite_error("Failed to checkpoint diskcache database", e))?;
+        Ok(VacuumReport::default())
+    }
+
+    fn generate_filename(&self, _key: &str) -> String {
+        // Same layout as diskcache's Disk.filename(): two levels of subdirectories
+        let hex = uuid::Uuid::new_v4().simple().to_string();
+        format!("{}/{}/{}.val", &hex[..2], &hex[2..4], &hex[4..])
+    }
+
+    fn write_data_file(&self, filename: &str, data: &[u8]) -> CacheResult<()> {
+        let path = self.directory.join(filename);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(CacheError::Io)?;
+        }
+        std::fs::write(path, data).map_err(CacheError::Io)
+    }
+
+    fn read_data_file(&self, filename: &str) -> CacheResult<Vec<u8>> {
+        std::fs::read(self.directory.join(filename)).map_err(CacheError::Io)
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
@@ -1,268 +1,2247 @@
 //! Tests for storage backends
 
+use super::conformance;
 use super::*;
 use crate::serialization::CacheEntry;
+use rusqlite::types::Value;
 use tempfile::TempDir;
 
-/// Helper function to create a test cache entry
-fn create_test_entry(key: &str, data: &[u8]) -> CacheEntry {
-    CacheEntry::new_inline(key.to_string(), data.to_vec(), vec![], None)
+fn wal_config(policy: WalSyncPolicy) -> optimized_backend::StorageConfig {
+    optimized_backend::StorageConfig {
+        wal: Some(policy),
+        ..Default::default()
+    }
 }
 
-#[cfg(test)]
-mod ultra_fast_storage_tests {
-    use super::*;
-    use crate::storage::ultra_fast_backend::UltraFastStorage;
+#[test]
+fn test_optimized_storage_conformance() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = OptimizedStorage::new(temp_dir.path()).unwrap();
+    conformance::run_all(&storage);
+}
 
-    #[test]
-    fn test_ultra_fast_basic_operations() {
-        let temp_dir = TempDir::new().unwrap();
-        let storage = UltraFastStorage::new(temp_dir.path(), false).unwrap();
+#[test]
+fn test_optimized_storage_conformance_with_wal() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage =
+        OptimizedStorage::with_config(temp_dir.path(), wal_config(WalSyncPolicy::Always)).unwrap();
+    conformance::run_all(&storage);
+}
+
+#[test]
+fn test_sequence_numbers_of_deleted_entries_are_not_reused() {
+    let temp_dir = TempDir::new().unwrap();
+    let large = vec![3u8; 128 * 1024];
+    let newest = |storage: &OptimizedStorage| storage.key_page(None, true, 1).unwrap()[0].clone();
+    let storage = OptimizedStorage::new(temp_dir.path()).unwrap();
+    for (key, value) in [("a", &b"value"[..]), ("b", &large[..])] {
+        let entry = CacheEntry::new_inline(key.to_string(), value.to_vec(), vec![], None);
+        storage.set(key, entry).unwrap();
+    }
+
+    // A cursor at the deleted tail still sees what is stored after it
+    let (cursor, key) = newest(&storage);
+    assert_eq!(key, "b");
+    storage.delete("b").unwrap();
+    drop(storage);
+    let storage = OptimizedStorage::new(temp_dir.path()).unwrap();
+    for (key, value) in [("c", &large[..]), ("d", &b"value"[..])] {
+        let entry = CacheEntry::new_inline(key.to_string(), value.to_vec(), vec![], None);
+        storage.set(key, entry).unwrap();
+    }
+    let after: Vec<String> = storage
+        .key_page(Some(cursor), false, 10)
+        .unwrap()
+        .into_iter()
+        .map(|(_, key)| key)
+        .collect();
+    assert_eq!(after, ["c", "d"]);
+}
 
-        let entry = create_test_entry("test_key", b"test_value");
+#[test]
+fn test_optimized_storage_conformance_after_reopen() {
+    let temp_dir = TempDir::new().unwrap();
+    {
+        let storage = OptimizedStorage::new(temp_dir.path()).unwrap();
+        conformance::check_set_get(&storage);
+        conformance::check_large_values(&storage);
+    }
 
-        // Test set and get
-        storage.set("test_key", entry.clone()).unwrap();
-        let retrieved = storage.get("test_key").unwrap();
-        assert!(retrieved.is_some());
+    // Entries written by a previous instance are served from the index
+    let storage = OptimizedStorage::new(temp_dir.path()).unwrap();
+    assert!(storage.exists("conf:get").unwrap());
+    assert!(storage.exists("conf:large").unwrap());
+    conformance::check_delete(&storage);
+    conformance::check_clear(&storage);
+}
 
-        let retrieved_entry = retrieved.unwrap();
-        assert_eq!(retrieved_entry.key, "test_key");
+#[test]
+fn test_optimized_storage_survives_directory_move() {
+    let temp_dir = TempDir::new().unwrap();
+    let original = temp_dir.path().join("original");
+    let moved = temp_dir.path().join("moved");
+    let large = vec![5u8; 128 * 1024];
+    {
+        let storage = OptimizedStorage::new(&original).unwrap();
+        let entry = CacheEntry::new_inline("large".to_string(), large.clone(), vec![], None);
+        storage.set("large", entry).unwrap();
+    }
 
-        // Test exists
-        assert!(storage.exists("test_key").unwrap());
-        assert!(!storage.exists("nonexistent").unwrap());
+    std::fs::rename(&original, &moved).unwrap();
+
+    let storage = OptimizedStorage::new(&moved).unwrap();
+    let entry = storage
+        .get("large")
+        .unwrap()
+        .expect("entry lost after move");
+    assert_eq!(entry.get_data(), Some(large.as_slice()));
+}
 
-        // Test delete
-        assert!(storage.delete("test_key").unwrap());
-        assert!(!storage.exists("test_key").unwrap());
+#[test]
+fn test_wal_replays_writes_lost_after_crash() {
+    let temp_dir = TempDir::new().unwrap();
+    let large = vec![6u8; 128 * 1024];
+    let entry = |key: &str, data: &[u8]| {
+        CacheEntry::new_inline(key.to_string(), data.to_vec(), vec![], None)
+    };
+    {
+        let storage =
+            OptimizedStorage::with_config(temp_dir.path(), wal_config(WalSyncPolicy::Always))
+                .unwrap();
+        storage.set("small", entry("small", b"value")).unwrap();
+        storage.set("large", entry("large", &large)).unwrap();
+        storage.set("gone", entry("gone", b"value")).unwrap();
+        storage.delete("gone").unwrap();
+        // Simulate a crash: nothing is flushed or checkpointed
+        storage.release_wal();
+        std::mem::forget(storage);
     }
 
-    #[test]
-    fn test_ultra_fast_keys() {
-        let temp_dir = TempDir::new().unwrap();
-        let storage = UltraFastStorage::new(temp_dir.path(), false).unwrap();
+    // ...that also lost the index rows and data files
+    let conn = rusqlite::Connection::open(temp_dir.path().join("index.sqlite3")).unwrap();
+    conn.execute("DELETE FROM cache_index", []).unwrap();
+    drop(conn);
+    std::fs::remove_dir_all(temp_dir.path().join("data")).unwrap();
+    std::fs::create_dir_all(temp_dir.path().join("data")).unwrap();
+
+    // A torn record at the tail is ignored
+    let wal_path = temp_dir.path().join(wal::WAL_FILE_NAME);
+    let mut torn = std::fs::read(&wal_path).unwrap();
+    torn.extend_from_slice(&[1, 200, 0, 0, 0, 9]);
+    std::fs::write(&wal_path, torn).unwrap();
+
+    let storage =
+        OptimizedStorage::with_config(temp_dir.path(), wal_config(WalSyncPolicy::Always)).unwrap();
+    let value = |key: &str| {
+        storage
+            .get(key)
+            .unwrap()
+            .map(|entry| entry.get_data().unwrap().to_vec())
+    };
+    assert_eq!(value("small"), Some(b"value".to_vec()));
+    assert_eq!(value("large"), Some(large.clone()));
+    assert_eq!(value("gone"), None);
+    assert_eq!(
+        std::fs::metadata(&wal_path).unwrap().len(),
+        0,
+        "replay must checkpoint the log"
+    );
+}
+
+#[test]
+fn test_wal_is_owned_by_one_handle() {
+    let temp_dir = TempDir::new().unwrap();
+    let entry =
+        |key: &str| CacheEntry::new_inline(key.to_string(), b"value".to_vec(), vec![], None);
+    let first =
+        OptimizedStorage::with_config(temp_dir.path(), wal_config(WalSyncPolicy::Always)).unwrap();
+    first.set("first", entry("first")).unwrap();
+    // A second handle must not truncate the log the first one is writing
+    let second =
+        OptimizedStorage::with_config(temp_dir.path(), wal_config(WalSyncPolicy::Always)).unwrap();
+    second.set("second", entry("second")).unwrap();
+    first.set("third", entry("third")).unwrap();
+    drop(second);
+    first.release_wal();
+    std::mem::forget(first);
+
+    let conn = rusqlite::Connection::open(temp_dir.path().join("index.sqlite3")).unwrap();
+    conn.execute("DELETE FROM cache_index", []).unwrap();
+    drop(conn);
+
+    // Both of the owner's writes come back from its log
+    let storage =
+        OptimizedStorage::with_config(temp_dir.path(), wal_config(WalSyncPolicy::Always)).unwrap();
+    assert!(storage.get("first").unwrap().is_some());
+    assert!(storage.get("third").unwrap().is_some());
+}
+
+#[test]
+fn test_wal_drops_records_whose_lengths_overflow() {
+    let temp_dir = TempDir::new().unwrap();
+    {
+        let storage =
+            OptimizedStorage::with_config(temp_dir.path(), wal_config(WalSyncPolicy::Always))
+                .unwrap();
+        let entry = CacheEntry::new_inline("kept".to_string(), b"value".to_vec(), vec![], None);
+        storage.set("kept", entry).unwrap();
+        storage.release_wal();
+        std::mem::forget(storage);
+    }
+
+    let wal_path = temp_dir.path().join(wal::WAL_FILE_NAME);
+    let mut torn = std::fs::read(&wal_path).unwrap();
+    let intact = torn.len() as u64;
+    torn.push(1);
+    torn.extend_from_slice(&u64::MAX.to_le_bytes());
+    torn.extend_from_slice(&u64::MAX.to_le_bytes());
+    std::fs::write(&wal_path, torn).unwrap();
+
+    let (_, records) = wal::WriteAheadLog::open(temp_dir.path(), WalSyncPolicy::Always, 1).unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), intact);
+}
+
+#[test]
+fn test_wal_is_empty_after_clean_close() {
+    let temp_dir = TempDir::new().unwrap();
+    {
+        let storage =
+            OptimizedStorage::with_config(temp_dir.path(), wal_config(WalSyncPolicy::Batch))
+                .unwrap();
+        conformance::check_set_batch(&storage);
+    }
+
+    let wal_path = temp_dir.path().join(wal::WAL_FILE_NAME);
+    assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), 0);
+    let storage = OptimizedStorage::new(temp_dir.path()).unwrap();
+    assert!(storage.exists("conf:batch:b").unwrap());
+}
+
+#[test]
+fn test_sqlite_storage_conformance() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = SqliteStorage::new(temp_dir.path()).unwrap();
+    conformance::run_all(&storage);
+}
+
+#[test]
+fn test_sqlite_storage_reads_diskcache_rows() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = SqliteStorage::new(temp_dir.path()).unwrap();
+
+    // Rows as python-diskcache writes them: raw bytes, a str, a pickle, a
+    // file-backed value and a bytes key this backend cannot address
+    std::fs::create_dir_all(temp_dir.path().join("ab/cd")).unwrap();
+    std::fs::write(temp_dir.path().join("ab/cd/ef.val"), vec![7u8; 40_000]).unwrap();
+    let conn = rusqlite::Connection::open(temp_dir.path().join("cache.db")).unwrap();
+    let insert = |key: Value, mode: i64, filename: Option<&str>, value: Value| {
+        let size = if filename.is_some() { 40_000 } else { 0 };
+        conn.execute(
+            "INSERT INTO Cache (key, raw, store_time, access_time, size, mode, filename, value) \
+             VALUES (?1, 1, 0, 0, ?2, ?3, ?4, ?5)",
+            rusqlite::params![key, size, mode, filename, value],
+        )
+        .unwrap();
+    };
+    let text = |s: &str| Value::Text(s.to_string());
+    let blob = |b: &[u8]| Value::Blob(b.to_vec());
+    insert(text("bytes"), 1, None, blob(b"abc"));
+    insert(text("text"), 1, None, text("hello"));
+    insert(text("pickle"), 4, None, blob(b"\x80\x04K\x05."));
+    insert(text("file"), 2, Some("ab/cd/ef.val"), Value::Null);
+    insert(blob(b"blob-key"), 1, None, blob(b"hidden"));
+    let count: i64 = conn
+        .query_row(
+            "SELECT value FROM Settings WHERE key = 'count'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(count, 5, "schema triggers must keep diskcache's count");
+
+    let value = |key: &str| {
+        storage
+            .get(key)
+            .unwrap()
+            .map(|e| e.get_data().unwrap().to_vec())
+    };
+    assert_eq!(value("bytes"), Some(b"abc".to_vec()));
+    assert_eq!(value("file"), Some(vec![7u8; 40_000]));
+    let pickle = value("pickle").unwrap();
+    assert!(pickle.starts_with(b"\x00diskcache_rs:pickle\x00"));
+    assert!(pickle.ends_with(b"\x80\x04K\x05."));
+    let text = value("text").unwrap();
+    assert!(text.starts_with(b"\x00diskcache_rs:pickle\x00"));
+    assert_eq!(
+        storage.keys().unwrap(),
+        vec!["bytes", "text", "pickle", "file"]
+    );
 
-        // Add multiple entries
-        for i in 0..5 {
-            let entry = create_test_entry(&format!("key_{}", i), b"test_value");
-            storage.set(&format!("key_{}", i), entry).unwrap();
+    // Deleting a file-backed row removes its value file, as diskcache does
+    assert!(storage.delete("file").unwrap());
+    assert!(!temp_dir.path().join("ab/cd/ef.val").exists());
+}
+
+#[test]
+fn test_sqlite_storage_writes_diskcache_rows() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = SqliteStorage::new(temp_dir.path()).unwrap();
+    let entry = |data: &[u8]| CacheEntry::new_inline(String::new(), data.to_vec(), vec![], None);
+
+    storage
+        .set("bytes", entry(b"\x00diskcache_rs:bytes\x00abc"))
+        .unwrap();
+    storage
+        .set(
+            "object",
+            entry(b"\x00diskcache_rs:pickle\x00\x80\x04K\x05."),
+        )
+        .unwrap();
+    storage.set("large", entry(&[9u8; 64 * 1024])).unwrap();
+
+    let conn = rusqlite::Connection::open(temp_dir.path().join("cache.db")).unwrap();
+    let row = |key: &str| {
+        conn.query_row(
+            "SELECT raw, mode, size, filename, value FROM Cache WHERE key = ?1",
+            [key],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, Option<Vec<u8>>>(4)?,
+                ))
+            },
+        )
+        .unwrap()
+    };
+    assert_eq!(row("bytes"), (1, 1, 0, None, Some(b"abc".to_vec())));
+    assert_eq!(
+        row("object"),
+        (1, 4, 0, None, Some(b"\x80\x04K\x05.".to_vec()))
+    );
+    let (_, mode, size, filename, value) = row("large");
+    assert_eq!((mode, size, value), (2, 64 * 1024, None));
+    let filename = filename.expect("large values live in files");
+    assert_eq!(
+        std::fs::read(temp_dir.path().join(&filename)).unwrap(),
+        vec![9u8; 64 * 1024]
+    );
+
+    let size: i64 = conn
+        .query_row("SELECT value FROM Settings WHERE key = 'size'", [], |row| {
+            row.get(0)
+        })
+        .unwrap();
+    assert_eq!(size, 64 * 1024);
+}
+
+#[test]
+fn test_optimized_storage_io_stats() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = optimized_backend::StorageConfig {
+        sync_writes: true,
+        use_compression: false,
+        ..wal_config(WalSyncPolicy::Always)
+    };
+    let storage = OptimizedStorage::with_config(temp_dir.path(), config).unwrap();
+    let entry = |data: &[u8]| CacheEntry::new_inline(String::new(), data.to_vec(), vec![], None);
+
+    storage.set("small", entry(b"value")).unwrap();
+    let stats = storage.io_stats();
+    assert_eq!(stats.logical_bytes_written, 5);
+    assert_eq!(stats.files_created, 0);
+    assert_eq!(stats.fsyncs, 1, "the WAL record is synced");
+    // The index row and the log record both cost more than the value itself
+    assert!(stats.write_amplification() > 2.0);
+
+    let large = vec![1u8; 128 * 1024];
+    storage.set("large", entry(&large)).unwrap();
+    storage.set("large", entry(&large)).unwrap();
+    storage.delete("small").unwrap();
+    let stats = storage.io_stats();
+    assert_eq!(stats.logical_bytes_written, 5 + 2 * large.len() as u64);
+    assert_eq!(stats.files_created, 2);
+    assert_eq!(
+        stats.files_deleted, 1,
+        "the rewrite replaces the first file"
+    );
+    assert!(stats.disk_bytes_written > 2 * 2 * large.len() as u64);
+    assert_eq!(stats.fsyncs, 4);
+}
+
+#[test]
+fn test_hot_tier_overflow_keeps_recently_read_entries() {
+    for policy in [HotCachePolicy::Lru, HotCachePolicy::Clock] {
+        let temp_dir = TempDir::new().unwrap();
+        let config = optimized_backend::StorageConfig {
+            hot_cache_size: 100,
+            hot_cache_policy: policy,
+            ..Default::default()
+        };
+        let storage = OptimizedStorage::with_config(temp_dir.path(), config).unwrap();
+        let entry = |key: &str| {
+            CacheEntry::new_inline(key.to_string(), key.as_bytes().to_vec(), vec![], None)
+        };
+        let hot: Vec<String> = (0..20).map(|n| format!("hot-{}", n)).collect();
+        for key in &hot {
+            storage.set(key, entry(key)).unwrap();
         }
 
-        let keys = storage.keys().unwrap();
-        assert_eq!(keys.len(), 5);
+        // Every overflow drops a tenth of the tier while the hot keys stay in use
+        for n in 0..1000 {
+            let key = format!("once-{}", n);
+            storage.set(&key, entry(&key)).unwrap();
+            if n % 10 == 0 {
+                for key in &hot {
+                    storage.get(key).unwrap().unwrap();
+                }
+            }
+        }
+        assert!(storage.stats().hot_cache_size <= 100);
 
-        // Check all keys are present
-        for i in 0..5 {
-            assert!(keys.contains(&format!("key_{}", i)));
+        let hits = storage.stats().hot_hits;
+        for key in &hot {
+            storage.get(key).unwrap().unwrap();
         }
+        assert_eq!(
+            storage.stats().hot_hits - hits,
+            hot.len() as u64,
+            "{:?} evicted entries in use",
+            policy
+        );
     }
+}
 
-    #[test]
-    fn test_ultra_fast_clear() {
+#[test]
+fn test_pinned_entries_stay_in_the_hot_tier() {
+    for policy in [HotCachePolicy::Lru, HotCachePolicy::Clock] {
         let temp_dir = TempDir::new().unwrap();
-        let storage = UltraFastStorage::new(temp_dir.path(), false).unwrap();
+        let config = optimized_backend::StorageConfig {
+            hot_cache_size: 100,
+            hot_cache_policy: policy,
+            ..Default::default()
+        };
+        let storage = OptimizedStorage::with_config(temp_dir.path(), config).unwrap();
+        let entry = |key: &str| {
+            CacheEntry::new_inline(key.to_string(), key.as_bytes().to_vec(), vec![], None)
+        };
+        storage.set_pinned("license", true);
+        storage.set("license", entry("license")).unwrap();
+        // Never read again, so the first to go unless pinned
+        for n in 0..1000 {
+            let key = format!("once-{}", n);
+            storage.set(&key, entry(&key)).unwrap();
+        }
+
+        let hits = storage.stats().hot_hits;
+        storage.get("license").unwrap().unwrap();
+        assert_eq!(storage.stats().hot_hits - hits, 1, "{:?}", policy);
+    }
+}
+
+#[test]
+fn test_redb_storage_conformance() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = RedbStorage::with_config(temp_dir.path(), false, 4).unwrap();
+    conformance::run_all(&storage);
+}
+
+#[test]
+fn test_memory_storage_conformance() {
+    conformance::run_all(&MemoryStorage::new());
+}
 
-        // Add entries
-        for i in 0..3 {
-            let entry = create_test_entry(&format!("key_{}", i), b"test_value");
-            storage.set(&format!("key_{}", i), entry).unwrap();
+#[test]
+fn test_ring_storage_conformance() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = RingStorage::with_config(temp_dir.path(), 1024 * 1024, false).unwrap();
+    // Key aliases are not supported by the ring
+    conformance::check_set_get(&storage);
+    conformance::check_overwrite(&storage);
+    conformance::check_large_values(&storage);
+    conformance::check_missing_keys(&storage);
+    conformance::check_delete(&storage);
+    conformance::check_versions(&storage);
+    conformance::check_keys(&storage);
+    conformance::check_set_batch(&storage);
+    conformance::check_get_many(&storage);
+    conformance::check_expiry_metadata(&storage);
+    conformance::check_vacuum(&storage);
+    conformance::check_store_order(&storage);
+    conformance::check_key_pages(&storage);
+    conformance::check_key_range(&storage);
+    conformance::check_clear(&storage);
+}
+
+#[test]
+fn test_ring_storage_overwrites_oldest_entries_in_a_fixed_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let ring_file = temp_dir.path().join(ring_backend::RING_FILE_NAME);
+    let capacity = 16 * 1024;
+    {
+        let storage = RingStorage::with_config(temp_dir.path(), capacity, false).unwrap();
+        let file_len = std::fs::metadata(&ring_file).unwrap().len();
+        // Values of varying sizes wrap the ring many times over
+        for i in 0..2000usize {
+            let key = format!("event:{:04}", i);
+            let entry =
+                CacheEntry::new_inline(key.clone(), vec![i as u8; 50 + i % 300], vec![], None);
+            storage.set(&key, entry).unwrap();
+            if i % 7 == 0 {
+                storage.delete(&format!("event:{:04}", i - i / 7)).unwrap();
+            }
         }
+        assert_eq!(std::fs::metadata(&ring_file).unwrap().len(), file_len);
+        assert!(storage.exists("event:1999").unwrap());
+        assert!(!storage.exists("event:0000").unwrap());
+    }
+
+    let storage = RingStorage::with_config(temp_dir.path(), capacity, false).unwrap();
+    let keys = storage.keys().unwrap();
+    assert!(!keys.is_empty() && keys.len() < 2000);
+    // What survives is the newest stretch of writes, every one readable
+    let oldest: usize = keys[0]["event:".len()..].parse().unwrap();
+    for key in &keys {
+        let i: usize = key["event:".len()..].parse().unwrap();
+        assert!(i >= oldest);
+        let entry = storage.get(key).unwrap().unwrap();
+        assert_eq!(entry.get_data(), Some(&vec![i as u8; 50 + i % 300][..]));
+    }
+    assert_eq!(
+        storage.peek_key(true).unwrap().as_deref(),
+        Some("event:1999")
+    );
+    assert!(storage.get("event:1995").unwrap().is_some());
+
+    // The capacity is fixed when the ring is created
+    drop(storage);
+    assert!(RingStorage::with_config(temp_dir.path(), capacity * 2, false).is_err());
 
-        assert_eq!(storage.keys().unwrap().len(), 3);
+    let storage = RingStorage::with_config(temp_dir.path(), capacity, false).unwrap();
+    let too_large =
+        CacheEntry::new_inline("big".to_string(), vec![0; capacity as usize], vec![], None);
+    assert!(storage.set("big", too_large).is_err());
+    storage.close_db();
+    assert!(storage.get("event:1999").is_err());
+}
 
-        // Clear all
-        storage.clear().unwrap();
-        assert_eq!(storage.keys().unwrap().len(), 0);
+#[test]
+fn test_redb_storage_reopen_keeps_entries_and_order() {
+    let temp_dir = TempDir::new().unwrap();
+    {
+        let storage = RedbStorage::with_config(temp_dir.path(), false, 100).unwrap();
+        for i in 0..1000 {
+            let key = format!("tiny:{:04}", i);
+            let entry = CacheEntry::new_inline(key.clone(), vec![i as u8], vec![], None);
+            storage.set(&key, entry).unwrap();
+        }
+        storage.delete("tiny:0000").unwrap();
+        // Dropping syncs the commits the batch interval left unsynced
     }
+
+    let storage = RedbStorage::new(temp_dir.path()).unwrap();
+    assert_eq!(storage.keys().unwrap().len(), 999);
+    assert_eq!(
+        storage.peek_key(false).unwrap().as_deref(),
+        Some("tiny:0001")
+    );
+    assert_eq!(
+        storage.peek_key(true).unwrap().as_deref(),
+        Some("tiny:0999")
+    );
+    let entry = storage.get("tiny:0500").unwrap().unwrap();
+    assert_eq!(entry.get_data(), Some(&[(500 % 256) as u8][..]));
+    // No file per key: the database is the only thing in the directory
+    assert!(!temp_dir.path().join("data").exists());
+
+    storage.close_db();
+    assert!(storage.get("tiny:0500").is_err());
 }
 
-#[cfg(test)]
-mod hybrid_storage_tests {
-    use super::*;
-    use crate::storage::hybrid_backend::HybridStorage;
+fn packing_config() -> optimized_backend::StorageConfig {
+    optimized_backend::StorageConfig {
+        disk_write_threshold: 16,
+        pack_threshold: 256 * 1024,
+        segment_size: 4096,
+        use_compression: false,
+        ..Default::default()
+    }
+}
 
-    #[test]
-    fn test_hybrid_small_data() {
-        let temp_dir = TempDir::new().unwrap();
-        let storage = HybridStorage::new(temp_dir.path(), 1024).unwrap(); // 1KB threshold
+#[test]
+fn test_optimized_storage_conformance_with_packing() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = OptimizedStorage::with_config(temp_dir.path(), packing_config()).unwrap();
+    conformance::run_all(&storage);
+}
 
-        let small_data = vec![0u8; 512]; // 512 bytes - should go to memory
-        let entry = create_test_entry("small_key", &small_data);
+#[test]
+fn test_optimized_storage_keeps_entry_metadata_in_every_tier() {
+    let temp_dir = TempDir::new().unwrap();
+    let values = [
+        ("inline", b"tiny".to_vec()),
+        ("packed", vec![1; 1000]),
+        ("file", vec![2; 300 * 1024]),
+    ];
+    {
+        let storage = OptimizedStorage::with_config(temp_dir.path(), packing_config()).unwrap();
+        for (key, data) in &values {
+            let mut entry = CacheEntry::new_inline(
+                key.to_string(),
+                data.clone(),
+                vec!["tag".to_string(), key.to_string()],
+                Some(4_000_000_000),
+            );
+            entry.created_at = 1_000;
+            entry.access_count = 3;
+            storage.set(key, entry).unwrap();
+        }
+    }
 
-        storage.set("small_key", entry).unwrap();
-        let retrieved = storage.get("small_key").unwrap();
-        assert!(retrieved.is_some());
+    // Read back from the index of a fresh instance, then from its hot tier
+    let storage = OptimizedStorage::with_config(temp_dir.path(), packing_config()).unwrap();
+    for _ in 0..2 {
+        for (key, data) in &values {
+            let entry = storage.get(key).unwrap().unwrap();
+            assert_eq!(entry.get_data(), Some(&data[..]), "{}", key);
+            assert_eq!(entry.tags, vec!["tag".to_string(), key.to_string()]);
+            assert_eq!(entry.expire_time, Some(4_000_000_000));
+            assert_eq!((entry.created_at, entry.access_count), (1_000, 3));
+        }
+    }
+}
+
+#[test]
+fn test_optimized_storage_never_serves_expired_entries() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = OptimizedStorage::with_config(temp_dir.path(), packing_config()).unwrap();
+    let past = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        - 1;
+    let values = [
+        ("inline", b"tiny".to_vec()),
+        ("packed", vec![1; 1000]),
+        ("file", vec![2; 300 * 1024]),
+    ];
+    let set_expired = |key: &str, data: &[u8]| {
+        let entry = CacheEntry::new_inline(key.to_string(), data.to_vec(), vec![], Some(past));
+        storage.set(key, entry).unwrap();
+    };
 
-        let retrieved_entry = retrieved.unwrap();
-        assert_eq!(retrieved_entry.key, "small_key");
+    for (key, data) in &values {
+        set_expired(key, data);
+        assert!(storage.get(key).unwrap().is_none(), "{}", key);
+        // The lazily evicted row is gone, and its file with it
+        assert!(!storage.exists(key).unwrap(), "{}", key);
     }
+    assert_eq!(storage.size().unwrap(), TierSizes::default());
 
-    #[test]
-    fn test_hybrid_large_data() {
-        let temp_dir = TempDir::new().unwrap();
-        let storage = HybridStorage::new(temp_dir.path(), 1024).unwrap(); // 1KB threshold
+    let keys: Vec<String> = values.iter().map(|(key, _)| key.to_string()).collect();
+    for (key, data) in &values {
+        set_expired(key, data);
+    }
+    assert!(storage.get_many(&keys).unwrap().iter().all(Option::is_none));
+    for (key, data) in &values {
+        set_expired(key, data);
+    }
+    assert_eq!(storage.prefetch_prefix("").unwrap(), 0);
+}
+
+#[test]
+fn test_optimized_storage_counts_live_bytes_per_tier() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = OptimizedStorage::with_config(temp_dir.path(), packing_config()).unwrap();
+    let sizes = |storage: &OptimizedStorage| storage.size().unwrap();
+    let set = |key: &str, data: &[u8]| {
+        let entry = CacheEntry::new_inline(key.to_string(), data.to_vec(), vec![], None);
+        storage.set(key, entry).unwrap();
+    };
+
+    set("inline", b"tiny");
+    set("packed", &[1; 1000]);
+    let large = vec![2; 300 * 1024];
+    set("file", &large);
+    let header = data_file::DataFileHeader::new("file", Compression::None).encoded_len() as u64;
+    assert_eq!(
+        sizes(&storage),
+        TierSizes {
+            inline: 4,
+            packed: 1000,
+            files: large.len() as u64 + header,
+        }
+    );
+
+    // Overwrites replace the old bytes, in whichever tier they were
+    set("packed", &[3; 10]);
+    set("inline", &[4; 2000]);
+    assert_eq!(sizes(&storage).inline, 10);
+    assert_eq!(sizes(&storage).packed, 2000);
+
+    assert!(storage.delete("file").unwrap());
+    assert_eq!(sizes(&storage).files, 0);
+    drop(storage);
+
+    let reopened = OptimizedStorage::with_config(temp_dir.path(), packing_config()).unwrap();
+    assert_eq!(sizes(&reopened).total(), 2010);
+    reopened.clear().unwrap();
+    assert_eq!(sizes(&reopened), TierSizes::default());
+}
 
-        let large_data = vec![0u8; 2048]; // 2KB - should go to disk
-        let entry = create_test_entry("large_key", &large_data);
+#[test]
+fn test_packed_segments_are_compacted() {
+    let temp_dir = TempDir::new().unwrap();
+    let value = |i: usize, round: u8| vec![(i as u8).wrapping_add(round); 512];
+    let entry = |data: Vec<u8>| CacheEntry::new_inline(String::new(), data, vec![], None);
+    let segment_count = || {
+        std::fs::read_dir(temp_dir.path().join(segment::SEGMENT_DIR))
+            .map(|entries| entries.count())
+            .unwrap_or(0)
+    };
 
-        storage.set("large_key", entry).unwrap();
-        let retrieved = storage.get("large_key").unwrap();
-        assert!(retrieved.is_some());
+    {
+        let storage = OptimizedStorage::with_config(temp_dir.path(), packing_config()).unwrap();
+        for i in 0..64 {
+            storage
+                .set(&format!("packed:{}", i), entry(value(i, 0)))
+                .unwrap();
+        }
+        // Eight values fill a segment, and no key gets a file of its own
+        assert_eq!(segment_count(), 8);
+        assert_eq!(
+            std::fs::read_dir(temp_dir.path().join("data"))
+                .unwrap()
+                .filter(|entry| entry.as_ref().unwrap().file_name() != ".layout")
+                .count(),
+            0
+        );
 
-        let retrieved_entry = retrieved.unwrap();
-        assert_eq!(retrieved_entry.key, "large_key");
+        // Leave one live value in each of the first four segments
+        for i in 0..32 {
+            match i % 8 {
+                0 => {}
+                1 => storage
+                    .set(&format!("packed:{}", i), entry(value(i, 1)))
+                    .unwrap(),
+                _ => assert!(storage.delete(&format!("packed:{}", i)).unwrap()),
+            }
+        }
+        let version = storage.version("packed:8").unwrap();
+
+        let reclaimed = storage.compact_segments().unwrap();
+        assert_eq!(reclaimed, 4 * (4096 - 512));
+        assert_eq!(segment_count(), 5);
+        assert_eq!(storage.compact_segments().unwrap(), 0);
+        // Moving a value is not a rewrite
+        assert_eq!(storage.version("packed:8").unwrap(), version);
     }
 
-    #[test]
-    fn test_hybrid_mixed_operations() {
-        let temp_dir = TempDir::new().unwrap();
-        let storage = HybridStorage::new(temp_dir.path(), 1024).unwrap();
+    let storage = OptimizedStorage::with_config(temp_dir.path(), packing_config()).unwrap();
+    for i in 0..64 {
+        let stored = storage.get(&format!("packed:{}", i)).unwrap();
+        let expected = match i {
+            0..32 if i % 8 == 0 => Some(value(i, 0)),
+            0..32 if i % 8 == 1 => Some(value(i, 1)),
+            0..32 => None,
+            _ => Some(value(i, 0)),
+        };
+        assert_eq!(
+            stored.and_then(|e| e.get_data().map(<[u8]>::to_vec)),
+            expected
+        );
+    }
+    assert_eq!(storage.verify_and_recover().unwrap().entries_removed, 0);
 
-        // Add small and large data
-        let small_entry = create_test_entry("small", &vec![0u8; 512]);
-        let large_entry = create_test_entry("large", &vec![0u8; 2048]);
+    storage.clear().unwrap();
+    assert_eq!(segment_count(), 0);
+}
 
-        storage.set("small", small_entry).unwrap();
-        storage.set("large", large_entry).unwrap();
+#[test]
+fn test_dead_space_triggers_background_compaction() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = OptimizedStorage::with_config(temp_dir.path(), packing_config()).unwrap();
+    let entry = |byte: u8| CacheEntry::new_inline(String::new(), vec![byte; 512], vec![], None);
 
-        // Both should be retrievable
-        assert!(storage.get("small").unwrap().is_some());
-        assert!(storage.get("large").unwrap().is_some());
+    for i in 0..32 {
+        storage.set(&format!("churn:{}", i), entry(0)).unwrap();
+    }
+    assert_eq!(storage.stats().segment_dead_bytes, 0);
 
-        // Test keys
-        let keys = storage.keys().unwrap();
-        assert_eq!(keys.len(), 2);
-        assert!(keys.contains(&"small".to_string()));
-        assert!(keys.contains(&"large".to_string()));
+    // Rewriting every key leaves the first four segments entirely dead
+    for i in 0..32 {
+        storage.set(&format!("churn:{}", i), entry(1)).unwrap();
+    }
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    while storage.stats().compactions == 0 {
+        assert!(
+            std::time::Instant::now() < deadline,
+            "compaction never started"
+        );
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
 
-        // Test delete
-        assert!(storage.delete("small").unwrap());
-        assert!(storage.delete("large").unwrap());
-        assert_eq!(storage.keys().unwrap().len(), 0);
+    // The background run reclaims whatever it found, and vacuum the rest
+    storage.vacuum().unwrap();
+    let stats = storage.stats();
+    assert_eq!(stats.bytes_reclaimed, 4 * 4096);
+    assert_eq!(stats.segment_dead_bytes, 0);
+    assert_eq!(stats.segment_bytes, 4 * 4096);
+    for i in 0..32 {
+        let stored = storage.get(&format!("churn:{}", i)).unwrap().unwrap();
+        assert_eq!(stored.get_data(), Some(&[1u8; 512][..]));
     }
 }
 
-#[cfg(test)]
-mod file_storage_tests {
-    use super::*;
-    use crate::storage::FileStorage;
+#[test]
+fn test_clear_removes_files_in_parallel_with_progress() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = optimized_backend::StorageConfig {
+        disk_write_threshold: 16,
+        unlink_workers: 4,
+        ..Default::default()
+    };
+    let storage = OptimizedStorage::with_config(temp_dir.path(), config).unwrap();
+    let entries = (0..400)
+        .map(|i| (format!("file:{}", i), vec![i as u8; 64]))
+        .collect();
+    storage.set_batch(entries).unwrap();
+    let files_deleted = storage.io_stats().files_deleted;
 
-    #[test]
-    fn test_file_storage_basic() {
-        let temp_dir = TempDir::new().unwrap();
-        let storage = FileStorage::new(temp_dir.path(), true, false, false).unwrap();
+    let reports = parking_lot::Mutex::new(Vec::new());
+    storage
+        .clear_with_progress(&|progress| reports.lock().push(progress))
+        .unwrap();
+
+    let last = *reports.lock().last().expect("no progress reported");
+    assert_eq!((last.total, last.removed, last.failed), (400, 400, 0));
+    assert_eq!(storage.io_stats().files_deleted, files_deleted + 400);
+    assert!(storage.keys().unwrap().is_empty());
+    let mut pending = vec![temp_dir.path().join("data")];
+    while let Some(directory) = pending.pop() {
+        for entry in std::fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                pending.push(path);
+            } else {
+                assert_ne!(path.extension(), Some("dat".as_ref()));
+            }
+        }
+    }
+}
 
-        let entry = create_test_entry("test_key", b"test_value");
+#[test]
+fn test_lost_index_is_rebuilt_from_data_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = || optimized_backend::StorageConfig {
+        disk_write_threshold: 0,
+        ..Default::default()
+    };
+    let values: Vec<(String, Vec<u8>)> = (0..10)
+        .map(|i| (format!("key{}", i), vec![i as u8; 4096]))
+        .collect();
+    {
+        let storage = OptimizedStorage::with_config(temp_dir.path(), config()).unwrap();
+        for (key, value) in &values {
+            let entry = CacheEntry::new_inline(key.clone(), value.clone(), vec![], None);
+            storage.set(key, entry).unwrap();
+        }
+    }
+    for name in ["index.sqlite3", "index.sqlite3-wal", "index.sqlite3-shm"] {
+        let _ = std::fs::remove_file(temp_dir.path().join(name));
+    }
 
-        storage.set("test_key", entry).unwrap();
-        let retrieved = storage.get("test_key").unwrap();
-        assert!(retrieved.is_some());
+    let storage = OptimizedStorage::with_config(temp_dir.path(), config()).unwrap();
+    for (key, value) in &values {
+        let entry = storage
+            .get(key)
+            .unwrap()
+            .expect("key restored from its data file");
+        assert_eq!(entry.get_data().unwrap(), value.as_slice());
+    }
 
-        assert!(storage.exists("test_key").unwrap());
-        assert!(storage.delete("test_key").unwrap());
-        assert!(!storage.exists("test_key").unwrap());
+    // Rows lost from a live index come back through verify_and_recover
+    let conn = rusqlite::Connection::open(temp_dir.path().join("index.sqlite3")).unwrap();
+    conn.execute("DELETE FROM cache_index WHERE key = 'key3'", [])
+        .unwrap();
+    let report = storage.verify_and_recover().unwrap();
+    assert_eq!(report.entries_restored, 1);
+    assert!(storage.exists("key3").unwrap());
+    assert_eq!(storage.verify_and_recover().unwrap().entries_restored, 0);
+}
+
+#[test]
+fn test_vacuum_collects_orphaned_data_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = |orphan_grace| optimized_backend::StorageConfig {
+        disk_write_threshold: 0,
+        orphan_grace,
+        ..Default::default()
+    };
+    let storage =
+        OptimizedStorage::with_config(temp_dir.path(), config(std::time::Duration::ZERO)).unwrap();
+    for i in 0..5 {
+        let entry = CacheEntry::new_inline(format!("key{}", i), vec![i; 4096], vec![], None);
+        storage.set(&format!("key{}", i), entry).unwrap();
     }
+    let data_dir = temp_dir.path().join("data");
+    std::fs::write(data_dir.join("0123456789abcdef.dat"), b"no header").unwrap();
+    std::fs::write(data_dir.join("0123456789abcdef.42-0.tmp"), b"half written").unwrap();
+    let conn = rusqlite::Connection::open(temp_dir.path().join("index.sqlite3")).unwrap();
+    conn.execute("DELETE FROM cache_index WHERE key = 'key3'", [])
+        .unwrap();
 
-    #[test]
-    fn test_file_storage_persistence() {
-        let temp_dir = TempDir::new().unwrap();
-        let entry = create_test_entry("persist_key", b"persist_value");
+    // Nothing is old enough under the default grace period
+    let patient = OptimizedStorage::with_config(
+        temp_dir.path(),
+        config(optimized_backend::StorageConfig::default().orphan_grace),
+    )
+    .unwrap();
+    let report = patient.vacuum().unwrap();
+    assert_eq!(report.orphans_removed + report.orphans_adopted, 0);
+    assert_eq!(report.temp_files_removed, 0);
+    drop(patient);
+
+    let report = storage.vacuum().unwrap();
+    assert_eq!(report.orphans_removed, 1);
+    assert_eq!(report.orphans_adopted, 1);
+    assert_eq!(report.temp_files_removed, 1);
+    assert!(!data_dir.join("0123456789abcdef.dat").exists());
+    for i in 0..5 {
+        let entry = storage.get(&format!("key{}", i)).unwrap().unwrap();
+        assert_eq!(entry.get_data().unwrap(), vec![i; 4096].as_slice());
+    }
+
+    let report = storage.vacuum().unwrap();
+    assert_eq!(report.orphans_removed + report.orphans_adopted, 0);
+}
+
+#[test]
+fn test_vacuum_reclaims_space_values_no_longer_use() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = OptimizedStorage::with_config(
+        temp_dir.path(),
+        optimized_backend::StorageConfig {
+            disk_write_threshold: 0,
+            pack_threshold: 16 * 1024,
+            segment_size: 64 * 1024,
+            compaction_ratio: 1.0,
+            use_compression: false,
+            orphan_grace: std::time::Duration::ZERO,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let value = |i: u8, len: usize| vec![i; len];
+    let set = |key: &str, data: Vec<u8>| {
+        let entry = CacheEntry::new_inline(key.into(), data, vec![], None);
+        storage.set(key, entry).unwrap();
+    };
+
+    // A data file left longer than its value, as by a crash mid-write
+    set("big", value(0xaa, 64 * 1024));
+    let (path, _) = storage.data_file_path("big").unwrap().unwrap();
+    let len = std::fs::metadata(&path).unwrap().len();
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap();
+    std::io::Write::write_all(&mut file, &[0; 8192]).unwrap();
+    drop(file);
+
+    // A sealed segment with a run of deleted values, too live to compact
+    for i in 0..8 {
+        set(&format!("packed{}", i), value(i, 8 * 1024));
+    }
+    for i in 2..6 {
+        storage.delete(&format!("packed{}", i)).unwrap();
+    }
+    let segment_len = storage.stats().segment_bytes;
+
+    storage.clear_memory_tiers();
+    let report = storage.vacuum().unwrap();
+    assert_eq!(report.files_truncated, 1);
+    assert!(report.bytes_reclaimed >= 8192);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+    assert_eq!(
+        storage.get("big").unwrap().unwrap().get_data(),
+        Some(&value(0xaa, 64 * 1024)[..])
+    );
+    for i in [0, 1, 6, 7] {
+        let entry = storage.get(&format!("packed{}", i)).unwrap().unwrap();
+        assert_eq!(entry.get_data(), Some(&value(i, 8 * 1024)[..]));
+    }
+    // Holes keep every offset, so nothing was moved or compacted
+    assert_eq!(storage.stats().segment_bytes, segment_len);
+    assert_eq!(storage.stats().bytes_reclaimed, 0);
+
+    let report = storage.vacuum().unwrap();
+    assert_eq!(report.files_truncated, 0);
+    assert_eq!(report.bytes_reclaimed, 0);
+}
+
+#[test]
+fn test_registered_files_are_served_but_never_removed() {
+    let temp_dir = TempDir::new().unwrap();
+    let assets = TempDir::new().unwrap();
+    let logo = assets.path().join("logo.png");
+    let readme = assets.path().join("readme.txt");
+    std::fs::write(&logo, [0x89, b'P', b'N', b'G']).unwrap();
+    std::fs::write(&readme, b"hello").unwrap();
+
+    let registered = {
+        let storage = OptimizedStorage::new(temp_dir.path()).unwrap();
+        storage
+            .register_files(&[
+                ("logo".to_string(), logo.clone()),
+                ("readme".to_string(), readme.clone()),
+            ])
+            .unwrap()
+    };
+    assert_eq!(registered, 2);
+
+    // Registrations are persisted like any other entry
+    let storage = OptimizedStorage::new(temp_dir.path()).unwrap();
+    let entry = storage.get("logo").unwrap().expect("registered file");
+    assert_eq!(entry.get_data().unwrap(), [0x89, b'P', b'N', b'G']);
+    assert_eq!(
+        storage.data_file_path("readme").unwrap(),
+        Some((readme.clone(), 0))
+    );
+
+    assert!(storage.delete("logo").unwrap());
+    assert!(!storage.exists("logo").unwrap());
+    assert!(logo.exists());
+
+    // Overwriting a registered key writes a data file of the cache's own
+    storage
+        .set(
+            "readme",
+            CacheEntry::new_inline("readme".into(), vec![7; 64], vec![], None),
+        )
+        .unwrap();
+    storage.clear().unwrap();
+    assert_eq!(std::fs::read(&readme).unwrap(), b"hello");
+
+    let missing = assets.path().join("missing.bin");
+    assert!(storage
+        .register_files(&[("missing".to_string(), missing)])
+        .is_err());
+    assert!(!storage.exists("missing").unwrap());
+}
+
+#[test]
+fn test_corrupted_values_are_reported_and_dropped() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = OptimizedStorage::with_config(
+        temp_dir.path(),
+        optimized_backend::StorageConfig {
+            disk_write_threshold: 0,
+            pack_threshold: 1024,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let file_value: Vec<u8> = (0..8192u32).map(|i| (i * 7) as u8).collect();
+    storage
+        .set(
+            "file",
+            CacheEntry::new_inline("file".into(), file_value, vec![], None),
+        )
+        .unwrap();
+    storage
+        .set(
+            "packed",
+            CacheEntry::new_inline("packed".into(), vec![3; 100], vec![], None),
+        )
+        .unwrap();
+    storage.vacuum().unwrap();
+
+    // Flip the last byte of the data file and of the segment holding the packed value
+    let flip_last_byte = |path: &std::path::Path| {
+        let mut bytes = std::fs::read(path).unwrap();
+        *bytes.last_mut().unwrap() ^= 0xff;
+        std::fs::write(path, bytes).unwrap();
+    };
+    let (data_file, _) = storage.data_file_path("file").unwrap().unwrap();
+    flip_last_byte(&data_file);
+    let segment = std::fs::read_dir(temp_dir.path().join("segments"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .next()
+        .unwrap();
+    flip_last_byte(&segment);
+
+    // Reopen so neither value is served from memory
+    drop(storage);
+    let storage = OptimizedStorage::new(temp_dir.path()).unwrap();
+    for key in ["file", "packed"] {
+        assert!(matches!(
+            storage.get(key),
+            Err(CacheError::Corrupted(corrupted)) if corrupted == key
+        ));
+        assert!(!storage.exists(key).unwrap());
+        assert!(storage.get(key).unwrap().is_none());
+    }
+    assert!(!data_file.exists());
+}
+
+#[test]
+fn test_truncated_data_files_are_reported_and_dropped() {
+    let temp_dir = TempDir::new().unwrap();
+    let value: Vec<u8> = (0..8192u32).map(|i| (i * 7) as u8).collect();
+    let config = || optimized_backend::StorageConfig {
+        disk_write_threshold: 0,
+        pack_threshold: 1024,
+        use_compression: false,
+        ..Default::default()
+    };
+    let storage = OptimizedStorage::with_config(temp_dir.path(), config()).unwrap();
+    for key in ["payload", "header"] {
+        let entry = CacheEntry::new_inline(key.into(), value.clone(), vec![], None);
+        storage.set(key, entry).unwrap();
+    }
+
+    // An interrupted write leaves part of the payload, or of the header
+    let (payload_file, offset) = storage.data_file_path("payload").unwrap().unwrap();
+    let (header_file, _) = storage.data_file_path("header").unwrap().unwrap();
+    let truncate = |path: &std::path::Path, len: u64| {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_len(len)
+            .unwrap();
+    };
+    truncate(&payload_file, offset + 100);
+    truncate(&header_file, 12);
+    assert!(storage.data_file_path("payload").unwrap().is_none());
+
+    drop(storage);
+    let storage = OptimizedStorage::with_config(temp_dir.path(), config()).unwrap();
+    for key in ["payload", "header"] {
+        assert!(matches!(
+            storage.get(key),
+            Err(CacheError::Corrupted(corrupted)) if corrupted == key
+        ));
+        assert!(!storage.exists(key).unwrap());
+    }
+    assert!(!payload_file.exists());
+}
+
+#[test]
+fn test_truncated_values_are_restored_from_the_wal() {
+    let temp_dir = TempDir::new().unwrap();
+    let value: Vec<u8> = (0..8192u32).map(|i| (i * 7) as u8).collect();
+    let config = optimized_backend::StorageConfig {
+        disk_write_threshold: 0,
+        pack_threshold: 1024,
+        use_compression: false,
+        ..wal_config(WalSyncPolicy::Always)
+    };
+    let storage = OptimizedStorage::with_config(temp_dir.path(), config).unwrap();
+    let entry = CacheEntry::new_inline("file".into(), value.clone(), vec!["shots".into()], None);
+    storage.set("file", entry).unwrap();
+    storage.delete("gone").unwrap();
+
+    let (path, offset) = storage.data_file_path("file").unwrap().unwrap();
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(offset + 10)
+        .unwrap();
+    storage.clear_memory_tiers();
+
+    let entry = storage.get("file").unwrap().unwrap();
+    assert_eq!(entry.get_data(), Some(&value[..]));
+    assert_eq!(entry.tags, vec!["shots".to_string()]);
+    storage.clear_memory_tiers();
+    let entry = storage.get("file").unwrap().unwrap();
+    assert_eq!(entry.get_data(), Some(&value[..]));
+}
+
+#[test]
+fn test_mapped_files_are_remapped_once_replaced() {
+    let temp_dir = TempDir::new().unwrap();
+    let value =
+        |fill: u8| -> Vec<u8> { (0..128 * 1024u32).map(|i| (i % 251) as u8 ^ fill).collect() };
+    let config = || optimized_backend::StorageConfig {
+        disk_write_threshold: 0,
+        use_compression: false,
+        ..Default::default()
+    };
+    let storage = OptimizedStorage::with_config(temp_dir.path(), config()).unwrap();
+    let other = OptimizedStorage::with_config(temp_dir.path(), config()).unwrap();
+    let get = |storage: &OptimizedStorage| storage.get("key").unwrap().unwrap();
+
+    storage
+        .set(
+            "key",
+            CacheEntry::new_inline("key".into(), value(1), vec![], None),
+        )
+        .unwrap();
+    storage.clear_memory_tiers();
+    for _ in 0..3 {
+        assert_eq!(get(&storage).get_data(), Some(&value(1)[..]));
+    }
+    let stats = storage.stats();
+    assert_eq!((stats.cold_hits, stats.warm_hits), (1, 2));
+
+    // Replaced by another handle, the file no longer matches the mapping
+    other
+        .set(
+            "key",
+            CacheEntry::new_inline("key".into(), value(2), vec![], None),
+        )
+        .unwrap();
+    assert_eq!(get(&storage).get_data(), Some(&value(2)[..]));
+
+    // Rewritten in place with the same length, it is mapped again and
+    // refused for no longer holding the payload the index expects
+    let (path, offset) = storage.data_file_path("key").unwrap().unwrap();
+    let mut contents = std::fs::read(&path).unwrap();
+    contents[offset as usize] ^= 0xff;
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    std::fs::write(&path, &contents).unwrap();
+    assert!(matches!(
+        storage.get("key"),
+        Err(CacheError::Corrupted(corrupted)) if corrupted == "key"
+    ));
+
+    // Without a threshold nothing is mapped
+    let storage = OptimizedStorage::with_config(
+        temp_dir.path(),
+        optimized_backend::StorageConfig {
+            mmap_threshold: 0,
+            ..config()
+        },
+    )
+    .unwrap();
+    storage
+        .set(
+            "key",
+            CacheEntry::new_inline("key".into(), value(3), vec![], None),
+        )
+        .unwrap();
+    storage.clear_memory_tiers();
+    for _ in 0..2 {
+        assert_eq!(get(&storage).get_data(), Some(&value(3)[..]));
+    }
+    assert_eq!(storage.stats().warm_hits, 0);
+}
+
+#[test]
+fn test_warm_tier_unmaps_the_least_recently_read_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let value: Vec<u8> = (0..128 * 1024u32).map(|i| (i % 251) as u8).collect();
+    let storage = OptimizedStorage::with_config(
+        temp_dir.path(),
+        optimized_backend::StorageConfig {
+            disk_write_threshold: 0,
+            use_compression: false,
+            warm_cache_size: 3,
+            warm_cache_bytes: 10 * value.len() as u64,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    for key in ["a", "b", "c", "d"] {
+        storage
+            .set(
+                key,
+                CacheEntry::new_inline(key.into(), value.clone(), vec![], None),
+            )
+            .unwrap();
+    }
+    storage.clear_memory_tiers();
+    let warm_hits = |key: &str| {
+        let before = storage.stats().warm_hits;
+        storage.get(key).unwrap().unwrap();
+        storage.stats().warm_hits - before
+    };
+
+    // Stored first but read after "b", "a" outlasts it
+    for key in ["b", "a", "c", "d"] {
+        warm_hits(key);
+    }
+    assert_eq!(storage.stats().warm_cache_size, 3);
+    assert_eq!(warm_hits("a"), 1);
+    assert_eq!(warm_hits("b"), 0);
+}
+
+#[test]
+fn test_peek_leaves_the_hot_and_warm_order_alone() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = OptimizedStorage::with_config(
+        temp_dir.path(),
+        optimized_backend::StorageConfig {
+            hot_cache_size: 10,
+            hot_cache_policy: HotCachePolicy::Lru,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let entry =
+        |key: &str| CacheEntry::new_inline(key.into(), key.as_bytes().to_vec(), vec![], None);
+    for n in 0..10 {
+        let key = format!("key{}", n);
+        storage.set(&key, entry(&key)).unwrap();
+    }
+    storage.peek("key0").unwrap().unwrap();
+    // The overflow drops the two stored first, peeked or not
+    storage.set("key10", entry("key10")).unwrap();
+    assert_eq!(storage.tiers_holding("key0"), (false, false));
+    assert!(storage.tiers_holding("key2").0);
+    storage.peek("key0").unwrap().unwrap();
+    assert!(!storage.tiers_holding("key0").0);
+
+    let temp_dir = TempDir::new().unwrap();
+    let value: Vec<u8> = (0..128 * 1024u32).map(|i| (i % 251) as u8).collect();
+    let storage = OptimizedStorage::with_config(
+        temp_dir.path(),
+        optimized_backend::StorageConfig {
+            disk_write_threshold: 0,
+            use_compression: false,
+            warm_cache_size: 3,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    for key in ["a", "b", "c", "d"] {
+        storage
+            .set(
+                key,
+                CacheEntry::new_inline(key.into(), value.clone(), vec![], None),
+            )
+            .unwrap();
+    }
+    storage.clear_memory_tiers();
+    for key in ["a", "b", "c"] {
+        storage.get(key).unwrap().unwrap();
+    }
+    assert_eq!(
+        storage.peek("a").unwrap().unwrap().get_data(),
+        Some(&value[..])
+    );
+    storage.peek("d").unwrap().unwrap();
+    assert_eq!(storage.tiers_holding("d"), (false, false));
+    // Read first and only peeked since, "a" is the one unmapped
+    storage.get("d").unwrap().unwrap();
+    assert_eq!(storage.tiers_holding("a"), (false, false));
+    assert!(storage.tiers_holding("b").1);
+}
+
+#[test]
+fn test_prefetch_leaves_files_the_warm_tier_maps() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = OptimizedStorage::with_config(
+        temp_dir.path(),
+        optimized_backend::StorageConfig {
+            disk_write_threshold: 1024,
+            use_compression: false,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let set = |key: &str, size: usize| {
+        let entry = CacheEntry::new_inline(key.into(), vec![7; size], vec![], None);
+        storage.set(key, entry).unwrap();
+    };
+    for key in ["p/small0", "p/small1", "p/small2"] {
+        set(key, 4 * 1024);
+    }
+    set("p/large", 256 * 1024);
+    storage.clear_memory_tiers();
+
+    assert_eq!(storage.prefetch_prefix("p/").unwrap(), 3);
+    assert!(storage.tiers_holding("p/small0").0);
+    assert_eq!(storage.tiers_holding("p/large"), (false, false));
+}
+
+#[test]
+fn test_warm_tier_is_bounded_by_bytes() {
+    let temp_dir = TempDir::new().unwrap();
+    let value: Vec<u8> = (0..128 * 1024u32).map(|i| (i % 251) as u8).collect();
+    let storage = OptimizedStorage::with_config(
+        temp_dir.path(),
+        optimized_backend::StorageConfig {
+            disk_write_threshold: 0,
+            use_compression: false,
+            warm_cache_bytes: 2 * value.len() as u64,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    for i in 0..5 {
+        let key = format!("key{}", i);
+        storage
+            .set(
+                &key,
+                CacheEntry::new_inline(key.clone(), value.clone(), vec![], None),
+            )
+            .unwrap();
+    }
+    storage.clear_memory_tiers();
+    for i in 0..5 {
+        storage.get(&format!("key{}", i)).unwrap().unwrap();
+        assert!(storage.stats().warm_cache_size <= 2);
+    }
+}
+
+#[test]
+fn test_watched_data_files_leave_the_memory_tiers_when_removed() {
+    let temp_dir = TempDir::new().unwrap();
+    let value: Vec<u8> = (0..128 * 1024u32).map(|i| (i % 251) as u8).collect();
+    let config = |watch_directory| optimized_backend::StorageConfig {
+        disk_write_threshold: 0,
+        use_compression: false,
+        watch_directory,
+        ..Default::default()
+    };
+    let storage = OptimizedStorage::with_config(temp_dir.path(), config(true)).unwrap();
+    let other = OptimizedStorage::with_config(temp_dir.path(), config(false)).unwrap();
+
+    storage
+        .set(
+            "key",
+            CacheEntry::new_inline("key".into(), value.clone(), vec![], None),
+        )
+        .unwrap();
+    storage.clear_memory_tiers();
+    for _ in 0..2 {
+        storage.get("key").unwrap().unwrap();
+    }
+    assert_eq!(storage.stats().warm_cache_size, 1);
 
-        // Create storage, add entry, and drop it
-        {
-            let storage = FileStorage::new(temp_dir.path(), true, false, false).unwrap();
-            storage.set("persist_key", entry).unwrap();
+    // Removed by another handle, the mapping goes without a read of the key
+    other.delete("key").unwrap();
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while storage.stats().warm_cache_size > 0 && std::time::Instant::now() < deadline {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert_eq!(storage.stats().warm_cache_size, 0);
+    assert!(storage.get("key").unwrap().is_none());
+}
+
+#[test]
+fn test_logged_events_leave_the_memory_tiers_of_other_handles() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = || optimized_backend::StorageConfig {
+        event_log: true,
+        ..Default::default()
+    };
+    let storage = OptimizedStorage::with_config(temp_dir.path(), config()).unwrap();
+    let other = OptimizedStorage::with_config(temp_dir.path(), config()).unwrap();
+    let set = |storage: &OptimizedStorage, key: &str, value: &[u8]| {
+        storage
+            .set(
+                key,
+                CacheEntry::new_inline(key.into(), value.to_vec(), vec![], None),
+            )
+            .unwrap()
+    };
+
+    set(&storage, "kept", b"value");
+    set(&storage, "changed", b"old");
+    for key in ["kept", "changed"] {
+        storage.get(key).unwrap().unwrap();
+    }
+    assert_eq!(storage.stats().hot_cache_size, 2);
+
+    // Only the key another handle wrote goes, once this one reads anything
+    set(&other, "changed", b"new");
+    assert_eq!(
+        storage.get("kept").unwrap().unwrap().get_data(),
+        Some(&b"value"[..])
+    );
+    assert_eq!(storage.stats().hot_cache_size, 1);
+    assert_eq!(
+        storage.get("changed").unwrap().unwrap().get_data(),
+        Some(&b"new"[..])
+    );
+
+    // A clear empties the memory tiers of every handle
+    other.clear().unwrap();
+    assert!(storage.get("kept").unwrap().is_none());
+    assert_eq!(storage.stats().hot_cache_size, 0);
+}
+
+#[test]
+fn test_data_files_overwritten_by_another_key_are_refused() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = || optimized_backend::StorageConfig {
+        disk_write_threshold: 0,
+        ..Default::default()
+    };
+    let storage = OptimizedStorage::with_config(temp_dir.path(), config()).unwrap();
+    for key in ["first", "second"] {
+        let entry =
+            CacheEntry::new_inline(key.to_string(), key.repeat(1000).into_bytes(), vec![], None);
+        storage.set(key, entry).unwrap();
+    }
+    let (first, _) = storage.data_file_path("first").unwrap().unwrap();
+    let (second, _) = storage.data_file_path("second").unwrap().unwrap();
+    let hash = blake3::hash(b"first").to_hex();
+    assert_eq!(first.file_name().unwrap(), format!("{}.dat", hash).as_str());
+
+    // What a write of "second" under a name shared with "first" would leave
+    std::fs::copy(&second, &first).unwrap();
+    drop(storage);
+    let storage = OptimizedStorage::with_config(temp_dir.path(), config()).unwrap();
+    assert!(storage.data_file_path("first").unwrap().is_none());
+    assert!(matches!(
+        storage.get("first"),
+        Err(CacheError::Collided(collided)) if collided == "first"
+    ));
+    assert!(!storage.exists("first").unwrap());
+    // The file is the other key's, so it stays
+    assert!(first.exists());
+    let entry = storage.get("second").unwrap().unwrap();
+    assert_eq!(entry.get_data().unwrap(), "second".repeat(1000).as_bytes());
+}
+
+#[test]
+fn test_data_files_are_named_by_the_configured_scheme() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = |file_naming| optimized_backend::StorageConfig {
+        disk_write_threshold: 0,
+        file_naming,
+        ..Default::default()
+    };
+    let set = |storage: &OptimizedStorage, key: &str| {
+        let entry = CacheEntry::new_inline(key.to_string(), vec![7; 4096], vec![], None);
+        storage.set(key, entry).unwrap();
+    };
+
+    let storage = OptimizedStorage::with_config(temp_dir.path(), config(FileNaming::Slug)).unwrap();
+    set(&storage, "user/42.profile");
+    let (slugged, _) = storage.data_file_path("user/42.profile").unwrap().unwrap();
+    let hash = blake3::hash(b"user/42.profile").to_hex();
+    let name = format!("user_42_profile.{}.dat", &hash[..32]);
+    assert_eq!(slugged.file_name().unwrap(), name.as_str());
+    // Fan-out directories still come from the hash
+    let fanout = temp_dir
+        .path()
+        .join("data")
+        .join(&hash[..2])
+        .join(&hash[2..4]);
+    assert_eq!(slugged.parent().unwrap(), fanout);
+    drop(storage);
+
+    // Files keep their names when the scheme changes
+    let storage = OptimizedStorage::with_config(temp_dir.path(), config(FileNaming::Xxh3)).unwrap();
+    set(&storage, "other");
+    let (hashed, _) = storage.data_file_path("other").unwrap().unwrap();
+    let xxh3 = format!("{:032x}.dat", xxhash_rust::xxh3::xxh3_128(b"other"));
+    assert_eq!(hashed.file_name().unwrap(), xxh3.as_str());
+    assert_eq!(
+        storage
+            .get("user/42.profile")
+            .unwrap()
+            .unwrap()
+            .get_data()
+            .unwrap(),
+        &[7; 4096][..]
+    );
+    drop(storage);
+
+    // and are recognised under either scheme when the index is rebuilt
+    for name in ["index.sqlite3", "index.sqlite3-wal", "index.sqlite3-shm"] {
+        let _ = std::fs::remove_file(temp_dir.path().join(name));
+    }
+    let storage =
+        OptimizedStorage::with_config(temp_dir.path(), config(FileNaming::Blake3)).unwrap();
+    assert!(storage.exists("user/42.profile").unwrap());
+    assert!(storage.exists("other").unwrap());
+
+    assert!(matches!(
+        "md5".parse::<FileNaming>(),
+        Err(CacheError::Config(issue)) if issue.option == "file_naming"
+    ));
+}
+
+#[test]
+fn test_values_keep_the_codec_they_were_written_with() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = |compression| optimized_backend::StorageConfig {
+        disk_write_threshold: 16,
+        pack_threshold: 64 * 1024,
+        compression_threshold: 1024,
+        compression,
+        ..Default::default()
+    };
+    let value = |size: usize| b"shot_010 frame ".repeat(size / 15);
+
+    let codecs = Compression::ALL
+        .into_iter()
+        .filter(|codec| *codec != Compression::ZstdDictionary)
+        .collect::<Vec<_>>();
+    for &codec in &codecs {
+        let storage = OptimizedStorage::with_config(temp_dir.path(), config(codec)).unwrap();
+        for (tier, size) in [("packed", 40 * 1024), ("file", 300 * 1024)] {
+            let key = format!("{}-{}", codec.name(), tier);
+            let entry = CacheEntry::new_inline(key.clone(), value(size), vec![], None);
+            storage.set(&key, entry).unwrap();
         }
+        let hash = blake3::hash(format!("{}-file", codec.name()).as_bytes()).to_hex();
+        let path = temp_dir
+            .path()
+            .join("data")
+            .join(&hash[..2])
+            .join(&hash[2..4])
+            .join(format!("{}.dat", hash));
+        let (header, _) = data_file::DataFileHeader::read_from(&path)
+            .unwrap()
+            .unwrap();
+        assert_eq!(header.compression, codec);
+    }
 
-        // Create new storage instance and check if data persists
-        {
-            let storage = FileStorage::new(temp_dir.path(), true, false, false).unwrap();
-            let retrieved = storage.get("persist_key").unwrap();
-            assert!(retrieved.is_some());
+    // Every value reads back whatever codec the cache now writes with, also
+    // once the index has been rebuilt from the data file headers
+    let storage = OptimizedStorage::with_config(temp_dir.path(), config(Compression::Lz4)).unwrap();
+    for &codec in &codecs {
+        for (tier, size) in [("packed", 40 * 1024), ("file", 300 * 1024)] {
+            let key = format!("{}-{}", codec.name(), tier);
+            let entry = storage.get(&key).unwrap().unwrap();
+            assert_eq!(entry.get_data(), Some(&value(size)[..]), "{}", key);
         }
     }
+    drop(storage);
+    for name in ["index.sqlite3", "index.sqlite3-wal", "index.sqlite3-shm"] {
+        let _ = std::fs::remove_file(temp_dir.path().join(name));
+    }
+    let storage =
+        OptimizedStorage::with_config(temp_dir.path(), config(Compression::Zstd)).unwrap();
+    for &codec in &codecs {
+        let key = format!("{}-file", codec.name());
+        let entry = storage.get(&key).unwrap().unwrap();
+        assert_eq!(entry.get_data(), Some(&value(300 * 1024)[..]), "{}", key);
+    }
+}
+
+#[test]
+fn test_small_values_are_compressed_with_a_trained_dictionary() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = || optimized_backend::StorageConfig {
+        zstd_dictionary: true,
+        ..Default::default()
+    };
+    let value = |i: usize| {
+        format!(
+            r#"{{"shot": "sq010_sh{:04}", "frames": [1001, {}], "status": "approved"}}"#,
+            i,
+            1001 + i
+        )
+        .into_bytes()
+    };
+    let count = compression::DICTIONARY_SAMPLES + 100;
+
+    let storage = OptimizedStorage::with_config(temp_dir.path(), config()).unwrap();
+    for i in 0..count {
+        let entry = CacheEntry::new_inline(format!("shot-{}", i), value(i), vec![], None);
+        storage.set(&format!("shot-{}", i), entry).unwrap();
+    }
+    drop(storage);
+    assert!(temp_dir
+        .path()
+        .join(compression::DICTIONARY_DIR)
+        .join("1.zdict")
+        .exists());
+
+    // Values written before the dictionary was trained stay as they were
+    let conn = rusqlite::Connection::open(temp_dir.path().join("index.sqlite3")).unwrap();
+    let stored_len = |key: &str| -> i64 {
+        conn.query_row(
+            "SELECT length(value) FROM cache_index WHERE key = ?1",
+            [key],
+            |row| row.get(0),
+        )
+        .unwrap()
+    };
+    assert!(stored_len(&format!("shot-{}", count - 1)) < stored_len("shot-0"));
+    drop(conn);
+
+    // Without the mode, values already compressed still read back
+    let storage = OptimizedStorage::with_config(temp_dir.path(), Default::default()).unwrap();
+    for i in [0, count / 2, count - 1] {
+        let entry = storage.get(&format!("shot-{}", i)).unwrap().unwrap();
+        assert_eq!(entry.get_data(), Some(&value(i)[..]));
+    }
 }
 
-#[cfg(test)]
-mod storage_backend_tests {
-    use super::*;
+#[test]
+fn test_seekable_values_are_read_a_frame_at_a_time() {
+    use std::io::{Read, Seek, SeekFrom};
 
-    fn test_storage_backend<T: StorageBackend>(storage: T) {
-        let entry = create_test_entry("test_key", b"test_value");
+    let temp_dir = TempDir::new().unwrap();
+    let value = (0..frames::FRAME_SIZE * 4)
+        .map(|i| (i / 64 % 251) as u8)
+        .collect::<Vec<_>>();
+    for (codec, framed) in [
+        (Compression::Lz4, Compression::Lz4Frames),
+        (Compression::Zstd, Compression::ZstdFrames),
+    ] {
+        let config = optimized_backend::StorageConfig {
+            compression: codec,
+            seekable_compression: true,
+            ..Default::default()
+        };
+        let storage = OptimizedStorage::with_config(temp_dir.path(), config).unwrap();
+        let key = format!("preview-{}", codec.name());
+        let entry = CacheEntry::new_inline(key.clone(), value.clone(), vec![], None);
+        storage.set(&key, entry).unwrap();
 
-        // Test basic operations
-        storage.set("test_key", entry.clone()).unwrap();
-        assert!(storage.exists("test_key").unwrap());
+        let mut frames = storage.open_frames(&key).unwrap().unwrap();
+        assert_eq!(frames.len(), value.len() as u64);
+        let start = frames::FRAME_SIZE * 3 - 8;
+        frames.seek(SeekFrom::Start(start as u64)).unwrap();
+        let mut range = vec![0u8; 16];
+        frames.read_exact(&mut range).unwrap();
+        assert_eq!(range, value[start..start + 16]);
 
-        let retrieved = storage.get("test_key").unwrap();
-        assert!(retrieved.is_some());
+        let entry = storage.get(&key).unwrap().unwrap();
+        assert_eq!(entry.get_data(), Some(&value[..]));
+        let hash = blake3::hash(key.as_bytes()).to_hex();
+        let path = temp_dir
+            .path()
+            .join("data")
+            .join(&hash[..2])
+            .join(&hash[2..4])
+            .join(format!("{}.dat", hash));
+        let (header, _) = data_file::DataFileHeader::read_from(&path)
+            .unwrap()
+            .unwrap();
+        assert_eq!(header.compression, framed);
+    }
 
-        // Test keys
-        let keys = storage.keys().unwrap();
-        assert!(keys.contains(&"test_key".to_string()));
+    // Values compressed whole have no frames to seek in
+    let storage = OptimizedStorage::new(temp_dir.path()).unwrap();
+    let entry = CacheEntry::new_inline("whole".into(), value.clone(), vec![], None);
+    storage.set("whole", entry).unwrap();
+    assert!(storage.open_frames("whole").unwrap().is_none());
+    assert!(storage.open_frames("preview-lz4").unwrap().is_some());
+}
 
-        // Test delete
-        assert!(storage.delete("test_key").unwrap());
-        assert!(!storage.exists("test_key").unwrap());
+#[test]
+fn test_linked_files_are_adopted_without_rewriting() {
+    let temp_dir = TempDir::new().unwrap();
+    let outside = TempDir::new().unwrap();
+    let storage = OptimizedStorage::new(temp_dir.path()).unwrap();
 
-        // Test vacuum (should not fail)
-        storage.vacuum().unwrap();
+    let large = outside.path().join("large.bin");
+    let contents: Vec<u8> = (0..256 * 1024u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&large, &contents).unwrap();
+    assert_eq!(
+        storage.link_file("large", &large).unwrap(),
+        contents.len() as u64
+    );
+
+    let (linked, offset) = storage.data_file_path("large").unwrap().unwrap();
+    assert_eq!(offset, 0);
+    assert!(linked.starts_with(temp_dir.path().join("data")));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        assert_eq!(
+            std::fs::metadata(&linked).unwrap().ino(),
+            std::fs::metadata(&large).unwrap().ino()
+        );
     }
 
-    #[test]
-    fn test_all_storage_backends() {
+    // The cache keeps its own link once the original is gone
+    std::fs::remove_file(&large).unwrap();
+    let entry = storage.get("large").unwrap().expect("linked value");
+    assert_eq!(entry.get_data().unwrap(), contents.as_slice());
+    assert!(storage.delete("large").unwrap());
+    storage.vacuum().unwrap();
+    assert!(!linked.exists());
+
+    // Small files are stored like any other small value
+    let small = outside.path().join("small.txt");
+    std::fs::write(&small, b"tiny").unwrap();
+    storage.link_file("small", &small).unwrap();
+    assert_eq!(storage.data_file_path("small").unwrap(), None);
+    let entry = storage.get("small").unwrap().expect("small value");
+    assert_eq!(entry.get_data().unwrap(), b"tiny");
+
+    assert!(storage
+        .link_file("missing", &outside.path().join("missing"))
+        .is_err());
+}
+
+#[test]
+fn test_streamed_values_are_written_in_chunks() {
+    use std::io::Write;
+
+    let temp_dir = TempDir::new().unwrap();
+    let storage = OptimizedStorage::new(temp_dir.path()).unwrap();
+    let chunk: Vec<u8> = (0..64 * 1024u32).map(|i| (i % 251) as u8).collect();
+
+    let mut writer = storage.write_stream("large").unwrap();
+    for _ in 0..32 {
+        writer.write_all(&chunk).unwrap();
+    }
+    assert_eq!(
+        storage.commit_stream(writer).unwrap(),
+        32 * chunk.len() as u64
+    );
+
+    let (path, offset) = storage.data_file_path("large").unwrap().unwrap();
+    let file = std::fs::read(&path).unwrap();
+    assert_eq!(file.len() as u64 - offset, 32 * chunk.len() as u64);
+    assert!(file[offset as usize..]
+        .chunks(chunk.len())
+        .all(|c| c == chunk));
+    let entry = storage.get("large").unwrap().expect("streamed value");
+    assert_eq!(entry.get_data().unwrap().len(), 32 * chunk.len());
+
+    // An abandoned write keeps the previous value and leaves no file behind
+    let mut writer = storage.write_stream("large").unwrap();
+    writer.write_all(b"replacement").unwrap();
+    drop(writer);
+    assert_eq!(storage.data_file_path("large").unwrap().unwrap().0, path);
+    let leftovers = std::fs::read_dir(path.parent().unwrap())
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("tmp".as_ref()))
+        .count();
+    assert_eq!(leftovers, 0);
+
+    // Small values are stored like any other small value
+    let mut writer = storage.write_stream("small").unwrap();
+    writer.write_all(b"tiny").unwrap();
+    storage.commit_stream(writer).unwrap();
+    assert_eq!(storage.data_file_path("small").unwrap(), None);
+    let entry = storage.get("small").unwrap().expect("small value");
+    assert_eq!(entry.get_data().unwrap(), b"tiny");
+
+    // Backends without data files refuse to stream
+    let memory = MemoryStorage::new();
+    assert!(matches!(
+        memory.write_stream("large"),
+        Err(CacheError::Config(_))
+    ));
+}
+
+#[test]
+fn test_optimized_storage_conformance_at_each_durability() {
+    for durability in [
+        Durability::Relaxed,
+        Durability::Flush,
+        Durability::Fsync,
+        Durability::FsyncDir,
+    ] {
         let temp_dir = TempDir::new().unwrap();
+        let config = optimized_backend::StorageConfig {
+            durability,
+            ..Default::default()
+        };
+        conformance::run_all(&OptimizedStorage::with_config(temp_dir.path(), config).unwrap());
+    }
+}
+
+#[test]
+fn test_fsync_durability_syncs_files_and_directories() {
+    let fsyncs_for_one_file = |durability| {
+        let temp_dir = TempDir::new().unwrap();
+        let config = optimized_backend::StorageConfig {
+            durability,
+            ..Default::default()
+        };
+        let storage = OptimizedStorage::with_config(temp_dir.path(), config).unwrap();
+        let entry = CacheEntry::new_inline("large".into(), vec![9; 128 * 1024], vec![], None);
+        storage.set("large", entry).unwrap();
+        storage.io_stats().fsyncs
+    };
+
+    assert_eq!(fsyncs_for_one_file(Durability::Flush), 0);
+    assert_eq!(fsyncs_for_one_file(Durability::Fsync), 1);
+    // The file's directory, plus the two fan-out levels created for it
+    if cfg!(unix) {
+        assert_eq!(fsyncs_for_one_file(Durability::FsyncDir), 1 + 1 + 2);
+    }
+}
+
+#[test]
+fn test_signed_index_refuses_rows_it_did_not_sign() {
+    let temp_dir = TempDir::new().unwrap();
+    let signed = |secret: &[u8]| optimized_backend::StorageConfig {
+        disk_write_threshold: 1024,
+        pack_threshold: 4096,
+        index_key: Some(IndexKey::new(secret).unwrap()),
+        ..Default::default()
+    };
+    conformance::run_all(
+        &OptimizedStorage::with_config(TempDir::new().unwrap().path(), signed(b"secret")).unwrap(),
+    );
+
+    let storage = OptimizedStorage::with_config(temp_dir.path(), signed(b"secret")).unwrap();
+    for (key, len) in [("inline", 100), ("packed", 2000), ("file", 8192)] {
+        storage
+            .set(
+                key,
+                CacheEntry::new_inline(key.into(), vec![7; len], vec![], None),
+            )
+            .unwrap();
+    }
+    // Readers must go through the signature check, not the file
+    assert_eq!(storage.data_file_path("file").unwrap(), None);
+    drop(storage);
 
-        // Test UltraFastStorage
-        let ultra_fast = UltraFastStorage::new(temp_dir.path().join("ultra_fast"), false).unwrap();
-        test_storage_backend(ultra_fast);
+    // Without the secret a process can still read, but whatever it writes is refused
+    let unsigned = OptimizedStorage::new(temp_dir.path()).unwrap();
+    let (data_file, _) = unsigned.data_file_path("file").unwrap().unwrap();
+    unsigned
+        .set(
+            "inline",
+            CacheEntry::new_inline("inline".into(), b"forged".to_vec(), vec![], None),
+        )
+        .unwrap();
+    drop(unsigned);
+    std::fs::write(
+        &data_file,
+        data_file::DataFileHeader::new("file", Compression::None).frame(&[6; 8192]),
+    )
+    .unwrap();
+
+    let storage = OptimizedStorage::with_config(temp_dir.path(), signed(b"secret")).unwrap();
+    for key in ["inline", "file"] {
+        assert!(matches!(
+            storage.get(key),
+            Err(CacheError::Tampered(tampered)) if tampered == key
+        ));
+        assert!(!storage.exists(key).unwrap());
+    }
+    let entry = storage.get("packed").unwrap().expect("untouched value");
+    assert_eq!(entry.get_data().unwrap(), vec![7; 2000].as_slice());
+    drop(storage);
+
+    // A different secret trusts none of the rows
+    let storage = OptimizedStorage::with_config(temp_dir.path(), signed(b"other")).unwrap();
+    assert!(matches!(
+        storage.get("packed"),
+        Err(CacheError::Tampered(_))
+    ));
+    assert!(IndexKey::new(b"").is_err());
+}
 
-        // Test HybridStorage
-        let hybrid = HybridStorage::new(temp_dir.path().join("hybrid"), 1024).unwrap();
-        test_storage_backend(hybrid);
+#[test]
+fn test_optimized_storage_with_and_without_atomic_writes() {
+    for atomic_writes in [true, false] {
+        let temp_dir = TempDir::new().unwrap();
+        let config = optimized_backend::StorageConfig {
+            disk_write_threshold: 0,
+            atomic_writes,
+            ..Default::default()
+        };
+        let storage = OptimizedStorage::with_config(temp_dir.path(), config).unwrap();
+        conformance::run_all(&storage);
+        for i in 0..20 {
+            let key = format!("key{}", i % 5);
+            storage
+                .set(
+                    &key,
+                    CacheEntry::new_inline(key.clone(), vec![i as u8; 4096], vec![], None),
+                )
+                .unwrap();
+        }
+        storage.vacuum().unwrap();
 
-        // Test FileStorage
-        let file_storage =
-            FileStorage::new(temp_dir.path().join("file"), true, false, false).unwrap();
-        test_storage_backend(file_storage);
+        let mut pending = vec![temp_dir.path().join("data")];
+        while let Some(directory) = pending.pop() {
+            for entry in std::fs::read_dir(directory).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    pending.push(path);
+                } else {
+                    assert_ne!(path.extension(), Some("tmp".as_ref()), "left behind");
+                }
+            }
+        }
+        let entry = storage.get("key4").unwrap().expect("last write");
+        assert_eq!(entry.get_data().unwrap(), vec![19; 4096].as_slice());
     }
 }
 
-#[cfg(test)]
-mod performance_tests {
-    use super::*;
-    use std::time::Instant;
+#[test]
+fn test_smb_mode_publishes_by_rename_without_key_locks() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = optimized_backend::StorageConfig {
+        disk_write_threshold: 0,
+        smb_mode: true,
+        ..Default::default()
+    };
+    let storage = std::sync::Arc::new(
+        OptimizedStorage::with_config(temp_dir.path(), config.clone()).unwrap(),
+    );
+    conformance::run_all(storage.as_ref());
 
-    #[test]
-    fn test_ultra_fast_performance() {
+    // Two handles racing on the same keys never see an error
+    let other =
+        std::sync::Arc::new(OptimizedStorage::with_config(temp_dir.path(), config).unwrap());
+    let writers: Vec<_> = [storage.clone(), other]
+        .into_iter()
+        .enumerate()
+        .map(|(n, storage)| {
+            std::thread::spawn(move || {
+                for i in 0..50u8 {
+                    let key = format!("key{}", i % 3);
+                    let value = vec![n as u8; 8192];
+                    storage
+                        .set(
+                            &key,
+                            CacheEntry::new_inline(key.clone(), value, vec![], None),
+                        )
+                        .unwrap();
+                    if let Some(entry) = storage.get(&key).unwrap() {
+                        assert!(matches!(entry.get_data().unwrap(), [0, ..] | [1, ..]));
+                    }
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    assert!(!temp_dir.path().join("locks").exists());
+
+    // A file not matching its row may belong to a writer yet to commit
+    storage
+        .set(
+            "file",
+            CacheEntry::new_inline("file".into(), vec![7; 8192], vec![], None),
+        )
+        .unwrap();
+    let (data_file, _) = storage.data_file_path("file").unwrap().unwrap();
+    let mut bytes = std::fs::read(&data_file).unwrap();
+    *bytes.last_mut().unwrap() ^= 0xff;
+    std::fs::write(&data_file, bytes).unwrap();
+    drop(storage);
+    let storage = OptimizedStorage::with_config(
+        temp_dir.path(),
+        optimized_backend::StorageConfig {
+            smb_mode: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(storage.get("file").unwrap().is_none());
+    assert!(data_file.exists());
+}
+
+#[test]
+fn test_direct_io_writes_round_trip() {
+    // Sizes off the O_DIRECT alignment, spanning more than one staged chunk
+    let values: Vec<Vec<u8>> = [100_003usize, 3 * 1024 * 1024 + 7]
+        .iter()
+        .map(|&len| {
+            (0..len as u64)
+                .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+                .collect()
+        })
+        .collect();
+    for atomic_writes in [true, false] {
         let temp_dir = TempDir::new().unwrap();
-        let storage = UltraFastStorage::new(temp_dir.path(), false).unwrap();
+        let config = optimized_backend::StorageConfig {
+            direct_io_threshold: Some(64 * 1024),
+            durability: Durability::Fsync,
+            atomic_writes,
+            ..Default::default()
+        };
+        let storage = OptimizedStorage::with_config(temp_dir.path(), config).unwrap();
+        conformance::run_all(&storage);
+        for (i, value) in values.iter().enumerate() {
+            let key = format!("direct{}", i);
+            let entry = CacheEntry::new_inline(key.clone(), value.clone(), vec![], None);
+            storage.set(&key, entry).unwrap();
+        }
+        drop(storage);
 
-        let data = vec![0u8; 1024]; // 1KB data
-        let iterations = 1000;
+        let storage = OptimizedStorage::new(temp_dir.path()).unwrap();
+        for (i, value) in values.iter().enumerate() {
+            let entry = storage.get(&format!("direct{}", i)).unwrap().unwrap();
+            assert_eq!(entry.get_data().unwrap(), value.as_slice());
+        }
+    }
+}
+
+#[test]
+fn test_group_commit_syncs_concurrent_writes_together() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = optimized_backend::StorageConfig {
+        durability: Durability::FsyncDir,
+        group_commit: Some(std::time::Duration::from_millis(100)),
+        ..Default::default()
+    };
+    let storage = OptimizedStorage::with_config(temp_dir.path(), config).unwrap();
+    conformance::run_all(&storage);
+
+    // A batch lands in one group rather than waiting out a window per file
+    let entries: Vec<_> = (0..10u8)
+        .map(|i| (format!("batch{}", i), vec![i; 64 * 1024]))
+        .collect();
+    let started = std::time::Instant::now();
+    StorageBackend::set_batch(&storage, entries).unwrap();
+    assert!(started.elapsed() < std::time::Duration::from_secs(1));
 
-        let start = Instant::now();
-        for i in 0..iterations {
-            let entry = create_test_entry(&format!("key_{}", i), &data);
-            storage.set(&format!("key_{}", i), entry).unwrap();
+    std::thread::scope(|scope| {
+        for thread in 0..4u8 {
+            let storage = &storage;
+            scope.spawn(move || {
+                for i in 0..3u8 {
+                    let key = format!("thread{}-{}", thread, i);
+                    let entry =
+                        CacheEntry::new_inline(key.clone(), vec![i; 64 * 1024], vec![], None);
+                    storage.set(&key, entry).unwrap();
+                }
+            });
         }
-        let duration = start.elapsed();
-
-        #[cfg(test)]
-        eprintln!(
-            "UltraFast SET: {} ops in {:?} ({:.1} ops/s)",
-            iterations,
-            duration,
-            iterations as f64 / duration.as_secs_f64()
-        );
+    });
+    drop(storage);
 
-        // Should be very fast (> 10k ops/s)
-        let ops_per_sec = iterations as f64 / duration.as_secs_f64();
-        assert!(
-            ops_per_sec > 10000.0,
-            "UltraFast storage should be > 10k ops/s, got {:.1}",
-            ops_per_sec
-        );
+    let storage = OptimizedStorage::new(temp_dir.path()).unwrap();
+    for i in 0..10u8 {
+        let entry = storage.get(&format!("batch{}", i)).unwrap().unwrap();
+        assert_eq!(entry.get_data().unwrap(), vec![i; 64 * 1024].as_slice());
+    }
+    for thread in 0..4u8 {
+        for i in 0..3u8 {
+            let entry = storage.get(&format!("thread{}-{}", thread, i)).unwrap();
+            assert_eq!(
+                entry.unwrap().get_data().unwrap(),
+                vec![i; 64 * 1024].as_slice()
+            );
+        }
+    }
+}
+
+#[test]
+fn test_evicted_entries_move_to_the_remote_tier() {
+    let temp_dir = TempDir::new().unwrap();
+    let remote_dir = temp_dir.path().join("remote");
+    let config = optimized_backend::StorageConfig {
+        remote_tier: Some(std::sync::Arc::new(
+            DirectoryTier::new(&remote_dir).unwrap(),
+        )),
+        ..Default::default()
+    };
+    let storage = OptimizedStorage::with_config(temp_dir.path().join("cache"), config).unwrap();
+    let large = vec![7u8; 100 * 1024];
+    for (key, value) in [("small", &b"tiny"[..]), ("large", &large[..])] {
+        let entry = CacheEntry::new_inline(key.to_string(), value.to_vec(), vec![], None);
+        storage.set(key, entry).unwrap();
+        assert!(storage.evict(key).unwrap());
+    }
+    assert!(storage.keys().unwrap().is_empty());
+    storage.wait_for_uploads();
+    assert_eq!(std::fs::read_dir(&remote_dir).unwrap().count(), 2);
+
+    // Misses fall through to the remote tier
+    assert!(storage.exists("large").unwrap());
+    let read = storage.get("large").unwrap().unwrap();
+    assert_eq!(read.get_data(), Some(&large[..]));
+    let reads = storage
+        .get_many(&["small".to_string(), "missing".to_string()])
+        .unwrap();
+    assert_eq!(reads[0].as_ref().unwrap().get_data(), Some(&b"tiny"[..]));
+    assert!(reads[1].is_none());
+    assert!(!storage.evict("missing").unwrap());
+
+    // Deletes reach the remote copy, so it cannot come back
+    assert!(storage.delete("small").unwrap());
+    assert!(storage.get("small").unwrap().is_none());
+    assert!(!storage.delete("small").unwrap());
+
+    // A damaged object is refused and dropped
+    let object = remote_dir.join(storage.generate_filename("large"));
+    let mut bytes = std::fs::read(&object).unwrap();
+    *bytes.last_mut().unwrap() ^= 1;
+    std::fs::write(&object, bytes).unwrap();
+    assert!(matches!(
+        storage.get("large"),
+        Err(crate::error::CacheError::Corrupted(_))
+    ));
+    assert!(!object.exists());
+
+    let entry = CacheEntry::new_inline("kept".to_string(), b"x".to_vec(), vec![], None);
+    storage.set("kept", entry).unwrap();
+    storage.evict("kept").unwrap();
+    storage.clear().unwrap();
+    assert_eq!(std::fs::read_dir(&remote_dir).unwrap().count(), 0);
+    assert!(storage.get("kept").unwrap().is_none());
+}
+
+/// Remote tier whose uploads wait until the test lets them through
+#[derive(Debug)]
+struct GatedTier {
+    inner: DirectoryTier,
+    open: parking_lot::Mutex<bool>,
+    opened: parking_lot::Condvar,
+}
+
+impl RemoteTier for GatedTier {
+    fn put(&self, name: &str, data: &[u8]) -> crate::error::CacheResult<()> {
+        let mut open = self.open.lock();
+        while !*open {
+            self.opened.wait(&mut open);
+        }
+        self.inner.put(name, data)
+    }
+
+    fn get(&self, name: &str) -> crate::error::CacheResult<Option<Vec<u8>>> {
+        self.inner.get(name)
+    }
+
+    fn exists(&self, name: &str) -> crate::error::CacheResult<bool> {
+        self.inner.exists(name)
     }
+
+    fn delete(&self, name: &str) -> crate::error::CacheResult<()> {
+        self.inner.delete(name)
+    }
+
+    fn clear(&self) -> crate::error::CacheResult<()> {
+        self.inner.clear()
+    }
+}
+
+#[test]
+fn test_evictions_do_not_wait_for_the_remote_tier() {
+    let temp_dir = TempDir::new().unwrap();
+    let remote_dir = temp_dir.path().join("remote");
+    let remote = std::sync::Arc::new(GatedTier {
+        inner: DirectoryTier::new(&remote_dir).unwrap(),
+        open: parking_lot::Mutex::new(false),
+        opened: parking_lot::Condvar::new(),
+    });
+    let config = optimized_backend::StorageConfig {
+        remote_tier: Some(remote.clone()),
+        ..Default::default()
+    };
+    let storage = OptimizedStorage::with_config(temp_dir.path().join("cache"), config).unwrap();
+    for key in ["a", "b"] {
+        let entry = CacheEntry::new_inline(key.to_string(), key.as_bytes().to_vec(), vec![], None);
+        storage.set(key, entry).unwrap();
+        assert!(storage.evict(key).unwrap());
+    }
+
+    // Served from the queue while the uploads are held up
+    assert_eq!(std::fs::read_dir(&remote_dir).unwrap().count(), 0);
+    assert!(storage.exists("a").unwrap());
+    assert!(!storage.exists_local("a").unwrap());
+    assert_eq!(
+        storage.get("a").unwrap().unwrap().get_data(),
+        Some(&b"a"[..])
+    );
+
+    *remote.open.lock() = true;
+    remote.opened.notify_all();
+    // A deleted key is not uploaded after the fact
+    assert!(storage.delete("b").unwrap());
+    storage.wait_for_uploads();
+    assert!(storage.get("a").unwrap().is_some());
+    assert!(storage.get("b").unwrap().is_none());
 }
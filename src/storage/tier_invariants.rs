@@ -0,0 +1,307 @@
+//! Property tests for the invariants between OptimizedStorage's tiers
+//!
+//! Random sequences of writes, reads, deletes and reopens run against a
+//! model map. After every step reads must agree with the model, the hot tier
+//! must never hold bytes that differ from the index row at the same
+//! generation, the cold tier must only point at the data file its row names,
+//! the warm tier's byte count must match its mappings, and the segment byte
+//! counts must match the packed rows and the files.
+
+use super::optimized_backend::{OptimizedStorage, StorageConfig};
+use super::StorageBackend;
+use crate::serialization::CacheEntry;
+use proptest::prelude::*;
+use std::collections::BTreeMap;
+use std::path::Path;
+use tempfile::TempDir;
+
+const KEYS: usize = 6;
+
+/// Where a value of a given size ends up under [`config`]
+#[derive(Debug, Clone, Copy)]
+enum Size {
+    Inline,
+    Packed,
+    File,
+    /// Large enough to be compressed
+    Compressed,
+}
+
+impl Size {
+    fn len(self) -> usize {
+        match self {
+            Size::Inline => 100,
+            Size::Packed => 600,
+            Size::File => 3_000,
+            Size::Compressed => 40_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Op {
+    Set(usize, usize, Size),
+    SetBatch(usize, Vec<(usize, Size)>),
+    Get(usize, usize),
+    GetMany(usize, Vec<usize>),
+    Prefetch(usize),
+    Delete(usize, usize),
+    Vacuum(usize),
+    Reopen(usize),
+    Clear(usize),
+}
+
+fn size() -> impl Strategy<Value = Size> {
+    prop_oneof![
+        Just(Size::Inline),
+        Just(Size::Packed),
+        Just(Size::File),
+        Just(Size::Compressed),
+    ]
+}
+
+/// Operations on one of `handles` storages opened on the same directory
+fn op(handles: usize, with_clear: bool) -> impl Strategy<Value = Op> {
+    let handle = 0..handles;
+    let key = 0..KEYS;
+    let clear_weight = if with_clear { 1 } else { 0 };
+    prop_oneof![
+        6 => (handle.clone(), key.clone(), size()).prop_map(|(h, k, s)| Op::Set(h, k, s)),
+        2 => (handle.clone(), prop::collection::vec((key.clone(), size()), 1..4))
+            .prop_map(|(h, items)| Op::SetBatch(h, items)),
+        6 => (handle.clone(), key.clone()).prop_map(|(h, k)| Op::Get(h, k)),
+        2 => (handle.clone(), prop::collection::vec(key.clone(), 1..4))
+            .prop_map(|(h, keys)| Op::GetMany(h, keys)),
+        1 => handle.clone().prop_map(Op::Prefetch),
+        4 => (handle.clone(), key).prop_map(|(h, k)| Op::Delete(h, k)),
+        1 => handle.clone().prop_map(Op::Vacuum),
+        1 => handle.clone().prop_map(Op::Reopen),
+        clear_weight => handle.prop_map(Op::Clear),
+    ]
+}
+
+fn config() -> StorageConfig {
+    StorageConfig {
+        hot_cache_size: 4,
+        disk_write_threshold: 256,
+        pack_threshold: 1024,
+        segment_size: 4096,
+        mmap_threshold: 2048,
+        warm_cache_size: 2,
+        ..Default::default()
+    }
+}
+
+fn open(directory: &Path) -> OptimizedStorage {
+    OptimizedStorage::with_config(directory, config()).unwrap()
+}
+
+fn key(k: usize) -> String {
+    format!("key{}", k)
+}
+
+/// A value no earlier write produced, so a stale read cannot pass for it
+fn value(size: Size, write: u32) -> Vec<u8> {
+    let mut data = vec![(write % 251) as u8; size.len()];
+    data[..4].copy_from_slice(&write.to_le_bytes());
+    data
+}
+
+fn value_of(entry: Option<CacheEntry>) -> Option<Vec<u8>> {
+    entry.map(|entry| match entry.storage {
+        crate::serialization::StorageMode::Inline(data) => data,
+        crate::serialization::StorageMode::File(_) => panic!("read returned a file entry"),
+    })
+}
+
+/// Run `ops` against `handles` storages, checking the invariants after each step
+fn run(ops: Vec<Op>, handles: usize) -> Result<(), TestCaseError> {
+    let temp_dir = TempDir::new().unwrap();
+    let mut storages: Vec<Option<OptimizedStorage>> =
+        (0..handles).map(|_| Some(open(temp_dir.path()))).collect();
+    let mut model: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+    let mut writes = 0u32;
+
+    for (step, op) in ops.into_iter().enumerate() {
+        match op.clone() {
+            Op::Set(h, k, size) => {
+                writes += 1;
+                let data = value(size, writes);
+                let entry = CacheEntry::new_inline(key(k), data.clone(), vec![], None);
+                storages[h].as_ref().unwrap().set(&key(k), entry).unwrap();
+                model.insert(key(k), data);
+            }
+            Op::SetBatch(h, items) => {
+                let mut batch = Vec::new();
+                for (k, size) in items {
+                    writes += 1;
+                    let data = value(size, writes);
+                    batch.push((key(k), data.clone()));
+                    model.insert(key(k), data);
+                }
+                StorageBackend::set_batch(storages[h].as_ref().unwrap(), batch).unwrap();
+            }
+            Op::Get(h, k) => {
+                let read = value_of(storages[h].as_ref().unwrap().get(&key(k)).unwrap());
+                prop_assert_eq!(read, model.get(&key(k)).cloned(), "step {}: {:?}", step, op);
+            }
+            Op::GetMany(h, keys) => {
+                let names: Vec<String> = keys.iter().map(|&k| key(k)).collect();
+                let reads = storages[h].as_ref().unwrap().get_many(&names).unwrap();
+                for (name, read) in names.iter().zip(reads) {
+                    prop_assert_eq!(
+                        value_of(read),
+                        model.get(name).cloned(),
+                        "step {}: {:?}",
+                        step,
+                        op
+                    );
+                }
+            }
+            Op::Prefetch(h) => {
+                storages[h]
+                    .as_ref()
+                    .unwrap()
+                    .prefetch_prefix("key")
+                    .unwrap();
+            }
+            Op::Delete(h, k) => {
+                let existed = storages[h].as_ref().unwrap().delete(&key(k)).unwrap();
+                let expected = model.remove(&key(k)).is_some();
+                prop_assert_eq!(existed, expected, "step {}: {:?}", step, op);
+            }
+            Op::Vacuum(h) => {
+                storages[h].as_ref().unwrap().vacuum().unwrap();
+            }
+            Op::Reopen(h) => {
+                storages[h] = None;
+                storages[h] = Some(open(temp_dir.path()));
+            }
+            Op::Clear(h) => {
+                storages[h].as_ref().unwrap().clear().unwrap();
+                model.clear();
+            }
+        }
+
+        for storage in storages.iter().flatten() {
+            let mut violations = storage.hot_tier_violations();
+            violations.extend(storage.warm_tier_violations());
+            if handles == 1 {
+                violations.extend(storage.cold_tier_violations());
+                violations.extend(storage.segment_accounting_violations());
+            }
+            prop_assert!(
+                violations.is_empty(),
+                "step {}: {:?}: {:?}",
+                step,
+                op,
+                violations
+            );
+        }
+    }
+
+    // Whatever each handle still holds in memory, every one reads the model
+    for storage in storages.iter().flatten() {
+        let mut keys = storage.keys().unwrap();
+        keys.sort();
+        prop_assert_eq!(keys, model.keys().cloned().collect::<Vec<_>>());
+        for k in 0..KEYS {
+            prop_assert_eq!(
+                value_of(storage.get(&key(k)).unwrap()),
+                model.get(&key(k)).cloned()
+            );
+        }
+    }
+    // Dropping the handles must not bring back anything deleted
+    drop(storages);
+    let reopened = open(temp_dir.path());
+    let mut keys = reopened.keys().unwrap();
+    keys.sort();
+    prop_assert_eq!(keys, model.keys().cloned().collect::<Vec<_>>());
+    for (name, data) in &model {
+        let read = value_of(reopened.get(name).unwrap());
+        prop_assert_eq!(read.as_ref(), Some(data));
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(48))]
+
+    #[test]
+    fn tiers_follow_the_model(ops in prop::collection::vec(op(1, true), 1..40)) {
+        run(ops, 1)?;
+    }
+
+    #[test]
+    fn tiers_of_two_handles_follow_the_model(ops in prop::collection::vec(op(2, false), 1..40)) {
+        run(ops, 2)?;
+    }
+}
+
+/// Reads that race a delete may promote the value they read, but the delete
+/// still wins for every later read
+#[test]
+fn deletes_win_over_racing_promotions() {
+    let temp_dir = TempDir::new().unwrap();
+    let storage = std::sync::Arc::new(open(temp_dir.path()));
+    let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+    let readers: Vec<_> = (0..3)
+        .map(|reader| {
+            let storage = storage.clone();
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                let keys: Vec<String> = (0..KEYS).map(key).collect();
+                while !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                    match reader {
+                        0 => {
+                            for k in &keys {
+                                let _ = storage.get(k);
+                            }
+                        }
+                        1 => {
+                            let _ = storage.get_many(&keys);
+                        }
+                        _ => {
+                            let _ = storage.prefetch_prefix("key");
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let sizes = [Size::Inline, Size::Packed, Size::File, Size::Compressed];
+    let mut writes = 0;
+    for round in 0..60 {
+        for k in 0..KEYS {
+            writes += 1;
+            let size = sizes[(round + k) % sizes.len()];
+            let entry = CacheEntry::new_inline(key(k), value(size, writes), vec![], None);
+            storage.set(&key(k), entry).unwrap();
+        }
+        for k in 0..KEYS {
+            storage.delete(&key(k)).unwrap();
+        }
+    }
+    stop.store(true, std::sync::atomic::Ordering::Relaxed);
+    for reader in readers {
+        reader.join().unwrap();
+    }
+
+    for k in 0..KEYS {
+        assert!(
+            storage.get(&key(k)).unwrap().is_none(),
+            "{} came back",
+            key(k)
+        );
+    }
+    assert!(storage.keys().unwrap().is_empty());
+    assert_eq!(storage.hot_tier_violations(), Vec::<String>::new());
+    assert_eq!(storage.cold_tier_violations(), Vec::<String>::new());
+
+    let storage = std::sync::Arc::into_inner(storage).unwrap();
+    drop(storage);
+    assert!(open(temp_dir.path()).keys().unwrap().is_empty());
+}
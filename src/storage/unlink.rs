@@ -0,0 +1,212 @@
+//! Parallel removal of many data files
+//!
+//! Unlinking one file at a time is bound by the latency of each call, which on
+//! NFS turns clearing tens of millions of files into hours. [`UnlinkPool`]
+//! spreads a batch over worker threads, optionally capped at a number of
+//! unlinks per second so a large clear does not monopolise a shared file
+//! server, and reports progress while it runs.
+
+use parking_lot::Mutex;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Batches smaller than this are removed on the calling thread
+const MIN_PARALLEL_BATCH: usize = 256;
+/// How often a running batch reports progress
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Files handled so far by one [`UnlinkPool::unlink`] call
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UnlinkProgress {
+    pub total: u64,
+    pub removed: u64,
+    /// Already gone, e.g. removed by another process sharing the directory
+    pub missing: u64,
+    pub failed: u64,
+}
+
+impl UnlinkProgress {
+    /// Files handled, whatever the outcome
+    pub fn done(&self) -> u64 {
+        self.removed + self.missing + self.failed
+    }
+}
+
+/// Worker threads that remove batches of files
+#[derive(Debug, Clone, Copy)]
+pub struct UnlinkPool {
+    workers: usize,
+    /// Most unlinks per second across all workers; 0 is unlimited
+    rate: u64,
+}
+
+impl Default for UnlinkPool {
+    fn default() -> Self {
+        Self::new(8, 0)
+    }
+}
+
+struct Batch<'a> {
+    paths: &'a [PathBuf],
+    next: AtomicUsize,
+    removed: AtomicU64,
+    missing: AtomicU64,
+    failed: AtomicU64,
+    started: Instant,
+    last_report: Mutex<Instant>,
+}
+
+impl Batch<'_> {
+    fn progress(&self) -> UnlinkProgress {
+        UnlinkProgress {
+            total: self.paths.len() as u64,
+            removed: self.removed.load(Ordering::Relaxed),
+            missing: self.missing.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl UnlinkPool {
+    pub fn new(workers: usize, rate: u64) -> Self {
+        Self {
+            workers: workers.max(1),
+            rate,
+        }
+    }
+
+    /// Remove every path, blocking until all are handled
+    ///
+    /// `progress` is called from the workers every so often and once more
+    /// with the final counts. Missing files are not errors; other failures
+    /// are logged and counted, and do not stop the batch.
+    pub fn unlink(
+        &self,
+        paths: &[PathBuf],
+        progress: &(dyn Fn(UnlinkProgress) + Sync),
+    ) -> UnlinkProgress {
+        let now = Instant::now();
+        let batch = Batch {
+            paths,
+            next: AtomicUsize::new(0),
+            removed: AtomicU64::new(0),
+            missing: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            started: now,
+            last_report: Mutex::new(now),
+        };
+
+        let workers = if paths.len() < MIN_PARALLEL_BATCH {
+            1
+        } else {
+            self.workers.min(paths.len())
+        };
+        std::thread::scope(|scope| {
+            for _ in 1..workers {
+                scope.spawn(|| self.work(&batch, progress));
+            }
+            self.work(&batch, progress);
+        });
+
+        let done = batch.progress();
+        progress(done);
+        if paths.len() >= MIN_PARALLEL_BATCH {
+            tracing::info!(
+                "Removed {} of {} files in {:?} ({} already gone, {} failed)",
+                done.removed,
+                done.total,
+                batch.started.elapsed(),
+                done.missing,
+                done.failed
+            );
+        }
+        done
+    }
+
+    fn work(&self, batch: &Batch<'_>, progress: &(dyn Fn(UnlinkProgress) + Sync)) {
+        loop {
+            let index = batch.next.fetch_add(1, Ordering::Relaxed);
+            let Some(path) = batch.paths.get(index) else {
+                return;
+            };
+
+            if self.rate > 0 {
+                // Paths are handed out in order, so the n-th may go at n / rate seconds
+                let due = batch.started + Duration::from_secs_f64(index as f64 / self.rate as f64);
+                let now = Instant::now();
+                if due > now {
+                    std::thread::sleep(due - now);
+                }
+            }
+
+            match std::fs::remove_file(path) {
+                Ok(()) => batch.removed.fetch_add(1, Ordering::Relaxed),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    batch.missing.fetch_add(1, Ordering::Relaxed)
+                }
+                Err(e) => {
+                    tracing::warn!("Failed to remove {:?}: {}", path, e);
+                    batch.failed.fetch_add(1, Ordering::Relaxed)
+                }
+            };
+
+            // One worker reports at a time; the others carry on
+            if let Some(mut last_report) = batch.last_report.try_lock() {
+                if last_report.elapsed() >= PROGRESS_INTERVAL {
+                    *last_report = Instant::now();
+                    progress(batch.progress());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn removes_a_batch_across_workers_and_reports_progress() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut paths: Vec<_> = (0..1000)
+            .map(|i| {
+                let path = temp_dir.path().join(format!("{}.dat", i));
+                std::fs::write(&path, b"x").unwrap();
+                path
+            })
+            .collect();
+        paths.push(temp_dir.path().join("never-existed.dat"));
+
+        let reports = Mutex::new(Vec::new());
+        let done = UnlinkPool::new(4, 0).unlink(&paths, &|progress| reports.lock().push(progress));
+
+        assert_eq!(
+            done,
+            UnlinkProgress {
+                total: 1001,
+                removed: 1000,
+                missing: 1,
+                failed: 0,
+            }
+        );
+        assert_eq!(reports.lock().last(), Some(&done));
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn rate_limit_spaces_out_unlinks() {
+        let temp_dir = TempDir::new().unwrap();
+        let paths: Vec<_> = (0..300)
+            .map(|i| temp_dir.path().join(format!("{}.dat", i)))
+            .collect();
+
+        let started = Instant::now();
+        let done = UnlinkPool::new(8, 1000).unlink(&paths, &|_| {});
+
+        assert_eq!(done.missing, 300);
+        // The 300th unlink is due 299ms in
+        assert!(started.elapsed() >= Duration::from_millis(299));
+    }
+}
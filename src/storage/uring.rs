@@ -0,0 +1,427 @@
+//! io_uring reads and writes of whole data files on Linux
+//!
+//! A batch of cold reads or queued writes is submitted to the kernel with one
+//! `io_uring_enter` call instead of one syscall per file, and the kernel works
+//! through the files concurrently. Each thread keeps its own ring, created on
+//! first use. Kernels without io_uring, or sandboxes that forbid it, make
+//! every function here return `None` so callers fall back to `std::fs`.
+
+use rustix::fd::{AsFd, AsRawFd, OwnedFd, RawFd};
+use rustix::io_uring::{
+    addr_or_splice_off_in_union, io_uring_cqe, io_uring_enter, io_uring_params, io_uring_ptr,
+    io_uring_setup, io_uring_sqe, io_uring_user_data, len_union, off_or_addr2_union,
+    IoringEnterFlags, IoringOp, IORING_OFF_CQ_RING, IORING_OFF_SQES, IORING_OFF_SQ_RING,
+};
+use rustix::mm::{mmap, munmap, MapFlags, ProtFlags};
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// Submission queue slots per ring; larger batches go through in rounds
+const RING_ENTRIES: u32 = 64;
+/// Most bytes one read or write asks for, within the kernel's per-call limit
+const MAX_CHUNK: usize = 1 << 30;
+
+/// Set once creating a ring has failed, so later batches skip straight to std
+static UNAVAILABLE: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static RING: RefCell<Option<Ring>> = const { RefCell::new(None) };
+}
+
+/// Run `f` on this thread's ring, or return `None` without io_uring
+fn with_ring<T>(f: impl FnOnce(&mut Ring) -> T) -> Option<T> {
+    if UNAVAILABLE.load(Ordering::Relaxed) {
+        return None;
+    }
+    RING.with(|ring| {
+        let mut ring = ring.borrow_mut();
+        if ring.is_none() {
+            match Ring::new(RING_ENTRIES) {
+                Ok(created) => *ring = Some(created),
+                Err(e) => {
+                    tracing::debug!("io_uring is unavailable, using std::fs: {}", e);
+                    UNAVAILABLE.store(true, Ordering::Relaxed);
+                    return None;
+                }
+            }
+        }
+        ring.as_mut().map(f)
+    })
+}
+
+/// Read each file whole, in one batch
+///
+/// Returns `None` when io_uring is unavailable.
+pub fn read_files(paths: &[&Path]) -> Option<Vec<io::Result<Vec<u8>>>> {
+    if UNAVAILABLE.load(Ordering::Relaxed) {
+        return None;
+    }
+    let mut files = Vec::with_capacity(paths.len());
+    let mut buffers = Vec::with_capacity(paths.len());
+    for path in paths {
+        let opened = File::open(path).and_then(|file| {
+            let len = file.metadata()?.len() as usize;
+            Ok((file, len))
+        });
+        match opened {
+            Ok((file, len)) => {
+                files.push(Ok(file));
+                buffers.push(vec![0u8; len]);
+            }
+            Err(e) => {
+                files.push(Err(e));
+                buffers.push(Vec::new());
+            }
+        }
+    }
+
+    let mut ops: Vec<Op> = files
+        .iter()
+        .zip(buffers.iter_mut())
+        .map(|(file, buffer)| Op::new(IoringOp::Read, file, buffer.as_mut_ptr(), buffer.len()))
+        .collect();
+    with_ring(|ring| ring.run(&mut ops))?;
+
+    Some(
+        files
+            .into_iter()
+            .zip(buffers)
+            .zip(ops)
+            .map(|((file, mut buffer), op)| {
+                file?;
+                op.result?;
+                // The file shrank since it was sized
+                buffer.truncate(op.done);
+                Ok(buffer)
+            })
+            .collect(),
+    )
+}
+
+/// Write each buffer to the start of its file, in one batch
+///
+/// Returns `None` when io_uring is unavailable.
+pub fn write_files(files: &[(&File, &[u8])]) -> Option<Vec<io::Result<()>>> {
+    let mut ops: Vec<Op> = files
+        .iter()
+        .map(|(file, data)| {
+            // The kernel only reads from a write's buffer
+            Op::new(
+                IoringOp::Write,
+                &Ok(*file),
+                data.as_ptr().cast_mut(),
+                data.len(),
+            )
+        })
+        .collect();
+    with_ring(|ring| ring.run(&mut ops))?;
+    Some(ops.into_iter().map(|op| op.result).collect())
+}
+
+/// One whole-file read or write, issued in chunks until done
+struct Op {
+    opcode: IoringOp,
+    fd: RawFd,
+    buf: *mut u8,
+    len: usize,
+    done: usize,
+    finished: bool,
+    result: io::Result<()>,
+}
+
+impl Op {
+    fn new<F: AsFd>(opcode: IoringOp, file: &io::Result<F>, buf: *mut u8, len: usize) -> Self {
+        Self {
+            opcode,
+            fd: file.as_ref().map_or(-1, |file| file.as_fd().as_raw_fd()),
+            buf,
+            len,
+            done: 0,
+            // Files that failed to open, and empty ones, need no I/O
+            finished: file.is_err() || len == 0,
+            result: Ok(()),
+        }
+    }
+
+    /// Account for a completion carrying `res`
+    fn complete(&mut self, res: i32) {
+        match res {
+            res if res < 0 => {
+                self.result = Err(io::Error::from_raw_os_error(-res));
+                self.finished = true;
+            }
+            // End of file for a read; no progress at all for a write
+            0 => {
+                if self.opcode == IoringOp::Write {
+                    self.result = Err(io::ErrorKind::WriteZero.into());
+                }
+                self.finished = true;
+            }
+            res => {
+                self.done += res as usize;
+                self.finished = self.done >= self.len;
+            }
+        }
+    }
+}
+
+/// A submission and completion queue pair mapped from the kernel
+struct Ring {
+    fd: OwnedFd,
+    // Only held so the queues below stay mapped
+    _sq: Mapping,
+    _cq: Mapping,
+    sqes: Mapping,
+    sq_tail: *const AtomicU32,
+    sq_mask: u32,
+    sq_array: *mut u32,
+    cq_head: *const AtomicU32,
+    cq_tail: *const AtomicU32,
+    cq_mask: u32,
+    cqes: *const io_uring_cqe,
+    entries: u32,
+}
+
+impl Ring {
+    fn new(entries: u32) -> io::Result<Self> {
+        let mut params = io_uring_params::default();
+        // SAFETY: `params` is a valid, zeroed parameter block
+        let fd = unsafe { io_uring_setup(entries, &mut params) }?;
+
+        let sq_len =
+            params.sq_off.array as usize + params.sq_entries as usize * std::mem::size_of::<u32>();
+        let cq_len = params.cq_off.cqes as usize
+            + params.cq_entries as usize * std::mem::size_of::<io_uring_cqe>();
+        let sqes_len = params.sq_entries as usize * std::mem::size_of::<io_uring_sqe>();
+        let sq = Mapping::new(&fd, sq_len, IORING_OFF_SQ_RING)?;
+        let cq = Mapping::new(&fd, cq_len, IORING_OFF_CQ_RING)?;
+        let sqes = Mapping::new(&fd, sqes_len, IORING_OFF_SQES)?;
+
+        // SAFETY: the offsets come from the kernel and lie inside the mappings
+        unsafe {
+            let sq_base = sq.ptr.cast::<u8>();
+            let cq_base = cq.ptr.cast::<u8>();
+            Ok(Self {
+                sq_tail: sq_base.add(params.sq_off.tail as usize).cast(),
+                sq_mask: *sq_base.add(params.sq_off.ring_mask as usize).cast::<u32>(),
+                sq_array: sq_base.add(params.sq_off.array as usize).cast(),
+                cq_head: cq_base.add(params.cq_off.head as usize).cast(),
+                cq_tail: cq_base.add(params.cq_off.tail as usize).cast(),
+                cq_mask: *cq_base.add(params.cq_off.ring_mask as usize).cast::<u32>(),
+                cqes: cq_base.add(params.cq_off.cqes as usize).cast(),
+                entries: params.sq_entries,
+                fd,
+                _sq: sq,
+                _cq: cq,
+                sqes,
+            })
+        }
+    }
+
+    /// Drive every op to completion, at most `entries` requests in flight
+    ///
+    /// Each op has at most one request in flight, so its buffer position is
+    /// never raced.
+    fn run(&mut self, ops: &mut [Op]) {
+        loop {
+            let pending: Vec<usize> = (0..ops.len())
+                .filter(|&i| !ops[i].finished)
+                .take(self.entries as usize)
+                .collect();
+            if pending.is_empty() {
+                return;
+            }
+            for &i in &pending {
+                self.push(i, &ops[i]);
+            }
+
+            let submitted = pending.len() as u32;
+            let mut completed = 0;
+            // SAFETY: every submitted buffer outlives this call, which waits
+            // for all of their completions
+            let entered = unsafe {
+                io_uring_enter(
+                    self.fd.as_fd(),
+                    submitted,
+                    submitted,
+                    IoringEnterFlags::GETEVENTS,
+                )
+            };
+            if let Err(e) = entered {
+                for &i in &pending {
+                    ops[i].result = Err(e.into());
+                    ops[i].finished = true;
+                }
+                // Requests the kernel took must still be reaped before the buffers go
+                self.drain(ops);
+                return;
+            }
+            while completed < submitted {
+                let reaped = self.reap(ops);
+                completed += reaped;
+                if reaped == 0 {
+                    // SAFETY: as above
+                    if let Err(e) = unsafe {
+                        io_uring_enter(self.fd.as_fd(), 0, 1, IoringEnterFlags::GETEVENTS)
+                    } {
+                        if e != rustix::io::Errno::INTR {
+                            for &i in &pending {
+                                ops[i].result = Err(e.into());
+                                ops[i].finished = true;
+                            }
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Queue the next chunk of op `index`
+    fn push(&mut self, index: usize, op: &Op) {
+        // SAFETY: `done` is within the buffer
+        let addr = io_uring_ptr::new(unsafe { op.buf.add(op.done) }.cast::<c_void>());
+        let sqe = io_uring_sqe {
+            opcode: op.opcode,
+            fd: op.fd,
+            off_or_addr2: off_or_addr2_union {
+                off: op.done as u64,
+            },
+            addr_or_splice_off_in: addr_or_splice_off_in_union { addr },
+            len: len_union {
+                len: (op.len - op.done).min(MAX_CHUNK) as u32,
+            },
+            user_data: io_uring_user_data::from_u64(index as u64),
+            ..Default::default()
+        };
+
+        // SAFETY: only this thread submits to the ring; the kernel reads
+        // slots up to the tail published below
+        unsafe {
+            let tail = (*self.sq_tail).load(Ordering::Relaxed);
+            let slot = tail & self.sq_mask;
+            self.sqes
+                .ptr
+                .cast::<io_uring_sqe>()
+                .add(slot as usize)
+                .write(sqe);
+            self.sq_array.add(slot as usize).write(slot);
+            (*self.sq_tail).store(tail.wrapping_add(1), Ordering::Release);
+        }
+    }
+
+    /// Apply every completion posted so far, returning how many there were
+    fn reap(&mut self, ops: &mut [Op]) -> u32 {
+        // SAFETY: the kernel publishes completions up to the tail; only this
+        // thread advances the head
+        unsafe {
+            let head = (*self.cq_head).load(Ordering::Relaxed);
+            let tail = (*self.cq_tail).load(Ordering::Acquire);
+            let mut next = head;
+            while next != tail {
+                let cqe = &*self.cqes.add((next & self.cq_mask) as usize);
+                if let Some(op) = ops.get_mut(cqe.user_data.u64_() as usize) {
+                    op.complete(cqe.res);
+                }
+                next = next.wrapping_add(1);
+            }
+            (*self.cq_head).store(tail, Ordering::Release);
+            tail.wrapping_sub(head)
+        }
+    }
+
+    /// Wait out requests still in flight after a failed submission
+    fn drain(&mut self, ops: &mut [Op]) {
+        // SAFETY: as in `run`
+        while unsafe { io_uring_enter(self.fd.as_fd(), 0, 0, IoringEnterFlags::GETEVENTS) }.is_ok()
+        {
+            if self.reap(ops) == 0 {
+                return;
+            }
+        }
+    }
+}
+
+/// Shared memory the kernel maps for one part of a ring
+struct Mapping {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: &OwnedFd, len: usize, offset: u64) -> io::Result<Self> {
+        // SAFETY: a fresh shared mapping of the ring fd at a kernel-defined offset
+        let ptr = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                len,
+                ProtFlags::READ | ProtFlags::WRITE,
+                MapFlags::SHARED | MapFlags::POPULATE,
+                fd,
+                offset,
+            )
+        }?;
+        Ok(Self { ptr, len })
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: the mapping is not used past this point
+        let _ = unsafe { munmap(self.ptr, self.len) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    #[test]
+    fn batches_read_and_write_whole_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let paths: Vec<_> = (0..100)
+            .map(|i| temp_dir.path().join(format!("{}.dat", i)))
+            .collect();
+        let contents: Vec<Vec<u8>> = (0..100).map(|i| vec![i as u8; i * 1000]).collect();
+
+        let files: Vec<File> = paths
+            .iter()
+            .map(|path| File::create(path).unwrap())
+            .collect();
+        let writes: Vec<_> = files
+            .iter()
+            .zip(&contents)
+            .map(|(file, data)| (file, data.as_slice()))
+            .collect();
+        let Some(written) = write_files(&writes) else {
+            // Not available in this environment; callers use std::fs
+            return;
+        };
+        assert!(written.iter().all(|result| result.is_ok()));
+
+        let mut missing = paths.iter().map(|path| path.as_path()).collect::<Vec<_>>();
+        let absent = temp_dir.path().join("absent.dat");
+        missing.push(&absent);
+        let read = read_files(&missing).unwrap();
+        for (read, expected) in read.iter().zip(&contents) {
+            assert_eq!(read.as_ref().unwrap(), expected);
+        }
+        assert_eq!(
+            read.last().unwrap().as_ref().unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        // Writes through std are seen too
+        File::create(&paths[1]).unwrap().write_all(b"std").unwrap();
+        assert_eq!(
+            read_files(&[&paths[1]]).unwrap()[0].as_ref().unwrap(),
+            b"std"
+        );
+    }
+}
@@ -0,0 +1,298 @@
+//! Write-ahead log for [`OptimizedStorage`](super::OptimizedStorage)
+//!
+//! Data files go through the page cache and SQLite runs with
+//! `synchronous=NORMAL`, so an OS crash or power loss can drop writes that
+//! were already acknowledged. With the log enabled every mutation is appended
+//! to `data.wal` (and fsynced according to [`WalSyncPolicy`]) before it is
+//! applied. Opening the storage replays whatever the log still holds; a
+//! checkpoint makes the applied state durable and empties the log.
+//!
+//! One handle owns the log at a time, holding an exclusive lock on it until
+//! it closes. Other handles on the directory, in this process or others, and
+//! handles on filesystems without locks, write without a log, as do forked
+//! children while the parent keeps it.
+//!
+//! Record layout, little endian:
+//! `kind: u8 | key_len: u64 | data_len: u64 | key | data | checksum: [u8; 8]`
+//! where the checksum is the first eight bytes of the BLAKE3 hash of
+//! everything before it. Replay stops at the first torn or corrupt record.
+
+use crate::error::{CacheError, CacheResult, ConfigIssue};
+use crate::utils::locks_unsupported;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// File name of the log inside the cache directory
+pub const WAL_FILE_NAME: &str = "data.wal";
+
+const RECORD_PUT: u8 = 1;
+const RECORD_DELETE: u8 = 2;
+const RECORD_CLEAR: u8 = 3;
+const HEADER_LEN: usize = 17;
+const CHECKSUM_LEN: usize = 8;
+
+/// When appended log records are flushed to stable storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalSyncPolicy {
+    /// fsync after every write call; acknowledged writes survive power loss
+    Always,
+    /// fsync once `batch_size` records have accumulated
+    Batch,
+    /// Leave flushing to the OS; survives process crashes only
+    Never,
+}
+
+impl FromStr for WalSyncPolicy {
+    type Err = CacheError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(Self::Always),
+            "batch" => Ok(Self::Batch),
+            "never" => Ok(Self::Never),
+            other => Err(CacheError::Config(ConfigIssue::new(
+                "wal",
+                format!("unknown sync policy {:?}", other),
+                "use \"always\", \"batch\" or \"never\"",
+            ))),
+        }
+    }
+}
+
+/// One logged mutation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum WalRecord {
+    Put { key: String, data: Vec<u8> },
+    Delete { key: String },
+    Clear,
+}
+
+impl WalRecord {
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        let (kind, key, data): (u8, &str, &[u8]) = match self {
+            WalRecord::Put { key, data } => (RECORD_PUT, key, data),
+            WalRecord::Delete { key } => (RECORD_DELETE, key, &[]),
+            WalRecord::Clear => (RECORD_CLEAR, "", &[]),
+        };
+        let start = buf.len();
+        buf.push(kind);
+        buf.extend_from_slice(&(key.len() as u64).to_le_bytes());
+        buf.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        buf.extend_from_slice(key.as_bytes());
+        buf.extend_from_slice(data);
+        let checksum = blake3::hash(&buf[start..]);
+        buf.extend_from_slice(&checksum.as_bytes()[..CHECKSUM_LEN]);
+    }
+
+    /// Decode the record at the start of `bytes`, returning it with its length
+    fn decode(bytes: &[u8]) -> Option<(Self, usize)> {
+        let header = bytes.get(..HEADER_LEN)?;
+        let key_len = usize::try_from(u64::from_le_bytes(header[1..9].try_into().ok()?)).ok()?;
+        let data_len = usize::try_from(u64::from_le_bytes(header[9..17].try_into().ok()?)).ok()?;
+        let body_len = HEADER_LEN.checked_add(key_len)?.checked_add(data_len)?;
+        let record = bytes.get(..body_len.checked_add(CHECKSUM_LEN)?)?;
+
+        let checksum = blake3::hash(&record[..body_len]);
+        if record[body_len..] != checksum.as_bytes()[..CHECKSUM_LEN] {
+            return None;
+        }
+
+        let key = std::str::from_utf8(&record[HEADER_LEN..HEADER_LEN + key_len]).ok()?;
+        let data = &record[HEADER_LEN + key_len..body_len];
+        let record = match header[0] {
+            RECORD_PUT => WalRecord::Put {
+                key: key.to_string(),
+                data: data.to_vec(),
+            },
+            RECORD_DELETE => WalRecord::Delete {
+                key: key.to_string(),
+            },
+            RECORD_CLEAR => WalRecord::Clear,
+            _ => return None,
+        };
+        Some((record, body_len + CHECKSUM_LEN))
+    }
+}
+
+/// Append-only log file plus the data files written since the last checkpoint
+pub(crate) struct WriteAheadLog {
+    /// The locked log; `None` when another handle owns it
+    file: Option<File>,
+    policy: WalSyncPolicy,
+    batch_size: usize,
+    unsynced_records: usize,
+    len: u64,
+    dirty_files: HashSet<PathBuf>,
+}
+
+impl WriteAheadLog {
+    /// Open the log in `directory`, returning it with the records left by the
+    /// previous owner
+    ///
+    /// A torn or corrupt tail is dropped, so later appends start from the last
+    /// intact record. When another handle owns the log, or the filesystem
+    /// cannot lock it, the returned log records nothing.
+    pub(crate) fn open(
+        directory: &Path,
+        policy: WalSyncPolicy,
+        batch_size: usize,
+    ) -> CacheResult<(Self, Vec<WalRecord>)> {
+        use fs4::fs_std::FileExt;
+
+        let mut log = Self {
+            file: None,
+            policy,
+            batch_size: batch_size.max(1),
+            unsynced_records: 0,
+            len: 0,
+            dirty_files: HashSet::new(),
+        };
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(directory.join(WAL_FILE_NAME))?;
+        match FileExt::try_lock_exclusive(&file) {
+            Ok(true) => {}
+            Ok(false) => {
+                tracing::warn!(
+                    "Another handle owns the write-ahead log in {:?}; writing without it",
+                    directory
+                );
+                return Ok((log, Vec::new()));
+            }
+            Err(e) if locks_unsupported(&e) => {
+                tracing::warn!(
+                    "Cannot lock the write-ahead log in {:?}; writing without it",
+                    directory
+                );
+                return Ok((log, Vec::new()));
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while let Some((record, len)) = WalRecord::decode(&bytes[offset..]) {
+            records.push(record);
+            offset += len;
+        }
+        if offset < bytes.len() {
+            tracing::warn!(
+                "Discarding {} bytes of torn write-ahead log",
+                bytes.len() - offset
+            );
+            file.set_len(offset as u64)?;
+        }
+        file.seek(SeekFrom::Start(offset as u64))?;
+
+        log.file = Some(file);
+        log.len = offset as u64;
+        Ok((log, records))
+    }
+
+    /// Whether this handle owns the log and records writes in it
+    pub(crate) fn is_owned(&self) -> bool {
+        self.file.is_some()
+    }
+
+    /// Stop recording writes, closing this handle's descriptor of the log
+    ///
+    /// A forked child does this with the copy it inherited; the lock stays
+    /// with the parent's.
+    pub(crate) fn detach(&mut self) {
+        self.file = None;
+        self.len = 0;
+        self.unsynced_records = 0;
+        self.dirty_files.clear();
+    }
+
+    /// Append `records` and flush them as the sync policy requires
+    ///
+    /// Returns whether the append ended with an fsync.
+    pub(crate) fn append(&mut self, records: &[WalRecord]) -> CacheResult<bool> {
+        let Some(file) = &mut self.file else {
+            return Ok(false);
+        };
+        let mut buf = Vec::new();
+        for record in records {
+            record.encode_into(&mut buf);
+        }
+        file.write_all(&buf)?;
+        self.len += buf.len() as u64;
+        self.unsynced_records += records.len();
+
+        let due = match self.policy {
+            WalSyncPolicy::Always => true,
+            WalSyncPolicy::Batch => self.unsynced_records >= self.batch_size,
+            WalSyncPolicy::Never => false,
+        };
+        if due {
+            file.sync_data()?;
+            self.unsynced_records = 0;
+        }
+        Ok(due)
+    }
+
+    /// Value of the last logged write of `key`, unless the log deleted or
+    /// cleared it since
+    pub(crate) fn last_put(&mut self, key: &str) -> CacheResult<Option<Vec<u8>>> {
+        let Some(file) = &mut self.file else {
+            return Ok(None);
+        };
+        let mut bytes = vec![0u8; self.len as usize];
+        file.seek(SeekFrom::Start(0))?;
+        let read = file.read_exact(&mut bytes);
+        file.seek(SeekFrom::Start(self.len))?;
+        read?;
+
+        let mut last = None;
+        let mut offset = 0;
+        while let Some((record, len)) = WalRecord::decode(&bytes[offset..]) {
+            match record {
+                WalRecord::Put { key: logged, data } if logged == key => last = Some(data),
+                WalRecord::Delete { key: logged } if logged == key => last = None,
+                WalRecord::Clear => last = None,
+                _ => {}
+            }
+            offset += len;
+        }
+        Ok(last)
+    }
+
+    /// Bytes currently held by the log
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Remember a data file that must be fsynced before the next reset
+    pub(crate) fn mark_dirty(&mut self, path: PathBuf) {
+        if self.is_owned() {
+            self.dirty_files.insert(path);
+        }
+    }
+
+    /// Data files written since the last reset
+    pub(crate) fn take_dirty_files(&mut self) -> HashSet<PathBuf> {
+        std::mem::take(&mut self.dirty_files)
+    }
+
+    /// Empty the log once everything it records is durable elsewhere
+    pub(crate) fn reset(&mut self) -> CacheResult<()> {
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.sync_all()?;
+        self.len = 0;
+        self.unsynced_records = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+//! Notification of data files changed by other processes
+//!
+//! Every read checks what it serves against the index, so a stale memory
+//! tier is never served; it is only kept until the next read of its key.
+//! Until then a mapping or open descriptor of a removed file holds on to its
+//! disk space. [`DirectoryWatcher`] reports data files as they change, so
+//! the tiers can drop what they hold for them right away.
+
+use crate::error::{CacheError, CacheResult};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+
+/// Watch over a data directory, alive until dropped
+pub struct DirectoryWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+impl DirectoryWatcher {
+    /// Call `on_change` from a background thread with the paths of files
+    /// under `directory` that are written, replaced or removed
+    ///
+    /// Paths are given under `directory` as passed, even where the platform
+    /// reports them under its canonical form.
+    pub fn new<F>(directory: &Path, on_change: F) -> CacheResult<Self>
+    where
+        F: Fn(&[PathBuf]) + Send + 'static,
+    {
+        let canonical = directory.canonicalize()?;
+        let root = canonical.clone();
+        let directory = directory.to_path_buf();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        tracing::warn!("Watching {:?} failed: {}", directory, e);
+                        return;
+                    }
+                };
+                if !changes_contents(&event.kind) {
+                    return;
+                }
+                let paths: Vec<PathBuf> = event
+                    .paths
+                    .iter()
+                    .map(|path| match path.strip_prefix(&root) {
+                        Ok(relative) => directory.join(relative),
+                        Err(_) => path.clone(),
+                    })
+                    .collect();
+                on_change(&paths);
+            })
+            .map_err(watch_error)?;
+        watcher
+            .watch(&canonical, RecursiveMode::Recursive)
+            .map_err(watch_error)?;
+        Ok(Self { _watcher: watcher })
+    }
+}
+
+/// Whether an event can leave a file with other contents, or none
+fn changes_contents(kind: &EventKind) -> bool {
+    !matches!(
+        kind,
+        EventKind::Access(_) | EventKind::Modify(notify::event::ModifyKind::Metadata(_))
+    )
+}
+
+fn watch_error(e: notify::Error) -> CacheError {
+    match e.kind {
+        notify::ErrorKind::Io(e) => CacheError::Io(e),
+        kind => CacheError::Io(std::io::Error::other(format!("{:?}", kind))),
+    }
+}
@@ -0,0 +1,603 @@
+//! Designated writer for a cache directory shared by several processes
+//!
+//! Every process sharing a directory may write to it directly, but each then
+//! batches and fsyncs on its own. With a designated writer, the process
+//! holding `writer.lock` serves the writes of the others over a Unix socket in
+//! the directory, so batching and group commit span all of them. Reads stay
+//! local. When the writer exits its lock is released, and the next follower
+//! whose forwarded write fails takes over. A follower that reaches no writer
+//! applies the write itself, which the storage backends already make safe
+//! across processes; forwarded writes are idempotent, so one that may or may
+//! not have landed before its connection broke is simply applied again.
+
+use crate::error::{CacheError, CacheResult};
+use crate::fork::ForkCheck;
+use crate::serialization::CacheEntry;
+use crate::storage::{
+    CleanupGate, DataFileWriter, ExpiryListener, Footprint, FramedReader, IoStats, MaintenanceGate,
+    RecoveryReport, StorageBackend, TierSizes, UnlinkProgress, VacuumReport,
+};
+use parking_lot::Mutex;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const LOCK_FILE: &str = "writer.lock";
+const SOCKET_FILE: &str = "writer.sock";
+/// How long a follower waits on the writer before writing for itself
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(30);
+/// Largest request or response frame accepted
+const MAX_FRAME: u64 = 4 * 1024 * 1024 * 1024;
+
+const OP_SET: u8 = 1;
+const OP_SET_BATCH: u8 = 2;
+const OP_DELETE: u8 = 3;
+const STATUS_OK: u8 = 0;
+const STATUS_ERROR: u8 = 1;
+
+/// Storage backend that forwards writes to the designated writer, if this
+/// process is not the writer itself
+pub struct DesignatedWriter {
+    inner: Arc<dyn StorageBackend>,
+    directory: PathBuf,
+    role: Mutex<Role>,
+    fork: ForkCheck,
+}
+
+enum Role {
+    /// Holds the writer lock and serves followers until dropped
+    Writer(#[allow(dead_code)] WriterServer),
+    /// Connected to the writer, or not yet
+    Follower(Option<UnixStream>),
+    Closed,
+}
+
+impl DesignatedWriter {
+    /// Serve `inner`'s writes for the other processes in `directory`, or
+    /// forward to the process that already does
+    pub fn open(directory: &Path, inner: Arc<dyn StorageBackend>) -> CacheResult<Self> {
+        let role = match WriterServer::start(directory, inner.clone())? {
+            Some(server) => Role::Writer(server),
+            None => Role::Follower(None),
+        };
+        Ok(Self {
+            inner,
+            directory: directory.to_path_buf(),
+            role: Mutex::new(role),
+            fork: ForkCheck::new(),
+        })
+    }
+
+    /// Whether this process is currently the designated writer
+    #[cfg(test)]
+    pub fn is_writer(&self) -> bool {
+        matches!(*self.role.lock(), Role::Writer(_))
+    }
+
+    /// Stop serving or forwarding writes; later writes are applied locally
+    pub fn close(&self) {
+        self.after_fork();
+        *self.role.lock() = Role::Closed;
+    }
+
+    /// Send `request` to the writer and return its reply, or `None` when the
+    /// write should be applied locally
+    fn forward(&self, request: &[u8]) -> CacheResult<Option<Vec<u8>>> {
+        let mut role = self.role.lock();
+        // Once to reach the current writer, once more after a failed takeover
+        for _ in 0..2 {
+            let Role::Follower(connection) = &mut *role else {
+                return Ok(None);
+            };
+            if connection.is_none() {
+                *connection = connect(&self.directory.join(SOCKET_FILE)).ok();
+            }
+            if let Some(stream) = connection {
+                match exchange(stream, request) {
+                    Ok(reply) => return decode_reply(reply).map(Some),
+                    Err(e) => {
+                        tracing::debug!("Lost the designated writer: {}", e);
+                        *connection = None;
+                    }
+                }
+            }
+            // Nobody answered; take over if the writer is gone
+            if let Some(server) = WriterServer::start(&self.directory, self.inner.clone())? {
+                *role = Role::Writer(server);
+                return Ok(None);
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Drop for DesignatedWriter {
+    fn drop(&mut self) {
+        self.after_fork();
+    }
+}
+
+fn connect(socket: &Path) -> io::Result<UnixStream> {
+    let stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(FORWARD_TIMEOUT))?;
+    stream.set_write_timeout(Some(FORWARD_TIMEOUT))?;
+    Ok(stream)
+}
+
+fn exchange(stream: &mut UnixStream, request: &[u8]) -> io::Result<Vec<u8>> {
+    write_frame(stream, request)?;
+    read_frame(stream)?.ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+}
+
+fn decode_reply(reply: Vec<u8>) -> CacheResult<Vec<u8>> {
+    match reply.split_first() {
+        Some((&STATUS_OK, payload)) => Ok(payload.to_vec()),
+        Some((&STATUS_ERROR, message)) => Err(CacheError::Io(io::Error::other(format!(
+            "designated writer: {}",
+            String::from_utf8_lossy(message)
+        )))),
+        _ => Err(CacheError::Corruption(
+            "malformed reply from the designated writer".into(),
+        )),
+    }
+}
+
+pub(crate) fn write_frame(stream: &mut UnixStream, frame: &[u8]) -> io::Result<()> {
+    stream.write_all(&(frame.len() as u64).to_le_bytes())?;
+    stream.write_all(frame)
+}
+
+/// Next frame from `stream`, or `None` once the peer has hung up
+pub(crate) fn read_frame(stream: &mut UnixStream) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 8];
+    match stream.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u64::from_le_bytes(len);
+    if len > MAX_FRAME {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} byte frame", len),
+        ));
+    }
+    let mut frame = vec![0u8; len as usize];
+    stream.read_exact(&mut frame)?;
+    Ok(Some(frame))
+}
+
+pub(crate) fn put_bytes(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    buffer.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+    buffer.extend_from_slice(bytes);
+}
+
+pub(crate) fn take_bytes<'a>(buffer: &mut &'a [u8]) -> CacheResult<&'a [u8]> {
+    let malformed = || CacheError::Deserialization("malformed request".into());
+    let (len, rest) = buffer.split_first_chunk::<8>().ok_or_else(malformed)?;
+    let len = usize::try_from(u64::from_le_bytes(*len)).map_err(|_| malformed())?;
+    if rest.len() < len {
+        return Err(malformed());
+    }
+    let (bytes, rest) = rest.split_at(len);
+    *buffer = rest;
+    Ok(bytes)
+}
+
+pub(crate) fn take_string(buffer: &mut &[u8]) -> CacheResult<String> {
+    String::from_utf8(take_bytes(buffer)?.to_vec())
+        .map_err(|_| CacheError::Deserialization("request key is not UTF-8".into()))
+}
+
+/// Apply one forwarded request to `storage`, returning the reply payload
+fn apply(storage: &dyn StorageBackend, request: &[u8]) -> CacheResult<Vec<u8>> {
+    let Some((&op, mut body)) = request.split_first() else {
+        return Err(CacheError::Deserialization("empty writer request".into()));
+    };
+    match op {
+        OP_SET => {
+            let key = take_string(&mut body)?;
+            let (entry, _) = bincode::serde::decode_from_slice::<CacheEntry, _>(
+                take_bytes(&mut body)?,
+                bincode::config::standard(),
+            )
+            .map_err(|e| CacheError::Deserialization(format!("forwarded entry: {}", e)))?;
+            storage.set(&key, entry)?;
+            Ok(Vec::new())
+        }
+        OP_SET_BATCH => {
+            let mut entries = Vec::new();
+            while !body.is_empty() {
+                let key = take_string(&mut body)?;
+                entries.push((key, take_bytes(&mut body)?.to_vec()));
+            }
+            storage.set_batch(entries)?;
+            Ok(Vec::new())
+        }
+        OP_DELETE => {
+            let existed = storage.delete(&take_string(&mut body)?)?;
+            Ok(vec![existed as u8])
+        }
+        op => Err(CacheError::Deserialization(format!(
+            "unknown writer request {}",
+            op
+        ))),
+    }
+}
+
+/// Follower connections and the threads serving them
+type Connections = Arc<Mutex<Vec<(UnixStream, JoinHandle<()>)>>>;
+
+/// The writer's end: the lock that makes it the writer and the socket it
+/// serves followers on
+struct WriterServer {
+    _lock: File,
+    socket: PathBuf,
+    stop: Arc<AtomicBool>,
+    connections: Connections,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl WriterServer {
+    /// Become the writer for `directory`, or return `None` if another process is
+    fn start(directory: &Path, storage: Arc<dyn StorageBackend>) -> CacheResult<Option<Self>> {
+        use fs4::fs_std::FileExt;
+
+        let lock = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(directory.join(LOCK_FILE))?;
+        if !FileExt::try_lock_exclusive(&lock).unwrap_or(false) {
+            return Ok(None);
+        }
+
+        // Only a writer that died without cleaning up leaves a socket behind
+        let socket = directory.join(SOCKET_FILE);
+        let _ = std::fs::remove_file(&socket);
+        let stop = Arc::new(AtomicBool::new(false));
+        let connections = Arc::new(Mutex::new(Vec::new()));
+        let acceptor = match UnixListener::bind(&socket) {
+            Ok(listener) => {
+                let stop = stop.clone();
+                let connections = connections.clone();
+                Some(std::thread::spawn(move || {
+                    Self::accept(listener, storage, stop, connections)
+                }))
+            }
+            Err(e) => {
+                // Followers find nobody to forward to and write for themselves
+                tracing::warn!(
+                    "Designated writer cannot listen on {:?}, followers will write directly: {}",
+                    socket,
+                    e
+                );
+                None
+            }
+        };
+        Ok(Some(Self {
+            _lock: lock,
+            socket,
+            stop,
+            connections,
+            acceptor,
+        }))
+    }
+
+    fn accept(
+        listener: UnixListener,
+        storage: Arc<dyn StorageBackend>,
+        stop: Arc<AtomicBool>,
+        connections: Connections,
+    ) {
+        for stream in listener.incoming() {
+            if stop.load(Ordering::Acquire) {
+                break;
+            }
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let Ok(handle) = stream.try_clone() else {
+                continue;
+            };
+            let storage = storage.clone();
+            let worker = std::thread::spawn(move || Self::serve(stream, &*storage));
+            connections.lock().push((handle, worker));
+        }
+    }
+
+    /// Apply one follower's requests until it hangs up
+    fn serve(mut stream: UnixStream, storage: &dyn StorageBackend) {
+        while let Ok(Some(request)) = read_frame(&mut stream) {
+            let reply = match apply(storage, &request) {
+                Ok(mut payload) => {
+                    payload.insert(0, STATUS_OK);
+                    payload
+                }
+                Err(e) => {
+                    let mut reply = vec![STATUS_ERROR];
+                    reply.extend_from_slice(e.to_string().as_bytes());
+                    reply
+                }
+            };
+            if write_frame(&mut stream, &reply).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+impl Drop for WriterServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(acceptor) = self.acceptor.take() {
+            // Wake the acceptor so it sees the stop flag
+            let _ = UnixStream::connect(&self.socket);
+            let _ = acceptor.join();
+            let _ = std::fs::remove_file(&self.socket);
+        }
+        // Followers see their connection drop and elect a new writer
+        for (stream, worker) in self.connections.lock().drain(..) {
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+            let _ = worker.join();
+        }
+    }
+}
+
+impl StorageBackend for DesignatedWriter {
+    fn get(&self, key: &str) -> CacheResult<Option<CacheEntry>> {
+        self.inner.get(key)
+    }
+
+    fn peek(&self, key: &str) -> CacheResult<Option<CacheEntry>> {
+        self.inner.peek(key)
+    }
+
+    fn get_many(&self, keys: &[String]) -> CacheResult<Vec<Option<CacheEntry>>> {
+        self.inner.get_many(keys)
+    }
+
+    fn prefetch_prefix(&self, prefix: &str) -> CacheResult<usize> {
+        self.inner.prefetch_prefix(prefix)
+    }
+
+    fn set(&self, key: &str, entry: CacheEntry) -> CacheResult<()> {
+        let mut request = vec![OP_SET];
+        put_bytes(&mut request, key.as_bytes());
+        let encoded = bincode::serde::encode_to_vec(&entry, bincode::config::standard())
+            .map_err(|e| CacheError::Serialization(format!("forwarded entry: {}", e)))?;
+        put_bytes(&mut request, &encoded);
+        match self.forward(&request)? {
+            Some(_) => Ok(()),
+            None => self.inner.set(key, entry),
+        }
+    }
+
+    fn set_batch(&self, entries: Vec<(String, Vec<u8>)>) -> CacheResult<()> {
+        let mut request = vec![OP_SET_BATCH];
+        for (key, value) in &entries {
+            put_bytes(&mut request, key.as_bytes());
+            put_bytes(&mut request, value);
+        }
+        match self.forward(&request)? {
+            Some(_) => Ok(()),
+            None => self.inner.set_batch(entries),
+        }
+    }
+
+    fn delete(&self, key: &str) -> CacheResult<bool> {
+        let mut request = vec![OP_DELETE];
+        put_bytes(&mut request, key.as_bytes());
+        match self.forward(&request)? {
+            Some(reply) => Ok(reply.first() == Some(&1)),
+            None => self.inner.delete(key),
+        }
+    }
+
+    /// Applied here: an eviction moves the entry out of the shared directory
+    fn evict(&self, key: &str) -> CacheResult<bool> {
+        self.inner.evict(key)
+    }
+
+    fn exists(&self, key: &str) -> CacheResult<bool> {
+        self.inner.exists(key)
+    }
+
+    fn exists_local(&self, key: &str) -> CacheResult<bool> {
+        self.inner.exists_local(key)
+    }
+
+    fn version(&self, key: &str) -> CacheResult<Option<u64>> {
+        self.inner.version(key)
+    }
+
+    fn set_alias(&self, alias: &str, key: &str) -> CacheResult<()> {
+        self.inner.set_alias(alias, key)
+    }
+
+    fn resolve_alias(&self, alias: &str) -> CacheResult<Option<String>> {
+        self.inner.resolve_alias(alias)
+    }
+
+    fn remove_alias(&self, alias: &str) -> CacheResult<bool> {
+        self.inner.remove_alias(alias)
+    }
+
+    fn aliases(&self, key: &str) -> CacheResult<Vec<String>> {
+        self.inner.aliases(key)
+    }
+
+    fn keys(&self) -> CacheResult<Vec<String>> {
+        self.inner.keys()
+    }
+
+    fn peek_key(&self, last: bool) -> CacheResult<Option<String>> {
+        self.inner.peek_key(last)
+    }
+
+    fn key_page(
+        &self,
+        cursor: Option<i64>,
+        reverse: bool,
+        limit: usize,
+    ) -> CacheResult<Vec<(i64, String)>> {
+        self.inner.key_page(cursor, reverse, limit)
+    }
+
+    fn peek_key_in_range(&self, start: &str, end: &str, last: bool) -> CacheResult<Option<String>> {
+        self.inner.peek_key_in_range(start, end, last)
+    }
+
+    fn clear(&self) -> CacheResult<()> {
+        self.inner.clear()
+    }
+
+    fn clear_with_progress(&self, progress: &(dyn Fn(UnlinkProgress) + Sync)) -> CacheResult<()> {
+        self.inner.clear_with_progress(progress)
+    }
+
+    fn vacuum(&self) -> CacheResult<VacuumReport> {
+        self.inner.vacuum()
+    }
+
+    fn generate_filename(&self, key: &str) -> String {
+        self.inner.generate_filename(key)
+    }
+
+    fn write_data_file(&self, filename: &str, data: &[u8]) -> CacheResult<()> {
+        self.inner.write_data_file(filename, data)
+    }
+
+    fn read_data_file(&self, filename: &str) -> CacheResult<Vec<u8>> {
+        self.inner.read_data_file(filename)
+    }
+
+    fn data_file_path(&self, key: &str) -> CacheResult<Option<(PathBuf, u64)>> {
+        self.inner.data_file_path(key)
+    }
+
+    fn open_frames(&self, key: &str) -> CacheResult<Option<FramedReader<File>>> {
+        self.inner.open_frames(key)
+    }
+
+    fn link_file(&self, key: &str, source: &Path) -> CacheResult<u64> {
+        self.inner.link_file(key, source)
+    }
+
+    fn write_stream(&self, key: &str) -> CacheResult<DataFileWriter> {
+        self.inner.write_stream(key)
+    }
+
+    fn commit_stream(&self, writer: DataFileWriter) -> CacheResult<u64> {
+        self.inner.commit_stream(writer)
+    }
+
+    fn register_files(&self, files: &[(String, PathBuf)]) -> CacheResult<u64> {
+        self.inner.register_files(files)
+    }
+
+    fn watch_expirations(&self, listener: ExpiryListener) {
+        self.inner.watch_expirations(listener)
+    }
+
+    fn set_pinned(&self, key: &str, pinned: bool) {
+        self.inner.set_pinned(key, pinned)
+    }
+
+    fn remove_expired(&self) -> CacheResult<u64> {
+        self.inner.remove_expired()
+    }
+
+    fn gate_maintenance(&self, gate: MaintenanceGate) {
+        self.inner.gate_maintenance(gate)
+    }
+
+    fn gate_cleanup(&self, gate: CleanupGate) {
+        self.inner.gate_cleanup(gate)
+    }
+
+    fn was_unclean_shutdown(&self) -> Option<bool> {
+        self.inner.was_unclean_shutdown()
+    }
+
+    /// A forked child of the writer does not serve: the server, its socket
+    /// and the writer lock stay the parent's. It starts out as a follower.
+    fn after_fork(&self) {
+        let mut role = self.role.lock();
+        if self.fork.forked() && !matches!(*role, Role::Closed) {
+            if let Role::Writer(server) = std::mem::replace(&mut *role, Role::Follower(None)) {
+                // Dropping it here would stop the parent's server
+                std::mem::forget(server);
+            }
+        }
+        drop(role);
+        self.inner.after_fork();
+    }
+
+    fn verify_and_recover(&self) -> CacheResult<RecoveryReport> {
+        self.inner.verify_and_recover()
+    }
+
+    fn estimate_footprint(&self, entries: u64, key_size: usize, value_size: usize) -> Footprint {
+        self.inner.estimate_footprint(entries, key_size, value_size)
+    }
+
+    fn io_stats(&self) -> IoStats {
+        self.inner.io_stats()
+    }
+
+    fn size(&self) -> Option<TierSizes> {
+        self.inner.size()
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self.inner.as_any()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::OptimizedStorage;
+    use tempfile::TempDir;
+
+    fn open(directory: &Path) -> DesignatedWriter {
+        let storage = Arc::new(OptimizedStorage::new(directory).unwrap());
+        DesignatedWriter::open(directory, storage).unwrap()
+    }
+
+    #[test]
+    fn followers_forward_writes_and_take_over() {
+        let temp_dir = TempDir::new().unwrap();
+        let writer = open(temp_dir.path());
+        let follower = open(temp_dir.path());
+        assert!(writer.is_writer());
+        assert!(!follower.is_writer());
+
+        let entry = CacheEntry::new_inline("key".into(), b"value".to_vec(), vec![], None);
+        follower.set("key", entry).unwrap();
+        StorageBackend::set_batch(
+            &follower,
+            vec![("a".into(), b"1".to_vec()), ("b".into(), b"2".to_vec())],
+        )
+        .unwrap();
+        for (key, value) in [("key", &b"value"[..]), ("a", b"1"), ("b", b"2")] {
+            let entry = writer.get(key).unwrap().unwrap();
+            assert_eq!(entry.get_data().unwrap(), value);
+        }
+        assert!(follower.delete("a").unwrap());
+        assert!(!follower.delete("a").unwrap());
+        assert!(writer.get("a").unwrap().is_none());
+
+        // The next write after the writer goes away elects the follower
+        drop(writer);
+        follower.delete("b").unwrap();
+        assert!(follower.is_writer());
+        assert!(follower.get("b").unwrap().is_none());
+    }
+}
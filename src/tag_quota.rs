@@ -0,0 +1,157 @@
+//! Per-tag quotas
+//!
+//! [`DiskCache::set_tag_limit`](crate::DiskCache::set_tag_limit) caps the
+//! bytes or entries stored under one tag, so one tenant cannot crowd out the
+//! others. The entries of every limited tag are counted here as they are
+//! stored and removed, and a write leaving its tag over the limit evicts
+//! within that tag before anything else. Tags without a limit are not
+//! counted at all; setting a limit counts what the tag already holds once.
+//!
+//! Like pins, limits belong to the handle that set them and are not stored.
+
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap};
+
+/// Most a tag may hold; `None` leaves that dimension unlimited
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TagLimit {
+    pub max_bytes: Option<u64>,
+    pub max_entries: Option<u64>,
+}
+
+/// How far a tag is over its limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TagExcess {
+    pub bytes: u64,
+    pub entries: u64,
+}
+
+/// What a limited tag holds
+struct TagUsage {
+    limit: TagLimit,
+    /// Store sequence and size of each key, as stored
+    keys: HashMap<String, (u64, u64)>,
+    /// Keys in the order they were stored
+    order: BTreeMap<u64, String>,
+    bytes: u64,
+}
+
+impl TagUsage {
+    fn insert(&mut self, key: &str, size: u64, sequence: u64) {
+        self.remove(key);
+        self.keys.insert(key.to_string(), (sequence, size));
+        self.order.insert(sequence, key.to_string());
+        self.bytes += size;
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some((sequence, size)) = self.keys.remove(key) {
+            self.order.remove(&sequence);
+            self.bytes -= size;
+        }
+    }
+}
+
+#[derive(Default)]
+struct Quotas {
+    tags: HashMap<String, TagUsage>,
+    sequence: u64,
+}
+
+/// The limited tags of a cache and what each holds
+#[derive(Default)]
+pub struct TagQuotas {
+    quotas: Mutex<Quotas>,
+}
+
+impl TagQuotas {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit `tag`, whose stored entries and their sizes are `stored`
+    pub fn set_limit(&self, tag: &str, limit: TagLimit, stored: Vec<(String, u64)>) {
+        let mut quotas = self.quotas.lock();
+        let mut usage = TagUsage {
+            limit,
+            keys: HashMap::new(),
+            order: BTreeMap::new(),
+            bytes: 0,
+        };
+        for (key, size) in stored {
+            quotas.sequence += 1;
+            usage.insert(&key, size, quotas.sequence);
+        }
+        quotas.tags.insert(tag.to_string(), usage);
+    }
+
+    /// Stop limiting `tag`, returning whether it was limited
+    pub fn remove_limit(&self, tag: &str) -> bool {
+        self.quotas.lock().tags.remove(tag).is_some()
+    }
+
+    pub fn limit(&self, tag: &str) -> Option<TagLimit> {
+        self.quotas.lock().tags.get(tag).map(|usage| usage.limit)
+    }
+
+    /// Count `key` as stored with `size` bytes under `tags`, and under no
+    /// other tag
+    pub fn on_store(&self, key: &str, tags: &[String], size: u64) {
+        let mut quotas = self.quotas.lock();
+        if quotas.tags.is_empty() {
+            return;
+        }
+        quotas.sequence += 1;
+        let sequence = quotas.sequence;
+        for (tag, usage) in quotas.tags.iter_mut() {
+            if tags.contains(tag) {
+                usage.insert(key, size, sequence);
+            } else {
+                usage.remove(key);
+            }
+        }
+    }
+
+    pub fn on_remove(&self, key: &str) {
+        for usage in self.quotas.lock().tags.values_mut() {
+            usage.remove(key);
+        }
+    }
+
+    /// Forget every entry, keeping the limits
+    pub fn clear(&self) {
+        for usage in self.quotas.lock().tags.values_mut() {
+            usage.keys.clear();
+            usage.order.clear();
+            usage.bytes = 0;
+        }
+    }
+
+    /// How far `tag` is over its limit, if it is limited and over
+    pub fn excess(&self, tag: &str) -> Option<TagExcess> {
+        let quotas = self.quotas.lock();
+        let usage = quotas.tags.get(tag)?;
+        let over = |held: u64, limit: Option<u64>| limit.map_or(0, |max| held.saturating_sub(max));
+        let excess = TagExcess {
+            bytes: over(usage.bytes, usage.limit.max_bytes),
+            entries: over(usage.keys.len() as u64, usage.limit.max_entries),
+        };
+        (excess != TagExcess::default()).then_some(excess)
+    }
+
+    /// Bytes `key` holds under `tag`, if `tag` is limited and holds it
+    pub fn size(&self, tag: &str, key: &str) -> Option<u64> {
+        let quotas = self.quotas.lock();
+        quotas.tags.get(tag)?.keys.get(key).map(|&(_, size)| size)
+    }
+
+    /// The keys held under `tag`, the least recently stored first
+    pub fn keys(&self, tag: &str) -> Vec<String> {
+        let quotas = self.quotas.lock();
+        quotas
+            .tags
+            .get(tag)
+            .map(|usage| usage.order.values().cloned().collect())
+            .unwrap_or_default()
+    }
+}
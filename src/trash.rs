@@ -0,0 +1,143 @@
+//! Trash area for soft-deleted entries
+//!
+//! With soft delete on, [`DiskCache::delete`](crate::DiskCache::delete) moves
+//! an entry into `trash/` under the cache directory instead of dropping it,
+//! and [`DiskCache::undelete`](crate::DiskCache::undelete) puts it back. Each
+//! trashed entry is one file named after the hash of its key, so deleting a
+//! key again replaces the copy kept for it. Entries are purged for good once
+//! they have been in the trash for the grace period.
+
+use crate::error::{CacheError, CacheResult};
+use crate::utils::current_timestamp;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Directory under the cache directory holding trashed entries
+const TRASH_DIR: &str = "trash";
+const TRASH_EXTENSION: &str = "del";
+/// Least time between two purges triggered by deletes, in seconds
+const PURGE_INTERVAL: u64 = 60;
+
+/// Distinguishes temporary files written at the same time by one process
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// An entry as kept in the trash, with its stored (codec-encoded) value
+#[derive(Debug, bincode::Encode, bincode::Decode)]
+pub struct TrashedEntry {
+    pub key: String,
+    pub value: Vec<u8>,
+    pub tags: Vec<String>,
+    pub expire_time: Option<u64>,
+    pub deleted_at: u64,
+}
+
+pub struct Trash {
+    directory: PathBuf,
+    grace: Duration,
+    last_purge: AtomicU64,
+}
+
+impl Trash {
+    /// Open the trash of the cache in `cache_dir`, purging entries past `grace`
+    pub fn open(cache_dir: &Path, grace: Duration) -> CacheResult<Self> {
+        let directory = cache_dir.join(TRASH_DIR);
+        std::fs::create_dir_all(&directory)?;
+        let trash = Self {
+            directory,
+            grace,
+            last_purge: AtomicU64::new(current_timestamp()),
+        };
+        trash.purge()?;
+        Ok(trash)
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.directory.join(format!(
+            "{}.{}",
+            blake3::hash(key.as_bytes()).to_hex(),
+            TRASH_EXTENSION
+        ))
+    }
+
+    /// Keep `entry` until the grace period runs out
+    pub fn put(&self, entry: &TrashedEntry) -> CacheResult<()> {
+        let bytes = bincode::encode_to_vec(entry, bincode::config::standard())
+            .map_err(|e| CacheError::Serialization(format!("trashed entry: {}", e)))?;
+        // Written aside and renamed, so a crash never leaves half an entry
+        let path = self.path(&entry.key);
+        let temp_path = path.with_extension(format!(
+            "{}-{}.tmp",
+            std::process::id(),
+            TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&temp_path, bytes)?;
+        std::fs::rename(&temp_path, &path)?;
+
+        let now = current_timestamp();
+        let last_purge = self.last_purge.load(Ordering::Relaxed);
+        if now.saturating_sub(last_purge) >= PURGE_INTERVAL
+            && self
+                .last_purge
+                .compare_exchange(last_purge, now, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            self.purge()?;
+        }
+        Ok(())
+    }
+
+    /// Take `key`'s entry out of the trash, if it is still there
+    pub fn take(&self, key: &str) -> CacheResult<Option<TrashedEntry>> {
+        let path = self.path(key);
+        let Some(entry) = self.read(&path)? else {
+            return Ok(None);
+        };
+        std::fs::remove_file(&path)?;
+        // A hash collision is not the entry asked for; leave nothing behind either way
+        Ok((entry.key == key && !self.is_past_grace(&entry)).then_some(entry))
+    }
+
+    fn read(&self, path: &Path) -> CacheResult<Option<TrashedEntry>> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(CacheError::Io(e)),
+        };
+        bincode::decode_from_slice(&bytes, bincode::config::standard())
+            .map(|(entry, _)| Some(entry))
+            .map_err(|e| CacheError::Deserialization(format!("trashed entry {:?}: {}", path, e)))
+    }
+
+    fn is_past_grace(&self, entry: &TrashedEntry) -> bool {
+        current_timestamp().saturating_sub(entry.deleted_at) >= self.grace.as_secs()
+    }
+
+    /// Remove entries that have been in the trash for the grace period,
+    /// returning how many were removed
+    ///
+    /// Unreadable files are removed too, as are temporary files old enough
+    /// to have been left by a crash.
+    pub fn purge(&self) -> CacheResult<u64> {
+        let mut purged = 0;
+        for file in std::fs::read_dir(&self.directory)?.flatten() {
+            let path = file.path();
+            let expired = match path.extension().and_then(|ext| ext.to_str()) {
+                Some(TRASH_EXTENSION) => self
+                    .read(&path)
+                    .map_or(true, |entry| entry.is_some_and(|e| self.is_past_grace(&e))),
+                Some("tmp") => file
+                    .metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+                    .and_then(|modified| modified.elapsed().ok())
+                    .is_some_and(|age| age.as_secs() >= PURGE_INTERVAL),
+                _ => false,
+            };
+            if expired && std::fs::remove_file(&path).is_ok() {
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+}
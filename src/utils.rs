@@ -1,5 +1,11 @@
-use crate::error::{CacheError, CacheResult};
-use std::time::{SystemTime, UNIX_EPOCH};
+use crate::error::{CacheError, CacheResult, ConfigIssue};
+use crate::eviction::EvictionStrategy;
+use crate::process_lock::{LockBackend, LockGranularity, ProcessLock};
+use crate::storage::optimized_backend::MAX_DATA_FANOUT;
+use crate::storage::ring_backend::MIN_RING_CAPACITY;
+use crate::storage::{Compression, Durability, HotCachePolicy, StorageKind};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Get current timestamp in seconds since Unix epoch
 pub fn current_timestamp() -> u64 {
@@ -10,7 +16,6 @@ pub fn current_timestamp() -> u64 {
 }
 
 /// Get current timestamp in milliseconds since Unix epoch
-#[allow(dead_code)]
 pub fn current_timestamp_millis() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -186,6 +191,133 @@ impl Drop for FileLock {
     }
 }
 
+/// Per-process marker that is held locked while a cache directory is open
+///
+/// A marker left behind whose lock can be acquired by another opener means
+/// its owner exited without closing the cache cleanly.
+pub struct OpenMarker {
+    path: PathBuf,
+    file: Option<std::fs::File>,
+}
+
+impl OpenMarker {
+    const DIRECTORY: &'static str = "open_markers";
+
+    /// Claim a marker in `directory`, returning it together with whether
+    /// markers from an unclean shutdown were found (and cleaned up)
+    ///
+    /// On filesystems without locks no marker is kept, since it could not
+    /// tell a live owner from a dead one, and `None` is returned.
+    pub fn acquire(directory: &Path) -> CacheResult<Option<(Self, bool)>> {
+        use fs4::fs_std::FileExt;
+
+        let marker_dir = directory.join(Self::DIRECTORY);
+        std::fs::create_dir_all(&marker_dir)?;
+
+        let mut unclean = false;
+        for entry in std::fs::read_dir(&marker_dir)?.flatten() {
+            let Ok(file) = std::fs::OpenOptions::new().write(true).open(entry.path()) else {
+                continue;
+            };
+            // A lock we can take belongs to a process that is gone
+            if FileExt::try_lock_exclusive(&file).unwrap_or(false) {
+                unclean = true;
+                drop(file);
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+
+        let path = marker_dir.join(format!(
+            "{}-{}.marker",
+            std::process::id(),
+            uuid::Uuid::new_v4().simple()
+        ));
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        if let Err(e) = FileExt::lock_exclusive(&file) {
+            drop(file);
+            let _ = std::fs::remove_file(&path);
+            return match locks_unsupported(&e) {
+                true => Ok(None),
+                false => Err(e.into()),
+            };
+        }
+
+        Ok(Some((
+            Self {
+                path,
+                file: Some(file),
+            },
+            unclean,
+        )))
+    }
+
+    /// Release the lock and remove the marker, recording a clean shutdown
+    pub fn release(&mut self) {
+        if self.file.take().is_some() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    /// Close a marker inherited by a forked child, leaving it in place for
+    /// the parent that made it
+    pub fn disown(&mut self) {
+        self.file.take();
+    }
+}
+
+impl Drop for OpenMarker {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+/// Exclusive lock over the one-time setup of a cache directory
+///
+/// Creating the index schema, recording the directory format, finishing
+/// migrations and replaying logs are not safe to run twice at once. Every
+/// opener holds this lock across them, so workers started together set the
+/// directory up one after another and then share it. It is never held past
+/// the open. A thread that holds it already can take it again, as opening a
+/// cache opens its storage.
+pub struct SetupLock {
+    /// Lock and the path naming it; `None` for a nested acquisition
+    held: Option<(ProcessLock, PathBuf)>,
+}
+
+thread_local! {
+    static SETUP_LOCKS_HELD: std::cell::RefCell<std::collections::HashSet<PathBuf>> =
+        Default::default();
+}
+
+impl SetupLock {
+    const FILE: &'static str = "setup.lock";
+
+    /// Wait for the other openers of `directory`, then hold its setup lock
+    pub fn acquire(directory: &Path, backend: LockBackend) -> CacheResult<Self> {
+        std::fs::create_dir_all(directory)?;
+        let path = directory.canonicalize()?.join(Self::FILE);
+        if SETUP_LOCKS_HELD.with(|held| held.borrow().contains(&path)) {
+            return Ok(Self { held: None });
+        }
+        let lock = ProcessLock::acquire(backend, &path)?;
+        SETUP_LOCKS_HELD.with(|held| held.borrow_mut().insert(path.clone()));
+        Ok(Self {
+            held: Some((lock, path)),
+        })
+    }
+}
+
+impl Drop for SetupLock {
+    fn drop(&mut self) {
+        if let Some((_lock, path)) = self.held.take() {
+            SETUP_LOCKS_HELD.with(|held| held.borrow_mut().remove(&path));
+        }
+    }
+}
+
 /// Statistics collection
 #[derive(Debug, Clone, Default)]
 pub struct CacheStats {
@@ -197,6 +329,8 @@ pub struct CacheStats {
     pub errors: u64,
     pub total_size: u64,
     pub entry_count: u64,
+    /// Victims waiting for the background thread to evict them
+    pub eviction_backlog: u64,
 }
 
 impl CacheStats {
@@ -226,45 +360,584 @@ impl CacheStats {
     }
 }
 
+/// Largest write batch that still makes sense when every write is synced
+pub const MAX_SYNC_BATCH_SIZE: usize = 1_000;
+
+/// Filesystems that cannot be memory-mapped reliably
+const FAT_FILESYSTEMS: &[&str] = &["vfat", "msdos", "fat", "exfat"];
+
 /// Configuration validation
-pub fn validate_cache_config(
-    max_size: Option<u64>,
-    max_entries: Option<u64>,
-    directory: &std::path::Path,
-) -> CacheResult<()> {
-    // Check if directory exists or can be created
-    if !directory.exists() {
-        std::fs::create_dir_all(directory)
-            .map_err(|e| CacheError::InvalidConfig(format!("Cannot create directory: {}", e)))?;
+///
+/// Rejects unusable directories, zero limits and combinations of options that
+/// cannot work together. Each rejection names the option and how to fix it.
+pub fn validate_cache_config(config: &crate::cache::CacheConfig) -> CacheResult<()> {
+    if config.backend.uses_directory() {
+        validate_directory(&config.directory)?;
     }
 
-    // Check if directory is writable
-    let test_file = directory.join(".test_write");
-    std::fs::write(&test_file, b"test")
-        .map_err(|e| CacheError::InvalidConfig(format!("Directory not writable: {}", e)))?;
-    std::fs::remove_file(&test_file)
-        .map_err(|e| CacheError::InvalidConfig(format!("Cannot clean up test file: {}", e)))?;
-
     // Validate size limits
-    if let Some(max_size) = max_size {
-        if max_size == 0 {
-            return Err(CacheError::InvalidConfig(
-                "Max size cannot be zero".to_string(),
-            ));
+    if config.max_size == Some(0) {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "max_size",
+            "Max size cannot be zero",
+            "Pass None for an unbounded cache or a positive size in bytes",
+        )));
+    }
+
+    if config.max_entries == Some(0) {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "max_entries",
+            "Max entries cannot be zero",
+            "Pass None for an unbounded cache or a positive entry count",
+        )));
+    }
+
+    if config.batch_size == 0 {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "batch_size",
+            "Batch size cannot be zero",
+            "Use a batch size of at least 1",
+        )));
+    }
+
+    if config.write_queue_capacity == 0 {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "write_queue_capacity",
+            "The write queue needs room for at least one write",
+            "Use a capacity of at least 1, or sync_writes to skip the queue",
+        )));
+    }
+
+    if config.sync_writes && config.batch_size > MAX_SYNC_BATCH_SIZE {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "batch_size",
+            format!(
+                "sync_writes flushes every write, so a batch size of {} only delays errors",
+                config.batch_size
+            ),
+            format!(
+                "Lower batch_size to {} or less, or disable sync_writes",
+                MAX_SYNC_BATCH_SIZE
+            ),
+        )));
+    }
+
+    if config.backend != StorageKind::Optimized && config.wal.is_some() {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "wal",
+            "Only the optimized backend has a write-ahead log; the others commit through their own journal or keep nothing on disk",
+            "Drop the wal option or use the optimized backend",
+        )));
+    }
+
+    if config.backend == StorageKind::Memory && config.durability >= Durability::Fsync {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "durability",
+            "The memory backend keeps nothing on disk to sync",
+            "Drop the durability option or use a disk-backed backend",
+        )));
+    }
+
+    match (config.backend, config.ring_capacity_bytes) {
+        (StorageKind::Ring, None) => {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "ring_capacity_bytes",
+                "The ring backend allocates its file up front and needs to know its size",
+                "Set ring_capacity_bytes to the most bytes the cache may hold",
+            )));
+        }
+        (StorageKind::Ring, Some(capacity)) if capacity < MIN_RING_CAPACITY => {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "ring_capacity_bytes",
+                format!("A ring of {} bytes holds almost no entries", capacity),
+                format!("Use a capacity of at least {} bytes", MIN_RING_CAPACITY),
+            )));
+        }
+        (StorageKind::Ring, Some(_)) | (_, None) => {}
+        (_, Some(_)) => {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "ring_capacity_bytes",
+                "Only the ring backend has a fixed capacity",
+                "Drop the ring_capacity_bytes option or use the ring backend",
+            )));
+        }
+    }
+
+    if !config.backend.uses_directory() && config.soft_delete.is_some() {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "soft_delete",
+            format!(
+                "The {} backend has no directory to keep a trash in",
+                config.backend.name()
+            ),
+            "Drop the soft_delete option or use a disk-backed backend",
+        )));
+    }
+
+    if config.backend != StorageKind::Optimized && config.index_key.is_some() {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "index_key",
+            "Only the optimized backend signs its index",
+            "Drop the index_key option or use the optimized backend",
+        )));
+    }
+
+    if config.backend != StorageKind::Optimized && config.compression != Compression::default() {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "compression",
+            "Only the optimized backend compresses values",
+            "Drop the compression option or use the optimized backend",
+        )));
+    }
+
+    if let Some(level) = config.compression_level {
+        config.compression.check_level(level)?;
+    }
+
+    if config.backend != StorageKind::Optimized && config.zstd_dictionary {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "zstd_dictionary",
+            "Only the optimized backend compresses values with a dictionary",
+            "Drop the zstd_dictionary option or use the optimized backend",
+        )));
+    }
+
+    if config.zstd_dictionary && config.dictionary_threshold == 0 {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "dictionary_threshold",
+            "A dictionary threshold of 0 bytes leaves no value to compress with the dictionary",
+            "Set dictionary_threshold to a size such as 8192, or drop zstd_dictionary",
+        )));
+    }
+
+    if config.backend != StorageKind::Optimized && config.seekable_compression {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "seekable_compression",
+            "Only the optimized backend compresses values in seekable frames",
+            "Drop the seekable_compression option or use the optimized backend",
+        )));
+    }
+
+    if config.seekable_compression && config.compression.framed().is_none() {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "seekable_compression",
+            format!(
+                "{} compression has no seekable frames",
+                config.compression.name()
+            ),
+            "Set compression=\"lz4\" or compression=\"zstd\", or drop seekable_compression",
+        )));
+    }
+
+    if config.backend != StorageKind::Optimized && config.remote_tier.is_some() {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "remote_tier",
+            "Only the optimized backend pushes evicted entries to a remote tier",
+            "Drop the remote_tier option or use the optimized backend",
+        )));
+    }
+
+    if config.backend != StorageKind::Optimized && config.watch_directory {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "watch_directory",
+            "Only the optimized backend keeps data files to watch",
+            "Drop the watch_directory option or use the optimized backend",
+        )));
+    }
+
+    if config.backend != StorageKind::Optimized && config.event_log {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "event_log",
+            "Only the optimized backend keeps values in memory tiers to invalidate",
+            "Drop the event_log option or use the optimized backend",
+        )));
+    }
+
+    if config.backend != StorageKind::Optimized && config.lock_granularity != LockGranularity::Shard
+    {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "lock_granularity",
+            "Only the optimized backend locks keys across processes",
+            "Drop the lock_granularity option or use the optimized backend",
+        )));
+    }
+
+    if config.backend != StorageKind::Optimized && config.hot_cache_policy != HotCachePolicy::Lru {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "hot_cache_policy",
+            "Only the optimized backend keeps a hot tier of values",
+            "Drop the hot_cache_policy option or use the optimized backend",
+        )));
+    }
+
+    if config.warm_cache_size == 0 || config.warm_cache_bytes == 0 {
+        let option = if config.warm_cache_size == 0 {
+            "warm_cache_size"
+        } else {
+            "warm_cache_bytes"
+        };
+        return Err(CacheError::Config(ConfigIssue::new(
+            option,
+            "The warm tier must hold at least one mapped file",
+            "Set use_mmap to false to read data files without mapping them",
+        )));
+    }
+
+    if config.eviction_samples > 0
+        && !matches!(
+            config.eviction_strategy,
+            EvictionStrategy::Lru
+                | EvictionStrategy::LruTtl
+                | EvictionStrategy::Lfu
+                | EvictionStrategy::LfuTtl
+        )
+    {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "eviction_samples",
+            "Only the least-recently-used and least-frequently-used policies sample victims",
+            "Drop the eviction_samples option or set eviction_policy to \"least-recently-used\" or \"least-frequently-used\"",
+        )));
+    }
+
+    if config.max_idle.is_some_and(|max_idle| max_idle.is_zero()) {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "max_idle",
+            "An idle period of zero would expire every entry as soon as it is stored",
+            "Drop the max_idle option to keep entries however long they go unread",
+        )));
+    }
+
+    if config.smb_mode {
+        if config.backend != StorageKind::Optimized {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "smb_mode",
+                "Only the optimized backend publishes values by renaming files into place",
+                "Drop the smb_mode option or use the optimized backend",
+            )));
+        }
+        if config.pack_threshold > 0 {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "smb_mode",
+                "Packed values are appended to segment files under byte-range locks",
+                "Set pack_threshold to 0 to keep every value in its own file",
+            )));
+        }
+    }
+
+    if config.backend != StorageKind::Optimized && config.direct_io_threshold.is_some() {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "direct_io_threshold",
+            "Only the optimized backend writes data files with direct I/O",
+            "Drop the direct_io_threshold option or use the optimized backend",
+        )));
+    }
+
+    if config.designated_writer {
+        if config.backend == StorageKind::Server {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "designated_writer",
+                "The cache server is the only process writing to its directory",
+                "Drop the designated_writer option when attaching to a cache server",
+            )));
+        }
+        if !cfg!(unix) {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "designated_writer",
+                "Writes are forwarded to the designated writer over a Unix socket",
+                "Drop the designated_writer option on this platform",
+            )));
+        }
+        if matches!(
+            config.backend,
+            StorageKind::Memory | StorageKind::Redb | StorageKind::Ring
+        ) {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "designated_writer",
+                format!(
+                    "The {:?} backend cannot be shared between processes",
+                    config.backend
+                ),
+                "Use the optimized or sqlite backend, or drop the designated_writer option",
+            )));
+        }
+    }
+
+    if let Some(period) = config.writer_lease {
+        if config.backend == StorageKind::Server {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "writer_lease",
+                "The cache server is the only process writing to its directory",
+                "Drop the writer_lease option when attaching to a cache server",
+            )));
+        }
+        if matches!(
+            config.backend,
+            StorageKind::Memory | StorageKind::Redb | StorageKind::Ring
+        ) {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "writer_lease",
+                format!(
+                    "The {:?} backend cannot be shared between processes",
+                    config.backend
+                ),
+                "Use the optimized or sqlite backend, or drop the writer_lease option",
+            )));
+        }
+        if config.designated_writer {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "writer_lease",
+                "A designated writer already serves the writes of every process",
+                "Use either writer_lease or designated_writer",
+            )));
+        }
+        if period < Duration::from_secs(1) {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "writer_lease",
+                format!(
+                    "A lease period of {:?} is too short to renew reliably",
+                    period
+                ),
+                "Use a lease period of at least a second, e.g. 30",
+            )));
+        }
+    }
+
+    if let Some(period) = config.maintenance_lease {
+        if !config.backend.uses_directory() {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "maintenance_lease",
+                format!(
+                    "The {} backend shares no directory with other processes",
+                    config.backend.name()
+                ),
+                "Drop the maintenance_lease option",
+            )));
+        }
+        if period < Duration::from_secs(2) {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "maintenance_lease",
+                format!(
+                    "A lease period of {:?} is too short to renew reliably",
+                    period
+                ),
+                "Use a lease period of at least two seconds, e.g. 7200",
+            )));
+        }
+    }
+
+    if config.group_commit.is_some() {
+        if config.backend != StorageKind::Optimized {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "group_commit",
+                "Only the optimized backend gathers data file writes into group commits",
+                "Drop the group_commit option or use the optimized backend",
+            )));
+        }
+        if config.durability < Durability::Fsync && !config.use_file_locking {
+            return Err(CacheError::Config(ConfigIssue::new(
+                "group_commit",
+                format!(
+                    "Group commit shares fsyncs between writes, but durability {:?} does not fsync data files",
+                    config.durability
+                ),
+                "Set durability to fsync or fsync+dirsync, or drop the group_commit option",
+            )));
+        }
+    }
+
+    if !(config.compaction_ratio > 0.0 && config.compaction_ratio <= 1.0) {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "compaction_ratio",
+            format!(
+                "Compaction ratio must be in (0, 1], got {}",
+                config.compaction_ratio
+            ),
+            "Use the share of dead bytes that should trigger compaction, e.g. 0.5",
+        )));
+    }
+
+    if config.data_fanout > MAX_DATA_FANOUT {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "data_fanout",
+            format!(
+                "A fan-out of {} levels is deeper than the {} data file names support",
+                config.data_fanout, MAX_DATA_FANOUT
+            ),
+            "Use 2 levels, or 0 to keep data files in one directory",
+        )));
+    }
+
+    if config.unlink_workers == 0 {
+        return Err(CacheError::Config(ConfigIssue::new(
+            "unlink_workers",
+            "At least one worker is needed to remove data files",
+            "Use 1 worker to remove files one at a time, or more to remove them in parallel",
+        )));
+    }
+
+    // The remaining checks probe the filesystem the directory is on
+    if !config.backend.uses_directory() {
+        return Ok(());
+    }
+    let directory = config.directory.as_path();
+
+    // Probe the locks the cache will take: asking for locks that hold up
+    // on NFS trades file locks for lock files, which need no lock support
+    if config.use_file_locking {
+        let lock_backend = config
+            .lock_backend
+            .nfs_safe(config.use_file_locking || config.smb_mode);
+        match lock_backend {
+            LockBackend::File if !supports_file_locking(directory) => {
+                return Err(CacheError::Config(ConfigIssue::new(
+                    "use_file_locking",
+                    format!("{} does not support file locks", directory.display()),
+                    "Disable use_file_locking or move the cache to a filesystem with lock support",
+                )));
+            }
+            LockBackend::LockFile if !supports_exclusive_create(directory) => {
+                return Err(CacheError::Config(ConfigIssue::new(
+                    "use_file_locking",
+                    format!(
+                        "{} does not support creating lock files exclusively",
+                        directory.display()
+                    ),
+                    "Disable use_file_locking or move the cache to another filesystem",
+                )));
+            }
+            _ => {}
+        }
+    }
+
+    if config.use_mmap {
+        if let Some(fs_type) = filesystem_type(directory) {
+            if FAT_FILESYSTEMS.contains(&fs_type.as_str()) {
+                return Err(CacheError::Config(ConfigIssue::new(
+                    "use_mmap",
+                    format!("Memory mapping is unreliable on {} filesystems", fs_type),
+                    "Disable use_mmap for caches on FAT-formatted drives",
+                )));
+            }
         }
     }
 
-    if let Some(max_entries) = max_entries {
-        if max_entries == 0 {
-            return Err(CacheError::InvalidConfig(
-                "Max entries cannot be zero".to_string(),
-            ));
+    Ok(())
+}
+
+/// Check that the cache directory exists or can be created, and is writable
+fn validate_directory(directory: &Path) -> CacheResult<()> {
+    if !directory.exists() {
+        std::fs::create_dir_all(directory).map_err(|e| {
+            CacheError::Config(ConfigIssue::new(
+                "directory",
+                format!("Cannot create directory: {}", e),
+                "Choose a directory the current user is allowed to create",
+            ))
+        })?;
+    }
+
+    // Check if directory is writable
+    let test_file = directory.join(".test_write");
+    std::fs::write(&test_file, b"test").map_err(|e| {
+        CacheError::Config(ConfigIssue::new(
+            "directory",
+            format!("Directory not writable: {}", e),
+            "Fix the directory permissions or choose a writable directory",
+        ))
+    })?;
+    // Another process opening the directory may have removed it already
+    match std::fs::remove_file(&test_file) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(CacheError::InvalidConfig(format!(
+                "Cannot clean up test file: {}",
+                e
+            )))
         }
+        _ => {}
     }
 
     Ok(())
 }
 
+/// Probe whether exclusive file locks can be taken inside `directory`
+///
+/// The probe file is this call's own, but a lock that would block still
+/// shows locks work; only a filesystem refusing them fails the probe.
+fn supports_file_locking(directory: &Path) -> bool {
+    use fs4::fs_std::FileExt;
+
+    let probe = probe_path(directory);
+    let supported = match std::fs::File::create(&probe) {
+        Ok(file) => match FileExt::try_lock_exclusive(&file) {
+            Ok(_) => {
+                let _ = FileExt::unlock(&file);
+                true
+            }
+            Err(e) => !locks_unsupported(&e),
+        },
+        Err(_) => false,
+    };
+    let _ = std::fs::remove_file(&probe);
+    supported
+}
+
+/// Whether `error` says the filesystem does not support locks at all
+pub(crate) fn locks_unsupported(error: &std::io::Error) -> bool {
+    #[cfg(unix)]
+    if error.raw_os_error() == Some(libc::ENOLCK) {
+        return true;
+    }
+    error.kind() == std::io::ErrorKind::Unsupported
+}
+
+/// Probe whether lock files can be created exclusively inside `directory`
+fn supports_exclusive_create(directory: &Path) -> bool {
+    let probe = probe_path(directory);
+    let created = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .is_ok();
+    let _ = std::fs::remove_file(&probe);
+    created
+}
+
+/// A probe file in `directory` no other process or thread probes with
+fn probe_path(directory: &Path) -> PathBuf {
+    directory.join(format!(
+        ".lock_probe.{}.{}",
+        std::process::id(),
+        uuid::Uuid::new_v4().simple()
+    ))
+}
+
+/// Filesystem type of the mount containing `path`, when it can be determined
+#[cfg(target_os = "linux")]
+pub fn filesystem_type(path: &Path) -> Option<String> {
+    let path = path.canonicalize().ok()?;
+    let mounts = std::fs::read_to_string("/proc/self/mounts").ok()?;
+
+    // The longest mount point that prefixes the path is the one it lives on
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let _device = fields.next()?;
+            let mount_point = fields.next()?.replace("\\040", " ");
+            let fs_type = fields.next()?;
+            path.starts_with(&mount_point)
+                .then(|| (mount_point.len(), fs_type.to_string()))
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, fs_type)| fs_type)
+}
+
+/// Filesystem type of the mount containing `path`, when it can be determined
+#[cfg(not(target_os = "linux"))]
+pub fn filesystem_type(_path: &Path) -> Option<String> {
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -306,6 +979,51 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_open_marker_detects_unclean_shutdown() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+
+        let (mut marker, unclean) = OpenMarker::acquire(temp_dir.path()).unwrap().unwrap();
+        assert!(!unclean);
+        marker.release();
+
+        let (_clean, unclean) = OpenMarker::acquire(temp_dir.path()).unwrap().unwrap();
+        assert!(!unclean);
+
+        // Simulate a crashed owner: a marker file nobody holds a lock on
+        std::fs::write(
+            temp_dir.path().join("open_markers").join("1-dead.marker"),
+            b"",
+        )
+        .unwrap();
+        let (_marker, unclean) = OpenMarker::acquire(temp_dir.path()).unwrap().unwrap();
+        assert!(unclean);
+    }
+
+    #[test]
+    fn test_setup_lock_is_exclusive_across_openers() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let directory = temp_dir.path().to_path_buf();
+
+        let lock = SetupLock::acquire(&directory, LockBackend::File).unwrap();
+        // Taken again by its holder without waiting
+        drop(SetupLock::acquire(&directory, LockBackend::File).unwrap());
+
+        let (acquired_tx, acquired_rx) = std::sync::mpsc::channel();
+        let other = std::thread::spawn(move || {
+            let _lock = SetupLock::acquire(&directory, LockBackend::File).unwrap();
+            acquired_tx.send(()).unwrap();
+        });
+        assert!(acquired_rx
+            .recv_timeout(std::time::Duration::from_millis(100))
+            .is_err());
+        drop(lock);
+        acquired_rx
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        other.join().unwrap();
+    }
+
     #[test]
     fn test_cache_stats() {
         let mut stats = CacheStats::new();
@@ -315,4 +1033,66 @@ mod tests {
         assert!((stats.hit_rate() - 0.8).abs() < f64::EPSILON);
         assert!((stats.miss_rate() - 0.2).abs() < f64::EPSILON);
     }
+
+    #[test]
+    fn test_validate_cache_config_conflicts() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let config = crate::cache::CacheConfig {
+            directory: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        assert!(validate_cache_config(&config).is_ok());
+
+        let conflicting = crate::cache::CacheConfig {
+            sync_writes: true,
+            batch_size: MAX_SYNC_BATCH_SIZE + 1,
+            ..config.clone()
+        };
+        match validate_cache_config(&conflicting) {
+            Err(CacheError::Config(issue)) => assert_eq!(issue.option, "batch_size"),
+            other => panic!("expected a batch_size config issue, got {:?}", other),
+        }
+
+        let zero_size = crate::cache::CacheConfig {
+            max_size: Some(0),
+            ..config.clone()
+        };
+        match validate_cache_config(&zero_size) {
+            Err(CacheError::Config(issue)) => assert_eq!(issue.option, "max_size"),
+            other => panic!("expected a max_size config issue, got {:?}", other),
+        }
+
+        let never_compacts = crate::cache::CacheConfig {
+            compaction_ratio: 0.0,
+            ..config.clone()
+        };
+        match validate_cache_config(&never_compacts) {
+            Err(CacheError::Config(issue)) => assert_eq!(issue.option, "compaction_ratio"),
+            other => panic!("expected a compaction_ratio config issue, got {:?}", other),
+        }
+
+        let too_deep = crate::cache::CacheConfig {
+            data_fanout: MAX_DATA_FANOUT + 1,
+            ..config
+        };
+        match validate_cache_config(&too_deep) {
+            Err(CacheError::Config(issue)) => assert_eq!(issue.option, "data_fanout"),
+            other => panic!("expected a data_fanout config issue, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_lock_probes_leave_no_files_behind() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        // Probes running side by side each use a file of their own
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    assert!(supports_file_locking(temp_dir.path()));
+                    assert!(supports_exclusive_create(temp_dir.path()));
+                });
+            }
+        });
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+    }
 }
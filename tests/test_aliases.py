@@ -0,0 +1,80 @@
+"""
+Tests for key aliases
+"""
+
+import pytest
+
+from diskcache_rs import Cache
+
+
+class TestAliases:
+    """Several lookup keys share one stored value"""
+
+    def test_alias_reads_primary_value(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        cache["sha256:abc"] = b"payload"
+        cache.alias("report.pdf", "sha256:abc")
+
+        assert cache["report.pdf"] == b"payload"
+        assert "report.pdf" in cache
+        assert cache.aliases("sha256:abc") == ["report.pdf"]
+        assert list(cache) == ["sha256:abc"], "aliases are not entries"
+
+    def test_alias_follows_rewrites(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        cache["primary"] = {"v": 1}
+        cache.alias("name", "primary")
+        cache["primary"] = {"v": 2}
+
+        assert cache["name"] == {"v": 2}
+
+    def test_alias_of_alias_points_at_entry(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        cache["primary"] = "value"
+        cache.alias("first", "primary")
+        cache.alias("second", "first")
+
+        assert sorted(cache.aliases("primary")) == ["first", "second"]
+        assert cache["second"] == "value"
+
+    def test_missing_primary_raises_key_error(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        with pytest.raises(KeyError):
+            cache.alias("name", "missing")
+
+    def test_deleting_primary_removes_aliases(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        cache["primary"] = "value"
+        cache.alias("name", "primary")
+
+        del cache["primary"]
+        assert "name" not in cache
+        assert cache.get("name") is None
+
+    def test_deleting_alias_keeps_primary(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        cache["primary"] = "value"
+        cache.alias("name", "primary")
+        cache.alias("other", "primary")
+
+        del cache["name"]
+        assert cache["primary"] == "value"
+        assert cache.unalias("other")
+        assert not cache.unalias("other")
+        assert cache.aliases("primary") == []
+
+    def test_aliases_are_shared_between_handles(self, temp_cache_dir):
+        Cache(temp_cache_dir)["primary"] = "value"
+        Cache(temp_cache_dir).alias("name", "primary")
+
+        assert Cache(temp_cache_dir)["name"] == "value"
+
+    @pytest.mark.parametrize("backend", ["sqlite", "redb"])
+    def test_other_backends(self, temp_cache_dir, backend):
+        cache = Cache(temp_cache_dir, backend=backend)
+        cache["primary"] = "value"
+        cache.alias("name", "primary")
+
+        assert cache["name"] == "value"
+        del cache["primary"]
+        assert "name" not in cache
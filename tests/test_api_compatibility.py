@@ -110,10 +110,9 @@ class TestCacheAPICompatibility:
             cache["key2"] = "value2"
 
             # Stats
-            stats = cache.stats()
-            assert isinstance(stats, dict)
-            assert "hits" in stats
-            assert "misses" in stats
+            hits, misses = cache.stats()
+            assert isinstance(hits, int)
+            assert isinstance(misses, int)
 
             # Volume
             volume = cache.volume()
@@ -257,13 +256,13 @@ class TestCacheAPICompatibility:
             for key in [4, 1, 3, 0, 2]:
                 cache[str(key)] = key
 
-            # Forward iteration
+            # Forward iteration follows store order
             keys = list(cache.iterkeys())
-            assert keys == ["0", "1", "2", "3", "4"]
+            assert keys == ["4", "1", "3", "0", "2"]
 
             # Reverse iteration
             keys_reversed = list(cache.iterkeys(reverse=True))
-            assert keys_reversed == ["4", "3", "2", "1", "0"]
+            assert keys_reversed == ["2", "0", "3", "1", "4"]
 
             cache.close()
 
@@ -419,10 +418,9 @@ class TestFanoutCacheAPICompatibility:
             cache["key2"] = "value2"
 
             # Stats
-            stats = cache.stats()
-            assert isinstance(stats, dict)
-            assert "hits" in stats
-            assert "misses" in stats
+            hits, misses = cache.stats()
+            assert isinstance(hits, int)
+            assert isinstance(misses, int)
 
             # Volume
             volume = cache.volume()
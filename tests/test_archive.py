@@ -0,0 +1,55 @@
+"""
+Tests for export() and import_archive(), which move a cache through one file
+"""
+
+import time
+
+import pytest
+from diskcache_rs import Cache
+
+
+class TestArchive:
+    """An exported archive restores entries, tags and TTLs elsewhere"""
+
+    def test_round_trip(self, temp_cache_dir, tmp_path):
+        cache = Cache(temp_cache_dir)
+        cache["small"] = {"answer": 42}
+        cache["large"] = b"x" * 200_000
+        cache.set("tagged", "value", tag="group", expire=3600)
+        cache.set("gone", "soon", expire=1)
+
+        archive = tmp_path / "cache.archive"
+        time.sleep(1.1)
+        assert cache.export(archive) == 3
+
+        restored = Cache.import_archive(archive, tmp_path / "restored")
+        assert restored["small"] == {"answer": 42}
+        assert restored["large"] == b"x" * 200_000
+        assert "gone" not in restored
+        metadata = restored.get_metadata("tagged")
+        assert metadata["tag"] == "group"
+        assert 3500 < metadata["expire_time"] - time.time() <= 3600
+        assert restored.evict("group") == 1
+        assert "tagged" not in restored
+
+    def test_import_into_another_backend(self, temp_cache_dir, tmp_path):
+        cache = Cache(temp_cache_dir)
+        for i in range(1_000):
+            cache[f"key{i}"] = i
+        archive = tmp_path / "cache.archive"
+        cache.export(archive)
+
+        restored = Cache.import_archive(archive, tmp_path / "restored", backend="sqlite")
+        assert len(restored) == 1_000
+        assert restored["key999"] == 999
+
+    def test_damaged_archive_is_refused(self, temp_cache_dir, tmp_path):
+        cache = Cache(temp_cache_dir)
+        cache["a"] = "value"
+        archive = tmp_path / "cache.archive"
+        cache.export(archive)
+
+        data = archive.read_bytes()
+        archive.write_bytes(data[:-2])
+        with pytest.raises(Exception, match="archive"):
+            Cache.import_archive(archive, tmp_path / "restored")
@@ -0,0 +1,43 @@
+"""
+Tests for migrate_backend(), which moves a cache to another backend in place
+"""
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+
+class TestBackendMigration:
+    """Entries survive a switch of backend without a clear()"""
+
+    def test_entries_move_to_the_new_backend(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        cache["small"] = b"bytes"
+        cache["large"] = b"x" * 100_000
+        cache["object"] = {"nested": [1, 2, 3]}
+        cache.set("tagged", "value", tag="group")
+
+        assert cache.migrate_backend("redb") == 4
+        assert cache["small"] == b"bytes"
+        assert cache["large"] == b"x" * 100_000
+        assert cache["object"] == {"nested": [1, 2, 3]}
+        cache["new"] = "after"
+        cache.close()
+
+        with pytest.raises(CacheConfigError):
+            Cache(temp_cache_dir)
+        cache = Cache(temp_cache_dir, backend="redb")
+        assert cache["new"] == "after"
+        assert cache["tagged"] == "value"
+        cache.close()
+
+    def test_round_trip_through_sqlite(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        cache["key"] = b"value"
+        cache["text"] = "text"
+
+        assert cache.migrate_backend("sqlite") == 2
+        assert cache.migrate_backend("optimized") == 2
+        assert cache["key"] == b"value"
+        assert cache["text"] == "text"
+        assert cache.migrate_backend("optimized") == 0
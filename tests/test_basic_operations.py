@@ -78,14 +78,14 @@ class TestBasicOperations:
 
     def test_stats(self, cache):
         """Test cache statistics"""
-        stats = cache.stats()
-        assert isinstance(stats, dict)
+        # Off until enabled, like python-diskcache
+        assert cache.stats(enable=True) == (0, 0)
+        cache.set("key", "value")
+        cache.get("key")
+        cache.get("missing")
 
-        # Check for expected keys
-        expected_keys = ["hits", "misses"]
-        for key in expected_keys:
-            assert key in stats
-            assert isinstance(stats[key], int)
+        # (hits, misses) like python-diskcache
+        assert cache.stats() == (1, 1)
 
     def test_cache_size_limit(self, temp_cache_dir):
         """Test cache respects size limits"""
@@ -104,8 +104,7 @@ class TestBasicOperations:
 
         # The cache should handle this gracefully
         # (exact behavior depends on implementation)
-        stats = small_cache.stats()
-        assert isinstance(stats, dict)
+        assert isinstance(small_cache.stats(), tuple)
 
     def test_cache_entry_limit(self, temp_cache_dir):
         """Test cache respects entry count limits"""
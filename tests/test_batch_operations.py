@@ -91,7 +91,7 @@ class TestBatchOperations:
                 }
             )
             assert stored == 3
-            assert cache.stats()["count"] == 3
+            assert cache._cache.stats()["entry_count"] == 3
             cache.close()
 
             reopened = Cache(cache_dir, disk_write_threshold=4096)
@@ -116,12 +116,12 @@ class TestBatchOperations:
             cache.vacuum()
 
             data_dir = Path(cache_dir) / "data"
-            assert len(list(data_dir.glob("*.dat"))) == 1
+            assert len(list(data_dir.rglob("*.dat"))) == 1
 
             cache.set("same-key", small_value)
             cache.close()
 
-            assert list(data_dir.glob("*.dat")) == []
+            assert list(data_dir.rglob("*.dat")) == []
 
             reopened = Cache(cache_dir)
             try:
@@ -137,13 +137,13 @@ class TestBatchOperations:
             cache.vacuum()
 
             data_dir = Path(cache_dir) / "data"
-            assert len(list(data_dir.glob("*.dat"))) == 1
+            assert len(list(data_dir.rglob("*.dat"))) == 1
 
             stored = cache.set_many({"same-key": b"b" * 128})
             assert stored == 1
             cache.close()
 
-            assert list(data_dir.glob("*.dat")) == []
+            assert list(data_dir.rglob("*.dat")) == []
 
             reopened = Cache(cache_dir)
             try:
@@ -156,17 +156,17 @@ class TestBatchOperations:
         cache = Cache(temp_cache_dir)
 
         cache.set("same-key", "first")
-        assert cache.stats()["count"] == 1
+        assert cache._cache.stats()["entry_count"] == 1
 
         cache.set("same-key", "second")
-        assert cache.stats()["count"] == 1
+        assert cache._cache.stats()["entry_count"] == 1
         assert len(cache) == 1
 
         stored = cache.set_many({"same-key": "third", "other-key": "value"})
         assert stored == 2
         assert cache.get("same-key") == "third"
         assert cache.get("other-key") == "value"
-        assert cache.stats()["count"] == 2
+        assert cache._cache.stats()["entry_count"] == 2
         assert len(cache) == 2
 
     def test_close_releases_background_writer_handles(self):
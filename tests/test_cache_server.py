@@ -0,0 +1,106 @@
+"""
+Tests for serving a cache to other processes over a Unix socket
+"""
+
+import os
+import signal
+import socket
+import struct
+import subprocess
+import sys
+
+import pytest
+
+from diskcache_rs import Cache, FanoutCache
+from diskcache_rs._diskcache_rs import PyCache
+
+pytestmark = pytest.mark.skipif(
+    sys.platform == "win32", reason="the cache server listens on a Unix socket"
+)
+
+
+def request(path, op, *fields):
+    """Send one request frame to the server at `path` and return its reply"""
+    body = bytes([op]) + b"".join(struct.pack("<Q", len(f)) + f for f in fields)
+    with socket.socket(socket.AF_UNIX, socket.SOCK_STREAM) as client:
+        client.connect(str(path))
+        client.sendall(struct.pack("<Q", len(body)) + body)
+        (length,) = struct.unpack("<Q", client.recv(8, socket.MSG_WAITALL))
+        return client.recv(length, socket.MSG_WAITALL)
+
+
+class TestCacheServer:
+    """PyCache.serve and python -m diskcache_rs.server"""
+
+    def test_serves_get_set_and_delete(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir)
+        with cache.serve() as server:
+            assert str(server.socket) == os.path.join(temp_cache_dir, "server.sock")
+            assert request(server.socket, 1, b"key") == b"\x00\x00"
+
+            no_expiry = struct.pack("<Q", 0)
+            assert request(server.socket, 2, b"key", b"value", no_expiry) == b"\x00"
+            assert cache.get("key") == b"value"
+            assert request(server.socket, 1, b"key") == b"\x00\x01value"
+
+            assert request(server.socket, 3, b"key") == b"\x00\x01"
+            assert request(server.socket, 3, b"key") == b"\x00\x00"
+            # Errors come back with their message
+            assert request(server.socket, 1, b"").startswith(b"\x01")
+
+            with pytest.raises(Exception):
+                cache.serve()
+        assert not os.path.exists(server.socket)
+
+    def test_entry_point_serves_until_terminated(self, temp_cache_dir):
+        server = subprocess.Popen(
+            [sys.executable, "-m", "diskcache_rs.server", temp_cache_dir],
+            env={**os.environ, "PYTHONPATH": os.pathsep.join(sys.path)},
+            stdout=subprocess.PIPE,
+            text=True,
+        )
+        try:
+            assert "server.sock" in server.stdout.readline()
+            path = os.path.join(temp_cache_dir, "server.sock")
+            no_expiry = struct.pack("<Q", 0)
+            assert request(path, 2, b"key", b"value", no_expiry) == b"\x00"
+        finally:
+            server.send_signal(signal.SIGTERM)
+            assert server.wait(timeout=60) == 0
+
+        assert not os.path.exists(path)
+        assert PyCache(temp_cache_dir).get("key") == b"value"
+
+
+class TestServerClients:
+    """Caches opened with a unix:// URL attach to a running server"""
+
+    def test_pycache_reads_and_writes_through_the_server(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir)
+        with cache.serve() as server:
+            client = PyCache(f"unix://{server.socket}")
+            client.set("key", b"value", tags=["tag"])
+            assert cache.get("key") == b"value"
+            assert client.get("key") == b"value"
+            assert client.keys() == ["key"]
+            assert client.delete("key")
+            assert cache.get("key") is None
+
+    def test_cache_and_fanout_cache_accept_the_url(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir)
+        with cache.serve() as server:
+            url = f"unix://{server.socket}"
+            client = Cache(url)
+            client["key"] = {"nested": [1, 2]}
+            assert client["key"] == {"nested": [1, 2]}
+            assert client.directory is None
+
+            fanout = FanoutCache(url, shards=4)
+            assert fanout.shards == 1
+            fanout.set("other", "value")
+            assert client.get("other") == "value"
+            assert len(fanout) == 2
+
+    def test_url_without_a_server_fails(self, temp_cache_dir):
+        with pytest.raises(Exception, match="no cache server"):
+            PyCache(f"unix://{os.path.join(temp_cache_dir, 'missing.sock')}")
@@ -0,0 +1,99 @@
+"""
+Tests for versioned writes through get_with_version() and cas()
+"""
+
+import os
+import subprocess
+import sys
+import threading
+
+import pytest
+
+from diskcache_rs import Cache, FanoutCache
+from diskcache_rs._diskcache_rs import PyCache
+
+INCREMENT = (
+    "import sys\n"
+    "from diskcache_rs import Cache\n"
+    "cache = Cache(sys.argv[1])\n"
+    "for _ in range(20):\n"
+    "    while True:\n"
+    "        count, version = cache.get_with_version('count', 0)\n"
+    "        if cache.cas('count', version, count + 1):\n"
+    "            break\n"
+)
+
+
+class TestCas:
+    """cas stores a value only while the key keeps the version it was read with"""
+
+    def test_cas_compares_versions(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        assert cache.get_with_version("key") == (None, None)
+        assert cache.cas("key", None, {"n": 1})
+        assert not cache.cas("key", None, {"n": 2})
+
+        value, version = cache.get_with_version("key")
+        assert value == {"n": 1}
+        assert version == cache.get_metadata("key")["version"]
+        assert cache.cas("key", version, {"n": 2}, tag="counted")
+        assert not cache.cas("key", version, {"n": 3})
+        assert cache["key"] == {"n": 2}
+
+        # Plain writes change the version too
+        _, version = cache.get_with_version("key")
+        cache["key"] = "rewritten"
+        assert not cache.cas("key", version, "lost")
+        assert cache["key"] == "rewritten"
+
+    def test_threads_lose_no_increments(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+
+        def increment():
+            for _ in range(50):
+                while True:
+                    count, version = cache.get_with_version("count", 0)
+                    if cache.cas("count", version, count + 1):
+                        break
+
+        threads = [threading.Thread(target=increment) for _ in range(4)]
+        for thread in threads:
+            thread.start()
+        for thread in threads:
+            thread.join()
+        assert cache["count"] == 200
+
+    def test_processes_lose_no_increments(self, temp_cache_dir):
+        workers = [
+            subprocess.Popen(
+                [sys.executable, "-c", INCREMENT, temp_cache_dir],
+                env={**os.environ, "PYTHONPATH": os.pathsep.join(sys.path)},
+            )
+            for _ in range(3)
+        ]
+        assert [worker.wait(timeout=120) for worker in workers] == [0, 0, 0]
+        assert Cache(temp_cache_dir)["count"] == 60
+
+    def test_fanout_cache_uses_the_key_shard(self, temp_cache_dir):
+        cache = FanoutCache(temp_cache_dir, shards=4)
+        for n in range(8):
+            assert cache.cas(f"key{n}", None, n)
+            value, version = cache.get_with_version(f"key{n}")
+            assert value == n
+            assert cache.cas(f"key{n}", version, n + 1)
+        assert [cache[f"key{n}"] for n in range(8)] == list(range(1, 9))
+
+    @pytest.mark.skipif(
+        sys.platform == "win32", reason="the cache server listens on a Unix socket"
+    )
+    def test_server_clients_compare_on_the_server(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir)
+        with cache.serve() as server:
+            url = f"unix://{server.socket}"
+            first, second = Cache(url), Cache(url)
+            assert first.cas("key", None, "first")
+            assert not second.cas("key", None, "second")
+            _, version = second.get_with_version("key")
+            assert second.cas("key", version, "second")
+            assert not first.cas("key", version, "stale")
+            assert cache.get_with_version("key")[1] == first.get_with_version("key")[1]
@@ -0,0 +1,48 @@
+"""
+Tests for detecting stored values that were damaged on disk
+"""
+
+import os
+
+import pytest
+
+from diskcache_rs import Cache
+from diskcache_rs._diskcache_rs import PyCache
+
+
+def flip_last_byte(path):
+    with open(path, "r+b") as f:
+        f.seek(-1, os.SEEK_END)
+        byte = f.read(1)
+        f.seek(-1, os.SEEK_END)
+        f.write(bytes([byte[0] ^ 0xFF]))
+
+
+class TestChecksums:
+    """Values are checked against their checksum when read from disk"""
+
+    def test_corrupted_value_reads_as_missing(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, disk_write_threshold=0)
+        cache["key"] = os.urandom(4096)
+        path, _ = cache._cache.data_file("key")
+        cache.close()
+
+        flip_last_byte(path)
+
+        cache = Cache(temp_cache_dir, disk_write_threshold=0)
+        assert cache.get("key") is None
+        assert "key" not in cache
+        assert not os.path.exists(path)
+
+    def test_corruption_is_a_distinct_error(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir, disk_write_threshold=0)
+        cache.set("key", os.urandom(4096))
+        path, _ = cache.data_file("key")
+        cache.close()
+
+        flip_last_byte(path)
+
+        cache = PyCache(temp_cache_dir, disk_write_threshold=0)
+        with pytest.raises(Exception, match="checksum"):
+            cache.get("key")
+        assert cache.get("key") is None
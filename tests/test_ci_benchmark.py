@@ -119,7 +119,7 @@ class TestCIBenchmarks:
 
         # Stats
         stats = cache.stats()
-        assert isinstance(stats, dict)
+        assert isinstance(stats, tuple)
 
         # Volume
         volume = cache.volume()
@@ -0,0 +1,63 @@
+"""
+Tests for removing data files in parallel on clear()
+"""
+
+import threading
+from pathlib import Path
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+
+def data_files(directory):
+    return list(Path(directory, "data").rglob("*.dat"))
+
+
+class TestClearProgress:
+    """clear() removes data files across a worker pool and reports progress"""
+
+    def test_progress_reaches_total(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, disk_write_threshold=0, unlink_workers=4)
+        for i in range(500):
+            cache[f"k{i}"] = b"x" * 100
+        cache.vacuum()
+        assert len(data_files(temp_cache_dir)) == 500
+
+        reports = []
+        lock = threading.Lock()
+
+        def progress(done, total):
+            with lock:
+                reports.append((done, total))
+
+        assert cache.clear(progress=progress) == 500
+        assert reports[-1] == (500, 500)
+        assert all(done <= total for done, total in reports)
+        assert data_files(temp_cache_dir) == []
+        assert len(cache) == 0
+
+    def test_clear_without_progress(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, disk_write_threshold=0, unlink_rate=100_000)
+        for i in range(20):
+            cache[f"k{i}"] = b"y" * 100
+
+        assert cache.clear() == 20
+        assert data_files(temp_cache_dir) == []
+
+    def test_failing_callback_does_not_stop_clear(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, disk_write_threshold=0)
+        for i in range(10):
+            cache[f"k{i}"] = b"z" * 100
+
+        def progress(done, total):
+            raise RuntimeError("progress display went away")
+
+        cache.clear(progress=progress)
+        assert data_files(temp_cache_dir) == []
+
+    def test_invalid_worker_count(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(temp_cache_dir, unlink_workers=0)
+
+        assert excinfo.value.option == "unlink_workers"
@@ -0,0 +1,149 @@
+"""
+Tests for structured configuration errors raised when a cache is opened
+"""
+
+import os
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+from diskcache_rs._diskcache_rs import PyCache
+
+
+class TestConfigErrors:
+    """Invalid and conflicting options raise CacheConfigError"""
+
+    def test_zero_max_size(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            PyCache(temp_cache_dir, max_size=0)
+
+        assert excinfo.value.option == "max_size"
+        assert "None" in excinfo.value.suggestion
+
+    def test_sync_writes_with_huge_batch(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            PyCache(temp_cache_dir, sync_writes=True, batch_size=100_000)
+
+        assert excinfo.value.option == "batch_size"
+        assert "sync_writes" in excinfo.value.suggestion
+
+    def test_zero_batch_size(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            PyCache(temp_cache_dir, batch_size=0)
+
+        assert excinfo.value.option == "batch_size"
+
+    def test_write_queue_options(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            PyCache(temp_cache_dir, write_queue_capacity=0)
+        assert excinfo.value.option == "write_queue_capacity"
+
+        with pytest.raises(CacheConfigError) as excinfo:
+            PyCache(temp_cache_dir, queue_full="drop")
+        assert excinfo.value.option == "queue_full"
+
+    def test_compression_options(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            PyCache(temp_cache_dir, compression="brotli")
+        assert excinfo.value.option == "compression"
+
+        for options in (
+            {"compression": "zstd", "compression_level": 99},
+            {"compression_level": 5},
+        ):
+            with pytest.raises(CacheConfigError) as excinfo:
+                PyCache(temp_cache_dir, **options)
+            assert excinfo.value.option == "compression_level"
+
+        with pytest.raises(CacheConfigError) as excinfo:
+            PyCache(temp_cache_dir, backend="redb", compression="zstd")
+        assert excinfo.value.option == "compression"
+
+    def test_switching_codecs_keeps_values_readable(self, temp_cache_dir):
+        value = b"frame " * 20_000
+        for codec in ("zstd", "snappy", "none", "lz4"):
+            cache = PyCache(temp_cache_dir, compression=codec)
+            cache.set(codec, value)
+            cache.close()
+
+        cache = PyCache(temp_cache_dir, compression="zstd", compression_level=19)
+        for codec in ("zstd", "snappy", "none", "lz4"):
+            assert cache.get(codec) == value
+
+    def test_zstd_dictionary_options(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            PyCache(temp_cache_dir, zstd_dictionary=True, dictionary_threshold=0)
+        assert excinfo.value.option == "dictionary_threshold"
+
+        with pytest.raises(CacheConfigError) as excinfo:
+            PyCache(temp_cache_dir, backend="redb", zstd_dictionary=True)
+        assert excinfo.value.option == "zstd_dictionary"
+
+    def test_small_values_compressed_with_a_dictionary_read_back(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, zstd_dictionary=True)
+        for i in range(1100):
+            cache[f"shot-{i}"] = {"shot": f"sq010_sh{i:04d}", "status": "approved"}
+        cache.close()
+
+        assert os.path.exists(os.path.join(temp_cache_dir, "dictionaries", "1.zdict"))
+        cache = Cache(temp_cache_dir)
+        for i in (0, 1099):
+            assert cache[f"shot-{i}"] == {"shot": f"sq010_sh{i:04d}", "status": "approved"}
+
+    def test_lock_backend_options(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            PyCache(temp_cache_dir, lock_backend="flock")
+        assert excinfo.value.option == "lock_backend"
+
+        # Named mutexes fall back to file locks off Windows
+        cache = Cache(temp_cache_dir, lock_backend="named_mutex")
+        cache["key"] = b"value"
+        assert cache.incr("counter") == 1
+        assert cache["key"] == b"value"
+
+    def test_use_file_locking_uses_lock_files(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, use_file_locking=True)
+        with cache.lock_key("key"):
+            cache["key"] = b"value"
+        assert cache.incr("counter") == 1
+        assert cache["key"] == b"value"
+
+    def test_lock_granularity_options(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            PyCache(temp_cache_dir, lock_granularity="row")
+        assert excinfo.value.option == "lock_granularity"
+
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(temp_cache_dir, backend="redb", lock_granularity="entry")
+        assert excinfo.value.option == "lock_granularity"
+
+        for granularity in ("entry", "cache"):
+            directory = os.path.join(temp_cache_dir, granularity)
+            cache = Cache(
+                directory,
+                use_file_locking=True,
+                lock_granularity=granularity,
+                disk_write_threshold=64,
+            )
+            cache["key"] = b"value" * 100
+            assert cache.incr("counter") == 1
+            assert cache["key"] == b"value" * 100
+            cache.close()
+
+    def test_is_value_error(self, temp_cache_dir):
+        with pytest.raises(ValueError):
+            Cache(temp_cache_dir, max_entries=0)
+
+    def test_valid_combination(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir, sync_writes=True, batch_size=10, use_mmap=False)
+        cache.set("key", b"value")
+        assert cache.get("key") == b"value"
+
+    def test_directory_of_another_backend(self, temp_cache_dir):
+        PyCache(temp_cache_dir, backend="redb").set("key", b"value")
+        with pytest.raises(CacheConfigError) as excinfo:
+            PyCache(temp_cache_dir)
+
+        assert excinfo.value.option == "backend"
+        assert 'backend="redb"' in excinfo.value.suggestion
+        assert PyCache(temp_cache_dir, backend="redb").get("key") == b"value"
@@ -0,0 +1,64 @@
+"""
+Tests for spreading data files over hash-prefix subdirectories
+"""
+
+import os
+from pathlib import Path
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+
+def data_files(directory):
+    data_dir = Path(directory, "data")
+    return sorted(path.relative_to(data_dir) for path in data_dir.rglob("*.dat"))
+
+
+class TestDataFanout:
+    """Data files live under data/<2 hex>/<2 hex>/ unless data_fanout says otherwise"""
+
+    def test_files_are_spread_over_two_levels(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, disk_write_threshold=0)
+        for i in range(20):
+            cache[f"k{i}"] = os.urandom(256)
+        cache.vacuum()
+
+        files = data_files(temp_cache_dir)
+        assert len(files) == 20
+        for path in files:
+            assert path.parts == (path.name[:2], path.name[2:4], path.name)
+        assert cache["k7"] is not None
+
+    def test_flat_layout(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, disk_write_threshold=0, data_fanout=0)
+        cache["key"] = b"value" * 100
+        cache.vacuum()
+
+        assert [len(path.parts) for path in data_files(temp_cache_dir)] == [1]
+
+    def test_flat_cache_is_migrated_on_open(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, disk_write_threshold=0, data_fanout=0)
+        values = {f"k{i}": os.urandom(512) for i in range(50)}
+        for key, value in values.items():
+            cache[key] = value
+        cache.close()
+
+        cache = Cache(temp_cache_dir, disk_write_threshold=0)
+        assert all(len(path.parts) == 3 for path in data_files(temp_cache_dir))
+        assert len(data_files(temp_cache_dir)) == 50
+        for key, value in values.items():
+            assert cache[key] == value
+        cache.close()
+
+        # Reopening with the same layout leaves everything where it is
+        before = data_files(temp_cache_dir)
+        cache = Cache(temp_cache_dir, disk_write_threshold=0)
+        assert data_files(temp_cache_dir) == before
+        assert cache["k0"] == values["k0"]
+
+    def test_invalid_fanout(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(temp_cache_dir, data_fanout=9)
+
+        assert excinfo.value.option == "data_fanout"
@@ -0,0 +1,58 @@
+"""
+Tests for forwarding writes to a designated writer process
+"""
+
+import os
+import subprocess
+import sys
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+pytestmark = pytest.mark.skipif(
+    sys.platform == "win32", reason="the designated writer listens on a Unix socket"
+)
+
+
+def run_follower(directory, script):
+    """Run `script` in another process with `cache` open on `directory`"""
+    prelude = (
+        "import sys\n"
+        "from diskcache_rs import Cache\n"
+        "cache = Cache(sys.argv[1], designated_writer=True)\n"
+    )
+    subprocess.run(
+        [sys.executable, "-c", prelude + script, directory],
+        env={**os.environ, "PYTHONPATH": os.pathsep.join(sys.path)},
+        check=True,
+    )
+
+
+class TestDesignatedWriter:
+    """designated_writer routes every process's writes through one writer"""
+
+    def test_follower_writes_reach_the_writer(self, temp_cache_dir):
+        writer = Cache(temp_cache_dir, designated_writer=True)
+        writer["own"] = b"writer"
+        run_follower(
+            temp_cache_dir,
+            "cache['forwarded'] = b'follower'\n"
+            "cache.set_many({'a': 1, 'b': 2})\n"
+            "del cache['own']\n"
+            "assert cache['forwarded'] == b'follower'\n",
+        )
+        assert writer["forwarded"] == b"follower"
+        assert writer["a"] == 1 and writer["b"] == 2
+        assert "own" not in writer
+
+    def test_follower_takes_over_when_the_writer_closes(self, temp_cache_dir):
+        writer = Cache(temp_cache_dir, designated_writer=True)
+        writer.close()
+        run_follower(temp_cache_dir, "cache['key'] = b'value'\n")
+        assert Cache(temp_cache_dir)["key"] == b"value"
+
+    def test_rejects_memory_backend(self):
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(backend="memory", designated_writer=True)
+        assert excinfo.value.option == "designated_writer"
@@ -0,0 +1,33 @@
+"""
+Tests for direct I/O writes of large values
+"""
+
+import os
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+
+class TestDirectIO:
+    """direct_io_threshold writes large data files past the page cache"""
+
+    def test_large_values_round_trip(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, direct_io_threshold=64 * 1024)
+        values = {
+            "small": b"x" * 100,
+            "odd": os.urandom(100_003),
+            "large": os.urandom(3 * 1024 * 1024 + 7),
+        }
+        for key, value in values.items():
+            cache[key] = value
+        cache.close()
+
+        cache = Cache(temp_cache_dir)
+        for key, value in values.items():
+            assert cache[key] == value
+
+    def test_optimized_backend_only(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(temp_cache_dir, backend="sqlite", direct_io_threshold=1024)
+        assert excinfo.value.option == "direct_io_threshold"
@@ -0,0 +1,73 @@
+"""
+Tests for pluggable disk codecs on the Rust PyCache.
+
+PyCache accepts ``disk="raw"`` (default), ``disk="json"`` or any object with
+``store``/``fetch`` (and optionally ``put``/``get``) methods, mirroring
+python-diskcache's ``Disk`` extension point.
+"""
+
+import json
+import tempfile
+import zlib
+
+import pytest
+
+from diskcache_rs._diskcache_rs import PyCache
+
+
+class ZlibDisk:
+    """Custom codec compressing values and namespacing keys."""
+
+    def put(self, key):
+        return "z:" + key
+
+    def get(self, key):
+        return key[len("z:") :]
+
+    def store(self, value):
+        return zlib.compress(value)
+
+    def fetch(self, data):
+        return zlib.decompress(data)
+
+
+@pytest.fixture
+def temp_cache_dir():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        yield temp_dir
+
+
+def test_json_disk_round_trip(temp_cache_dir):
+    cache = PyCache(temp_cache_dir, disk="json")
+    cache.set("doc", json.dumps({"a": [1, 2, 3]}).encode())
+    assert json.loads(cache.get("doc")) == {"a": [1, 2, 3]}
+    cache.close()
+
+
+def test_json_disk_rejects_non_json(temp_cache_dir):
+    cache = PyCache(temp_cache_dir, disk="json")
+    with pytest.raises(Exception, match="not valid JSON"):
+        cache.set("doc", b"\x00\x01")
+    cache.close()
+
+
+def test_custom_disk_codec(temp_cache_dir):
+    cache = PyCache(temp_cache_dir, disk=ZlibDisk())
+    payload = b"x" * 4096
+    cache.set("blob", payload)
+
+    assert cache.get("blob") == payload
+    assert cache.exists("blob")
+    assert cache.keys() == ["blob"]
+    assert cache.delete("blob")
+    cache.close()
+
+
+def test_custom_disk_requires_store_and_fetch(temp_cache_dir):
+    with pytest.raises(TypeError, match="fetch"):
+        PyCache(temp_cache_dir, disk=type("Incomplete", (), {"store": lambda s, v: v})())
+
+
+def test_unknown_disk_name(temp_cache_dir):
+    with pytest.raises(ValueError, match="Unknown disk codec"):
+        PyCache(temp_cache_dir, disk="yaml")
@@ -0,0 +1,97 @@
+"""
+Tests for python-diskcache constructor settings on the Rust Cache and FanoutCache
+"""
+
+import pytest
+
+from diskcache_rs import CacheConfigError
+from diskcache_rs._diskcache_rs import Cache, FanoutCache
+
+
+class TestDiskcacheSettings:
+    """Settings spelled the way diskcache.Cache(**settings) accepts them"""
+
+    def test_accepts_diskcache_kwargs(self, temp_cache_dir):
+        cache = Cache(
+            temp_cache_dir,
+            timeout=60,
+            size_limit=2**30,
+            cull_limit=10,
+            eviction_policy="least-recently-used",
+            statistics=1,
+            disk_min_file_size=2**15,
+        )
+        cache.set("key", b"value")
+        assert cache.get("key") == b"value"
+        cache.close()
+
+    def test_statistics_off_by_default(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        cache.set("key", b"value")
+        cache.get("key")
+        cache.get("missing")
+        assert cache.stats(enable=False) == (0, 0)
+        cache.close()
+
+    def test_fanout_statistics_off_by_default(self, temp_cache_dir):
+        cache = FanoutCache(temp_cache_dir, shards=2)
+        cache.set("key", b"value")
+        cache.get("key")
+        cache.get("missing")
+        assert cache.stats(enable=False) == (0, 0)
+
+    def test_statistics_on_by_setting(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, statistics=1)
+        cache.set("key", b"value")
+        cache.get("key")
+        cache.get("missing")
+        assert cache.stats(enable=False) == (1, 1)
+        cache.close()
+
+    def test_statistics_off_by_setting(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, statistics=0)
+        cache.set("key", b"value")
+        cache.get("key")
+        cache.get("missing")
+        assert cache.stats(enable=False) == (0, 0)
+        cache.close()
+
+    def test_cull_limit_zero_disables_eviction(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, count_limit=5, cull_limit=0)
+        for i in range(20):
+            cache.set(f"key{i}", b"value")
+        assert len(cache) == 20
+        cache.close()
+
+    def test_eviction_policy_names(self, temp_cache_dir):
+        for policy in (
+            "none",
+            "least-recently-stored",
+            "least-recently-used",
+            "least-frequently-used",
+            "window-tinylfu",
+            "gdsf",
+        ):
+            Cache(f"{temp_cache_dir}/{policy}", eviction_policy=policy).close()
+
+    def test_unknown_eviction_policy(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(temp_cache_dir, eviction_policy="most-recently-used")
+        assert excinfo.value.option == "eviction_policy"
+
+    def test_directory_is_optional(self):
+        cache = Cache()
+        cache.set("key", b"value")
+        assert cache.get("key") == b"value"
+        cache.close()
+
+    def test_fanout_accepts_diskcache_signature(self, temp_cache_dir):
+        cache = FanoutCache(
+            temp_cache_dir, shards=4, timeout=0.010, eviction_policy="least-recently-used"
+        )
+        cache.set("key", b"value")
+        assert cache.get("key") == b"value"
+
+        anonymous = FanoutCache(shards=2)
+        anonymous.set("key", b"value")
+        assert anonymous.get("key") == b"value"
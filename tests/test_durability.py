@@ -0,0 +1,33 @@
+"""
+Tests for the durability option
+"""
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+
+class TestDurability:
+    """durability trades write latency for crash safety"""
+
+    @pytest.mark.parametrize("level", ["relaxed", "flush", "fsync", "fsync+dirsync"])
+    def test_every_level_round_trips(self, temp_cache_dir, level):
+        cache = Cache(temp_cache_dir, durability=level, disk_write_threshold=0)
+        cache["small"] = "value"
+        cache["large"] = b"x" * 100_000
+        assert cache["small"] == "value"
+        assert cache["large"] == b"x" * 100_000
+        cache.close()
+
+        cache = Cache(temp_cache_dir, durability=level)
+        assert cache["large"] == b"x" * 100_000
+
+    def test_unknown_level(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(temp_cache_dir, durability="paranoid")
+        assert excinfo.value.option == "durability"
+
+    def test_memory_backend_has_nothing_to_sync(self):
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(backend="memory", durability="fsync")
+        assert excinfo.value.option == "durability"
@@ -0,0 +1,81 @@
+"""
+Tests for encrypting values at rest
+"""
+
+import os
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+PLAINTEXT = b"studio-confidential-" * 100
+
+
+def stored_bytes(directory):
+    """Everything the cache wrote to disk, concatenated"""
+    content = b""
+    for root, _, files in os.walk(directory):
+        for name in files:
+            with open(os.path.join(root, name), "rb") as f:
+                content += f.read()
+    return content
+
+
+class TestEncryption:
+    """encryption_key encrypts every value before it reaches the disk"""
+
+    @pytest.mark.parametrize("secret", [b"secret", "secret"])
+    def test_round_trips_values(self, temp_cache_dir, secret):
+        cache = Cache(temp_cache_dir, encryption_key=secret, disk_write_threshold=1024)
+        cache["small"] = {"answer": 42}
+        cache["large"] = PLAINTEXT
+        cache.close()
+
+        cache = Cache(temp_cache_dir, encryption_key=b"secret", disk_write_threshold=1024)
+        assert cache["small"] == {"answer": 42}
+        assert cache["large"] == PLAINTEXT
+        with cache.read("large") as reader:
+            assert reader.read() == PLAINTEXT
+
+    def test_values_never_reach_the_disk_in_the_clear(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, encryption_key=b"secret", disk_write_threshold=1024)
+        cache["small"] = b"studio-confidential"
+        cache["large"] = PLAINTEXT
+        cache.close()
+
+        assert b"studio-confidential" not in stored_bytes(temp_cache_dir)
+
+    def test_key_can_come_from_a_callable(self, temp_cache_dir):
+        directories = []
+
+        def key_for(directory):
+            directories.append(directory)
+            return b"from-keyring"
+
+        cache = Cache(temp_cache_dir, encryption_key=key_for)
+        cache["key"] = "value"
+        cache.close()
+
+        assert directories == [str(temp_cache_dir)]
+        assert Cache(temp_cache_dir, encryption_key=b"from-keyring")["key"] == "value"
+
+    def test_only_the_creating_key_opens_the_cache(self, temp_cache_dir):
+        Cache(temp_cache_dir, encryption_key=b"secret")["key"] = "value"
+
+        for options in ({"encryption_key": b"guess"}, {}):
+            with pytest.raises(CacheConfigError) as excinfo:
+                Cache(temp_cache_dir, **options)
+            assert excinfo.value.option == "encryption_key"
+
+    def test_plain_caches_refuse_a_key(self, temp_cache_dir):
+        Cache(temp_cache_dir)["key"] = "value"
+
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(temp_cache_dir, encryption_key=b"secret")
+        assert excinfo.value.option == "encryption_key"
+
+    def test_empty_or_non_bytes_keys_are_refused(self, temp_cache_dir):
+        for key in (b"", lambda directory: 42):
+            with pytest.raises(CacheConfigError) as excinfo:
+                Cache(temp_cache_dir, encryption_key=key)
+            assert excinfo.value.option == "encryption_key"
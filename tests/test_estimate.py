@@ -0,0 +1,42 @@
+"""
+Tests for capacity planning: estimate()
+"""
+
+import pytest
+
+from diskcache_rs import Cache
+from diskcache_rs._diskcache_rs import PyCache
+
+DAY = 24 * 60 * 60
+
+
+class TestEstimate:
+    """estimate() projections"""
+
+    def test_entries_follow_ttl(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir)
+
+        assert cache.estimate(1000, 512, ttl=DAY)["entries"] == 1000
+        assert cache.estimate(1000, 512, ttl=DAY / 4)["entries"] == 250
+
+    def test_reports_footprint_and_limits(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+
+        estimate = cache.estimate(10_000, 1024, ttl=DAY)
+
+        assert estimate["disk_bytes"] >= 10_000 * 1024
+        assert estimate["memory_bytes"] > 0
+        assert estimate["recommended_max_entries"] >= estimate["entries"]
+        assert estimate["recommended_max_size"] >= 10_000 * 1024
+        assert estimate["fits_current_limits"] is True
+
+    def test_flags_workloads_over_configured_limits(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, max_entries=100)
+
+        assert cache.estimate(1000, 64, ttl=DAY)["fits_current_limits"] is False
+
+    def test_rejects_negative_ttl(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir)
+
+        with pytest.raises(ValueError):
+            cache.estimate(1000, 64, ttl=-1)
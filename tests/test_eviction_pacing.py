@@ -0,0 +1,43 @@
+"""
+Tests for leaving most of a write's evictions to a background thread
+with inline_evictions
+"""
+
+import time
+
+from diskcache_rs import Cache
+
+
+def wait_for_backlog(cache):
+    for _ in range(500):
+        if cache._cache.stats()["eviction_backlog"] == 0:
+            return
+        time.sleep(0.01)
+    raise AssertionError("the eviction backlog never drained")
+
+
+class TestInlineEvictions:
+    """Victims past inline_evictions are evicted in the background"""
+
+    def test_backlog_drains_to_the_limit(self, temp_cache_dir):
+        cache = Cache(
+            temp_cache_dir,
+            max_size=20_000,
+            eviction_policy="least-recently-used",
+            inline_evictions=1,
+        )
+        for n in range(100):
+            cache.set(f"key-{n}", b"x" * 100)
+        cache.set("large", b"x" * 10_000)
+        wait_for_backlog(cache)
+        stats = cache._cache.stats()
+        assert stats["eviction_backlog_bytes"] == 0
+        assert stats["evictions"] > 0
+        assert "large" in cache
+        assert "key-99" in cache
+
+    def test_no_backlog_by_default(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, max_size=5_000)
+        for n in range(100):
+            cache.set(f"key-{n}", b"x" * 100)
+        assert cache._cache.stats()["eviction_backlog"] == 0
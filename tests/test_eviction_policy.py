@@ -0,0 +1,133 @@
+"""
+Tests for choosing which entries eviction removes with eviction_policy
+"""
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+
+def scan(cache, hot, misses):
+    """Read `hot` keys a few times, then store `misses` keys read by nobody"""
+    for key in hot:
+        cache[key] = key
+    for _ in range(5):
+        for key in hot:
+            assert cache[key] == key
+    for n in range(misses):
+        cache[f"once-{n}"] = n
+
+
+class TestWindowTinyLfu:
+    """window-tinylfu keeps entries read often over entries stored once"""
+
+    def test_hot_entries_survive_a_scan(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, max_entries=100, eviction_policy="window-tinylfu")
+        hot = [f"hot-{n}" for n in range(50)]
+        scan(cache, hot, 1000)
+        assert all(key in cache for key in hot)
+        assert len(cache) <= 100
+
+    def test_least_recently_stored_loses_them(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, max_entries=100)
+        hot = [f"hot-{n}" for n in range(50)]
+        scan(cache, hot, 1000)
+        assert not any(key in cache for key in hot)
+
+    def test_unknown_policy_is_rejected(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as raised:
+            Cache(temp_cache_dir, eviction_policy="most-recently-used")
+        assert raised.value.option == "eviction_policy"
+
+
+class TestGdsf:
+    """gdsf keeps entries that cost more to recompute per byte"""
+
+    def test_weighty_entries_survive_cheap_ones(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, max_entries=50, eviction_policy="gdsf")
+        for n in range(10):
+            assert cache.set(f"render-{n}", b"x" * 1000, weight=3600)
+        for n in range(500):
+            cache.set(f"thumbnail-{n}", b"x" * 1000, weight=0.01)
+        assert all(f"render-{n}" in cache for n in range(10))
+        assert len(cache) <= 50
+
+    def test_weight_must_be_positive(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, eviction_policy="gdsf")
+        for weight in (0, -1, float("nan"), float("inf")):
+            with pytest.raises(ValueError):
+                cache.set("key", "value", weight=weight)
+        assert "key" not in cache
+
+
+class TestPriority:
+    """priority outranks whatever the eviction policy prefers"""
+
+    def test_low_priority_entries_go_first(self, temp_cache_dir):
+        cache = Cache(
+            temp_cache_dir, max_entries=50, eviction_policy="least-recently-used"
+        )
+        for n in range(10):
+            cache.set(f"simulation-{n}", n, priority="high")
+        for n in range(200):
+            cache.set(f"thumbnail-{n}", n, priority="low")
+            # Read the thumbnails so recency alone would keep them over the rest
+            assert cache[f"thumbnail-{n}"] == n
+        cache.set("config", "prod")
+        assert all(f"simulation-{n}" in cache for n in range(10))
+        assert "config" in cache
+        assert len(cache) <= 50
+
+    def test_unknown_priority_is_rejected(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        with pytest.raises(ValueError):
+            cache.set("key", "value", priority="urgent")
+        assert "key" not in cache
+
+
+class TestSampled:
+    """eviction_samples approximates least-recently-used by sampling keys"""
+
+    def test_read_entries_survive(self, temp_cache_dir):
+        cache = Cache(
+            temp_cache_dir,
+            max_entries=100,
+            eviction_policy="least-recently-used",
+            eviction_samples=10,
+        )
+        hot = [f"hot-{n}" for n in range(10)]
+        for key in hot:
+            cache[key] = key
+        for n in range(500):
+            cache[f"once-{n}"] = n
+            if n % 10 == 0:
+                assert all(cache[key] == key for key in hot)
+        assert all(key in cache for key in hot)
+        assert len(cache) <= 100
+
+    def test_needs_a_sampling_policy(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as raised:
+            Cache(temp_cache_dir, eviction_samples=5)
+        assert raised.value.option == "eviction_samples"
+
+
+class TestSwitching:
+    """set_eviction_strategy moves to another policy without reopening"""
+
+    def test_hot_entries_survive_after_switching(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, max_entries=100)
+        cache.set_eviction_strategy("least-frequently-used")
+        hot = [f"hot-{n}" for n in range(50)]
+        scan(cache, hot, 1000)
+        assert all(key in cache for key in hot)
+        assert len(cache) <= 100
+
+        cache.set_eviction_strategy("least-recently-stored")
+        scan(cache, [f"new-{n}" for n in range(50)], 1000)
+        assert not any(key in cache for key in hot)
+
+    def test_unknown_strategy_is_rejected(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        with pytest.raises(CacheConfigError) as raised:
+            cache.set_eviction_strategy("most-recently-used")
+        assert raised.value.option == "eviction_policy"
@@ -0,0 +1,66 @@
+"""
+Tests for caches used by children forked while they were open
+"""
+
+import os
+
+import pytest
+
+from diskcache_rs import Cache
+
+pytestmark = pytest.mark.skipif(not hasattr(os, "fork"), reason="needs os.fork")
+
+
+def run_child(body):
+    """Run `body` in a forked child and return its exit status"""
+    pid = os.fork()
+    if pid == 0:
+        status = 1
+        try:
+            body()
+            status = 0
+        finally:
+            os._exit(status)
+    _, status = os.waitpid(pid, 0)
+    return os.waitstatus_to_exitcode(status)
+
+
+class TestForkSafety:
+    """A forked child gets a working cache of its own"""
+
+    def test_child_writes_land(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        cache["parent"] = b"before"
+
+        def child():
+            assert cache["parent"] == b"before"
+            cache["child"] = b"value"
+            assert pid_of(cache) == os.getpid()
+            cache.close()
+
+        assert run_child(child) == 0
+        assert cache["child"] == b"value"
+        # The parent's own threads carry on
+        cache["parent"] = b"after"
+        assert cache["parent"] == b"after"
+        assert [process["pid"] for process in cache.processes()] == [os.getpid()]
+
+    def test_child_does_not_inherit_the_writer_lease(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, writer_lease=3600)
+        assert not cache.read_only
+
+        def child():
+            assert cache.read_only
+            with pytest.raises(PermissionError):
+                cache["key"] = b"value"
+            cache.close()
+
+        assert run_child(child) == 0
+        assert not cache.read_only
+        cache["key"] = b"value"
+
+
+def pid_of(cache):
+    """The pid this process is registered under in the cache directory"""
+    (current,) = [process for process in cache.processes() if process["current"]]
+    return current["pid"]
@@ -190,16 +190,16 @@ class TestCacheQueueOperations:
 class TestCacheRead:
     """Tests for Cache.read()"""
 
-    def test_read_returns_bytesio(self, cache):
+    def test_read_returns_binary_file(self, cache):
         cache.set("key", b"hello world")
-        result = cache.read("key")
-        assert isinstance(result, io.BytesIO)
+        with cache.read("key") as result:
+            assert isinstance(result, io.BufferedIOBase)
+            assert result.readable()
 
     def test_read_content(self, cache):
         cache.set("key", b"hello world")
-        result = cache.read("key")
-        data = result.read()
-        assert isinstance(data, bytes)
+        with cache.read("key") as result:
+            assert result.read() == b"hello world"
 
     def test_read_missing_key(self, cache):
         with pytest.raises(KeyError):
@@ -311,8 +311,8 @@ class TestFanoutCacheEvict:
 class TestFanoutCacheRead:
     def test_read(self, fanout_cache):
         fanout_cache.set("key", b"hello")
-        result = fanout_cache.read("key")
-        assert isinstance(result, io.BytesIO)
+        with fanout_cache.read("key") as result:
+            assert result.read() == b"hello"
 
     def test_read_missing_key(self, fanout_cache):
         with pytest.raises(KeyError):
@@ -0,0 +1,66 @@
+"""
+Tests for batched reads: get_many()
+"""
+
+import time
+
+from diskcache_rs import Cache
+from diskcache_rs._diskcache_rs import PyCache
+
+
+class TestPyCacheGetMany:
+    """get_many() on the Rust cache"""
+
+    def test_results_follow_request_order(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir)
+        large = b"x" * (256 * 1024)
+        cache.set("small", b"value")
+        cache.set("large", large)
+
+        values = cache.get_many(["large", "missing", "small"])
+
+        assert values == [large, None, b"value"]
+
+    def test_counts_hits_and_misses(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir, statistics=True)
+        cache.set("a", b"1")
+
+        cache.get_many(["a", "b", "c"])
+
+        stats = cache.stats()
+        assert stats["hits"] == 1
+        assert stats["misses"] == 2
+
+    def test_prefetch_prefix(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir)
+        for i in range(20):
+            cache.set(f"user:{i}", bytes([i]) * (64 * 1024))
+        cache.set("other", b"value")
+
+        assert cache.get_many(["user:0"], prefetch_prefix="user:") == [
+            bytes([0]) * (64 * 1024)
+        ]
+        for i in range(20):
+            assert cache.get(f"user:{i}") == bytes([i]) * (64 * 1024)
+
+
+class TestCacheGetMany:
+    """get_many() on the diskcache-compatible wrapper"""
+
+    def test_returns_present_keys(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        cache["a"] = {"n": 1}
+        cache["b"] = [1, 2, 3]
+
+        assert cache.get_many(["a", "b", "missing"]) == {
+            "a": {"n": 1},
+            "b": [1, 2, 3],
+        }
+
+    def test_skips_expired_keys(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        cache.set("fresh", "value")
+        cache.set("stale", "value", expire=0.1)
+        time.sleep(0.2)
+
+        assert cache.get_many(["fresh", "stale"]) == {"fresh": "value"}
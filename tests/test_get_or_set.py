@@ -0,0 +1,155 @@
+"""
+Tests for computing missing values once across processes with get_or_set()
+and memoize(lock=True)
+"""
+
+import os
+import subprocess
+import sys
+import threading
+import time
+
+import pytest
+
+from diskcache_rs import Cache, FanoutCache
+
+COMPUTE = (
+    "import sys, time\n"
+    "from diskcache_rs import Cache\n"
+    "cache = Cache(sys.argv[1])\n"
+    "def factory():\n"
+    "    with open(sys.argv[2], 'a') as calls:\n"
+    "        calls.write('called\\n')\n"
+    "    time.sleep(0.5)\n"
+    "    return 'computed'\n"
+    "assert cache.get_or_set('key', factory) == 'computed'\n"
+)
+
+MEMOIZED = (
+    "import sys, time\n"
+    "from diskcache_rs import Cache\n"
+    "cache = Cache(sys.argv[1])\n"
+    "@cache.memoize(lock=True)\n"
+    "def compile_shader(name):\n"
+    "    with open(sys.argv[2], 'a') as calls:\n"
+    "        calls.write(name + '\\n')\n"
+    "    time.sleep(0.5)\n"
+    "    return name.upper()\n"
+    "assert compile_shader('blur') == 'BLUR'\n"
+)
+
+
+class TestGetOrSet:
+    """get_or_set computes a missing key in one caller while the others wait"""
+
+    def test_processes_compute_once(self, temp_cache_dir, tmp_path):
+        calls = tmp_path / "calls"
+        workers = [
+            subprocess.Popen(
+                [sys.executable, "-c", COMPUTE, temp_cache_dir, str(calls)],
+                env={**os.environ, "PYTHONPATH": os.pathsep.join(sys.path)},
+            )
+            for _ in range(3)
+        ]
+        assert [worker.wait(timeout=120) for worker in workers] == [0, 0, 0]
+        assert calls.read_text() == "called\n"
+        assert Cache(temp_cache_dir)["key"] == "computed"
+
+    def test_stale_value_is_served_while_one_caller_recomputes(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        assert cache.get_or_set("key", lambda: "old", expire=1, stale=60) == "old"
+        time.sleep(2.1)
+
+        started = threading.Event()
+
+        def recompute():
+            started.set()
+            time.sleep(0.3)
+            return "new"
+
+        def unexpected():
+            raise AssertionError("the stale value is being recomputed")
+
+        results = []
+        thread = threading.Thread(
+            target=lambda: results.append(
+                cache.get_or_set("key", recompute, expire=60, stale=60)
+            )
+        )
+        thread.start()
+        assert started.wait(10)
+        assert cache.get_or_set("key", unexpected, expire=60, stale=60) == "old"
+        thread.join()
+        assert results == ["new"]
+        assert cache.get_or_set("key", unexpected, expire=60, stale=60) == "new"
+
+    def test_factory_errors_reach_their_caller(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+
+        def fail():
+            raise RuntimeError("unavailable")
+
+        with pytest.raises(RuntimeError, match="unavailable"):
+            cache.get_or_set("key", fail)
+        assert "key" not in cache
+        assert cache.get_or_set("key", lambda: [1, 2], tag="lists") == [1, 2]
+        with pytest.raises(ValueError):
+            cache.get_or_set("other", lambda: 1, expire=60, stale=-1)
+
+    def test_fanout_cache_uses_the_key_shard(self, temp_cache_dir):
+        cache = FanoutCache(temp_cache_dir, shards=4)
+        for n in range(8):
+            assert cache.get_or_set(f"key{n}", lambda n=n: n) == n
+            assert cache.get_or_set(f"key{n}", lambda: None) == n
+
+
+
+class TestMemoizeLock:
+    """memoize(lock=True) and early_recompute evaluate once across processes"""
+
+    def test_lock_evaluates_once_across_processes(self, temp_cache_dir, tmp_path):
+        calls = tmp_path / "calls"
+        workers = [
+            subprocess.Popen(
+                [sys.executable, "-c", MEMOIZED, temp_cache_dir, str(calls)],
+                env={**os.environ, "PYTHONPATH": os.pathsep.join(sys.path)},
+            )
+            for _ in range(3)
+        ]
+        assert [worker.wait(timeout=120) for worker in workers] == [0, 0, 0]
+        assert calls.read_text() == "blur\n"
+
+    def test_early_recompute_refreshes_in_one_call(self, temp_cache_dir):
+        cache = FanoutCache(temp_cache_dir, shards=2)
+        calls = []
+        started = threading.Event()
+
+        @cache.memoize(expire=4, early_recompute=0.5)
+        def compile_shader(name):
+            calls.append(name)
+            if len(calls) > 1:
+                started.set()
+                time.sleep(0.3)
+            return f"{name}-{len(calls)}"
+
+        assert compile_shader("blur") == "blur-1"
+        assert compile_shader("blur") == "blur-1"
+        # Past the fresh half of expire, but before the result expires
+        time.sleep(2.2)
+
+        results = []
+        thread = threading.Thread(target=lambda: results.append(compile_shader("blur")))
+        thread.start()
+        assert started.wait(10)
+        assert compile_shader("blur") == "blur-1"
+        thread.join()
+        assert results == ["blur-2"]
+        assert compile_shader("blur") == "blur-2"
+        assert calls == ["blur", "blur"]
+
+    def test_early_recompute_is_a_fraction_of_expire(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        with pytest.raises(ValueError):
+            cache.memoize(early_recompute=0.1)
+        with pytest.raises(ValueError):
+            cache.memoize(expire=60, early_recompute=1.5)
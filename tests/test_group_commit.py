@@ -0,0 +1,47 @@
+"""
+Tests for group commit, which syncs concurrent durable writes together
+"""
+
+import threading
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+
+class TestGroupCommit:
+    """group_commit gathers fsynced writes over a short window"""
+
+    def test_concurrent_writes_all_land(self, temp_cache_dir):
+        cache = Cache(
+            temp_cache_dir,
+            durability="fsync",
+            group_commit=0.01,
+            disk_write_threshold=0,
+        )
+
+        def write(thread):
+            for i in range(5):
+                cache[f"{thread}-{i}"] = bytes([i]) * 10_000
+
+        threads = [threading.Thread(target=write, args=(t,)) for t in range(4)]
+        for thread in threads:
+            thread.start()
+        for thread in threads:
+            thread.join()
+        cache.close()
+
+        cache = Cache(temp_cache_dir, durability="fsync")
+        for thread in range(4):
+            for i in range(5):
+                assert cache[f"{thread}-{i}"] == bytes([i]) * 10_000
+
+    def test_needs_fsync_durability(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(temp_cache_dir, group_commit=0.01)
+        assert excinfo.value.option == "group_commit"
+
+    def test_rejects_negative_window(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(temp_cache_dir, durability="fsync", group_commit=-1)
+        assert excinfo.value.option == "group_commit"
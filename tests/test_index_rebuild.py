@@ -0,0 +1,47 @@
+"""
+Tests for rebuilding the index from the keys recorded in data file headers
+"""
+
+import os
+import sqlite3
+
+from diskcache_rs import Cache
+
+
+def remove_index(directory):
+    for name in ("index.sqlite3", "index.sqlite3-wal", "index.sqlite3-shm"):
+        path = os.path.join(directory, name)
+        if os.path.exists(path):
+            os.remove(path)
+
+
+class TestIndexRebuild:
+    """Data files carry their key, so a lost index can be rebuilt from them"""
+
+    def test_deleted_index_is_rebuilt_on_open(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, disk_write_threshold=0)
+        values = {f"key{i}": os.urandom(2048) for i in range(20)}
+        for key, value in values.items():
+            cache[key] = value
+        cache.close()
+
+        remove_index(temp_cache_dir)
+
+        cache = Cache(temp_cache_dir, disk_write_threshold=0)
+        assert len(cache) == 20
+        for key, value in values.items():
+            assert cache[key] == value
+        cache.close()
+
+    def test_verify_and_recover_restores_unindexed_files(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, disk_write_threshold=0)
+        cache["kept"] = b"k" * 4096
+        cache["lost"] = b"l" * 4096
+
+        with sqlite3.connect(os.path.join(temp_cache_dir, "index.sqlite3")) as conn:
+            conn.execute("DELETE FROM cache_index WHERE key = 'lost'")
+
+        report = cache.verify_and_recover()
+        assert report["entries_restored"] == 1
+        assert cache["lost"] == b"l" * 4096
+        cache.close()
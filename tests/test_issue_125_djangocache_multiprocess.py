@@ -192,3 +192,44 @@ finally:
         assert cache.get("key") is None
     finally:
         cache.close()
+
+
+def test_workers_started_together_share_one_directory():
+    path = tempfile.mkdtemp(prefix="diskcache-rs-issue-125-workers-")
+
+    code = """
+import sys
+from diskcache_rs import FanoutCache
+worker = int(sys.argv[2])
+cache = FanoutCache(sys.argv[1], shards=2)
+try:
+    for n in range(100):
+        key = "shared%d" % (n % 5)
+        cache.set(key, bytes([worker]) * (1000 + (n % 4) * 20000))
+        value = cache.get("shared%d" % ((n + 2) % 5))
+        assert value is None or value == value[:1] * len(value)
+    cache.set("own%d" % worker, b"done")
+finally:
+    cache.close()
+"""
+    workers = [
+        subprocess.Popen(
+            [sys.executable, "-c", code, path, str(worker)],
+            text=True,
+            stdout=subprocess.PIPE,
+            stderr=subprocess.PIPE,
+        )
+        for worker in range(4)
+    ]
+    for worker in workers:
+        stdout, stderr = worker.communicate(timeout=120)
+        assert worker.returncode == 0, stderr or stdout
+
+    from diskcache_rs import FanoutCache
+
+    cache = FanoutCache(path, shards=2)
+    try:
+        for worker in range(4):
+            assert cache.get("own%d" % worker) == b"done"
+    finally:
+        cache.close()
@@ -0,0 +1,62 @@
+"""
+Tests for lazy, store-ordered key iteration
+"""
+
+from diskcache_rs import Cache
+from diskcache_rs._diskcache_rs import PyCache
+
+
+class TestIterkeys:
+    """iterkeys() follows per-entry sequence numbers"""
+
+    def test_lazy_iterator(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir)
+        for i in range(600):
+            cache.set(f"key-{i:03d}", b"v")
+
+        keys = cache.iterkeys()
+        assert iter(keys) is keys
+        assert next(keys) == "key-000"
+        assert list(keys)[-1] == "key-599"
+
+    def test_overwrite_moves_key_to_end(self, temp_cache_dir):
+        with Cache(temp_cache_dir) as cache:
+            for key in ("a", "b", "c"):
+                cache[key] = key
+            cache["a"] = "again"
+
+            assert list(cache.iterkeys()) == ["b", "c", "a"]
+            assert list(reversed(cache)) == ["a", "c", "b"]
+
+    def test_order_survives_reopen(self, temp_cache_dir):
+        with Cache(temp_cache_dir) as cache:
+            cache["small"] = 1
+            cache["large"] = b"x" * (256 * 1024)
+            cache["last"] = 2
+
+        with Cache(temp_cache_dir) as cache:
+            assert list(cache.iterkeys()) == ["small", "large", "last"]
+
+    def test_empty(self, temp_cache_dir):
+        with Cache(temp_cache_dir) as cache:
+            assert list(cache.iterkeys()) == []
+            assert list(cache.iterkeys(reverse=True)) == []
+
+    def test_rust_caches_iterate_lazily(self, temp_cache_dir):
+        from diskcache_rs._diskcache_rs import Cache as RustCache
+        from diskcache_rs._diskcache_rs import FanoutCache as RustFanoutCache
+
+        cache = RustCache(temp_cache_dir)
+        for i in range(600):
+            cache.set(f"key-{i:03d}", b"v")
+        keys = cache.iterkeys()
+        assert iter(keys) is keys
+        assert next(keys) == "key-000"
+        assert next(cache.iterkeys(reverse=True)) == "key-599"
+
+        fanout = RustFanoutCache(f"{temp_cache_dir}/fanout", shards=4)
+        for i in range(600):
+            fanout.set(f"key-{i:03d}", b"v")
+        keys = fanout.iterkeys()
+        assert iter(keys) is keys
+        assert sorted(keys) == [f"key-{i:03d}" for i in range(600)]
@@ -0,0 +1,52 @@
+"""
+Tests for adopting existing files into the cache with link_file()
+"""
+
+import os
+
+import pytest
+
+from diskcache_rs import Cache
+
+
+class TestLinkFile:
+    """link_file() stores a file's bytes by linking it instead of rewriting it"""
+
+    def test_linked_file_is_served_and_survives_the_original(
+        self, temp_cache_dir, tmp_path
+    ):
+        source = tmp_path / "model.bin"
+        contents = os.urandom(1024 * 1024)
+        source.write_bytes(contents)
+
+        cache = Cache(temp_cache_dir)
+        cache.link_file("model", source)
+        assert "model" in cache
+
+        path, offset = cache._cache.data_file("model")
+        assert offset == 0
+        assert os.path.samefile(path, source)
+
+        source.unlink()
+        with cache.read("model") as reader:
+            assert reader.read() == contents
+
+        del cache["model"]
+        assert "model" not in cache
+
+    def test_deleting_the_key_keeps_the_original(self, temp_cache_dir, tmp_path):
+        source = tmp_path / "archive.tar"
+        source.write_bytes(os.urandom(256 * 1024))
+
+        cache = Cache(temp_cache_dir)
+        cache.link_file("archive", str(source))
+        cache.clear()
+
+        assert len(cache) == 0
+        assert source.stat().st_size == 256 * 1024
+
+    def test_missing_file(self, temp_cache_dir, tmp_path):
+        cache = Cache(temp_cache_dir)
+        with pytest.raises(Exception):
+            cache.link_file("missing", tmp_path / "missing.bin")
+        assert "missing" not in cache
@@ -0,0 +1,70 @@
+"""
+Tests for per-key advisory locks around read-modify-write operations
+"""
+
+import subprocess
+import sys
+import threading
+import time
+
+from diskcache_rs import Cache, FanoutCache
+
+
+class TestLockKey:
+    """lock_key holds off other holders of the same key"""
+
+    def test_lock_excludes_other_holders_until_released(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        acquired = threading.Event()
+
+        lock = cache.lock_key("key")
+        assert lock.locked
+        # Other keys are free
+        with cache.lock_key("other"):
+            pass
+
+        def waiter():
+            with cache.lock_key("key"):
+                acquired.set()
+
+        thread = threading.Thread(target=waiter)
+        thread.start()
+        time.sleep(0.1)
+        assert not acquired.is_set()
+        lock.release()
+        assert not lock.locked
+        thread.join(timeout=5)
+        assert acquired.is_set()
+
+    def test_fanout_cache_locks_in_the_key_shard(self, temp_cache_dir):
+        cache = FanoutCache(temp_cache_dir, shards=4)
+        with cache.lock_key("key"):
+            cache["key"] = cache.get("key", 0) + 1
+        assert cache["key"] == 1
+
+    def test_incr_from_several_processes_loses_no_update(self, temp_cache_dir):
+        code = """
+import sys
+from diskcache_rs import Cache
+cache = Cache(sys.argv[1])
+try:
+    for _ in range(50):
+        cache.incr("counter")
+finally:
+    cache.close()
+"""
+        workers = [
+            subprocess.Popen(
+                [sys.executable, "-c", code, temp_cache_dir],
+                text=True,
+                stdout=subprocess.PIPE,
+                stderr=subprocess.PIPE,
+            )
+            for _ in range(4)
+        ]
+        for worker in workers:
+            stdout, stderr = worker.communicate(timeout=120)
+            assert worker.returncode == 0, stderr or stdout
+
+        cache = Cache(temp_cache_dir)
+        assert cache.get("counter") == 200
@@ -0,0 +1,49 @@
+"""
+Tests for electing one process to maintain a shared cache directory
+"""
+
+import os
+import subprocess
+import sys
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+
+def run_other(directory, script):
+    """Run `script` in another process with `cache` open on `directory`"""
+    prelude = (
+        "import sys\n"
+        "from diskcache_rs import Cache\n"
+        "cache = Cache(sys.argv[1], maintenance_lease=3600)\n"
+    )
+    subprocess.run(
+        [sys.executable, "-c", prelude + script, directory],
+        env={**os.environ, "PYTHONPATH": os.pathsep.join(sys.path)},
+        check=True,
+    )
+
+
+class TestMaintenanceLease:
+    """maintenance_lease leaves vacuum, expiry sweeps and compaction to one process"""
+
+    def test_one_process_leads_maintenance(self, temp_cache_dir):
+        leader = Cache(temp_cache_dir, maintenance_lease=3600)
+        assert leader.maintenance_leader
+        run_other(temp_cache_dir, "assert not cache.maintenance_leader\n")
+
+        # The leader closing frees the lease for the next process
+        leader.close()
+        run_other(temp_cache_dir, "assert cache.maintenance_leader\n")
+
+    def test_every_process_maintains_without_it(self, temp_cache_dir):
+        first = Cache(temp_cache_dir)
+        second = Cache(temp_cache_dir)
+        assert first.maintenance_leader
+        assert second.maintenance_leader
+
+    def test_memory_backend_is_rejected(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as raised:
+            Cache(temp_cache_dir, backend="memory", maintenance_lease=3600)
+        assert raised.value.option == "maintenance_lease"
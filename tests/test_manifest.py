@@ -0,0 +1,69 @@
+"""
+Tests for serving prepackaged files listed in a manifest
+"""
+
+import json
+import os
+
+import pytest
+
+from diskcache_rs import Cache
+
+
+@pytest.fixture
+def bundle(tmp_path):
+    """An asset bundle with a manifest written next to it"""
+    assets = tmp_path / "bundle"
+    (assets / "img").mkdir(parents=True)
+    (assets / "img" / "logo.png").write_bytes(b"\x89PNG" + os.urandom(4096))
+    (assets / "strings.bin").write_bytes(b"\x00\x01hello")
+    manifest = assets / "manifest.json"
+    manifest.write_text(
+        json.dumps({"logo": "img/logo.png", "strings": "strings.bin"})
+    )
+    return manifest
+
+
+class TestManifest:
+    """load_manifest registers files as entries without copying them"""
+
+    def test_files_are_served_in_place(self, temp_cache_dir, bundle):
+        cache = Cache(temp_cache_dir)
+        assert cache.load_manifest(bundle) == 2
+
+        logo = (bundle.parent / "img" / "logo.png").read_bytes()
+        assert len(cache) == 2
+        assert cache.get("logo") == logo
+        with cache.read("strings") as reader:
+            assert reader.read() == b"\x00\x01hello"
+
+        # Nothing was copied into the cache directory
+        data_dir = os.path.join(temp_cache_dir, "data")
+        copied = [name for _, _, names in os.walk(data_dir) for name in names]
+        assert not any(name.endswith(".dat") for name in copied)
+        cache.close()
+
+        cache = Cache(temp_cache_dir)
+        assert cache.get("logo") == logo
+        cache.close()
+
+    def test_deleting_keys_keeps_the_files(self, temp_cache_dir, bundle):
+        cache = Cache(temp_cache_dir)
+        cache.load_manifest(str(bundle))
+
+        del cache["logo"]
+        assert "logo" not in cache
+        cache.clear()
+        assert len(cache) == 0
+
+        assert (bundle.parent / "img" / "logo.png").exists()
+        assert (bundle.parent / "strings.bin").read_bytes() == b"\x00\x01hello"
+
+    def test_missing_file_registers_nothing(self, temp_cache_dir, tmp_path):
+        manifest = tmp_path / "manifest.json"
+        manifest.write_text(json.dumps({"gone": "missing.bin"}))
+
+        cache = Cache(temp_cache_dir)
+        with pytest.raises(Exception):
+            cache.load_manifest(manifest)
+        assert "gone" not in cache
@@ -0,0 +1,34 @@
+"""
+Tests for expiring entries nobody reads with max_idle
+"""
+
+import time
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+
+def test_unread_entries_expire(temp_cache_dir):
+    cache = Cache(temp_cache_dir, max_idle=0.5)
+    cache["read"] = 1
+    cache["unread"] = 2
+    time.sleep(0.3)
+    assert cache["read"] == 1
+    time.sleep(0.3)
+    assert "unread" not in cache
+    assert cache.get("unread") is None
+    assert cache["read"] == 1
+
+
+def test_idle_expiry_comes_before_the_entry_expiry(temp_cache_dir):
+    cache = Cache(temp_cache_dir, max_idle=0.3)
+    cache.set("key", "value", expire=3600)
+    time.sleep(0.5)
+    assert cache.get("key") is None
+
+
+def test_rejects_a_zero_idle_period(temp_cache_dir):
+    with pytest.raises(CacheConfigError) as raised:
+        Cache(temp_cache_dir, max_idle=0)
+    assert raised.value.option == "max_idle"
@@ -0,0 +1,52 @@
+"""
+Tests for the memory backend, which keeps entries in process memory only
+"""
+
+import os
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+
+class TestMemoryBackend:
+    """backend="memory" serves the usual API without touching the filesystem"""
+
+    def test_round_trips_values_without_a_directory(self):
+        cache = Cache(backend="memory")
+        cache["object"] = {"answer": 42}
+        cache["large"] = b"x" * 100_000
+        cache.set("tagged", "value", expire=60, tag="group")
+
+        assert cache.directory is None
+        assert cache["object"] == {"answer": 42}
+        assert cache["large"] == b"x" * 100_000
+        assert cache.get("tagged") == "value"
+        assert len(cache) == 3
+
+        del cache["object"]
+        assert "object" not in cache
+        cache.clear()
+        assert len(cache) == 0
+
+    def test_directory_is_never_created(self, temp_cache_dir):
+        directory = os.path.join(temp_cache_dir, "unused")
+        cache = Cache(directory, backend="memory", disk_write_threshold=0)
+        cache["key"] = b"value" * 1000
+        cache.vacuum()
+
+        assert cache["key"] == b"value" * 1000
+        assert not os.path.exists(directory)
+
+    def test_entries_do_not_outlive_the_cache(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, backend="memory")
+        cache["key"] = "value"
+        cache.close()
+
+        assert Cache(temp_cache_dir, backend="memory").get("key") is None
+        assert os.listdir(temp_cache_dir) == []
+
+    def test_rejects_wal(self):
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(backend="memory", wal="always")
+        assert excinfo.value.option == "wal"
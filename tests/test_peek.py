@@ -0,0 +1,68 @@
+"""
+Tests for non-intrusive reads: peek() and peekitem()
+"""
+
+import time
+
+import pytest
+
+from diskcache_rs import Cache
+from diskcache_rs._diskcache_rs import PyCache
+
+
+class TestPyCachePeek:
+    """peek() and peekitem() on the Rust cache"""
+
+    def test_peek_does_not_count_as_hit(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir)
+        cache.set("key", b"value")
+
+        assert cache.peek("key") == b"value"
+        assert cache.peek("missing") is None
+
+        stats = cache.stats()
+        assert stats["hits"] == 0
+        assert stats["misses"] == 0
+
+    def test_peekitem_follows_store_order(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir)
+        for key in ("a", "c", "b"):
+            cache.set(key, key.encode())
+
+        assert cache.peekitem() == ("b", b"b")
+        assert cache.peekitem(last=False) == ("a", b"a")
+
+        # Rewriting a key moves it to the end of store order
+        cache.set("a", b"again")
+        assert cache.peekitem() == ("a", b"again")
+        assert cache.peekitem(last=False) == ("c", b"c")
+
+    def test_peekitem_empty(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir)
+        assert cache.peekitem() is None
+
+
+class TestCachePeekitem:
+    """peekitem() on the Python Cache wrapper"""
+
+    def test_store_order_not_sorted(self, temp_cache_dir):
+        with Cache(temp_cache_dir) as cache:
+            cache["zebra"] = 1
+            cache["apple"] = 2
+
+            assert cache.peekitem() == ("apple", 2)
+            assert cache.peekitem(last=False) == ("zebra", 1)
+
+    def test_skips_expired_items(self, temp_cache_dir):
+        with Cache(temp_cache_dir) as cache:
+            cache.set("live", 1)
+            cache.set("stale", 2, expire=0.05)
+            time.sleep(0.1)
+
+            assert cache.peekitem() == ("live", 1)
+            assert "stale" not in cache
+
+    def test_empty_raises_key_error(self, temp_cache_dir):
+        with Cache(temp_cache_dir) as cache:
+            with pytest.raises(KeyError):
+                cache.peekitem()
@@ -2,6 +2,9 @@
 Tests for PickleCache functionality
 """
 
+import json
+import os
+import pickle
 import tempfile
 import time
 
@@ -251,3 +254,132 @@ class TestPickleCache:
         stats = cache.stats()
         assert stats["entries"] < 10
         assert stats["size_bytes"] <= 1024
+
+    def test_index_survives_reopen(self, temp_cache_dir):
+        """The index is stored as index.bin and reloaded on open"""
+        cache = PickleCache(temp_cache_dir)
+        cache.set("persisted", [1, 2, 3], ttl_seconds=3600)
+
+        reopened = PickleCache(temp_cache_dir)
+        assert reopened.get("persisted") == [1, 2, 3]
+        assert reopened.ttl("persisted") > 0
+        assert os.path.exists(os.path.join(temp_cache_dir, "index.bin"))
+        assert not os.path.exists(os.path.join(temp_cache_dir, "index.json"))
+
+    def test_legacy_json_index_is_upgraded(self, temp_cache_dir):
+        """Caches written with index.json open and switch to the binary index"""
+        cache = PickleCache(temp_cache_dir)
+        cache.set("legacy", {"answer": 42})
+        del cache
+
+        # Swap the binary index and journal for the JSON index earlier versions wrote
+        pickled = pickle.dumps({"answer": 42}, protocol=pickle.HIGHEST_PROTOCOL)
+        now = "2024-01-01T00:00:00Z"
+        legacy = {
+            "legacy": {
+                "data": list(pickled),
+                "expires_at": None,
+                "created_at": now,
+                "accessed_at": now,
+                "size": len(pickled),
+            }
+        }
+        for name in ("index.bin", "index.journal"):
+            path = os.path.join(temp_cache_dir, name)
+            if os.path.exists(path):
+                os.remove(path)
+        with open(os.path.join(temp_cache_dir, "index.json"), "w") as f:
+            json.dump(legacy, f)
+
+        cache = PickleCache(temp_cache_dir)
+        assert cache.get("legacy") == {"answer": 42}
+        assert cache.stats()["size_bytes"] == len(pickled)
+        assert os.path.exists(os.path.join(temp_cache_dir, "index.bin"))
+        assert not os.path.exists(os.path.join(temp_cache_dir, "index.json"))
+
+    def test_corrupted_index_starts_empty(self, temp_cache_dir):
+        """A damaged index fails its checksum and the cache starts fresh"""
+        cache = PickleCache(temp_cache_dir)
+        cache.set("key", "value")
+        # Reopening folds the journal into index.bin
+        del cache
+        PickleCache(temp_cache_dir)
+
+        index_path = os.path.join(temp_cache_dir, "index.bin")
+        with open(index_path, "r+b") as f:
+            f.seek(-1, os.SEEK_END)
+            last = f.read(1)
+            f.seek(-1, os.SEEK_END)
+            f.write(bytes([last[0] ^ 0xFF]))
+
+        reopened = PickleCache(temp_cache_dir)
+        assert reopened.get("key") is None
+        reopened.set("key", "fresh")
+        assert PickleCache(temp_cache_dir).get("key") == "fresh"
+
+    def test_changes_are_journaled_until_reopen(self, temp_cache_dir):
+        """Writes are appended to the journal and folded into the index on open"""
+        cache = PickleCache(temp_cache_dir)
+        cache.set("kept", "value")
+        cache.set("dropped", "value")
+        cache.delete("dropped")
+        cache.expire("kept", 3600)
+
+        journal_path = os.path.join(temp_cache_dir, "index.journal")
+        assert os.path.getsize(journal_path) > 0
+        assert not os.path.exists(os.path.join(temp_cache_dir, "index.bin"))
+
+        reopened = PickleCache(temp_cache_dir)
+        assert os.path.getsize(journal_path) == 0
+        assert reopened.get("kept") == "value"
+        assert reopened.get("dropped") is None
+
+
+class TestPickleCacheSchemaVersions:
+    """Values from older schema versions are upgraded or dropped on read"""
+
+    def test_upgrade_chain_runs_once(self, tmp_path):
+        old = PickleCache(str(tmp_path), schema_version=1)
+        old.set("user", {"name": "ada"}, ttl_seconds=3600)
+        assert old.schema_version_of("user") == 1
+
+        calls = []
+        cache = PickleCache(str(tmp_path), schema_version=3)
+
+        @cache.upgrade(1)
+        def add_email(user):
+            calls.append(1)
+            return {**user, "email": None}
+
+        cache.register_upgrade(2, lambda user: {**user, "active": True})
+
+        expected = {"name": "ada", "email": None, "active": True}
+        assert cache.get("user") == expected
+        assert cache.schema_version_of("user") == 3
+        assert cache.ttl("user") > 0
+        # Written back, so later reads skip the chain
+        assert cache.get("user") == expected
+        assert calls == [1]
+
+    def test_missing_upgrade_invalidates(self, tmp_path):
+        PickleCache(str(tmp_path)).set("legacy", [1, 2])
+        cache = PickleCache(str(tmp_path), schema_version=2)
+        cache.register_upgrade(1, lambda value: value)
+
+        assert cache.get("legacy", "gone") == "gone"
+        assert not cache.exists("legacy")
+
+    def test_newer_entries_are_left_alone(self, tmp_path):
+        PickleCache(str(tmp_path), schema_version=5).set("key", "new")
+        cache = PickleCache(str(tmp_path), schema_version=4)
+        assert cache.get("key") is None
+        assert cache.exists("key")
+        # A cache without a schema version reads everything as is
+        assert PickleCache(str(tmp_path)).get("key") == "new"
+
+    def test_register_upgrade_validates_versions(self, tmp_path):
+        with pytest.raises(ValueError):
+            PickleCache(str(tmp_path)).register_upgrade(0, lambda value: value)
+        cache = PickleCache(str(tmp_path), schema_version=2)
+        with pytest.raises(ValueError):
+            cache.register_upgrade(2, lambda value: value)
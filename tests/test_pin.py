@@ -0,0 +1,45 @@
+"""
+Tests for exempting keys from eviction with pin()
+"""
+
+from diskcache_rs import Cache, FanoutCache
+
+
+class TestPin:
+    """Pinned keys are never evicted, whatever the policy picks"""
+
+    def test_pinned_keys_outlive_eviction(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, max_entries=10)
+        cache.pin("license")
+        cache["license"] = b"signed"
+        cache.set("config", {"mode": "prod"}, pin=True)
+        for n in range(100):
+            cache[f"key{n}"] = n
+        assert cache["license"] == b"signed"
+        assert cache["config"] == {"mode": "prod"}
+        assert len(cache) <= 11
+
+        assert cache.unpin("license")
+        assert not cache.unpin("license")
+        for n in range(100, 120):
+            cache[f"key{n}"] = n
+        assert "license" not in cache
+        assert "config" in cache
+
+    def test_pins_survive_deletes(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, max_entries=5)
+        cache.set("license", b"old", pin=True)
+        del cache["license"]
+        cache["license"] = b"new"
+        for n in range(50):
+            cache[f"key{n}"] = n
+        assert cache["license"] == b"new"
+
+    def test_fanout_cache_pins_in_the_key_shard(self, temp_cache_dir):
+        cache = FanoutCache(temp_cache_dir, shards=4, max_entries=5)
+        for n in range(4):
+            cache.set(f"pinned{n}", n, pin=True)
+        for n in range(100):
+            cache[f"key{n}"] = n
+        assert [cache[f"pinned{n}"] for n in range(4)] == [0, 1, 2, 3]
+        assert cache.unpin("pinned0")
@@ -0,0 +1,52 @@
+"""
+Tests for the registry of processes attached to a cache directory
+"""
+
+import os
+import subprocess
+import sys
+
+from diskcache_rs import Cache, FanoutCache
+
+
+class TestProcessRegistry:
+    """processes() lists the live openers of a directory"""
+
+    def test_lists_this_process_until_closed(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        processes = cache.processes()
+        assert [process["pid"] for process in processes] == [os.getpid()]
+        assert processes[0]["current"]
+        assert processes[0]["heartbeat"] >= processes[0]["opened_at"]
+
+        other = Cache(temp_cache_dir)
+        other.close()
+        assert len(cache.processes()) == 1
+
+    def test_fanout_cache_lists_each_process_once(self, temp_cache_dir):
+        cache = FanoutCache(temp_cache_dir, shards=4)
+        assert [process["pid"] for process in cache.processes()] == [os.getpid()]
+
+    def test_crashed_process_is_reaped_with_its_lease(self, temp_cache_dir):
+        code = """
+import os
+import sys
+from diskcache_rs import Cache
+cache = Cache(sys.argv[1], writer_lease=3600)
+cache["key"] = b"value"
+os._exit(0)
+"""
+        worker = subprocess.run(
+            [sys.executable, "-c", code, temp_cache_dir],
+            capture_output=True,
+            text=True,
+            timeout=120,
+        )
+        assert worker.returncode == 0, worker.stderr or worker.stdout
+
+        # The crashed writer's lease does not leave the cache read-only
+        cache = Cache(temp_cache_dir, writer_lease=3600)
+        assert not cache.read_only
+        assert [process["pid"] for process in cache.processes()] == [os.getpid()]
+        cache["key"] = b"other"
+        cache.close()
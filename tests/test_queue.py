@@ -0,0 +1,77 @@
+"""
+Tests for push()/pull() queue operations backed by ordered key scans
+"""
+
+import subprocess
+import sys
+import time
+
+from diskcache_rs import Cache, Deque
+
+
+class TestQueuePersistence:
+    """Queues survive reopening because order lives in the keys"""
+
+    def test_order_survives_reopen(self, temp_cache_dir):
+        with Cache(temp_cache_dir) as cache:
+            cache.push("b")
+            cache.push("c")
+            cache.push("a", side="front")
+
+        with Cache(temp_cache_dir) as cache:
+            assert cache.pull() == (-1, "a")
+            assert cache.pull(side="back") == (1, "c")
+            assert cache.pull() == (0, "b")
+            assert cache.pull() == (None, None)
+
+    def test_push_from_another_process(self, temp_cache_dir):
+        script = (
+            "from diskcache_rs import Cache\n"
+            f"cache = Cache({temp_cache_dir!r})\n"
+            "cache.push('from-child', prefix='jobs')\n"
+            "cache.close()\n"
+        )
+        with Cache(temp_cache_dir) as cache:
+            cache.push("from-parent", prefix="jobs")
+            subprocess.run([sys.executable, "-c", script], check=True)
+
+            assert cache.pull(prefix="jobs") == (0, "from-parent")
+            assert cache.pull(prefix="jobs") == (1, "from-child")
+
+    def test_prefixes_are_independent(self, temp_cache_dir):
+        with Cache(temp_cache_dir) as cache:
+            cache.push("x", prefix="a")
+            cache.push("y", prefix="ab")
+
+            assert cache.pull(prefix="a") == (0, "x")
+            assert cache.pull(prefix="a") == (None, None)
+            assert cache.pull(prefix="ab") == (0, "y")
+
+
+class TestQueueExpiry:
+    """Expired items are skipped by pull() and peek()"""
+
+    def test_pull_skips_expired(self, temp_cache_dir):
+        with Cache(temp_cache_dir) as cache:
+            cache.push("stale", expire=0.05)
+            cache.push("fresh")
+            time.sleep(1.1)
+
+            assert cache.peek() == (1, "fresh")
+            assert cache.pull() == (1, "fresh")
+
+
+class TestDequeOnQueue:
+    """Deque is built on the same queue keys"""
+
+    def test_len_and_iteration(self, temp_cache_dir):
+        deque = Deque(directory=temp_cache_dir)
+        deque.append(2)
+        deque.append(3)
+        deque.appendleft(1)
+
+        assert len(deque) == 3
+        assert list(deque) == [1, 2, 3]
+        assert deque.pop() == 3
+        assert deque.popleft() == 1
+        assert list(deque) == [2]
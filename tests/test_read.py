@@ -0,0 +1,70 @@
+"""
+Tests for file-handle reads: read() and data_file()
+"""
+
+import os
+
+import pytest
+
+from diskcache_rs import Cache
+from diskcache_rs._diskcache_rs import PyCache
+
+
+class TestDataFile:
+    """data_file() on the Rust cache"""
+
+    def test_large_values_have_a_data_file(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir)
+        value = os.urandom(128 * 1024)
+        cache.set("large", value)
+
+        located = cache.data_file("large")
+
+        assert located is not None
+        path, offset = located
+        with open(path, "rb") as f:
+            f.seek(offset)
+            assert f.read() == value
+
+    def test_inline_and_missing_values_have_none(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir)
+        cache.set("small", b"value")
+
+        assert cache.data_file("small") is None
+        assert cache.data_file("missing") is None
+
+
+class TestCacheRead:
+    """read() on the diskcache-compatible wrapper"""
+
+    def test_reads_large_value_from_its_data_file(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        value = os.urandom(128 * 1024)
+        cache.set("large", value)
+
+        with cache.read("large") as reader:
+            assert reader.name == str(cache._cache.data_file("large")[0])
+            assert reader.read() == value
+        assert reader.closed
+
+    def test_spills_inline_value_to_temp_file(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        cache.set("small", b"hello")
+
+        with cache.read("small") as reader:
+            assert reader.fileno() >= 0
+            assert reader.read() == b"hello"
+
+    def test_spills_compressed_value(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        value = b"x" * (256 * 1024)
+        cache.set("compressible", value)
+
+        with cache.read("compressible") as reader:
+            assert reader.read() == value
+
+    def test_missing_key_raises(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+
+        with pytest.raises(KeyError):
+            cache.read("missing")
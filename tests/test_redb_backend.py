@@ -0,0 +1,59 @@
+"""
+Tests for the redb backend, which keeps every entry in one database file
+"""
+
+import os
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+
+class TestRedbBackend:
+    """Many tiny entries live in cache.redb instead of a file each"""
+
+    def test_round_trips_values(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, backend="redb")
+        cache["object"] = {"answer": 42}
+        cache["bytes"] = b"raw"
+        cache["large"] = b"x" * 100_000
+
+        assert cache["object"] == {"answer": 42}
+        assert cache["bytes"] == b"raw"
+        assert cache["large"] == b"x" * 100_000
+        assert os.path.exists(os.path.join(temp_cache_dir, "cache.redb"))
+
+    def test_many_tiny_entries(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, backend="redb")
+        for i in range(2000):
+            cache[f"k{i}"] = i
+
+        assert len(cache) == 2000
+        assert cache["k1999"] == 1999
+        assert not os.path.exists(os.path.join(temp_cache_dir, "data"))
+
+    def test_delete_clear_and_vacuum(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, backend="redb")
+        cache["a"] = 1
+        cache["b"] = 2
+
+        del cache["a"]
+        assert "a" not in cache
+        assert cache.get("b") == 2
+
+        cache.clear()
+        assert len(cache) == 0
+        cache.vacuum()
+
+    def test_entries_survive_close(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, backend="redb")
+        cache["key"] = "value"
+        cache.close()
+
+        reopened = Cache(temp_cache_dir, backend="redb")
+        assert reopened["key"] == "value"
+
+    def test_rejects_wal(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(temp_cache_dir, backend="redb", wal="always")
+        assert excinfo.value.option == "wal"
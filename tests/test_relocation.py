@@ -0,0 +1,44 @@
+"""
+Tests for moving cache directories between locations
+"""
+
+import os
+import shutil
+
+from diskcache_rs import Cache
+
+
+class TestRelocation:
+    """Index paths are relative, so moved and copied caches keep working"""
+
+    def test_moved_cache_serves_file_entries(self, temp_cache_dir):
+        original = os.path.join(temp_cache_dir, "original")
+        moved = os.path.join(temp_cache_dir, "moved")
+        payload = b"x" * (256 * 1024)
+
+        with Cache(original) as cache:
+            cache["large"] = payload
+            cache["small"] = "inline"
+
+        shutil.move(original, moved)
+
+        with Cache(moved) as cache:
+            assert cache["large"] == payload
+            assert cache["small"] == "inline"
+            assert cache.relocate() == 0
+
+    def test_copied_cache_is_independent(self, temp_cache_dir):
+        source = os.path.join(temp_cache_dir, "source")
+        copy = os.path.join(temp_cache_dir, "copy")
+        payload = b"y" * (256 * 1024)
+
+        with Cache(source) as cache:
+            cache["large"] = payload
+
+        shutil.copytree(source, copy)
+
+        with Cache(copy) as cache:
+            del cache["large"]
+
+        with Cache(source) as cache:
+            assert cache["large"] == payload
@@ -0,0 +1,38 @@
+"""
+Tests for remote_tier, which keeps evicted entries in an object store
+"""
+
+import os
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+
+class TestRemoteTier:
+    """Entries evicted from the cache stay readable from the remote tier"""
+
+    def test_evicted_entries_are_read_back(self, temp_cache_dir, tmp_path):
+        remote = tmp_path / "remote"
+        cache = Cache(temp_cache_dir, max_entries=20, remote_tier=str(remote))
+        for i in range(100):
+            cache[f"artifact{i}"] = b"a" * 10_000 + bytes([i])
+
+        assert len(os.listdir(remote)) > 0
+        assert cache["artifact0"] == b"a" * 10_000 + bytes([0])
+        assert "artifact1" in cache
+
+        del cache["artifact0"]
+        assert "artifact0" not in cache
+        cache.clear()
+        assert os.listdir(remote) == []
+
+    def test_other_backends_are_rejected(self, temp_cache_dir, tmp_path):
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(temp_cache_dir, backend="sqlite", remote_tier=str(tmp_path / "remote"))
+        assert excinfo.value.option == "remote_tier"
+
+    def test_unknown_schemes_are_rejected(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(temp_cache_dir, remote_tier="ftp://example.com/cache")
+        assert excinfo.value.option == "remote_tier"
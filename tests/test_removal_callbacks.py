@@ -0,0 +1,69 @@
+"""
+Tests for hearing about evictions and expirations with on_evict() and
+on_expire()
+"""
+
+import threading
+import time
+
+from diskcache_rs import Cache, FanoutCache
+
+WAIT = 10
+
+
+class Recorder:
+    """Callback collecting (key, reason) pairs until it has *count* of them"""
+
+    def __init__(self, count):
+        self.calls = []
+        self.count = count
+        self.done = threading.Event()
+
+    def __call__(self, key, reason):
+        self.calls.append((key, reason))
+        if len(self.calls) >= self.count:
+            self.done.set()
+
+
+class TestRemovalCallbacks:
+    """Callbacks hear (key, reason) for entries removed by policy"""
+
+    def test_on_evict_hears_evictions_but_not_deletes(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, max_entries=10)
+        evicted = cache.on_evict(Recorder(5))
+        cache["deleted"] = "value"
+        del cache["deleted"]
+        for n in range(30):
+            cache[f"key{n}"] = n
+        assert evicted.done.wait(WAIT)
+        assert all(reason == "evict" for _, reason in evicted.calls)
+        assert all(key not in cache for key, _ in evicted.calls)
+        assert ("deleted", "evict") not in evicted.calls
+        cache.close()
+
+    def test_one_callback_tells_evictions_from_expirations(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, max_entries=5)
+        removed = Recorder(2)
+        cache.on_evict(removed)
+        cache.on_expire(removed)
+        cache.set("short", "lived", expire=0.5)
+        time.sleep(1.5)
+        assert cache.get("short") is None
+        for n in range(10):
+            cache[f"key{n}"] = n
+        assert removed.done.wait(WAIT)
+        assert removed.calls[0] == ("short", "expire")
+        assert removed.calls[1][1] == "evict"
+        cache.close()
+
+    def test_failing_callback_does_not_stop_the_others(self, temp_cache_dir):
+        cache = FanoutCache(temp_cache_dir, shards=2, max_entries=4)
+
+        @cache.on_evict
+        def fail(key, reason):
+            raise RuntimeError("downstream unavailable")
+
+        evicted = cache.on_evict(Recorder(2))
+        for n in range(20):
+            cache[f"key{n}"] = n
+        assert evicted.done.wait(WAIT)
@@ -0,0 +1,68 @@
+"""
+Tests for the ring backend, which keeps entries in one preallocated file
+"""
+
+import os
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+CAPACITY = 64 * 1024
+
+
+def ring_file(directory):
+    return os.path.join(directory, "ring.dat")
+
+
+class TestRingBackend:
+    """The ring file never grows; the oldest entries make room for new ones"""
+
+    def test_round_trips_values(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, backend="ring", ring_capacity_bytes=CAPACITY)
+        cache["object"] = {"answer": 42}
+        cache["bytes"] = b"raw"
+        cache.set("tagged", "value", tag="group", expire=3600)
+
+        assert cache["object"] == {"answer": 42}
+        assert cache["bytes"] == b"raw"
+        assert cache["tagged"] == "value"
+        assert os.path.exists(ring_file(temp_cache_dir))
+
+    def test_oldest_entries_are_overwritten(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, backend="ring", ring_capacity_bytes=CAPACITY)
+        size = os.path.getsize(ring_file(temp_cache_dir))
+        for i in range(5000):
+            cache[f"event{i}"] = b"e" * 100
+
+        assert os.path.getsize(ring_file(temp_cache_dir)) == size
+        assert "event0" not in cache
+        assert cache["event4999"] == b"e" * 100
+        assert not os.path.exists(os.path.join(temp_cache_dir, "data"))
+
+    def test_entries_survive_close(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, backend="ring", ring_capacity_bytes=CAPACITY)
+        cache["key"] = "value"
+        cache["gone"] = "deleted"
+        del cache["gone"]
+        cache.close()
+
+        reopened = Cache(temp_cache_dir, backend="ring", ring_capacity_bytes=CAPACITY)
+        assert reopened["key"] == "value"
+        assert "gone" not in reopened
+
+    def test_capacity_is_required_and_fixed(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(temp_cache_dir, backend="ring")
+        assert excinfo.value.option == "ring_capacity_bytes"
+
+        cache = Cache(temp_cache_dir, backend="ring", ring_capacity_bytes=CAPACITY)
+        cache.close()
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(temp_cache_dir, backend="ring", ring_capacity_bytes=2 * CAPACITY)
+        assert excinfo.value.option == "ring_capacity_bytes"
+
+    def test_capacity_needs_the_ring_backend(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(temp_cache_dir, ring_capacity_bytes=CAPACITY)
+        assert excinfo.value.option == "ring_capacity_bytes"
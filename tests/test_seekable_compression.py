@@ -0,0 +1,61 @@
+"""
+Tests for reading parts of large values stored in seekable compressed frames
+"""
+
+import io
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+from diskcache_rs._diskcache_rs import PyCache
+
+FRAME_SIZE = 256 * 1024
+VALUE = bytes(i // 64 % 251 for i in range(FRAME_SIZE * 4))
+
+
+class TestSeekableCompression:
+    """seekable_compression stores large values as frames read one at a time"""
+
+    def test_read_seeks_within_the_value(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, seekable_compression=True)
+        cache["preview"] = VALUE
+
+        with cache.read("preview") as reader:
+            assert reader.read(16) == VALUE[:16]
+            reader.seek(FRAME_SIZE * 3 - 8)
+            assert reader.read(16) == VALUE[FRAME_SIZE * 3 - 8 : FRAME_SIZE * 3 + 8]
+            reader.seek(-4, io.SEEK_END)
+            assert reader.read() == VALUE[-4:]
+            reader.seek(0)
+            assert reader.read() == VALUE
+        assert cache["preview"] == VALUE
+
+    def test_get_range_reads_only_the_range(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir, compression="zstd", seekable_compression=True)
+        cache.set("preview", VALUE)
+
+        assert cache.open_frames("preview").size == len(VALUE)
+        assert cache.get_range("preview", 10, 20) == VALUE[10:20]
+        assert cache.get_range("preview", FRAME_SIZE * 4 - 3) == VALUE[-3:]
+        assert cache.get_range("preview", len(VALUE) + 10) == b""
+        assert cache.get_range("missing", 0, 10) is None
+
+    def test_other_values_still_read_back(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, seekable_compression=True)
+        cache["small"] = b"tiny"
+        cache["object"] = {"frames": list(range(10))}
+
+        with cache.read("small") as reader:
+            assert reader.read() == b"tiny"
+        assert cache["object"] == {"frames": list(range(10))}
+        assert cache._cache.get_range("small", 0, 4) is not None
+
+    def test_needs_a_codec_with_frames(self, temp_cache_dir):
+        for options in (
+            {"compression": "snappy"},
+            {"compression": "none"},
+            {"backend": "redb"},
+        ):
+            with pytest.raises(CacheConfigError) as excinfo:
+                PyCache(temp_cache_dir, seekable_compression=True, **options)
+            assert excinfo.value.option == "seekable_compression"
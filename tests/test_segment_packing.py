@@ -0,0 +1,52 @@
+"""
+Tests for packing small values into shared segment files
+"""
+
+import os
+from pathlib import Path
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+
+class TestSegmentPacking:
+    """Values below pack_threshold are appended to segments/ instead of data/"""
+
+    def test_small_values_share_segments(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, disk_write_threshold=16, pack_threshold=8192)
+        for i in range(500):
+            cache[f"k{i}"] = os.urandom(1000)
+        cache["k7"] = b"rewritten"
+
+        assert cache["k7"] == b"rewritten"
+        assert len(cache["k499"]) == 1000
+        assert list(Path(temp_cache_dir, "data").rglob("*.dat")) == []
+        assert len(os.listdir(os.path.join(temp_cache_dir, "segments"))) == 1
+
+    def test_values_survive_vacuum_and_reopen(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, disk_write_threshold=16, pack_threshold=8192)
+        for i in range(100):
+            cache[f"k{i}"] = f"value-{i}".encode() * 20
+        for i in range(0, 100, 2):
+            del cache[f"k{i}"]
+        cache.vacuum()
+        cache.close()
+
+        cache = Cache(temp_cache_dir, disk_write_threshold=16, pack_threshold=8192)
+        assert cache.get("k0") is None
+        assert cache["k1"] == b"value-1" * 20
+        assert len(cache) == 50
+
+    def test_large_values_keep_their_own_files(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, disk_write_threshold=16, pack_threshold=1024)
+        cache["large"] = os.urandom(4096)
+
+        assert len(list(Path(temp_cache_dir, "data").rglob("*.dat"))) == 1
+        assert not os.path.exists(os.path.join(temp_cache_dir, "segments"))
+
+    def test_invalid_compaction_ratio(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(temp_cache_dir, pack_threshold=8192, compaction_ratio=1.5)
+
+        assert excinfo.value.option == "compaction_ratio"
@@ -0,0 +1,65 @@
+"""
+Tests for signing index rows so a shared directory cannot be tampered with
+"""
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+UNPICKLED = []
+
+
+def record_unpickle():
+    UNPICKLED.append(True)
+    return "planted"
+
+
+class Planted:
+    """Runs code when unpickled, as a malicious pickle would"""
+
+    def __reduce__(self):
+        return (record_unpickle, ())
+
+
+class TestSignedIndex:
+    """index_key signs every row; rows that fail the check are never unpickled"""
+
+    @pytest.mark.parametrize("secret", [b"secret", "secret"])
+    def test_round_trips_values(self, temp_cache_dir, secret):
+        cache = Cache(temp_cache_dir, index_key=secret, disk_write_threshold=1024)
+        cache["small"] = {"answer": 42}
+        cache["large"] = b"x" * 100_000
+        cache.close()
+
+        cache = Cache(temp_cache_dir, index_key=b"secret", disk_write_threshold=1024)
+        assert cache["small"] == {"answer": 42}
+        assert cache["large"] == b"x" * 100_000
+        with cache.read("large") as reader:
+            assert reader.read() == b"x" * 100_000
+
+    def test_unsigned_writes_are_not_unpickled(self, temp_cache_dir):
+        UNPICKLED.clear()
+        Cache(temp_cache_dir, index_key=b"secret")["key"] = "original"
+
+        intruder = Cache(temp_cache_dir)
+        intruder["key"] = Planted()
+        intruder["large"] = [Planted()] * 10_000
+        intruder.close()
+
+        cache = Cache(temp_cache_dir, index_key=b"secret")
+        assert cache.get("key") is None
+        assert cache.get("large") is None
+        assert "key" not in cache
+        assert UNPICKLED == []
+
+    def test_wrong_secret_trusts_nothing(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, index_key=b"secret")
+        cache["key"] = "value"
+        cache.close()
+
+        assert Cache(temp_cache_dir, index_key=b"guess").get("key") is None
+
+    def test_rejects_other_backends(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(temp_cache_dir, backend="sqlite", index_key=b"secret")
+        assert excinfo.value.option == "index_key"
@@ -0,0 +1,92 @@
+"""
+Tests for coalescing concurrent loads of the same key
+"""
+
+import threading
+import time
+
+import pytest
+
+from diskcache_rs import Cache, FanoutCache
+
+
+class TestGetOrLoad:
+    """Concurrent misses on one key share a single loader call"""
+
+    def test_loads_once_then_serves_from_cache(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        calls = []
+
+        def loader():
+            calls.append(1)
+            return {"answer": 42}
+
+        assert cache.get_or_load("key", loader) == {"answer": 42}
+        assert cache.get_or_load("key", loader) == {"answer": 42}
+        assert cache["key"] == {"answer": 42}
+        assert len(calls) == 1
+
+    def test_concurrent_misses_share_one_load(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        calls = []
+        barrier = threading.Barrier(8)
+        results = []
+
+        def loader():
+            calls.append(1)
+            time.sleep(0.2)
+            return b"expensive"
+
+        def worker():
+            barrier.wait()
+            results.append(cache.get_or_load("shader", loader))
+
+        threads = [threading.Thread(target=worker) for _ in range(8)]
+        for thread in threads:
+            thread.start()
+        for thread in threads:
+            thread.join()
+
+        assert results == [b"expensive"] * 8
+        assert len(calls) == 1
+
+    def test_loader_error_is_raised_and_not_cached(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+
+        def failing():
+            raise ValueError("backend down")
+
+        with pytest.raises(ValueError, match="backend down"):
+            cache.get_or_load("key", failing)
+        assert "key" not in cache
+        assert cache.get_or_load("key", lambda: 1) == 1
+
+    def test_expire_and_tag_apply_to_loaded_entry(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        cache.get_or_load("key", lambda: "value", expire=60, tag="loaded")
+
+        value, expire_time, tag = cache.get("key", expire_time=True, tag=True)
+        assert value == "value"
+        assert expire_time is not None
+        assert tag == "loaded"
+
+    def test_memoize_coalesces_concurrent_calls(self, temp_cache_dir):
+        cache = FanoutCache(temp_cache_dir, shards=2)
+        calls = []
+
+        @cache.memoize()
+        def compile_shader(name):
+            calls.append(name)
+            time.sleep(0.2)
+            return name.upper()
+
+        threads = [
+            threading.Thread(target=compile_shader, args=("blur",)) for _ in range(4)
+        ]
+        for thread in threads:
+            thread.start()
+        for thread in threads:
+            thread.join()
+
+        assert compile_shader("blur") == "BLUR"
+        assert calls == ["blur"]
@@ -0,0 +1,35 @@
+"""
+Tests for volume(), which reports the live bytes held by the cache
+"""
+
+import os
+
+from diskcache_rs import Cache
+
+
+class TestSizeAccounting:
+    """volume() follows writes, overwrites, deletes and clears"""
+
+    def test_volume_tracks_live_values(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        cache["small"] = os.urandom(100)
+        cache["large"] = os.urandom(100_000)
+        full = cache.volume()
+        assert full >= 100_100
+
+        cache["large"] = os.urandom(10_000)
+        shrunk = cache.volume()
+        assert shrunk < full
+
+        del cache["large"]
+        assert cache.volume() < shrunk
+        cache.clear()
+        assert cache.volume() == 0
+
+    def test_volume_survives_reopen(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        cache["large"] = os.urandom(100_000)
+        volume = cache.volume()
+        cache.close()
+
+        assert Cache(temp_cache_dir).volume() == volume
@@ -0,0 +1,52 @@
+"""
+Tests for sharing a cache directory over SMB with smb_mode
+"""
+
+import os
+import subprocess
+import sys
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+WRITE = (
+    "import sys\n"
+    "from diskcache_rs import Cache\n"
+    "cache = Cache(sys.argv[1], smb_mode=True, disk_write_threshold=0)\n"
+    "for n in range(50):\n"
+    "    cache[f'key{n % 5}'] = sys.argv[2] * 4096\n"
+    "    assert cache.get(f'key{n % 5}') in (None, 'a' * 4096, 'b' * 4096)\n"
+)
+
+
+class TestSmbMode:
+    """smb_mode publishes values by rename and locks no keys"""
+
+    def test_values_round_trip_without_key_locks(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, smb_mode=True, sharing_retries=2)
+        cache["small"] = "value"
+        cache["large"] = b"x" * 100_000
+        assert cache["small"] == "value"
+        assert cache["large"] == b"x" * 100_000
+        del cache["small"]
+        assert "small" not in cache
+        assert not os.path.exists(os.path.join(temp_cache_dir, "locks"))
+
+    def test_processes_racing_on_keys_see_whole_values(self, temp_cache_dir):
+        workers = [
+            subprocess.Popen(
+                [sys.executable, "-c", WRITE, temp_cache_dir, letter],
+                env={**os.environ, "PYTHONPATH": os.pathsep.join(sys.path)},
+            )
+            for letter in "ab"
+        ]
+        assert [worker.wait(timeout=120) for worker in workers] == [0, 0]
+        cache = Cache(temp_cache_dir, smb_mode=True, disk_write_threshold=0)
+        for n in range(5):
+            assert cache.get(f"key{n}") in (None, "a" * 4096, "b" * 4096)
+
+    def test_packing_is_rejected(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as raised:
+            Cache(temp_cache_dir, smb_mode=True, pack_threshold=1024)
+        assert raised.value.option == "smb_mode"
@@ -0,0 +1,54 @@
+"""
+Tests for snapshot(), which writes a point-in-time copy of a cache
+"""
+
+import os
+
+import pytest
+from diskcache_rs import Cache
+
+
+class TestSnapshot:
+    """A snapshot opens as its own cache and does not follow later writes"""
+
+    def test_snapshot_round_trip(self, temp_cache_dir, tmp_path):
+        cache = Cache(temp_cache_dir, disk_write_threshold=1024)
+        cache["small"] = "inline"
+        cache["large"] = b"x" * 100_000
+        cache.set("tagged", 42, tag="group")
+
+        destination = tmp_path / "backup"
+        report = cache.snapshot(destination)
+        assert report["entries"] == 3
+        assert report["files"] >= 1
+
+        cache["small"] = "changed"
+        cache["later"] = 1
+        del cache["large"]
+
+        copy = Cache(str(destination))
+        assert copy["small"] == "inline"
+        assert copy["large"] == b"x" * 100_000
+        assert copy["tagged"] == 42
+        assert "later" not in copy
+        copy.close()
+
+        assert cache["small"] == "changed"
+        assert "large" not in cache
+
+    def test_destination_must_be_empty(self, temp_cache_dir, tmp_path):
+        cache = Cache(temp_cache_dir)
+        cache["a"] = 1
+        destination = tmp_path / "backup"
+        destination.mkdir()
+        (destination / "existing").write_text("keep me")
+
+        with pytest.raises(Exception):
+            cache.snapshot(destination)
+        assert os.listdir(destination) == ["existing"]
+
+    def test_other_backends_are_rejected(self, temp_cache_dir, tmp_path):
+        cache = Cache(temp_cache_dir, backend="sqlite")
+        cache["a"] = 1
+        with pytest.raises(Exception, match="snapshot"):
+            cache.snapshot(tmp_path / "backup")
@@ -0,0 +1,62 @@
+"""
+Tests for soft delete, which keeps deleted entries restorable for a while
+"""
+
+import os
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+
+class TestSoftDelete:
+    """soft_delete moves deleted entries to a trash that undelete() restores from"""
+
+    def test_deleted_entries_can_be_restored(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, soft_delete=3600)
+        cache["small"] = {"expensive": True}
+        cache["large"] = b"x" * 100_000
+
+        del cache["small"]
+        assert cache.delete("large")
+        assert "small" not in cache
+
+        assert cache.undelete("small")
+        assert cache.undelete("large")
+        assert cache["small"] == {"expensive": True}
+        assert cache["large"] == b"x" * 100_000
+        # Each deletion can be undone once
+        assert not cache.undelete("small")
+
+    def test_trash_survives_reopening(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, soft_delete=3600)
+        cache["key"] = "value"
+        cache.delete("key")
+        cache.close()
+
+        cache = Cache(temp_cache_dir, soft_delete=3600)
+        assert cache.undelete("key")
+        assert cache["key"] == "value"
+
+    def test_newer_values_are_kept(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, soft_delete=3600)
+        cache["key"] = "old"
+        cache.delete("key")
+        cache["key"] = "new"
+
+        assert not cache.undelete("key")
+        assert cache["key"] == "new"
+
+    def test_entries_past_the_grace_period_are_purged(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, soft_delete=0)
+        cache["key"] = "value"
+        cache.delete("key")
+        cache.vacuum()
+
+        assert os.listdir(os.path.join(temp_cache_dir, "trash")) == []
+        assert not cache.undelete("key")
+
+    def test_undelete_needs_soft_delete(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        with pytest.raises(CacheConfigError):
+            cache.undelete("key")
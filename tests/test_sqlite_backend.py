@@ -0,0 +1,89 @@
+"""
+Tests for the sqlite backend, which shares python-diskcache's cache.db
+"""
+
+import os
+import pickle
+import sqlite3
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+
+def _rows(directory):
+    with sqlite3.connect(os.path.join(directory, "cache.db")) as conn:
+        return {
+            row[0]: row[1:]
+            for row in conn.execute(
+                "SELECT key, raw, mode, filename, value FROM Cache"
+            )
+        }
+
+
+class TestSqliteBackend:
+    """Values written here are readable the way diskcache reads them, and back"""
+
+    def test_values_use_diskcache_modes(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, backend="sqlite")
+        cache["object"] = {"answer": 42}
+        cache["bytes"] = b"raw"
+        cache["large"] = b"x" * 100_000
+
+        rows = _rows(temp_cache_dir)
+        raw, mode, filename, value = rows["object"]
+        assert (raw, mode, filename) == (1, 4, None)
+        assert pickle.loads(value) == {"answer": 42}
+        assert rows["bytes"] == (1, 1, None, b"raw")
+
+        raw, mode, filename, value = rows["large"]
+        assert (mode, value) == (2, None)
+        with open(os.path.join(temp_cache_dir, filename), "rb") as handle:
+            assert handle.read() == b"x" * 100_000
+
+        assert cache["object"] == {"answer": 42}
+        assert cache["large"] == b"x" * 100_000
+
+    def test_reads_rows_written_by_diskcache(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, backend="sqlite")
+        with sqlite3.connect(os.path.join(temp_cache_dir, "cache.db")) as conn:
+            conn.executemany(
+                "INSERT INTO Cache (key, raw, store_time, access_time, mode, value)"
+                " VALUES (?, 1, 0, 0, ?, ?)",
+                [
+                    ("text", 1, "hello"),
+                    ("number", 1, 7),
+                    ("pickled", 4, pickle.dumps([1, 2, 3])),
+                ],
+            )
+
+        assert cache.get("text") == "hello"
+        assert cache.get("number") == 7
+        assert cache.get("pickled") == [1, 2, 3]
+        assert sorted(cache) == ["number", "pickled", "text"]
+
+    def test_delete_removes_value_file(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, backend="sqlite")
+        cache["large"] = b"y" * 100_000
+        filename = _rows(temp_cache_dir)["large"][2]
+
+        del cache["large"]
+        assert not os.path.exists(os.path.join(temp_cache_dir, filename))
+        assert "large" not in _rows(temp_cache_dir)
+
+    def test_existing_database_is_not_migrated_away(self, temp_cache_dir):
+        Cache(temp_cache_dir, backend="sqlite")["key"] = "value"
+
+        cache = Cache(temp_cache_dir, backend="sqlite")
+        assert cache["key"] == "value"
+        assert os.path.exists(os.path.join(temp_cache_dir, "cache.db"))
+        assert not os.path.exists(os.path.join(temp_cache_dir, "cache.db.migrated"))
+
+    def test_rejects_unknown_backend_and_wal(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(temp_cache_dir, backend="redis")
+        assert excinfo.value.option == "backend"
+
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(temp_cache_dir, backend="sqlite", wal="always")
+        assert excinfo.value.option == "wal"
@@ -0,0 +1,121 @@
+"""
+Tests for diskcache-style statistics: stats(enable, reset)
+"""
+
+import subprocess
+import sys
+
+from diskcache_rs import Cache, FanoutCache
+from diskcache_rs._diskcache_rs import PyCache
+
+
+class TestCacheStats:
+    """stats() on the diskcache-compatible wrappers"""
+
+    def test_off_by_default(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        cache["key"] = "value"
+        cache.get("key")
+        cache.get("missing")
+        assert cache.stats() == (0, 0)
+
+        cache.get("key")
+        assert cache.stats() == (1, 0)
+
+    def test_reset_returns_previous_counts(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, statistics=1)
+        cache["key"] = "value"
+        cache.get("key")
+        cache.get("missing")
+
+        assert cache.stats(reset=True) == (1, 1)
+        assert cache.stats() == (0, 0)
+
+    def test_disable_stops_counting(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, statistics=1)
+        cache["key"] = "value"
+
+        cache.stats(enable=False)
+        cache.get("key")
+        cache.get("missing")
+        assert cache.stats() == (0, 0)
+
+        cache.get("key")
+        assert cache.stats() == (1, 0)
+
+    def test_fanout_sums_shards(self, temp_cache_dir):
+        cache = FanoutCache(temp_cache_dir, shards=4, statistics=1)
+        for i in range(8):
+            cache[f"key{i}"] = i
+        for i in range(8):
+            cache.get(f"key{i}")
+        cache.get("missing")
+
+        assert cache.stats(reset=True) == (8, 1)
+        assert cache.stats() == (0, 0)
+
+
+class TestAggregateStats:
+    """stats(aggregate=True) adds up every process using the directory"""
+
+    def test_includes_processes_that_closed_the_cache(self, temp_cache_dir):
+        code = """
+import sys
+from diskcache_rs import Cache
+cache = Cache(sys.argv[1], statistics=1)
+cache["key"] = "value"
+for _ in range(3):
+    cache.get("key")
+cache.get("missing")
+cache.close()
+"""
+        worker = subprocess.run(
+            [sys.executable, "-c", code, temp_cache_dir],
+            capture_output=True,
+            text=True,
+            timeout=120,
+        )
+        assert worker.returncode == 0, worker.stderr or worker.stdout
+
+        cache = Cache(temp_cache_dir, statistics=1)
+        cache.get("key")
+        assert cache.stats() == (1, 0)
+        assert cache.stats(aggregate=True) == (4, 1)
+
+        totals = cache._cache.stats(aggregate=True)
+        assert totals["sets"] == 1
+        assert totals["processes"] == 1
+
+        # A reset zeroes this process's share only
+        assert cache.stats(reset=True, aggregate=True) == (4, 1)
+        assert cache.stats(aggregate=True) == (3, 1)
+        cache.close()
+
+    def test_fanout_adds_up_shards(self, temp_cache_dir):
+        cache = FanoutCache(temp_cache_dir, shards=4, statistics=1)
+        for i in range(8):
+            cache[f"key{i}"] = i
+            cache.get(f"key{i}")
+        cache.close()
+
+        cache = FanoutCache(temp_cache_dir, shards=4, statistics=1)
+        cache.get("missing")
+        assert cache.stats(aggregate=True) == (8, 1)
+
+
+class TestIoStats:
+    """Write amplification and I/O counters on the Rust cache"""
+
+    def test_counts_files_and_amplification(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir, sync_writes=True)
+        assert cache.write_amplification() == 0.0
+
+        cache.set("small", b"value")
+        cache.set("large", b"x" * 100_000)
+        cache.delete("large")
+
+        stats = cache.stats()
+        assert stats["logical_bytes_written"] >= 100_005
+        assert stats["disk_bytes_written"] > 0
+        assert stats["files_created"] == 1
+        assert cache.write_amplification() > 0.0
@@ -0,0 +1,67 @@
+"""
+Tests for open_write(), which streams a value into the cache
+"""
+
+import shutil
+
+import pytest
+
+from diskcache_rs import Cache
+
+CHUNK = bytes(range(256)) * 256
+
+
+class TestStreaming:
+    """Streamed values are written in pieces and read back as bytes"""
+
+    def test_streamed_value_round_trips(self, temp_cache_dir, tmp_path):
+        cache = Cache(temp_cache_dir)
+        source = tmp_path / "source.bin"
+        source.write_bytes(CHUNK * 64)
+
+        with cache.open_write("large") as writer, open(source, "rb") as reader:
+            shutil.copyfileobj(reader, writer)
+
+        assert writer.closed
+        assert cache["large"] == CHUNK * 64
+        with cache.read("large") as handle:
+            assert handle.read(len(CHUNK)) == CHUNK
+            assert handle.read() == CHUNK * 63
+
+    def test_value_is_stored_on_close(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        cache["key"] = b"old"
+        writer = cache.open_write("key")
+        writer.write(CHUNK)
+        writer.write(memoryview(CHUNK))
+
+        assert cache["key"] == b"old"
+        writer.close()
+        writer.close()
+        assert cache["key"] == CHUNK * 2
+        with pytest.raises(Exception):
+            writer.write(b"more")
+
+    def test_failed_write_keeps_previous_value(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        cache["key"] = b"old"
+
+        with pytest.raises(RuntimeError):
+            with cache.open_write("key") as writer:
+                writer.write(CHUNK)
+                raise RuntimeError("source went away")
+
+        assert cache["key"] == b"old"
+        writer = cache.open_write("new")
+        writer.write(CHUNK)
+        writer.discard()
+        assert "new" not in cache
+
+    def test_small_values_can_be_streamed(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        with cache.open_write("small") as writer:
+            writer.write(b"tiny")
+
+        assert cache["small"] == b"tiny"
+        with cache.read("small") as handle:
+            assert handle.read() == b"tiny"
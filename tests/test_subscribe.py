@@ -0,0 +1,118 @@
+"""
+Tests for change notifications through subscribe()
+"""
+
+import os
+import subprocess
+import sys
+import threading
+import time
+
+import pytest
+
+from diskcache_rs import Cache, FanoutCache
+from diskcache_rs._diskcache_rs import PyCache
+
+WAIT = 10
+
+
+class TestSubscribe:
+    """subscribe yields (event, key) for the keys of its topic"""
+
+    def test_prefix_hears_sets_deletes_and_expirations(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        with cache.subscribe(prefix="user:") as changes:
+            cache["order:1"] = "ignored"
+            cache["user:1"] = "alice"
+            assert changes.get(WAIT) == ("set", "user:1")
+            del cache["user:1"]
+            assert changes.get(WAIT) == ("delete", "user:1")
+
+            cache.set("user:2", "bob", expire=0.5)
+            assert changes.get(WAIT) == ("set", "user:2")
+            time.sleep(1.5)
+            assert cache.get("user:2") is None
+            assert changes.get(WAIT) == ("expire", "user:2")
+
+            cache.clear()
+            assert changes.get(WAIT) == ("clear", "")
+            assert changes.get(0.1) is None
+        assert changes.closed
+        assert changes.get() is None
+
+    def test_tag_hears_about_the_keys_set_with_it(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        changes = cache.subscribe(tag="red")
+        cache.set("a", 1, tag="red")
+        cache.set("b", 2, tag="blue")
+        assert changes.get(WAIT) == ("set", "a")
+        cache.delete("b")
+        cache.delete("a")
+        assert changes.get(WAIT) == ("delete", "a")
+        assert changes.get(0.1) is None
+
+        with pytest.raises(ValueError):
+            cache.subscribe(prefix="a", tag="red")
+
+    def test_iteration_blocks_until_the_next_change(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        changes = cache.subscribe()
+
+        def writer():
+            time.sleep(0.2)
+            cache["key"] = "value"
+
+        thread = threading.Thread(target=writer)
+        thread.start()
+        assert next(iter(changes)) == ("set", "key")
+        thread.join()
+        changes.close()
+        assert list(changes) == []
+
+    def test_fanout_cache_subscribes_every_shard(self, temp_cache_dir):
+        cache = FanoutCache(temp_cache_dir, shards=4)
+        changes = cache.subscribe()
+        keys = {f"key{n}" for n in range(8)}
+        for key in keys:
+            cache[key] = "value"
+        assert {changes.get(WAIT) for _ in keys} == {("set", key) for key in keys}
+
+
+class TestSubscribeAcrossProcesses:
+    """Changes other processes make reach subscribers through a shared feed"""
+
+    def test_event_log_carries_other_processes_changes(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir, event_log=True)
+        changes = cache.subscribe(prefix="user:")
+        script = (
+            "import sys\n"
+            "from diskcache_rs._diskcache_rs import PyCache\n"
+            "cache = PyCache(sys.argv[1], event_log=True)\n"
+            "cache.set('user:1', b'alice')\n"
+            "cache.set('order:1', b'ignored')\n"
+            "cache.delete('user:1')\n"
+            "cache.close()\n"
+        )
+        subprocess.run(
+            [sys.executable, "-c", script, temp_cache_dir],
+            env={**os.environ, "PYTHONPATH": os.pathsep.join(sys.path)},
+            check=True,
+        )
+        assert changes.get(WAIT) == ("set", "user:1")
+        assert changes.get(WAIT) == ("delete", "user:1")
+        assert changes.get(0.3) is None
+
+    @pytest.mark.skipif(
+        sys.platform == "win32", reason="the cache server listens on a Unix socket"
+    )
+    def test_server_clients_hear_each_others_changes(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir)
+        with cache.serve() as server:
+            url = f"unix://{server.socket}"
+            reader, writer = Cache(url), Cache(url)
+            changes = reader.subscribe()
+            writer["key"] = "value"
+            assert changes.get(WAIT) == ("set", "key")
+            cache.delete("key")
+            assert changes.get(WAIT) == ("delete", "key")
+            changes.close()
@@ -0,0 +1,45 @@
+"""
+Tests for capping what one tag holds with set_tag_limit()
+"""
+
+import pytest
+
+from diskcache_rs import Cache, FanoutCache
+
+
+class TestTagLimits:
+    """A tag over its limit gives up its own entries, not other tags'"""
+
+    def test_tag_cannot_crowd_out_others(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        for n in range(10):
+            cache.set(f"b{n}", n, tag="tenant-b")
+        cache.set_tag_limit("tenant-a", max_entries=5)
+        for n in range(50):
+            cache.set(f"a{n}", n, tag="tenant-a")
+        assert sum(f"a{n}" in cache for n in range(50)) == 5
+        assert all(f"a{n}" in cache for n in range(45, 50))
+        assert all(f"b{n}" in cache for n in range(10))
+
+    def test_limit_applies_to_stored_entries(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        for n in range(10):
+            cache.set(f"key{n}", b"x" * 1000, tag="bulk")
+        cache.set_tag_limit("bulk", max_bytes=5000)
+        assert sum(f"key{n}" in cache for n in range(10)) < 5
+
+        assert cache.remove_tag_limit("bulk")
+        assert not cache.remove_tag_limit("bulk")
+
+    def test_limit_needs_a_maximum(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        with pytest.raises(ValueError):
+            cache.set_tag_limit("tenant-a")
+
+    def test_fanout_cache_splits_the_limit(self, temp_cache_dir):
+        cache = FanoutCache(temp_cache_dir, shards=4)
+        cache.set_tag_limit("tenant-a", max_entries=8)
+        for n in range(100):
+            cache.set(f"a{n}", n, tag="tenant-a")
+        assert sum(f"a{n}" in cache for n in range(100)) <= 8
+        assert cache.remove_tag_limit("tenant-a")
@@ -0,0 +1,35 @@
+"""
+Tests for top_keys(), which reports the most hit keys
+"""
+
+from diskcache_rs import Cache
+
+
+class TestTopKeys:
+    """top_keys counts hits per key approximately"""
+
+    def test_most_hit_keys_come_first(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, statistics=1)
+        for i in range(50):
+            cache[f"key{i}"] = i
+        for _ in range(30):
+            cache.get("hot")  # Misses are not hits
+            cache.get("key1")
+        for _ in range(10):
+            cache.get_many(["key2", "key3"])
+        cache.get("key4")
+
+        top = cache.top_keys(3)
+        assert [key for key, _ in top] == ["key1", "key2", "key3"]
+        assert top[0][1] >= 30
+        assert top[1][1] >= 10
+
+    def test_reset_and_disabled_statistics(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, statistics=1)
+        cache["a"] = 1
+        cache.get("a")
+        assert cache.top_keys() == [("a", 1)]
+
+        cache.stats(enable=False, reset=True)
+        cache.get("a")
+        assert cache.top_keys() == []
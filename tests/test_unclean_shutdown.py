@@ -0,0 +1,108 @@
+"""
+Tests for unclean-shutdown detection.
+
+Opening a cache drops a per-process marker into ``open_markers/`` that is
+removed on close; a leftover marker is reported through ``info()``.
+"""
+
+import os
+import subprocess
+import sys
+import tempfile
+
+import pytest
+
+from diskcache_rs import Cache
+
+
+@pytest.fixture
+def temp_cache_dir():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        yield temp_dir
+
+
+def _crash_with_open_cache(directory):
+    """Open the cache in a child process and exit without closing it."""
+    script = (
+        "import os, sys\n"
+        "from diskcache_rs import Cache\n"
+        "cache = Cache(sys.argv[1])\n"
+        "cache.set('key', 'value')\n"
+        "os._exit(1)\n"
+    )
+    subprocess.run(
+        [sys.executable, "-c", script, directory],
+        env={**os.environ, "PYTHONPATH": os.pathsep.join(sys.path)},
+        check=False,
+    )
+
+
+def test_clean_close_is_not_reported(temp_cache_dir):
+    cache = Cache(temp_cache_dir)
+    cache.set("key", "value")
+    cache.close()
+
+    cache = Cache(temp_cache_dir)
+    assert cache.info()["was_unclean_shutdown"] is False
+    cache.close()
+
+
+def test_crash_is_reported(temp_cache_dir):
+    _crash_with_open_cache(temp_cache_dir)
+
+    cache = Cache(temp_cache_dir)
+    info = cache.info()
+    assert info["was_unclean_shutdown"] is True
+    assert info["last_recovery"] is None
+    cache.close()
+
+
+def test_auto_recover_runs_after_crash(temp_cache_dir):
+    _crash_with_open_cache(temp_cache_dir)
+
+    cache = Cache(temp_cache_dir, auto_recover=True)
+    report = cache.info()["last_recovery"]
+    assert report is not None
+    assert report["integrity_ok"] is True
+    assert report["entries_removed"] == 0
+    assert cache.get("key") == "value"
+    cache.close()
+
+
+def test_verify_and_recover_drops_missing_files(temp_cache_dir):
+    cache = Cache(temp_cache_dir, disk_write_threshold=0)
+    cache.set("big", b"x" * 1024)
+    cache.vacuum()
+
+    data_dir = os.path.join(temp_cache_dir, "data")
+    for root, _, names in os.walk(data_dir):
+        for name in names:
+            if name.endswith(".dat"):
+                os.remove(os.path.join(root, name))
+
+    report = cache.verify_and_recover()
+    assert report["entries_removed"] == 1
+    assert "big" not in cache
+    cache.close()
+
+
+def test_vacuum_spares_recent_unindexed_files(temp_cache_dir):
+    cache = Cache(temp_cache_dir, disk_write_threshold=0)
+    cache.set("big", b"x" * 1024)
+
+    # Could belong to a write from another process whose index row is not in yet
+    stray = os.path.join(temp_cache_dir, "data", "0123456789abcdef.dat")
+    with open(stray, "wb") as f:
+        f.write(b"no header")
+
+    report = cache.vacuum()
+    assert report == {
+        "orphans_removed": 0,
+        "orphans_adopted": 0,
+        "temp_files_removed": 0,
+        "files_truncated": 0,
+        "bytes_reclaimed": 0,
+    }
+    assert os.path.exists(stray)
+    assert cache.get("big") == b"x" * 1024
+    cache.close()
@@ -88,7 +88,7 @@ class TestDiskWriteThreshold:
             # Check data directory - might have 0 or 1 files depending on serialization overhead
             data_dir = Path(cache_dir) / "data"
             if data_dir.exists():
-                files = list(data_dir.glob("*.dat"))
+                files = list(data_dir.rglob("*.dat"))
                 # Small data might still be in memory only
                 # This is acceptable behavior
                 pass
@@ -118,7 +118,7 @@ class TestDiskWriteThreshold:
             data_dir = Path(cache_dir) / "data"
             assert data_dir.exists(), "Data directory should exist"
 
-            files = list(data_dir.glob("*.dat"))
+            files = list(data_dir.rglob("*.dat"))
             assert len(files) == 0, "Medium data should stay inline by default"
 
             # Verify we can retrieve the value
@@ -148,7 +148,7 @@ class TestDiskWriteThreshold:
             # Check data directory
             data_dir = Path(cache_dir) / "data"
             if data_dir.exists():
-                files = list(data_dir.glob("*.dat"))
+                files = list(data_dir.rglob("*.dat"))
                 if len(files) > 0:
                     # Check file sizes
                     total_size = sum(f.stat().st_size for f in files)
@@ -186,7 +186,7 @@ class TestDiskWriteThreshold:
             data_dir = Path(cache_dir) / "data"
             assert data_dir.exists(), "Data directory should exist"
 
-            files = list(data_dir.glob("*.dat"))
+            files = list(data_dir.rglob("*.dat"))
             # Should have at least one file on disk (entries might be batched)
             assert len(files) >= 1, f"Should have at least 1 file on disk, got {len(files)}"
 
@@ -236,7 +236,7 @@ class TestVacuumMethod:
             # Check that files exist
             data_dir = Path(cache_dir) / "data"
             if data_dir.exists():
-                files = list(data_dir.glob("*.dat"))
+                files = list(data_dir.rglob("*.dat"))
                 # Files should exist after vacuum
                 assert len(files) > 0, "Files should exist after vacuum"
 
@@ -285,7 +285,7 @@ class TestDiskFileVisibility:
             # Check that files exist and are readable
             data_dir = Path(cache_dir) / "data"
             if data_dir.exists():
-                files = list(data_dir.glob("*.dat"))
+                files = list(data_dir.rglob("*.dat"))
                 if files:
                     # Files should be readable
                     for file_path in files:
@@ -318,13 +318,13 @@ class TestDiskFileVisibility:
             # Check that files were written
             data_dir = Path(cache_dir) / "data"
             assert data_dir.exists(), "Data directory should exist"
-            files_before = list(data_dir.glob("*.dat"))
+            files_before = list(data_dir.rglob("*.dat"))
             assert len(files_before) > 0, "Should have files on disk before closing"
 
             cache1.close()
 
             # Verify files still exist after closing
-            files_after = list(data_dir.glob("*.dat"))
+            files_after = list(data_dir.rglob("*.dat"))
             assert len(files_after) > 0, "Files should still exist after closing"
 
             # Second instance: read data
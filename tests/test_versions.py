@@ -0,0 +1,71 @@
+"""
+Tests for per-key version stamps
+"""
+
+import pytest
+
+from diskcache_rs import Cache, FanoutCache
+from diskcache_rs._diskcache_rs import PyCache
+
+
+class TestVersions:
+    """Versions change on every write and let callers detect changes cheaply"""
+
+    def test_set_returns_version(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir)
+        version = cache.set("key", b"value")
+
+        assert isinstance(version, int)
+        assert cache.version("key") == version
+        assert not cache.has_changed("key", version)
+        assert cache.version("missing") is None
+
+    def test_rewrite_changes_version(self, temp_cache_dir):
+        cache = PyCache(temp_cache_dir)
+        first = cache.set("key", b"value")
+        second = cache.set("key", b"value")
+
+        assert first != second
+        assert cache.has_changed("key", first)
+        assert not cache.has_changed("key", second)
+
+    def test_get_metadata(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        cache.set("key", {"a": 1}, expire=60, tag="group")
+
+        metadata = cache.get_metadata("key")
+        assert set(metadata) == {"version", "expire_time", "tag"}
+        assert metadata["tag"] == "group"
+        assert metadata["expire_time"] is not None
+        assert cache.get_metadata("missing") is None
+
+    def test_detects_writes_from_another_handle(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir)
+        cache["key"] = "old"
+        version = cache.get_metadata("key")["version"]
+
+        other = Cache(temp_cache_dir)
+        other["key"] = "new"
+        assert cache.has_changed("key", version)
+
+        version = cache.get_metadata("key")["version"]
+        del other["key"]
+        assert cache.has_changed("key", version)
+
+    @pytest.mark.parametrize("backend", ["sqlite", "redb"])
+    def test_other_backends(self, temp_cache_dir, backend):
+        cache = PyCache(temp_cache_dir, backend=backend)
+        first = cache.set("key", b"one")
+        second = cache.set("key", b"two")
+
+        assert first is not None and first != second
+        assert not cache.has_changed("key", second)
+
+    def test_fanout_cache(self, temp_cache_dir):
+        cache = FanoutCache(temp_cache_dir, shards=4)
+        cache["key"] = "value"
+        version = cache.get_metadata("key")["version"]
+
+        assert not cache.has_changed("key", version)
+        cache["key"] = "other"
+        assert cache.has_changed("key", version)
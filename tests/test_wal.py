@@ -0,0 +1,39 @@
+"""
+Tests for the optional write-ahead log
+"""
+
+import os
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+
+class TestWriteAheadLog:
+    """wal= option on the diskcache-compatible wrapper"""
+
+    @pytest.mark.parametrize("policy", ["always", "batch", "never"])
+    def test_values_round_trip(self, temp_cache_dir, policy):
+        cache = Cache(temp_cache_dir, wal=policy)
+        cache["small"] = "value"
+        cache["large"] = b"x" * (128 * 1024)
+        cache.close()
+
+        reopened = Cache(temp_cache_dir, wal=policy)
+        assert reopened["small"] == "value"
+        assert reopened["large"] == b"x" * (128 * 1024)
+        reopened.close()
+
+    def test_log_is_checkpointed_on_close(self, temp_cache_dir):
+        cache = Cache(temp_cache_dir, wal="always")
+        cache["key"] = "value"
+        wal_path = os.path.join(temp_cache_dir, "data.wal")
+        assert os.path.getsize(wal_path) > 0
+
+        cache.close()
+        assert os.path.getsize(wal_path) == 0
+
+    def test_unknown_policy_is_a_config_error(self, temp_cache_dir):
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(temp_cache_dir, wal="sometimes")
+        assert excinfo.value.option == "wal"
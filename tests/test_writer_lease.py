@@ -0,0 +1,64 @@
+"""
+Tests for the single-writer lease on a shared cache directory
+"""
+
+import os
+import subprocess
+import sys
+
+import pytest
+
+from diskcache_rs import Cache, CacheConfigError
+
+
+def run_reader(directory, script):
+    """Run `script` in another process with `cache` open on `directory`"""
+    prelude = (
+        "import sys\n"
+        "from diskcache_rs import Cache\n"
+        "cache = Cache(sys.argv[1], writer_lease=30)\n"
+    )
+    subprocess.run(
+        [sys.executable, "-c", prelude + script, directory],
+        env={**os.environ, "PYTHONPATH": os.pathsep.join(sys.path)},
+        check=True,
+    )
+
+
+class TestWriterLease:
+    """writer_lease lets one process write and opens the others read-only"""
+
+    def test_other_processes_read_but_cannot_write(self, temp_cache_dir):
+        writer = Cache(temp_cache_dir, writer_lease=30)
+        assert not writer.read_only
+        writer["key"] = b"value"
+        run_reader(
+            temp_cache_dir,
+            "assert cache.read_only\n"
+            "assert cache['key'] == b'value'\n"
+            "try:\n"
+            "    cache['other'] = b'value'\n"
+            "except PermissionError:\n"
+            "    pass\n"
+            "else:\n"
+            "    raise AssertionError('read-only cache accepted a write')\n"
+            "try:\n"
+            "    del cache['key']\n"
+            "except PermissionError:\n"
+            "    pass\n"
+            "else:\n"
+            "    raise AssertionError('read-only cache accepted a delete')\n",
+        )
+        assert writer["key"] == b"value"
+        assert "other" not in writer
+
+    def test_lease_passes_on_when_the_writer_closes(self, temp_cache_dir):
+        writer = Cache(temp_cache_dir, writer_lease=30)
+        writer.close()
+        run_reader(temp_cache_dir, "cache['key'] = b'value'\n")
+        assert Cache(temp_cache_dir)["key"] == b"value"
+
+    def test_rejects_memory_backend(self):
+        with pytest.raises(CacheConfigError) as excinfo:
+            Cache(backend="memory", writer_lease=30)
+        assert excinfo.value.option == "writer_lease"